@@ -118,7 +118,7 @@ async function buildC(release: boolean, example: boolean = false): Promise<void>
     await liveExec(`cp -f ${libDir}/libxpatch_c.${libExt} ${distDir}/ || true`);
 
     // Copy header
-    await liveExec(`cp -f crates/xpatch-c/xpatch.h ${distDir}/`);
+    await liveExec(`cp -f crates/xpatch-c/include/xpatch.h ${distDir}/`);
 
     // Copy README
     await liveExec(`cp -f crates/xpatch-c/README.md ${distDir}/`);
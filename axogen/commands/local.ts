@@ -28,7 +28,8 @@ export const localCommands = group({
                 else if (platform === "win32") libExt = "dll";
 
                 await liveExec(`cp -f target/release/libxpatch_c.${libExt} crates/xpatch-c/dist/`);
-                await liveExec(`cp -f crates/xpatch-c/xpatch.h crates/xpatch-c/dist/`);
+                await liveExec(`cp -f crates/xpatch-c/include/xpatch.h crates/xpatch-c/dist/`);
+                await liveExec(`cp -f crates/xpatch-c/bindings/XPatch.cs crates/xpatch-c/dist/`);
                 await liveExec(`cp -f crates/xpatch-c/README.md crates/xpatch-c/dist/`);
 
                 logger.success("C/C++ bindings prepared");
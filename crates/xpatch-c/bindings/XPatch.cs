@@ -0,0 +1,299 @@
+// <auto-generated>
+// Generated by xpatch-c's build.rs from bindings/XPatch.cs.in. Do not modify manually.
+// </auto-generated>
+//
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+using System;
+using System.Runtime.InteropServices;
+
+namespace XPatch
+{
+    /// <summary>
+    /// High-level access to the xpatch native library (xpatch_c.dll / libxpatch_c.so).
+    /// </summary>
+    public static class Delta
+    {
+        /// <summary>Version of the library this wrapper was generated from.</summary>
+        public const string WrapperVersion = "0.3.1";
+
+        /// <summary>ABI major version of the C interface this wrapper was generated from.</summary>
+        public const uint AbiVersion = 1;
+
+        /// <summary>ABI minor version of the C interface this wrapper was generated from.</summary>
+        public const uint AbiVersionMinor = 3;
+
+        /// <summary>Version string of the loaded native library.</summary>
+        public static string Version
+        {
+            get { return Marshal.PtrToStringAnsi(NativeMethods.xpatch_version()); }
+        }
+
+        /// <summary>
+        /// Whether the loaded native library has the ABI this wrapper was generated for.
+        /// </summary>
+        public static bool IsCompatible
+        {
+            get
+            {
+                try
+                {
+                    return NativeMethods.xpatch_abi_compatible(AbiVersion, AbiVersionMinor);
+                }
+                catch (EntryPointNotFoundException)
+                {
+                    // Libraries older than ABI 1.1 cannot report compatibility
+                    return false;
+                }
+            }
+        }
+
+        /// <summary>Encode a delta patch between baseData and newData.</summary>
+        /// <param name="tag">Metadata tag to embed in the delta (0-15 with no overhead).</param>
+        /// <param name="baseData">The original data.</param>
+        /// <param name="newData">The new data.</param>
+        /// <param name="enableZstd">Whether to enable zstd compression.</param>
+        /// <returns>The encoded delta.</returns>
+        public static byte[] Encode(ulong tag, byte[] baseData, byte[] newData, bool enableZstd = true)
+        {
+            if (baseData == null) throw new ArgumentNullException("baseData");
+            if (newData == null) throw new ArgumentNullException("newData");
+
+            XPatchBuffer raw = NativeMethods.xpatch_encode(
+                new UIntPtr(tag),
+                baseData, new UIntPtr((uint)baseData.Length),
+                newData, new UIntPtr((uint)newData.Length),
+                enableZstd);
+            if (raw.Data == IntPtr.Zero)
+            {
+                throw new XPatchException("Encoding failed");
+            }
+
+            using (XPatchBufferHandle buffer = NativeMethods.xpatch_buffer_into_handle(raw))
+            {
+                return buffer.ToArray();
+            }
+        }
+
+        /// <summary>Decode a delta patch to reconstruct the new data.</summary>
+        /// <param name="baseData">The original data.</param>
+        /// <param name="delta">The delta patch.</param>
+        /// <returns>The reconstructed data.</returns>
+        /// <exception cref="XPatchException">The delta is invalid or corrupted.</exception>
+        public static byte[] Decode(byte[] baseData, byte[] delta)
+        {
+            if (baseData == null) throw new ArgumentNullException("baseData");
+            if (delta == null) throw new ArgumentNullException("delta");
+
+            XPatchResult result = NativeMethods.xpatch_decode(
+                baseData, new UIntPtr((uint)baseData.Length),
+                delta, new UIntPtr((uint)delta.Length));
+            if (result.ErrorMessage != IntPtr.Zero)
+            {
+                throw new XPatchException(TakeErrorMessage(result.ErrorMessage));
+            }
+
+            using (XPatchBufferHandle buffer = NativeMethods.xpatch_buffer_into_handle(result.Buffer))
+            {
+                return buffer.ToArray();
+            }
+        }
+
+        /// <summary>Extract the metadata tag from a delta patch.</summary>
+        /// <exception cref="XPatchException">The delta is invalid or corrupted.</exception>
+        public static ulong GetTag(byte[] delta)
+        {
+            if (delta == null) throw new ArgumentNullException("delta");
+
+            UIntPtr tag;
+            using (XPatchErrorHandle error = NativeMethods.xpatch_get_tag(
+                delta, new UIntPtr((uint)delta.Length), out tag))
+            {
+                if (!error.IsInvalid)
+                {
+                    throw new XPatchException(error.Message);
+                }
+            }
+            return tag.ToUInt64();
+        }
+
+        private static string TakeErrorMessage(IntPtr message)
+        {
+            try
+            {
+                return ReadErrorMessage(message);
+            }
+            finally
+            {
+                NativeMethods.xpatch_free_error(message);
+            }
+        }
+
+        internal static string ReadErrorMessage(IntPtr message)
+        {
+            int length = checked((int)NativeMethods.xpatch_error_message_utf16(message, null, UIntPtr.Zero).ToUInt64());
+            char[] text = new char[length + 1];
+            NativeMethods.xpatch_error_message_utf16(message, text, new UIntPtr((uint)text.Length));
+            return new string(text, 0, length);
+        }
+    }
+
+    /// <summary>Thrown when a delta cannot be decoded.</summary>
+    public class XPatchException : Exception
+    {
+        public XPatchException(string message) : base(message)
+        {
+        }
+    }
+
+    /// <summary>
+    /// Owns a native buffer created by <c>xpatch_buffer_into_handle</c> and releases it
+    /// with <c>xpatch_buffer_release</c>.
+    /// </summary>
+    public sealed class XPatchBufferHandle : SafeHandle
+    {
+        private XPatchBufferHandle() : base(IntPtr.Zero, true)
+        {
+        }
+
+        public override bool IsInvalid
+        {
+            get { return handle == IntPtr.Zero; }
+        }
+
+        /// <summary>Length of the buffer in bytes.</summary>
+        public long Length
+        {
+            get { return (long)Read().Len.ToUInt64(); }
+        }
+
+        /// <summary>Copy the buffer into a managed array.</summary>
+        public byte[] ToArray()
+        {
+            XPatchBuffer buffer = Read();
+            byte[] data = new byte[checked((int)buffer.Len.ToUInt64())];
+            if (data.Length > 0)
+            {
+                Marshal.Copy(buffer.Data, data, 0, data.Length);
+            }
+            GC.KeepAlive(this);
+            return data;
+        }
+
+        private XPatchBuffer Read()
+        {
+            if (IsInvalid || IsClosed) throw new ObjectDisposedException("XPatchBufferHandle");
+            return (XPatchBuffer)Marshal.PtrToStructure(handle, typeof(XPatchBuffer));
+        }
+
+        protected override bool ReleaseHandle()
+        {
+            return NativeMethods.xpatch_buffer_release(handle);
+        }
+    }
+
+    /// <summary>Owns an error message and releases it with <c>xpatch_free_error</c>.</summary>
+    internal sealed class XPatchErrorHandle : SafeHandle
+    {
+        private XPatchErrorHandle() : base(IntPtr.Zero, true)
+        {
+        }
+
+        public override bool IsInvalid
+        {
+            get { return handle == IntPtr.Zero; }
+        }
+
+        public string Message
+        {
+            get { return Delta.ReadErrorMessage(handle); }
+        }
+
+        protected override bool ReleaseHandle()
+        {
+            NativeMethods.xpatch_free_error(handle);
+            return true;
+        }
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct XPatchBuffer
+    {
+        public IntPtr Data;
+        public UIntPtr Len;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal struct XPatchResult
+    {
+        public XPatchBuffer Buffer;
+        public IntPtr ErrorMessage;
+    }
+
+    /// <summary>
+    /// Raw P/Invoke declarations. All exports use the C calling convention, so the
+    /// declarations state Cdecl explicitly (the .NET default on 32-bit Windows is StdCall).
+    /// </summary>
+    internal static class NativeMethods
+    {
+        private const string Library = "xpatch_c";
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern XPatchBuffer xpatch_encode(
+            UIntPtr tag,
+            byte[] baseData, UIntPtr baseLen,
+            byte[] newData, UIntPtr newLen,
+            [MarshalAs(UnmanagedType.I1)] bool enableZstd);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern XPatchResult xpatch_decode(
+            byte[] baseData, UIntPtr baseLen,
+            byte[] delta, UIntPtr deltaLen);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern XPatchErrorHandle xpatch_get_tag(
+            byte[] delta, UIntPtr deltaLen, out UIntPtr tagOut);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern XPatchBufferHandle xpatch_buffer_into_handle(XPatchBuffer buffer);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        [return: MarshalAs(UnmanagedType.I1)]
+        internal static extern bool xpatch_buffer_release(IntPtr handle);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern void xpatch_free_error(IntPtr errorMessage);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, CharSet = CharSet.Unicode)]
+        internal static extern UIntPtr xpatch_error_message_utf16(
+            IntPtr errorMessage, [Out] char[] output, UIntPtr outputLen);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern IntPtr xpatch_version();
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        internal static extern uint xpatch_abi_version();
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl)]
+        [return: MarshalAs(UnmanagedType.I1)]
+        internal static extern bool xpatch_abi_compatible(uint major, uint minor);
+    }
+}
@@ -19,16 +19,70 @@
 // available. Contact xpatch-commercial@alias.oseifert.ch for details.
 
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=bindings/XPatch.cs.in");
+
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let output_file = PathBuf::from(&crate_dir).join("xpatch.h");
+    let config_file = PathBuf::from(&crate_dir).join("cbindgen.toml");
+    // The header is committed so C/C++ consumers can use it without running cbindgen.
+    // Regenerating it on every build keeps it in sync with the exported symbols.
+    let output_file = PathBuf::from(&crate_dir).join("include").join("xpatch.h");
 
     cbindgen::Builder::new()
-        .with_crate(crate_dir)
-        .with_config(cbindgen::Config::from_file("cbindgen.toml").unwrap())
+        .with_crate(&crate_dir)
+        .with_config(cbindgen::Config::from_file(config_file).unwrap())
         .generate()
         .expect("Unable to generate C bindings")
         .write_to_file(output_file);
+
+    let source = fs::read_to_string(PathBuf::from(&crate_dir).join("src").join("lib.rs")).unwrap();
+
+    // The C# wrapper is committed for the same reason; only the version constants are filled in.
+    generate_csharp(Path::new(&crate_dir), &source);
+
+    // The shared library is named after the ABI major version, so that programs never load a
+    // library with an incompatible ABI. Installers create the versioned file name.
+    let abi_major = abi_constant(&source, "ABI_VERSION");
+    match env::var("CARGO_CFG_TARGET_OS").as_deref() {
+        Ok("linux" | "android" | "freebsd" | "netbsd" | "openbsd" | "dragonfly") => {
+            println!("cargo:rustc-cdylib-link-arg=-Wl,-soname,libxpatch_c.so.{abi_major}");
+        }
+        Ok("macos" | "ios") => {
+            println!(
+                "cargo:rustc-cdylib-link-arg=-Wl,-install_name,@rpath/libxpatch_c.{abi_major}.dylib"
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Reads the value of a `pub const NAME: u32` from the crate source.
+fn abi_constant<'a>(source: &'a str, name: &str) -> &'a str {
+    let prefix = format!("pub const {name}: u32 = ");
+    source
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .and_then(|rest| rest.strip_suffix(';'))
+        .unwrap_or_else(|| panic!("{name} not found in src/lib.rs"))
+}
+
+fn generate_csharp(crate_dir: &Path, source: &str) {
+    let template = fs::read_to_string(crate_dir.join("bindings").join("XPatch.cs.in")).unwrap();
+    let wrapper = template
+        .replace("@VERSION@", &env::var("CARGO_PKG_VERSION").unwrap())
+        .replace("@ABI_VERSION@", abi_constant(source, "ABI_VERSION"))
+        .replace(
+            "@ABI_VERSION_MINOR@",
+            abi_constant(source, "ABI_VERSION_MINOR"),
+        );
+
+    let output_file = crate_dir.join("bindings").join("XPatch.cs");
+    if fs::read_to_string(&output_file).ok().as_deref() != Some(wrapper.as_str()) {
+        fs::write(output_file, wrapper).expect("Unable to write C# bindings");
+    }
 }
@@ -9,7 +9,7 @@
 
 #include <stdio.h>
 #include <string.h>
-#include "../xpatch.h"
+#include "xpatch.h"
 
 int main() {
     // Example data
@@ -23,6 +23,13 @@ int main() {
     const char* version = (const char*)xpatch_version();
     printf("Using xpatch version: %s\n\n", version);
 
+    // Make sure the header matches the library we linked against
+    if (!xpatch_abi_compatible(xpatch_ABI_VERSION, xpatch_ABI_VERSION_MINOR)) {
+        fprintf(stderr, "ABI mismatch: header %d.%d, library %u\n",
+                xpatch_ABI_VERSION, xpatch_ABI_VERSION_MINOR, xpatch_abi_version());
+        return 1;
+    }
+
     // Encode delta
     printf("Original: %s\n", base);
     printf("New:      %s\n\n", new_text);
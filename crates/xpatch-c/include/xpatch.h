@@ -0,0 +1,727 @@
+/* xpatch - High-performance delta compression library
+ * Copyright (c) 2025 Oliver Seifert
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * Commercial License Option:
+ * For commercial use in proprietary software, a commercial license is
+ * available. Contact xpatch-commercial@alias.oseifert.ch for details.
+ */
+
+
+#ifndef XPATCH_H
+#define XPATCH_H
+
+/* Warning: This file is auto-generated by cbindgen. Do not modify manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Log level that disables logging.
+ */
+#define xpatch_LOG_OFF 0
+
+/**
+ * Log level for errors, including caught panics.
+ */
+#define xpatch_LOG_ERROR 1
+
+/**
+ * Log level for warnings.
+ */
+#define xpatch_LOG_WARN 2
+
+/**
+ * Log level for informational messages.
+ */
+#define xpatch_LOG_INFO 3
+
+/**
+ * Log level for debug messages.
+ */
+#define xpatch_LOG_DEBUG 4
+
+/**
+ * Log level for trace messages.
+ */
+#define xpatch_LOG_TRACE 5
+
+/**
+ * ABI major version of the C interface described by `xpatch.h`.
+ *
+ * Bumped whenever an exported function or struct changes incompatibly or is removed, together
+ * with the shared library's soname (`libxpatch_c.so.<major>`).
+ */
+#define xpatch_ABI_VERSION 1
+
+/**
+ * ABI minor version of the C interface described by `xpatch.h`.
+ *
+ * Bumped whenever functions are added or fields are appended to an options struct. Existing
+ * structs never change; new settings go into options structs that carry their own size.
+ */
+#define xpatch_ABI_VERSION_MINOR 3
+
+/**
+ * Compression state reused across calls, created with xpatch_context_new.
+ */
+typedef struct xpatch_XPatchContext xpatch_XPatchContext;
+
+/**
+ * A buffer returned from xpatch functions.
+ * The caller is responsible for freeing this buffer using xpatch_free_buffer.
+ */
+typedef struct xpatch_XPatchBuffer {
+  /**
+   * Pointer to the data
+   */
+  uint8_t *data;
+  /**
+   * Length of the data in bytes
+   */
+  uintptr_t len;
+} xpatch_XPatchBuffer;
+
+/**
+ * Result type for operations that can fail.
+ * If error_message is not NULL, the operation failed and the message describes the error.
+ * The caller is responsible for freeing the error message using xpatch_free_error.
+ */
+typedef struct xpatch_XPatchResult {
+  /**
+   * The result buffer (valid only if error_message is NULL)
+   */
+  struct xpatch_XPatchBuffer buffer;
+  /**
+   * Error message (NULL on success, non-NULL on error)
+   */
+  int8_t *error_message;
+} xpatch_XPatchResult;
+
+/**
+ * Progress callback for long-running operations.
+ *
+ * Called with the `user_data` pointer passed to the operation, the amount of work done
+ * and the total amount of work. Return `true` to continue or `false` to cancel.
+ */
+typedef bool (*xpatch_XPatchProgressCallback)(void *user_data, uint64_t done, uint64_t total);
+
+/**
+ * Options for xpatch_encode_with_options.
+ *
+ * Initialize with xpatch_encode_options_init, passing the `sizeof` of the struct. New
+ * fields are only ever appended in later ABI minor versions, and the library reads only the
+ * first `struct_size` bytes, so programs built against an older header keep working.
+ */
+typedef struct xpatch_XPatchEncodeOptions {
+  /**
+   * Size of the struct the caller was compiled with, set by xpatch_encode_options_init
+   */
+  uintptr_t struct_size;
+  /**
+   * Whether to try zstd compression (default: true)
+   */
+  bool enable_zstd;
+  /**
+   * zstd compression level, 1-22 (default: 3)
+   */
+  int32_t zstd_level;
+  /**
+   * Whether to embed CRC32 checksums of the base and the new data (default: false)
+   */
+  bool checksum;
+} xpatch_XPatchEncodeOptions;
+
+/**
+ * Allocation function with the semantics of C `malloc`.
+ */
+typedef void *(*xpatch_XPatchMallocFn)(uintptr_t size);
+
+/**
+ * Deallocation function with the semantics of C `free`.
+ */
+typedef void (*xpatch_XPatchFreeFn)(void *ptr);
+
+/**
+ * Log callback installed with xpatch_set_log_callback.
+ *
+ * Called with the level (xpatch_LOG_ERROR to xpatch_LOG_TRACE), the target (usually the Rust
+ * module that logged) and the message. Both strings are null-terminated UTF-8 and only valid
+ * during the call.
+ */
+typedef void (*xpatch_XPatchLogCallback)(uint32_t level, const int8_t *target, const int8_t *message);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Encode a delta patch between base_data and new_data.
+ *
+ * # Parameters
+ * - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `new_data`: Pointer to the new data
+ * - `new_len`: Length of the new data in bytes
+ * - `enable_zstd`: Whether to enable zstd compression (true recommended)
+ *
+ * # Returns
+ * An XPatchBuffer containing the encoded delta. The caller must free this buffer
+ * using xpatch_free_buffer when done.
+ *
+ * # Safety
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `new_data` must point to valid memory of at least `new_len` bytes
+ * - The returned buffer must be freed with xpatch_free_buffer
+ *
+ * # Example
+ * ```c
+ * const char* base = "Hello, World!";
+ * const char* new = "Hello, Rust!";
+ * XPatchBuffer delta = xpatch_encode(0, base, strlen(base), new, strlen(new), true);
+ * // Use delta...
+ * xpatch_free_buffer(delta);
+ * ```
+ */
+struct xpatch_XPatchBuffer xpatch_encode(uintptr_t tag,
+                                         const uint8_t *base_data,
+                                         uintptr_t base_len,
+                                         const uint8_t *new_data,
+                                         uintptr_t new_len,
+                                         bool enable_zstd);
+
+/**
+ * Decode a delta patch to reconstruct new_data from base_data.
+ *
+ * # Parameters
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `delta`: Pointer to the delta patch
+ * - `delta_len`: Length of the delta patch in bytes
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the reconstructed data.
+ * On failure, error_message contains a description of the error.
+ * The caller must free the buffer with xpatch_free_buffer and error with xpatch_free_error.
+ *
+ * # Safety
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `delta` must point to valid memory of at least `delta_len` bytes
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ *
+ * # Example
+ * ```c
+ * XPatchResult result = xpatch_decode(base, base_len, delta.data, delta.len);
+ * if (result.error_message == NULL) {
+ *     // Use result.buffer...
+ *     xpatch_free_buffer(result.buffer);
+ * } else {
+ *     fprintf(stderr, "Error: %s\n", result.error_message);
+ *     xpatch_free_error(result.error_message);
+ * }
+ * ```
+ */
+struct xpatch_XPatchResult xpatch_decode(const uint8_t *base_data,
+                                         uintptr_t base_len,
+                                         const uint8_t *delta,
+                                         uintptr_t delta_len);
+
+/**
+ * Extract the metadata tag from a delta patch.
+ *
+ * # Parameters
+ * - `delta`: Pointer to the delta patch
+ * - `delta_len`: Length of the delta patch in bytes
+ * - `tag_out`: Pointer to store the extracted tag value
+ *
+ * # Returns
+ * An error message string (NULL on success, non-NULL on error).
+ * The caller must free the error message using xpatch_free_error if not NULL.
+ *
+ * # Safety
+ * - `delta` must point to valid memory of at least `delta_len` bytes
+ * - `tag_out` must point to valid memory for a usize
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ *
+ * # Example
+ * ```c
+ * usize tag;
+ * char* error = xpatch_get_tag(delta.data, delta.len, &tag);
+ * if (error == NULL) {
+ *     printf("Tag: %zu\n", tag);
+ * } else {
+ *     fprintf(stderr, "Error: %s\n", error);
+ *     xpatch_free_error(error);
+ * }
+ * ```
+ */
+int8_t *xpatch_get_tag(const uint8_t *delta, uintptr_t delta_len, uintptr_t *tag_out);
+
+/**
+ * Encode a delta patch, reporting progress to a callback.
+ *
+ * Works like xpatch_encode, but calls `progress` at the start, between internal phases and
+ * at the end of the operation. Returning `false` from the callback cancels the encode.
+ *
+ * # Parameters
+ * - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `new_data`: Pointer to the new data
+ * - `new_len`: Length of the new data in bytes
+ * - `enable_zstd`: Whether to enable zstd compression (true recommended)
+ * - `progress`: Progress callback (may be NULL); `total` is `new_len`
+ * - `user_data`: Opaque pointer passed through to `progress`
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+ * If the callback cancelled the operation, error_message is "Operation cancelled".
+ *
+ * # Safety
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `new_data` must point to valid memory of at least `new_len` bytes
+ * - `progress` must be safe to call from the calling thread with `user_data`
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ *
+ * # Example
+ * ```c
+ * bool on_progress(void* user_data, uint64_t done, uint64_t total) {
+ *     printf("\r%llu / %llu", (unsigned long long)done, (unsigned long long)total);
+ *     return !*(volatile bool*)user_data; // stop when the user pressed cancel
+ * }
+ *
+ * bool cancelled = false;
+ * XPatchResult result = xpatch_encode_with_progress(
+ *     0, base, base_len, new, new_len, true, on_progress, &cancelled);
+ * ```
+ */
+struct xpatch_XPatchResult xpatch_encode_with_progress(uintptr_t tag,
+                                                       const uint8_t *base_data,
+                                                       uintptr_t base_len,
+                                                       const uint8_t *new_data,
+                                                       uintptr_t new_len,
+                                                       bool enable_zstd,
+                                                       xpatch_XPatchProgressCallback progress,
+                                                       void *user_data);
+
+/**
+ * Decode a delta patch, reporting progress to a callback.
+ *
+ * Works like xpatch_decode, but calls `progress` at the start, between internal phases and
+ * at the end of the operation. Returning `false` from the callback cancels the decode.
+ *
+ * # Parameters
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `delta`: Pointer to the delta patch
+ * - `delta_len`: Length of the delta patch in bytes
+ * - `progress`: Progress callback (may be NULL); `total` is `delta_len`
+ * - `user_data`: Opaque pointer passed through to `progress`
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the reconstructed data.
+ * If the callback cancelled the operation, error_message is "Operation cancelled".
+ *
+ * # Safety
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `delta` must point to valid memory of at least `delta_len` bytes
+ * - `progress` must be safe to call from the calling thread with `user_data`
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ */
+struct xpatch_XPatchResult xpatch_decode_with_progress(const uint8_t *base_data,
+                                                       uintptr_t base_len,
+                                                       const uint8_t *delta,
+                                                       uintptr_t delta_len,
+                                                       xpatch_XPatchProgressCallback progress,
+                                                       void *user_data);
+
+/**
+ * Initialize encoding options with their defaults.
+ *
+ * # Parameters
+ * - `options`: The options to initialize
+ * - `struct_size`: `sizeof` of the struct as compiled by the caller
+ *
+ * # Returns
+ * `false` if `options` is NULL or `struct_size` is smaller than the first published layout
+ * (ABI 1.1), `true` otherwise.
+ *
+ * # Safety
+ * - `options` must point to at least `struct_size` writable bytes
+ *
+ * # Example
+ * ```c
+ * XPatchEncodeOptions options;
+ * xpatch_encode_options_init(&options, sizeof(options));
+ * options.checksum = true;
+ * ```
+ */
+bool xpatch_encode_options_init(struct xpatch_XPatchEncodeOptions *options, uintptr_t struct_size);
+
+/**
+ * Encode a delta patch with explicit encoding options.
+ *
+ * # Parameters
+ * - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `new_data`: Pointer to the new data
+ * - `new_len`: Length of the new data in bytes
+ * - `options`: Options initialized with xpatch_encode_options_init, or NULL for the defaults
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+ *
+ * # Safety
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `new_data` must point to valid memory of at least `new_len` bytes
+ * - `options` must be NULL or point to at least `options->struct_size` readable bytes
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ *
+ * # Example
+ * ```c
+ * XPatchEncodeOptions options;
+ * xpatch_encode_options_init(&options, sizeof(options));
+ * options.zstd_level = 19;
+ * XPatchResult result = xpatch_encode_with_options(0, base, base_len, new, new_len, &options);
+ * ```
+ */
+struct xpatch_XPatchResult xpatch_encode_with_options(uintptr_t tag,
+                                                      const uint8_t *base_data,
+                                                      uintptr_t base_len,
+                                                      const uint8_t *new_data,
+                                                      uintptr_t new_len,
+                                                      const struct xpatch_XPatchEncodeOptions *options);
+
+/**
+ * Create a context that keeps compression state between calls.
+ *
+ * Encoding and decoding through a context reuses its zstd contexts instead of allocating
+ * new ones for every delta, which helps services that handle many deltas. A context is used
+ * by one call at a time; create one per thread.
+ *
+ * # Returns
+ * A context to pass to xpatch_context_encode and xpatch_context_decode. Free it with
+ * xpatch_context_free.
+ *
+ * # Example
+ * ```c
+ * XPatchContext* context = xpatch_context_new();
+ * XPatchResult delta = xpatch_context_encode(context, 0, base, base_len, new, new_len, NULL);
+ * // ...
+ * xpatch_context_free(context);
+ * ```
+ */
+struct xpatch_XPatchContext *xpatch_context_new(void);
+
+/**
+ * Free a context created with xpatch_context_new.
+ *
+ * # Parameters
+ * - `context`: The context to free (NULL is ignored)
+ *
+ * # Safety
+ * - `context` must be NULL or have been returned by xpatch_context_new
+ * - `context` must not be used after calling this function
+ */
+void xpatch_context_free(struct xpatch_XPatchContext *context);
+
+/**
+ * Encode a delta patch like xpatch_encode_with_options, reusing the context's state.
+ *
+ * # Parameters
+ * - `context`: A context created with xpatch_context_new
+ * - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `new_data`: Pointer to the new data
+ * - `new_len`: Length of the new data in bytes
+ * - `options`: Options initialized with xpatch_encode_options_init, or NULL for the defaults
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+ *
+ * # Safety
+ * - `context` must have been returned by xpatch_context_new and not be in use by another call
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `new_data` must point to valid memory of at least `new_len` bytes
+ * - `options` must be NULL or point to at least `options->struct_size` readable bytes
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ */
+struct xpatch_XPatchResult xpatch_context_encode(struct xpatch_XPatchContext *context,
+                                                 uintptr_t tag,
+                                                 const uint8_t *base_data,
+                                                 uintptr_t base_len,
+                                                 const uint8_t *new_data,
+                                                 uintptr_t new_len,
+                                                 const struct xpatch_XPatchEncodeOptions *options);
+
+/**
+ * Decode a delta patch like xpatch_decode, reusing the context's state.
+ *
+ * # Parameters
+ * - `context`: A context created with xpatch_context_new
+ * - `base_data`: Pointer to the original data
+ * - `base_len`: Length of the original data in bytes
+ * - `delta`: Pointer to the delta patch
+ * - `delta_len`: Length of the delta patch in bytes
+ *
+ * # Returns
+ * An XPatchResult. On success, error_message is NULL and buffer contains the reconstructed data.
+ *
+ * # Safety
+ * - `context` must have been returned by xpatch_context_new and not be in use by another call
+ * - `base_data` must point to valid memory of at least `base_len` bytes
+ * - `delta` must point to valid memory of at least `delta_len` bytes
+ * - The returned buffer must be freed with xpatch_free_buffer
+ * - The returned error message (if not NULL) must be freed with xpatch_free_error
+ */
+struct xpatch_XPatchResult xpatch_context_decode(struct xpatch_XPatchContext *context,
+                                                 const uint8_t *base_data,
+                                                 uintptr_t base_len,
+                                                 const uint8_t *delta,
+                                                 uintptr_t delta_len);
+
+/**
+ * Use the host application's allocator for buffers and error messages returned by xpatch.
+ *
+ * Once installed, every XPatchBuffer and error message handed out by xpatch is allocated
+ * with `malloc_fn` and released with `free_fn` in xpatch_free_buffer and xpatch_free_error.
+ * This lets the host free them with its own `free` and avoids crossing CRT heaps (e.g.
+ * Windows DLL consumers). Buffers are allocated at their final size, so no `realloc` is
+ * needed. If `malloc_fn` fails for an error message, the message is a static "Out of memory"
+ * that only xpatch_free_error may release.
+ *
+ * Pass NULL for both functions to restore the default Rust allocator.
+ *
+ * # Parameters
+ * - `malloc_fn`: Allocation function (`malloc` semantics)
+ * - `free_fn`: Deallocation function (`free` semantics)
+ *
+ * # Returns
+ * `true` if the allocator was installed, `false` if only one of the functions was provided.
+ *
+ * # Safety
+ * - Call this before any other xpatch function, or while no xpatch buffers or error
+ *   messages are alive: they must be freed by the same allocator that allocated them
+ * - The functions must be thread-safe if xpatch is used from multiple threads
+ *
+ * # Example
+ * ```c
+ * #include <stdlib.h>
+ *
+ * xpatch_set_allocator(malloc, free);
+ * XPatchResult result = xpatch_decode(...);
+ * free(result.buffer.data); // or xpatch_free_buffer(result.buffer)
+ * free(result.error_message); // or xpatch_free_error(result.error_message)
+ * ```
+ */
+bool xpatch_set_allocator(xpatch_XPatchMallocFn malloc_fn, xpatch_XPatchFreeFn free_fn);
+
+/**
+ * Forward log messages of xpatch (and any other Rust code using the `log` crate in this
+ * process) to a host callback.
+ *
+ * Panics caught at the FFI boundary are logged at xpatch_LOG_ERROR with their message and
+ * source location, which also appear in the returned error message.
+ *
+ * # Parameters
+ * - `level`: Most verbose level to forward, xpatch_LOG_OFF to xpatch_LOG_TRACE
+ * - `callback`: Function receiving the messages, or NULL to stop logging
+ *
+ * # Returns
+ * `false` if another Rust logger was already installed in this process, `true` otherwise.
+ *
+ * # Safety
+ * - `callback` is called from whichever thread logs, so it must be thread-safe
+ * - `callback` must not call xpatch_set_log_callback
+ *
+ * # Example
+ * ```c
+ * void on_log(uint32_t level, const char* target, const char* message) {
+ *     fprintf(stderr, "[xpatch %u] %s: %s\n", level, target, message);
+ * }
+ *
+ * xpatch_set_log_callback(xpatch_LOG_WARN, on_log);
+ * ```
+ */
+bool xpatch_set_log_callback(uint32_t level, xpatch_XPatchLogCallback callback);
+
+/**
+ * Free a buffer returned by xpatch_encode or xpatch_decode.
+ *
+ * # Parameters
+ * - `buffer`: The buffer to free
+ *
+ * # Safety
+ * - `buffer` must have been returned by xpatch_encode or from a successful xpatch_decode
+ * - `buffer` must not be used after calling this function
+ * - This function must be called exactly once per buffer
+ *
+ * # Example
+ * ```c
+ * XPatchBuffer delta = xpatch_encode(...);
+ * // Use delta...
+ * xpatch_free_buffer(delta);
+ * ```
+ */
+void xpatch_free_buffer(struct xpatch_XPatchBuffer buffer);
+
+/**
+ * Free an error message returned by xpatch functions.
+ *
+ * # Parameters
+ * - `error_message`: The error message to free
+ *
+ * # Safety
+ * - `error_message` must have been returned by a xpatch function
+ * - `error_message` must not be used after calling this function
+ * - This function must be called exactly once per error message
+ *
+ * # Example
+ * ```c
+ * char* error = xpatch_get_tag(...);
+ * if (error != NULL) {
+ *     fprintf(stderr, "Error: %s\n", error);
+ *     xpatch_free_error(error);
+ * }
+ * ```
+ */
+void xpatch_free_error(int8_t *error_message);
+
+/**
+ * Copy an error message into a caller-provided UTF-16 buffer.
+ *
+ * Intended for hosts whose native string type is UTF-16, such as .NET and Win32 `WCHAR`
+ * APIs. The message is still released with xpatch_free_error afterwards.
+ *
+ * # Parameters
+ * - `error_message`: An error message returned by a xpatch function (may be NULL)
+ * - `out`: Buffer receiving the null-terminated UTF-16 message (may be NULL if `out_len` is 0)
+ * - `out_len`: Capacity of `out` in UTF-16 code units, including the terminator
+ *
+ * # Returns
+ * The length of the message in UTF-16 code units, excluding the terminator. If this is
+ * not less than `out_len`, the message was truncated; call again with a larger buffer.
+ *
+ * # Safety
+ * - `error_message` must be NULL or a message returned by a xpatch function
+ * - `out` must point to valid memory for `out_len` UTF-16 code units
+ *
+ * # Example
+ * ```c
+ * size_t len = xpatch_error_message_utf16(error, NULL, 0);
+ * uint16_t* text = malloc((len + 1) * sizeof(uint16_t));
+ * xpatch_error_message_utf16(error, text, len + 1);
+ * ```
+ */
+uintptr_t xpatch_error_message_utf16(const int8_t *error_message, uint16_t *out, uintptr_t out_len);
+
+/**
+ * Move a buffer to the heap and return an owning pointer to it.
+ *
+ * A buffer handle is a single pointer, so it fits APIs that track native resources by
+ * one handle value, such as .NET `SafeHandle`. Release it with xpatch_buffer_release.
+ *
+ * # Parameters
+ * - `buffer`: A buffer returned by xpatch_encode or xpatch_decode
+ *
+ * # Returns
+ * A non-NULL pointer to the buffer. Read `data` and `len` through it.
+ *
+ * # Example
+ * ```c
+ * XPatchBuffer* handle = xpatch_buffer_into_handle(xpatch_encode(...));
+ * fwrite(handle->data, 1, handle->len, file);
+ * xpatch_buffer_release(handle);
+ * ```
+ */
+struct xpatch_XPatchBuffer *xpatch_buffer_into_handle(struct xpatch_XPatchBuffer buffer);
+
+/**
+ * Release a buffer handle together with the data it owns.
+ *
+ * # Parameters
+ * - `handle`: A handle returned by xpatch_buffer_into_handle (NULL is ignored)
+ *
+ * # Returns
+ * Always `true`, matching the contract of `SafeHandle.ReleaseHandle`.
+ *
+ * # Safety
+ * - `handle` must be NULL or have been returned by xpatch_buffer_into_handle
+ * - `handle` must not be used after calling this function
+ */
+bool xpatch_buffer_release(struct xpatch_XPatchBuffer *handle);
+
+/**
+ * Get the version string of the xpatch library.
+ *
+ * # Returns
+ * A null-terminated string containing the version. This string is statically allocated
+ * and must NOT be freed.
+ *
+ * # Example
+ * ```c
+ * const char* version = xpatch_version();
+ * printf("xpatch version: %s\n", version);
+ * ```
+ */
+const int8_t *xpatch_version(void);
+
+/**
+ * Get the ABI major version of the loaded xpatch library.
+ *
+ * # Returns
+ * The ABI major version the library was built with.
+ */
+uint32_t xpatch_abi_version(void);
+
+/**
+ * Check whether the loaded xpatch library provides a given ABI version.
+ *
+ * Pass the `xpatch_ABI_VERSION` and `xpatch_ABI_VERSION_MINOR` macros from the header to
+ * detect a header/library mismatch at runtime. A library is compatible with programs built
+ * against the same major version and an equal or lower minor version.
+ *
+ * # Parameters
+ * - `major`: ABI major version the caller was built against
+ * - `minor`: ABI minor version the caller was built against
+ *
+ * # Returns
+ * `true` if the loaded library implements that ABI.
+ *
+ * # Example
+ * ```c
+ * if (!xpatch_abi_compatible(xpatch_ABI_VERSION, xpatch_ABI_VERSION_MINOR)) {
+ *     fprintf(stderr, "xpatch.h does not match the loaded library\n");
+ *     return 1;
+ * }
+ * ```
+ */
+bool xpatch_abi_compatible(uint32_t major, uint32_t minor);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* XPATCH_H */
@@ -18,9 +18,13 @@
 // For commercial use in proprietary software, a commercial license is
 // available. Contact xpatch-commercial@alias.oseifert.ch for details.
 
-use std::panic;
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString, c_void};
+use std::mem::{self, offset_of};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
 use std::slice;
+use std::sync::{Once, RwLock};
 
 /// A buffer returned from xpatch functions.
 /// The caller is responsible for freeing this buffer using xpatch_free_buffer.
@@ -43,6 +47,86 @@ pub struct XPatchResult {
     pub error_message: *mut i8,
 }
 
+/// Options for xpatch_encode_with_options.
+///
+/// Initialize with xpatch_encode_options_init, passing the `sizeof` of the struct. New
+/// fields are only ever appended in later ABI minor versions, and the library reads only the
+/// first `struct_size` bytes, so programs built against an older header keep working.
+#[repr(C)]
+pub struct XPatchEncodeOptions {
+    /// Size of the struct the caller was compiled with, set by xpatch_encode_options_init
+    pub struct_size: usize,
+    /// Whether to try zstd compression (default: true)
+    pub enable_zstd: bool,
+    /// zstd compression level, 1-22 (default: 3)
+    pub zstd_level: i32,
+    /// Whether to embed CRC32 checksums of the base and the new data (default: false)
+    pub checksum: bool,
+}
+
+/// Compression state reused across calls, created with xpatch_context_new.
+pub struct XPatchContext {
+    context: xpatch::delta::Context,
+}
+
+/// Progress callback for long-running operations.
+///
+/// Called with the `user_data` pointer passed to the operation, the amount of work done
+/// and the total amount of work. Return `true` to continue or `false` to cancel.
+pub type XPatchProgressCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, done: u64, total: u64) -> bool>;
+
+/// Log callback installed with xpatch_set_log_callback.
+///
+/// Called with the level (xpatch_LOG_ERROR to xpatch_LOG_TRACE), the target (usually the Rust
+/// module that logged) and the message. Both strings are null-terminated UTF-8 and only valid
+/// during the call.
+pub type XPatchLogCallback =
+    Option<unsafe extern "C" fn(level: u32, target: *const i8, message: *const i8)>;
+
+/// Log level that disables logging.
+pub const LOG_OFF: u32 = 0;
+/// Log level for errors, including caught panics.
+pub const LOG_ERROR: u32 = 1;
+/// Log level for warnings.
+pub const LOG_WARN: u32 = 2;
+/// Log level for informational messages.
+pub const LOG_INFO: u32 = 3;
+/// Log level for debug messages.
+pub const LOG_DEBUG: u32 = 4;
+/// Log level for trace messages.
+pub const LOG_TRACE: u32 = 5;
+
+/// Allocation function with the semantics of C `malloc`.
+pub type XPatchMallocFn = Option<unsafe extern "C" fn(size: usize) -> *mut c_void>;
+
+/// Deallocation function with the semantics of C `free`.
+pub type XPatchFreeFn = Option<unsafe extern "C" fn(ptr: *mut c_void)>;
+
+/// Host allocator installed with xpatch_set_allocator.
+#[derive(Clone, Copy)]
+struct HostAllocator {
+    malloc: unsafe extern "C" fn(size: usize) -> *mut c_void,
+    free: unsafe extern "C" fn(ptr: *mut c_void),
+}
+
+static HOST_ALLOCATOR: RwLock<Option<HostAllocator>> = RwLock::new(None);
+
+/// Forwards records of the Rust `log` facade to the callback installed with
+/// xpatch_set_log_callback.
+struct HostLogger;
+
+static HOST_LOGGER: HostLogger = HostLogger;
+static LOG_CALLBACK: RwLock<XPatchLogCallback> = RwLock::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Number of catch_panic calls active on this thread.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    /// Message of the last panic caught on this thread, recorded by the panic hook.
+    static PANIC_MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
 /// Encode a delta patch between base_data and new_data.
 ///
 /// # Parameters
@@ -93,7 +177,7 @@ pub unsafe extern "C" fn xpatch_encode(
         };
     }
 
-    let result = panic::catch_unwind(|| {
+    let result = catch_panic(|| {
         // Safety: validated above
         let base = if base_len == 0 {
             &[]
@@ -107,12 +191,7 @@ pub unsafe extern "C" fn xpatch_encode(
         };
 
         let delta = xpatch::encode(tag, base, new, enable_zstd);
-        let mut boxed = delta.into_boxed_slice();
-        let data = boxed.as_mut_ptr();
-        let len = boxed.len();
-        std::mem::forget(boxed); // Prevent deallocation
-
-        XPatchBuffer { data, len }
+        into_buffer(delta)
     });
 
     match result {
@@ -163,18 +242,10 @@ pub unsafe extern "C" fn xpatch_decode(
 ) -> XPatchResult {
     // Input validation
     if (base_data.is_null() && base_len > 0) || (delta.is_null() && delta_len > 0) {
-        let error_msg = "Invalid null pointer\0";
-        let error_ptr = error_msg.as_ptr() as *mut i8;
-        return XPatchResult {
-            buffer: XPatchBuffer {
-                data: ptr::null_mut(),
-                len: 0,
-            },
-            error_message: error_ptr,
-        };
+        return error_result("Invalid null pointer");
     }
 
-    let result = panic::catch_unwind(|| {
+    let result = catch_panic(|| {
         // Safety: validated above
         let base = if base_len == 0 {
             &[]
@@ -188,47 +259,14 @@ pub unsafe extern "C" fn xpatch_decode(
         };
 
         match xpatch::decode(base, delta_slice) {
-            Ok(decoded) => {
-                let mut boxed = decoded.into_boxed_slice();
-                let data = boxed.as_mut_ptr();
-                let len = boxed.len();
-                std::mem::forget(boxed); // Prevent deallocation
-
-                XPatchResult {
-                    buffer: XPatchBuffer { data, len },
-                    error_message: ptr::null_mut(),
-                }
-            }
-            Err(error) => {
-                let error_msg = format!("{}\0", error);
-                let error_ptr = error_msg.as_ptr() as *mut i8;
-                std::mem::forget(error_msg); // Prevent deallocation
-
-                XPatchResult {
-                    buffer: XPatchBuffer {
-                        data: ptr::null_mut(),
-                        len: 0,
-                    },
-                    error_message: error_ptr,
-                }
-            }
+            Ok(decoded) => success_result(decoded),
+            Err(error) => error_result(error),
         }
     });
 
     match result {
         Ok(res) => res,
-        Err(_) => {
-            let panic_msg = "Rust panic occurred\0";
-            let error_ptr = panic_msg.as_ptr() as *mut i8;
-
-            XPatchResult {
-                buffer: XPatchBuffer {
-                    data: ptr::null_mut(),
-                    len: 0,
-                },
-                error_message: error_ptr,
-            }
-        }
+        Err(message) => error_result(&message),
     }
 }
 
@@ -267,11 +305,10 @@ pub unsafe extern "C" fn xpatch_get_tag(
 ) -> *mut i8 {
     // Input validation
     if (delta.is_null() && delta_len > 0) || tag_out.is_null() {
-        let error_msg = "Invalid null pointer\0";
-        return error_msg.as_ptr() as *mut i8;
+        return error_message("Invalid null pointer");
     }
 
-    let result = panic::catch_unwind(|| {
+    let result = catch_panic(|| {
         // Safety: validated above
         let delta_slice = if delta_len == 0 {
             &[]
@@ -284,22 +321,486 @@ pub unsafe extern "C" fn xpatch_get_tag(
                 unsafe { *tag_out = tag };
                 ptr::null_mut()
             }
-            Err(error) => {
-                let error_msg = format!("{}\0", error);
-                let error_ptr = error_msg.as_ptr() as *mut i8;
-                std::mem::forget(error_msg); // Prevent deallocation
-                error_ptr
-            }
+            Err(error) => error_message(error),
         }
     });
 
     match result {
         Ok(res) => res,
-        Err(_) => {
-            let panic_msg = "Rust panic occurred\0";
-            panic_msg.as_ptr() as *mut i8
+        Err(message) => error_message(&message),
+    }
+}
+
+/// Encode a delta patch, reporting progress to a callback.
+///
+/// Works like xpatch_encode, but calls `progress` at the start, between internal phases and
+/// at the end of the operation. Returning `false` from the callback cancels the encode.
+///
+/// # Parameters
+/// - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+/// - `base_data`: Pointer to the original data
+/// - `base_len`: Length of the original data in bytes
+/// - `new_data`: Pointer to the new data
+/// - `new_len`: Length of the new data in bytes
+/// - `enable_zstd`: Whether to enable zstd compression (true recommended)
+/// - `progress`: Progress callback (may be NULL); `total` is `new_len`
+/// - `user_data`: Opaque pointer passed through to `progress`
+///
+/// # Returns
+/// An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+/// If the callback cancelled the operation, error_message is "Operation cancelled".
+///
+/// # Safety
+/// - `base_data` must point to valid memory of at least `base_len` bytes
+/// - `new_data` must point to valid memory of at least `new_len` bytes
+/// - `progress` must be safe to call from the calling thread with `user_data`
+/// - The returned buffer must be freed with xpatch_free_buffer
+/// - The returned error message (if not NULL) must be freed with xpatch_free_error
+///
+/// # Example
+/// ```c
+/// bool on_progress(void* user_data, uint64_t done, uint64_t total) {
+///     printf("\r%llu / %llu", (unsigned long long)done, (unsigned long long)total);
+///     return !*(volatile bool*)user_data; // stop when the user pressed cancel
+/// }
+///
+/// bool cancelled = false;
+/// XPatchResult result = xpatch_encode_with_progress(
+///     0, base, base_len, new, new_len, true, on_progress, &cancelled);
+/// ```
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn xpatch_encode_with_progress(
+    tag: usize,
+    base_data: *const u8,
+    base_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    enable_zstd: bool,
+    progress: XPatchProgressCallback,
+    user_data: *mut c_void,
+) -> XPatchResult {
+    // Input validation
+    if (base_data.is_null() && base_len > 0) || (new_data.is_null() && new_len > 0) {
+        return error_result("Invalid null pointer");
+    }
+
+    let result = catch_panic(AssertUnwindSafe(|| {
+        // Safety: validated above
+        let base = unsafe { byte_slice(base_data, base_len) };
+        let new = unsafe { byte_slice(new_data, new_len) };
+
+        let encoded = match progress {
+            Some(callback) => xpatch::delta::encode_with_progress(
+                tag,
+                base,
+                new,
+                enable_zstd,
+                &mut |done, total| unsafe { callback(user_data, done, total) },
+            ),
+            None => Ok(xpatch::encode(tag, base, new, enable_zstd)),
+        };
+
+        match encoded {
+            Ok(delta) => success_result(delta),
+            Err(error) => error_result(error),
+        }
+    }));
+
+    result.unwrap_or_else(|message| error_result(&message))
+}
+
+/// Decode a delta patch, reporting progress to a callback.
+///
+/// Works like xpatch_decode, but calls `progress` at the start, between internal phases and
+/// at the end of the operation. Returning `false` from the callback cancels the decode.
+///
+/// # Parameters
+/// - `base_data`: Pointer to the original data
+/// - `base_len`: Length of the original data in bytes
+/// - `delta`: Pointer to the delta patch
+/// - `delta_len`: Length of the delta patch in bytes
+/// - `progress`: Progress callback (may be NULL); `total` is `delta_len`
+/// - `user_data`: Opaque pointer passed through to `progress`
+///
+/// # Returns
+/// An XPatchResult. On success, error_message is NULL and buffer contains the reconstructed data.
+/// If the callback cancelled the operation, error_message is "Operation cancelled".
+///
+/// # Safety
+/// - `base_data` must point to valid memory of at least `base_len` bytes
+/// - `delta` must point to valid memory of at least `delta_len` bytes
+/// - `progress` must be safe to call from the calling thread with `user_data`
+/// - The returned buffer must be freed with xpatch_free_buffer
+/// - The returned error message (if not NULL) must be freed with xpatch_free_error
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_decode_with_progress(
+    base_data: *const u8,
+    base_len: usize,
+    delta: *const u8,
+    delta_len: usize,
+    progress: XPatchProgressCallback,
+    user_data: *mut c_void,
+) -> XPatchResult {
+    // Input validation
+    if (base_data.is_null() && base_len > 0) || (delta.is_null() && delta_len > 0) {
+        return error_result("Invalid null pointer");
+    }
+
+    let result = catch_panic(AssertUnwindSafe(|| {
+        // Safety: validated above
+        let base = unsafe { byte_slice(base_data, base_len) };
+        let delta_slice = unsafe { byte_slice(delta, delta_len) };
+
+        let decoded = match progress {
+            Some(callback) => {
+                xpatch::delta::decode_with_progress(base, delta_slice, &mut |done, total| unsafe {
+                    callback(user_data, done, total)
+                })
+            }
+            None => xpatch::decode(base, delta_slice),
+        };
+
+        match decoded {
+            Ok(data) => success_result(data),
+            Err(error) => error_result(error),
+        }
+    }));
+
+    result.unwrap_or_else(|message| error_result(&message))
+}
+
+/// Initialize encoding options with their defaults.
+///
+/// # Parameters
+/// - `options`: The options to initialize
+/// - `struct_size`: `sizeof` of the struct as compiled by the caller
+///
+/// # Returns
+/// `false` if `options` is NULL or `struct_size` is smaller than the first published layout
+/// (ABI 1.1), `true` otherwise.
+///
+/// # Safety
+/// - `options` must point to at least `struct_size` writable bytes
+///
+/// # Example
+/// ```c
+/// XPatchEncodeOptions options;
+/// xpatch_encode_options_init(&options, sizeof(options));
+/// options.checksum = true;
+/// ```
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_encode_options_init(
+    options: *mut XPatchEncodeOptions,
+    struct_size: usize,
+) -> bool {
+    if options.is_null() || struct_size < ENCODE_OPTIONS_MIN_SIZE {
+        return false;
+    }
+    let defaults = xpatch::EncodeOptions::default();
+    // Fields beyond `struct_size` do not exist in the caller's layout and must not be written
+    unsafe {
+        ptr::write_bytes(options.cast::<u8>(), 0, struct_size);
+        ptr::addr_of_mut!((*options).struct_size).write(struct_size);
+        ptr::addr_of_mut!((*options).enable_zstd).write(defaults.enable_zstd);
+        ptr::addr_of_mut!((*options).zstd_level).write(defaults.zstd_level);
+        ptr::addr_of_mut!((*options).checksum).write(defaults.checksum);
+    }
+    true
+}
+
+/// Encode a delta patch with explicit encoding options.
+///
+/// # Parameters
+/// - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+/// - `base_data`: Pointer to the original data
+/// - `base_len`: Length of the original data in bytes
+/// - `new_data`: Pointer to the new data
+/// - `new_len`: Length of the new data in bytes
+/// - `options`: Options initialized with xpatch_encode_options_init, or NULL for the defaults
+///
+/// # Returns
+/// An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+///
+/// # Safety
+/// - `base_data` must point to valid memory of at least `base_len` bytes
+/// - `new_data` must point to valid memory of at least `new_len` bytes
+/// - `options` must be NULL or point to at least `options->struct_size` readable bytes
+/// - The returned buffer must be freed with xpatch_free_buffer
+/// - The returned error message (if not NULL) must be freed with xpatch_free_error
+///
+/// # Example
+/// ```c
+/// XPatchEncodeOptions options;
+/// xpatch_encode_options_init(&options, sizeof(options));
+/// options.zstd_level = 19;
+/// XPatchResult result = xpatch_encode_with_options(0, base, base_len, new, new_len, &options);
+/// ```
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_encode_with_options(
+    tag: usize,
+    base_data: *const u8,
+    base_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    options: *const XPatchEncodeOptions,
+) -> XPatchResult {
+    // Input validation
+    if (base_data.is_null() && base_len > 0) || (new_data.is_null() && new_len > 0) {
+        return error_result("Invalid null pointer");
+    }
+    let options = match unsafe { read_encode_options(options) } {
+        Ok(options) => options,
+        Err(error) => return error_result(error),
+    };
+
+    let result = catch_panic(AssertUnwindSafe(|| {
+        // Safety: validated above
+        let base = unsafe { byte_slice(base_data, base_len) };
+        let new = unsafe { byte_slice(new_data, new_len) };
+        success_result(xpatch::encode_with_options(tag, base, new, &options))
+    }));
+
+    result.unwrap_or_else(|message| error_result(&message))
+}
+
+/// Create a context that keeps compression state between calls.
+///
+/// Encoding and decoding through a context reuses its zstd contexts instead of allocating
+/// new ones for every delta, which helps services that handle many deltas. A context is used
+/// by one call at a time; create one per thread.
+///
+/// # Returns
+/// A context to pass to xpatch_context_encode and xpatch_context_decode. Free it with
+/// xpatch_context_free.
+///
+/// # Example
+/// ```c
+/// XPatchContext* context = xpatch_context_new();
+/// XPatchResult delta = xpatch_context_encode(context, 0, base, base_len, new, new_len, NULL);
+/// // ...
+/// xpatch_context_free(context);
+/// ```
+#[unsafe(no_mangle)]
+pub extern "C" fn xpatch_context_new() -> *mut XPatchContext {
+    Box::into_raw(Box::new(XPatchContext {
+        context: xpatch::delta::Context::new(),
+    }))
+}
+
+/// Free a context created with xpatch_context_new.
+///
+/// # Parameters
+/// - `context`: The context to free (NULL is ignored)
+///
+/// # Safety
+/// - `context` must be NULL or have been returned by xpatch_context_new
+/// - `context` must not be used after calling this function
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_context_free(context: *mut XPatchContext) {
+    if !context.is_null() {
+        drop(unsafe { Box::from_raw(context) });
+    }
+}
+
+/// Encode a delta patch like xpatch_encode_with_options, reusing the context's state.
+///
+/// # Parameters
+/// - `context`: A context created with xpatch_context_new
+/// - `tag`: Metadata tag to embed in the delta (0-15 with no overhead)
+/// - `base_data`: Pointer to the original data
+/// - `base_len`: Length of the original data in bytes
+/// - `new_data`: Pointer to the new data
+/// - `new_len`: Length of the new data in bytes
+/// - `options`: Options initialized with xpatch_encode_options_init, or NULL for the defaults
+///
+/// # Returns
+/// An XPatchResult. On success, error_message is NULL and buffer contains the delta.
+///
+/// # Safety
+/// - `context` must have been returned by xpatch_context_new and not be in use by another call
+/// - `base_data` must point to valid memory of at least `base_len` bytes
+/// - `new_data` must point to valid memory of at least `new_len` bytes
+/// - `options` must be NULL or point to at least `options->struct_size` readable bytes
+/// - The returned buffer must be freed with xpatch_free_buffer
+/// - The returned error message (if not NULL) must be freed with xpatch_free_error
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_context_encode(
+    context: *mut XPatchContext,
+    tag: usize,
+    base_data: *const u8,
+    base_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    options: *const XPatchEncodeOptions,
+) -> XPatchResult {
+    // Input validation
+    if context.is_null()
+        || (base_data.is_null() && base_len > 0)
+        || (new_data.is_null() && new_len > 0)
+    {
+        return error_result("Invalid null pointer");
+    }
+    let options = match unsafe { read_encode_options(options) } {
+        Ok(options) => options,
+        Err(error) => return error_result(error),
+    };
+
+    let result = catch_panic(AssertUnwindSafe(|| {
+        // Safety: validated above
+        let context = unsafe { &mut (*context).context };
+        let base = unsafe { byte_slice(base_data, base_len) };
+        let new = unsafe { byte_slice(new_data, new_len) };
+        success_result(context.encode(tag, base, new, &options))
+    }));
+
+    result.unwrap_or_else(|message| error_result(&message))
+}
+
+/// Decode a delta patch like xpatch_decode, reusing the context's state.
+///
+/// # Parameters
+/// - `context`: A context created with xpatch_context_new
+/// - `base_data`: Pointer to the original data
+/// - `base_len`: Length of the original data in bytes
+/// - `delta`: Pointer to the delta patch
+/// - `delta_len`: Length of the delta patch in bytes
+///
+/// # Returns
+/// An XPatchResult. On success, error_message is NULL and buffer contains the reconstructed data.
+///
+/// # Safety
+/// - `context` must have been returned by xpatch_context_new and not be in use by another call
+/// - `base_data` must point to valid memory of at least `base_len` bytes
+/// - `delta` must point to valid memory of at least `delta_len` bytes
+/// - The returned buffer must be freed with xpatch_free_buffer
+/// - The returned error message (if not NULL) must be freed with xpatch_free_error
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_context_decode(
+    context: *mut XPatchContext,
+    base_data: *const u8,
+    base_len: usize,
+    delta: *const u8,
+    delta_len: usize,
+) -> XPatchResult {
+    // Input validation
+    if context.is_null()
+        || (base_data.is_null() && base_len > 0)
+        || (delta.is_null() && delta_len > 0)
+    {
+        return error_result("Invalid null pointer");
+    }
+
+    let result = catch_panic(AssertUnwindSafe(|| {
+        // Safety: validated above
+        let context = unsafe { &mut (*context).context };
+        let base = unsafe { byte_slice(base_data, base_len) };
+        let delta = unsafe { byte_slice(delta, delta_len) };
+        match context.decode(base, delta) {
+            Ok(decoded) => success_result(decoded),
+            Err(error) => error_result(error),
         }
+    }));
+
+    result.unwrap_or_else(|message| error_result(&message))
+}
+
+/// Use the host application's allocator for buffers and error messages returned by xpatch.
+///
+/// Once installed, every XPatchBuffer and error message handed out by xpatch is allocated
+/// with `malloc_fn` and released with `free_fn` in xpatch_free_buffer and xpatch_free_error.
+/// This lets the host free them with its own `free` and avoids crossing CRT heaps (e.g.
+/// Windows DLL consumers). Buffers are allocated at their final size, so no `realloc` is
+/// needed. If `malloc_fn` fails for an error message, the message is a static "Out of memory"
+/// that only xpatch_free_error may release.
+///
+/// Pass NULL for both functions to restore the default Rust allocator.
+///
+/// # Parameters
+/// - `malloc_fn`: Allocation function (`malloc` semantics)
+/// - `free_fn`: Deallocation function (`free` semantics)
+///
+/// # Returns
+/// `true` if the allocator was installed, `false` if only one of the functions was provided.
+///
+/// # Safety
+/// - Call this before any other xpatch function, or while no xpatch buffers or error
+///   messages are alive: they must be freed by the same allocator that allocated them
+/// - The functions must be thread-safe if xpatch is used from multiple threads
+///
+/// # Example
+/// ```c
+/// #include <stdlib.h>
+///
+/// xpatch_set_allocator(malloc, free);
+/// XPatchResult result = xpatch_decode(...);
+/// free(result.buffer.data); // or xpatch_free_buffer(result.buffer)
+/// free(result.error_message); // or xpatch_free_error(result.error_message)
+/// ```
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_set_allocator(
+    malloc_fn: XPatchMallocFn,
+    free_fn: XPatchFreeFn,
+) -> bool {
+    let allocator = match (malloc_fn, free_fn) {
+        (Some(malloc), Some(free)) => Some(HostAllocator { malloc, free }),
+        (None, None) => None,
+        _ => return false,
+    };
+
+    match HOST_ALLOCATOR.write() {
+        Ok(mut guard) => *guard = allocator,
+        Err(poisoned) => *poisoned.into_inner() = allocator,
+    }
+    true
+}
+
+/// Forward log messages of xpatch (and any other Rust code using the `log` crate in this
+/// process) to a host callback.
+///
+/// Panics caught at the FFI boundary are logged at xpatch_LOG_ERROR with their message and
+/// source location, which also appear in the returned error message.
+///
+/// # Parameters
+/// - `level`: Most verbose level to forward, xpatch_LOG_OFF to xpatch_LOG_TRACE
+/// - `callback`: Function receiving the messages, or NULL to stop logging
+///
+/// # Returns
+/// `false` if another Rust logger was already installed in this process, `true` otherwise.
+///
+/// # Safety
+/// - `callback` is called from whichever thread logs, so it must be thread-safe
+/// - `callback` must not call xpatch_set_log_callback
+///
+/// # Example
+/// ```c
+/// void on_log(uint32_t level, const char* target, const char* message) {
+///     fprintf(stderr, "[xpatch %u] %s: %s\n", level, target, message);
+/// }
+///
+/// xpatch_set_log_callback(xpatch_LOG_WARN, on_log);
+/// ```
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_set_log_callback(level: u32, callback: XPatchLogCallback) -> bool {
+    if log::set_logger(&HOST_LOGGER).is_err()
+        && !ptr::addr_eq(log::logger(), &HOST_LOGGER as &dyn log::Log)
+    {
+        return false;
     }
+
+    match LOG_CALLBACK.write() {
+        Ok(mut guard) => *guard = callback,
+        Err(poisoned) => *poisoned.into_inner() = callback,
+    }
+    log::set_max_level(match (callback, level) {
+        (None, _) | (_, LOG_OFF) => log::LevelFilter::Off,
+        (_, LOG_ERROR) => log::LevelFilter::Error,
+        (_, LOG_WARN) => log::LevelFilter::Warn,
+        (_, LOG_INFO) => log::LevelFilter::Info,
+        (_, LOG_DEBUG) => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    });
+    true
 }
 
 /// Free a buffer returned by xpatch_encode or xpatch_decode.
@@ -320,11 +821,7 @@ pub unsafe extern "C" fn xpatch_get_tag(
 /// ```
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn xpatch_free_buffer(buffer: XPatchBuffer) {
-    if !buffer.data.is_null() && buffer.len > 0 {
-        unsafe {
-            let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer.data, buffer.len));
-        }
-    }
+    unsafe { free_buffer_with(host_allocator(), buffer) }
 }
 
 /// Free an error message returned by xpatch functions.
@@ -347,34 +844,407 @@ pub unsafe extern "C" fn xpatch_free_buffer(buffer: XPatchBuffer) {
 /// ```
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn xpatch_free_error(error_message: *mut i8) {
-    if !error_message.is_null() {
+    unsafe { free_error_with(host_allocator(), error_message) }
+}
+
+/// Copy an error message into a caller-provided UTF-16 buffer.
+///
+/// Intended for hosts whose native string type is UTF-16, such as .NET and Win32 `WCHAR`
+/// APIs. The message is still released with xpatch_free_error afterwards.
+///
+/// # Parameters
+/// - `error_message`: An error message returned by a xpatch function (may be NULL)
+/// - `out`: Buffer receiving the null-terminated UTF-16 message (may be NULL if `out_len` is 0)
+/// - `out_len`: Capacity of `out` in UTF-16 code units, including the terminator
+///
+/// # Returns
+/// The length of the message in UTF-16 code units, excluding the terminator. If this is
+/// not less than `out_len`, the message was truncated; call again with a larger buffer.
+///
+/// # Safety
+/// - `error_message` must be NULL or a message returned by a xpatch function
+/// - `out` must point to valid memory for `out_len` UTF-16 code units
+///
+/// # Example
+/// ```c
+/// size_t len = xpatch_error_message_utf16(error, NULL, 0);
+/// uint16_t* text = malloc((len + 1) * sizeof(uint16_t));
+/// xpatch_error_message_utf16(error, text, len + 1);
+/// ```
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_error_message_utf16(
+    error_message: *const i8,
+    out: *mut u16,
+    out_len: usize,
+) -> usize {
+    let units: Vec<u16> = if error_message.is_null() {
+        Vec::new()
+    } else {
+        let message = unsafe { CStr::from_ptr(error_message.cast()) };
+        message.to_string_lossy().encode_utf16().collect()
+    };
+
+    if !out.is_null() && out_len > 0 {
+        let copied = units.len().min(out_len - 1);
         unsafe {
-            // We need to reconstruct the original String to free it properly
-            // The error messages are created with format!("{}\0", ...) so we need to find the length
-            let mut len = 0;
-            while *error_message.offset(len) != 0 {
-                len += 1;
+            ptr::copy_nonoverlapping(units.as_ptr(), out, copied);
+            *out.add(copied) = 0;
+        }
+    }
+    units.len()
+}
+
+/// Move a buffer to the heap and return an owning pointer to it.
+///
+/// A buffer handle is a single pointer, so it fits APIs that track native resources by
+/// one handle value, such as .NET `SafeHandle`. Release it with xpatch_buffer_release.
+///
+/// # Parameters
+/// - `buffer`: A buffer returned by xpatch_encode or xpatch_decode
+///
+/// # Returns
+/// A non-NULL pointer to the buffer. Read `data` and `len` through it.
+///
+/// # Example
+/// ```c
+/// XPatchBuffer* handle = xpatch_buffer_into_handle(xpatch_encode(...));
+/// fwrite(handle->data, 1, handle->len, file);
+/// xpatch_buffer_release(handle);
+/// ```
+#[unsafe(no_mangle)]
+pub extern "C" fn xpatch_buffer_into_handle(buffer: XPatchBuffer) -> *mut XPatchBuffer {
+    Box::into_raw(Box::new(buffer))
+}
+
+/// Release a buffer handle together with the data it owns.
+///
+/// # Parameters
+/// - `handle`: A handle returned by xpatch_buffer_into_handle (NULL is ignored)
+///
+/// # Returns
+/// Always `true`, matching the contract of `SafeHandle.ReleaseHandle`.
+///
+/// # Safety
+/// - `handle` must be NULL or have been returned by xpatch_buffer_into_handle
+/// - `handle` must not be used after calling this function
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xpatch_buffer_release(handle: *mut XPatchBuffer) -> bool {
+    if !handle.is_null() {
+        let buffer = unsafe { Box::from_raw(handle) };
+        unsafe { xpatch_free_buffer(*buffer) };
+    }
+    true
+}
+
+/// Get the version string of the xpatch library.
+///
+/// # Returns
+/// A null-terminated string containing the version. This string is statically allocated
+/// and must NOT be freed.
+///
+/// # Example
+/// ```c
+/// const char* version = xpatch_version();
+/// printf("xpatch version: %s\n", version);
+/// ```
+#[unsafe(no_mangle)]
+pub extern "C" fn xpatch_version() -> *const i8 {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const i8
+}
+
+/// ABI major version of the C interface described by `xpatch.h`.
+///
+/// Bumped whenever an exported function or struct changes incompatibly or is removed, together
+/// with the shared library's soname (`libxpatch_c.so.<major>`).
+pub const ABI_VERSION: u32 = 1;
+
+/// ABI minor version of the C interface described by `xpatch.h`.
+///
+/// Bumped whenever functions are added or fields are appended to an options struct. Existing
+/// structs never change; new settings go into options structs that carry their own size.
+pub const ABI_VERSION_MINOR: u32 = 3;
+
+/// Get the ABI major version of the loaded xpatch library.
+///
+/// # Returns
+/// The ABI major version the library was built with.
+#[unsafe(no_mangle)]
+pub extern "C" fn xpatch_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Check whether the loaded xpatch library provides a given ABI version.
+///
+/// Pass the `xpatch_ABI_VERSION` and `xpatch_ABI_VERSION_MINOR` macros from the header to
+/// detect a header/library mismatch at runtime. A library is compatible with programs built
+/// against the same major version and an equal or lower minor version.
+///
+/// # Parameters
+/// - `major`: ABI major version the caller was built against
+/// - `minor`: ABI minor version the caller was built against
+///
+/// # Returns
+/// `true` if the loaded library implements that ABI.
+///
+/// # Example
+/// ```c
+/// if (!xpatch_abi_compatible(xpatch_ABI_VERSION, xpatch_ABI_VERSION_MINOR)) {
+///     fprintf(stderr, "xpatch.h does not match the loaded library\n");
+///     return 1;
+/// }
+/// ```
+#[unsafe(no_mangle)]
+pub extern "C" fn xpatch_abi_compatible(major: u32, minor: u32) -> bool {
+    major == ABI_VERSION && minor <= ABI_VERSION_MINOR
+}
+
+// ============================================================================
+// Internal helpers
+// ============================================================================
+
+/// Builds a slice from a validated pointer/length pair, allowing NULL for empty input.
+///
+/// # Safety
+/// `data` must point to at least `len` readable bytes unless `len` is 0.
+unsafe fn byte_slice<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Size of the first published XPatchEncodeOptions layout (ABI 1.1).
+const ENCODE_OPTIONS_MIN_SIZE: usize =
+    offset_of!(XPatchEncodeOptions, checksum) + mem::size_of::<bool>();
+
+/// Reads the fields of `options` that fit into its `struct_size`, keeping defaults for the rest.
+///
+/// # Safety
+/// `options` must be NULL or point to at least `struct_size` readable bytes.
+unsafe fn read_encode_options(
+    options: *const XPatchEncodeOptions,
+) -> Result<xpatch::EncodeOptions, &'static str> {
+    let mut parsed = xpatch::EncodeOptions::default();
+    if options.is_null() {
+        return Ok(parsed);
+    }
+    let size = unsafe { ptr::addr_of!((*options).struct_size).read() };
+    if size < ENCODE_OPTIONS_MIN_SIZE {
+        return Err("Invalid options struct size");
+    }
+    unsafe {
+        parsed.enable_zstd = ptr::addr_of!((*options).enable_zstd).read();
+        parsed.zstd_level = ptr::addr_of!((*options).zstd_level).read();
+        parsed.checksum = ptr::addr_of!((*options).checksum).read();
+    }
+    if !(1..=22).contains(&parsed.zstd_level) {
+        return Err("zstd_level must be between 1 and 22");
+    }
+    Ok(parsed)
+}
+
+impl log::Log for HostLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // Copied out so the callback runs without holding the lock
+        let callback = match LOG_CALLBACK.read() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        };
+        let Some(callback) = callback else {
+            return;
+        };
+
+        let level = match record.level() {
+            log::Level::Error => LOG_ERROR,
+            log::Level::Warn => LOG_WARN,
+            log::Level::Info => LOG_INFO,
+            log::Level::Debug => LOG_DEBUG,
+            log::Level::Trace => LOG_TRACE,
+        };
+        let target = CString::new(record.target().replace('\0', " ")).unwrap_or_default();
+        let message =
+            CString::new(record.args().to_string().replace('\0', " ")).unwrap_or_default();
+        unsafe { callback(level, target.as_ptr().cast(), message.as_ptr().cast()) };
+    }
+
+    fn flush(&self) {}
+}
+
+/// Runs `f`, turning a panic into an error message with the panic's message and location.
+///
+/// Panics inside `f` are logged instead of being printed to stderr by the default hook.
+fn catch_panic<R>(f: impl FnOnce() -> R + panic::UnwindSafe) -> Result<R, String> {
+    install_panic_hook();
+    CATCHING.with(|depth| depth.set(depth.get() + 1));
+    let result = panic::catch_unwind(f);
+    CATCHING.with(|depth| depth.set(depth.get() - 1));
+
+    result.map_err(|payload| {
+        // Without our hook (replaced by the host), only the payload is left
+        let message = PANIC_MESSAGE
+            .with(|message| message.borrow_mut().take())
+            .unwrap_or_else(|| payload_message(payload.as_ref()).to_string());
+        format!("Rust panic occurred: {}", message)
+    })
+}
+
+/// Installs a process-wide panic hook that records panics inside catch_panic and passes all
+/// other panics on to the previous hook.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) == 0 {
+                return previous(info);
             }
-            let _ =
-                String::from_raw_parts(error_message as *mut u8, len as usize, len as usize + 1);
+            let message = match info.location() {
+                Some(location) => format!(
+                    "{} at {}:{}",
+                    payload_message(info.payload()),
+                    location.file(),
+                    location.line()
+                ),
+                None => payload_message(info.payload()).to_string(),
+            };
+            log::error!("panic: {}", message);
+            PANIC_MESSAGE.with(|slot| *slot.borrow_mut() = Some(message));
+        }));
+    });
+}
+
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic payload")
+}
+
+fn host_allocator() -> Option<HostAllocator> {
+    match HOST_ALLOCATOR.read() {
+        Ok(guard) => *guard,
+        Err(poisoned) => *poisoned.into_inner(),
+    }
+}
+
+/// Hands ownership of `data` to the caller as an XPatchBuffer.
+///
+/// Returns a buffer with a NULL data pointer if the host allocator failed.
+fn into_buffer(data: Vec<u8>) -> XPatchBuffer {
+    into_buffer_with(host_allocator(), data)
+}
+
+fn into_buffer_with(allocator: Option<HostAllocator>, data: Vec<u8>) -> XPatchBuffer {
+    if let Some(allocator) = allocator {
+        if data.is_empty() {
+            return XPatchBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+        }
+
+        let host = unsafe { (allocator.malloc)(data.len()) } as *mut u8;
+        if host.is_null() {
+            log::warn!("host allocator failed to allocate {} bytes", data.len());
+            return XPatchBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+        }
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), host, data.len()) };
+        return XPatchBuffer {
+            data: host,
+            len: data.len(),
+        };
+    }
+
+    let mut boxed = data.into_boxed_slice();
+    let data = boxed.as_mut_ptr();
+    let len = boxed.len();
+    std::mem::forget(boxed); // Prevent deallocation
+
+    XPatchBuffer { data, len }
+}
+
+/// Releases a buffer created by into_buffer_with using the same allocator.
+unsafe fn free_buffer_with(allocator: Option<HostAllocator>, buffer: XPatchBuffer) {
+    if buffer.data.is_null() {
+        return;
+    }
+
+    if let Some(allocator) = allocator {
+        unsafe { (allocator.free)(buffer.data as *mut c_void) };
+    } else if buffer.len > 0 {
+        unsafe {
+            let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer.data, buffer.len));
         }
     }
 }
 
-/// Get the version string of the xpatch library.
-///
-/// # Returns
-/// A null-terminated string containing the version. This string is statically allocated
-/// and must NOT be freed.
-///
-/// # Example
-/// ```c
-/// const char* version = xpatch_version();
-/// printf("xpatch version: %s\n", version);
-/// ```
-#[unsafe(no_mangle)]
-pub extern "C" fn xpatch_version() -> *const i8 {
-    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const i8
+/// Returned in place of an error message the host allocator failed to allocate.
+static OUT_OF_MEMORY: &CStr = c"Out of memory";
+
+/// Allocates a null-terminated error message that xpatch_free_error can release.
+fn error_message(message: &str) -> *mut i8 {
+    error_message_with(host_allocator(), message)
+}
+
+fn error_message_with(allocator: Option<HostAllocator>, message: &str) -> *mut i8 {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    if allocator.is_none() {
+        return message.into_raw().cast();
+    }
+
+    let buffer = into_buffer_with(allocator, message.into_bytes_with_nul());
+    if buffer.data.is_null() {
+        return OUT_OF_MEMORY.as_ptr().cast_mut().cast();
+    }
+    buffer.data.cast()
+}
+
+/// Releases an error message created by error_message_with using the same allocator.
+unsafe fn free_error_with(allocator: Option<HostAllocator>, error_message: *mut i8) {
+    if error_message.is_null() || ptr::eq(error_message, OUT_OF_MEMORY.as_ptr().cast()) {
+        return;
+    }
+
+    match allocator {
+        Some(allocator) => unsafe { (allocator.free)(error_message.cast()) },
+        None => unsafe {
+            let _ = CString::from_raw(error_message.cast());
+        },
+    }
+}
+
+fn success_result(data: Vec<u8>) -> XPatchResult {
+    let expected_len = data.len();
+    let buffer = into_buffer(data);
+    if buffer.data.is_null() && expected_len > 0 {
+        return error_result("Out of memory");
+    }
+
+    XPatchResult {
+        buffer,
+        error_message: ptr::null_mut(),
+    }
+}
+
+fn error_result(message: &str) -> XPatchResult {
+    XPatchResult {
+        buffer: XPatchBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        },
+        error_message: error_message(message),
+    }
 }
 
 #[cfg(test)]
@@ -409,6 +1279,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_roundtrip_arbitrary_pairs() {
+        for seed in 0..64 {
+            let (base, new) = xpatch::testing::arbitrary_pair(seed);
+
+            unsafe {
+                let delta = xpatch_encode(
+                    seed as usize,
+                    base.as_ptr(),
+                    base.len(),
+                    new.as_ptr(),
+                    new.len(),
+                    seed % 2 == 0,
+                );
+                let delta_bytes = slice::from_raw_parts(delta.data, delta.len);
+                if let Err(e) = xpatch::testing::roundtrip_check(&base, &new, delta_bytes) {
+                    panic!("seed {}: {}", seed, e);
+                }
+
+                let result = xpatch_decode(base.as_ptr(), base.len(), delta.data, delta.len);
+                assert!(result.error_message.is_null());
+                // Empty outputs may come back as a null pointer
+                let decoded = if result.buffer.len == 0 {
+                    &[][..]
+                } else {
+                    slice::from_raw_parts(result.buffer.data, result.buffer.len)
+                };
+                assert_eq!(decoded, new.as_slice());
+
+                xpatch_free_buffer(delta);
+                xpatch_free_buffer(result.buffer);
+            }
+        }
+    }
+
     #[test]
     fn test_get_tag() {
         let base = b"Hello, World!";
@@ -453,6 +1358,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_abi_version() {
+        assert_eq!(xpatch_abi_version(), ABI_VERSION);
+        assert!(xpatch_abi_compatible(ABI_VERSION, ABI_VERSION_MINOR));
+        assert!(xpatch_abi_compatible(ABI_VERSION, 0));
+        assert!(!xpatch_abi_compatible(ABI_VERSION, ABI_VERSION_MINOR + 1));
+        assert!(!xpatch_abi_compatible(ABI_VERSION + 1, 0));
+    }
+
+    #[test]
+    fn test_header_declares_all_exports() {
+        // The shipped header must stay in sync with every exported symbol
+        let header = include_str!("../include/xpatch.h");
+        let source = include_str!("lib.rs");
+
+        let exports: Vec<&str> = source
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_start();
+                line.strip_prefix("pub unsafe extern \"C\" fn ")
+                    .or_else(|| line.strip_prefix("pub extern \"C\" fn "))
+            })
+            .filter_map(|rest| rest.split('(').next())
+            .collect();
+
+        assert!(!exports.is_empty());
+        for name in exports {
+            assert!(
+                header.contains(&format!("{}(", name)),
+                "include/xpatch.h is missing `{}`, rebuild xpatch-c to regenerate it",
+                name
+            );
+        }
+        assert!(header.contains(&format!("#define xpatch_ABI_VERSION {}", ABI_VERSION)));
+        assert!(header.contains(&format!(
+            "#define xpatch_ABI_VERSION_MINOR {}",
+            ABI_VERSION_MINOR
+        )));
+    }
+
+    #[test]
+    fn test_symbol_map_matches_exports() {
+        let map = include_str!("../symbols.map");
+        let source = include_str!("lib.rs");
+
+        let mut exports: Vec<&str> = source
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_start();
+                line.strip_prefix("pub unsafe extern \"C\" fn ")
+                    .or_else(|| line.strip_prefix("pub extern \"C\" fn "))
+            })
+            .filter_map(|rest| rest.split('(').next())
+            .collect();
+        let mut versions = Vec::new();
+        let mut symbols = Vec::new();
+        for line in map
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            match line.strip_suffix(':') {
+                Some(version) => versions.push(version),
+                None => symbols.push(line.trim()),
+            }
+        }
+
+        exports.sort_unstable();
+        symbols.sort_unstable();
+        assert_eq!(
+            symbols, exports,
+            "symbols.map must list every exported function"
+        );
+        assert_eq!(
+            versions.last().copied(),
+            Some(format!("{}.{}", ABI_VERSION, ABI_VERSION_MINOR).as_str()),
+            "symbols added since the last ABI version need a new minor version"
+        );
+    }
+
     #[test]
     fn test_empty_data() {
         let base = b"";
@@ -768,6 +1752,288 @@ mod tests {
         }
     }
 
+    unsafe extern "C" fn count_progress(user_data: *mut c_void, done: u64, total: u64) -> bool {
+        assert!(done <= total);
+        unsafe { *(user_data as *mut u32) += 1 };
+        true
+    }
+
+    unsafe extern "C" fn cancel_progress(_user_data: *mut c_void, _done: u64, _total: u64) -> bool {
+        false
+    }
+
+    #[test]
+    fn test_progress_roundtrip() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"A fast red wolf leaps across the sleepy cat";
+        let mut calls: u32 = 0;
+        let user_data = &mut calls as *mut u32 as *mut c_void;
+
+        unsafe {
+            let delta = xpatch_encode_with_progress(
+                0,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                true,
+                Some(count_progress),
+                user_data,
+            );
+            assert!(delta.error_message.is_null());
+            assert!(calls >= 2);
+
+            calls = 0;
+            let result = xpatch_decode_with_progress(
+                base.as_ptr(),
+                base.len(),
+                delta.buffer.data,
+                delta.buffer.len,
+                Some(count_progress),
+                user_data,
+            );
+            assert!(result.error_message.is_null());
+            assert!(calls >= 2);
+
+            let decoded = slice::from_raw_parts(result.buffer.data, result.buffer.len);
+            assert_eq!(decoded, new);
+
+            xpatch_free_buffer(delta.buffer);
+            xpatch_free_buffer(result.buffer);
+        }
+    }
+
+    #[test]
+    fn test_progress_cancel() {
+        let base = b"Hello, World!";
+        let new = b"Hello, Rust!";
+
+        unsafe {
+            let result = xpatch_encode_with_progress(
+                0,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                false,
+                Some(cancel_progress),
+                ptr::null_mut(),
+            );
+            assert!(result.buffer.data.is_null());
+            assert!(!result.error_message.is_null());
+
+            let error_str = std::ffi::CStr::from_ptr(result.error_message);
+            assert_eq!(error_str.to_str().unwrap(), "Operation cancelled");
+            xpatch_free_error(result.error_message);
+
+            // A NULL callback behaves like xpatch_encode
+            let result = xpatch_encode_with_progress(
+                0,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                false,
+                None,
+                ptr::null_mut(),
+            );
+            assert!(result.error_message.is_null());
+            xpatch_free_buffer(result.buffer);
+        }
+    }
+
+    #[test]
+    fn test_encode_with_options() {
+        let base = b"Hello, World! Hello, World! Hello, World!";
+        let new = b"Hello, Rust! Hello, World! Hello, World!";
+
+        unsafe {
+            let mut options = mem::MaybeUninit::<XPatchEncodeOptions>::uninit();
+            assert!(xpatch_encode_options_init(
+                options.as_mut_ptr(),
+                mem::size_of::<XPatchEncodeOptions>()
+            ));
+            let mut options = options.assume_init();
+            assert!(options.enable_zstd);
+            assert_eq!(options.zstd_level, 3);
+            options.checksum = true;
+
+            let result = xpatch_encode_with_options(
+                7,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                &options,
+            );
+            assert!(result.error_message.is_null());
+            let delta = slice::from_raw_parts(result.buffer.data, result.buffer.len);
+            let info = xpatch::inspect(delta).unwrap();
+            assert_eq!(info.tag, 7);
+            assert!(info.base_checksum.is_some());
+            assert_eq!(xpatch::decode(base, delta).unwrap(), new);
+            xpatch_free_buffer(result.buffer);
+
+            // NULL selects the defaults
+            let result = xpatch_encode_with_options(
+                0,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                ptr::null(),
+            );
+            assert!(result.error_message.is_null());
+            xpatch_free_buffer(result.buffer);
+
+            options.zstd_level = 0;
+            let result = xpatch_encode_with_options(
+                0,
+                base.as_ptr(),
+                base.len(),
+                new.as_ptr(),
+                new.len(),
+                &options,
+            );
+            assert!(!result.error_message.is_null());
+            xpatch_free_error(result.error_message);
+        }
+    }
+
+    #[test]
+    fn test_context() {
+        let base = b"key = value\n".repeat(1000);
+        let mut new = base.clone();
+        new[6_000..6_005].copy_from_slice(b"other");
+
+        unsafe {
+            let context = xpatch_context_new();
+            assert!(!context.is_null());
+            for _ in 0..3 {
+                let result = xpatch_context_encode(
+                    context,
+                    5,
+                    base.as_ptr(),
+                    base.len(),
+                    new.as_ptr(),
+                    new.len(),
+                    ptr::null(),
+                );
+                assert!(result.error_message.is_null());
+                let decoded = xpatch_context_decode(
+                    context,
+                    base.as_ptr(),
+                    base.len(),
+                    result.buffer.data,
+                    result.buffer.len,
+                );
+                assert!(decoded.error_message.is_null());
+                assert_eq!(
+                    slice::from_raw_parts(decoded.buffer.data, decoded.buffer.len),
+                    &new[..]
+                );
+                xpatch_free_buffer(decoded.buffer);
+                xpatch_free_buffer(result.buffer);
+            }
+
+            let result = xpatch_context_decode(context, base.as_ptr(), base.len(), b"".as_ptr(), 0);
+            assert!(!result.error_message.is_null());
+            xpatch_free_error(result.error_message);
+            xpatch_context_free(context);
+
+            let result = xpatch_context_decode(ptr::null_mut(), ptr::null(), 0, ptr::null(), 0);
+            assert!(!result.error_message.is_null());
+            xpatch_free_error(result.error_message);
+            xpatch_context_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_encode_options_size() {
+        unsafe {
+            // A caller built against a header without the last field
+            let mut options = mem::MaybeUninit::<XPatchEncodeOptions>::zeroed();
+            let size = offset_of!(XPatchEncodeOptions, checksum);
+            assert!(!xpatch_encode_options_init(options.as_mut_ptr(), size));
+            assert!(!xpatch_encode_options_init(
+                ptr::null_mut(),
+                ENCODE_OPTIONS_MIN_SIZE
+            ));
+
+            // Bytes beyond struct_size belong to the caller and must stay untouched
+            let mut words =
+                [usize::MAX; mem::size_of::<XPatchEncodeOptions>() / mem::size_of::<usize>() + 1];
+            let options = words.as_mut_ptr().cast::<XPatchEncodeOptions>();
+            assert!(xpatch_encode_options_init(options, ENCODE_OPTIONS_MIN_SIZE));
+            let bytes = slice::from_raw_parts(options.cast::<u8>(), mem::size_of_val(&words));
+            assert!(bytes[ENCODE_OPTIONS_MIN_SIZE..].iter().all(|&b| b == 0xFF));
+            assert!(read_encode_options(options).unwrap().enable_zstd);
+
+            ptr::addr_of_mut!((*options).struct_size).write(size);
+            assert!(read_encode_options(options).is_err());
+        }
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let error = catch_panic(|| panic!("boom")).unwrap_err();
+        assert!(
+            error.starts_with("Rust panic occurred: boom at "),
+            "{}",
+            error
+        );
+        assert!(error.contains("lib.rs:"), "{}", error);
+
+        let error = catch_panic(|| panic!("code {}", 42)).unwrap_err();
+        assert!(
+            error.starts_with("Rust panic occurred: code 42 at "),
+            "{}",
+            error
+        );
+
+        assert_eq!(catch_panic(|| 7), Ok(7));
+    }
+
+    static LOGGED: std::sync::Mutex<Vec<(u32, String)>> = std::sync::Mutex::new(Vec::new());
+
+    unsafe extern "C" fn record_log(level: u32, target: *const i8, message: *const i8) {
+        let target = unsafe { std::ffi::CStr::from_ptr(target.cast()) };
+        let message = unsafe { std::ffi::CStr::from_ptr(message.cast()) };
+        assert!(!target.is_empty());
+        LOGGED
+            .lock()
+            .unwrap()
+            .push((level, message.to_string_lossy().into_owned()));
+    }
+
+    #[test]
+    fn test_log_callback() {
+        let logged = |marker: &str| -> Vec<(u32, String)> {
+            let logged = LOGGED.lock().unwrap();
+            logged
+                .iter()
+                .filter(|(_, message)| message.contains(marker))
+                .cloned()
+                .collect()
+        };
+
+        unsafe {
+            assert!(xpatch_set_log_callback(LOG_WARN, Some(record_log)));
+            log::warn!("log-test warning");
+            log::info!("log-test info");
+            assert_eq!(logged("log-test"), [(LOG_WARN, "log-test warning".into())]);
+
+            let _ = catch_panic(|| panic!("log-test panic"));
+            let panics = logged("log-test panic");
+            assert_eq!(panics.len(), 1);
+            assert_eq!(panics[0].0, LOG_ERROR);
+
+            assert!(xpatch_set_log_callback(LOG_TRACE, None));
+            log::error!("log-test after");
+            assert!(logged("log-test after").is_empty());
+        }
+    }
+
     #[test]
     fn test_free_null_buffer() {
         // Test that freeing a null/empty buffer doesn't crash
@@ -787,4 +2053,174 @@ mod tests {
             xpatch_free_error(ptr::null_mut()); // Should not crash
         }
     }
+
+    static HOST_ALLOCATIONS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
+    unsafe extern "C" fn counting_malloc(size: usize) -> *mut c_void {
+        HOST_ALLOCATIONS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let layout = std::alloc::Layout::from_size_align(size + 16, 16).unwrap();
+        unsafe {
+            let base = std::alloc::alloc(layout);
+            (base as *mut usize).write(size);
+            base.add(16) as *mut c_void
+        }
+    }
+
+    unsafe extern "C" fn counting_free(ptr: *mut c_void) {
+        HOST_ALLOCATIONS.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        unsafe {
+            let base = (ptr as *mut u8).sub(16);
+            let size = (base as *const usize).read();
+            let layout = std::alloc::Layout::from_size_align(size + 16, 16).unwrap();
+            std::alloc::dealloc(base, layout);
+        }
+    }
+
+    #[test]
+    fn test_host_allocator() {
+        let allocator = Some(HostAllocator {
+            malloc: counting_malloc,
+            free: counting_free,
+        });
+
+        let buffer = into_buffer_with(allocator, vec![1, 2, 3, 4, 5]);
+        assert!(!buffer.data.is_null());
+        assert_eq!(
+            HOST_ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        unsafe {
+            assert_eq!(
+                slice::from_raw_parts(buffer.data, buffer.len),
+                &[1, 2, 3, 4, 5]
+            );
+            free_buffer_with(allocator, buffer);
+        }
+        assert_eq!(
+            HOST_ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+
+        // Empty output never reaches the host allocator
+        let empty = into_buffer_with(allocator, Vec::new());
+        assert!(empty.data.is_null());
+        assert_eq!(empty.len, 0);
+        unsafe { free_buffer_with(allocator, empty) };
+        assert_eq!(
+            HOST_ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+
+        // Error messages come from the host allocator as well
+        let error = error_message_with(allocator, "Invalid\0delta");
+        assert_eq!(
+            HOST_ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        unsafe {
+            assert_eq!(CStr::from_ptr(error).to_str().unwrap(), "Invalid delta");
+            free_error_with(allocator, error);
+        }
+        assert_eq!(
+            HOST_ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+    }
+
+    #[test]
+    fn test_set_allocator_rejects_partial() {
+        unsafe {
+            assert!(!xpatch_set_allocator(Some(counting_malloc), None));
+            assert!(!xpatch_set_allocator(None, Some(counting_free)));
+            assert!(host_allocator().is_none());
+
+            // Resetting to the default is always allowed
+            assert!(xpatch_set_allocator(None, None));
+            assert!(host_allocator().is_none());
+        }
+    }
+
+    #[test]
+    fn test_error_message_utf16() {
+        let error = error_message("Ungültiges Delta");
+
+        unsafe {
+            let len = xpatch_error_message_utf16(error, ptr::null_mut(), 0);
+            assert_eq!(len, "Ungültiges Delta".encode_utf16().count());
+
+            let mut out = vec![0xFFFFu16; len + 1];
+            assert_eq!(
+                xpatch_error_message_utf16(error, out.as_mut_ptr(), out.len()),
+                len
+            );
+            assert_eq!(String::from_utf16(&out[..len]).unwrap(), "Ungültiges Delta");
+            assert_eq!(out[len], 0);
+
+            // Truncated, but still terminated
+            let mut short = [0xFFFFu16; 4];
+            assert_eq!(
+                xpatch_error_message_utf16(error, short.as_mut_ptr(), 4),
+                len
+            );
+            assert_eq!(String::from_utf16(&short[..3]).unwrap(), "Ung");
+            assert_eq!(short[3], 0);
+
+            assert_eq!(
+                xpatch_error_message_utf16(ptr::null(), short.as_mut_ptr(), 4),
+                0
+            );
+            assert_eq!(short[0], 0);
+
+            xpatch_free_error(error);
+        }
+    }
+
+    #[test]
+    fn test_buffer_handle() {
+        let base = b"Hello, World!";
+        let new = b"Hello, Rust!";
+
+        unsafe {
+            let delta = xpatch_encode(0, base.as_ptr(), base.len(), new.as_ptr(), new.len(), true);
+            let handle = xpatch_buffer_into_handle(delta);
+            assert!(!handle.is_null());
+
+            let result = xpatch_decode(base.as_ptr(), base.len(), (*handle).data, (*handle).len);
+            assert!(result.error_message.is_null());
+            assert!(xpatch_buffer_release(handle));
+
+            let decoded = xpatch_buffer_into_handle(result.buffer);
+            assert_eq!(slice::from_raw_parts((*decoded).data, (*decoded).len), new);
+            assert!(xpatch_buffer_release(decoded));
+
+            assert!(xpatch_buffer_release(ptr::null_mut()));
+        }
+    }
+
+    #[test]
+    fn test_csharp_wrapper_matches_exports() {
+        let wrapper = include_str!("../bindings/XPatch.cs");
+        let source = include_str!("lib.rs");
+
+        let mut imports = 0;
+        for line in wrapper.lines() {
+            let Some(rest) = line.trim_start().strip_prefix("internal static extern ") else {
+                continue;
+            };
+            let name = rest
+                .split('(')
+                .next()
+                .and_then(|decl| decl.split_whitespace().last())
+                .unwrap();
+            assert!(
+                source.contains(&format!("extern \"C\" fn {}(", name)),
+                "bindings/XPatch.cs imports unknown function `{}`",
+                name
+            );
+            imports += 1;
+        }
+        assert!(imports > 0);
+        assert!(wrapper.contains(&format!("public const uint AbiVersion = {};", ABI_VERSION)));
+    }
 }
@@ -0,0 +1,81 @@
+package io.github.imgajeed76.xpatch;
+
+import java.nio.ByteBuffer;
+
+/**
+ * Java bindings for the xpatch delta compression library.
+ *
+ * <p>The {@code byte[]} methods copy their inputs into native memory. The
+ * {@code ByteBuffer} overloads read <em>direct</em> buffers in place (between
+ * position and limit), which avoids copying large base files on device.
+ *
+ * <pre>{@code
+ * byte[] delta = XPatch.encode(0, base, newData);
+ * byte[] restored = XPatch.decode(base, delta);
+ * }</pre>
+ */
+public final class XPatch {
+    static {
+        System.loadLibrary("xpatch_jni");
+    }
+
+    private XPatch() {}
+
+    /**
+     * Encode a delta patch between base and newData.
+     *
+     * @param tag metadata tag to embed in the delta (0-15 with no overhead)
+     * @param base the original data
+     * @param newData the new data
+     * @param enableZstd whether to enable zstd compression
+     * @return the encoded delta
+     */
+    public static native byte[] encode(long tag, byte[] base, byte[] newData, boolean enableZstd);
+
+    /** Encode with zstd compression enabled. */
+    public static byte[] encode(long tag, byte[] base, byte[] newData) {
+        return encode(tag, base, newData, true);
+    }
+
+    /**
+     * Encode a delta patch, reading direct buffers without copying.
+     *
+     * @throws IllegalArgumentException if a buffer is not direct
+     */
+    public static byte[] encode(long tag, ByteBuffer base, ByteBuffer newData, boolean enableZstd) {
+        return encodeDirect(tag, base, newData, enableZstd);
+    }
+
+    /**
+     * Decode a delta patch to reconstruct the new data.
+     *
+     * @param base the original data
+     * @param delta the delta patch
+     * @return the reconstructed data
+     * @throws XPatchException if the delta is invalid or corrupted
+     */
+    public static native byte[] decode(byte[] base, byte[] delta) throws XPatchException;
+
+    /**
+     * Decode a delta patch, reading direct buffers without copying.
+     *
+     * @throws XPatchException if the delta is invalid or corrupted
+     * @throws IllegalArgumentException if a buffer is not direct
+     */
+    public static byte[] decode(ByteBuffer base, ByteBuffer delta) throws XPatchException {
+        return decodeDirect(base, delta);
+    }
+
+    /**
+     * Extract the metadata tag from a delta patch.
+     *
+     * @throws XPatchException if the delta is invalid or corrupted
+     */
+    public static native long getTag(byte[] delta) throws XPatchException;
+
+    // Overloaded natives would need JNI long names, so the buffer variants
+    // get their own symbols.
+    private static native byte[] encodeDirect(long tag, ByteBuffer base, ByteBuffer newData, boolean enableZstd);
+
+    private static native byte[] decodeDirect(ByteBuffer base, ByteBuffer delta) throws XPatchException;
+}
@@ -0,0 +1,26 @@
+package io.github.imgajeed76.xpatch;
+
+/**
+ * Thrown when a delta cannot be decoded.
+ *
+ * <p>{@link #getCode()} distinguishes a malformed delta ({@code INVALID_DELTA})
+ * from one whose embedded checksum does not match ({@code CHECKSUM_MISMATCH}).
+ */
+public class XPatchException extends Exception {
+    /** The delta is malformed, truncated or does not match the base. */
+    public static final String INVALID_DELTA = "INVALID_DELTA";
+    /** An embedded checksum did not match the base or reconstructed data. */
+    public static final String CHECKSUM_MISMATCH = "CHECKSUM_MISMATCH";
+
+    private final String code;
+
+    public XPatchException(String code, String message) {
+        super(message);
+        this.code = code;
+    }
+
+    /** Returns the error code, one of the constants of this class. */
+    public String getCode() {
+        return code;
+    }
+}
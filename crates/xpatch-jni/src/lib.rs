@@ -0,0 +1,212 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! JNI bindings for xpatch, loaded from Java/Kotlin (including Android) as
+//! `libxpatch_jni`. The Java side lives in `java/io/github/imgajeed76/xpatch`.
+//!
+//! Decode errors are thrown as `XPatchException` with a code; invalid
+//! arguments as `IllegalArgumentException`.
+
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JByteBuffer, JClass};
+use jni::sys::{jboolean, jbyteArray, jlong};
+use std::ptr;
+use std::slice;
+
+const EXCEPTION_CLASS: &str = "io/github/imgajeed76/xpatch/XPatchException";
+
+/// Errors raised on the Rust side, thrown as Java exceptions.
+enum Error {
+    /// Decoding failed in the core library
+    Decode(&'static str),
+    /// An argument is out of range or of the wrong kind
+    InvalidArgument(&'static str),
+    /// A JNI call failed, usually with a Java exception already pending
+    Jni(jni::errors::Error),
+}
+
+impl From<jni::errors::Error> for Error {
+    fn from(e: jni::errors::Error) -> Self {
+        Error::Jni(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Encode a delta patch between two byte arrays.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_github_imgajeed76_xpatch_XPatch_encode<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    tag: jlong,
+    base: JByteArray<'local>,
+    new: JByteArray<'local>,
+    enable_zstd: jboolean,
+) -> jbyteArray {
+    let result = (|| {
+        let tag = to_tag(tag)?;
+        let base = env.convert_byte_array(&base)?;
+        let new = env.convert_byte_array(&new)?;
+        Ok(xpatch::delta::encode(tag, &base, &new, enable_zstd != 0))
+    })();
+    byte_array_or_throw(&mut env, result)
+}
+
+/// Encode a delta patch, reading two direct buffers in place.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_github_imgajeed76_xpatch_XPatch_encodeDirect<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    tag: jlong,
+    base: JByteBuffer<'local>,
+    new: JByteBuffer<'local>,
+    enable_zstd: jboolean,
+) -> jbyteArray {
+    let result = (|| {
+        let tag = to_tag(tag)?;
+        // SAFETY: the slices are only used for the duration of this call,
+        // while the Java side keeps the buffers reachable.
+        let base = unsafe { direct_slice(&mut env, &base)? };
+        let new = unsafe { direct_slice(&mut env, &new)? };
+        Ok(xpatch::delta::encode(tag, base, new, enable_zstd != 0))
+    })();
+    byte_array_or_throw(&mut env, result)
+}
+
+/// Decode a delta patch from two byte arrays.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_github_imgajeed76_xpatch_XPatch_decode<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JByteArray<'local>,
+    delta: JByteArray<'local>,
+) -> jbyteArray {
+    let result = (|| {
+        let base = env.convert_byte_array(&base)?;
+        let delta = env.convert_byte_array(&delta)?;
+        xpatch::delta::decode(&base, &delta).map_err(Error::Decode)
+    })();
+    byte_array_or_throw(&mut env, result)
+}
+
+/// Decode a delta patch, reading two direct buffers in place.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_github_imgajeed76_xpatch_XPatch_decodeDirect<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JByteBuffer<'local>,
+    delta: JByteBuffer<'local>,
+) -> jbyteArray {
+    let result = (|| {
+        // SAFETY: see encodeDirect
+        let base = unsafe { direct_slice(&mut env, &base)? };
+        let delta = unsafe { direct_slice(&mut env, &delta)? };
+        xpatch::delta::decode(base, delta).map_err(Error::Decode)
+    })();
+    byte_array_or_throw(&mut env, result)
+}
+
+/// Extract the metadata tag from a delta patch.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_github_imgajeed76_xpatch_XPatch_getTag<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    delta: JByteArray<'local>,
+) -> jlong {
+    let result = (|| {
+        let delta = env.convert_byte_array(&delta)?;
+        let tag = xpatch::delta::get_tag(&delta).map_err(Error::Decode)?;
+        jlong::try_from(tag).map_err(|_| Error::Decode("Tag does not fit in a long"))
+    })();
+    match result {
+        Ok(tag) => tag,
+        Err(e) => {
+            throw(&mut env, e);
+            0
+        }
+    }
+}
+
+// Helper functions
+
+fn to_tag(tag: jlong) -> Result<usize> {
+    usize::try_from(tag).map_err(|_| Error::InvalidArgument("tag must not be negative"))
+}
+
+/// Borrow the bytes between a direct buffer's position and limit.
+///
+/// # Safety
+/// The returned slice must not outlive the buffer, and the Java side must
+/// not write to the buffer while the slice is in use.
+unsafe fn direct_slice<'a>(env: &mut JNIEnv<'_>, buffer: &JByteBuffer<'_>) -> Result<&'a [u8]> {
+    if buffer.is_null() {
+        return Err(Error::InvalidArgument("buffer must not be null"));
+    }
+    let address = env
+        .get_direct_buffer_address(buffer)
+        .map_err(|_| Error::InvalidArgument("buffer must be a direct ByteBuffer"))?;
+    let position = env.call_method(buffer, "position", "()I", &[])?.i()? as usize;
+    let limit = env.call_method(buffer, "limit", "()I", &[])?.i()? as usize;
+    if position >= limit {
+        return Ok(&[]);
+    }
+    // SAFETY: position <= limit <= capacity, so the range lies inside the
+    // buffer's memory; the caller guarantees the lifetime.
+    Ok(unsafe { slice::from_raw_parts(address.add(position), limit - position) })
+}
+
+fn byte_array_or_throw(env: &mut JNIEnv<'_>, result: Result<Vec<u8>>) -> jbyteArray {
+    let result = result.and_then(|data| Ok(env.byte_array_from_slice(&data)?.into_raw()));
+    match result {
+        Ok(array) => array,
+        Err(e) => {
+            throw(env, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+fn throw(env: &mut JNIEnv<'_>, error: Error) {
+    // A failed JNI call usually leaves its own exception pending (e.g. a
+    // NullPointerException); keep it rather than masking it.
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    let _ = match error {
+        Error::Decode(msg) => throw_decode_error(env, msg),
+        Error::InvalidArgument(msg) => env.throw_new("java/lang/IllegalArgumentException", msg),
+        Error::Jni(e) => env.throw_new("java/lang/RuntimeException", e.to_string()),
+    };
+}
+
+fn throw_decode_error(env: &mut JNIEnv<'_>, msg: &str) -> jni::errors::Result<()> {
+    let code = match msg {
+        "Base data checksum mismatch" | "Output checksum mismatch" => "CHECKSUM_MISMATCH",
+        _ => "INVALID_DELTA",
+    };
+    let code = env.new_string(code)?;
+    let msg = env.new_string(msg)?;
+    let exception = env.new_object(
+        EXCEPTION_CLASS,
+        "(Ljava/lang/String;Ljava/lang/String;)V",
+        &[(&code).into(), (&msg).into()],
+    )?;
+    env.throw(jni::objects::JThrowable::from(exception))
+}
@@ -0,0 +1,71 @@
+import io.github.imgajeed76.xpatch.XPatch;
+import io.github.imgajeed76.xpatch.XPatchException;
+
+import java.nio.ByteBuffer;
+import java.nio.charset.StandardCharsets;
+import java.util.Arrays;
+
+/**
+ * Smoke tests for the JNI bindings.
+ *
+ * <pre>
+ * cargo build -p xpatch-jni
+ * javac -d build java/io/github/imgajeed76/xpatch/*.java test/XPatchTest.java
+ * java -Djava.library.path=../../target/debug -cp build XPatchTest
+ * </pre>
+ */
+public class XPatchTest {
+    private static int failures = 0;
+
+    public static void main(String[] args) throws Exception {
+        byte[] base = "Hello, World!".getBytes(StandardCharsets.UTF_8);
+        byte[] newData = "Hello, Rust World!".getBytes(StandardCharsets.UTF_8);
+
+        byte[] delta = XPatch.encode(42, base, newData);
+        check("roundtrip", Arrays.equals(XPatch.decode(base, delta), newData));
+        check("get_tag", XPatch.getTag(delta) == 42);
+
+        byte[] plain = XPatch.encode(0, base, newData, false);
+        check("without_zstd", Arrays.equals(XPatch.decode(base, plain), newData));
+
+        ByteBuffer directBase = ByteBuffer.allocateDirect(base.length + 4);
+        directBase.put(new byte[] {1, 2, 3, 4}).put(base).flip().position(4);
+        ByteBuffer directNew = ByteBuffer.allocateDirect(newData.length).put(newData).flip();
+        byte[] directDelta = XPatch.encode(7, directBase, directNew, true);
+        ByteBuffer deltaBuffer = ByteBuffer.allocateDirect(directDelta.length).put(directDelta).flip();
+        check("direct_roundtrip", Arrays.equals(XPatch.decode(directBase, deltaBuffer), newData));
+
+        try {
+            XPatch.decode(base, new byte[0]);
+            check("invalid_delta_throws", false);
+        } catch (XPatchException e) {
+            check("invalid_delta_throws", XPatchException.INVALID_DELTA.equals(e.getCode()));
+        }
+
+        try {
+            XPatch.encode(-1, base, newData);
+            check("negative_tag_throws", false);
+        } catch (IllegalArgumentException e) {
+            check("negative_tag_throws", true);
+        }
+
+        try {
+            XPatch.encode(0, ByteBuffer.wrap(base), directNew, true);
+            check("heap_buffer_throws", false);
+        } catch (IllegalArgumentException e) {
+            check("heap_buffer_throws", true);
+        }
+
+        if (failures > 0) {
+            System.exit(1);
+        }
+        System.out.println("All tests passed");
+    }
+
+    private static void check(String name, boolean ok) {
+        System.out.println((ok ? "PASS " : "FAIL ") + name);
+        if (!ok) {
+            failures++;
+        }
+    }
+}
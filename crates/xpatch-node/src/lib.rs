@@ -20,8 +20,44 @@
 // For commercial use in proprietary software, a commercial license is
 // available. Contact xpatch-commercial@alias.oseifert.ch for details.
 
+use memmap2::Mmap;
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{JsFunction, JsObject, JsUnknown, NapiValue};
 use napi_derive::napi;
+use std::fs::File;
+
+/// Error codes set as the `code` property of errors thrown by this module.
+///
+/// Keep in sync with `XPatchErrorCode` in xpatch.d.ts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The delta is malformed, truncated or does not match the base
+    InvalidDelta,
+    /// An embedded checksum did not match the base or reconstructed data
+    ChecksumMismatch,
+    /// An option is out of range
+    InvalidOption,
+    /// Reading or writing a file failed
+    IoError,
+    /// The operation was aborted through its `AbortSignal`
+    Cancelled,
+}
+
+impl AsRef<str> for ErrorCode {
+    fn as_ref(&self) -> &str {
+        match self {
+            ErrorCode::InvalidDelta => "INVALID_DELTA",
+            ErrorCode::ChecksumMismatch => "CHECKSUM_MISMATCH",
+            ErrorCode::InvalidOption => "INVALID_OPTION",
+            ErrorCode::IoError => "IO_ERROR",
+            ErrorCode::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+/// Result type whose errors carry an [`ErrorCode`].
+type XPatchResult<T> = Result<T, ErrorCode>;
 
 /// Encode a delta patch between base_data and new_data.
 ///
@@ -68,10 +104,10 @@ pub fn encode(
 /// console.log(decoded.equals(newData)); // true
 /// ```
 #[napi]
-pub fn decode(base_data: Buffer, delta: Buffer) -> Result<Buffer> {
+pub fn decode(base_data: Buffer, delta: Buffer) -> Result<Buffer, ErrorCode> {
     match xpatch::decode(&base_data, &delta) {
         Ok(result) => Ok(Buffer::from(result)),
-        Err(error) => Err(Error::from_reason(error)),
+        Err(error) => Err(core_error(error)),
     }
 }
 
@@ -91,9 +127,607 @@ pub fn decode(base_data: Buffer, delta: Buffer) -> Result<Buffer> {
 /// console.log(`Tag: ${tag}`); // Tag: 42
 /// ```
 #[napi]
-pub fn get_tag(delta: Buffer) -> Result<u32> {
+pub fn get_tag(delta: Buffer) -> Result<u32, ErrorCode> {
     match xpatch::get_tag(&delta) {
         Ok(tag) => Ok(tag as u32),
-        Err(error) => Err(Error::from_reason(error)),
+        Err(error) => Err(core_error(error)),
+    }
+}
+
+/// Options for [`encode_with_options`].
+#[napi(object)]
+pub struct EncodeOptions {
+    /// Whether to enable zstd compression (default: true)
+    pub enable_zstd: Option<bool>,
+    /// zstd compression level, 1-22 (default: 3)
+    pub compression_level: Option<i32>,
+    /// zstd worker threads; 0 compresses on the calling thread (default: 0)
+    pub threads: Option<u32>,
+    /// Embed CRC32 checksums of base and new data, verified on decode (default: false)
+    pub checksum: Option<bool>,
+}
+
+/// Header information returned by [`inspect`].
+#[napi(object)]
+pub struct DeltaInfo {
+    /// Algorithm used to encode the payload, e.g. "GDeltaZstd"
+    pub algorithm: String,
+    /// Embedded metadata tag
+    pub tag: i64,
+    /// Header size in bytes (including checksums)
+    pub header_size: i64,
+    /// Algorithm payload size in bytes
+    pub payload_size: i64,
+    /// Total delta size in bytes
+    pub total_size: i64,
+    /// CRC32 of the base data, if embedded
+    pub base_checksum: Option<u32>,
+    /// CRC32 of the reconstructed data, if embedded
+    pub output_checksum: Option<u32>,
+    /// Whether the delta is encrypted
+    pub encrypted: bool,
+    /// Whether the delta stores the new data whole instead of a diff
+    pub stored: bool,
+}
+
+/// Encode a delta patch with explicit encoding options.
+///
+/// @param tag - Metadata tag to embed in the delta (0-15 with no overhead)
+/// @param baseData - The original data as a Buffer
+/// @param newData - The new data as a Buffer
+/// @param options - Compression level, threads, checksums, ...
+/// @returns The encoded delta patch as a Buffer
+/// @throws {Error} If an option is out of range
+///
+/// @example
+/// ```javascript
+/// const xpatch = require('xpatch-rs');
+/// const delta = xpatch.encodeWithOptions(0, base, newData, {
+///   compressionLevel: 19,
+///   threads: 4,
+///   checksum: true,
+/// });
+/// ```
+#[napi]
+pub fn encode_with_options(
+    tag: u32,
+    base_data: Buffer,
+    new_data: Buffer,
+    options: Option<EncodeOptions>,
+) -> Result<Buffer, ErrorCode> {
+    let options = match options {
+        Some(options) => core_options(
+            options.enable_zstd,
+            options.compression_level,
+            options.threads,
+            options.checksum,
+        )?,
+        None => xpatch::EncodeOptions::default(),
+    };
+    let result = xpatch::encode_with_options(tag as usize, &base_data, &new_data, &options);
+    Ok(Buffer::from(result))
+}
+
+/// Read the header of a delta patch without decoding it.
+///
+/// @param delta - The delta patch as a Buffer
+/// @returns Algorithm, tag, size breakdown and embedded checksums
+/// @throws {Error} If the delta is invalid or corrupted
+///
+/// @example
+/// ```javascript
+/// const info = xpatch.inspect(delta);
+/// console.log(`${info.algorithm}: ${info.headerSize} + ${info.payloadSize} bytes`);
+/// ```
+#[napi]
+pub fn inspect(delta: Buffer) -> Result<DeltaInfo, ErrorCode> {
+    let info = xpatch::inspect(&delta).map_err(core_error)?;
+    Ok(DeltaInfo {
+        algorithm: format!("{:?}", info.algorithm),
+        tag: info.tag as i64,
+        header_size: info.header_size as i64,
+        payload_size: info.payload_size as i64,
+        total_size: info.total_size as i64,
+        base_checksum: info.base_checksum,
+        output_checksum: info.output_checksum,
+        encrypted: info.encrypted,
+        stored: info.stored,
+    })
+}
+
+/// Check that a delta applies cleanly to baseData.
+///
+/// Decodes the delta and compares any embedded checksums against the base and the
+/// reconstructed data. Deltas encoded without `checksum` can only be checked for
+/// structural validity.
+///
+/// @param baseData - The original data as a Buffer
+/// @param delta - The delta patch as a Buffer
+/// @returns `true` if the delta applies and all checksums match
+///
+/// @example
+/// ```javascript
+/// if (!xpatch.verify(base, delta)) {
+///   throw new Error('Patch does not match this release');
+/// }
+/// ```
+#[napi]
+pub fn verify(base_data: Buffer, delta: Buffer) -> bool {
+    xpatch::verify(&base_data, &delta).is_ok()
+}
+
+/// Background task backing [`encode_async`].
+pub struct EncodeTask {
+    tag: u32,
+    base_data: Buffer,
+    new_data: Buffer,
+    enable_zstd: bool,
+    token: xpatch::delta::CancellationToken,
+}
+
+impl Task for EncodeTask {
+    type Output = XPatchResult<Vec<u8>>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let options = xpatch::EncodeOptions {
+            enable_zstd: self.enable_zstd,
+            ..Default::default()
+        };
+        Ok(xpatch::delta::encode_cancellable(
+            self.tag as usize,
+            &self.base_data,
+            &self.new_data,
+            &options,
+            &self.token,
+        )
+        .map_err(core_error))
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        output
+            .map(Buffer::from)
+            .map_err(|error| to_js_error(env, error))
+    }
+}
+
+/// Background task backing [`decode_async`].
+pub struct DecodeTask {
+    base_data: Buffer,
+    delta: Buffer,
+    token: xpatch::delta::CancellationToken,
+}
+
+impl Task for DecodeTask {
+    type Output = XPatchResult<Vec<u8>>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(
+            xpatch::delta::decode_cancellable(&self.base_data, &self.delta, &self.token)
+                .map_err(core_error),
+        )
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        output
+            .map(Buffer::from)
+            .map_err(|error| to_js_error(env, error))
     }
 }
+
+/// Encode a delta patch on the libuv thread pool without blocking the event loop.
+///
+/// Same as `encode`, but returns a Promise. Prefer this for large inputs; for small
+/// inputs the synchronous version avoids the scheduling overhead.
+///
+/// @param tag - Metadata tag to embed in the delta (0-15 with no overhead)
+/// @param baseData - The original data as a Buffer
+/// @param newData - The new data as a Buffer
+/// @param enableZstd - Whether to enable zstd compression (default: true)
+/// @param signal - Aborts the encode; the Promise then rejects with code `CANCELLED`
+/// @returns A Promise resolving to the encoded delta patch
+///
+/// @example
+/// ```javascript
+/// const xpatch = require('xpatch-rs');
+/// const controller = new AbortController();
+/// const delta = await xpatch.encodeAsync(0, base, newData, true, controller.signal);
+/// ```
+#[napi]
+pub fn encode_async(
+    env: Env,
+    tag: u32,
+    base_data: Buffer,
+    new_data: Buffer,
+    enable_zstd: Option<bool>,
+    #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+) -> Result<AsyncTask<EncodeTask>> {
+    Ok(AsyncTask::new(EncodeTask {
+        tag,
+        base_data,
+        new_data,
+        enable_zstd: enable_zstd.unwrap_or(true),
+        token: cancellation_token(env, signal)?,
+    }))
+}
+
+/// Decode a delta patch on the libuv thread pool without blocking the event loop.
+///
+/// Same as `decode`, but returns a Promise.
+///
+/// @param baseData - The original data as a Buffer
+/// @param delta - The delta patch as a Buffer
+/// @param signal - Aborts the decode; the Promise then rejects with code `CANCELLED`
+/// @returns A Promise resolving to the reconstructed new data
+/// @throws {Error} The Promise rejects if the delta is invalid or corrupted
+///
+/// @example
+/// ```javascript
+/// const xpatch = require('xpatch-rs');
+/// const decoded = await xpatch.decodeAsync(base, delta, AbortSignal.timeout(5000));
+/// ```
+#[napi]
+pub fn decode_async(
+    env: Env,
+    base_data: Buffer,
+    delta: Buffer,
+    #[napi(ts_arg_type = "AbortSignal")] signal: Option<JsObject>,
+) -> Result<AsyncTask<DecodeTask>> {
+    Ok(AsyncTask::new(DecodeTask {
+        base_data,
+        delta,
+        token: cancellation_token(env, signal)?,
+    }))
+}
+
+/// Options for [`encode_file`].
+#[napi(object)]
+pub struct EncodeFileOptions {
+    /// Metadata tag to embed in the delta (default: 0)
+    pub tag: Option<u32>,
+    /// Whether to enable zstd compression (default: true)
+    pub enable_zstd: Option<bool>,
+    /// zstd compression level, 1-22 (default: 3)
+    pub compression_level: Option<i32>,
+    /// zstd worker threads; 0 compresses on the calling thread (default: 0)
+    pub threads: Option<u32>,
+    /// Embed CRC32 checksums of base and new data, verified on decode (default: false)
+    pub checksum: Option<bool>,
+    /// Called with `(done, total)` bytes of new data while encoding
+    #[napi(ts_type = "(done: number, total: number) => void")]
+    pub on_progress: Option<JsFunction>,
+    /// Minimum number of bytes between two progress reports (default: 1 MiB)
+    pub progress_interval: Option<i64>,
+}
+
+/// Options for [`apply_file`].
+#[napi(object)]
+pub struct ApplyFileOptions {
+    /// Called with `(done, total)` bytes of reconstructed data while decoding
+    #[napi(ts_type = "(done: number, total: number) => void")]
+    pub on_progress: Option<JsFunction>,
+    /// Minimum number of bytes between two progress reports (default: 1 MiB)
+    pub progress_interval: Option<i64>,
+}
+
+/// Background task backing [`encode_file`].
+pub struct EncodeFileTask {
+    base_path: String,
+    new_path: String,
+    delta_path: String,
+    tag: u32,
+    options: xpatch::EncodeOptions,
+}
+
+impl EncodeFileTask {
+    fn run(&self) -> XPatchResult<usize> {
+        let base = map_file(&self.base_path)?;
+        let new = map_file(&self.new_path)?;
+        let delta = xpatch::encode_with_options(
+            self.tag as usize,
+            base.as_deref().unwrap_or_default(),
+            new.as_deref().unwrap_or_default(),
+            &self.options,
+        );
+        write_file(&self.delta_path, &delta)?;
+        Ok(delta.len())
+    }
+}
+
+impl Task for EncodeFileTask {
+    type Output = XPatchResult<usize>;
+    type JsValue = i64;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(self.run())
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        output
+            .map(|size| size as i64)
+            .map_err(|error| to_js_error(env, error))
+    }
+}
+
+/// Background task backing [`apply_file`].
+pub struct ApplyFileTask {
+    base_path: String,
+    delta_path: String,
+    output_path: String,
+    options: xpatch::EncodeOptions,
+}
+
+impl ApplyFileTask {
+    fn run(&self) -> XPatchResult<usize> {
+        let base = map_file(&self.base_path)?;
+        let delta = map_file(&self.delta_path)?;
+        let decoded = xpatch::delta::decode_with_options(
+            base.as_deref().unwrap_or_default(),
+            delta.as_deref().unwrap_or_default(),
+            &self.options,
+        )
+        .map_err(core_error)?;
+        write_file(&self.output_path, &decoded)?;
+        Ok(decoded.len())
+    }
+}
+
+impl Task for ApplyFileTask {
+    type Output = XPatchResult<usize>;
+    type JsValue = i64;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(self.run())
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        output
+            .map(|size| size as i64)
+            .map_err(|error| to_js_error(env, error))
+    }
+}
+
+/// Encode a delta between two files and write it to `deltaPath`.
+///
+/// Files are memory-mapped and processed on the libuv thread pool, so the data never
+/// passes through JS Buffers. The input files must not be modified while the task runs.
+///
+/// @param basePath - Path to the original file
+/// @param newPath - Path to the new file
+/// @param deltaPath - Path the delta is written to (overwritten if it exists)
+/// @param opts - Optional `{ tag, enableZstd, compressionLevel, threads, checksum, onProgress,
+/// progressInterval }`
+/// @returns A Promise resolving to the delta size in bytes
+/// @throws {Error} If an option is out of range
+///
+/// @example
+/// ```javascript
+/// const size = await xpatch.encodeFile('v1.bin', 'v2.bin', 'v1-v2.xpatch', { tag: 2 });
+/// ```
+#[napi]
+pub fn encode_file(
+    base_path: String,
+    new_path: String,
+    delta_path: String,
+    opts: Option<EncodeFileOptions>,
+) -> Result<AsyncTask<EncodeFileTask>, ErrorCode> {
+    let (tag, options) = match opts {
+        Some(opts) => (
+            opts.tag.unwrap_or(0),
+            with_progress(
+                core_options(
+                    opts.enable_zstd,
+                    opts.compression_level,
+                    opts.threads,
+                    opts.checksum,
+                )?,
+                opts.on_progress,
+                opts.progress_interval,
+            )?,
+        ),
+        None => (0, xpatch::EncodeOptions::default()),
+    };
+
+    Ok(AsyncTask::new(EncodeFileTask {
+        base_path,
+        new_path,
+        delta_path,
+        tag,
+        options,
+    }))
+}
+
+/// Apply a delta file to a base file and write the result to `outputPath`.
+///
+/// Files are memory-mapped and processed on the libuv thread pool. The input files must
+/// not be modified while the task runs.
+///
+/// @param basePath - Path to the original file
+/// @param deltaPath - Path to the delta created by `encode` or `encodeFile`
+/// @param outputPath - Path the reconstructed file is written to (overwritten if it exists)
+/// @param opts - Optional `{ onProgress, progressInterval }`
+/// @returns A Promise resolving to the reconstructed size in bytes
+/// @throws {Error} The Promise rejects on IO errors or if the delta is invalid
+///
+/// @example
+/// ```javascript
+/// await xpatch.applyFile('v1.bin', 'v1-v2.xpatch', 'v2.bin', {
+///   onProgress: (done, total) => console.log(`${done}/${total}`),
+/// });
+/// ```
+#[napi]
+pub fn apply_file(
+    base_path: String,
+    delta_path: String,
+    output_path: String,
+    opts: Option<ApplyFileOptions>,
+) -> Result<AsyncTask<ApplyFileTask>, ErrorCode> {
+    let options = match opts {
+        Some(opts) => with_progress(
+            xpatch::EncodeOptions::default(),
+            opts.on_progress,
+            opts.progress_interval,
+        )?,
+        None => xpatch::EncodeOptions::default(),
+    };
+
+    Ok(AsyncTask::new(ApplyFileTask {
+        base_path,
+        delta_path,
+        output_path,
+        options,
+    }))
+}
+
+/// Builds core encoding options from optional JS fields, keeping defaults for missing ones.
+fn core_options(
+    enable_zstd: Option<bool>,
+    compression_level: Option<i32>,
+    threads: Option<u32>,
+    checksum: Option<bool>,
+) -> XPatchResult<xpatch::EncodeOptions> {
+    let defaults = xpatch::EncodeOptions::default();
+    let zstd_level = compression_level.unwrap_or(defaults.zstd_level);
+    if !(1..=22).contains(&zstd_level) {
+        return Err(Error::new(
+            ErrorCode::InvalidOption,
+            "compressionLevel must be between 1 and 22",
+        ));
+    }
+
+    Ok(xpatch::EncodeOptions {
+        enable_zstd: enable_zstd.unwrap_or(defaults.enable_zstd),
+        zstd_level,
+        zstd_threads: threads.unwrap_or(defaults.zstd_threads),
+        checksum: checksum.unwrap_or(defaults.checksum),
+        ..defaults
+    })
+}
+
+/// Forwards core progress reports to a JS callback.
+///
+/// The task runs on the libuv thread pool, so reports are queued to the event loop and
+/// may still arrive shortly after the Promise settles.
+fn with_progress(
+    options: xpatch::EncodeOptions,
+    on_progress: Option<JsFunction>,
+    progress_interval: Option<i64>,
+) -> XPatchResult<xpatch::EncodeOptions> {
+    let progress_interval = match progress_interval {
+        Some(interval) => u64::try_from(interval).map_err(|_| {
+            Error::new(
+                ErrorCode::InvalidOption,
+                "progressInterval must not be negative",
+            )
+        })?,
+        None => options.progress_interval,
+    };
+    let Some(on_progress) = on_progress else {
+        return Ok(xpatch::EncodeOptions {
+            progress_interval,
+            ..options
+        });
+    };
+
+    let callback: ThreadsafeFunction<(u64, u64), ErrorStrategy::Fatal> = on_progress
+        .create_threadsafe_function(0, |ctx| {
+            let (done, total): (u64, u64) = ctx.value;
+            Ok(vec![done as f64, total as f64])
+        })
+        .map_err(|error| Error::new(ErrorCode::InvalidOption, error.reason))?;
+    Ok(xpatch::EncodeOptions {
+        progress_interval,
+        ..options.progress(move |done, total| {
+            callback.call((done, total), ThreadsafeFunctionCallMode::NonBlocking);
+        })
+    })
+}
+
+/// Maps an error from the core library to its error code.
+fn core_error(message: &'static str) -> Error<ErrorCode> {
+    let code = match message {
+        "Base data checksum mismatch" | "Output checksum mismatch" => ErrorCode::ChecksumMismatch,
+        xpatch::delta::CANCELLED => ErrorCode::Cancelled,
+        _ => ErrorCode::InvalidDelta,
+    };
+    Error::new(code, message)
+}
+
+fn io_error(action: &str, path: &str, error: std::io::Error) -> Error<ErrorCode> {
+    Error::new(
+        ErrorCode::IoError,
+        format!("Failed to {} {}: {}", action, path, error),
+    )
+}
+
+/// Creates a token that is cancelled when `signal` aborts.
+///
+/// The work runs on the libuv thread pool, so the token is only checked between the
+/// phases of an encode or decode; an abort during a phase takes effect when it ends.
+fn cancellation_token(
+    env: Env,
+    signal: Option<JsObject>,
+) -> Result<xpatch::delta::CancellationToken> {
+    let token = xpatch::delta::CancellationToken::new();
+    let Some(signal) = signal else {
+        return Ok(token);
+    };
+
+    if signal.get_named_property::<bool>("aborted")? {
+        token.cancel();
+        return Ok(token);
+    }
+
+    let listener_token = token.clone();
+    let listener = env.create_function_from_closure("onabort", move |ctx| {
+        listener_token.cancel();
+        ctx.env.get_undefined()
+    })?;
+    let options = {
+        let mut options = env.create_object()?;
+        options.set_named_property("once", true)?;
+        options
+    };
+    let add_event_listener: JsFunction = signal.get_named_property("addEventListener")?;
+    add_event_listener.call(
+        Some(&signal),
+        &[
+            env.create_string("abort")?.into_unknown(),
+            listener.into_unknown(),
+            options.into_unknown(),
+        ],
+    )?;
+    Ok(token)
+}
+
+/// Converts a coded error into a JS error object, so Promise rejections keep the code.
+fn to_js_error(env: Env, error: Error<ErrorCode>) -> Error {
+    // Safety: `env` is the live environment passed to `Task::resolve`, and `value` is the
+    // error object just created in it.
+    unsafe {
+        let value = JsError::from(error).into_value(env.raw());
+        Error::from(JsUnknown::from_raw_unchecked(env.raw(), value))
+    }
+}
+
+/// Memory-maps a file for reading. Returns `None` for empty files, which cannot be mapped.
+fn map_file(path: &str) -> XPatchResult<Option<Mmap>> {
+    let file = File::open(path).map_err(|e| io_error("open", path, e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| io_error("stat", path, e))?
+        .len();
+    if len == 0 {
+        return Ok(None);
+    }
+
+    // Safety: the mapping is read-only and callers are told not to modify inputs while
+    // a task runs.
+    let map = unsafe { Mmap::map(&file) }.map_err(|e| io_error("map", path, e))?;
+    Ok(Some(map))
+}
+
+fn write_file(path: &str, data: &[u8]) -> XPatchResult<()> {
+    std::fs::write(path, data).map_err(|e| io_error("write", path, e))
+}
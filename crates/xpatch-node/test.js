@@ -3,7 +3,10 @@
  * Unit tests for xpatch-rs Node.js bindings (JavaScript)
  */
 
-const xpatch = require('./index.js');
+const fs = require('fs');
+const os = require('os');
+const path = require('path');
+const xpatch = require('./xpatch.js');
 
 function test_encode_decode() {
     const base = Buffer.from('Hello, World!');
@@ -111,21 +114,254 @@ function test_buffer_types() {
     console.log('✓ test_buffer_types passed');
 }
 
+function test_encode_with_options() {
+    const base = Buffer.from('The quick brown fox jumps over the lazy dog');
+    const newData = Buffer.from('The quick red fox jumped over the sleeping dog');
+
+    const delta = xpatch.encodeWithOptions(3, base, newData, {
+        compressionLevel: 19,
+        threads: 2,
+        checksum: true,
+    });
+    if (!xpatch.decode(base, delta).equals(newData)) {
+        throw new Error('encodeWithOptions roundtrip failed');
+    }
+
+    let thrown = null;
+    try {
+        xpatch.encodeWithOptions(0, base, newData, { compressionLevel: 99 });
+    } catch (error) {
+        thrown = error;
+    }
+    if (!(thrown instanceof xpatch.XPatchError) || thrown.code !== 'INVALID_OPTION') {
+        throw new Error(`encodeWithOptions should throw INVALID_OPTION, got ${thrown}`);
+    }
+    console.log('✓ test_encode_with_options passed');
+}
+
+function test_inspect_verify() {
+    const base = Buffer.from('hello world');
+    const newData = Buffer.from('hello brave new world');
+
+    const delta = xpatch.encodeWithOptions(42, base, newData, { checksum: true });
+    const info = xpatch.inspect(delta);
+    if (info.tag !== 42 || info.totalSize !== delta.length ||
+        info.headerSize + info.payloadSize !== info.totalSize ||
+        typeof info.algorithm !== 'string' || typeof info.outputChecksum !== 'number') {
+        throw new Error(`Unexpected inspect result: ${JSON.stringify(info)}`);
+    }
+
+    if (xpatch.inspect(xpatch.encode(0, base, newData)).baseChecksum !== undefined) {
+        throw new Error('Plain deltas should not report checksums');
+    }
+
+    if (!xpatch.verify(base, delta)) {
+        throw new Error('verify should accept the matching base');
+    }
+    if (xpatch.verify(Buffer.from('hello WORLD'), delta)) {
+        throw new Error('verify should reject a different base');
+    }
+    console.log('✓ test_inspect_verify passed');
+}
+
+function test_error_codes() {
+    const base = Buffer.from('hello world');
+    const delta = xpatch.encodeWithOptions(0, base, Buffer.from('hello brave world'), { checksum: true });
+
+    const cases = [
+        ['INVALID_DELTA', () => xpatch.decode(base, Buffer.alloc(0))],
+        ['INVALID_DELTA', () => xpatch.getTag(Buffer.alloc(0))],
+        ['INVALID_DELTA', () => xpatch.inspect(Buffer.alloc(0))],
+        ['CHECKSUM_MISMATCH', () => xpatch.decode(Buffer.from('hello WORLD'), delta)],
+    ];
+
+    for (const [code, fn] of cases) {
+        let thrown = null;
+        try {
+            fn();
+        } catch (error) {
+            thrown = error;
+        }
+        if (!(thrown instanceof xpatch.XPatchError) || !(thrown instanceof Error) || thrown.code !== code) {
+            throw new Error(`Expected XPatchError with code ${code}, got ${thrown}`);
+        }
+    }
+    console.log('✓ test_error_codes passed');
+}
+
+async function test_encode_decode_async() {
+    const base = Buffer.alloc(100000, 'a');
+    const newData = Buffer.concat([base, Buffer.from('appended')]);
+
+    const delta = await xpatch.encodeAsync(7, base, newData);
+    const reconstructed = await xpatch.decodeAsync(base, delta);
+
+    if (!reconstructed.equals(newData)) {
+        throw new Error('Async roundtrip failed');
+    }
+    if (!delta.equals(xpatch.encode(7, base, newData))) {
+        throw new Error('Async and sync encoding differ');
+    }
+    console.log('✓ test_encode_decode_async passed');
+}
+
+async function test_decode_async_rejects() {
+    let rejection = null;
+    try {
+        await xpatch.decodeAsync(Buffer.from('base'), Buffer.from([0xFF, 0xFF, 0xFF]));
+    } catch (error) {
+        rejection = error;
+    }
+
+    if (!(rejection instanceof xpatch.XPatchError) || rejection.code !== 'INVALID_DELTA') {
+        throw new Error(`decodeAsync should reject with INVALID_DELTA, got ${rejection}`);
+    }
+    console.log('✓ test_decode_async_rejects passed');
+}
+
+async function test_async_abort_signal() {
+    const base = Buffer.alloc(100000, 'a');
+    const newData = Buffer.concat([base, Buffer.from('appended')]);
+
+    const controller = new AbortController();
+    const delta = await xpatch.encodeAsync(0, base, newData, true, controller.signal);
+    if (!(await xpatch.decodeAsync(base, delta, controller.signal)).equals(newData)) {
+        throw new Error('Roundtrip with an unused signal failed');
+    }
+
+    controller.abort();
+    for (const operation of [
+        () => xpatch.encodeAsync(0, base, newData, true, controller.signal),
+        () => xpatch.decodeAsync(base, delta, controller.signal),
+    ]) {
+        let rejection = null;
+        try {
+            await operation();
+        } catch (error) {
+            rejection = error;
+        }
+        if (!(rejection instanceof xpatch.XPatchError) || rejection.code !== 'CANCELLED') {
+            throw new Error(`Aborted operation should reject with CANCELLED, got ${rejection}`);
+        }
+    }
+    console.log('✓ test_async_abort_signal passed');
+}
+
+async function test_encode_apply_file() {
+    const dir = fs.mkdtempSync(path.join(os.tmpdir(), 'xpatch-'));
+    const basePath = path.join(dir, 'base.bin');
+    const newPath = path.join(dir, 'new.bin');
+    const deltaPath = path.join(dir, 'delta.xpatch');
+    const outputPath = path.join(dir, 'output.bin');
+
+    try {
+        const base = Buffer.alloc(200000, 'base ');
+        const newData = Buffer.concat([base.subarray(0, 100000), Buffer.from('changed'), base.subarray(100000)]);
+        fs.writeFileSync(basePath, base);
+        fs.writeFileSync(newPath, newData);
+
+        const deltaSize = await xpatch.encodeFile(basePath, newPath, deltaPath, { tag: 5, checksum: true });
+        if (deltaSize !== fs.statSync(deltaPath).size) {
+            throw new Error(`encodeFile returned ${deltaSize}, file has ${fs.statSync(deltaPath).size} bytes`);
+        }
+        if (xpatch.getTag(fs.readFileSync(deltaPath)) !== 5) {
+            throw new Error('encodeFile did not apply the tag');
+        }
+
+        const outputSize = await xpatch.applyFile(basePath, deltaPath, outputPath);
+        if (outputSize !== newData.length || !fs.readFileSync(outputPath).equals(newData)) {
+            throw new Error('applyFile output differs from the new file');
+        }
+
+        // Empty files cannot be memory-mapped and are handled separately
+        fs.writeFileSync(basePath, '');
+        await xpatch.encodeFile(basePath, newPath, deltaPath);
+        await xpatch.applyFile(basePath, deltaPath, outputPath);
+        if (!fs.readFileSync(outputPath).equals(newData)) {
+            throw new Error('applyFile failed with an empty base');
+        }
+
+        let rejection = null;
+        try {
+            await xpatch.applyFile(path.join(dir, 'missing.bin'), deltaPath, outputPath);
+        } catch (error) {
+            rejection = error;
+        }
+        if (!(rejection instanceof xpatch.XPatchError) || rejection.code !== 'IO_ERROR') {
+            throw new Error(`applyFile should reject missing files with IO_ERROR, got ${rejection}`);
+        }
+    } finally {
+        fs.rmSync(dir, { recursive: true, force: true });
+    }
+    console.log('✓ test_encode_apply_file passed');
+}
+
+async function test_file_progress() {
+    const dir = fs.mkdtempSync(path.join(os.tmpdir(), 'xpatch-'));
+    const basePath = path.join(dir, 'base.bin');
+    const newPath = path.join(dir, 'new.bin');
+    const deltaPath = path.join(dir, 'delta.xpatch');
+    const outputPath = path.join(dir, 'output.bin');
+
+    try {
+        const base = Buffer.alloc(1000000);
+        for (let i = 0; i < base.length; i++) {
+            base[i] = (i * 7919) % 251;
+        }
+        const newData = Buffer.from(base);
+        for (let i = 0; i < newData.length; i += 5000) {
+            newData[i] ^= 0xFF;
+        }
+        fs.writeFileSync(basePath, base);
+        fs.writeFileSync(newPath, newData);
+
+        const reports = [];
+        const onProgress = (done, total) => reports.push([done, total]);
+        await xpatch.encodeFile(basePath, newPath, deltaPath, { onProgress, progressInterval: 65536 });
+        await xpatch.applyFile(basePath, deltaPath, outputPath, { onProgress, progressInterval: 65536 });
+        // Reports are queued to the event loop and may trail the Promise
+        await new Promise((resolve) => setImmediate(resolve));
+
+        const finals = reports.filter(([done, total]) => done === total && total === newData.length);
+        if (finals.length !== 2 || reports.length < 4) {
+            throw new Error(`Unexpected progress reports: ${JSON.stringify(reports)}`);
+        }
+        if (!fs.readFileSync(outputPath).equals(newData)) {
+            throw new Error('applyFile with progress produced wrong output');
+        }
+    } finally {
+        fs.rmSync(dir, { recursive: true, force: true });
+    }
+    console.log('✓ test_file_progress passed');
+}
+
 // Run all tests
-console.log('Running xpatch-rs Node.js binding tests (JavaScript)...\n');
-
-try {
-    test_encode_decode();
-    test_get_tag();
-    test_compression();
-    test_empty_data();
-    test_large_tag();
-    test_identical_data();
-    test_zstd_disabled();
-    test_buffer_types();
-
-    console.log('\n✅ All JavaScript tests passed!');
-} catch (error) {
-    console.error('\n❌ Test failed:', error.message);
-    process.exit(1);
+async function main() {
+    console.log('Running xpatch-rs Node.js binding tests (JavaScript)...\n');
+
+    try {
+        test_encode_decode();
+        test_get_tag();
+        test_compression();
+        test_empty_data();
+        test_large_tag();
+        test_identical_data();
+        test_zstd_disabled();
+        test_buffer_types();
+        test_encode_with_options();
+        test_inspect_verify();
+        test_error_codes();
+        await test_encode_decode_async();
+        await test_decode_async_rejects();
+        await test_async_abort_signal();
+        await test_encode_apply_file();
+        await test_file_progress();
+
+        console.log('\n✅ All JavaScript tests passed!');
+    } catch (error) {
+        console.error('\n❌ Test failed:', error.message);
+        process.exit(1);
+    }
 }
+
+main();
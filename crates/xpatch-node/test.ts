@@ -3,7 +3,8 @@
  * Unit tests for xpatch-rs Node.js bindings (TypeScript)
  */
 
-import { encode, decode, getTag } from './index.js';
+import { encode, decode, getTag, encodeAsync, decodeAsync, XPatchError } from './xpatch.js';
+import type { XPatchErrorCode } from './xpatch.js';
 
 function test_encode_decode(): void {
     const base = Buffer.from('Hello, World!');
@@ -120,6 +121,35 @@ function test_type_safety(): void {
     console.log('✓ test_type_safety passed');
 }
 
+async function test_encode_decode_async(): Promise<void> {
+    const base = Buffer.alloc(100000, 'a');
+    const newData = Buffer.concat([base, Buffer.from('appended')]);
+
+    const delta: Buffer = await encodeAsync(7, base, newData);
+    const reconstructed: Buffer = await decodeAsync(base, delta);
+
+    if (!reconstructed.equals(newData)) {
+        throw new Error('Async roundtrip failed');
+    }
+    console.log('✓ test_encode_decode_async passed');
+}
+
+function test_error_code(): void {
+    let code: XPatchErrorCode | undefined;
+    try {
+        decode(Buffer.from('base'), Buffer.alloc(0));
+    } catch (error) {
+        if (error instanceof XPatchError) {
+            code = error.code;
+        }
+    }
+
+    if (code !== 'INVALID_DELTA') {
+        throw new Error(`Expected INVALID_DELTA, got ${code}`);
+    }
+    console.log('✓ test_error_code passed');
+}
+
 // Run all tests
 console.log('Running xpatch-rs Node.js binding tests (TypeScript)...\n');
 
@@ -132,6 +162,8 @@ try {
     test_identical_data();
     test_zstd_disabled();
     test_type_safety();
+    test_error_code();
+    await test_encode_decode_async();
 
     console.log('\n✅ All TypeScript tests passed!');
 } catch (error) {
@@ -0,0 +1,43 @@
+/**
+ * Type declarations for the xpatch-rs entry point.
+ *
+ * Function declarations are generated from the Rust sources by napi (index.d.ts);
+ * this file adds the error types thrown by them.
+ */
+
+export * from './index';
+
+/**
+ * Machine-readable reason for an {@link XPatchError}.
+ *
+ * - `INVALID_DELTA`: the delta is malformed, truncated or does not match the base data
+ * - `CHECKSUM_MISMATCH`: an embedded checksum did not match the base or reconstructed data
+ * - `INVALID_OPTION`: an encoding option is out of range
+ * - `IO_ERROR`: reading or writing a file failed (`encodeFile` / `applyFile`)
+ * - `CANCELLED`: an async operation was aborted through its `AbortSignal`
+ */
+export type XPatchErrorCode =
+    | 'INVALID_DELTA'
+    | 'CHECKSUM_MISMATCH'
+    | 'INVALID_OPTION'
+    | 'IO_ERROR'
+    | 'CANCELLED';
+
+/**
+ * Error thrown (or used to reject Promises) by xpatch functions.
+ *
+ * @example
+ * ```typescript
+ * try {
+ *     decode(base, delta);
+ * } catch (error) {
+ *     if (error instanceof XPatchError && error.code === 'CHECKSUM_MISMATCH') {
+ *         // wrong base version
+ *     }
+ * }
+ * ```
+ */
+export declare class XPatchError extends Error {
+    readonly name: 'XPatchError';
+    readonly code: XPatchErrorCode;
+}
@@ -0,0 +1,56 @@
+/**
+ * Public entry point for xpatch-rs.
+ *
+ * Re-exports the native bindings (index.js, generated by napi) and rethrows their
+ * errors as XPatchError instances carrying a typed `code`.
+ */
+
+const native = require('./index.js');
+
+const ERROR_CODES = new Set([
+    'INVALID_DELTA',
+    'CHECKSUM_MISMATCH',
+    'INVALID_OPTION',
+    'IO_ERROR',
+    'CANCELLED',
+]);
+
+class XPatchError extends Error {
+    constructor(code, message) {
+        super(message);
+        this.name = 'XPatchError';
+        this.code = code;
+    }
+}
+
+function translate(error) {
+    if (error instanceof Error && !(error instanceof XPatchError) && ERROR_CODES.has(error.code)) {
+        const translated = new XPatchError(error.code, error.message);
+        translated.stack = error.stack;
+        return translated;
+    }
+    return error;
+}
+
+function wrap(fn) {
+    return function (...args) {
+        let result;
+        try {
+            result = fn.apply(this, args);
+        } catch (error) {
+            throw translate(error);
+        }
+        if (result instanceof Promise) {
+            return result.catch((error) => {
+                throw translate(error);
+            });
+        }
+        return result;
+    };
+}
+
+for (const [name, value] of Object.entries(native)) {
+    module.exports[name] = typeof value === 'function' ? wrap(value) : value;
+}
+
+module.exports.XPatchError = XPatchError;
@@ -18,10 +18,11 @@
 // For commercial use in proprietary software, a commercial license is
 // available. Contact xpatch-commercial@alias.oseifert.ch for details.
 
-use ::xpatch::delta;
+use ::xpatch::delta::{self, DEFAULT_PROGRESS_INTERVAL, EncodeOptions};
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
+use std::sync::{Arc, Mutex};
 
 /// Encode a delta patch between base_data and new_data.
 ///
@@ -30,6 +31,8 @@ use pyo3::types::PyBytes;
 ///     base_data: The original data as bytes
 ///     new_data: The new data as bytes
 ///     enable_zstd: Whether to enable zstd compression (default: True)
+///     progress: Called as progress(done, total) with bytes of new_data encoded (optional)
+///     progress_interval: Minimum bytes between two progress calls (default: 1 MiB)
 ///
 /// Returns:
 ///     bytes: The encoded delta patch
@@ -42,15 +45,23 @@ use pyo3::types::PyBytes;
 ///     >>> len(delta)
 ///     8
 #[pyfunction]
-#[pyo3(signature = (tag, base_data, new_data, enable_zstd=true))]
+#[pyo3(signature = (tag, base_data, new_data, enable_zstd=true, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL))]
 fn encode<'py>(
     py: Python<'py>,
     tag: usize,
     base_data: &[u8],
     new_data: &[u8],
     enable_zstd: bool,
+    progress: Option<Py<PyAny>>,
+    progress_interval: u64,
 ) -> PyResult<Bound<'py, PyBytes>> {
-    let result = delta::encode(tag, base_data, new_data, enable_zstd);
+    let (options, error) = progress_options(progress, progress_interval);
+    let options = EncodeOptions {
+        enable_zstd,
+        ..options
+    };
+    let result = delta::encode_with_options(tag, base_data, new_data, &options);
+    raise_progress_error(error)?;
     Ok(PyBytes::new(py, &result[..]))
 }
 
@@ -59,6 +70,8 @@ fn encode<'py>(
 /// Args:
 ///     base_data: The original data as bytes
 ///     delta: The delta patch as bytes
+///     progress: Called as progress(done, total) with bytes reconstructed (optional)
+///     progress_interval: Minimum bytes between two progress calls (default: 1 MiB)
 ///
 /// Returns:
 ///     bytes: The reconstructed new data
@@ -75,8 +88,18 @@ fn encode<'py>(
 ///     >>> decoded == new
 ///     True
 #[pyfunction]
-fn decode<'py>(py: Python<'py>, base_data: &[u8], delta: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
-    match delta::decode(base_data, delta) {
+#[pyo3(signature = (base_data, delta, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL))]
+fn decode<'py>(
+    py: Python<'py>,
+    base_data: &[u8],
+    delta: &[u8],
+    progress: Option<Py<PyAny>>,
+    progress_interval: u64,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let (options, error) = progress_options(progress, progress_interval);
+    let result = delta::decode_with_options(base_data, delta, &options);
+    raise_progress_error(error)?;
+    match result {
         Ok(result) => Ok(PyBytes::new(py, &result[..])),
         Err(error) => Err(PyValueError::new_err(error)),
     }
@@ -108,6 +131,41 @@ fn get_tag(delta: &[u8]) -> PyResult<usize> {
     }
 }
 
+/// First exception raised by a progress callback, if any.
+type ProgressError = Arc<Mutex<Option<PyErr>>>;
+
+/// Builds options that forward progress reports to a Python callable.
+///
+/// Progress reports cannot fail, so an exception raised by the callable is kept and
+/// re-raised by [`raise_progress_error`] once the operation finished.
+fn progress_options(progress: Option<Py<PyAny>>, interval: u64) -> (EncodeOptions, ProgressError) {
+    let error = ProgressError::default();
+    let options = EncodeOptions {
+        progress_interval: interval,
+        ..EncodeOptions::default()
+    };
+    let Some(progress) = progress else {
+        return (options, error);
+    };
+
+    let sink = Arc::clone(&error);
+    let options = options.progress(move |done, total| {
+        Python::attach(|py| {
+            if let Err(raised) = progress.call1(py, (done, total)) {
+                sink.lock().unwrap().get_or_insert(raised);
+            }
+        });
+    });
+    (options, error)
+}
+
+fn raise_progress_error(error: ProgressError) -> PyResult<()> {
+    match error.lock().unwrap().take() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
 /// xpatch - High-performance delta compression library
 ///
 /// This library provides extremely efficient delta compression for byte sequences,
@@ -88,6 +88,36 @@ def test_zstd_disabled():
     print("✓ test_zstd_disabled passed")
 
 
+def test_progress():
+    """Test progress callbacks on encode and decode."""
+    base = bytes((i * 7919) % 251 for i in range(200_000))
+    new = bytearray(base)
+    for i in range(0, len(new), 1000):
+        new[i] ^= 0xFF
+    new = bytes(new)
+
+    reports = []
+    progress = lambda done, total: reports.append((done, total))
+    delta = xpatch.encode(0, base, new, progress=progress)
+    assert reports[0] == (0, len(new)), reports
+    assert reports[-1] == (len(new), len(new)), reports
+
+    reports.clear()
+    assert xpatch.decode(base, delta, progress=progress, progress_interval=10_000) == new
+    assert len(reports) > 5, reports
+    assert reports[-1] == (len(new), len(new)), reports
+
+    def failing(done, total):
+        raise RuntimeError("stop")
+
+    try:
+        xpatch.decode(base, delta, progress=failing)
+        assert False, "Expected the callback's exception"
+    except RuntimeError:
+        pass
+    print("✓ test_progress passed")
+
+
 if __name__ == "__main__":
     print("Running xpatch Python binding tests...\n")
 
@@ -98,5 +128,6 @@ if __name__ == "__main__":
     test_large_tag()
     test_identical_data()
     test_zstd_disabled()
+    test_progress()
 
     print("\n✅ All tests passed!")
@@ -1,12 +1,14 @@
 """Type stubs for xpatch"""
 
-from typing import Union
+from typing import Callable, Optional, Union
 
 def encode(
     tag: int,
     base_data: bytes,
     new_data: bytes,
-    enable_zstd: bool = True
+    enable_zstd: bool = True,
+    progress: Optional[Callable[[int, int], None]] = None,
+    progress_interval: int = 1048576
 ) -> bytes:
     """Encode a delta patch between base_data and new_data.
 
@@ -15,6 +17,8 @@ def encode(
         base_data: The original data as bytes
         new_data: The new data as bytes
         enable_zstd: Whether to enable zstd compression (default: True)
+        progress: Called as progress(done, total) with bytes of new_data encoded
+        progress_interval: Minimum bytes between two progress calls (default: 1 MiB)
 
     Returns:
         The encoded delta patch as bytes
@@ -31,13 +35,17 @@ def encode(
 
 def decode(
     base_data: bytes,
-    delta: bytes
+    delta: bytes,
+    progress: Optional[Callable[[int, int], None]] = None,
+    progress_interval: int = 1048576
 ) -> bytes:
     """Decode a delta patch and reconstruct the new data.
 
     Args:
         base_data: The original data the delta was created from
         delta: The encoded delta patch
+        progress: Called as progress(done, total) with bytes reconstructed
+        progress_interval: Minimum bytes between two progress calls (default: 1 MiB)
 
     Returns:
         The reconstructed new data as bytes
@@ -0,0 +1,617 @@
+#![deny(clippy::all)]
+
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+use js_sys::{Array, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+#[cfg(feature = "encode")]
+use xpatch::EncodeOptions;
+use xpatch::stream::StreamDecoder;
+#[cfg(feature = "encode")]
+use xpatch::stream::{DEFAULT_WINDOW_SIZE, StreamEncoder};
+
+/// Start the Web Worker pool used for parallel encoding.
+///
+/// Only available in builds with the `threads` feature. Must be awaited once before
+/// enabling parallel encoding, e.g. `await initThreadPool(navigator.hardwareConcurrency)`.
+#[cfg(feature = "threads")]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+/// Whether this build supports parallel encoding (the `threads` feature).
+///
+/// @returns `true` if `initThreadPool` and `WasmEncoder.setParallel` are available
+#[wasm_bindgen(js_name = hasThreads)]
+pub fn has_threads() -> bool {
+    cfg!(feature = "threads")
+}
+
+/// Whether this build can encode (the `encode` feature, left out of decode-only builds).
+///
+/// @returns `true` if `encode`, `encodeWithOptions` and `WasmEncoder` are available
+#[wasm_bindgen(js_name = hasEncoder)]
+pub fn has_encoder() -> bool {
+    cfg!(feature = "encode")
+}
+
+/// Encode a delta patch between baseData and newData.
+///
+/// @param tag - Metadata tag to embed in the delta (0-15 with no overhead)
+/// @param baseData - The original data
+/// @param newData - The new data
+/// @param enableZstd - Whether to enable zstd compression (default: true)
+/// @returns The encoded delta patch
+#[cfg(feature = "encode")]
+#[wasm_bindgen]
+pub fn encode(tag: u32, base_data: &[u8], new_data: &[u8], enable_zstd: Option<bool>) -> Vec<u8> {
+    xpatch::encode(
+        tag as usize,
+        base_data,
+        new_data,
+        enable_zstd.unwrap_or(true),
+    )
+}
+
+/// Encode a delta patch with explicit encoding options.
+///
+/// @param tag - Metadata tag to embed in the delta
+/// @param baseData - The original data
+/// @param newData - The new data
+/// @param options - `{ enableZstd?: boolean, zstdLevel?: number, checksum?: boolean,
+/// signal?: AbortSignal }`
+/// @returns The encoded delta patch
+/// @throws {Error} If an option has the wrong type, or `signal` is already aborted
+///
+/// @example
+/// ```javascript
+/// const delta = encodeWithOptions(0, base, newData, { checksum: true, zstdLevel: 19 });
+/// ```
+#[cfg(feature = "encode")]
+#[wasm_bindgen(js_name = encodeWithOptions)]
+pub fn encode_with_options(
+    tag: u32,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: JsValue,
+) -> Result<Vec<u8>, JsError> {
+    let signal = if options.is_object() {
+        Reflect::get(&options, &JsValue::from_str("signal")).unwrap_or(JsValue::UNDEFINED)
+    } else {
+        JsValue::UNDEFINED
+    };
+    check_signal(&signal)?;
+    let options = parse_options(&options)?;
+    Ok(xpatch::encode_with_options(
+        tag as usize,
+        base_data,
+        new_data,
+        &options,
+    ))
+}
+
+/// Read the header of a delta patch without decoding it.
+///
+/// @param delta - The delta patch
+/// @returns `{ algorithm, tag, headerSize, payloadSize, totalSize, baseChecksum, outputChecksum,
+/// encrypted, stored }`,
+/// where the checksums are `null` if the delta carries none
+/// @throws {Error} If the delta is invalid or corrupted
+#[wasm_bindgen]
+pub fn inspect(delta: &[u8]) -> Result<JsValue, JsError> {
+    let info = xpatch::inspect(delta).map_err(JsError::new)?;
+    let checksum = |value: Option<u32>| value.map_or(JsValue::NULL, JsValue::from);
+
+    let object = Object::new();
+    for (key, value) in [
+        ("algorithm", JsValue::from(format!("{:?}", info.algorithm))),
+        ("tag", JsValue::from(info.tag as f64)),
+        ("headerSize", JsValue::from(info.header_size as f64)),
+        ("payloadSize", JsValue::from(info.payload_size as f64)),
+        ("totalSize", JsValue::from(info.total_size as f64)),
+        ("baseChecksum", checksum(info.base_checksum)),
+        ("outputChecksum", checksum(info.output_checksum)),
+        ("encrypted", JsValue::from(info.encrypted)),
+        ("stored", JsValue::from(info.stored)),
+    ] {
+        Reflect::set(&object, &JsValue::from_str(key), &value)
+            .map_err(|_| JsError::new("Failed to build inspection result"))?;
+    }
+    Ok(object.into())
+}
+
+/// Decode a delta patch to reconstruct newData from baseData.
+///
+/// @param baseData - The original data
+/// @param delta - The delta patch
+/// @param signal - An `AbortSignal`; the call throws if it is already aborted
+/// @returns The reconstructed new data
+/// @throws {Error} If the delta is invalid or corrupted, or `signal` is already aborted
+#[wasm_bindgen]
+pub fn decode(base_data: &[u8], delta: &[u8], signal: Option<Object>) -> Result<Vec<u8>, JsError> {
+    check_signal(&signal.map_or(JsValue::UNDEFINED, JsValue::from))?;
+    xpatch::decode(base_data, delta).map_err(JsError::new)
+}
+
+/// Extract the metadata tag from a delta patch.
+///
+/// @param delta - The delta patch
+/// @returns The embedded metadata tag
+/// @throws {Error} If the delta is invalid or corrupted
+#[wasm_bindgen(js_name = getTag)]
+pub fn get_tag(delta: &[u8]) -> Result<u32, JsError> {
+    xpatch::get_tag(delta)
+        .map(|tag| tag as u32)
+        .map_err(JsError::new)
+}
+
+/// Streaming encoder for new files that are too large to pass in one piece.
+///
+/// The base is copied into wasm memory once; the new data is pushed in chunks and
+/// only about one window of it is buffered at a time.
+///
+/// @example
+/// ```javascript
+/// const encoder = new WasmEncoder(base, 0);
+/// const parts = [];
+/// for await (const chunk of file.stream()) {
+///   parts.push(encoder.push(chunk));
+/// }
+/// parts.push(encoder.finish());
+/// const patch = new Blob(parts);
+/// ```
+#[cfg(feature = "encode")]
+#[wasm_bindgen]
+pub struct WasmEncoder {
+    inner: Option<StreamEncoder<Vec<u8>>>,
+    signal: JsValue,
+}
+
+#[cfg(feature = "encode")]
+#[wasm_bindgen]
+impl WasmEncoder {
+    /// @param baseData - The original data
+    /// @param tag - Metadata tag stored in every frame (default: 0)
+    /// @param enableZstd - Whether to enable zstd compression (default: true)
+    /// @param windowSize - Bytes of new data encoded per frame (default: 1 MiB)
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        base_data: Vec<u8>,
+        tag: Option<u32>,
+        enable_zstd: Option<bool>,
+        window_size: Option<u32>,
+    ) -> Result<WasmEncoder, JsError> {
+        let window_size = window_size.map_or(DEFAULT_WINDOW_SIZE, |size| size as usize);
+        if window_size == 0 {
+            return Err(JsError::new("Window size must be greater than zero"));
+        }
+
+        Ok(WasmEncoder {
+            inner: Some(StreamEncoder::with_window_size(
+                base_data,
+                tag.unwrap_or(0) as usize,
+                enable_zstd.unwrap_or(true),
+                window_size,
+            )),
+            signal: JsValue::UNDEFINED,
+        })
+    }
+
+    /// Abort the stream once `signal` aborts.
+    ///
+    /// The signal is checked at the start of every `push` and `finish`, which then throw
+    /// `Operation cancelled`. An aborted encoder cannot be resumed.
+    ///
+    /// @param signal - The `AbortSignal` to observe
+    #[wasm_bindgen(js_name = setSignal)]
+    pub fn set_signal(&mut self, signal: Object) {
+        self.signal = signal.into();
+    }
+
+    /// Encode windows in parallel on the thread pool started by `initThreadPool`.
+    ///
+    /// Buffers up to one window per thread before encoding. The output is identical
+    /// to sequential encoding.
+    ///
+    /// @param enabled - Whether to encode in parallel
+    /// @throws {Error} If the encoder was already finished
+    #[cfg(feature = "threads")]
+    #[wasm_bindgen(js_name = setParallel)]
+    pub fn set_parallel(&mut self, enabled: bool) -> Result<(), JsError> {
+        match self.inner.as_mut() {
+            Some(encoder) => {
+                encoder.set_parallel(enabled);
+                Ok(())
+            }
+            None => Err(JsError::new("Encoder already finished")),
+        }
+    }
+
+    /// Feed the next chunk of new data.
+    ///
+    /// @param chunk - The next chunk of new data
+    /// @returns Encoded stream bytes that are ready (may be empty)
+    /// @throws {Error} If the encoder was already finished or aborted
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        check_signal(&self.signal)?;
+        match self.inner.as_mut() {
+            Some(encoder) => Ok(encoder.push(chunk)),
+            None => Err(JsError::new("Encoder already finished")),
+        }
+    }
+
+    /// Flush the remaining data and end the stream.
+    ///
+    /// @returns The final encoded stream bytes
+    /// @throws {Error} If the encoder was already finished or aborted
+    pub fn finish(&mut self) -> Result<Vec<u8>, JsError> {
+        check_signal(&self.signal)?;
+        match self.inner.take() {
+            Some(encoder) => Ok(encoder.finish()),
+            None => Err(JsError::new("Encoder already finished")),
+        }
+    }
+}
+
+/// Streaming decoder for patches produced by WasmEncoder.
+///
+/// @example
+/// ```javascript
+/// const decoder = new WasmDecoder(base);
+/// const parts = [];
+/// for await (const chunk of patch.stream()) {
+///   parts.push(decoder.push(chunk));
+/// }
+/// decoder.finish();
+/// const restored = new Blob(parts);
+/// ```
+#[wasm_bindgen]
+pub struct WasmDecoder {
+    inner: Option<StreamDecoder<Vec<u8>>>,
+    signal: JsValue,
+}
+
+#[wasm_bindgen]
+impl WasmDecoder {
+    /// @param baseData - The original data
+    #[wasm_bindgen(constructor)]
+    pub fn new(base_data: Vec<u8>) -> WasmDecoder {
+        WasmDecoder {
+            inner: Some(StreamDecoder::new(base_data)),
+            signal: JsValue::UNDEFINED,
+        }
+    }
+
+    /// Abort the stream once `signal` aborts.
+    ///
+    /// The signal is checked at the start of every `push` and `finish`, which then throw
+    /// `Operation cancelled`.
+    ///
+    /// @param signal - The `AbortSignal` to observe
+    #[wasm_bindgen(js_name = setSignal)]
+    pub fn set_signal(&mut self, signal: Object) {
+        self.signal = signal.into();
+    }
+
+    /// Feed the next chunk of the encoded stream.
+    ///
+    /// @param chunk - The next chunk of the stream
+    /// @returns Decoded bytes that are ready (may be empty)
+    /// @throws {Error} If the stream is invalid or the decoder was already finished or aborted
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        check_signal(&self.signal)?;
+        match self.inner.as_mut() {
+            Some(decoder) => decoder.push(chunk).map_err(JsError::new),
+            None => Err(JsError::new("Decoder already finished")),
+        }
+    }
+
+    /// Verify that the complete stream was received.
+    ///
+    /// @throws {Error} If the stream is truncated or the decoder was already finished or aborted
+    pub fn finish(&mut self) -> Result<(), JsError> {
+        check_signal(&self.signal)?;
+        match self.inner.take() {
+            Some(decoder) => decoder.finish().map_err(JsError::new),
+            None => Err(JsError::new("Decoder already finished")),
+        }
+    }
+}
+
+/// Diff two `Blob`s (e.g. `File`s picked by the user) into a streaming patch.
+///
+/// The new blob is read through `ReadableStream` in chunks, so only about one window of
+/// it is held at a time. The base is not streamed: copies may refer to any part of it, so
+/// it is read into wasm memory whole and must fit there (under 4 GiB on wasm32, with the
+/// window on top). The patch parts stay in JS memory until they are joined into the
+/// returned `Blob`.
+///
+/// @param baseBlob - The original data
+/// @param newBlob - The new data
+/// @param options - `{ tag?: number, enableZstd?: boolean, windowSize?: number,
+/// signal?: AbortSignal }`
+/// @returns A `Promise` of the patch as a `Blob`, readable by `applyToBlob` and `WasmDecoder`
+/// @throws {Error} If an option has the wrong type, a blob cannot be read, or `signal` aborts
+///
+/// @example
+/// ```javascript
+/// const patch = await encodeFromBlobs(oldFile, newFile, { signal: controller.signal });
+/// ```
+#[cfg(feature = "encode")]
+#[wasm_bindgen(js_name = encodeFromBlobs)]
+pub async fn encode_from_blobs(
+    base_blob: Blob,
+    new_blob: Blob,
+    options: JsValue,
+) -> Result<Blob, JsValue> {
+    let options = parse_stream_options(&options)?;
+    check_signal(&options.signal)?;
+    if options.window_size == 0 {
+        return Err(JsError::new("Window size must be greater than zero").into());
+    }
+
+    let base = read_blob(&base_blob).await?;
+    let mut encoder = StreamEncoder::with_window_size(
+        base,
+        options.tag,
+        options.enable_zstd,
+        options.window_size,
+    );
+    let parts = Array::new();
+    for_each_chunk(&new_blob, &options.signal, |chunk| {
+        push_part(&parts, &encoder.push(chunk));
+        Ok(())
+    })
+    .await?;
+    check_signal(&options.signal)?;
+    push_part(&parts, &encoder.finish());
+    Blob::new(&parts)
+}
+
+/// Apply a streaming patch to a `Blob`, reading the patch in chunks.
+///
+/// As with `encodeFromBlobs`, the base is read into wasm memory whole and must fit there.
+/// Decoded data is handed back in parts and joined into the returned `Blob`.
+///
+/// @param baseBlob - The original data
+/// @param delta - The patch from `encodeFromBlobs` or `WasmEncoder`, as a `Blob` or
+/// `Uint8Array`
+/// @param signal - An `AbortSignal`, checked between chunks
+/// @returns A `Promise` of the reconstructed data as a `Blob`
+/// @throws {Error} If the patch is invalid or truncated, a blob cannot be read, or `signal`
+/// aborts
+///
+/// @example
+/// ```javascript
+/// const restored = await applyToBlob(oldFile, patch);
+/// ```
+#[wasm_bindgen(js_name = applyToBlob)]
+pub async fn apply_to_blob(
+    base_blob: Blob,
+    delta: JsValue,
+    signal: Option<Object>,
+) -> Result<Blob, JsValue> {
+    let signal = signal.map_or(JsValue::UNDEFINED, JsValue::from);
+    check_signal(&signal)?;
+    let delta = if delta.is_instance_of::<Blob>() {
+        delta.unchecked_into::<Blob>()
+    } else if delta.is_instance_of::<Uint8Array>() {
+        Blob::new(&Array::of1(&delta))?
+    } else {
+        return Err(JsError::new("delta must be a Blob or Uint8Array").into());
+    };
+
+    let base = read_blob(&base_blob).await?;
+    let mut decoder = StreamDecoder::new(base);
+    let parts = Array::new();
+    for_each_chunk(&delta, &signal, |chunk| {
+        push_part(&parts, &decoder.push(chunk).map_err(JsError::new)?);
+        Ok(())
+    })
+    .await?;
+    check_signal(&signal)?;
+    decoder.finish().map_err(JsError::new)?;
+    Blob::new(&parts)
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "Blob")]
+    pub type Blob;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(parts: &Array) -> Result<Blob, JsValue>;
+
+    #[wasm_bindgen(method, js_name = arrayBuffer)]
+    fn array_buffer(this: &Blob) -> Promise;
+
+    #[wasm_bindgen(method)]
+    fn stream(this: &Blob) -> ReadableStream;
+
+    type ReadableStream;
+
+    #[wasm_bindgen(method, js_name = getReader)]
+    fn get_reader(this: &ReadableStream) -> ReadableStreamDefaultReader;
+
+    type ReadableStreamDefaultReader;
+
+    #[wasm_bindgen(method)]
+    fn read(this: &ReadableStreamDefaultReader) -> Promise;
+
+    #[wasm_bindgen(method)]
+    fn cancel(this: &ReadableStreamDefaultReader) -> Promise;
+}
+
+/// Copies a whole blob into wasm memory.
+async fn read_blob(blob: &Blob) -> Result<Vec<u8>, JsValue> {
+    let buffer = JsFuture::from(blob.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+/// Reads `blob` through its `ReadableStream`, checking `signal` before every chunk.
+///
+/// The stream is cancelled if `f`, the read or the signal fails, so that the browser
+/// can release the underlying file.
+async fn for_each_chunk(
+    blob: &Blob,
+    signal: &JsValue,
+    mut f: impl FnMut(&[u8]) -> Result<(), JsError>,
+) -> Result<(), JsValue> {
+    let reader = blob.stream().get_reader();
+    let result: Result<(), JsValue> = async {
+        loop {
+            check_signal(signal)?;
+            let read = JsFuture::from(reader.read()).await?;
+            if Reflect::get(&read, &JsValue::from_str("done"))?.is_truthy() {
+                return Ok(());
+            }
+            let chunk = Reflect::get(&read, &JsValue::from_str("value"))?;
+            f(&Uint8Array::new(&chunk).to_vec())?;
+        }
+    }
+    .await;
+    if result.is_err() {
+        let _ = reader.cancel();
+    }
+    result
+}
+
+/// Appends `bytes` to the JS-side part list, skipping empty output.
+fn push_part(parts: &Array, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        parts.push(&Uint8Array::from(bytes));
+    }
+}
+
+/// Fails with the core cancellation error if `signal` is an aborted `AbortSignal`.
+///
+/// Wasm calls run to completion on the JS thread, so an abort can only be observed
+/// between calls; `undefined` and `null` mean no signal.
+fn check_signal(signal: &JsValue) -> Result<(), JsError> {
+    if signal.is_undefined() || signal.is_null() {
+        return Ok(());
+    }
+    if !signal.is_object() {
+        return Err(JsError::new("signal must be an AbortSignal"));
+    }
+
+    let aborted = Reflect::get(signal, &JsValue::from_str("aborted"))
+        .ok()
+        .and_then(|value| value.as_bool())
+        .ok_or_else(|| JsError::new("signal must be an AbortSignal"))?;
+    if aborted {
+        return Err(JsError::new(xpatch::delta::CANCELLED));
+    }
+    Ok(())
+}
+
+/// Reads EncodeOptions from a plain JS object; missing fields keep their defaults.
+#[cfg(feature = "encode")]
+fn parse_options(options: &JsValue) -> Result<EncodeOptions, JsError> {
+    let mut parsed = EncodeOptions::default();
+    if options.is_undefined() || options.is_null() {
+        return Ok(parsed);
+    }
+    if !options.is_object() {
+        return Err(JsError::new("options must be an object"));
+    }
+
+    let field = |name: &str| {
+        Reflect::get(options, &JsValue::from_str(name))
+            .ok()
+            .filter(|value| !value.is_undefined())
+    };
+
+    if let Some(value) = field("enableZstd") {
+        parsed.enable_zstd = value
+            .as_bool()
+            .ok_or_else(|| JsError::new("enableZstd must be a boolean"))?;
+    }
+    if let Some(value) = field("zstdLevel") {
+        let level = value
+            .as_f64()
+            .filter(|level| level.fract() == 0.0 && (1.0..=22.0).contains(level))
+            .ok_or_else(|| JsError::new("zstdLevel must be an integer between 1 and 22"))?;
+        parsed.zstd_level = level as i32;
+    }
+    if let Some(value) = field("checksum") {
+        parsed.checksum = value
+            .as_bool()
+            .ok_or_else(|| JsError::new("checksum must be a boolean"))?;
+    }
+
+    Ok(parsed)
+}
+
+/// Options of `encodeFromBlobs`, matching the `WasmEncoder` constructor.
+#[cfg(feature = "encode")]
+struct StreamOptions {
+    tag: usize,
+    enable_zstd: bool,
+    window_size: usize,
+    signal: JsValue,
+}
+
+/// Reads StreamOptions from a plain JS object; missing fields keep their defaults.
+#[cfg(feature = "encode")]
+fn parse_stream_options(options: &JsValue) -> Result<StreamOptions, JsError> {
+    let mut parsed = StreamOptions {
+        tag: 0,
+        enable_zstd: true,
+        window_size: DEFAULT_WINDOW_SIZE,
+        signal: JsValue::UNDEFINED,
+    };
+    if options.is_undefined() || options.is_null() {
+        return Ok(parsed);
+    }
+    if !options.is_object() {
+        return Err(JsError::new("options must be an object"));
+    }
+
+    let field = |name: &str| {
+        Reflect::get(options, &JsValue::from_str(name))
+            .ok()
+            .filter(|value| !value.is_undefined())
+    };
+    let integer = |value: JsValue, name: &str| {
+        value
+            .as_f64()
+            .filter(|number| number.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(number))
+            .map(|number| number as usize)
+            .ok_or_else(|| JsError::new(&format!("{name} must be a non-negative integer")))
+    };
+
+    if let Some(value) = field("tag") {
+        parsed.tag = integer(value, "tag")?;
+    }
+    if let Some(value) = field("enableZstd") {
+        parsed.enable_zstd = value
+            .as_bool()
+            .ok_or_else(|| JsError::new("enableZstd must be a boolean"))?;
+    }
+    if let Some(value) = field("windowSize") {
+        parsed.window_size = integer(value, "windowSize")?;
+    }
+    if let Some(value) = field("signal") {
+        parsed.signal = value;
+    }
+
+    Ok(parsed)
+}
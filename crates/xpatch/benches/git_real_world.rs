@@ -29,7 +29,12 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+#[cfg(feature = "gdelta")]
+use xpatch::bench::Gdelta;
+use xpatch::bench::{
+    self, BenchmarkResult, DeltaAlgorithm, HardwareInfo, Report, Version, XpatchSequential,
+    XpatchTags,
+};
 
 // ============================================================================
 // GLOBAL SHUTDOWN FLAG
@@ -50,98 +55,9 @@ fn should_continue() -> bool {
 }
 
 // ============================================================================
-// ALGORITHM TRAIT
+// EXTRA ALGORITHMS
 // ============================================================================
 
-trait DeltaAlgorithm: Send + Sync {
-    fn name(&self) -> &str;
-
-    // Regular encode (for algorithms that don't use multi-version)
-    fn encode(&self, _base: &[u8], _new: &[u8]) -> Result<Vec<u8>> {
-        anyhow::bail!("Not implemented")
-    }
-
-    // Multi-version encode (for xpatch_tags)
-    fn encode_with_history(
-        &self,
-        new: &[u8],
-        previous_versions: &[(usize, &[u8])],
-    ) -> Result<(usize, Vec<u8>)> {
-        // Default: just use immediate previous
-        if let Some((tag, base)) = previous_versions.first() {
-            Ok((*tag, self.encode(base, new)?))
-        } else {
-            anyhow::bail!("No previous versions")
-        }
-    }
-
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>>;
-}
-
-// xpatch - Sequential mode (no tags, fair comparison to xdelta3(vcdiff))
-struct XpatchSequential;
-
-impl DeltaAlgorithm for XpatchSequential {
-    fn name(&self) -> &str {
-        "xpatch_sequential"
-    }
-
-    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>> {
-        Ok(xpatch::delta::encode(0, base, new, true))
-    }
-
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>> {
-        xpatch::delta::decode(base, delta).map_err(|e| anyhow::anyhow!("{}", e))
-    }
-}
-
-// xpatch - With tag optimization (search back N commits for best base)
-struct XpatchTags {
-    max_search_depth: usize,
-}
-
-impl XpatchTags {
-    fn new(max_search_depth: usize) -> Self {
-        Self { max_search_depth }
-    }
-}
-
-impl DeltaAlgorithm for XpatchTags {
-    fn name(&self) -> &str {
-        "xpatch_tags"
-    }
-
-    fn encode_with_history(
-        &self,
-        new: &[u8],
-        previous_versions: &[(usize, &[u8])],
-    ) -> Result<(usize, Vec<u8>)> {
-        let search_depth = self.max_search_depth.min(previous_versions.len());
-
-        let mut best_tag = 0;
-        let mut best_delta_size = usize::MAX;
-        let mut best_delta = Vec::new();
-
-        // Search through previous N versions
-        for i in 0..search_depth {
-            let (tag, base) = previous_versions[i];
-            let delta = xpatch::delta::encode(tag, base, new, true);
-
-            if delta.len() < best_delta_size {
-                best_delta_size = delta.len();
-                best_tag = tag;
-                best_delta = delta;
-            }
-        }
-
-        Ok((best_tag, best_delta))
-    }
-
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>> {
-        xpatch::delta::decode(base, delta).map_err(|e| anyhow::anyhow!("{}", e))
-    }
-}
-
 // vcdiff (VCDIFF standard implementation)
 #[cfg(feature = "vcdiff")]
 struct VcdiffAlgo;
@@ -152,119 +68,21 @@ impl DeltaAlgorithm for VcdiffAlgo {
         "vcdiff"
     }
 
-    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>, bench::Error> {
         // Use standard format with checksum for compatibility
         let format = vcdiff::FORMAT_STANDARD | vcdiff::FORMAT_CHECKSUM;
         Ok(vcdiff::encode(base, new, format, true))
     }
 
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>> {
+    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, bench::Error> {
         Ok(vcdiff::decode(base, delta))
     }
 }
 
-// gdelta (optional, if feature enabled)
-#[cfg(feature = "gdelta")]
-struct GdeltaAlgo;
-
-#[cfg(feature = "gdelta")]
-impl DeltaAlgorithm for GdeltaAlgo {
-    fn name(&self) -> &str {
-        "gdelta"
-    }
-
-    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>> {
-        gdelta::encode(new, base).map_err(Into::into)
-    }
-
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>> {
-        gdelta::decode(delta, base).map_err(Into::into)
-    }
-}
-
-// ============================================================================
-// STATISTICS HELPERS
-// ============================================================================
-
-fn median(values: &mut [f64]) -> f64 {
-    if values.is_empty() {
-        return 0.0;
-    }
-    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let mid = values.len() / 2;
-    if values.len() % 2 == 0 {
-        (values[mid - 1] + values[mid]) / 2.0
-    } else {
-        values[mid]
-    }
-}
-
-fn median_u128(values: &mut [u128]) -> u128 {
-    if values.is_empty() {
-        return 0;
-    }
-    values.sort();
-    let mid = values.len() / 2;
-    if values.len() % 2 == 0 {
-        (values[mid - 1] + values[mid]) / 2
-    } else {
-        values[mid]
-    }
-}
-
-fn median_usize(values: &mut [usize]) -> usize {
-    if values.is_empty() {
-        return 0;
-    }
-    values.sort();
-    let mid = values.len() / 2;
-    if values.len() % 2 == 0 {
-        (values[mid - 1] + values[mid]) / 2
-    } else {
-        values[mid]
-    }
-}
-
 // ============================================================================
-// RESULT TRACKING
+// HARDWARE INFO
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BenchmarkResult {
-    repo_name: String,
-    file_path: String,
-    commit_from: String,
-    commit_to: String,
-    commit_distance: usize,
-    file_size: usize,
-
-    algorithm: String,
-    tag_used: Option<usize>,
-    tag_base_commit: Option<String>,
-    tag_base_distance: Option<usize>,
-
-    delta_size: usize,
-    compression_ratio: f64,
-    encode_us: u128,
-    decode_us: u128,
-    verified: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct HardwareInfo {
-    cpu: String,
-    cores: usize,
-    memory_gb: f64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Report {
-    generated_at: String,
-    hardware: HardwareInfo,
-    results: Vec<BenchmarkResult>,
-    early_termination: bool,
-}
-
 fn collect_hardware_info() -> HardwareInfo {
     let mut sys = sysinfo::System::new_all();
     sys.refresh_all();
@@ -371,7 +189,7 @@ impl Cache {
         self.manifest
             .files
             .entry(file_path.to_string())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(CachedVersion {
                 commit_hash: commit.hash.clone(),
                 commit_date: commit.date.clone(),
@@ -406,12 +224,6 @@ struct CommitInfo {
     index: usize,
 }
 
-impl CommitInfo {
-    fn distance_from(&self, other: &CommitInfo) -> usize {
-        self.index.abs_diff(other.index)
-    }
-}
-
 fn clone_or_open_repo(url: &str, path: &Path) -> Result<Repository> {
     if path.join(".git").exists() {
         log::info!("Using existing repository at {}", path.display());
@@ -430,11 +242,11 @@ fn get_commit_history(repo: &Repository, file_path: &str, limit: usize) -> Resul
 
     if revwalk.push_head().is_err() {
         for branch_name in &["main", "master", "develop"] {
-            if let Ok(branch) = repo.find_branch(branch_name, git2::BranchType::Local) {
-                if let Some(target) = branch.get().target() {
-                    revwalk.push(target)?;
-                    break;
-                }
+            if let Ok(branch) = repo.find_branch(branch_name, git2::BranchType::Local)
+                && let Some(target) = branch.get().target()
+            {
+                revwalk.push(target)?;
+                break;
             }
         }
     }
@@ -450,23 +262,23 @@ fn get_commit_history(repo: &Repository, file_path: &str, limit: usize) -> Resul
         let oid = oid?;
         let commit = repo.find_commit(oid)?;
 
-        if let Ok(tree) = commit.tree() {
-            if let Ok(entry) = tree.get_path(Path::new(file_path)) {
-                let blob_id = entry.id();
-
-                // Skip if content didn't actually change
-                if last_blob_id == Some(blob_id) {
-                    continue;
-                }
+        if let Ok(tree) = commit.tree()
+            && let Ok(entry) = tree.get_path(Path::new(file_path))
+        {
+            let blob_id = entry.id();
 
-                commits.push(CommitInfo {
-                    hash: commit.id().to_string(),
-                    date: commit.time().seconds().to_string(),
-                    message: commit.summary().unwrap_or("").to_string(),
-                    index: 0, // Will be fixed after reversal
-                });
-                last_blob_id = Some(blob_id);
+            // Skip if content didn't actually change
+            if last_blob_id == Some(blob_id) {
+                continue;
             }
+
+            commits.push(CommitInfo {
+                hash: commit.id().to_string(),
+                date: commit.time().seconds().to_string(),
+                message: commit.summary().unwrap_or("").to_string(),
+                index: 0, // Will be fixed after reversal
+            });
+            last_blob_id = Some(blob_id);
         }
     }
 
@@ -593,13 +405,13 @@ fn collect_files_from_tree(
 // BENCHMARKING WITH TAG OPTIMIZATION
 // ============================================================================
 
+#[allow(clippy::too_many_arguments)]
 fn benchmark_file_with_tags(
     repo: &Repository,
     cache: &Option<Arc<Mutex<Cache>>>,
     repo_name: &str,
     file_path: &str,
     max_commits: usize,
-    max_tag_depth: usize,
     min_file_size: usize,
     algos: &[Box<dyn DeltaAlgorithm>],
 ) -> Result<Vec<BenchmarkResult>> {
@@ -668,153 +480,28 @@ fn benchmark_file_with_tags(
         anyhow::bail!("File too small (avg {} bytes): {}", avg_size, file_path);
     }
 
+    let versions: Vec<Version> = commit_data
+        .iter()
+        .map(|(commit, content)| Version {
+            id: &commit.hash[..8],
+            index: Some(commit.index),
+            data: content,
+        })
+        .collect();
+
     let mut results = Vec::new();
 
     // Process each target commit (oldest→newest order)
-    // For each commit i, we encode a delta from commit i-1 (older) → i (newer)
-    for i in 1..commit_data.len() {
+    // For each commit i, we encode a delta from an older commit → i (newer)
+    for step in bench::compare(repo_name, file_path, &versions, algos) {
         if !should_continue() {
             break;
         }
 
-        let (target_commit, target_content) = &commit_data[i];
-        let (prev_commit, prev_content) = &commit_data[i - 1];
-
-        // For each algorithm
-        for algo in algos {
-            let result = if algo.name() == "xpatch_tags" {
-                // Build list of previous versions for tag search
-                let search_depth = max_tag_depth.min(i);
-                let previous_versions: Vec<(usize, &[u8])> = (0..search_depth)
-                    .map(|j| {
-                        let base_idx = i - 1 - j;
-                        let tag = j + 1;
-                        (tag, commit_data[base_idx].1.as_slice())
-                    })
-                    .collect();
-
-                let start = Instant::now();
-                let (tag_used, delta) =
-                    match algo.encode_with_history(target_content, &previous_versions) {
-                        Ok(d) => d,
-                        Err(e) => {
-                            log::debug!("Tag encode failed for {}: {}", file_path, e);
-                            continue;
-                        }
-                    };
-                let encode_us = start.elapsed().as_micros();
-
-                let base_idx = i - tag_used;
-                let (base_commit, base_content) = &commit_data[base_idx];
-
-                let start = Instant::now();
-                let reconstructed = match algo.decode(&delta, base_content) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        log::warn!(
-                            "Tag decode failed for {} (tag={}, base={}→target={}, base_size={}, delta_size={}, target_size={}): {}",
-                            file_path,
-                            tag_used,
-                            base_commit.hash[..8].to_string(),
-                            target_commit.hash[..8].to_string(),
-                            base_content.len(),
-                            delta.len(),
-                            target_content.len(),
-                            e
-                        );
-                        continue;
-                    }
-                };
-                let decode_us = start.elapsed().as_micros();
-
-                let verified = reconstructed == *target_content;
-
-                Some(BenchmarkResult {
-                    repo_name: repo_name.to_string(),
-                    file_path: file_path.to_string(),
-                    commit_from: base_commit.hash[..8].to_string(),
-                    commit_to: target_commit.hash[..8].to_string(),
-                    commit_distance: target_commit.distance_from(base_commit),
-                    file_size: target_content.len(),
-                    algorithm: algo.name().to_string(),
-                    tag_used: Some(tag_used),
-                    tag_base_commit: Some(base_commit.hash[..8].to_string()),
-                    tag_base_distance: Some(target_commit.distance_from(base_commit)),
-                    delta_size: delta.len(),
-                    compression_ratio: if target_content.len() > 0 {
-                        delta.len() as f64 / target_content.len() as f64
-                    } else {
-                        0.0
-                    },
-                    encode_us,
-                    decode_us,
-                    verified,
-                })
-            } else {
-                // Standard algorithms use immediate previous
-                let start = Instant::now();
-                let delta = match algo.encode(prev_content, target_content) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        log::debug!(
-                            "Encode failed for {} ({}→{}): {}",
-                            file_path,
-                            prev_commit.hash[..8].to_string(),
-                            target_commit.hash[..8].to_string(),
-                            e
-                        );
-                        continue;
-                    }
-                };
-                let encode_us = start.elapsed().as_micros();
-
-                let start = Instant::now();
-                let reconstructed = match algo.decode(&delta, prev_content) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        log::warn!(
-                            "Decode failed for {} with {} ({}→{}, base_size={}, delta_size={}, target_size={}): {}",
-                            file_path,
-                            algo.name(),
-                            prev_commit.hash[..8].to_string(),
-                            target_commit.hash[..8].to_string(),
-                            prev_content.len(),
-                            delta.len(),
-                            target_content.len(),
-                            e
-                        );
-                        continue;
-                    }
-                };
-                let decode_us = start.elapsed().as_micros();
-
-                let verified = reconstructed == *target_content;
-
-                Some(BenchmarkResult {
-                    repo_name: repo_name.to_string(),
-                    file_path: file_path.to_string(),
-                    commit_from: prev_commit.hash[..8].to_string(),
-                    commit_to: target_commit.hash[..8].to_string(),
-                    commit_distance: 1,
-                    file_size: target_content.len(),
-                    algorithm: algo.name().to_string(),
-                    tag_used: None,
-                    tag_base_commit: None,
-                    tag_base_distance: None,
-                    delta_size: delta.len(),
-                    compression_ratio: if target_content.len() > 0 {
-                        delta.len() as f64 / target_content.len() as f64
-                    } else {
-                        0.0
-                    },
-                    encode_us,
-                    decode_us,
-                    verified,
-                })
-            };
-
-            if let Some(result) = result {
-                results.push(result);
+        for outcome in step {
+            match outcome {
+                Ok(result) => results.push(result),
+                Err(e) => log::warn!("{}", e),
             }
         }
     }
@@ -826,333 +513,26 @@ fn benchmark_file_with_tags(
 // REPORT GENERATION
 // ============================================================================
 
-fn generate_markdown_report(
-    results: &[BenchmarkResult],
-    hardware: &HardwareInfo,
-    early_termination: bool,
-    output_path: &Path,
-) -> Result<()> {
-    let mut report = String::new();
-
-    report.push_str("# 📊 Git Repository Benchmark Report\n\n");
-
-    if early_termination {
-        report.push_str("**⚠️ PARTIAL RESULTS - Benchmark was interrupted**\n\n");
-    }
-
-    report.push_str(&format!(
-        "**Generated:** {}\n\n",
-        chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
-    ));
-
-    // Hardware
-    report.push_str("## 💻 Hardware\n\n");
-    report.push_str("```\n");
-    report.push_str(&format!("CPU:    {}\n", hardware.cpu));
-    report.push_str(&format!("Cores:  {}\n", hardware.cores));
-    report.push_str(&format!("Memory: {:.1} GB\n", hardware.memory_gb));
-    report.push_str("```\n\n");
-
-    // Overview
-    let total_tests = results.len();
-    let verified = results.iter().filter(|r| r.verified).count();
-    let unique_files: std::collections::HashSet<_> = results.iter().map(|r| &r.file_path).collect();
-    let files_tested = unique_files.len();
-
-    report.push_str("## 📈 Overview\n\n");
-    report.push_str(&format!("- **Files Tested:** {}\n", files_tested));
-    report.push_str(&format!("- **Total Tests:** {}\n", total_tests));
-    report.push_str(&format!(
-        "- **Verified:** {} ({:.1}%)\n\n",
-        verified,
-        (verified as f64 / total_tests as f64) * 100.0
-    ));
-
-    // Algorithm verification status
-    report.push_str("## ⚠️ Algorithm Health\n\n");
-    report.push_str("| Algorithm | Tests Passed | Tests Failed | Status |\n");
-    report.push_str("|-----------|--------------|--------------|--------|\n");
-
-    let algos: Vec<String> = results
-        .iter()
-        .map(|r| r.algorithm.clone())
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect();
-
-    for algo in &algos {
-        let algo_results: Vec<_> = results.iter().filter(|r| r.algorithm == *algo).collect();
-        let passed = algo_results.iter().filter(|r| r.verified).count();
-        let failed = algo_results.len() - passed;
-        let status = if failed == 0 {
-            "✅ VERIFIED"
-        } else {
-            "❌ FAILED"
-        };
-        report.push_str(&format!(
-            "| {} | {} | {} | {} |\n",
-            algo, passed, failed, status
-        ));
-    }
-    report.push_str("\n");
-    report.push_str("*Note: Some algorithms may have fewer tests if they failed to encode/decode certain file versions. Failed tests are skipped and logged as warnings.*\n\n");
-
-    // Filter verified algorithms for rankings
-    let verified_algos: Vec<_> = algos
-        .iter()
-        .filter(|algo| {
-            let algo_results: Vec<_> = results.iter().filter(|r| r.algorithm == **algo).collect();
-            algo_results.iter().all(|r| r.verified)
-        })
-        .collect();
-
-    // Algorithm comparison
-    report.push_str("## 🏆 Algorithm Rankings\n\n");
-    report.push_str("*Only verified algorithms*\n\n");
-    report.push_str("### By Compression Ratio (Lower is Better)\n\n");
-    report.push_str("| Algorithm | Avg Ratio | Median Ratio | Avg Saved | Median Saved | Avg Encode (µs) | Median Encode (µs) | Avg Decode (µs) | Median Decode (µs) |\n");
-    report.push_str("|-----------|-----------|--------------|-----------|--------------|-----------------|--------------------|-----------------|-----------------|\n");
-
-    let mut algo_stats: Vec<_> = verified_algos
-        .iter()
-        .map(|algo| {
-            let algo_results: Vec<_> = results
-                .iter()
-                .filter(|r| r.algorithm == **algo && r.verified)
-                .collect();
-
-            // Calculate averages
-            let avg_ratio = algo_results
-                .iter()
-                .map(|r| r.compression_ratio)
-                .sum::<f64>()
-                / algo_results.len() as f64;
-            let avg_encode =
-                algo_results.iter().map(|r| r.encode_us).sum::<u128>() / algo_results.len() as u128;
-            let avg_decode =
-                algo_results.iter().map(|r| r.decode_us).sum::<u128>() / algo_results.len() as u128;
-
-            // Calculate medians
-            let mut ratios: Vec<f64> = algo_results.iter().map(|r| r.compression_ratio).collect();
-            let mut encode_times: Vec<u128> = algo_results.iter().map(|r| r.encode_us).collect();
-            let mut decode_times: Vec<u128> = algo_results.iter().map(|r| r.decode_us).collect();
-
-            let median_ratio = median(&mut ratios);
-            let median_encode = median_u128(&mut encode_times);
-            let median_decode = median_u128(&mut decode_times);
-
-            (
-                *algo,
-                avg_ratio,
-                median_ratio,
-                avg_encode,
-                median_encode,
-                avg_decode,
-                median_decode,
-            )
-        })
-        .collect();
-
-    algo_stats.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-
-    for (algo, avg_ratio, median_ratio, avg_encode, median_encode, avg_decode, median_decode) in
-        &algo_stats
-    {
-        let avg_saved = if avg_ratio.is_finite() && *avg_ratio > 0.0 {
-            format!("{:.1}%", (1.0 - avg_ratio) * 100.0)
-        } else {
-            "N/A".to_string()
-        };
-
-        let median_saved = if median_ratio.is_finite() && *median_ratio > 0.0 {
-            format!("{:.1}%", (1.0 - median_ratio) * 100.0)
-        } else {
-            "N/A".to_string()
-        };
-
-        report.push_str(&format!(
-            "| {} | {:.4} | {:.4} | {} | {} | {} | {} | {} | {} |\n",
-            algo,
-            avg_ratio,
-            median_ratio,
-            avg_saved,
-            median_saved,
-            avg_encode,
-            median_encode,
-            avg_decode,
-            median_decode
-        ));
-    }
-
-    // Detailed statistics section
-    report.push_str("\n## 📊 Detailed Statistics\n\n");
-
-    for algo in &verified_algos {
-        let algo_results: Vec<_> = results
-            .iter()
-            .filter(|r| r.algorithm == **algo && r.verified)
-            .collect();
-
-        if algo_results.is_empty() {
-            continue;
-        }
-
-        report.push_str(&format!("### {}\n\n", algo));
-
-        // Delta size statistics
-        let mut delta_sizes: Vec<usize> = algo_results.iter().map(|r| r.delta_size).collect();
-        let avg_delta_size = delta_sizes.iter().sum::<usize>() / delta_sizes.len();
-        let median_delta_size = median_usize(&mut delta_sizes);
-
-        // Compression ratio statistics
-        let mut ratios: Vec<f64> = algo_results.iter().map(|r| r.compression_ratio).collect();
-        let avg_ratio = ratios.iter().sum::<f64>() / ratios.len() as f64;
-        let median_ratio = median(&mut ratios);
-
-        // Space saved statistics
-        let avg_saved = if avg_ratio.is_finite() && avg_ratio > 0.0 {
-            (1.0 - avg_ratio) * 100.0
-        } else {
-            0.0
-        };
-        let median_saved = if median_ratio.is_finite() && median_ratio > 0.0 {
-            (1.0 - median_ratio) * 100.0
-        } else {
-            0.0
-        };
-
-        // Timing statistics
-        let mut encode_times: Vec<u128> = algo_results.iter().map(|r| r.encode_us).collect();
-        let mut decode_times: Vec<u128> = algo_results.iter().map(|r| r.decode_us).collect();
-        let avg_encode = encode_times.iter().sum::<u128>() / encode_times.len() as u128;
-        let avg_decode = decode_times.iter().sum::<u128>() / decode_times.len() as u128;
-        let median_encode = median_u128(&mut encode_times);
-        let median_decode = median_u128(&mut decode_times);
-
-        report.push_str("| Metric | Average | Median |\n");
-        report.push_str("|--------|---------|--------|\n");
-        report.push_str(&format!(
-            "| Delta Size | {} bytes | {} bytes |\n",
-            avg_delta_size, median_delta_size
-        ));
-        report.push_str(&format!(
-            "| Compression Ratio | {:.4} | {:.4} |\n",
-            avg_ratio, median_ratio
-        ));
-        report.push_str(&format!(
-            "| Space Saved | {:.2}% | {:.2}% |\n",
-            avg_saved, median_saved
-        ));
-        report.push_str(&format!(
-            "| Encode Time | {} µs | {} µs |\n",
-            avg_encode, median_encode
-        ));
-        report.push_str(&format!(
-            "| Decode Time | {} µs | {} µs |\n\n",
-            avg_decode, median_decode
-        ));
-    }
-
-    // Tag optimization analysis
-    report.push_str("\n## 💡 Tag Optimization Impact\n\n");
-
-    let seq_results: Vec<_> = results
-        .iter()
-        .filter(|r| r.algorithm == "xpatch_sequential" && r.verified)
-        .collect();
-    let tags_results: Vec<_> = results
-        .iter()
-        .filter(|r| r.algorithm == "xpatch_tags" && r.verified)
-        .collect();
-
-    if !seq_results.is_empty() && !tags_results.is_empty() {
-        let seq_ratio =
-            seq_results.iter().map(|r| r.compression_ratio).sum::<f64>() / seq_results.len() as f64;
-        let tags_ratio = tags_results
-            .iter()
-            .map(|r| r.compression_ratio)
-            .sum::<f64>()
-            / tags_results.len() as f64;
-
-        // Calculate median ratios
-        let mut seq_ratios: Vec<f64> = seq_results.iter().map(|r| r.compression_ratio).collect();
-        let mut tags_ratios: Vec<f64> = tags_results.iter().map(|r| r.compression_ratio).collect();
-        let seq_median = median(&mut seq_ratios);
-        let tags_median = median(&mut tags_ratios);
-
-        if seq_ratio.is_finite() && tags_ratio.is_finite() && seq_ratio > 0.0 {
-            let avg_improvement = ((seq_ratio - tags_ratio) / seq_ratio) * 100.0;
-            let median_improvement = if seq_median > 0.0 {
-                ((seq_median - tags_median) / seq_median) * 100.0
-            } else {
-                0.0
-            };
-
-            report.push_str(&format!(
-                "**Average:** Tags provide **{:.1}%** better compression than sequential mode.\n\n",
-                avg_improvement
-            ));
-
-            report.push_str(&format!(
-                "**Median:** Tags provide **{:.1}%** better compression than sequential mode.\n\n",
-                median_improvement
-            ));
-
-            // Tag usage statistics
-            let mut tag_values: Vec<usize> =
-                tags_results.iter().filter_map(|r| r.tag_used).collect();
-            let mut base_distances: Vec<usize> = tags_results
-                .iter()
-                .filter_map(|r| r.tag_base_distance)
-                .collect();
-
-            let avg_tag = tag_values.iter().sum::<usize>() as f64 / tag_values.len() as f64;
-            let avg_base_distance =
-                base_distances.iter().sum::<usize>() as f64 / base_distances.len() as f64;
-            let median_tag = median_usize(&mut tag_values);
-            let median_base_distance = median_usize(&mut base_distances);
-
-            report.push_str(&format!("**Tag Statistics:**\n"));
-            report.push_str(&format!(
-                "- Average tag value: {:.1} (median: {})\n",
-                avg_tag, median_tag
-            ));
-            report.push_str(&format!(
-                "- Average base distance: {:.1} commits back (median: {})\n\n",
-                avg_base_distance, median_base_distance
-            ));
-        } else {
-            report.push_str("*Insufficient data for tag optimization analysis*\n\n");
-        }
-    }
-
-    report.push_str("---\n");
-    report.push_str(
-        "\n*Commits processed in chronological order (oldest→newest). Run with different repositories and XPATCH_MAX_TAG_DEPTH to explore optimization*\n",
-    );
-
-    fs::write(output_path, report)?;
-    println!("✅ Report saved to: {}", output_path.display());
-
-    Ok(())
-}
-
-fn generate_json_report(
+fn write_reports(
     results: Vec<BenchmarkResult>,
     hardware: HardwareInfo,
     early_termination: bool,
-    output_path: &Path,
+    markdown_path: &Path,
+    json_path: &Path,
 ) -> Result<()> {
-    let report = Report {
-        generated_at: chrono::Local::now().to_rfc3339(),
+    let mut report = Report {
+        generated_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
         hardware,
         results,
         early_termination,
     };
 
-    let json = serde_json::to_string_pretty(&report)?;
-    fs::write(output_path, json)?;
-    println!("✅ JSON saved to: {}", output_path.display());
+    fs::write(markdown_path, report.to_markdown())?;
+    println!("✅ Report saved to: {}", markdown_path.display());
+
+    report.generated_at = chrono::Local::now().to_rfc3339();
+    fs::write(json_path, report.to_json())?;
+    println!("✅ JSON saved to: {}", json_path.display());
 
     Ok(())
 }
@@ -1169,6 +549,7 @@ struct Config {
     output: PathBuf,
     cache_dir: Option<PathBuf>,
     build_cache: bool,
+    #[allow(dead_code)]
     use_cache: bool,
     max_tag_depth: usize,
     all_files_head: bool,
@@ -1287,10 +668,10 @@ impl Config {
         );
         println!();
         println!("Examples:");
-        println!("  XPATCH_PRESET=tokio cargo bench --bench git_real_world");
+        println!("  XPATCH_PRESET=tokio cargo bench --features bench --bench git_real_world");
         println!("  XPATCH_PRESET=tokio XPATCH_MAX_COMMITS=0 XPATCH_ALL_FILES=true \\");
         println!(
-            "    XPATCH_BUILD_CACHE=true XPATCH_CACHE_DIR=./cache cargo bench --bench git_real_world"
+            "    XPATCH_BUILD_CACHE=true XPATCH_CACHE_DIR=./cache cargo bench --features bench --bench git_real_world"
         );
     }
 }
@@ -1340,7 +721,7 @@ fn run_git_benchmark(config: Config) -> Result<()> {
     let (repo_url, repo_name, predefined_files) = if let Some(url) = config.repo {
         let name = url
             .split('/')
-            .last()
+            .next_back()
             .unwrap_or("repo")
             .trim_end_matches(".git")
             .to_string();
@@ -1403,7 +784,7 @@ fn run_git_benchmark(config: Config) -> Result<()> {
         #[cfg(feature = "vcdiff")]
         Box::new(VcdiffAlgo),
         #[cfg(feature = "gdelta")]
-        Box::new(GdeltaAlgo),
+        Box::new(Gdelta),
     ];
 
     log::info!("🔍 Benchmarking with {} algorithms", algos.len());
@@ -1443,7 +824,6 @@ fn run_git_benchmark(config: Config) -> Result<()> {
                 &repo_name,
                 file_path,
                 config.max_commits,
-                config.max_tag_depth,
                 config.min_file_size,
                 &algos,
             ) {
@@ -1469,7 +849,6 @@ fn run_git_benchmark(config: Config) -> Result<()> {
                 &repo_name,
                 file_path,
                 config.max_commits,
-                config.max_tag_depth,
                 config.min_file_size,
                 &algos,
             ) {
@@ -1524,8 +903,13 @@ fn run_git_benchmark(config: Config) -> Result<()> {
     let report_md = output_dir.join(format!("report_{}.md", timestamp));
     let report_json = output_dir.join(format!("report_{}.json", timestamp));
 
-    generate_markdown_report(&results, &hardware, early_termination, &report_md)?;
-    generate_json_report(results, hardware, early_termination, &report_json)?;
+    write_reports(
+        results,
+        hardware,
+        early_termination,
+        &report_md,
+        &report_json,
+    )?;
 
     Ok(())
 }
@@ -1533,7 +917,7 @@ fn run_git_benchmark(config: Config) -> Result<()> {
 fn build_cache(
     repo: &Repository,
     cache: &Arc<Mutex<Cache>>,
-    repo_name: &str,
+    _repo_name: &str,
     files: &[String],
     max_commits: usize,
 ) -> Result<()> {
@@ -0,0 +1,119 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Small-Input Benchmark: Config Blobs and Cache Values
+//!
+//! Encodes and decodes JSON documents of 16 bytes to 4 KiB with one value changed, the
+//! workload of key-value stores that keep values as deltas. At these sizes the cost is
+//! dominated by per-delta setup rather than by the data, so the summary estimates that fixed
+//! overhead from the smallest input, which should stay under a microsecond. The data adds to
+//! it, so a whole 4 KiB encode takes around a microsecond, depending on the machine.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use std::time::Instant;
+use xpatch::delta;
+
+const SIZES: [usize; 5] = [16, 64, 256, 1024, 4096];
+
+/// A JSON object of exactly `size` bytes, and a copy with one value changed.
+fn config_blob(size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut base = String::from("{");
+    for i in 0.. {
+        if base.len() >= size {
+            break;
+        }
+        base.push_str(&format!("\"setting_{i}\":{},", i * 37 % 1000));
+    }
+    let mut base = base.into_bytes();
+    base.truncate(size - 1);
+    base.push(b'}');
+
+    let mut new = base.clone();
+    let at = size / 2;
+    new[at] = if new[at] == b'7' { b'8' } else { b'7' };
+    (base, new)
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_inputs/encode");
+    for size in SIZES {
+        let (base, new) = config_blob(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| delta::encode(0, black_box(&base), black_box(&new), true));
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_inputs/decode");
+    for size in SIZES {
+        let (base, new) = config_blob(size);
+        let patch = delta::encode(0, &base, &new, true);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| delta::decode(black_box(&base), black_box(&patch)).unwrap());
+        });
+    }
+    group.finish();
+}
+
+/// Prints the encode time per size, and the fixed overhead as the time for the smallest input.
+fn print_summary(_: &mut Criterion) {
+    const ITERATIONS: u32 = 100_000;
+
+    println!("\n{:>8} {:>12} {:>8}", "size", "encode", "delta");
+    let mut overhead = None;
+    for size in SIZES {
+        let (base, new) = config_blob(size);
+        let patch = delta::encode(0, &base, &new, true);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+
+        // Best of three runs, to discount other load on the machine
+        let per_encode = (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..ITERATIONS {
+                    black_box(delta::encode(0, black_box(&base), black_box(&new), true));
+                }
+                start.elapsed() / ITERATIONS
+            })
+            .min()
+            .unwrap();
+        overhead.get_or_insert(per_encode);
+        println!("{size:>8} {per_encode:>12.2?} {:>8}", patch.len());
+    }
+
+    let overhead = overhead.unwrap();
+    let verdict = if overhead.as_nanos() < 1_000 {
+        "under"
+    } else {
+        "OVER"
+    };
+    println!(
+        "Fixed overhead: {overhead:.2?} per encode at {} B ({verdict} the 1 µs target)\n",
+        SIZES[0]
+    );
+}
+
+criterion_group!(benches, bench_encode, bench_decode, print_summary);
+criterion_main!(benches);
@@ -44,6 +44,7 @@ struct TestResult {
     scenario: String,
     format: String,
     size: usize,
+    #[allow(dead_code)]
     delta_size: usize,
     compression_ratio: f64,
     encode_us: u128,
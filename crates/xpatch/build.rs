@@ -0,0 +1,54 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generates the client and server of the gRPC patch service (`proto/xpatch.proto`).
+///
+/// The messages are declared by hand in `src/grpc.rs`, so building does not need `protoc`.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route_name: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("super::{}Request", route_name))
+            .output_type(format!("super::{}Response", route_name))
+            .codec_path("tonic_prost::ProstCodec")
+            .build()
+    };
+
+    let service = Service::builder()
+        .name("PatchService")
+        .package("xpatch.v1")
+        .method(method("get_patch", "GetPatch"))
+        .method(method("get_signature", "GetSignature"))
+        .method(method("upload_version", "UploadVersion"))
+        .build();
+
+    Builder::new().compile(&[service]);
+}
@@ -0,0 +1,292 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Incremental updates between `.xpk` packs.
+//!
+//! A mirror that already has one release of a pack shouldn't have to download the next one in
+//! full. [`diff_packs`] writes a pack of deltas from an old pack to a new one, and
+//! [`apply_pack_diff`] turns the old pack and that diff back into the new pack.
+//!
+//! The diff has one entry per entry of the new pack, in the same order and with the same name,
+//! tag, hash and size:
+//!
+//! - deltas against another entry of the new pack are copied as they are
+//! - other content the old pack already has is a delta against itself, a few bytes long
+//! - snapshots of new content become deltas against the old entry of the same name, when that
+//!   is smaller
+//!
+//! Entries whose base is in the old pack are external deltas, so the diff can also be read on
+//! its own with [`PackReader::get_with_base`]. The rebuilt pack holds the same entries as the
+//! new pack, but is not necessarily identical byte for byte: an entry whose base was dropped
+//! from the new pack is stored as a snapshot.
+//!
+//! # Example
+//!
+//! ```
+//! use std::io::Cursor;
+//! use xpatch::archive::{apply_pack_diff, diff_packs};
+//! use xpatch::pack::{PackReader, PackWriter};
+//!
+//! let mut old = PackWriter::new(Vec::new(), false)?;
+//! old.add_snapshot("app.txt", 1, b"release one of the application")?;
+//! let old = old.finish()?;
+//!
+//! let mut new = PackWriter::new(Vec::new(), false)?;
+//! let v2 = new.add_snapshot("app.txt", 2, b"release two of the application")?;
+//! let new = new.finish()?;
+//!
+//! let mut old = PackReader::new(Cursor::new(old))?;
+//! let mut new = PackReader::new(Cursor::new(new))?;
+//! let diff = diff_packs(&mut old, &mut new, Vec::new(), false)?;
+//!
+//! let mut diff = PackReader::new(Cursor::new(diff))?;
+//! let rebuilt = apply_pack_diff(&mut old, &mut diff, Vec::new(), false)?;
+//! let mut rebuilt = PackReader::new(Cursor::new(rebuilt))?;
+//! assert_eq!(rebuilt.get(&v2)?, b"release two of the application");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::delta;
+use crate::pack::{PackEntry, PackReader, PackWriter};
+use crate::store::ContentHash;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Seek, Write};
+
+/// Writes a pack of deltas that updates `old` to `new` into `out` and returns it.
+///
+/// Read it with [`apply_pack_diff`] and the same old pack. `enable_zstd` is passed on to the
+/// deltas encoded along the way.
+pub fn diff_packs<A, B, W>(
+    old: &mut PackReader<A>,
+    new: &mut PackReader<B>,
+    out: W,
+    enable_zstd: bool,
+) -> io::Result<W>
+where
+    A: Read + Seek,
+    B: Read + Seek,
+    W: Write,
+{
+    let mut writer = PackWriter::new(out, enable_zstd)?;
+    let by_name: HashMap<String, ContentHash> = old
+        .entries()
+        .iter()
+        .filter(|entry| !entry.name.is_empty())
+        .map(|entry| (entry.name.clone(), entry.hash))
+        .collect();
+
+    for entry in new.entries().to_vec() {
+        // Content the old pack has: a delta against itself only records its size. Deltas
+        // within the new pack are usually smaller than a snapshot the old pack stored under a
+        // different tag, so those are copied below.
+        let internal = entry.base.is_some() && !entry.external;
+        if !internal
+            && old.entry(&entry.hash).is_some()
+            && let Some(data) = readable(old.get(&entry.hash))?
+        {
+            let delta = delta::encode(entry.tag, &data, &data, false);
+            writer.add_external(&entry.name, entry.hash, entry.hash, entry.size, &delta)?;
+            continue;
+        }
+
+        let raw = new.read_raw(&entry.hash)?;
+        match entry.base {
+            Some(base) if entry.external => {
+                writer.add_external(&entry.name, entry.hash, base, entry.size, &raw)?
+            }
+            Some(base) => {
+                writer.add_encoded(&entry.name, entry.hash, Some(base), entry.size, &raw)?
+            }
+            None => {
+                let previous = match by_name.get(&entry.name) {
+                    Some(hash) => readable(old.get(hash))?.map(|data| (*hash, data)),
+                    None => None,
+                };
+                let delta = match previous {
+                    Some((hash, base)) => {
+                        let data = new.get(&entry.hash)?;
+                        let delta = delta::encode(entry.tag, &base, &data, enable_zstd);
+                        (delta.len() < raw.len()).then_some((hash, delta))
+                    }
+                    None => None,
+                };
+                match delta {
+                    Some((base, delta)) => {
+                        writer.add_external(&entry.name, entry.hash, base, entry.size, &delta)?
+                    }
+                    None => writer.add_encoded(&entry.name, entry.hash, None, entry.size, &raw)?,
+                }
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Rebuilds the new pack from `old` and a `diff` written by [`diff_packs`] into `out` and
+/// returns it.
+///
+/// Every entry of the diff becomes an entry of the result, checked against its hash when it has
+/// to be decoded. `enable_zstd` is passed on to entries stored as snapshots again.
+pub fn apply_pack_diff<A, B, W>(
+    old: &mut PackReader<A>,
+    diff: &mut PackReader<B>,
+    out: W,
+    enable_zstd: bool,
+) -> io::Result<W>
+where
+    A: Read + Seek,
+    B: Read + Seek,
+    W: Write,
+{
+    let mut writer = PackWriter::new(out, enable_zstd)?;
+    for entry in diff.entries().to_vec() {
+        let raw = diff.read_raw(&entry.hash)?;
+        match entry.base {
+            Some(base) if entry.external && old.entry(&base).is_some() => {
+                if base == entry.hash {
+                    copy_entry(old, &mut writer, &entry, enable_zstd)?;
+                } else if writer.contains(&base) {
+                    writer.add_encoded(&entry.name, entry.hash, Some(base), entry.size, &raw)?;
+                } else {
+                    // The base was dropped from the new pack
+                    let data = diff.get_with_base(&entry.hash, &old.get(&base)?)?;
+                    let delta = delta::encode(entry.tag, &[], &data, enable_zstd);
+                    writer.add_encoded(&entry.name, entry.hash, None, entry.size, &delta)?;
+                }
+            }
+            // External in the new pack as well
+            Some(base) if entry.external => {
+                writer.add_external(&entry.name, entry.hash, base, entry.size, &raw)?
+            }
+            base => writer.add_encoded(&entry.name, entry.hash, base, entry.size, &raw)?,
+        }
+    }
+    writer.finish()
+}
+
+/// Adds the old pack's entry for `entry.hash` under the diff entry's name and tag, copying its
+/// delta when the base is already in `writer`.
+fn copy_entry<R, W>(
+    old: &mut PackReader<R>,
+    writer: &mut PackWriter<W>,
+    entry: &PackEntry,
+    enable_zstd: bool,
+) -> io::Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let source = old.entry(&entry.hash).expect("checked by caller").clone();
+    let copyable = source.tag == entry.tag
+        && !source.external
+        && source.base.is_none_or(|base| writer.contains(&base));
+    if copyable {
+        let raw = old.read_raw(&entry.hash)?;
+        return writer.add_encoded(&entry.name, entry.hash, source.base, entry.size, &raw);
+    }
+    let data = old.get(&entry.hash)?;
+    let delta = delta::encode(entry.tag, &[], &data, enable_zstd);
+    writer.add_encoded(&entry.name, entry.hash, None, entry.size, &delta)
+}
+
+/// Turns the error of an entry built on external content into `None`.
+fn readable(result: io::Result<Vec<u8>>) -> io::Result<Option<Vec<u8>>> {
+    match result {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn text(seed: usize, edit: &str) -> Vec<u8> {
+        let mut data: String = (0..200)
+            .map(|i| format!("line {} of file {}\n", i, seed))
+            .collect();
+        data.insert_str(data.len() / 2, edit);
+        data.into_bytes()
+    }
+
+    #[test]
+    fn test_diff_and_apply() {
+        let readme = text(1, "");
+        let (app_v1, app_v2, app_v3) = (text(2, "v1"), text(2, "v2"), text(2, "v3"));
+        let (notes_v1, notes_v2) = (text(3, "first"), text(3, "second"));
+        let installed = text(4, "installed");
+        let updated = text(4, "updated");
+
+        let mut old = PackWriter::new(Vec::new(), false).unwrap();
+        old.add_snapshot("README", 0, &readme).unwrap();
+        old.add_snapshot("app", 1, &app_v1).unwrap();
+        old.add_delta("app", 2, &app_v1, &app_v2).unwrap();
+        old.add_snapshot("notes", 1, &notes_v1).unwrap();
+        old.add_snapshot("removed", 0, &text(5, "")).unwrap();
+        let old = old.finish().unwrap();
+
+        let mut new = PackWriter::new(Vec::new(), false).unwrap();
+        new.add_snapshot("README", 0, &readme).unwrap();
+        new.add_snapshot("app", 2, &app_v2).unwrap();
+        new.add_delta("app", 3, &app_v2, &app_v3).unwrap();
+        new.add_snapshot("notes", 2, &notes_v2).unwrap();
+        new.add_snapshot("added", 0, &text(6, "")).unwrap();
+        new.add_external(
+            "data",
+            ContentHash::of(&updated),
+            ContentHash::of(&installed),
+            updated.len() as u64,
+            &delta::encode(0, &installed, &updated, false),
+        )
+        .unwrap();
+        let new = new.finish().unwrap();
+
+        let mut old = PackReader::new(Cursor::new(old)).unwrap();
+        let mut new_reader = PackReader::new(Cursor::new(new.clone())).unwrap();
+        let diff = diff_packs(&mut old, &mut new_reader, Vec::new(), false).unwrap();
+        assert!(diff.len() < new.len() / 2);
+
+        let mut diff = PackReader::new(Cursor::new(diff)).unwrap();
+        assert_eq!(diff.len(), new_reader.len());
+        let notes = diff.entry(&ContentHash::of(&notes_v2)).unwrap();
+        assert_eq!(notes.base, Some(ContentHash::of(&notes_v1)));
+        assert!(notes.external);
+
+        let rebuilt = apply_pack_diff(&mut old, &mut diff, Vec::new(), false).unwrap();
+        let mut rebuilt = PackReader::new(Cursor::new(rebuilt)).unwrap();
+        rebuilt.verify().unwrap();
+        for (expected, actual) in new_reader.entries().iter().zip(rebuilt.entries()) {
+            assert_eq!(
+                (&expected.name, expected.tag, expected.hash, expected.size),
+                (&actual.name, actual.tag, actual.hash, actual.size)
+            );
+        }
+        assert_eq!(rebuilt.len(), new_reader.len());
+        for version in [&readme, &app_v2, &app_v3, &notes_v2] {
+            assert_eq!(rebuilt.get(&ContentHash::of(version)).unwrap(), *version);
+        }
+        let data = rebuilt
+            .get_with_base(&ContentHash::of(&updated), &installed)
+            .unwrap();
+        assert_eq!(data, updated);
+    }
+}
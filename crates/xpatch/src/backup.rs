@@ -0,0 +1,1111 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Incremental, deduplicated directory backups.
+//!
+//! A [`BackupRepo`] stores snapshots of directory trees. Files are split into chunks with
+//! content-defined chunking (CDC), so an insertion only changes the chunks around it, and each
+//! chunk is stored once, keyed by its SHA-256. A chunk that is new but replaces a chunk of the
+//! previous snapshot is stored as a delta against it, so small edits inside large chunks stay
+//! small too. Files whose size and modification time did not change since the previous
+//! snapshot of the same directory are not read again.
+//!
+//! [`BackupRepo::prune`] deletes old snapshots along with every chunk only they used.
+//!
+//! # Layout
+//!
+//! ```text
+//! <root>/snapshots/<id>         snapshot manifest (text)
+//! <root>/chunks/<xx>/<hash>     chunk: [chain length u8][base hash if chained][delta]
+//! ```
+//!
+//! Chunks are written before the manifest that references them, so an interrupted snapshot
+//! leaves only unreferenced chunks, which the next [`prune`](BackupRepo::prune) removes. The
+//! repository assumes a single writer.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::backup::BackupRepo;
+//!
+//! # let tmp = std::env::temp_dir().join(format!("xpatch-backup-doc-{}", std::process::id()));
+//! # let _ = std::fs::remove_dir_all(&tmp);
+//! # let (source, repo_dir, restored) = (tmp.join("src"), tmp.join("repo"), tmp.join("out"));
+//! # std::fs::create_dir_all(&source)?;
+//! std::fs::write(source.join("notes.txt"), "first draft")?;
+//!
+//! let mut repo = BackupRepo::open(&repo_dir)?;
+//! let first = repo.snapshot(&source)?.snapshot;
+//! std::fs::write(source.join("notes.txt"), "second draft")?;
+//! repo.snapshot(&source)?;
+//!
+//! repo.restore(&first.id, &restored)?;
+//! assert_eq!(std::fs::read_to_string(restored.join("notes.txt"))?, "first draft");
+//!
+//! // Keep only the latest snapshot
+//! assert_eq!(repo.prune(1)?.snapshots_removed, 1);
+//! # std::fs::remove_dir_all(&tmp)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::delta::{self, EncodeOptions};
+use crate::store::{ContentHash, write_atomic};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+const CHUNKS_DIR: &str = "chunks";
+const MANIFEST_HEADER: &str = "xpatch-snapshot 1";
+
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// Mask over the top bits of the gear hash, giving 8 KiB chunks on average.
+const CHUNK_MASK: u64 = !0 << (64 - 13);
+/// Maximum number of deltas between a chunk and a standalone chunk.
+const MAX_CHAIN_LENGTH: u8 = 8;
+
+/// Random values for the gear hash, generated with SplitMix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// A stored snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Identifier (16 hex digits)
+    pub id: String,
+    /// Creation time
+    pub time: SystemTime,
+    /// Directory the snapshot was taken of
+    pub source: PathBuf,
+    /// Number of regular files
+    pub files: u64,
+    /// Total size of all files in bytes
+    pub size: u64,
+}
+
+/// Result of [`BackupRepo::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotStats {
+    /// The new snapshot
+    pub snapshot: Snapshot,
+    /// Files read, as opposed to taken unchanged from the previous snapshot
+    pub files_read: u64,
+    /// Chunks that were not yet stored
+    pub new_chunks: u64,
+    /// Bytes written for the new chunks
+    pub stored_size: u64,
+}
+
+/// Result of [`BackupRepo::prune`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneStats {
+    /// Number of deleted snapshots
+    pub snapshots_removed: usize,
+    /// Number of deleted chunks
+    pub chunks_removed: usize,
+    /// Bytes freed by deleting chunks
+    pub bytes_freed: u64,
+}
+
+/// A chunk of a file: its hash and length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChunkRef {
+    hash: ContentHash,
+    len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryKind {
+    Dir,
+    File {
+        mtime: u64,
+        size: u64,
+        chunks: Vec<ChunkRef>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+/// A manifest entry. Paths are relative to the snapshot root and use `/` as separator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    path: String,
+    mode: u32,
+    kind: EntryKind,
+}
+
+/// A directory of deduplicated snapshots.
+///
+/// See the [module documentation](self) for the storage model.
+pub struct BackupRepo {
+    root: PathBuf,
+    options: EncodeOptions,
+}
+
+impl BackupRepo {
+    /// Opens the repository at `root`, creating it if it does not exist.
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(SNAPSHOTS_DIR))?;
+        fs::create_dir_all(root.join(CHUNKS_DIR))?;
+        Ok(Self {
+            root,
+            options: EncodeOptions::default(),
+        })
+    }
+
+    /// Returns the repository's root directory.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Takes a snapshot of the directory `source`.
+    ///
+    /// Regular files, directories and (on Unix) symbolic links are recorded; other file types
+    /// are skipped, as is the repository itself if it lies inside `source`. Files are compared
+    /// against the latest snapshot of the same directory.
+    pub fn snapshot(&mut self, source: impl AsRef<Path>) -> io::Result<SnapshotStats> {
+        let source = fs::canonicalize(source)?;
+        let parent = match self.latest_of(&source)? {
+            Some(snapshot) => self.read_manifest(&snapshot.id)?.1,
+            None => Vec::new(),
+        };
+        let parent: HashMap<&str, &Entry> = parent.iter().map(|e| (e.path.as_str(), e)).collect();
+
+        let mut stats = SnapshotStats {
+            snapshot: Snapshot {
+                id: String::new(),
+                time: SystemTime::now(),
+                source: source.clone(),
+                files: 0,
+                size: 0,
+            },
+            files_read: 0,
+            new_chunks: 0,
+            stored_size: 0,
+        };
+        let skip = fs::canonicalize(&self.root)?;
+        let mut entries = Vec::new();
+        self.walk(&source, &source, &skip, &parent, &mut entries, &mut stats)?;
+
+        let time = stats
+            .snapshot
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut manifest = format!(
+            "{}\ntime {}.{:09}\nsource {}\n",
+            MANIFEST_HEADER,
+            time.as_secs(),
+            time.subsec_nanos(),
+            escape(&source.to_string_lossy())
+        );
+        for entry in &entries {
+            manifest.push_str(&format_entry(entry));
+            manifest.push('\n');
+        }
+
+        let id = ContentHash::of(manifest.as_bytes()).to_string()[..16].to_string();
+        write_atomic(
+            &self.root.join(SNAPSHOTS_DIR).join(&id),
+            manifest.as_bytes(),
+        )?;
+        stats.snapshot.id = id;
+        Ok(stats)
+    }
+
+    /// Lists all snapshots, oldest first.
+    pub fn snapshots(&self) -> io::Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        for dir_entry in fs::read_dir(self.root.join(SNAPSHOTS_DIR))? {
+            let name = dir_entry?.file_name();
+            let Some(id) = name.to_str().filter(|id| is_snapshot_id(id)) else {
+                continue;
+            };
+            snapshots.push(self.read_manifest(id)?.0);
+        }
+        snapshots.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(snapshots)
+    }
+
+    /// Finds a snapshot by id, unique id prefix, or `"latest"`.
+    pub fn find(&self, id: &str) -> io::Result<Snapshot> {
+        let snapshots = self.snapshots()?;
+        if id == "latest" {
+            return snapshots
+                .into_iter()
+                .next_back()
+                .ok_or_else(|| not_found("No snapshots"));
+        }
+
+        let mut matches = snapshots.into_iter().filter(|s| s.id.starts_with(id));
+        match (matches.next(), matches.next()) {
+            (Some(snapshot), None) if !id.is_empty() => Ok(snapshot),
+            (Some(_), _) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Ambiguous snapshot id",
+            )),
+            (None, _) => Err(not_found("Snapshot not found")),
+        }
+    }
+
+    /// Restores a snapshot (see [`find`](Self::find)) into the directory `target`.
+    ///
+    /// Existing files in `target` are overwritten; other files are left alone. Permissions
+    /// and modification times are restored, and every chunk is checked against its hash.
+    pub fn restore(&self, id: &str, target: impl AsRef<Path>) -> io::Result<Snapshot> {
+        let snapshot = self.find(id)?;
+        let (_, entries) = self.read_manifest(&snapshot.id)?;
+        let target = target.as_ref();
+        fs::create_dir_all(target)?;
+
+        let mut cache = HashMap::new();
+        let mut dirs = Vec::new();
+        for entry in &entries {
+            let path = target.join(&entry.path);
+            match &entry.kind {
+                EntryKind::Dir => {
+                    fs::create_dir_all(&path)?;
+                    dirs.push((path, entry.mode));
+                }
+                EntryKind::File { mtime, chunks, .. } => {
+                    remove_existing(&path)?;
+                    let mut file = File::create(&path)?;
+                    for chunk in chunks {
+                        file.write_all(&self.read_chunk(&chunk.hash, &mut cache)?)?;
+                    }
+                    file.set_modified(UNIX_EPOCH + Duration::from_nanos(*mtime))?;
+                    drop(file);
+                    set_mode(&path, entry.mode)?;
+                }
+                EntryKind::Symlink { target } => {
+                    remove_existing(&path)?;
+                    create_symlink(target, &path)?;
+                }
+            }
+            // Bound the memory held by decoded chunks
+            if cache.len() > 64 {
+                cache.clear();
+            }
+        }
+        // Directory permissions last, in case they make a directory read-only
+        for (path, mode) in dirs.iter().rev() {
+            set_mode(path, *mode)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Deletes all but the `keep_last` most recent snapshots, then every chunk no remaining
+    /// snapshot needs.
+    pub fn prune(&mut self, keep_last: usize) -> io::Result<PruneStats> {
+        let snapshots = self.snapshots()?;
+        let remove = snapshots.len().saturating_sub(keep_last);
+        let mut stats = PruneStats::default();
+        for snapshot in &snapshots[..remove] {
+            fs::remove_file(self.root.join(SNAPSHOTS_DIR).join(&snapshot.id))?;
+            stats.snapshots_removed += 1;
+        }
+
+        // Live chunks are those referenced by a snapshot, plus the bases they are stored against
+        let mut live = HashSet::new();
+        for snapshot in &snapshots[remove..] {
+            for entry in self.read_manifest(&snapshot.id)?.1 {
+                if let EntryKind::File { chunks, .. } = entry.kind {
+                    live.extend(chunks.iter().map(|chunk| chunk.hash));
+                }
+            }
+        }
+        let mut pending: Vec<ContentHash> = live.iter().copied().collect();
+        while let Some(hash) = pending.pop() {
+            if let (_, Some(base)) = self.chunk_header(&hash)?
+                && live.insert(base)
+            {
+                pending.push(base);
+            }
+        }
+
+        for prefix in fs::read_dir(self.root.join(CHUNKS_DIR))? {
+            let prefix = prefix?;
+            if !prefix.file_type()?.is_dir() {
+                continue;
+            }
+            for chunk in fs::read_dir(prefix.path())? {
+                let chunk = chunk?;
+                let hash = chunk
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse().ok());
+                if hash.is_some_and(|hash| !live.contains(&hash)) {
+                    stats.bytes_freed += chunk.metadata()?.len();
+                    fs::remove_file(chunk.path())?;
+                    stats.chunks_removed += 1;
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    // Internal helpers
+
+    fn latest_of(&self, source: &Path) -> io::Result<Option<Snapshot>> {
+        Ok(self
+            .snapshots()?
+            .into_iter()
+            .rev()
+            .find(|snapshot| snapshot.source == source))
+    }
+
+    fn walk(
+        &self,
+        root: &Path,
+        dir: &Path,
+        skip: &Path,
+        parent: &HashMap<&str, &Entry>,
+        entries: &mut Vec<Entry>,
+        stats: &mut SnapshotStats,
+    ) -> io::Result<()> {
+        let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+
+        for child in children {
+            let path = child.path();
+            let metadata = fs::symlink_metadata(&path)?;
+            let relative = relative_path(root, &path)?;
+            let mode = file_mode(&metadata);
+
+            if metadata.is_dir() {
+                if path == skip {
+                    continue;
+                }
+                entries.push(Entry {
+                    path: relative,
+                    mode,
+                    kind: EntryKind::Dir,
+                });
+                self.walk(root, &path, skip, parent, entries, stats)?;
+            } else if metadata.is_file() {
+                let mtime = metadata
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64);
+                let size = metadata.len();
+                let previous = match parent.get(relative.as_str()).map(|e| &e.kind) {
+                    Some(EntryKind::File {
+                        mtime: prev_mtime,
+                        size: prev_size,
+                        chunks,
+                    }) => Some((*prev_mtime == mtime && *prev_size == size, chunks)),
+                    _ => None,
+                };
+
+                let chunks = match previous {
+                    Some((true, chunks)) => chunks.clone(),
+                    _ => {
+                        stats.files_read += 1;
+                        let base = previous.map_or(&[][..], |(_, chunks)| &chunks[..]);
+                        self.store_file(&path, base, stats)?
+                    }
+                };
+                let size = chunks.iter().map(|chunk| chunk.len).sum();
+                stats.snapshot.files += 1;
+                stats.snapshot.size += size;
+                entries.push(Entry {
+                    path: relative,
+                    mode,
+                    kind: EntryKind::File {
+                        mtime,
+                        size,
+                        chunks,
+                    },
+                });
+            } else if metadata.is_symlink() {
+                let target = fs::read_link(&path)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| invalid_data(format!("Non UTF-8 path: {}", target.display())))?;
+                entries.push(Entry {
+                    path: relative,
+                    mode,
+                    kind: EntryKind::Symlink {
+                        target: target.to_string(),
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Chunks and stores a file. `previous` are the chunks of the file's previous version,
+    /// used as delta bases for new chunks at the same offset.
+    fn store_file(
+        &self,
+        path: &Path,
+        previous: &[ChunkRef],
+        stats: &mut SnapshotStats,
+    ) -> io::Result<Vec<ChunkRef>> {
+        let mut file = File::open(path)?;
+        let mut buffer = Vec::with_capacity(2 * MAX_CHUNK_SIZE);
+        let mut chunks = Vec::new();
+        let mut offset = 0u64;
+        let mut eof = false;
+
+        loop {
+            while !eof && buffer.len() < MAX_CHUNK_SIZE {
+                let start = buffer.len();
+                buffer.resize(start + MAX_CHUNK_SIZE, 0);
+                let n = file.read(&mut buffer[start..])?;
+                buffer.truncate(start + n);
+                eof = n == 0;
+            }
+            if buffer.is_empty() {
+                break;
+            }
+
+            let len = chunk_boundary(&buffer);
+            let hash = ContentHash::of(&buffer[..len]);
+            if !self.chunk_path(&hash).exists() {
+                let base = base_chunk(previous, offset).filter(|base| base.hash != hash);
+                stats.stored_size += self.write_chunk(&hash, &buffer[..len], base)?;
+                stats.new_chunks += 1;
+            }
+            chunks.push(ChunkRef {
+                hash,
+                len: len as u64,
+            });
+            offset += len as u64;
+            buffer.drain(..len);
+        }
+        Ok(chunks)
+    }
+
+    /// Stores a chunk, as a delta against `base` if that is smaller, and returns the size written.
+    fn write_chunk(
+        &self,
+        hash: &ContentHash,
+        data: &[u8],
+        base: Option<&ChunkRef>,
+    ) -> io::Result<u64> {
+        let mut object = vec![0];
+        let mut encoded = delta::encode_with_options(0, &[], data, &self.options);
+
+        if let Some(base) = base
+            && let (chain_length, _) = self.chunk_header(&base.hash)?
+            && chain_length < MAX_CHAIN_LENGTH
+        {
+            let base_data = self.read_chunk(&base.hash, &mut HashMap::new())?;
+            let delta = delta::encode_with_options(0, &base_data, data, &self.options);
+            if delta.len() + base.hash.as_bytes().len() < encoded.len() {
+                object = vec![chain_length + 1];
+                object.extend_from_slice(base.hash.as_bytes());
+                encoded = delta;
+            }
+        }
+        object.extend(encoded);
+
+        let path = self.chunk_path(hash);
+        fs::create_dir_all(path.parent().unwrap())?;
+        write_atomic(&path, &object)?;
+        Ok(object.len() as u64)
+    }
+
+    /// Reads the chain length and base of a stored chunk.
+    fn chunk_header(&self, hash: &ContentHash) -> io::Result<(u8, Option<ContentHash>)> {
+        let mut file = File::open(self.chunk_path(hash))?;
+        let mut header = [0u8; 33];
+        file.read_exact(&mut header[..1])?;
+        if header[0] == 0 {
+            return Ok((0, None));
+        }
+        file.read_exact(&mut header[1..])?;
+        Ok((
+            header[0],
+            Some(ContentHash::from_bytes(header[1..].try_into().unwrap())),
+        ))
+    }
+
+    /// Reconstructs a chunk, checking its hash. Decoded bases are kept in `cache`.
+    fn read_chunk(
+        &self,
+        hash: &ContentHash,
+        cache: &mut HashMap<ContentHash, Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        if let Some(data) = cache.get(hash) {
+            return Ok(data.clone());
+        }
+
+        let object = fs::read(self.chunk_path(hash))?;
+        let (base, encoded) = match object.first() {
+            Some(0) => (Vec::new(), &object[1..]),
+            Some(_) if object.len() > 33 => {
+                let base = ContentHash::from_bytes(object[1..33].try_into().unwrap());
+                (self.read_chunk(&base, cache)?, &object[33..])
+            }
+            _ => return Err(invalid_data("Corrupt chunk")),
+        };
+        let data = delta::decode(&base, encoded).map_err(invalid_data)?;
+        if ContentHash::of(&data) != *hash {
+            return Err(invalid_data("Content hash mismatch"));
+        }
+        cache.insert(*hash, data.clone());
+        Ok(data)
+    }
+
+    fn chunk_path(&self, hash: &ContentHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.root.join(CHUNKS_DIR).join(&hex[..2]).join(hex)
+    }
+
+    fn read_manifest(&self, id: &str) -> io::Result<(Snapshot, Vec<Entry>)> {
+        let content = fs::read_to_string(self.root.join(SNAPSHOTS_DIR).join(id))?;
+        parse_manifest(id, &content).ok_or_else(|| invalid_data("Corrupt snapshot manifest"))
+    }
+}
+
+/// Returns the length of the first chunk of `data`, which holds at least [`MAX_CHUNK_SIZE`]
+/// bytes unless it is the end of the file.
+pub(crate) fn chunk_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, &byte) in data[..end].iter().enumerate().skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & CHUNK_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Finds the chunk of the previous version that covers `offset`.
+fn base_chunk(previous: &[ChunkRef], offset: u64) -> Option<&ChunkRef> {
+    let mut start = 0;
+    previous.iter().find(|chunk| {
+        start += chunk.len;
+        start > offset
+    })
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let path = escape(&entry.path);
+    match &entry.kind {
+        EntryKind::Dir => format!("D\t{:o}\t{}", entry.mode, path),
+        EntryKind::File {
+            mtime,
+            size,
+            chunks,
+        } => {
+            let chunks = if chunks.is_empty() {
+                "-".to_string()
+            } else {
+                chunks
+                    .iter()
+                    .map(|chunk| format!("{}:{}", chunk.hash, chunk.len))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            format!(
+                "F\t{:o}\t{}\t{}\t{}\t{}",
+                entry.mode, mtime, size, chunks, path
+            )
+        }
+        EntryKind::Symlink { target } => {
+            format!("L\t{:o}\t{}\t{}", entry.mode, escape(target), path)
+        }
+    }
+}
+
+fn parse_manifest(id: &str, content: &str) -> Option<(Snapshot, Vec<Entry>)> {
+    let mut lines = content.lines();
+    if lines.next()? != MANIFEST_HEADER {
+        return None;
+    }
+    let (secs, nanos) = lines.next()?.strip_prefix("time ")?.split_once('.')?;
+    let time = UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    let source = PathBuf::from(unescape(lines.next()?.strip_prefix("source ")?)?);
+
+    let mut snapshot = Snapshot {
+        id: id.to_string(),
+        time,
+        source,
+        files: 0,
+        size: 0,
+    };
+    let mut entries = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let mode = u32::from_str_radix(fields.get(1)?, 8).ok()?;
+        let (kind, path) = match (fields[0], fields.len()) {
+            ("D", 3) => (EntryKind::Dir, fields[2]),
+            ("F", 6) => {
+                let chunks = match fields[4] {
+                    "-" => Vec::new(),
+                    chunks => chunks
+                        .split(',')
+                        .map(|chunk| {
+                            let (hash, len) = chunk.split_once(':')?;
+                            Some(ChunkRef {
+                                hash: hash.parse().ok()?,
+                                len: len.parse().ok()?,
+                            })
+                        })
+                        .collect::<Option<_>>()?,
+                };
+                let size = fields[3].parse().ok()?;
+                snapshot.files += 1;
+                snapshot.size += size;
+                let kind = EntryKind::File {
+                    mtime: fields[2].parse().ok()?,
+                    size,
+                    chunks,
+                };
+                (kind, fields[5])
+            }
+            ("L", 4) => {
+                let target = unescape(fields[2])?;
+                (EntryKind::Symlink { target }, fields[3])
+            }
+            _ => return None,
+        };
+
+        let path = unescape(path)?;
+        // Never restore outside the target directory
+        if !Path::new(&path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        entries.push(Entry { path, mode, kind });
+    }
+    Some((snapshot, entries))
+}
+
+/// Escapes backslashes, tabs and newlines so a value fits in one manifest field.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> Option<String> {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        result.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            _ => return None,
+        });
+    }
+    Some(result)
+}
+
+fn relative_path(root: &Path, path: &Path) -> io::Result<String> {
+    let relative = path
+        .strip_prefix(root)
+        .map_err(|_| invalid_data("Path outside source"))?;
+    let parts = relative
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid_data(format!("Non UTF-8 path: {}", path.display())))?;
+    Ok(parts.join("/"))
+}
+
+fn is_snapshot_id(name: &str) -> bool {
+    name.len() == 16 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Removes a file or symlink at `path`, so it can be recreated.
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Directory in the way: {}", path.display()),
+        )),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        format!("Cannot restore symbolic link: {}", path.display()),
+    ))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn not_found(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "xpatch-backup-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn populate(source: &Path) {
+        fs::create_dir_all(source.join("docs/nested")).unwrap();
+        fs::create_dir_all(source.join("empty")).unwrap();
+        fs::write(source.join("big.bin"), pseudo_random(300_000, 1)).unwrap();
+        fs::write(source.join("docs/readme.txt"), "Hello, backup!").unwrap();
+        fs::write(source.join("docs/nested/tab\tname.txt"), "odd name").unwrap();
+        fs::write(source.join("empty.txt"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("docs/readme.txt", source.join("link")).unwrap();
+    }
+
+    /// Asserts that two directory trees hold the same files, links and modes.
+    fn assert_same_tree(expected: &Path, actual: &Path) {
+        let mut children: Vec<_> = fs::read_dir(expected)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        children.sort();
+        let mut actual_children: Vec<_> = fs::read_dir(actual)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        actual_children.sort();
+        assert_eq!(children, actual_children, "in {}", actual.display());
+
+        for name in children {
+            let (expected, actual) = (expected.join(&name), actual.join(&name));
+            let metadata = fs::symlink_metadata(&expected).unwrap();
+            let actual_metadata = fs::symlink_metadata(&actual).unwrap();
+            assert_eq!(file_mode(&metadata), file_mode(&actual_metadata));
+            if metadata.is_dir() {
+                assert_same_tree(&expected, &actual);
+            } else if metadata.is_symlink() {
+                assert_eq!(
+                    fs::read_link(&expected).unwrap(),
+                    fs::read_link(&actual).unwrap()
+                );
+            } else {
+                assert_eq!(fs::read(&expected).unwrap(), fs::read(&actual).unwrap());
+                assert_eq!(
+                    metadata.modified().unwrap(),
+                    actual_metadata.modified().unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_restore_roundtrip() {
+        let dir = temp_dir("roundtrip");
+        let source = dir.join("source");
+        populate(&source);
+        #[cfg(unix)]
+        set_mode(&source.join("docs/readme.txt"), 0o600).unwrap();
+
+        let mut repo = BackupRepo::open(dir.join("repo")).unwrap();
+        let stats = repo.snapshot(&source).unwrap();
+        assert_eq!(stats.snapshot.files, 4);
+        assert_eq!(stats.snapshot.size, 300_000 + 14 + 8);
+        assert_eq!(stats.files_read, 4);
+        assert!(stats.new_chunks > 10);
+
+        let restored = repo
+            .restore(&stats.snapshot.id, dir.join("restored"))
+            .unwrap();
+        assert_eq!(restored, stats.snapshot);
+        assert_same_tree(&source, &dir.join("restored"));
+
+        // Restoring over an existing tree replaces its files
+        fs::write(dir.join("restored/docs/readme.txt"), "changed").unwrap();
+        repo.restore("latest", dir.join("restored")).unwrap();
+        assert_same_tree(&source, &dir.join("restored"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_snapshots() {
+        let dir = temp_dir("incremental");
+        let source = dir.join("source");
+        populate(&source);
+        let mut repo = BackupRepo::open(dir.join("repo")).unwrap();
+        let first = repo.snapshot(&source).unwrap();
+
+        // Nothing changed: no file is read
+        let second = repo.snapshot(&source).unwrap();
+        assert_eq!(second.files_read, 0);
+        assert_eq!(second.new_chunks, 0);
+
+        // An insertion only affects the chunk around it, which is stored as a delta
+        let mut big = pseudo_random(300_000, 1);
+        big.splice(150_000..150_000, b"inserted".iter().copied());
+        fs::write(source.join("big.bin"), &big).unwrap();
+        let third = repo.snapshot(&source).unwrap();
+        assert_eq!(third.files_read, 1);
+        assert!(third.new_chunks <= 2, "{} new chunks", third.new_chunks);
+        assert!(
+            third.stored_size < 1_000,
+            "{} bytes stored",
+            third.stored_size
+        );
+
+        repo.restore(&first.snapshot.id, dir.join("first")).unwrap();
+        assert_eq!(
+            fs::read(dir.join("first/big.bin")).unwrap(),
+            pseudo_random(300_000, 1)
+        );
+        repo.restore(&third.snapshot.id, dir.join("third")).unwrap();
+        assert_eq!(fs::read(dir.join("third/big.bin")).unwrap(), big);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_repo_inside_source_is_skipped() {
+        let dir = temp_dir("inside");
+        fs::write(dir.join("file.txt"), "data").unwrap();
+        let mut repo = BackupRepo::open(dir.join(".backup")).unwrap();
+        repo.snapshot(&dir).unwrap();
+        let stats = repo.snapshot(&dir).unwrap();
+        assert_eq!(stats.snapshot.files, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = temp_dir("prune");
+        let source = dir.join("source");
+        fs::create_dir_all(&source).unwrap();
+        let mut repo = BackupRepo::open(dir.join("repo")).unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..3u64 {
+            fs::write(source.join("data.bin"), pseudo_random(100_000, i)).unwrap();
+            ids.push(repo.snapshot(&source).unwrap().snapshot.id);
+        }
+        assert_eq!(repo.prune(3).unwrap(), PruneStats::default());
+
+        let stats = repo.prune(1).unwrap();
+        assert_eq!(stats.snapshots_removed, 2);
+        assert!(stats.chunks_removed > 0);
+        assert!(stats.bytes_freed > 100_000);
+
+        let snapshots = repo.snapshots().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].id, ids[2]);
+        assert_eq!(repo.find(&ids[0]).unwrap_err().kind(), ErrorKind::NotFound);
+        repo.restore(&ids[2], dir.join("restored")).unwrap();
+        assert_eq!(
+            fs::read(dir.join("restored/data.bin")).unwrap(),
+            pseudo_random(100_000, 2)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_delta_bases() {
+        let dir = temp_dir("prune-bases");
+        let source = dir.join("source");
+        fs::create_dir_all(&source).unwrap();
+        let mut repo = BackupRepo::open(dir.join("repo")).unwrap();
+
+        let mut data = pseudo_random(50_000, 7);
+        fs::write(source.join("data.bin"), &data).unwrap();
+        repo.snapshot(&source).unwrap();
+        data[25_000] ^= 0xFF;
+        fs::write(source.join("data.bin"), &data).unwrap();
+        let latest = repo.snapshot(&source).unwrap();
+        assert!(latest.stored_size < 1_000);
+
+        // The changed chunk is a delta against a chunk only the first snapshot references
+        let stats = repo.prune(1).unwrap();
+        assert_eq!(stats.chunks_removed, 0);
+        repo.restore("latest", dir.join("restored")).unwrap();
+        assert_eq!(fs::read(dir.join("restored/data.bin")).unwrap(), data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = temp_dir("find");
+        let mut repo = BackupRepo::open(dir.join("repo")).unwrap();
+        assert_eq!(repo.find("latest").unwrap_err().kind(), ErrorKind::NotFound);
+
+        fs::create_dir_all(dir.join("a")).unwrap();
+        let id = repo.snapshot(dir.join("a")).unwrap().snapshot.id;
+        assert_eq!(repo.find(&id[..6]).unwrap().id, id);
+        assert_eq!(repo.find("latest").unwrap().id, id);
+        assert_eq!(repo.find("").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(repo.find("xyz").unwrap_err().kind(), ErrorKind::NotFound);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_boundaries() {
+        let data = pseudo_random(2_000_000, 3);
+        let mut sizes = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let len = chunk_boundary(&rest[..rest.len().min(MAX_CHUNK_SIZE)]);
+            sizes.push(len);
+            rest = &rest[len..];
+        }
+
+        let last = sizes.pop().unwrap();
+        assert!(last <= MAX_CHUNK_SIZE);
+        assert!(
+            sizes
+                .iter()
+                .all(|&len| (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&len))
+        );
+        let average = data.len() / (sizes.len() + 1);
+        assert!((6_000..14_000).contains(&average), "average {}", average);
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let entries = vec![
+            Entry {
+                path: "dir with\ttab\nnewline".to_string(),
+                mode: 0o755,
+                kind: EntryKind::Dir,
+            },
+            Entry {
+                path: "dir/file".to_string(),
+                mode: 0o644,
+                kind: EntryKind::File {
+                    mtime: 1_700_000_000_123_456_789,
+                    size: 10,
+                    chunks: vec![ChunkRef {
+                        hash: ContentHash::of(b"0123456789"),
+                        len: 10,
+                    }],
+                },
+            },
+            Entry {
+                path: "link".to_string(),
+                mode: 0o777,
+                kind: EntryKind::Symlink {
+                    target: "../x\\y".to_string(),
+                },
+            },
+        ];
+        let mut manifest = format!("{}\ntime 5.000000007\nsource /tmp/a\\tb\n", MANIFEST_HEADER);
+        for entry in &entries {
+            manifest.push_str(&format_entry(entry));
+            manifest.push('\n');
+        }
+
+        let (snapshot, parsed) = parse_manifest("0123456789abcdef", &manifest).unwrap();
+        assert_eq!(parsed, entries);
+        assert_eq!(snapshot.time, UNIX_EPOCH + Duration::new(5, 7));
+        assert_eq!(snapshot.source, PathBuf::from("/tmp/a\tb"));
+        assert_eq!((snapshot.files, snapshot.size), (1, 10));
+
+        // Paths escaping the restore target are rejected
+        for path in ["../evil", "/etc/passwd", "a/../../b"] {
+            let manifest = format!(
+                "{}\ntime 0.0\nsource /\nD\t755\t{}\n",
+                MANIFEST_HEADER, path
+            );
+            assert!(parse_manifest("0123456789abcdef", &manifest).is_none());
+        }
+    }
+}
@@ -0,0 +1,821 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Comparing delta algorithms on version histories.
+//!
+//! This is the harness behind the `git_real_world` benchmark, usable on any corpus. A
+//! [`DeltaAlgorithm`] wraps one encoder/decoder pair; [`compare`] walks a sequence of versions
+//! (oldest first) and, for every version after the first, encodes it with each algorithm,
+//! decodes it again and records sizes, timings and whether the roundtrip was exact as a
+//! [`BenchmarkResult`]. A [`Report`] turns the collected results into the markdown or JSON
+//! reports the benchmark writes.
+//!
+//! xpatch itself is available as [`XpatchSequential`] (always against the previous version)
+//! and [`XpatchTags`] (best of the last N versions, recorded as the tag), with plain
+//! [`Gdelta`] as a baseline. Other libraries plug in by implementing [`DeltaAlgorithm`].
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::bench::{self, DeltaAlgorithm, Version, XpatchSequential, XpatchTags};
+//!
+//! let v1 = "fn main() { println!(\"hello\"); }\n".repeat(20);
+//! let v2 = v1.replace("hello", "hello, world");
+//! let v3 = v2.replacen("main", "start", 1);
+//! let versions = [
+//!     Version::new("v1", v1.as_bytes()),
+//!     Version::new("v2", v2.as_bytes()),
+//!     Version::new("v3", v3.as_bytes()),
+//! ];
+//!
+//! let algorithms: Vec<Box<dyn DeltaAlgorithm>> =
+//!     vec![Box::new(XpatchSequential), Box::new(XpatchTags::new(8))];
+//! let results: Vec<_> = bench::compare("demo", "main.rs", &versions, &algorithms)
+//!     .flatten()
+//!     .filter_map(Result::ok)
+//!     .collect();
+//!
+//! assert_eq!(results.len(), 4);
+//! assert!(results.iter().all(|r| r.verified));
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// Error returned by a [`DeltaAlgorithm`].
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// A delta algorithm under test.
+pub trait DeltaAlgorithm: Send + Sync {
+    /// Name the algorithm is reported under.
+    fn name(&self) -> &str;
+
+    /// Encodes `new` against `base`.
+    fn encode(&self, _base: &[u8], _new: &[u8]) -> Result<Vec<u8>, Error> {
+        Err("Not implemented".into())
+    }
+
+    /// Whether the algorithm picks its own base through
+    /// [`encode_with_history`](Self::encode_with_history).
+    fn searches_history(&self) -> bool {
+        false
+    }
+
+    /// Encodes `new` against one of `previous_versions`, given as `(tag, data)` pairs with the
+    /// immediate predecessor first, and returns the tag of the base used.
+    fn encode_with_history(
+        &self,
+        new: &[u8],
+        previous_versions: &[(usize, &[u8])],
+    ) -> Result<(usize, Vec<u8>), Error> {
+        // Default: just use immediate previous
+        match previous_versions.first() {
+            Some(&(tag, base)) => Ok((tag, self.encode(base, new)?)),
+            None => Err("No previous versions".into()),
+        }
+    }
+
+    /// Reconstructs the new data from `delta` and `base`.
+    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// xpatch against the immediate predecessor, without tags.
+pub struct XpatchSequential;
+
+impl XpatchSequential {
+    /// Name results are reported under.
+    pub const NAME: &'static str = "xpatch_sequential";
+}
+
+impl DeltaAlgorithm for XpatchSequential {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(crate::delta::encode(0, base, new, true))
+    }
+
+    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(crate::delta::decode(base, delta)?)
+    }
+}
+
+/// xpatch with tag optimization: encodes against each of the last `max_search_depth`
+/// versions and keeps the smallest delta.
+pub struct XpatchTags {
+    max_search_depth: usize,
+}
+
+impl XpatchTags {
+    /// Name results are reported under.
+    pub const NAME: &'static str = "xpatch_tags";
+
+    /// Searches at most `max_search_depth` previous versions.
+    pub fn new(max_search_depth: usize) -> Self {
+        Self { max_search_depth }
+    }
+}
+
+impl DeltaAlgorithm for XpatchTags {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn searches_history(&self) -> bool {
+        true
+    }
+
+    fn encode_with_history(
+        &self,
+        new: &[u8],
+        previous_versions: &[(usize, &[u8])],
+    ) -> Result<(usize, Vec<u8>), Error> {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+
+        // Search through previous N versions
+        for &(tag, base) in previous_versions.iter().take(self.max_search_depth) {
+            let delta = crate::delta::encode(tag, base, new, true);
+            if best.as_ref().is_none_or(|(_, b)| delta.len() < b.len()) {
+                best = Some((tag, delta));
+            }
+        }
+
+        best.ok_or_else(|| "No previous versions".into())
+    }
+
+    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(crate::delta::decode(base, delta)?)
+    }
+}
+
+/// Plain gdelta, without xpatch's headers, algorithm selection or compression.
+pub struct Gdelta;
+
+impl DeltaAlgorithm for Gdelta {
+    fn name(&self) -> &str {
+        "gdelta"
+    }
+
+    fn encode(&self, base: &[u8], new: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(gdelta::encode(new, base)?)
+    }
+
+    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(gdelta::decode(delta, base)?)
+    }
+}
+
+// ============================================================================
+// STATISTICS HELPERS
+// ============================================================================
+
+/// Median of `values` (the mean of the middle two for an even count), 0 when empty.
+/// Sorts `values` in place.
+pub fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Integer [`median`], rounded down.
+pub fn median_u128(values: &mut [u128]) -> u128 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2
+    } else {
+        values[mid]
+    }
+}
+
+/// Integer [`median`], rounded down.
+pub fn median_usize(values: &mut [usize]) -> usize {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2
+    } else {
+        values[mid]
+    }
+}
+
+// ============================================================================
+// RESULT TRACKING
+// ============================================================================
+
+/// One version of a document in the compared history.
+#[derive(Debug, Clone, Copy)]
+pub struct Version<'a> {
+    /// Identifier shown in results (a commit hash, file name, ...)
+    pub id: &'a str,
+    /// Position in the full history, for distances between versions. Defaults to the position
+    /// in the sequence given to [`compare`]; set it when versions were skipped.
+    pub index: Option<usize>,
+    /// Contents of this version
+    pub data: &'a [u8],
+}
+
+impl<'a> Version<'a> {
+    /// Creates a version positioned by its place in the sequence.
+    pub fn new(id: &'a str, data: &'a [u8]) -> Self {
+        Self {
+            id,
+            index: None,
+            data,
+        }
+    }
+}
+
+/// Outcome of encoding one version with one algorithm.
+///
+/// Field names follow the git benchmark the reports originated in: `repo_name` is the corpus,
+/// `file_path` the document and `commit_from`/`commit_to` the version ids.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub repo_name: String,
+    pub file_path: String,
+    pub commit_from: String,
+    pub commit_to: String,
+    pub commit_distance: usize,
+    pub file_size: usize,
+
+    pub algorithm: String,
+    pub tag_used: Option<usize>,
+    pub tag_base_commit: Option<String>,
+    pub tag_base_distance: Option<usize>,
+
+    pub delta_size: usize,
+    pub compression_ratio: f64,
+    pub encode_us: u128,
+    pub decode_us: u128,
+    pub verified: bool,
+}
+
+/// Iterator returned by [`compare`].
+pub struct Comparison<'a> {
+    repo_name: &'a str,
+    file_path: &'a str,
+    versions: &'a [Version<'a>],
+    algorithms: &'a [Box<dyn DeltaAlgorithm>],
+    next: usize,
+}
+
+/// Compares `algorithms` on a history of `versions`, ordered oldest to newest.
+///
+/// Each item covers the next version: one entry per algorithm, either its result or a message
+/// describing why encoding or decoding failed. Algorithms that
+/// [search history](DeltaAlgorithm::searches_history) see all earlier versions and limit the
+/// search themselves; all others encode against the immediate predecessor. Work happens as the
+/// iterator advances, so a caller can stop early and keep what it has.
+pub fn compare<'a>(
+    repo_name: &'a str,
+    file_path: &'a str,
+    versions: &'a [Version<'a>],
+    algorithms: &'a [Box<dyn DeltaAlgorithm>],
+) -> Comparison<'a> {
+    Comparison {
+        repo_name,
+        file_path,
+        versions,
+        algorithms,
+        next: 1,
+    }
+}
+
+impl Comparison<'_> {
+    fn run(&self, algo: &dyn DeltaAlgorithm, i: usize) -> Result<BenchmarkResult, String> {
+        let target = &self.versions[i];
+        let position = |j: usize| self.versions[j].index.unwrap_or(j);
+
+        let start = Instant::now();
+        let (tag_used, base_idx, delta) = if algo.searches_history() {
+            let previous_versions: Vec<(usize, &[u8])> = (1..=i)
+                .map(|tag| (tag, self.versions[i - tag].data))
+                .collect();
+            let (tag, delta) = algo
+                .encode_with_history(target.data, &previous_versions)
+                .map_err(|e| format!("Tag encode failed for {}: {}", self.file_path, e))?;
+            if tag == 0 || tag > i {
+                return Err(format!(
+                    "Tag encode for {} returned unknown tag {}",
+                    self.file_path, tag
+                ));
+            }
+            (Some(tag), i - tag, delta)
+        } else {
+            let delta = algo
+                .encode(self.versions[i - 1].data, target.data)
+                .map_err(|e| {
+                    format!(
+                        "Encode failed for {} ({}→{}): {}",
+                        self.file_path,
+                        self.versions[i - 1].id,
+                        target.id,
+                        e
+                    )
+                })?;
+            (None, i - 1, delta)
+        };
+        let encode_us = start.elapsed().as_micros();
+
+        let base = &self.versions[base_idx];
+        let start = Instant::now();
+        let reconstructed = algo.decode(&delta, base.data).map_err(|e| {
+            format!(
+                "Decode failed for {} with {} ({}→{}, base_size={}, delta_size={}, target_size={}): {}",
+                self.file_path,
+                algo.name(),
+                base.id,
+                target.id,
+                base.data.len(),
+                delta.len(),
+                target.data.len(),
+                e
+            )
+        })?;
+        let decode_us = start.elapsed().as_micros();
+
+        let distance = position(i).abs_diff(position(base_idx));
+        Ok(BenchmarkResult {
+            repo_name: self.repo_name.to_string(),
+            file_path: self.file_path.to_string(),
+            commit_from: base.id.to_string(),
+            commit_to: target.id.to_string(),
+            commit_distance: distance,
+            file_size: target.data.len(),
+            algorithm: algo.name().to_string(),
+            tag_used,
+            tag_base_commit: tag_used.map(|_| base.id.to_string()),
+            tag_base_distance: tag_used.map(|_| distance),
+            delta_size: delta.len(),
+            compression_ratio: if !target.data.is_empty() {
+                delta.len() as f64 / target.data.len() as f64
+            } else {
+                0.0
+            },
+            encode_us,
+            decode_us,
+            verified: reconstructed == target.data,
+        })
+    }
+}
+
+impl Iterator for Comparison<'_> {
+    type Item = Vec<Result<BenchmarkResult, String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.versions.len() {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+        Some(
+            self.algorithms
+                .iter()
+                .map(|algo| self.run(algo.as_ref(), i))
+                .collect(),
+        )
+    }
+}
+
+// ============================================================================
+// REPORT GENERATION
+// ============================================================================
+
+/// Machine the benchmark ran on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HardwareInfo {
+    pub cpu: String,
+    pub cores: usize,
+    pub memory_gb: f64,
+}
+
+/// Collected results plus the context needed to interpret them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Report {
+    /// Timestamp shown in the report, in whatever format the caller prefers
+    pub generated_at: String,
+    pub hardware: HardwareInfo,
+    pub results: Vec<BenchmarkResult>,
+    /// Whether the run was interrupted before covering the whole corpus
+    pub early_termination: bool,
+}
+
+impl Report {
+    /// Renders the report as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("reports serialize to JSON")
+    }
+
+    /// Renders the markdown report: algorithm health, rankings by compression ratio, per-algorithm
+    /// statistics and, when both xpatch variants ran, the gain from tag optimization.
+    pub fn to_markdown(&self) -> String {
+        let results = &self.results;
+        let mut report = String::new();
+
+        report.push_str("# 📊 Delta Benchmark Report\n\n");
+
+        if self.early_termination {
+            report.push_str("**⚠️ PARTIAL RESULTS - Benchmark was interrupted**\n\n");
+        }
+
+        report.push_str(&format!("**Generated:** {}\n\n", self.generated_at));
+
+        // Hardware
+        report.push_str("## 💻 Hardware\n\n");
+        report.push_str("```\n");
+        report.push_str(&format!("CPU:    {}\n", self.hardware.cpu));
+        report.push_str(&format!("Cores:  {}\n", self.hardware.cores));
+        report.push_str(&format!("Memory: {:.1} GB\n", self.hardware.memory_gb));
+        report.push_str("```\n\n");
+
+        // Overview
+        let total_tests = results.len();
+        let verified = results.iter().filter(|r| r.verified).count();
+        let unique_files: HashSet<_> = results.iter().map(|r| &r.file_path).collect();
+        let files_tested = unique_files.len();
+
+        report.push_str("## 📈 Overview\n\n");
+        report.push_str(&format!("- **Files Tested:** {}\n", files_tested));
+        report.push_str(&format!("- **Total Tests:** {}\n", total_tests));
+        report.push_str(&format!(
+            "- **Verified:** {} ({:.1}%)\n\n",
+            verified,
+            (verified as f64 / total_tests as f64) * 100.0
+        ));
+
+        // Algorithm verification status
+        report.push_str("## ⚠️ Algorithm Health\n\n");
+        report.push_str("| Algorithm | Tests Passed | Tests Failed | Status |\n");
+        report.push_str("|-----------|--------------|--------------|--------|\n");
+
+        // First-seen order, so reports are deterministic
+        let mut algos: Vec<&String> = Vec::new();
+        for r in results {
+            if !algos.contains(&&r.algorithm) {
+                algos.push(&r.algorithm);
+            }
+        }
+
+        for algo in &algos {
+            let algo_results: Vec<_> = results.iter().filter(|r| r.algorithm == **algo).collect();
+            let passed = algo_results.iter().filter(|r| r.verified).count();
+            let failed = algo_results.len() - passed;
+            let status = if failed == 0 {
+                "✅ VERIFIED"
+            } else {
+                "❌ FAILED"
+            };
+            report.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                algo, passed, failed, status
+            ));
+        }
+        report.push('\n');
+        report.push_str("*Note: Some algorithms may have fewer tests if they failed to encode/decode certain file versions. Failed tests are skipped and logged as warnings.*\n\n");
+
+        // Filter verified algorithms for rankings
+        let verified_algos: Vec<_> = algos
+            .iter()
+            .filter(|algo| {
+                results
+                    .iter()
+                    .filter(|r| r.algorithm == ***algo)
+                    .all(|r| r.verified)
+            })
+            .collect();
+
+        // Algorithm comparison
+        report.push_str("## 🏆 Algorithm Rankings\n\n");
+        report.push_str("*Only verified algorithms*\n\n");
+        report.push_str("### By Compression Ratio (Lower is Better)\n\n");
+        report.push_str("| Algorithm | Avg Ratio | Median Ratio | Avg Saved | Median Saved | Avg Encode (µs) | Median Encode (µs) | Avg Decode (µs) | Median Decode (µs) |\n");
+        report.push_str("|-----------|-----------|--------------|-----------|--------------|-----------------|--------------------|-----------------|-----------------|\n");
+
+        let mut algo_stats: Vec<_> = verified_algos
+            .iter()
+            .map(|algo| {
+                let algo_results: Vec<_> = results
+                    .iter()
+                    .filter(|r| r.algorithm == ***algo && r.verified)
+                    .collect();
+
+                // Calculate averages
+                let avg_ratio = algo_results
+                    .iter()
+                    .map(|r| r.compression_ratio)
+                    .sum::<f64>()
+                    / algo_results.len() as f64;
+                let avg_encode = algo_results.iter().map(|r| r.encode_us).sum::<u128>()
+                    / algo_results.len() as u128;
+                let avg_decode = algo_results.iter().map(|r| r.decode_us).sum::<u128>()
+                    / algo_results.len() as u128;
+
+                // Calculate medians
+                let mut ratios: Vec<f64> =
+                    algo_results.iter().map(|r| r.compression_ratio).collect();
+                let mut encode_times: Vec<u128> =
+                    algo_results.iter().map(|r| r.encode_us).collect();
+                let mut decode_times: Vec<u128> =
+                    algo_results.iter().map(|r| r.decode_us).collect();
+
+                (
+                    **algo,
+                    avg_ratio,
+                    median(&mut ratios),
+                    avg_encode,
+                    median_u128(&mut encode_times),
+                    avg_decode,
+                    median_u128(&mut decode_times),
+                )
+            })
+            .collect();
+
+        algo_stats.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        for (algo, avg_ratio, median_ratio, avg_encode, median_encode, avg_decode, median_decode) in
+            &algo_stats
+        {
+            report.push_str(&format!(
+                "| {} | {:.4} | {:.4} | {} | {} | {} | {} | {} | {} |\n",
+                algo,
+                avg_ratio,
+                median_ratio,
+                saved(*avg_ratio).map_or("N/A".to_string(), |s| format!("{:.1}%", s)),
+                saved(*median_ratio).map_or("N/A".to_string(), |s| format!("{:.1}%", s)),
+                avg_encode,
+                median_encode,
+                avg_decode,
+                median_decode
+            ));
+        }
+
+        // Detailed statistics section
+        report.push_str("\n## 📊 Detailed Statistics\n\n");
+
+        for algo in &verified_algos {
+            let algo_results: Vec<_> = results
+                .iter()
+                .filter(|r| r.algorithm == ***algo && r.verified)
+                .collect();
+
+            if algo_results.is_empty() {
+                continue;
+            }
+
+            report.push_str(&format!("### {}\n\n", algo));
+
+            // Delta size statistics
+            let mut delta_sizes: Vec<usize> = algo_results.iter().map(|r| r.delta_size).collect();
+            let avg_delta_size = delta_sizes.iter().sum::<usize>() / delta_sizes.len();
+            let median_delta_size = median_usize(&mut delta_sizes);
+
+            // Compression ratio statistics
+            let mut ratios: Vec<f64> = algo_results.iter().map(|r| r.compression_ratio).collect();
+            let avg_ratio = ratios.iter().sum::<f64>() / ratios.len() as f64;
+            let median_ratio = median(&mut ratios);
+
+            // Timing statistics
+            let mut encode_times: Vec<u128> = algo_results.iter().map(|r| r.encode_us).collect();
+            let mut decode_times: Vec<u128> = algo_results.iter().map(|r| r.decode_us).collect();
+            let avg_encode = encode_times.iter().sum::<u128>() / encode_times.len() as u128;
+            let avg_decode = decode_times.iter().sum::<u128>() / decode_times.len() as u128;
+            let median_encode = median_u128(&mut encode_times);
+            let median_decode = median_u128(&mut decode_times);
+
+            report.push_str("| Metric | Average | Median |\n");
+            report.push_str("|--------|---------|--------|\n");
+            report.push_str(&format!(
+                "| Delta Size | {} bytes | {} bytes |\n",
+                avg_delta_size, median_delta_size
+            ));
+            report.push_str(&format!(
+                "| Compression Ratio | {:.4} | {:.4} |\n",
+                avg_ratio, median_ratio
+            ));
+            report.push_str(&format!(
+                "| Space Saved | {:.2}% | {:.2}% |\n",
+                saved(avg_ratio).unwrap_or(0.0),
+                saved(median_ratio).unwrap_or(0.0)
+            ));
+            report.push_str(&format!(
+                "| Encode Time | {} µs | {} µs |\n",
+                avg_encode, median_encode
+            ));
+            report.push_str(&format!(
+                "| Decode Time | {} µs | {} µs |\n\n",
+                avg_decode, median_decode
+            ));
+        }
+
+        // Tag optimization analysis
+        let seq_results: Vec<_> = results
+            .iter()
+            .filter(|r| r.algorithm == XpatchSequential::NAME && r.verified)
+            .collect();
+        let tags_results: Vec<_> = results
+            .iter()
+            .filter(|r| r.algorithm == XpatchTags::NAME && r.verified)
+            .collect();
+
+        if !seq_results.is_empty() && !tags_results.is_empty() {
+            report.push_str("\n## 💡 Tag Optimization Impact\n\n");
+            push_tag_impact(&mut report, &seq_results, &tags_results);
+        }
+
+        report.push_str("---\n");
+        report.push_str("\n*Versions processed in chronological order (oldest→newest).*\n");
+
+        report
+    }
+}
+
+/// Percentage of space saved at `ratio`, if meaningful.
+fn saved(ratio: f64) -> Option<f64> {
+    (ratio.is_finite() && ratio > 0.0).then_some((1.0 - ratio) * 100.0)
+}
+
+fn push_tag_impact(
+    report: &mut String,
+    seq_results: &[&BenchmarkResult],
+    tags_results: &[&BenchmarkResult],
+) {
+    let seq_ratio =
+        seq_results.iter().map(|r| r.compression_ratio).sum::<f64>() / seq_results.len() as f64;
+    let tags_ratio = tags_results
+        .iter()
+        .map(|r| r.compression_ratio)
+        .sum::<f64>()
+        / tags_results.len() as f64;
+
+    // Calculate median ratios
+    let mut seq_ratios: Vec<f64> = seq_results.iter().map(|r| r.compression_ratio).collect();
+    let mut tags_ratios: Vec<f64> = tags_results.iter().map(|r| r.compression_ratio).collect();
+    let seq_median = median(&mut seq_ratios);
+    let tags_median = median(&mut tags_ratios);
+
+    if !(seq_ratio.is_finite() && tags_ratio.is_finite() && seq_ratio > 0.0) {
+        report.push_str("*Insufficient data for tag optimization analysis*\n\n");
+        return;
+    }
+
+    let avg_improvement = ((seq_ratio - tags_ratio) / seq_ratio) * 100.0;
+    let median_improvement = if seq_median > 0.0 {
+        ((seq_median - tags_median) / seq_median) * 100.0
+    } else {
+        0.0
+    };
+
+    report.push_str(&format!(
+        "**Average:** Tags provide **{:.1}%** better compression than sequential mode.\n\n",
+        avg_improvement
+    ));
+    report.push_str(&format!(
+        "**Median:** Tags provide **{:.1}%** better compression than sequential mode.\n\n",
+        median_improvement
+    ));
+
+    // Tag usage statistics
+    let mut tag_values: Vec<usize> = tags_results.iter().filter_map(|r| r.tag_used).collect();
+    let mut base_distances: Vec<usize> = tags_results
+        .iter()
+        .filter_map(|r| r.tag_base_distance)
+        .collect();
+
+    let avg_tag = tag_values.iter().sum::<usize>() as f64 / tag_values.len() as f64;
+    let avg_base_distance =
+        base_distances.iter().sum::<usize>() as f64 / base_distances.len() as f64;
+
+    report.push_str("**Tag Statistics:**\n");
+    report.push_str(&format!(
+        "- Average tag value: {:.1} (median: {})\n",
+        avg_tag,
+        median_usize(&mut tag_values)
+    ));
+    report.push_str(&format!(
+        "- Average base distance: {:.1} versions back (median: {})\n\n",
+        avg_base_distance,
+        median_usize(&mut base_distances)
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        let v1 = "line one\nline two\nline three\n".repeat(30);
+        let v2 = v1.replacen("two", "2", 5);
+        let v3 = v1.clone();
+        vec![v1, v2, v3]
+    }
+
+    #[test]
+    fn test_compare() {
+        let history = history();
+        let versions: Vec<_> = history
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Version {
+                id: ["a", "b", "c"][i],
+                index: Some(i * 2),
+                data: v.as_bytes(),
+            })
+            .collect();
+        let algorithms: Vec<Box<dyn DeltaAlgorithm>> = vec![
+            Box::new(XpatchSequential),
+            Box::new(XpatchTags::new(4)),
+            Box::new(Gdelta),
+        ];
+
+        let steps: Vec<_> = compare("corpus", "doc", &versions, &algorithms).collect();
+        assert_eq!(steps.len(), 2);
+        let results: Vec<_> = steps.into_iter().flatten().map(Result::unwrap).collect();
+        assert_eq!(results.len(), 6);
+        assert!(results.iter().all(|r| r.verified));
+
+        let seq = &results[3];
+        assert_eq!(seq.algorithm, XpatchSequential::NAME);
+        assert_eq!(
+            (seq.commit_from.as_str(), seq.commit_to.as_str()),
+            ("b", "c")
+        );
+        assert_eq!(seq.commit_distance, 2);
+        assert_eq!(seq.tag_used, None);
+
+        // v3 is identical to v1, so the tag search goes back two versions
+        let tags = &results[4];
+        assert_eq!(tags.tag_used, Some(2));
+        assert_eq!(tags.tag_base_commit.as_deref(), Some("a"));
+        assert_eq!(tags.tag_base_distance, Some(4));
+        assert!(tags.delta_size <= seq.delta_size);
+    }
+
+    #[test]
+    fn test_report() {
+        let history = history();
+        let versions: Vec<_> = history
+            .iter()
+            .map(|v| Version::new("v", v.as_bytes()))
+            .collect();
+        let algorithms: Vec<Box<dyn DeltaAlgorithm>> =
+            vec![Box::new(XpatchSequential), Box::new(XpatchTags::new(4))];
+        let report = Report {
+            generated_at: "now".to_string(),
+            results: compare("corpus", "doc", &versions, &algorithms)
+                .flatten()
+                .collect::<Result<_, _>>()
+                .unwrap(),
+            ..Default::default()
+        };
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("**Generated:** now"));
+        assert!(markdown.contains("| xpatch_sequential | 2 | 0 | ✅ VERIFIED |"));
+        assert!(markdown.contains("## 💡 Tag Optimization Impact"));
+
+        let parsed: Report = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(parsed.results.len(), 4);
+        assert_eq!(parsed.results[1].tag_used, Some(1));
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut []), 0.0);
+        assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median_u128(&mut [4, 1, 3, 2]), 2);
+        assert_eq!(median_usize(&mut [7]), 7);
+    }
+}
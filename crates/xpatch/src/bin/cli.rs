@@ -38,13 +38,30 @@
 
 use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
 use owo_colors::OwoColorize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::Instant;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use sysinfo::System;
+use xpatch::archive;
+use xpatch::backup::BackupRepo;
+use xpatch::delta::{EncodeOptions, MatchHash, Provenance};
+use xpatch::firmware::{self, Expected};
+use xpatch::manifest::{self, SigningKey, VerifyingKey};
+use xpatch::net::{ResumableApply, UpdateStatus, Updater};
+use xpatch::pack::{PackReader, PackWriter};
+use xpatch::stats::{CountingAllocator, ResourceStats};
+use xpatch::store::{ContentHash, DeltaStore, GcPolicy};
+
+/// Counts heap allocations for `--stats`.
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 // ============================================================================
 // CLI Structure
@@ -82,10 +99,60 @@ enum Commands {
         #[arg(short, long)]
         zstd: bool,
 
+        /// Use zstd long-distance matching (better on large text, more memory)
+        #[arg(long)]
+        long: bool,
+
+        /// zstd window size as a power of two (10-31); defaults to the level's
+        #[arg(long, value_name = "LOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+        window_log: Option<u32>,
+
         /// Verify delta after creation by decoding and comparing
         #[arg(short, long)]
         verify: bool,
 
+        /// Encrypt the delta with a 32-byte key read from this file
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+
+        /// Embed SHA-256 hashes of both files, the time and the xpatch version in the delta
+        #[arg(long)]
+        provenance: bool,
+
+        /// Add Reed-Solomon parity so small corruptions can be repaired on decode
+        #[arg(long)]
+        fec: bool,
+
+        /// Canonicalize the delta's instructions (never larger, byte-stable output)
+        #[arg(long)]
+        optimize: bool,
+
+        /// Align matches to pages of this many bytes (e.g. 4096 for SQLite databases)
+        #[arg(long, value_name = "BYTES", default_value = "0")]
+        page_size: usize,
+
+        /// Record zero runs of at least this many bytes as holes (e.g. 4096 for disk images)
+        #[arg(long, value_name = "BYTES", default_value = "0")]
+        hole_size: usize,
+
+        /// Hash the matcher indexes the base with: standard, xxhash or crc
+        #[arg(long, value_name = "HASH", default_value = "standard")]
+        match_hash: MatchHash,
+
+        /// Delta format to write, for decoders running an older xpatch (1 = xpatch 0.3)
+        #[arg(long, value_name = "FORMAT", value_parser = clap::value_parser!(u32).range(1..))]
+        target_format: Option<u32>,
+
+        /// Diff the contents of gzip/zstd-compressed files, tar and ZIP archives entry by entry,
+        /// JSON structurally, and executables with their moved code realigned; decode rebuilds
+        /// them exactly
+        #[arg(long, conflicts_with_all = ["key", "provenance"])]
+        transparent: bool,
+
+        /// Report the peak heap memory, allocations and time of the encode
+        #[arg(long)]
+        stats: bool,
+
         /// Skip memory warning prompt
         #[arg(short = 'y', long)]
         yes: bool,
@@ -99,6 +166,7 @@ enum Commands {
         quiet: bool,
     },
     /// Apply a delta patch to reconstruct the new file
+    #[command(visible_alias = "apply")]
     Decode {
         /// Base file (original version)
         base: PathBuf,
@@ -110,6 +178,22 @@ enum Commands {
         #[arg(short, long)]
         output: PathBuf,
 
+        /// Key file for encrypted deltas
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+
+        /// Fail before decoding unless the base has this SHA-256 (hex)
+        #[arg(long, value_name = "SHA256", value_parser = parse_sha256)]
+        expect_base_sha256: Option<[u8; 32]>,
+
+        /// Fail without writing the output unless it has this SHA-256 (hex)
+        #[arg(long, value_name = "SHA256", value_parser = parse_sha256)]
+        expect_out_sha256: Option<[u8; 32]>,
+
+        /// Report the peak heap memory, allocations and time of the decode
+        #[arg(long)]
+        stats: bool,
+
         /// Skip memory warning prompt
         #[arg(short = 'y', long)]
         yes: bool,
@@ -126,6 +210,460 @@ enum Commands {
     Info {
         /// Delta patch file
         delta: PathBuf,
+
+        /// Base file to verify the delta against
+        #[arg(short, long)]
+        base: Option<PathBuf>,
+
+        /// Break the payload down into instructions and literals, before and after compression
+        #[arg(long)]
+        breakdown: bool,
+    },
+    /// Show what a delta changes as a color-coded diff
+    Show {
+        /// Base file the delta applies to
+        base: PathBuf,
+
+        /// Delta patch file
+        delta: PathBuf,
+    },
+    /// List a delta's instructions with their byte offsets, stopping at the first malformed one
+    Explain {
+        /// Delta patch file
+        delta: PathBuf,
+    },
+    /// Re-encode an existing delta with different settings
+    Recompress {
+        /// Base file the delta applies to
+        base: PathBuf,
+
+        /// Delta patch file
+        delta: PathBuf,
+
+        /// Output delta file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Enable zstd compression for complex changes
+        #[arg(short, long)]
+        zstd: bool,
+
+        /// zstd compression level
+        #[arg(short, long, default_value = "3", value_parser = clap::value_parser!(i32).range(1..=22))]
+        level: i32,
+
+        /// Use zstd long-distance matching (better on large text, more memory)
+        #[arg(long)]
+        long: bool,
+
+        /// zstd window size as a power of two (10-31); defaults to the level's
+        #[arg(long, value_name = "LOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+        window_log: Option<u32>,
+
+        /// Embed checksums of the base and new data
+        #[arg(short, long)]
+        checksum: bool,
+
+        /// Canonicalize the delta's instructions (never larger, byte-stable output)
+        #[arg(long)]
+        optimize: bool,
+
+        /// Skip memory warning prompt
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Find the encoder settings that produce the smallest deltas on a corpus
+    ///
+    /// Files in the same directory are treated as successive versions, in name order.
+    Tune {
+        /// Corpus directory
+        corpus: PathBuf,
+
+        /// Write the options JSON to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Pack successive versions of a file into a .xpk pack
+    Pack {
+        /// Versions in order, oldest first
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Output pack file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Enable zstd compression for complex changes
+        #[arg(short, long)]
+        zstd: bool,
+
+        /// Store every Nth version as a full snapshot (0 = only the first)
+        #[arg(short, long, default_value = "16")]
+        keyframe_interval: usize,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Extract the versions stored in a .xpk pack
+    Unpack {
+        /// Pack file
+        pack: PathBuf,
+
+        /// Output directory
+        #[arg(short, long, required_unless_present_any = ["list", "verify"])]
+        output: Option<PathBuf>,
+
+        /// Only list the entries
+        #[arg(short, long)]
+        list: bool,
+
+        /// Only check every entry against its recorded hash
+        #[arg(long, conflicts_with = "list")]
+        verify: bool,
+
+        /// Overwrite output files if they exist
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Write a pack of deltas that updates one .xpk pack to another
+    ///
+    /// With --apply, rebuild the new pack from the old pack and such a diff instead.
+    PackDiff {
+        /// Old pack
+        old: PathBuf,
+
+        /// New pack, or the diff with --apply
+        new: PathBuf,
+
+        /// Output file: the diff, or the rebuilt pack with --apply
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Rebuild the new pack from the old pack and a diff
+        #[arg(short, long)]
+        apply: bool,
+
+        /// Enable zstd compression for complex changes
+        #[arg(short, long)]
+        zstd: bool,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Pack the history of a file in a git repository into a .xpk pack
+    ///
+    /// Each version is stored as a delta against whichever of the preceding versions gives the
+    /// smallest delta.
+    #[cfg(feature = "git")]
+    FromGit {
+        /// Repository directory
+        repo: PathBuf,
+
+        /// Path of the file inside the repository
+        path: String,
+
+        /// Output pack file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Number of preceding versions tried as the base of each version
+        #[arg(short, long, default_value = "16")]
+        depth: usize,
+
+        /// Longest chain of deltas before a version is stored as a snapshot
+        #[arg(long, default_value = "64")]
+        max_chain: usize,
+
+        /// Only pack the newest N versions (0 = all)
+        #[arg(short = 'n', long, default_value = "0")]
+        max_versions: usize,
+
+        /// Enable zstd compression for complex changes
+        #[arg(short, long)]
+        zstd: bool,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Generate a pack that updates one directory tree to another
+    ///
+    /// Changed files are stored as deltas against the old tree, new files as snapshots. With
+    /// --cache, files are only hashed again when their size or modification time changed, and
+    /// deltas from the previous pack are reused.
+    SyncGen {
+        /// Directory with the old version
+        old: PathBuf,
+
+        /// Directory with the new version
+        new: PathBuf,
+
+        /// Output pack file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Number of files to hash and encode in parallel (default: one per CPU)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Cache file keeping file hashes and the pack between runs
+        #[arg(short, long)]
+        cache: Option<PathBuf>,
+
+        /// Summary report file (default: the output path with .json appended)
+        #[arg(short, long)]
+        report: Option<PathBuf>,
+
+        /// Enable zstd compression for complex changes
+        #[arg(short, long)]
+        zstd: bool,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Update a file from an xpatch-serve server, resuming interrupted updates
+    FetchApply {
+        /// Manifest URL, e.g. https://updates.example.com/manifest
+        url: String,
+
+        /// File to update (downloaded in full if missing)
+        file: PathBuf,
+
+        /// Continue an interrupted update instead of starting over
+        #[arg(long)]
+        resume: bool,
+
+        /// Hex-encoded Ed25519 public key the manifest must be signed with
+        #[arg(long)]
+        public_key: Option<String>,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Maintain a delta store
+    Store {
+        #[command(subcommand)]
+        command: StoreCommands,
+    },
+    /// Store files of a git repository as delta chains through a clean/smudge filter
+    GitFilter {
+        #[command(subcommand)]
+        command: GitFilterCommands,
+    },
+    /// Build and check signed release manifests for updaters
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommands,
+    },
+    /// Back up a directory into a deduplicating repository
+    Snapshot {
+        /// Directory to back up
+        source: PathBuf,
+
+        /// Backup repository (created if missing)
+        #[arg(short, long)]
+        repo: PathBuf,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// List the snapshots in a backup repository
+    Snapshots {
+        /// Backup repository
+        #[arg(short, long)]
+        repo: PathBuf,
+    },
+    /// Restore a snapshot from a backup repository
+    Restore {
+        /// Snapshot id, unique id prefix, or "latest"
+        snapshot: String,
+
+        /// Backup repository
+        #[arg(short, long)]
+        repo: PathBuf,
+
+        /// Output directory
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Delete old snapshots and the data only they use
+    Prune {
+        /// Backup repository
+        #[arg(short, long)]
+        repo: PathBuf,
+
+        /// Number of most recent snapshots to keep
+        #[arg(short, long)]
+        keep_last: usize,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum StoreCommands {
+    /// Show chain depths, decode costs and dedup ratios, and check for missing objects
+    ///
+    /// Exits with an error if a version's object is missing.
+    Stats {
+        /// Store directory
+        store: PathBuf,
+
+        /// Print a JSON report instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete unreferenced versions and re-base long delta chains
+    Gc {
+        /// Store directory
+        store: PathBuf,
+
+        /// Number of most recent versions to keep even if no ref points to them
+        #[arg(short, long, default_value = "0")]
+        keep_recent: usize,
+
+        /// Re-base chains longer than this onto a new keyframe
+        #[arg(long)]
+        max_chain_length: Option<usize>,
+
+        /// Re-base chains whose decode cost exceeds this many bytes onto a new keyframe
+        #[arg(long)]
+        max_chain_cost: Option<u64>,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GitFilterCommands {
+    /// Register the filter in the repository of the current directory
+    ///
+    /// Writes filter.xpatch.* to .git/config and adds each pattern to .gitattributes.
+    Install {
+        /// Files to filter, as .gitattributes patterns (e.g. "*.psd")
+        patterns: Vec<String>,
+
+        /// Store directory (default: xpatch inside the git directory)
+        #[arg(short, long)]
+        store: Option<PathBuf>,
+    },
+    /// Store a file read from stdin and write its pointer to stdout (run by git)
+    Clean {
+        /// Path of the file in the repository (unused, passed by git)
+        path: Option<String>,
+
+        /// Store directory (default: xpatch inside the git directory)
+        #[arg(short, long)]
+        store: Option<PathBuf>,
+    },
+    /// Turn a pointer read from stdin back into the file on stdout (run by git)
+    Smudge {
+        /// Path of the file in the repository (unused, passed by git)
+        path: Option<String>,
+
+        /// Store directory (default: xpatch inside the git directory)
+        #[arg(short, long)]
+        store: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ManifestCommands {
+    /// Write a manifest listing release files, optionally with patches to the latest release
+    Build {
+        /// Release files from oldest to latest, named by their file names
+        #[arg(required = true)]
+        releases: Vec<PathBuf>,
+
+        /// Output manifest file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Release channel name
+        #[arg(short, long, default_value = "stable")]
+        channel: String,
+
+        /// Encode a patch from every older release to the latest one into this directory
+        #[arg(long)]
+        patch_dir: Option<PathBuf>,
+
+        /// URL prefix of the release and patch downloads, followed by their file names
+        #[arg(long, default_value = "")]
+        base_url: String,
+
+        /// Sign the manifest with the Ed25519 key in this file (see `xpatch-serve keygen`)
+        #[arg(long)]
+        signing_key: Option<PathBuf>,
+
+        /// Write CBOR instead of JSON
+        #[arg(long)]
+        cbor: bool,
+
+        /// Overwrite output files if they exist
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Show a manifest and check its signature
+    Verify {
+        /// Manifest file (JSON or CBOR)
+        manifest: PathBuf,
+
+        /// Hex-encoded Ed25519 public key the manifest must be signed with
+        #[arg(long)]
+        public_key: Option<String>,
     },
 }
 
@@ -153,30 +691,275 @@ fn main() {
             output,
             tag,
             zstd,
+            long,
+            window_log,
+            verify,
+            key,
+            provenance,
+            fec,
+            optimize,
+            page_size,
+            hole_size,
+            match_hash,
+            target_format,
+            transparent,
+            stats,
+            yes,
+            force,
+            quiet,
+        } => handle_encode(
+            &base,
+            &new,
+            &output,
+            tag,
+            &EncodeOptions {
+                enable_zstd: zstd,
+                zstd_long_distance: long,
+                zstd_window_log: window_log.unwrap_or(0),
+                optimize,
+                page_size,
+                hole_size,
+                match_hash,
+                stats,
+                target_format: target_format.unwrap_or_else(xpatch::delta::max_supported_format),
+                ..EncodeOptions::default()
+            },
             verify,
+            key.as_deref(),
+            provenance,
+            fec,
+            transparent,
             yes,
             force,
             quiet,
-        } => handle_encode(&base, &new, &output, tag, zstd, verify, yes, force, quiet),
+        ),
         Commands::Decode {
             base,
             delta,
             output,
+            key,
+            expect_base_sha256,
+            expect_out_sha256,
+            stats,
             yes,
             force,
             quiet,
-        } => handle_decode(&base, &delta, &output, yes, force, quiet),
-        Commands::Info { delta } => handle_info(&delta),
-    };
-
-    match result {
-        Ok(()) => process::exit(EXIT_SUCCESS),
-        Err(e) => {
-            eprintln!("{} {}", "Error:".bright_red().bold(), e);
-
-            // Determine exit code based on error message
-            let exit_code = if e.to_string().contains("out of memory")
-                || e.to_string().contains("Out of memory")
+        } => handle_decode(
+            &base,
+            &delta,
+            &output,
+            key.as_deref(),
+            &Expected {
+                base_sha256: expect_base_sha256,
+                output_sha256: expect_out_sha256,
+            },
+            stats,
+            yes,
+            force,
+            quiet,
+        ),
+        Commands::Info {
+            delta,
+            base,
+            breakdown,
+        } => handle_info(&delta, base.as_deref(), breakdown),
+        Commands::Show { base, delta } => handle_show(&base, &delta),
+        Commands::Explain { delta } => handle_explain(&delta),
+        Commands::Recompress {
+            base,
+            delta,
+            output,
+            zstd,
+            level,
+            long,
+            window_log,
+            checksum,
+            optimize,
+            yes,
+            force,
+            quiet,
+        } => {
+            let options = EncodeOptions {
+                enable_zstd: zstd,
+                zstd_level: level,
+                zstd_long_distance: long,
+                zstd_window_log: window_log.unwrap_or(0),
+                checksum,
+                optimize,
+                ..EncodeOptions::default()
+            };
+            handle_recompress(&base, &delta, &output, &options, yes, force, quiet)
+        }
+        Commands::Tune {
+            corpus,
+            output,
+            force,
+            quiet,
+        } => handle_tune(&corpus, output.as_deref(), force, quiet),
+        Commands::Pack {
+            files,
+            output,
+            zstd,
+            keyframe_interval,
+            force,
+            quiet,
+        } => handle_pack(&files, &output, zstd, keyframe_interval, force, quiet),
+        #[cfg(feature = "git")]
+        Commands::FromGit {
+            repo,
+            path,
+            output,
+            depth,
+            max_chain,
+            max_versions,
+            zstd,
+            force,
+            quiet,
+        } => handle_from_git(
+            &repo,
+            &path,
+            &output,
+            &xpatch::git::PackOptions {
+                enable_zstd: zstd,
+                search_depth: depth,
+                max_chain_depth: max_chain,
+            },
+            max_versions,
+            force,
+            quiet,
+        ),
+        Commands::Unpack {
+            pack,
+            output,
+            list,
+            verify,
+            force,
+            quiet,
+        } => handle_unpack(&pack, output.as_deref(), list, verify, force, quiet),
+        Commands::PackDiff {
+            old,
+            new,
+            output,
+            apply,
+            zstd,
+            force,
+            quiet,
+        } => handle_pack_diff(&old, &new, &output, apply, zstd, force, quiet),
+        Commands::SyncGen {
+            old,
+            new,
+            output,
+            jobs,
+            cache,
+            report,
+            zstd,
+            force,
+            quiet,
+        } => handle_sync_gen(
+            &old,
+            &new,
+            &output,
+            jobs,
+            cache.as_deref(),
+            report.as_deref(),
+            zstd,
+            force,
+            quiet,
+        ),
+        Commands::Store {
+            command: StoreCommands::Stats { store, json },
+        } => handle_store_stats(&store, json),
+        Commands::Store {
+            command:
+                StoreCommands::Gc {
+                    store,
+                    keep_recent,
+                    max_chain_length,
+                    max_chain_cost,
+                    quiet,
+                },
+        } => {
+            let policy = GcPolicy {
+                keep_recent,
+                max_chain_length: max_chain_length.unwrap_or(usize::MAX),
+                max_chain_cost: max_chain_cost.unwrap_or(u64::MAX),
+            };
+            handle_store_gc(&store, &policy, quiet)
+        }
+        Commands::FetchApply {
+            url,
+            file,
+            resume,
+            public_key,
+            quiet,
+        } => handle_fetch_apply(&url, &file, resume, public_key.as_deref(), quiet),
+        Commands::GitFilter {
+            command: GitFilterCommands::Install { patterns, store },
+        } => handle_git_filter_install(&patterns, store.as_deref()),
+        Commands::GitFilter {
+            command: GitFilterCommands::Clean { store, .. },
+        } => handle_git_filter(store.as_deref(), true),
+        Commands::GitFilter {
+            command: GitFilterCommands::Smudge { store, .. },
+        } => handle_git_filter(store.as_deref(), false),
+        Commands::Manifest {
+            command:
+                ManifestCommands::Build {
+                    releases,
+                    output,
+                    channel,
+                    patch_dir,
+                    base_url,
+                    signing_key,
+                    cbor,
+                    force,
+                    quiet,
+                },
+        } => handle_manifest_build(
+            &releases,
+            &output,
+            &channel,
+            patch_dir.as_deref(),
+            &base_url,
+            signing_key.as_deref(),
+            cbor,
+            force,
+            quiet,
+        ),
+        Commands::Manifest {
+            command:
+                ManifestCommands::Verify {
+                    manifest,
+                    public_key,
+                },
+        } => handle_manifest_verify(&manifest, public_key.as_deref()),
+        Commands::Snapshot {
+            source,
+            repo,
+            quiet,
+        } => handle_snapshot(&source, &repo, quiet),
+        Commands::Snapshots { repo } => handle_snapshots(&repo),
+        Commands::Restore {
+            snapshot,
+            repo,
+            output,
+            quiet,
+        } => handle_restore(&snapshot, &repo, &output, quiet),
+        Commands::Prune {
+            repo,
+            keep_last,
+            quiet,
+        } => handle_prune(&repo, keep_last, quiet),
+    };
+
+    match result {
+        Ok(()) => process::exit(EXIT_SUCCESS),
+        Err(e) => {
+            eprintln!("{} {}", "Error:".bright_red().bold(), e);
+
+            // Determine exit code based on error message
+            let exit_code = if e.to_string().contains("out of memory")
+                || e.to_string().contains("Out of memory")
                 || e.to_string().contains("Insufficient memory")
             {
                 EXIT_OUT_OF_MEMORY
@@ -204,8 +987,12 @@ fn handle_encode(
     new_path: &Path,
     output_path: &Path,
     tag: usize,
-    zstd: bool,
+    options: &EncodeOptions,
     verify: bool,
+    key_path: Option<&Path>,
+    provenance: bool,
+    fec: bool,
+    transparent: bool,
     yes: bool,
     force: bool,
     quiet: bool,
@@ -218,239 +1005,2217 @@ fn handle_encode(
         bail!("File not found: {}", new_path.display());
     }
 
-    // Check if output exists
-    if output_path.exists() && !force {
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let key = key_path.map(read_key).transpose()?;
+
+    // Get file sizes
+    let base_size = fs::metadata(base_path)
+        .context("Failed to read base file metadata")?
+        .len();
+    let new_size = fs::metadata(new_path)
+        .context("Failed to read new file metadata")?
+        .len();
+
+    if !quiet {
+        println!(
+            "{} Base: {}, New: {}",
+            "File sizes:".bright_cyan(),
+            format_bytes(base_size),
+            format_bytes(new_size)
+        );
+    }
+
+    // Memory check
+    let required = estimate_encode_memory(base_size, new_size);
+    check_memory(required, yes, quiet)?;
+
+    // Read files
+    if !quiet {
+        let total_steps = if verify { 4 } else { 3 };
+        println!(
+            "{} Reading files...",
+            format!("Step 1/{}:", total_steps).bright_cyan()
+        );
+    }
+
+    let base_data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    let new_data = fs::read(new_path)
+        .with_context(|| format!("Failed to read new file: {}", new_path.display()))?;
+
+    // Encode
+    if !quiet {
+        let total_steps = if verify { 4 } else { 3 };
+        println!(
+            "{} Encoding delta...",
+            format!("Step 2/{}:", total_steps).bright_cyan()
+        );
+    }
+
+    let start = Instant::now();
+    let bar = progress_bar(new_size, quiet);
+    let options = match &bar {
+        Some(bar) => with_progress_bar(options.clone(), bar),
+        None => options.clone(),
+    };
+    let (mut delta, stats) = if transparent {
+        let encode = || xpatch::compressed::encode(tag, &base_data, &new_data, &options);
+        if options.stats {
+            let (delta, stats) = xpatch::stats::measure(encode);
+            (delta, Some(stats))
+        } else {
+            (encode(), None)
+        }
+    } else {
+        xpatch::delta::encode_with_stats(tag, &base_data, &new_data, &options)
+    };
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    if provenance {
+        let record = Provenance::new(
+            ContentHash::of(&base_data).as_bytes().to_vec(),
+            ContentHash::of(&new_data).as_bytes().to_vec(),
+        );
+        delta = xpatch::delta::annotate(&delta, &record)
+            .map_err(|e| anyhow::anyhow!("Failed to embed provenance: {}", e))?;
+    }
+    if let Some(key) = &key {
+        delta = xpatch::encryption::encrypt(&delta, key)
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    }
+    if fec {
+        delta = xpatch::fec::protect(&delta);
+    }
+    let encode_time = start.elapsed();
+
+    // Write output
+    if !quiet {
+        let total_steps = if verify { 4 } else { 3 };
+        println!(
+            "{} Writing output...",
+            format!("Step 3/{}:", total_steps).bright_cyan()
+        );
+    }
+
+    fs::write(output_path, &delta)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+    // Verify if requested
+    let verify_result = if verify {
+        if !quiet {
+            println!("{} Verifying delta...", "Step 4/4:".bright_cyan());
+        }
+
+        let verify_start = Instant::now();
+
+        // Decode and compare
+        let unprotected = if fec {
+            xpatch::fec::repair(&delta)
+                .map_err(|e| anyhow::anyhow!("Verification decode failed: {}", e))?
+                .0
+        } else {
+            delta.clone()
+        };
+        let reconstructed = match &key {
+            Some(key) => xpatch::encryption::decode(&base_data, &unprotected, key),
+            None => xpatch::delta::decode(&base_data, &unprotected),
+        }
+        .map_err(|e| anyhow::anyhow!("Verification decode failed: {}", e))?;
+
+        let verify_time = verify_start.elapsed();
+
+        // Compare
+        if reconstructed != new_data {
+            bail!(
+                "Verification failed: reconstructed output does not match original new file\n   \
+                 Expected {} bytes, got {} bytes",
+                new_data.len(),
+                reconstructed.len()
+            );
+        }
+
+        Some(verify_time)
+    } else {
+        None
+    };
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({}, {:.1}% of new file)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(delta.len() as u64),
+            (delta.len() as f64 / new_size as f64) * 100.0
+        );
+        print!("   Encoding took {}", format_duration(encode_time));
+        if let Some(verify_time) = verify_result {
+            print!(", verification took {}", format_duration(verify_time));
+        }
+        println!();
+    }
+    if let Some(stats) = &stats {
+        print_stats(stats);
+    }
+
+    Ok(())
+}
+
+/// Handle the decode subcommand
+#[allow(clippy::too_many_arguments)]
+fn handle_decode(
+    base_path: &Path,
+    delta_path: &Path,
+    output_path: &Path,
+    key_path: Option<&Path>,
+    expected: &Expected,
+    stats: bool,
+    yes: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input files
+    if !base_path.exists() {
+        bail!("File not found: {}", base_path.display());
+    }
+    if !delta_path.exists() {
+        bail!("File not found: {}", delta_path.display());
+    }
+
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let key = key_path.map(read_key).transpose()?;
+
+    // Get file sizes
+    let base_size = fs::metadata(base_path)
+        .context("Failed to read base file metadata")?
+        .len();
+    let delta_size = fs::metadata(delta_path)
+        .context("Failed to read delta file metadata")?
+        .len();
+
+    if !quiet {
+        println!(
+            "{} Base: {}, Delta: {}",
+            "File sizes:".bright_cyan(),
+            format_bytes(base_size),
+            format_bytes(delta_size)
+        );
+    }
+
+    // Memory check (estimate output size as ~base_size)
+    let required = estimate_decode_memory(base_size, delta_size);
+    check_memory(required, yes, quiet)?;
+
+    // Read files
+    if !quiet {
+        println!("{} Reading files...", "Step 1/3:".bright_cyan());
+    }
+
+    let base_data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    firmware::verify_base(&base_data, expected)?;
+    let mut delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+
+    // Decode
+    if !quiet {
+        println!("{} Decoding delta...", "Step 2/3:".bright_cyan());
+    }
+
+    let start = Instant::now();
+    let mut corrected = 0;
+    if xpatch::fec::is_protected(&delta_data) {
+        (delta_data, corrected) = xpatch::fec::repair(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Repair failed: {}", e))?;
+    }
+    if let Some(key) = &key {
+        delta_data = xpatch::encryption::decrypt(&delta_data, key)
+            .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
+    } else if xpatch::encryption::is_encrypted(&delta_data) {
+        bail!("Delta is encrypted\n   Use --key to provide the key file");
+    }
+    // The output size is unknown until the delta is parsed; the base size is a close guess
+    let bar = progress_bar(base_size, quiet);
+    let options = EncodeOptions::default().stats(stats);
+    let options = match &bar {
+        Some(bar) => with_progress_bar(options, bar),
+        None => options,
+    };
+    let (output_data, stats) = xpatch::delta::decode_with_stats(&base_data, &delta_data, &options)
+        .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    let decode_time = start.elapsed();
+    firmware::verify_output(&output_data, expected)?;
+
+    // Write output
+    if !quiet {
+        println!("{} Writing output...", "Step 3/3:".bright_cyan());
+    }
+
+    fs::write(output_path, &output_data)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({})",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(output_data.len() as u64)
+        );
+        println!("   Decoding took {}", format_duration(decode_time));
+        if corrected > 0 {
+            println!(
+                "   {} Repaired {} corrupted bytes",
+                "Note:".bright_yellow(),
+                corrected
+            );
+        }
+    }
+    if let Some(stats) = &stats {
+        print_stats(stats);
+    }
+
+    Ok(())
+}
+
+/// Handle the info subcommand
+fn handle_info(delta_path: &Path, base_path: Option<&Path>, breakdown: bool) -> Result<()> {
+    // Validate input files
+    if !delta_path.exists() {
+        bail!("File not found: {}", delta_path.display());
+    }
+    if let Some(base_path) = base_path
+        && !base_path.exists()
+    {
+        bail!("Base file not found: {}", base_path.display());
+    }
+
+    // Read delta file
+    let mut delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+    let size = delta_data.len();
+
+    // Apply the whole file up front; the envelopes below are unwrapped for display only
+    let base_data = base_path
+        .map(|path| {
+            fs::read(path).with_context(|| format!("Failed to read base file: {}", path.display()))
+        })
+        .transpose()?;
+    let applied = base_data
+        .as_ref()
+        .map(|base| xpatch::delta::decode(base, &delta_data));
+
+    // Look inside error-correcting envelopes
+    let mut corrected = None;
+    if xpatch::fec::is_protected(&delta_data) {
+        let (repaired, count) = xpatch::fec::repair(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to repair delta: {}", e))?;
+        delta_data = repaired;
+        corrected = Some(count);
+    }
+
+    // Look inside deltas between compressed files
+    let mut recipes = None;
+    if xpatch::compressed::is_wrapped(&delta_data) {
+        let envelope = xpatch::compressed::Envelope::parse(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to read delta: {}", e))?;
+        recipes = Some((envelope.base, envelope.new));
+        delta_data = envelope.delta.to_vec();
+    }
+
+    // Look inside deltas between executables
+    let mut executable = None;
+    if xpatch::formats::exe::is_wrapped(&delta_data) {
+        let envelope = xpatch::formats::exe::Envelope::parse(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to read delta: {}", e))?;
+        executable = Some((envelope.arch, envelope.regions.len(), envelope.shifts.len()));
+        delta_data = envelope.delta.to_vec();
+    }
+
+    // Member-by-member tar and ZIP deltas carry their own tag
+    let mut members = None;
+    let mut entries = None;
+    let tag = if xpatch::formats::zip::is_patch(&delta_data) {
+        let patch = xpatch::formats::zip::Patch::parse(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to read delta: {}", e))?;
+        let diffed = patch
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.source, xpatch::formats::zip::Source::Diffed { .. }))
+            .count();
+        let deflated = patch
+            .entries
+            .iter()
+            .filter(|entry| entry.deflated.is_some())
+            .count();
+        entries = Some((patch.entries.len(), diffed, deflated));
+        patch.tag
+    } else if xpatch::formats::tar::is_patch(&delta_data) {
+        let patch = xpatch::formats::tar::Patch::parse(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to read delta: {}", e))?;
+        let diffed = patch
+            .members
+            .iter()
+            .filter(|member| matches!(member, xpatch::formats::tar::PatchMember::Diffed { .. }))
+            .count();
+        members = Some((patch.members.len(), diffed));
+        patch.tag
+    } else {
+        xpatch::delta::get_tag(&delta_data)
+            .map_err(|e| anyhow::anyhow!("Failed to read delta tag: {}", e))?
+    };
+
+    // Envelopes diff adjusted inputs, so the inner checksums don't cover the files on disk
+    let unwrapped = recipes.is_none() && executable.is_none();
+
+    println!("Tag: {}", tag);
+    println!("Size: {} bytes", size);
+    if let Some(corrected) = corrected {
+        println!("Error correction: yes ({} corrupted bytes)", corrected);
+    }
+    if let Some((base, new)) = recipes {
+        println!("Compressed base: {}", describe_compression(base.as_ref()));
+        println!("Compressed new: {}", describe_compression(new.as_ref()));
+    }
+    if let Some((arch, regions, shifts)) = executable {
+        println!(
+            "Executable: {:?} ({} code sections, {} shifts)",
+            arch, regions, shifts
+        );
+    }
+    if let Some((count, diffed)) = members {
+        println!("Tar members: {} ({} matched in base)", count, diffed);
+    }
+    if let Some((count, diffed, deflated)) = entries {
+        println!(
+            "ZIP entries: {} ({} matched in base, {} recompressed)",
+            count, diffed, deflated
+        );
+    }
+
+    // Try to decode header for additional info
+    match xpatch::delta::inspect(&delta_data) {
+        Ok(info) => {
+            let payload = &delta_data[info.header_size..];
+            println!("Algorithm: {:?}", info.algorithm);
+            println!(
+                "Header format: {}",
+                if info.extended_header {
+                    "extended"
+                } else {
+                    "compact"
+                }
+            );
+            println!(
+                "Mode: {}",
+                if info.stored {
+                    "stored (holds the new data whole)"
+                } else {
+                    "delta"
+                }
+            );
+            println!("Header size: {} bytes", info.header_size);
+            println!("Payload size: {} bytes", info.payload_size);
+            match info.algorithm {
+                // zstd frames don't record the level they were compressed at
+                xpatch::delta::Algorithm::GDeltaZstd | xpatch::delta::Algorithm::CharsZstd => {
+                    println!("Compression: zstd (level not recorded)")
+                }
+                _ => println!("Compression: none"),
+            }
+            if info.encrypted {
+                println!("Encrypted: yes");
+            }
+            if !info.holes.is_empty() {
+                let zeros: usize = info.holes.iter().map(|hole| hole.len()).sum();
+                println!("Holes: {} ({} bytes of zeros)", info.holes.len(), zeros);
+            }
+
+            // GDelta instructions spell out the output size without needing the base
+            let output_size = match (&applied, info.algorithm) {
+                (Some(Ok(output)), _) => Some(output.len()),
+                (_, xpatch::delta::Algorithm::Chars) if info.stored => Some(
+                    info.payload_size + info.holes.iter().map(|hole| hole.len()).sum::<usize>(),
+                ),
+                (_, xpatch::delta::Algorithm::GDelta | xpatch::delta::Algorithm::GDeltaZstd)
+                    if !info.encrypted =>
+                {
+                    xpatch::delta::ops::decode_ops(&[], &delta_data)
+                        .ok()
+                        .map(|ops| ops.iter().map(|op| op.len()).sum())
+                }
+                _ => None,
+            };
+            match output_size {
+                Some(output_size) => println!("Output size: {} bytes", output_size),
+                None => println!("Output size: unknown (pass --base to compute it)"),
+            }
+
+            match info.base_checksum {
+                Some(checksum) => println!(
+                    "Base checksum: {:08x}{}",
+                    checksum,
+                    match &base_data {
+                        Some(base) if unwrapped => {
+                            if crc32fast::hash(base) == checksum {
+                                " (matches base)"
+                            } else {
+                                " (does not match base)"
+                            }
+                        }
+                        _ => "",
+                    }
+                ),
+                None => println!("Base checksum: none"),
+            }
+            match info.output_checksum {
+                Some(checksum) => println!(
+                    "Output checksum: {:08x}{}",
+                    checksum,
+                    match &applied {
+                        Some(Ok(output)) if unwrapped => {
+                            if crc32fast::hash(output) == checksum {
+                                " (matches output)"
+                            } else {
+                                " (does not match output)"
+                            }
+                        }
+                        _ => "",
+                    }
+                ),
+                None => println!("Output checksum: none"),
+            }
+
+            if !info.encrypted && !payload.is_empty() {
+                let preview = &payload[..payload.len().min(PREVIEW_LEN)];
+                let more = if payload.len() > PREVIEW_LEN {
+                    "..."
+                } else {
+                    ""
+                };
+                println!("Payload preview:");
+                println!("  Hex: {}{}", to_hex(preview), more);
+                println!(
+                    "  UTF-8: {}{}",
+                    String::from_utf8_lossy(preview).escape_debug(),
+                    more
+                );
+            }
+            if let Some(provenance) = info.provenance {
+                println!("Provenance:");
+                println!("  Source: {}", to_hex(&provenance.source_hash));
+                println!("  Target: {}", to_hex(&provenance.target_hash));
+                println!("  Created: {} UTC", format_time(provenance.created));
+                println!("  Tool: {}", provenance.tool);
+            }
+            if breakdown && !info.encrypted {
+                print_breakdown(&delta_data)?;
+            }
+        }
+        Err(_) => {
+            // Don't fail if header can't be decoded
+        }
+    }
+
+    match applied {
+        Some(Ok(_)) => println!("Applies to base: yes"),
+        Some(Err(e)) => println!("Applies to base: no ({})", e),
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Print where the bytes of a delta go, for `info --breakdown`
+fn print_breakdown(delta: &[u8]) -> Result<()> {
+    let breakdown = xpatch::delta::breakdown(delta)
+        .map_err(|e| anyhow::anyhow!("Failed to break down delta: {}", e))?;
+    let compressed = |size: Option<usize>| match size {
+        Some(size) => format!(", {} compressed", size),
+        None => String::new(),
+    };
+    let parts = breakdown.compressed;
+    println!("Breakdown:");
+    println!("  Header: {} bytes", breakdown.header_bytes);
+    println!(
+        "  Payload: {} bytes ({} uncompressed)",
+        breakdown.payload_bytes, breakdown.uncompressed_payload_bytes
+    );
+    println!("  Framing: {} bytes", breakdown.framing_bytes);
+    println!(
+        "  Copy instructions: {} ({} bytes{}, {} bytes copied)",
+        breakdown.copy_ops,
+        breakdown.copy_op_bytes,
+        compressed(parts.map(|parts| parts.copy_op_bytes)),
+        breakdown.copied_bytes
+    );
+    println!(
+        "  Insert instructions: {} ({} bytes{})",
+        breakdown.insert_ops,
+        breakdown.insert_op_bytes,
+        compressed(parts.map(|parts| parts.insert_op_bytes))
+    );
+    println!(
+        "  Literals: {} bytes{}",
+        breakdown.literal_bytes,
+        compressed(parts.map(|parts| parts.literal_bytes))
+    );
+    if breakdown.other_bytes > 0 {
+        println!("  Other: {} bytes", breakdown.other_bytes);
+    }
+    Ok(())
+}
+
+/// Number of payload bytes `info` previews
+const PREVIEW_LEN: usize = 32;
+
+/// Describe how an input of a transparent delta was compressed
+fn describe_compression(compressed: Option<&xpatch::compressed::Compressed>) -> String {
+    match compressed {
+        Some(xpatch::compressed::Compressed::Gzip { level, .. }) => {
+            format!("gzip, level {}", level)
+        }
+        Some(xpatch::compressed::Compressed::Zstd { level, .. }) => {
+            format!("zstd, level {}", level)
+        }
+        None => "no".to_string(),
+    }
+}
+
+/// Handle the show subcommand
+fn handle_show(base_path: &Path, delta_path: &Path) -> Result<()> {
+    // Validate input files
+    if !base_path.exists() {
+        bail!("File not found: {}", base_path.display());
+    }
+    if !delta_path.exists() {
+        bail!("File not found: {}", delta_path.display());
+    }
+
+    let base_data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    let delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+
+    let diff = xpatch::delta::render_diff(&base_data, &delta_data)
+        .map_err(|e| anyhow::anyhow!("Failed to render delta: {}", e))?;
+    if diff.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+
+    for line in diff.lines() {
+        match line.chars().next() {
+            Some('@') => println!("{}", line.bright_cyan()),
+            Some('+') => println!("{}", line.green()),
+            Some('-') => println!("{}", line.red()),
+            _ => println!("{}", line),
+        }
+    }
+    Ok(())
+}
+
+/// Handle the explain subcommand
+fn handle_explain(delta_path: &Path) -> Result<()> {
+    // Validate input file
+    if !delta_path.exists() {
+        bail!("File not found: {}", delta_path.display());
+    }
+
+    let delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+
+    // Offsets inside an envelope wouldn't match the file
+    let envelope = if xpatch::fec::is_protected(&delta_data) {
+        Some("error-correcting")
+    } else if xpatch::compressed::is_wrapped(&delta_data) {
+        Some("compressed file")
+    } else if xpatch::formats::exe::is_wrapped(&delta_data) {
+        Some("executable")
+    } else if xpatch::formats::zip::is_patch(&delta_data) {
+        Some("ZIP")
+    } else if xpatch::formats::tar::is_patch(&delta_data) {
+        Some("tar")
+    } else {
+        None
+    };
+    if let Some(envelope) = envelope {
+        bail!(
+            "Delta is wrapped in a {} envelope; explain reads plain deltas",
+            envelope
+        );
+    }
+
+    let info = xpatch::delta::inspect(&delta_data)
+        .map_err(|e| anyhow::anyhow!("Malformed header: {}", e))?;
+    println!("Algorithm: {:?}", info.algorithm);
+    println!("Tag: {}", info.tag);
+    println!("Header size: {} bytes", info.header_size);
+    println!("Payload offset: {:#x}", info.header_size);
+
+    let trace = match xpatch::delta::ops::trace(&delta_data) {
+        Ok(trace) => trace,
+        Err(e) => {
+            // Specialized algorithms encode a single edit; show the raw payload instead
+            println!("{}; payload:", e);
+            print_hexdump(&delta_data[info.header_size..], info.header_size);
+            return Ok(());
+        }
+    };
+    if trace.algorithm == xpatch::delta::Algorithm::GDeltaZstd {
+        println!("Offsets below are into the decompressed payload");
+    }
+
+    let mut output = 0;
+    for traced in &trace.ops {
+        match &traced.op {
+            xpatch::delta::ops::Op::Copy { offset, len } => println!(
+                "{:#010x}  copy    {:>10} bytes  from base {:#x}  to {:#x}",
+                traced.position, len, offset, output
+            ),
+            xpatch::delta::ops::Op::Insert(bytes) => println!(
+                "{:#010x}  insert  {:>10} bytes  literals at {:#x}  to {:#x}  \"{}{}\"",
+                traced.position,
+                bytes.len(),
+                traced.literals.unwrap_or_default(),
+                output,
+                String::from_utf8_lossy(&bytes[..bytes.len().min(PREVIEW_LEN)]).escape_debug(),
+                if bytes.len() > PREVIEW_LEN { "..." } else { "" }
+            ),
+        }
+        output += traced.op.len();
+    }
+
+    if let Some((position, error)) = trace.error {
+        bail!(
+            "Malformed instruction at byte {:#x} ({}): {}",
+            position,
+            position,
+            error
+        );
+    }
+    println!("{} instructions, {} output bytes", trace.ops.len(), output);
+    if trace.unused_literals > 0 {
+        println!("Unused literal bytes: {}", trace.unused_literals);
+    }
+    Ok(())
+}
+
+/// Prints `bytes` as a hexdump, numbering lines from `start`
+fn print_hexdump(bytes: &[u8], start: usize) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!(
+            "{:#010x}  {:<47}  |{}|",
+            start + i * 16,
+            hex.join(" "),
+            text
+        );
+    }
+}
+
+/// Handle the recompress subcommand
+fn handle_recompress(
+    base_path: &Path,
+    delta_path: &Path,
+    output_path: &Path,
+    options: &EncodeOptions,
+    yes: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input files
+    if !base_path.exists() {
+        bail!("File not found: {}", base_path.display());
+    }
+    if !delta_path.exists() {
+        bail!("File not found: {}", delta_path.display());
+    }
+
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    // Memory check: decoding and re-encoding both hold the base and the new data
+    let base_size = fs::metadata(base_path)
+        .context("Failed to read base file metadata")?
+        .len();
+    let delta_size = fs::metadata(delta_path)
+        .context("Failed to read delta file metadata")?
+        .len();
+    let required = estimate_encode_memory(base_size, base_size.max(delta_size));
+    check_memory(required, yes, quiet)?;
+
+    // Read files
+    if !quiet {
+        println!("{} Reading files...", "Step 1/3:".bright_cyan());
+    }
+
+    let base_data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    let delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+
+    // Recompress
+    if !quiet {
+        println!("{} Recompressing delta...", "Step 2/3:".bright_cyan());
+    }
+
+    if xpatch::encryption::is_encrypted(&delta_data) {
+        bail!("Delta is encrypted\n   Decrypt it before recompressing");
+    }
+    let start = Instant::now();
+    let recompressed = xpatch::delta::recompress(&delta_data, &base_data, options)
+        .map_err(|e| anyhow::anyhow!("Recompress failed: {}", e))?;
+    let recompress_time = start.elapsed();
+
+    // Write output
+    if !quiet {
+        println!("{} Writing output...", "Step 3/3:".bright_cyan());
+    }
+
+    fs::write(output_path, &recompressed)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({} -> {}, {:.1}% of original delta)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(delta_data.len() as u64),
+            format_bytes(recompressed.len() as u64),
+            (recompressed.len() as f64 / delta_data.len() as f64) * 100.0
+        );
+        println!("   Recompressing took {}", format_duration(recompress_time));
+    }
+
+    Ok(())
+}
+
+/// zstd settings tried by `tune`, cheapest first: off, then increasing levels
+const TUNE_ZSTD: &[Option<i32>] = &[None, Some(1), Some(3), Some(6), Some(9), Some(15), Some(19)];
+
+/// Block sizes tried by `tune`; 0 lets the encoder pick its matcher
+const TUNE_BLOCK_SIZES: &[usize] = &[0, 512, 4096];
+
+/// Handle the tune subcommand
+fn handle_tune(corpus: &Path, output_path: Option<&Path>, force: bool, quiet: bool) -> Result<()> {
+    if !corpus.is_dir() {
+        bail!("Directory not found: {}", corpus.display());
+    }
+
+    // Check if output exists
+    if let Some(path) = output_path
+        && path.exists()
+        && !force
+    {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            path.display()
+        );
+    }
+
+    // Successive files of each directory form the (base, new) pairs
+    let mut pairs = Vec::new();
+    collect_tune_pairs(corpus, &mut pairs)?;
+    if pairs.is_empty() {
+        bail!("Corpus needs at least two files in one directory");
+    }
+    let total_size: u64 = pairs.iter().map(|(_, new)| new.len() as u64).sum();
+
+    if !quiet {
+        eprintln!(
+            "{} {} version pairs ({})",
+            "Corpus:".bright_cyan(),
+            pairs.len(),
+            format_bytes(total_size)
+        );
+    }
+
+    let mut candidates = Vec::new();
+    for &zstd in TUNE_ZSTD {
+        for &block_size in TUNE_BLOCK_SIZES {
+            for optimize in [false, true] {
+                candidates.push(EncodeOptions {
+                    enable_zstd: zstd.is_some(),
+                    zstd_level: zstd.unwrap_or(EncodeOptions::default().zstd_level),
+                    block_size,
+                    optimize,
+                    ..EncodeOptions::default()
+                });
+            }
+        }
+    }
+
+    // Ties go to the cheaper settings, which come first
+    let start = Instant::now();
+    let default_size = tune_size(&pairs, &EncodeOptions::default());
+    let mut best = (EncodeOptions::default(), default_size);
+    for (i, options) in candidates.iter().enumerate() {
+        let size = tune_size(&pairs, options);
+        if size < best.1 {
+            best = (options.clone(), size);
+        }
+        if !quiet {
+            eprint!(
+                "\r{} {}/{} settings, best {}",
+                "Tuning:".bright_cyan(),
+                i + 1,
+                candidates.len(),
+                format_bytes(best.1)
+            );
+        }
+    }
+
+    if !quiet {
+        eprintln!();
+    }
+
+    let (options, size) = best;
+    let json = format!(
+        "{{\n  \"enable_zstd\": {},\n  \"zstd_level\": {},\n  \"zstd_threads\": {},\n  \"checksum\": {},\n  \"block_size\": {},\n  \"optimize\": {}\n}}\n",
+        options.enable_zstd,
+        options.zstd_level,
+        options.zstd_threads,
+        options.checksum,
+        options.block_size,
+        options.optimize
+    );
+
+    match output_path {
+        Some(path) => fs::write(path, &json)
+            .with_context(|| format!("Failed to write output file: {}", path.display()))?,
+        None => print!("{}", json),
+    }
+
+    // Success message
+    if !quiet {
+        eprintln!(
+            "{} Deltas total {} ({:.1}% of input, {:.1}% of default settings)",
+            "Success:".bright_green().bold(),
+            format_bytes(size),
+            (size as f64 / total_size.max(1) as f64) * 100.0,
+            (size as f64 / default_size.max(1) as f64) * 100.0
+        );
+        eprintln!("   Tuning took {}", format_duration(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Collects consecutive files of every directory under `dir` as (base, new) pairs
+fn collect_tune_pairs(dir: &Path, pairs: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+    let mut children = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    children.sort();
+
+    let mut previous: Option<Vec<u8>> = None;
+    for path in children {
+        if path.is_dir() {
+            collect_tune_pairs(&path, pairs)?;
+            continue;
+        }
+        let data =
+            fs::read(&path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if let Some(base) = previous.replace(data.clone()) {
+            pairs.push((base, data));
+        }
+    }
+
+    Ok(())
+}
+
+/// Total delta size of all pairs encoded with `options`
+fn tune_size(pairs: &[(Vec<u8>, Vec<u8>)], options: &EncodeOptions) -> u64 {
+    pairs
+        .iter()
+        .map(|(base, new)| xpatch::delta::encode_with_options(0, base, new, options).len() as u64)
+        .sum()
+}
+
+/// Handle the pack subcommand
+fn handle_pack(
+    files: &[PathBuf],
+    output_path: &Path,
+    zstd: bool,
+    keyframe_interval: usize,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input files
+    for file in files {
+        if !file.exists() {
+            bail!("File not found: {}", file.display());
+        }
+    }
+
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let output = fs::File::create(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    let mut writer =
+        PackWriter::new(io::BufWriter::new(output), zstd).context("Failed to write pack header")?;
+
+    let start = Instant::now();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut total_size = 0u64;
+    for (i, file) in files.iter().enumerate() {
+        let data =
+            fs::read(file).with_context(|| format!("Failed to read file: {}", file.display()))?;
+        let name = file.to_string_lossy();
+        let snapshot =
+            keyframe_interval == 0 && i == 0 || keyframe_interval > 0 && i % keyframe_interval == 0;
+
+        match previous.as_deref() {
+            Some(base) if !snapshot => writer.add_delta(&name, i, base, &data),
+            _ => writer.add_snapshot(&name, i, &data),
+        }
+        .with_context(|| format!("Failed to add {} to pack", file.display()))?;
+
+        if !quiet {
+            println!(
+                "{} {}",
+                format!("[{}/{}]", i + 1, files.len()).bright_cyan(),
+                file.display()
+            );
+        }
+        total_size += data.len() as u64;
+        previous = Some(data);
+    }
+
+    let mut output = writer.finish().context("Failed to write pack index")?;
+    output.flush().context("Failed to write pack file")?;
+    let pack_size = fs::metadata(output_path)
+        .context("Failed to read pack file metadata")?
+        .len();
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({}, {:.1}% of {} input)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(pack_size),
+            (pack_size as f64 / total_size.max(1) as f64) * 100.0,
+            format_bytes(total_size)
+        );
+        println!("   Packing took {}", format_duration(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Handle the from-git subcommand
+#[cfg(feature = "git")]
+fn handle_from_git(
+    repo_path: &Path,
+    file_path: &str,
+    output_path: &Path,
+    options: &xpatch::git::PackOptions,
+    max_versions: usize,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let repo = git2::Repository::open(repo_path)
+        .with_context(|| format!("Failed to open repository: {}", repo_path.display()))?;
+
+    if !quiet {
+        println!("{} Walking history...", "Step 1/2:".bright_cyan());
+    }
+    let start = Instant::now();
+    let versions = xpatch::git::file_history(&repo, file_path, max_versions)
+        .with_context(|| format!("Failed to read history of {}", file_path))?;
+    if versions.is_empty() {
+        bail!("No commit contains {}", file_path);
+    }
+    let total_size: u64 = versions.iter().map(|v| v.data.len() as u64).sum();
+
+    if !quiet {
+        println!(
+            "{} Packing {} versions ({})...",
+            "Step 2/2:".bright_cyan(),
+            versions.len(),
+            format_bytes(total_size)
+        );
+    }
+    let output = fs::File::create(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    let mut output =
+        xpatch::git::write_pack(file_path, &versions, io::BufWriter::new(output), options)
+            .context("Failed to write pack")?;
+    output.flush().context("Failed to write pack file")?;
+    let pack_size = fs::metadata(output_path)
+        .context("Failed to read pack file metadata")?
+        .len();
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({}, {:.1}% of {} input)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(pack_size),
+            (pack_size as f64 / total_size.max(1) as f64) * 100.0,
+            format_bytes(total_size)
+        );
+        println!("   Packing took {}", format_duration(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Handle the unpack subcommand
+fn handle_unpack(
+    pack_path: &Path,
+    output_dir: Option<&Path>,
+    list: bool,
+    verify: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input file
+    if !pack_path.exists() {
+        bail!("File not found: {}", pack_path.display());
+    }
+
+    let mut reader = PackReader::open(pack_path)
+        .with_context(|| format!("Failed to read pack file: {}", pack_path.display()))?;
+    let entries = reader.entries().to_vec();
+
+    if list {
+        for entry in &entries {
+            println!(
+                "{}  {:>10}  {:>10}  tag {:<4} {}{}",
+                &entry.hash.to_string()[..16],
+                format_bytes(entry.size),
+                format_bytes(entry.length),
+                entry.tag,
+                entry.name,
+                if entry.base.is_none() {
+                    " (snapshot)"
+                } else if entry.external {
+                    " (external base)"
+                } else {
+                    ""
+                }
+            );
+        }
+        return Ok(());
+    }
+
+    if verify {
+        reader
+            .verify()
+            .with_context(|| format!("Pack verification failed: {}", pack_path.display()))?;
+        if !quiet {
+            println!(
+                "{} All {} entries verified",
+                "Success:".bright_green().bold(),
+                entries.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let output_dir = output_dir.expect("clap requires --output without --list or --verify");
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create directory: {}", output_dir.display()))?;
+
+    let mut skipped = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        // Deltas against files outside the pack (see sync-gen) can't be extracted on their own
+        if entry.external {
+            skipped += 1;
+            continue;
+        }
+
+        // Only use the file name, so a pack cannot write outside the output directory
+        let file_name = Path::new(&entry.name)
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_else(|| entry.hash.to_string().into());
+        let output_path = output_dir.join(file_name);
+        if output_path.exists() && !force {
+            bail!(
+                "Output file already exists: {}\n   Use --force to overwrite",
+                output_path.display()
+            );
+        }
+
+        let data = reader
+            .get(&entry.hash)
+            .with_context(|| format!("Failed to decode pack entry {}", entry.name))?;
+        fs::write(&output_path, &data)
+            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+        if !quiet {
+            println!(
+                "{} {} ({})",
+                format!("[{}/{}]", i + 1, entries.len()).bright_cyan(),
+                output_path.display(),
+                format_bytes(data.len() as u64)
+            );
+        }
+    }
+
+    if !quiet {
+        println!();
+        println!(
+            "{} Extracted {} files to {}",
+            "Success:".bright_green().bold(),
+            entries.len() - skipped,
+            output_dir.display()
+        );
+        if skipped > 0 {
+            println!(
+                "   Skipped {} entries that are deltas against files outside the pack",
+                skipped
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle the pack-diff subcommand
+fn handle_pack_diff(
+    old_path: &Path,
+    new_path: &Path,
+    output_path: &Path,
+    apply: bool,
+    zstd: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input files
+    for path in [old_path, new_path] {
+        if !path.exists() {
+            bail!("File not found: {}", path.display());
+        }
+    }
+
+    // Check if output exists
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let mut old = PackReader::open(old_path)
+        .with_context(|| format!("Failed to read pack file: {}", old_path.display()))?;
+    let mut new = PackReader::open(new_path)
+        .with_context(|| format!("Failed to read pack file: {}", new_path.display()))?;
+    let output = fs::File::create(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+
+    let start = Instant::now();
+    let mut output = if apply {
+        archive::apply_pack_diff(&mut old, &mut new, io::BufWriter::new(output), zstd)
+            .context("Failed to apply pack diff")?
+    } else {
+        archive::diff_packs(&mut old, &mut new, io::BufWriter::new(output), zstd)
+            .context("Failed to diff packs")?
+    };
+    output.flush().context("Failed to write pack file")?;
+
+    // Success message
+    if !quiet {
+        let new_size = fs::metadata(new_path)
+            .context("Failed to read pack file metadata")?
+            .len();
+        let output_size = fs::metadata(output_path)
+            .context("Failed to read pack file metadata")?
+            .len();
+        println!(
+            "{} Created {} ({}, {} entries)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(output_size),
+            new.len()
+        );
+        if !apply {
+            println!(
+                "   {:.1}% of the new pack ({})",
+                (output_size as f64 / new_size.max(1) as f64) * 100.0,
+                format_bytes(new_size)
+            );
+        }
+        println!("   Took {}", format_duration(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Handle the sync-gen subcommand
+#[allow(clippy::too_many_arguments)]
+fn handle_sync_gen(
+    old_dir: &Path,
+    new_dir: &Path,
+    output_path: &Path,
+    jobs: Option<usize>,
+    cache_path: Option<&Path>,
+    report_path: Option<&Path>,
+    enable_zstd: bool,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    // Validate input directories
+    for dir in [old_dir, new_dir] {
+        if !dir.is_dir() {
+            bail!("Directory not found: {}", dir.display());
+        }
+    }
+    let old_dir = fs::canonicalize(old_dir)
+        .with_context(|| format!("Failed to resolve directory: {}", old_dir.display()))?;
+    let new_dir = fs::canonicalize(new_dir)
+        .with_context(|| format!("Failed to resolve directory: {}", new_dir.display()))?;
+    let jobs = jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+
+    let cache = match cache_path {
+        Some(path) if path.exists() => SyncCache::load(path)?,
+        _ => SyncCache::default(),
+    };
+
+    // The pack a previous run wrote may be overwritten without --force
+    if output_path.exists()
+        && !force
+        && cache.pack.as_deref() != fs::canonicalize(output_path).ok().as_deref()
+    {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    let start = Instant::now();
+
+    // Hash both trees, skipping files whose size and modification time are cached
+    let old_files = list_files(&old_dir)?;
+    let new_files = list_files(&new_dir)?;
+    let paths: Vec<PathBuf> = old_files
+        .iter()
+        .map(|name| old_dir.join(name))
+        .chain(new_files.iter().map(|name| new_dir.join(name)))
+        .collect();
+    let stamped = parallel_map(&paths, jobs, |path| {
+        let stamp = FileStamp::of(path)?;
+        let hash = match cache.files.get(path) {
+            Some(&(cached, hash)) if cached == stamp => hash,
+            _ => ContentHash::of(
+                &fs::read(path)
+                    .with_context(|| format!("Failed to read file: {}", path.display()))?,
+            ),
+        };
+        Ok((stamp, hash))
+    })?;
+    let hashes: HashMap<&Path, ContentHash> = paths
+        .iter()
+        .map(PathBuf::as_path)
+        .zip(stamped.iter().map(|&(_, hash)| hash))
+        .collect();
+
+    // Classify the files of the new tree
+    let old_names: HashSet<&str> = old_files.iter().map(String::as_str).collect();
+    let new_names: HashSet<&str> = new_files.iter().map(String::as_str).collect();
+    let mut unchanged = 0;
+    let mut work = Vec::new();
+    for name in &new_files {
+        let hash = hashes[new_dir.join(name).as_path()];
+        let base = if old_names.contains(name.as_str()) {
+            let base = hashes[old_dir.join(name).as_path()];
+            if base == hash {
+                unchanged += 1;
+                continue;
+            }
+            Some(base)
+        } else {
+            None
+        };
+        work.push((name.as_str(), base, hash));
+    }
+    let deleted: Vec<&str> = old_files
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !new_names.contains(name))
+        .collect();
+
+    // Deltas in the previous pack are reused if they were encoded the same way
+    let previous = match &cache.pack {
+        Some(path) if cache.zstd == enable_zstd && path.exists() => PackReader::open(path).ok(),
+        _ => None,
+    }
+    .map(Mutex::new);
+
+    // Encode the new and changed files
+    let options = EncodeOptions {
+        enable_zstd,
+        checksum: true,
+        ..EncodeOptions::default()
+    };
+    let deltas = parallel_map(&work, jobs, |&(name, base, hash)| {
+        if let Some(previous) = &previous {
+            let mut previous = previous.lock().unwrap();
+            if previous
+                .entry(&hash)
+                .is_some_and(|entry| entry.base == base && entry.external == base.is_some())
+                && let Ok(delta) = previous.read_raw(&hash)
+            {
+                return Ok((delta, true));
+            }
+        }
+
+        let new_path = new_dir.join(name);
+        let new_data = fs::read(&new_path)
+            .with_context(|| format!("Failed to read file: {}", new_path.display()))?;
+        let old_data = match base {
+            Some(_) => {
+                let old_path = old_dir.join(name);
+                fs::read(&old_path)
+                    .with_context(|| format!("Failed to read file: {}", old_path.display()))?
+            }
+            None => Vec::new(),
+        };
+        let delta = xpatch::delta::encode_with_options(0, &old_data, &new_data, &options);
+        if !quiet {
+            println!(
+                "{} {}",
+                if base.is_some() { "changed" } else { "added  " }.bright_cyan(),
+                name
+            );
+        }
+        Ok((delta, false))
+    })?;
+    drop(previous);
+
+    // Write the pack next to the output and move it into place, so the previous pack stays
+    // readable until the new one is complete
+    let mut temp_path = output_path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let output = fs::File::create(&temp_path)
+        .with_context(|| format!("Failed to create output file: {}", temp_path.display()))?;
+    let mut writer = PackWriter::new(io::BufWriter::new(output), enable_zstd)
+        .context("Failed to write pack header")?;
+    let mut files = Vec::new();
+    let mut new_size = 0u64;
+    for (&(name, base, hash), (delta, reused)) in work.iter().zip(&deltas) {
+        let size = fs::metadata(new_dir.join(name))
+            .with_context(|| format!("Failed to read file metadata: {}", name))?
+            .len();
+        match base {
+            Some(base) => writer.add_external(name, hash, base, size, delta),
+            None => writer.add_encoded(name, hash, None, size, delta),
+        }
+        .with_context(|| format!("Failed to add {} to pack", name))?;
+        new_size += size;
+        files.push(serde_json::json!({
+            "path": name,
+            "status": if base.is_some() { "changed" } else { "added" },
+            "size": size,
+            "delta_size": delta.len(),
+            "reused": reused,
+        }));
+    }
+    let mut output = writer.finish().context("Failed to write pack index")?;
+    output.flush().context("Failed to write pack file")?;
+    drop(output);
+    fs::rename(&temp_path, output_path)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    let pack_size = fs::metadata(output_path)
+        .context("Failed to read pack file metadata")?
+        .len();
+    let reused = deltas.iter().filter(|(_, reused)| *reused).count();
+
+    // Summary report
+    let report_path = report_path.map(Path::to_path_buf).unwrap_or_else(|| {
+        let mut path = output_path.as_os_str().to_owned();
+        path.push(".json");
+        PathBuf::from(path)
+    });
+    let report = serde_json::json!({
+        "old": old_dir,
+        "new": new_dir,
+        "pack": output_path,
+        "pack_size": pack_size,
+        "files": files,
+        "deleted": deleted,
+        "unchanged": unchanged,
+        "reused": reused,
+    });
+    fs::write(&report_path, serde_json::to_string_pretty(&report)? + "\n")
+        .with_context(|| format!("Failed to write report: {}", report_path.display()))?;
+
+    if let Some(cache_path) = cache_path {
+        let cache = SyncCache {
+            pack: Some(fs::canonicalize(output_path)?),
+            zstd: enable_zstd,
+            files: paths.into_iter().zip(stamped).collect(),
+        };
+        cache.save(cache_path)?;
+    }
+
+    // Success message
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({} for {} of changed files)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(pack_size),
+            format_bytes(new_size)
+        );
+        println!(
+            "   {} changed, {} added, {} deleted, {} unchanged; {} deltas reused",
+            work.iter().filter(|(_, base, _)| base.is_some()).count(),
+            work.iter().filter(|(_, base, _)| base.is_none()).count(),
+            deleted.len(),
+            unchanged,
+            reused
+        );
+        println!("   Report written to {}", report_path.display());
+        println!("   Generating took {}", format_duration(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// A file's size and modification time, used to tell whether its cached hash is still valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    modified: (u64, u32),
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read file metadata: {}", path.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            size: metadata.len(),
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+        })
+    }
+}
+
+/// The state sync-gen keeps between runs: the hash of every file it read and the pack it wrote
+#[derive(Default)]
+struct SyncCache {
+    pack: Option<PathBuf>,
+    zstd: bool,
+    files: HashMap<PathBuf, (FileStamp, ContentHash)>,
+}
+
+impl SyncCache {
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cache file: {}", path.display()))?;
+        let json: serde_json::Value = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid cache file: {}", path.display()))?;
+
+        // A cache that can't be read only costs a full run
+        let mut files = HashMap::new();
+        for (path, file) in json["files"].as_object().into_iter().flatten() {
+            let stamp = FileStamp {
+                size: file["size"].as_u64().unwrap_or_default(),
+                modified: (
+                    file["mtime"][0].as_u64().unwrap_or_default(),
+                    file["mtime"][1].as_u64().unwrap_or_default() as u32,
+                ),
+            };
+            if let Some(Ok(hash)) = file["hash"].as_str().map(str::parse) {
+                files.insert(PathBuf::from(path), (stamp, hash));
+            }
+        }
+        Ok(Self {
+            pack: json["pack"].as_str().map(PathBuf::from),
+            zstd: json["zstd"].as_bool().unwrap_or_default(),
+            files,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let files: serde_json::Map<String, serde_json::Value> = self
+            .files
+            .iter()
+            .map(|(path, (stamp, hash))| {
+                (
+                    path.to_string_lossy().into_owned(),
+                    serde_json::json!({
+                        "size": stamp.size,
+                        "mtime": [stamp.modified.0, stamp.modified.1],
+                        "hash": hash.to_string(),
+                    }),
+                )
+            })
+            .collect();
+        let json = serde_json::json!({
+            "version": 1,
+            "pack": self.pack,
+            "zstd": self.zstd,
+            "files": files,
+        });
+        fs::write(path, serde_json::to_string(&json)? + "\n")
+            .with_context(|| format!("Failed to write cache file: {}", path.display()))
+    }
+}
+
+/// Lists the regular files under `root` as sorted `/`-separated relative paths
+fn list_files(root: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+        {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                let path = entry.path();
+                let relative = path.strip_prefix(root).expect("walk stays under root");
+                let Some(name) = relative.to_str() else {
+                    bail!("File name is not valid UTF-8: {}", path.display());
+                };
+                files.push(name.replace(std::path::MAIN_SEPARATOR, "/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Runs `f` on every item on `jobs` threads, returning the results in order
+fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<Result<R>>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    *results[i].lock().unwrap() = Some(f(item));
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap()
+                .expect("every item was processed")
+        })
+        .collect()
+}
+
+/// Handle the store stats subcommand
+fn handle_store_stats(store_path: &Path, json: bool) -> Result<()> {
+    if !store_path.join("index").is_file() {
+        bail!("Store not found: {}", store_path.display());
+    }
+
+    let store = DeltaStore::open(store_path)
+        .with_context(|| format!("Failed to open store: {}", store_path.display()))?;
+    let stats = store
+        .stats()
+        .map_err(|e| anyhow::anyhow!("Failed to read store: {}", e))?;
+
+    if json {
+        let versions: Vec<_> = stats
+            .versions
+            .iter()
+            .map(|v| {
+                let refs: Vec<_> = store
+                    .refs()
+                    .filter(|(_, hash)| *hash == v.hash)
+                    .map(|(name, _)| name)
+                    .collect();
+                serde_json::json!({
+                    "hash": v.hash.to_string(),
+                    "base": v.base.map(|base| base.to_string()),
+                    "refs": refs,
+                    "size": v.size,
+                    "stored_size": v.stored_size,
+                    "chunked": v.chunked,
+                    "dedup_ratio": v.dedup_ratio(),
+                    "chain_length": v.chain_length,
+                    "chain_cost": v.chain_cost,
+                })
+            })
+            .collect();
+        let hashes = |hashes: &[ContentHash]| -> Vec<String> {
+            hashes.iter().map(|hash| hash.to_string()).collect()
+        };
+        let report = serde_json::json!({
+            "healthy": stats.is_healthy(),
+            "versions": stats.versions.len(),
+            "keyframes": stats.keyframes(),
+            "refs": stats.refs,
+            "content_size": stats.content_size(),
+            "stored_size": stats.stored_size(),
+            "chunks": stats.chunks,
+            "chunk_size": stats.chunk_size,
+            "dedup_ratio": stats.dedup_ratio(),
+            "max_chain_length": stats.deepest().map_or(0, |v| v.chain_length),
+            "max_chain_cost": stats.costliest().map_or(0, |v| v.chain_cost),
+            "unreferenced": hashes(&stats.unreferenced),
+            "orphaned_objects": stats.orphaned_objects,
+            "missing_objects": hashes(&stats.missing_objects),
+            "version_stats": versions,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("Versions: {}", stats.versions.len());
+        println!("Keyframes: {}", stats.keyframes());
+        println!(
+            "Refs: {} ({} unreferenced versions)",
+            stats.refs,
+            stats.unreferenced.len()
+        );
+        println!("Content size: {}", format_bytes(stats.content_size()));
+        println!("Stored size: {}", format_bytes(stats.stored_size()));
+        if stats.chunks > 0 {
+            println!(
+                "Chunks: {} ({})",
+                stats.chunks,
+                format_bytes(stats.chunk_size)
+            );
+        }
+        println!("Dedup ratio: {:.2}x", stats.dedup_ratio());
+        if let Some(deepest) = stats.deepest() {
+            println!(
+                "Longest chain: {} deltas ({})",
+                deepest.chain_length, deepest.hash
+            );
+        }
+        if let Some(costliest) = stats.costliest() {
+            println!(
+                "Costliest decode: {} ({})",
+                format_bytes(costliest.chain_cost),
+                costliest.hash
+            );
+        }
+        println!("Orphaned objects: {}", stats.orphaned_objects.len());
+        for hash in &stats.missing_objects {
+            println!("{} {}", "Missing object:".bright_red(), hash);
+        }
+    }
+
+    if !stats.is_healthy() {
         bail!(
-            "Output file already exists: {}\n   Use --force to overwrite",
-            output_path.display()
+            "{} versions have missing objects",
+            stats.missing_objects.len()
         );
     }
+    Ok(())
+}
 
-    // Get file sizes
-    let base_size = fs::metadata(base_path)
-        .context("Failed to read base file metadata")?
-        .len();
-    let new_size = fs::metadata(new_path)
-        .context("Failed to read new file metadata")?
-        .len();
-
-    if !quiet {
-        println!(
-            "{} Base: {}, New: {}",
-            "File sizes:".bright_cyan(),
-            format_bytes(base_size),
-            format_bytes(new_size)
-        );
+/// Handle the store gc subcommand
+fn handle_store_gc(store_path: &Path, policy: &GcPolicy, quiet: bool) -> Result<()> {
+    if !store_path.join("index").is_file() {
+        bail!("Store not found: {}", store_path.display());
     }
 
-    // Memory check
-    let required = estimate_encode_memory(base_size, new_size);
-    check_memory(required, yes, quiet)?;
+    let mut store = DeltaStore::open(store_path)
+        .with_context(|| format!("Failed to open store: {}", store_path.display()))?;
+    let stats = store
+        .gc(policy)
+        .map_err(|e| anyhow::anyhow!("Failed to collect garbage: {}", e))?;
 
-    // Read files
     if !quiet {
-        let total_steps = if verify { 4 } else { 3 };
         println!(
-            "{} Reading files...",
-            format!("Step 1/{}:", total_steps).bright_cyan()
+            "{} Removed {} versions and {} chunks, re-based {} onto keyframes, freed {}",
+            "Success:".bright_green().bold(),
+            stats.versions_removed,
+            stats.chunks_removed,
+            stats.versions_rebased,
+            format_bytes(stats.bytes_freed)
         );
     }
 
-    let base_data = fs::read(base_path)
-        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
-    let new_data = fs::read(new_path)
-        .with_context(|| format!("Failed to read new file: {}", new_path.display()))?;
+    Ok(())
+}
 
-    // Encode
-    if !quiet {
-        let total_steps = if verify { 4 } else { 3 };
-        println!(
-            "{} Encoding delta...",
-            format!("Step 2/{}:", total_steps).bright_cyan()
-        );
+/// Handle the fetch-apply subcommand
+fn handle_fetch_apply(
+    url: &str,
+    path: &Path,
+    resume: bool,
+    public_key: Option<&str>,
+    quiet: bool,
+) -> Result<()> {
+    let mut updater = Updater::new(url);
+    if let Some(hex) = public_key {
+        updater = updater.with_public_key(parse_public_key(hex)?);
+    }
+    if !resume {
+        ResumableApply::discard(path)
+            .with_context(|| format!("Failed to remove partial update of {}", path.display()))?;
     }
 
-    let start = Instant::now();
-    let delta = xpatch::delta::encode(tag, &base_data, &new_data, zstd);
-    let encode_time = start.elapsed();
+    let status = updater
+        .update_resumable(path)
+        .map_err(|e| anyhow::anyhow!("Failed to update {}: {}", path.display(), e))?;
 
-    // Write output
     if !quiet {
-        let total_steps = if verify { 4 } else { 3 };
-        println!(
-            "{} Writing output...",
-            format!("Step 3/{}:", total_steps).bright_cyan()
-        );
+        match status {
+            UpdateStatus::UpToDate(version) => println!(
+                "{} {} is up to date ({})",
+                "Success:".bright_green().bold(),
+                path.display(),
+                version
+            ),
+            UpdateStatus::Updated { to, .. } => println!(
+                "{} Updated {} to {}",
+                "Success:".bright_green().bold(),
+                path.display(),
+                to
+            ),
+        }
     }
 
-    fs::write(output_path, &delta)
-        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    Ok(())
+}
 
-    // Verify if requested
-    let verify_result = if verify {
-        if !quiet {
-            println!("{} Verifying delta...", "Step 4/4:".bright_cyan());
+/// Handle the git-filter install subcommand
+fn handle_git_filter_install(patterns: &[String], store: Option<&Path>) -> Result<()> {
+    let store_arg = match store {
+        Some(store) => {
+            fs::create_dir_all(store)
+                .with_context(|| format!("Failed to create directory: {}", store.display()))?;
+            let store = store
+                .canonicalize()
+                .with_context(|| format!("Failed to resolve {}", store.display()))?;
+            format!(" --store \"{}\"", store.display())
         }
+        None => String::new(),
+    };
+    for (key, value) in [
+        (
+            "clean",
+            format!("xpatch git-filter clean{} -- %f", store_arg),
+        ),
+        (
+            "smudge",
+            format!("xpatch git-filter smudge{} -- %f", store_arg),
+        ),
+        ("required", "true".to_string()),
+    ] {
+        git(&["config", &format!("filter.xpatch.{}", key), &value])?;
+    }
 
-        let verify_start = Instant::now();
-
-        // Decode and compare
-        let reconstructed = xpatch::delta::decode(&base_data, &delta)
-            .map_err(|e| anyhow::anyhow!("Verification decode failed: {}", e))?;
-
-        let verify_time = verify_start.elapsed();
+    if !patterns.is_empty() {
+        let top_level = git(&["rev-parse", "--show-toplevel"])?;
+        let attributes_path = Path::new(&top_level).join(".gitattributes");
+        let mut attributes = fs::read_to_string(&attributes_path).unwrap_or_default();
+        for pattern in patterns {
+            let line = format!("{} filter=xpatch -text", pattern);
+            if !attributes.lines().any(|existing| existing == line) {
+                if !attributes.is_empty() && !attributes.ends_with('\n') {
+                    attributes.push('\n');
+                }
+                attributes.push_str(&line);
+                attributes.push('\n');
+            }
+        }
+        fs::write(&attributes_path, attributes)
+            .with_context(|| format!("Failed to write file: {}", attributes_path.display()))?;
+    }
 
-        // Compare
-        if reconstructed != new_data {
-            bail!(
-                "Verification failed: reconstructed output does not match original new file\n   \
-                 Expected {} bytes, got {} bytes",
-                new_data.len(),
-                reconstructed.len()
-            );
+    println!(
+        "{} Installed the xpatch filter{}",
+        "Success:".bright_green().bold(),
+        match patterns.is_empty() {
+            true => String::new(),
+            false => format!(" for {}", patterns.join(", ")),
         }
+    );
+    Ok(())
+}
 
-        Some(verify_time)
+/// Handle the git-filter clean and smudge subcommands
+fn handle_git_filter(store: Option<&Path>, clean: bool) -> Result<()> {
+    let store_path = match store {
+        Some(store) => store.to_path_buf(),
+        None => PathBuf::from(git(&["rev-parse", "--git-common-dir"])?).join("xpatch"),
+    };
+    let mut store = DeltaStore::open(&store_path)
+        .with_context(|| format!("Failed to open store: {}", store_path.display()))?;
+
+    let mut input = Vec::new();
+    io::Read::read_to_end(&mut io::stdin().lock(), &mut input).context("Failed to read stdin")?;
+    let output = if clean {
+        xpatch::store::filter::clean(&mut store, &input).context("Failed to store file")?
     } else {
-        None
+        xpatch::store::filter::smudge(&store, &input).context("Failed to restore file")?
     };
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(&output)
+        .context("Failed to write stdout")?;
+    stdout.flush().context("Failed to write stdout")?;
+    Ok(())
+}
 
-    // Success message
-    if !quiet {
-        println!();
-        println!(
-            "{} Created {} ({}, {:.1}% of new file)",
-            "Success:".bright_green().bold(),
-            output_path.display(),
-            format_bytes(delta.len() as u64),
-            (delta.len() as f64 / new_size as f64) * 100.0
+/// Run git with `args` and return its trimmed output
+fn git(args: &[&str]) -> Result<String> {
+    let output = process::Command::new("git")
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
         );
-        print!("   Encoding took {}", format_duration(encode_time));
-        if let Some(verify_time) = verify_result {
-            print!(", verification took {}", format_duration(verify_time));
-        }
-        println!();
     }
-
-    Ok(())
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
-/// Handle the decode subcommand
-fn handle_decode(
-    base_path: &Path,
-    delta_path: &Path,
+/// Handle the manifest build subcommand
+#[allow(clippy::too_many_arguments)]
+fn handle_manifest_build(
+    releases: &[PathBuf],
     output_path: &Path,
-    yes: bool,
+    channel: &str,
+    patch_dir: Option<&Path>,
+    base_url: &str,
+    signing_key: Option<&Path>,
+    cbor: bool,
     force: bool,
     quiet: bool,
 ) -> Result<()> {
-    // Validate input files
-    if !base_path.exists() {
-        bail!("File not found: {}", base_path.display());
-    }
-    if !delta_path.exists() {
-        bail!("File not found: {}", delta_path.display());
-    }
-
-    // Check if output exists
     if output_path.exists() && !force {
         bail!(
             "Output file already exists: {}\n   Use --force to overwrite",
             output_path.display()
         );
     }
+    let signing_key = match signing_key {
+        Some(path) => {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Failed to read key file: {}", path.display()))?;
+            let seed: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Invalid key file: {}", path.display()))?;
+            Some(SigningKey::from_bytes(&seed))
+        }
+        None => None,
+    };
 
-    // Get file sizes
-    let base_size = fs::metadata(base_path)
-        .context("Failed to read base file metadata")?
-        .len();
-    let delta_size = fs::metadata(delta_path)
-        .context("Failed to read delta file metadata")?
-        .len();
+    let mut contents = Vec::with_capacity(releases.len());
+    let mut builder = manifest::Builder::new(channel);
+    for path in releases {
+        let data =
+            fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            bail!("File name is not valid UTF-8: {}", path.display());
+        };
+        builder = builder.release(name, &data, format!("{}{}", base_url, name));
+        contents.push((name, data));
+    }
+
+    let mut patches = 0;
+    if let Some(patch_dir) = patch_dir {
+        fs::create_dir_all(patch_dir)
+            .with_context(|| format!("Failed to create directory: {}", patch_dir.display()))?;
+        let (latest, new) = contents.last().expect("at least one release");
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        for (name, base) in &contents[..contents.len() - 1] {
+            if base == new {
+                continue;
+            }
+            let patch = xpatch::delta::encode_with_options(0, base, new, &options);
+            let patch_name = format!(
+                "{}-{}.xpatch",
+                &ContentHash::of(base).to_string()[..16],
+                &ContentHash::of(new).to_string()[..16]
+            );
+            let patch_path = patch_dir.join(&patch_name);
+            if patch_path.exists() && !force {
+                bail!(
+                    "Output file already exists: {}\n   Use --force to overwrite",
+                    patch_path.display()
+                );
+            }
+            fs::write(&patch_path, &patch)
+                .with_context(|| format!("Failed to write patch: {}", patch_path.display()))?;
+            builder = builder.patch(name, latest, &patch, format!("{}{}", base_url, patch_name));
+            patches += 1;
+
+            if !quiet {
+                println!(
+                    "{} {} -> {} ({})",
+                    "Patch:".bright_cyan(),
+                    name,
+                    latest,
+                    format_bytes(patch.len() as u64)
+                );
+            }
+        }
+    }
+
+    let manifest = match &signing_key {
+        Some(key) => builder.sign(key),
+        None => builder.build(),
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to build manifest: {}", e))?;
+    let bytes = if cbor {
+        manifest.to_cbor()
+    } else {
+        manifest.to_json().into_bytes()
+    };
+    fs::write(output_path, bytes)
+        .with_context(|| format!("Failed to write manifest: {}", output_path.display()))?;
 
     if !quiet {
         println!(
-            "{} Base: {}, Delta: {}",
-            "File sizes:".bright_cyan(),
-            format_bytes(base_size),
-            format_bytes(delta_size)
+            "{} Wrote {} manifest for {} releases and {} patches to {}",
+            "Success:".bright_green().bold(),
+            if signing_key.is_some() {
+                "a signed"
+            } else {
+                "an unsigned"
+            },
+            manifest.releases.len(),
+            patches,
+            output_path.display()
         );
     }
 
-    // Memory check (estimate output size as ~base_size)
-    let required = estimate_decode_memory(base_size, delta_size);
-    check_memory(required, yes, quiet)?;
+    Ok(())
+}
 
-    // Read files
-    if !quiet {
-        println!("{} Reading files...", "Step 1/3:".bright_cyan());
+/// Handle the manifest verify subcommand
+fn handle_manifest_verify(manifest_path: &Path, public_key: Option<&str>) -> Result<()> {
+    let bytes = fs::read(manifest_path)
+        .with_context(|| format!("Failed to read manifest: {}", manifest_path.display()))?;
+    let manifest = manifest::Manifest::parse(&bytes)
+        .map_err(|e| anyhow::anyhow!("Failed to parse manifest: {}", e))?;
+
+    println!("Channel: {}", manifest.channel);
+    println!("Min format version: {}", manifest.min_format_version);
+    for release in &manifest.releases {
+        println!(
+            "Release: {} ({}, {})",
+            release.name,
+            format_bytes(release.size),
+            release.hash
+        );
+    }
+    for patch in &manifest.patches {
+        let name = |hash| manifest.release(hash).map_or("?", |r| r.name.as_str());
+        println!(
+            "Patch: {} -> {} ({}, {})",
+            name(&patch.from),
+            name(&patch.to),
+            format_bytes(patch.size),
+            patch.url
+        );
     }
 
-    let base_data = fs::read(base_path)
-        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
-    let delta_data = fs::read(delta_path)
-        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+    match public_key {
+        Some(hex) => {
+            let key = parse_public_key(hex)?;
+            manifest
+                .verify(&key)
+                .map_err(|e| anyhow::anyhow!("Verification failed: {}", e))?;
+            println!("{} Signature is valid", "Success:".bright_green().bold());
+        }
+        None if manifest.signature.is_some() => {
+            println!("Signature: present (pass --public-key to check it)");
+        }
+        None => println!("Signature: none"),
+    }
 
-    // Decode
-    if !quiet {
-        println!("{} Decoding delta...", "Step 2/3:".bright_cyan());
+    Ok(())
+}
+
+/// Handle the snapshot subcommand
+fn handle_snapshot(source: &Path, repo_path: &Path, quiet: bool) -> Result<()> {
+    if !source.is_dir() {
+        bail!("Directory not found: {}", source.display());
     }
 
+    let mut repo = BackupRepo::open(repo_path)
+        .with_context(|| format!("Failed to open repository: {}", repo_path.display()))?;
     let start = Instant::now();
-    let output_data = xpatch::delta::decode(&base_data, &delta_data)
-        .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
-    let decode_time = start.elapsed();
+    let stats = repo
+        .snapshot(source)
+        .map_err(|e| anyhow::anyhow!("Failed to back up {}: {}", source.display(), e))?;
 
-    // Write output
     if !quiet {
-        println!("{} Writing output...", "Step 3/3:".bright_cyan());
+        println!(
+            "{} Created snapshot {} ({} files, {})",
+            "Success:".bright_green().bold(),
+            stats.snapshot.id,
+            stats.snapshot.files,
+            format_bytes(stats.snapshot.size)
+        );
+        println!(
+            "   {} files read, {} new chunks, {} stored in {}",
+            stats.files_read,
+            stats.new_chunks,
+            format_bytes(stats.stored_size),
+            format_duration(start.elapsed())
+        );
     }
 
-    fs::write(output_path, &output_data)
-        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    Ok(())
+}
 
-    // Success message
-    if !quiet {
-        println!();
+/// Handle the snapshots subcommand
+fn handle_snapshots(repo_path: &Path) -> Result<()> {
+    if !repo_path.is_dir() {
+        bail!("Repository not found: {}", repo_path.display());
+    }
+
+    let repo = BackupRepo::open(repo_path)
+        .with_context(|| format!("Failed to open repository: {}", repo_path.display()))?;
+    let snapshots = repo
+        .snapshots()
+        .map_err(|e| anyhow::anyhow!("Failed to read snapshots: {}", e))?;
+    for snapshot in snapshots {
         println!(
-            "{} Created {} ({})",
-            "Success:".bright_green().bold(),
-            output_path.display(),
-            format_bytes(output_data.len() as u64)
+            "{}  {}  {:>6} files  {:>10}  {}",
+            snapshot.id,
+            format_time(snapshot.time),
+            snapshot.files,
+            format_bytes(snapshot.size),
+            snapshot.source.display()
         );
-        println!("   Decoding took {}", format_duration(decode_time));
     }
 
     Ok(())
 }
 
-/// Handle the info subcommand
-fn handle_info(delta_path: &Path) -> Result<()> {
-    // Validate input file
-    if !delta_path.exists() {
-        bail!("File not found: {}", delta_path.display());
+/// Handle the restore subcommand
+fn handle_restore(snapshot: &str, repo_path: &Path, output_dir: &Path, quiet: bool) -> Result<()> {
+    if !repo_path.is_dir() {
+        bail!("Repository not found: {}", repo_path.display());
     }
 
-    // Read delta file
-    let delta_data = fs::read(delta_path)
-        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+    let repo = BackupRepo::open(repo_path)
+        .with_context(|| format!("Failed to open repository: {}", repo_path.display()))?;
+    let start = Instant::now();
+    let snapshot = repo
+        .restore(snapshot, output_dir)
+        .map_err(|e| anyhow::anyhow!("Failed to restore snapshot {}: {}", snapshot, e))?;
 
-    // Get tag
-    let tag = xpatch::delta::get_tag(&delta_data)
-        .map_err(|e| anyhow::anyhow!("Failed to read delta tag: {}", e))?;
+    if !quiet {
+        println!(
+            "{} Restored snapshot {} to {} ({} files, {})",
+            "Success:".bright_green().bold(),
+            snapshot.id,
+            output_dir.display(),
+            snapshot.files,
+            format_bytes(snapshot.size)
+        );
+        println!("   Restoring took {}", format_duration(start.elapsed()));
+    }
 
-    println!("Tag: {}", tag);
-    println!("Size: {} bytes", delta_data.len());
+    Ok(())
+}
 
-    // Try to decode header for additional info
-    match xpatch::delta::decode_header(&delta_data) {
-        Ok((algo, _, header_bytes)) => {
-            println!("Algorithm: {:?}", algo);
-            println!("Header size: {} bytes", header_bytes);
-        }
-        Err(_) => {
-            // Don't fail if header can't be decoded
-        }
+/// Handle the prune subcommand
+fn handle_prune(repo_path: &Path, keep_last: usize, quiet: bool) -> Result<()> {
+    if !repo_path.is_dir() {
+        bail!("Repository not found: {}", repo_path.display());
+    }
+
+    let mut repo = BackupRepo::open(repo_path)
+        .with_context(|| format!("Failed to open repository: {}", repo_path.display()))?;
+    let stats = repo
+        .prune(keep_last)
+        .map_err(|e| anyhow::anyhow!("Failed to prune repository: {}", e))?;
+
+    if !quiet {
+        println!(
+            "{} Removed {} snapshots and {} chunks, freed {}",
+            "Success:".bright_green().bold(),
+            stats.snapshots_removed,
+            stats.chunks_removed,
+            format_bytes(stats.bytes_freed)
+        );
     }
 
     Ok(())
@@ -561,7 +3326,53 @@ fn check_memory(required: u64, skip_prompt: bool, quiet: bool) -> Result<()> {
 // Utilities
 // ============================================================================
 
+/// Read an encryption key file (exactly 32 raw bytes)
+fn read_key(path: &Path) -> Result<[u8; xpatch::encryption::KEY_SIZE]> {
+    let key =
+        fs::read(path).with_context(|| format!("Failed to read key file: {}", path.display()))?;
+    key.as_slice().try_into().map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid key file: {} (expected {} bytes, got {})",
+            path.display(),
+            xpatch::encryption::KEY_SIZE,
+            key.len()
+        )
+    })
+}
+
 /// Format bytes in human-readable form
+/// Files at least this large get a progress bar while encoding or decoding.
+const PROGRESS_BAR_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Creates a progress bar on stderr for an operation on `size` bytes, or `None` for small
+/// inputs and in quiet mode. The bar is hidden when stderr is not a terminal.
+fn progress_bar(size: u64, quiet: bool) -> Option<ProgressBar> {
+    if quiet || size < PROGRESS_BAR_MIN_SIZE {
+        return None;
+    }
+
+    let bar = ProgressBar::new(size);
+    bar.set_style(
+        ProgressStyle::with_template("   [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+            .expect("progress bar template is valid")
+            .progress_chars("=> "),
+    );
+    Some(bar)
+}
+
+/// Adds a progress callback to `options` that advances `bar`.
+fn with_progress_bar(options: EncodeOptions, bar: &ProgressBar) -> EncodeOptions {
+    let bar = bar.clone();
+    let interval = (bar.length().unwrap_or(0) / 200).max(1);
+    EncodeOptions {
+        progress_interval: interval,
+        ..options.progress(move |done, total| {
+            bar.set_length(total);
+            bar.set_position(done);
+        })
+    }
+}
+
 fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -578,6 +3389,17 @@ fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Prints the resources an encode or decode used, for `--stats`.
+fn print_stats(stats: &ResourceStats) {
+    println!(
+        "{} Peak heap {}, {} allocations, {}",
+        "Resources:".bright_cyan(),
+        format_bytes(stats.peak_bytes.unwrap_or(0) as u64),
+        stats.allocations.unwrap_or(0),
+        format_duration(stats.elapsed)
+    );
+}
+
 /// Format duration in human-readable form
 fn format_duration(duration: std::time::Duration) -> String {
     let nanos = duration.as_nanos();
@@ -592,3 +3414,58 @@ fn format_duration(duration: std::time::Duration) -> String {
         format!("{:.3}s", duration.as_secs_f64())
     }
 }
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_sha256(hex: &str) -> Result<[u8; 32]> {
+    firmware::parse_sha256(hex).map_err(|e| anyhow::anyhow!(e))
+}
+
+fn parse_public_key(hex: &str) -> Result<VerifyingKey> {
+    from_hex(hex)
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid public key: {}", hex))
+}
+
+/// Format a point in time as UTC, e.g. "2025-01-31 14:05:09"
+fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
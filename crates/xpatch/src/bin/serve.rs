@@ -0,0 +1,463 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! HTTP server handing out patches between versions in a DeltaStore.
+//!
+//! `xpatch-serve publish` adds files to the store, `xpatch-serve run` serves it. Routes:
+//! - `GET /manifest[/<ref>]` - JSON `{"ref", "version", "size"[, "signature"]}` for a ref
+//!   (default: `--ref`), signed with `--signing-key` if given
+//! - `GET /patch/<from>/<to>` - delta from version `<from>` (a hash, or `none` for empty data)
+//!   to `<to>` (a hash or ref name)
+//! - `GET /metrics` - encode and decode statistics in the OpenMetrics text format
+//!
+//! A patch is the delta the store already holds if its base is `<from>`, and is generated (and
+//! cached on disk) otherwise. Responses carry an ETag and patches support single byte ranges.
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use xpatch::delta::{self, EncodeOptions};
+use xpatch::metrics::{self, PrometheusRecorder};
+use xpatch::net::{Manifest, SigningKey};
+use xpatch::store::{ContentHash, DeltaStore, GcPolicy};
+
+#[derive(Parser)]
+#[command(name = "xpatch-serve")]
+#[command(author, version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    /// DeltaStore directory holding the versions to serve
+    #[arg(short, long, global = true, default_value = ".")]
+    store: PathBuf,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Serve patches over HTTP
+    Run {
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        addr: String,
+
+        /// Ref served by /manifest
+        #[arg(short, long, default_value = "latest")]
+        r#ref: String,
+
+        /// Directory for generated patches (default: <store>/patch-cache)
+        #[arg(short, long)]
+        cache: Option<PathBuf>,
+
+        /// Number of worker threads (default: number of CPUs)
+        #[arg(short, long)]
+        threads: Option<usize>,
+
+        /// Sign manifests with the key in this file (see `keygen`)
+        #[arg(short = 'k', long)]
+        signing_key: Option<PathBuf>,
+
+        /// Suppress request logging
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Generate a key for signing manifests and print its public key
+    Keygen {
+        /// File to write the secret key to
+        output: PathBuf,
+    },
+    /// Add a file to the store and point a ref at it
+    Publish {
+        /// File to add
+        file: PathBuf,
+
+        /// Ref to update
+        #[arg(short, long, default_value = "latest")]
+        r#ref: String,
+    },
+    /// Delete versions no ref points to
+    Gc,
+}
+
+struct State {
+    store: DeltaStore,
+    signing_key: Option<SigningKey>,
+    default_ref: String,
+    cache_dir: PathBuf,
+    metrics: Arc<PrometheusRecorder>,
+    quiet: bool,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut store = DeltaStore::open(&cli.store)
+        .with_context(|| format!("Failed to open store: {}", cli.store.display()))?;
+
+    match cli.command {
+        Commands::Run {
+            addr,
+            r#ref,
+            cache,
+            threads,
+            signing_key,
+            quiet,
+        } => {
+            let signing_key = signing_key
+                .map(|path| read_signing_key(&path))
+                .transpose()?;
+            let cache_dir = cache.unwrap_or_else(|| cli.store.join("patch-cache"));
+            fs::create_dir_all(&cache_dir).with_context(|| {
+                format!("Failed to create cache directory: {}", cache_dir.display())
+            })?;
+            let metrics = Arc::new(PrometheusRecorder::new());
+            metrics::set_recorder(metrics.clone());
+            let state = State {
+                store,
+                signing_key,
+                default_ref: r#ref,
+                cache_dir,
+                metrics,
+                quiet,
+            };
+            run(&state, &addr, threads)
+        }
+        Commands::Publish { file, r#ref } => {
+            let data =
+                fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            let hash = store.insert(&data).context("Failed to add file to store")?;
+            store
+                .set_ref(&r#ref, hash)
+                .context("Failed to update ref")?;
+            println!("{} -> {}", r#ref, hash);
+            Ok(())
+        }
+        Commands::Keygen { output } => {
+            if output.exists() {
+                bail!("Key file already exists: {}", output.display());
+            }
+            let mut seed = [0u8; 32];
+            getrandom::fill(&mut seed)
+                .map_err(|e| anyhow::anyhow!("Failed to generate key: {}", e))?;
+            write_secret(&output, &seed)
+                .with_context(|| format!("Failed to write key file: {}", output.display()))?;
+
+            let public_key = SigningKey::from_bytes(&seed).verifying_key();
+            println!("Public key: {}", to_hex(public_key.as_bytes()));
+            Ok(())
+        }
+        Commands::Gc => {
+            let stats = store
+                .gc(&GcPolicy::default())
+                .context("Garbage collection failed")?;
+            println!(
+                "Removed {} unreferenced versions, freed {} bytes",
+                stats.versions_removed, stats.bytes_freed
+            );
+            Ok(())
+        }
+    }
+}
+
+fn run(state: &State, addr: &str, threads: Option<usize>) -> Result<()> {
+    let server =
+        Server::http(addr).map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+    let threads =
+        threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get()));
+
+    if !state.quiet {
+        println!(
+            "Serving {} versions from {} on http://{}",
+            state.store.len(),
+            state.store.path().display(),
+            addr
+        );
+    }
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while let Ok(request) = server.recv() {
+                    handle(state, request);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(state: &State, request: Request) {
+    let response = if matches!(request.method(), Method::Get | Method::Head) {
+        route(state, &request)
+    } else {
+        text_response(405, "Method not allowed")
+    };
+
+    if !state.quiet {
+        println!(
+            "{} {} {}",
+            request.method(),
+            request.url(),
+            response.status_code().0
+        );
+    }
+    let _ = request.respond(response);
+}
+
+fn route(state: &State, request: &Request) -> HttpResponse {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match segments.as_slice() {
+        ["manifest"] => manifest(state, request, &state.default_ref),
+        ["manifest", name] => manifest(state, request, name),
+        ["patch", from, to] => patch(state, request, from, to),
+        ["metrics"] => Response::from_data(state.metrics.render().into_bytes())
+            .with_header(header("Content-Type", PrometheusRecorder::CONTENT_TYPE))
+            .with_header(header("Cache-Control", "no-store")),
+        _ => text_response(404, "Not found"),
+    }
+}
+
+fn manifest(state: &State, request: &Request, name: &str) -> HttpResponse {
+    let Some(version) = state.store.get_ref(name) else {
+        return text_response(404, "Unknown ref");
+    };
+    let size = state.store.info(&version).map_or(0, |info| info.size);
+    let mut manifest = Manifest::new(name, version, size);
+    if let Some(key) = &state.signing_key {
+        manifest.sign(key);
+    }
+    let body = manifest.to_json().into_bytes();
+
+    let etag = etag(&body);
+    if not_modified(request, &etag) {
+        return empty_response(304).with_header(header("ETag", &etag));
+    }
+    Response::from_data(body)
+        .with_header(header("Content-Type", "application/json"))
+        .with_header(header("Cache-Control", "no-cache"))
+        .with_header(header("ETag", &etag))
+}
+
+fn patch(state: &State, request: &Request, from: &str, to: &str) -> HttpResponse {
+    let from = match from {
+        "none" => None,
+        hash => match hash.parse::<ContentHash>() {
+            Ok(hash) if state.store.contains(&hash) => Some(hash),
+            _ => return text_response(404, "Unknown base version"),
+        },
+    };
+    // Patches addressed by hash never change, patches to a ref do when the ref moves
+    let (to, immutable) = match to.parse::<ContentHash>() {
+        Ok(hash) if state.store.contains(&hash) => (hash, true),
+        Ok(_) => return text_response(404, "Unknown version"),
+        Err(_) => match state.store.get_ref(to) {
+            Some(hash) => (hash, false),
+            None => return text_response(404, "Unknown version"),
+        },
+    };
+
+    let data = match load_patch(state, from, to) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to build patch: {}", e);
+            return text_response(500, "Failed to build patch");
+        }
+    };
+
+    let etag = etag(&data);
+    let cache_control = if immutable {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    if not_modified(request, &etag) {
+        return empty_response(304)
+            .with_header(header("ETag", &etag))
+            .with_header(header("Cache-Control", cache_control));
+    }
+
+    // Ignore the range if the client's copy is outdated (If-Range)
+    let range = request_header(request, "Range")
+        .filter(|_| request_header(request, "If-Range").is_none_or(|tag| tag == etag));
+    let total = data.len() as u64;
+    let response = match range.map(|range| parse_range(range, total)) {
+        Some(Some((start, end))) => {
+            Response::from_data(data[start as usize..=end as usize].to_vec())
+                .with_status_code(206)
+                .with_header(header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, end, total),
+                ))
+        }
+        Some(None) if total > 0 => {
+            return text_response(416, "Range not satisfiable")
+                .with_header(header("Content-Range", &format!("bytes */{}", total)));
+        }
+        _ => Response::from_data(data),
+    };
+
+    response
+        .with_header(header("Content-Type", "application/octet-stream"))
+        .with_header(header("Accept-Ranges", "bytes"))
+        .with_header(header("Cache-Control", cache_control))
+        .with_header(header("ETag", &etag))
+}
+
+/// Picks the stored delta if it is based on `from`, else generates a patch and caches it.
+fn load_patch(state: &State, from: Option<ContentHash>, to: ContentHash) -> io::Result<Vec<u8>> {
+    if state.store.info(&to).is_some_and(|info| info.base == from) {
+        return state.store.read_raw(&to);
+    }
+
+    let from_name = from.map_or("none".to_string(), |hash| hash.to_string());
+    let cache_path = state.cache_dir.join(format!("{}-{}", from_name, to));
+    match fs::read(&cache_path) {
+        Ok(data) => return Ok(data),
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let base = match from {
+        Some(hash) => state.store.get(&hash)?,
+        None => Vec::new(),
+    };
+    let new = state.store.get(&to)?;
+    let options = EncodeOptions {
+        checksum: true,
+        ..EncodeOptions::default()
+    };
+    let data = delta::encode_with_options(0, &base, &new, &options);
+
+    // Workers may generate the same patch concurrently, so each writes its own temp file
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let tmp_path = cache_path.with_extension(format!(
+        "tmp{}",
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp_path, &data)?;
+    fs::rename(&tmp_path, &cache_path)?;
+    Ok(data)
+}
+
+/// Parses a single `bytes=` range into inclusive bounds within `total` bytes.
+///
+/// Returns `None` if the range cannot be satisfied.
+fn parse_range(range: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (total.checked_sub(suffix.min(total))?, total.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, total.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(total - 1)),
+    };
+    (start <= end && end < total).then_some((start, end))
+}
+
+fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read key file: {}", path.display()))?;
+    let seed: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid key file: {}", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Writes a file only the current user can read.
+fn write_secret(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data)
+}
+
+fn etag(data: &[u8]) -> String {
+    format!("\"{}\"", to_hex(&Sha256::digest(data)[..16]))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn not_modified(request: &Request, etag: &str) -> bool {
+    request_header(request, "If-None-Match").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*")
+    })
+}
+
+fn request_header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn text_response(status: u16, message: &str) -> HttpResponse {
+    Response::from_string(message)
+        .with_status_code(StatusCode(status))
+        .with_header(header("Content-Type", "text/plain; charset=utf-8"))
+}
+
+fn empty_response(status: u16) -> HttpResponse {
+    Response::from_data(Vec::new()).with_status_code(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-500", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+}
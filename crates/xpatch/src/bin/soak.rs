@@ -0,0 +1,290 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Soak test: encodes and decodes randomized version streams for a long time while tracking
+//! memory, and fails on leaks or unbounded growth.
+//!
+//! Every round takes a fresh pair from `xpatch::testing::arbitrary_pair` and runs it through
+//! delta encoding with random options (checked by `roundtrip_check`), the streaming encoder and
+//! decoder with random chunk sizes, and a delta against the previous round's version. Between
+//! rounds nothing but that previous version is alive, so after a warmup the heap has to return
+//! to the same size every round. A counting global allocator tracks the heap; the resident set
+//! size covers memory allocated outside Rust (zstd's C allocations, for instance).
+//!
+//! ```bash
+//! xpatch-soak --duration 4h --seed 42
+//! ```
+
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use std::time::{Duration, Instant};
+use xpatch::delta::{self, EncodeOptions};
+use xpatch::stats::{self, CountingAllocator};
+use xpatch::stream::{StreamDecoder, StreamEncoder};
+use xpatch::testing::{arbitrary_pair, roundtrip_check};
+
+// ============================================================================
+// Allocation Tracking
+// ============================================================================
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Memory usage at a point between rounds.
+#[derive(Clone, Copy)]
+struct Sample {
+    /// Live heap bytes, not counting the version carried over to the next round
+    heap: u64,
+    /// Live heap allocations, likewise
+    blocks: u64,
+    /// Resident set size in bytes, 0 where unavailable
+    rss: u64,
+}
+
+struct Monitor {
+    system: sysinfo::System,
+    pid: Option<sysinfo::Pid>,
+}
+
+impl Monitor {
+    fn new() -> Self {
+        Self {
+            system: sysinfo::System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+        }
+    }
+
+    /// Samples memory usage; `carried` is the version kept for the next round.
+    fn sample(&mut self, carried: &Vec<u8>) -> Sample {
+        // Refresh first, so sysinfo's own bookkeeping is in place when the heap is measured
+        let rss = match self.pid {
+            Some(pid) => {
+                self.system.refresh_processes_specifics(
+                    sysinfo::ProcessesToUpdate::Some(&[pid]),
+                    false,
+                    sysinfo::ProcessRefreshKind::nothing().with_memory(),
+                );
+                self.system.process(pid).map_or(0, |p| p.memory())
+            }
+            None => 0,
+        };
+        let carried_blocks = usize::from(carried.capacity() > 0);
+        Sample {
+            heap: (stats::live_bytes() - carried.capacity()) as u64,
+            blocks: (stats::live_blocks() - carried_blocks) as u64,
+            rss,
+        }
+    }
+}
+
+// ============================================================================
+// CLI Structure
+// ============================================================================
+
+/// Soak test for xpatch: randomized encode/decode rounds with leak detection
+#[derive(Parser)]
+#[command(name = "xpatch-soak")]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// How long to run, e.g. 90s, 30m or 4h
+    #[arg(short, long, default_value = "1h", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// Stop after this many rounds, even if time is left
+    #[arg(short, long)]
+    rounds: Option<u64>,
+
+    /// Seed of the first round; round i uses seed + i
+    #[arg(short, long, default_value = "0")]
+    seed: u64,
+
+    /// Rounds before the memory baseline is taken
+    #[arg(short, long, default_value = "200")]
+    warmup: u64,
+
+    /// Allowed heap growth over the baseline, in KiB
+    #[arg(long, default_value = "1024")]
+    max_heap_growth: u64,
+
+    /// Allowed resident set growth over the baseline, in MiB
+    #[arg(long, default_value = "64")]
+    max_rss_growth: u64,
+
+    /// Seconds between progress reports
+    #[arg(long, default_value = "10")]
+    report_every: u64,
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", value))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        _ => return Err(format!("unknown duration unit: {}", unit)),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+// ============================================================================
+// Workload
+// ============================================================================
+
+/// Runs one round for `seed` and returns the version to carry over to the next round.
+fn round(seed: u64, previous: &[u8]) -> Result<Vec<u8>, String> {
+    let (base, new) = arbitrary_pair(seed);
+    let pick = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+
+    // Delta encoding with varying options, checked through every decoding path
+    let options = EncodeOptions {
+        enable_zstd: pick & 1 != 0,
+        checksum: pick & 2 != 0,
+        optimize: pick & 4 != 0,
+        block_size: if pick & 24 == 0 { 64 } else { 0 },
+        ..EncodeOptions::default()
+    };
+    let delta = delta::encode_with_options(seed as usize, &base, &new, &options);
+    roundtrip_check(&base, &new, &delta).map_err(|e| format!("delta: {}", e))?;
+
+    // Streaming with uneven chunk sizes
+    let chunk = 1 + (pick >> 8) as usize % 4096;
+    let mut encoder = StreamEncoder::with_window_size(&base[..], 0, pick & 1 != 0, 4 * chunk);
+    let mut stream = Vec::new();
+    for piece in new.chunks(chunk) {
+        stream.extend(encoder.push(piece));
+    }
+    stream.extend(encoder.finish());
+
+    let mut decoder = StreamDecoder::new(&base[..]);
+    let mut decoded = Vec::with_capacity(new.len());
+    for piece in stream.chunks(1 + chunk / 3) {
+        decoded.extend(decoder.push(piece).map_err(|e| format!("stream: {}", e))?);
+    }
+    decoder.finish().map_err(|e| format!("stream: {}", e))?;
+    if decoded != new {
+        return Err("stream: output differs".to_string());
+    }
+
+    // The version stream: this round's version against the previous one
+    let delta = delta::encode(0, previous, &new, pick & 1 != 0);
+    roundtrip_check(previous, &new, &delta).map_err(|e| format!("chain: {}", e))?;
+
+    Ok(new)
+}
+
+// ============================================================================
+// Main Entry Point
+// ============================================================================
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut monitor = Monitor::new();
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut previous = Vec::new();
+    let mut baseline: Option<Sample> = None;
+    let mut rounds = 0u64;
+
+    println!(
+        "Soaking for {}s from seed {} ({} warmup rounds)",
+        cli.duration.as_secs(),
+        cli.seed,
+        cli.warmup
+    );
+
+    while start.elapsed() < cli.duration && cli.rounds.is_none_or(|limit| rounds < limit) {
+        let seed = cli.seed.wrapping_add(rounds);
+        previous = round(seed, &previous)
+            .map_err(|e| anyhow::anyhow!(e))
+            .with_context(|| format!("Round failed (seed {})", seed))?;
+        previous.shrink_to_fit();
+        rounds += 1;
+
+        if rounds == cli.warmup {
+            baseline = Some(monitor.sample(&previous));
+        }
+
+        let report_due = last_report.elapsed().as_secs() >= cli.report_every;
+        if let Some(baseline) = baseline
+            && (report_due || rounds.is_multiple_of(1000))
+        {
+            let sample = monitor.sample(&previous);
+            check(&cli, &baseline, &sample, seed)?;
+            if report_due {
+                report(start, rounds, &baseline, &sample);
+                last_report = Instant::now();
+            }
+        }
+    }
+
+    let sample = monitor.sample(&previous);
+    match baseline {
+        Some(baseline) => {
+            check(&cli, &baseline, &sample, cli.seed.wrapping_add(rounds))?;
+            report(start, rounds, &baseline, &sample);
+        }
+        None => println!(
+            "Only {} rounds ran, fewer than the warmup; memory was not checked",
+            rounds
+        ),
+    }
+
+    println!("Soak test passed: {} rounds", rounds);
+    Ok(())
+}
+
+/// Fails if memory grew past the allowed limits.
+fn check(cli: &Cli, baseline: &Sample, sample: &Sample, seed: u64) -> Result<()> {
+    let heap_growth = sample.heap.saturating_sub(baseline.heap);
+    if heap_growth > cli.max_heap_growth * 1024 {
+        bail!(
+            "Heap grew by {} bytes in {} allocations since warmup (around seed {})",
+            heap_growth,
+            sample.blocks as i64 - baseline.blocks as i64,
+            seed
+        );
+    }
+    let rss_growth = sample.rss.saturating_sub(baseline.rss);
+    if rss_growth > cli.max_rss_growth * 1024 * 1024 {
+        bail!(
+            "Resident set grew by {} MiB since warmup (around seed {})",
+            rss_growth / 1024 / 1024,
+            seed
+        );
+    }
+    Ok(())
+}
+
+fn report(start: Instant, rounds: u64, baseline: &Sample, sample: &Sample) {
+    println!(
+        "[{:>6}s] {} rounds, {} allocations, heap {} bytes in {} blocks ({:+}), rss {} KiB ({:+})",
+        start.elapsed().as_secs(),
+        rounds,
+        stats::allocations(),
+        sample.heap,
+        sample.blocks,
+        sample.heap as i64 - baseline.heap as i64,
+        sample.rss / 1024,
+        sample.rss as i64 / 1024 - baseline.rss as i64 / 1024
+    );
+}
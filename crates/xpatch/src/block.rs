@@ -0,0 +1,605 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Block-level patching for devices and disk images.
+//!
+//! Firmware updates on embedded Linux often write a new filesystem image to the inactive
+//! partition of an A/B pair while the system runs from the active one. Setting
+//! [`EncodeOptions::block_size`] produces a delta made of whole-block literals and copies that
+//! start on block boundaries, so unchanged and moved blocks cost a few bytes each.
+//! [`apply_to_block_device`] then streams the new image to the target block by block:
+//!
+//! - copies are read from the source (the active partition),
+//! - each finished block is compared with what the target already holds, and only blocks
+//!   that differ are written, which saves flash wear when the target holds an older image,
+//! - neither image is held in memory, only the delta and one block.
+//!
+//! Any GDelta delta can be applied this way, but only block mode guarantees that the delta
+//! consists of aligned blocks.
+//!
+//! With the `reflink` feature, [`apply_reflink`] uses the same block-wise writing to patch
+//! files on copy-on-write filesystems: the output starts as a clone of the base file and only
+//! changed blocks are rewritten.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::fs::OpenOptions;
+//! use xpatch::block::apply_to_block_device;
+//!
+//! let delta = std::fs::read("rootfs.xdelta")?;
+//! let mut active = OpenOptions::new().read(true).open("/dev/mmcblk0p2")?;
+//! let mut inactive = OpenOptions::new().read(true).write(true).open("/dev/mmcblk0p3")?;
+//!
+//! let stats = apply_to_block_device(&mut active, &delta, &mut inactive, 4096)?;
+//! inactive.sync_all()?;
+//! println!("{} blocks written, {} unchanged", stats.blocks_written, stats.blocks_unchanged);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+#[cfg(doc)]
+use crate::delta::EncodeOptions;
+use crate::delta::{self, Algorithm};
+use crate::varint::decode_varint;
+#[cfg(feature = "reflink")]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "reflink")]
+use std::ops::Range;
+#[cfg(feature = "reflink")]
+use std::path::Path;
+
+/// Block size [`apply_reflink`] rewrites its output in: the usual filesystem block size, so
+/// blocks that don't change keep sharing storage with the base.
+#[cfg(feature = "reflink")]
+const REFLINK_BLOCK_SIZE: usize = 4096;
+
+/// Summary of an [`apply_to_block_device`] run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApplyStats {
+    /// Size of the reconstructed image in bytes
+    pub size: u64,
+    /// Blocks that differed from the target's contents and were written
+    pub blocks_written: u64,
+    /// Blocks the target already held
+    pub blocks_unchanged: u64,
+}
+
+/// Applies a GDelta delta from `source` to `target`, writing only changed blocks.
+///
+/// `source` must hold the base data the delta was created from, starting at offset 0.
+/// The reconstructed image is written to `target` from offset 0 in chunks of `block_size`
+/// bytes; each chunk is first read back from `target` and skipped if identical. Data beyond
+/// the end of the image is left untouched, so `target` may be larger than the image.
+///
+/// If the delta carries an output checksum, it is verified after the image was written.
+/// The base checksum is not checked, as `source` may be larger than the base data.
+///
+/// # Errors
+/// Fails with [`ErrorKind::InvalidInput`] if `block_size` is 0 or the delta is not a GDelta
+/// delta (encode with [`EncodeOptions::block_size`] to ensure it is), and with
+/// [`ErrorKind::InvalidData`] if the delta is corrupted or the output checksum does not match.
+/// I/O errors of `source` and `target` are passed through.
+pub fn apply_to_block_device<S, T>(
+    source: &mut S,
+    delta: &[u8],
+    target: &mut T,
+    block_size: usize,
+) -> io::Result<ApplyStats>
+where
+    S: Read + Seek,
+    T: Read + Write + Seek,
+{
+    if block_size == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Block size must not be zero",
+        ));
+    }
+
+    let header = delta::parse_header(delta).map_err(invalid_data)?;
+    if header.encrypted {
+        return Err(invalid_data("Delta is encrypted"));
+    }
+    let decompressed;
+    let payload = match header.algorithm {
+        Algorithm::GDelta => &delta[header.size..],
+        Algorithm::GDeltaZstd => {
+            decompressed = crate::delta::zstd_decompress(&delta[header.size..])
+                .map_err(|_| invalid_data("Error decompressing zstd data"))?;
+            &decompressed[..]
+        }
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Delta is not a GDelta delta",
+            ));
+        }
+    };
+
+    let mut pos = 0;
+    let instructions_len = read_varint(payload, &mut pos)?;
+    let instructions = payload
+        .get(pos..pos.saturating_add(instructions_len))
+        .ok_or_else(|| invalid_data("Truncated delta"))?;
+    let mut literals = &payload[pos + instructions_len..];
+
+    let mut writer = BlockWriter::new(target, block_size);
+    let mut holes = delta::HoleFiller::new(&header.holes);
+    let mut pos = 0;
+    while pos < instructions.len() {
+        let head = instructions[pos];
+        pos += 1;
+        let mut length = (head & 0x3F) as usize;
+        if head & 0x40 != 0 {
+            length |= read_varint(instructions, &mut pos)?
+                .checked_mul(64)
+                .ok_or_else(|| invalid_data("Invalid delta"))?;
+        }
+
+        if head & 0x80 != 0 {
+            let offset = read_varint(instructions, &mut pos)?;
+            source.seek(SeekFrom::Start(offset as u64))?;
+            while length > 0 {
+                let (zeros, n) = holes.next(length);
+                writer.write_zeros(zeros)?;
+                writer.copy_from(source, n)?;
+                length -= n;
+            }
+        } else {
+            if literals.len() < length {
+                return Err(invalid_data("Truncated delta"));
+            }
+            let (mut run, rest) = literals.split_at(length);
+            while !run.is_empty() {
+                let (zeros, n) = holes.next(run.len());
+                writer.write_zeros(zeros)?;
+                writer.write(&run[..n])?;
+                run = &run[n..];
+            }
+            literals = rest;
+        }
+    }
+    writer.write_zeros(holes.finish().map_err(invalid_data)?)?;
+
+    let (stats, checksum) = writer.finish()?;
+    if header
+        .output_checksum
+        .is_some_and(|expected| expected != checksum)
+    {
+        return Err(invalid_data("Output checksum mismatch"));
+    }
+    Ok(stats)
+}
+
+/// Applies a delta to the file at `base_path`, writing the result to `out_path` as a
+/// copy-on-write clone of the base.
+///
+/// The base is first cloned to `out_path` (`FICLONE` on btrfs and XFS, `clonefile` on APFS),
+/// which shares its storage instead of copying it. The output is then written over the clone
+/// in 4 KiB blocks, skipping those the clone already holds, so only changed ranges take new
+/// space and time: patching a huge file with a small change is nearly instant. On filesystems
+/// without reflink support the base is copied instead, with the same result.
+///
+/// GDelta deltas are streamed like [`apply_to_block_device`], reading copies from the base
+/// file; other deltas are decoded in memory first. The holes of deltas encoded with
+/// [`EncodeOptions::hole_size`] are punched into the output on Linux (`fallocate`), so they
+/// take no space there either. An existing file at `out_path` is replaced, and `out_path` is
+/// removed again if applying fails. The output checksum is verified if the delta carries one.
+/// Requires the `reflink` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// use xpatch::block::apply_reflink;
+///
+/// let delta = std::fs::read("disk-v2.xdelta")?;
+/// let stats = apply_reflink("disk-v1.img", &delta, "disk-v2.img")?;
+/// println!("{} blocks written, {} shared", stats.blocks_written, stats.blocks_unchanged);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// # Errors
+/// Fails with [`ErrorKind::InvalidData`] if the delta is corrupted or does not apply to the
+/// base. I/O errors, including those of cloning the base, are passed through.
+#[cfg(feature = "reflink")]
+pub fn apply_reflink(
+    base_path: impl AsRef<Path>,
+    delta: &[u8],
+    out_path: impl AsRef<Path>,
+) -> io::Result<ApplyStats> {
+    let (base_path, out_path) = (base_path.as_ref(), out_path.as_ref());
+    match fs::remove_file(out_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    reflink_copy::reflink_or_copy(base_path, out_path)?;
+
+    let result = rewrite_clone(base_path, delta, out_path);
+    if result.is_err() {
+        let _ = fs::remove_file(out_path);
+    }
+    result
+}
+
+/// Writes the output of `delta` over `out_path`, a clone of the base, for [`apply_reflink`].
+#[cfg(feature = "reflink")]
+fn rewrite_clone(base_path: &Path, delta: &[u8], out_path: &Path) -> io::Result<ApplyStats> {
+    let mut base = File::open(base_path)?;
+    let mut out = OpenOptions::new().read(true).write(true).open(out_path)?;
+
+    let header = delta::parse_header(delta).ok();
+    let streamable = header.as_ref().is_some_and(|header| {
+        !header.encrypted
+            && !header.stored
+            && matches!(header.algorithm, Algorithm::GDelta | Algorithm::GDeltaZstd)
+    });
+    let holes = header.map(|header| header.holes).unwrap_or_default();
+    // Blocks the clone holds within holes now read as zeros and aren't written
+    punch_holes(&out, &holes)?;
+    let stats = if streamable {
+        apply_to_block_device(&mut base, delta, &mut out, REFLINK_BLOCK_SIZE)?
+    } else {
+        let mut base_data = Vec::new();
+        base.read_to_end(&mut base_data)?;
+        let new_data = delta::decode(&base_data, delta).map_err(invalid_data)?;
+        let mut writer = BlockWriter::new(&mut out, REFLINK_BLOCK_SIZE);
+        writer.write(&new_data)?;
+        writer.finish()?.0
+    };
+    out.set_len(stats.size)?;
+    // Zeros the writer had to write, e.g. past the end of the clone, are deallocated again
+    punch_holes(&out, &holes)?;
+    Ok(stats)
+}
+
+/// Deallocates the whole blocks within `holes` in `file`, which then read as zeros.
+///
+/// Only Linux punches holes (`fallocate`); elsewhere, and on filesystems that can't, this does
+/// nothing and the zeros are written as data.
+#[cfg(feature = "reflink")]
+fn punch_holes(file: &File, holes: &[Range<usize>]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    for hole in holes {
+        use std::os::fd::AsRawFd;
+
+        let start = hole.start.next_multiple_of(REFLINK_BLOCK_SIZE);
+        let end = hole.end / REFLINK_BLOCK_SIZE * REFLINK_BLOCK_SIZE;
+        if start >= end {
+            continue;
+        }
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        // Safety: the descriptor belongs to `file`, which outlives the call
+        let result = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                mode,
+                start as libc::off_t,
+                (end - start) as libc::off_t,
+            )
+        };
+        if result != 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                ErrorKind::Unsupported => Ok(()),
+                _ => Err(err),
+            };
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, holes);
+    Ok(())
+}
+
+/// Collects output into blocks and writes those that differ from the target.
+struct BlockWriter<'a, T> {
+    target: &'a mut T,
+    block_size: usize,
+    block: Vec<u8>,
+    current: Vec<u8>,
+    hasher: crc32fast::Hasher,
+    stats: ApplyStats,
+}
+
+impl<'a, T: Read + Write + Seek> BlockWriter<'a, T> {
+    fn new(target: &'a mut T, block_size: usize) -> Self {
+        Self {
+            target,
+            block_size,
+            block: Vec::with_capacity(block_size),
+            current: Vec::with_capacity(block_size),
+            hasher: crc32fast::Hasher::new(),
+            stats: ApplyStats::default(),
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let n = (self.block_size - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == self.block_size {
+                self.flush_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_zeros(&mut self, mut length: usize) -> io::Result<()> {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        while length > 0 {
+            let n = length.min(ZEROS.len());
+            self.write(&ZEROS[..n])?;
+            length -= n;
+        }
+        Ok(())
+    }
+
+    fn copy_from<S: Read>(&mut self, source: &mut S, mut length: usize) -> io::Result<()> {
+        while length > 0 {
+            let start = self.block.len();
+            let n = (self.block_size - start).min(length);
+            self.block.resize(start + n, 0);
+            source.read_exact(&mut self.block[start..])?;
+            length -= n;
+            if self.block.len() == self.block_size {
+                self.flush_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        self.hasher.update(&self.block);
+
+        let position = SeekFrom::Start(self.stats.size);
+        self.target.seek(position)?;
+        self.current.resize(self.block.len(), 0);
+        let unchanged = read_full(self.target, &mut self.current)? == self.block.len()
+            && self.current == self.block;
+
+        if unchanged {
+            self.stats.blocks_unchanged += 1;
+        } else {
+            self.target.seek(position)?;
+            self.target.write_all(&self.block)?;
+            self.stats.blocks_written += 1;
+        }
+        self.stats.size += self.block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last partial block and returns the stats and CRC32 of the output.
+    fn finish(mut self) -> io::Result<(ApplyStats, u32)> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+        self.target.flush()?;
+        Ok((self.stats, self.hasher.finalize()))
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let rest = &bytes[*pos..];
+    let Some(last) = rest.iter().take(10).position(|b| b & 0x80 == 0) else {
+        return Err(invalid_data("Invalid varint in delta"));
+    };
+    let (value, len) = decode_varint(&rest[..=last]);
+    *pos += len;
+    Ok(value)
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::EncodeOptions;
+    use std::io::Cursor;
+
+    const BLOCK: usize = 512;
+
+    fn block_options() -> EncodeOptions {
+        EncodeOptions {
+            block_size: BLOCK,
+            checksum: true,
+            ..EncodeOptions::default()
+        }
+    }
+
+    /// An image of distinct blocks, block `i` filled from seed `i`.
+    fn image(blocks: impl IntoIterator<Item = u32>) -> Vec<u8> {
+        blocks
+            .into_iter()
+            .flat_map(|seed| {
+                let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+                (0..BLOCK).map(move |_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (state >> 16) as u8
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_mode_roundtrip() {
+        let base = image(0..64);
+        // Changed, moved and appended blocks
+        let new = image(
+            (0..10)
+                .chain([100, 101])
+                .chain(30..64)
+                .chain(12..20)
+                .chain([102]),
+        );
+
+        let delta = delta::encode_with_options(5, &base, &new, &block_options());
+        assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+        assert_eq!(delta::get_tag(&delta).unwrap(), 5);
+        // Three new blocks plus instructions
+        assert!(delta.len() < 4 * BLOCK, "delta is {} bytes", delta.len());
+    }
+
+    #[test]
+    fn test_block_mode_partial_last_block() {
+        let base = image(0..8);
+        let mut new = base.clone();
+        new.truncate(new.len() - 100);
+        new[BLOCK + 1] ^= 0xFF;
+
+        for target in [Vec::new(), base.clone()] {
+            let delta = delta::encode_with_options(0, &base, &new, &block_options());
+            assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+
+            let mut target = Cursor::new(target);
+            apply_to_block_device(&mut Cursor::new(&base), &delta, &mut target, BLOCK).unwrap();
+            assert_eq!(&target.get_ref()[..new.len()], &new[..]);
+        }
+    }
+
+    #[test]
+    fn test_apply_writes_only_changed_blocks() {
+        let base = image(0..32);
+        let mut new = base.clone();
+        new[3 * BLOCK..4 * BLOCK].copy_from_slice(&image([99]));
+        new[20 * BLOCK + 7] ^= 1;
+        let delta = delta::encode_with_options(0, &base, &new, &block_options());
+
+        // The inactive partition holds the previous image
+        let mut target = Cursor::new(base.clone());
+        let stats =
+            apply_to_block_device(&mut Cursor::new(&base), &delta, &mut target, BLOCK).unwrap();
+        assert_eq!(target.into_inner(), new);
+        assert_eq!(
+            stats,
+            ApplyStats {
+                size: new.len() as u64,
+                blocks_written: 2,
+                blocks_unchanged: 30,
+            }
+        );
+
+        // An empty target receives everything
+        let mut target = Cursor::new(Vec::new());
+        let stats =
+            apply_to_block_device(&mut Cursor::new(&base), &delta, &mut target, BLOCK).unwrap();
+        assert_eq!(target.into_inner(), new);
+        assert_eq!(stats.blocks_written, 32);
+    }
+
+    #[test]
+    fn test_apply_leaves_trailing_data() {
+        let base = image(0..4);
+        let new = image([0, 1, 7]);
+        let delta = delta::encode_with_options(0, &base, &new, &block_options());
+
+        let mut device = image(0..8);
+        let mut target = Cursor::new(&mut device[..]);
+        apply_to_block_device(&mut Cursor::new(&base), &delta, &mut target, 4 * BLOCK).unwrap();
+        assert_eq!(&device[..new.len()], &new[..]);
+        assert_eq!(&device[new.len()..], &image(3..8)[..]);
+    }
+
+    #[cfg(feature = "reflink")]
+    #[test]
+    fn test_apply_reflink() {
+        let dir = std::env::temp_dir().join(format!("xpatch-reflink-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (base_path, out_path) = (dir.join("base.img"), dir.join("out.img"));
+
+        let base = image(0..64);
+        let mut new = base.clone();
+        new.truncate(40 * BLOCK);
+        new[9 * BLOCK..10 * BLOCK].copy_from_slice(&image([99]));
+        fs::write(&base_path, &base).unwrap();
+
+        let options = EncodeOptions::default();
+        let sparse = [&new[..], &[0; 64 * BLOCK]].concat();
+        for delta in [
+            delta::encode_with_options(0, &base, &new, &block_options()),
+            delta::encode_with_options(0, &base, &new, &options),
+            delta::encode_with_options(0, &base, b"small", &options),
+            delta::encode_with_options(0, &base, &sparse, &options.clone().hole_size(BLOCK)),
+        ] {
+            // A stale output is replaced
+            fs::write(&out_path, image(90..100)).unwrap();
+            let stats = apply_reflink(&base_path, &delta, &out_path).unwrap();
+            let expected = delta::decode(&base, &delta).unwrap();
+            assert_eq!(fs::read(&out_path).unwrap(), expected);
+            assert_eq!(stats.size, expected.len() as u64);
+        }
+
+        let stats = apply_reflink(&base_path, &delta::encode(0, &base, &new, true), &out_path);
+        assert_eq!(stats.unwrap().blocks_written, 1);
+
+        let err = apply_reflink(&base_path, b"\xFF\x00", &out_path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(!out_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let base = image(0..4);
+        let new = image([0, 5, 2, 3]);
+        let delta = delta::encode_with_options(0, &base, &new, &block_options());
+        let apply = |source: &[u8], delta: &[u8], block_size| {
+            apply_to_block_device(
+                &mut Cursor::new(source),
+                delta,
+                &mut Cursor::new(Vec::new()),
+                block_size,
+            )
+        };
+
+        let err = apply(&base, &delta, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // Source does not hold the base
+        let err = apply(&base[..BLOCK], &delta, BLOCK).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = apply(&image(10..14), &delta, BLOCK).unwrap_err();
+        assert_eq!(err.to_string(), "Output checksum mismatch");
+
+        // Not a GDelta delta
+        let err = apply(b"abc", &delta::encode(0, b"abc", b"abcdef", false), BLOCK).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let err = apply(&base, &delta[..delta.len() - 10], BLOCK).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,673 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Caches of encoded patches.
+//!
+//! Update servers hand out the same few patches over and over: every client on the previous
+//! release asks for the patch to the latest one. A [`PatchCache`] keeps encoded patches in a
+//! [`Backend`], so each one is encoded once:
+//!
+//! - [`MemoryBackend`] holds them in the process, evicting the least recently used ones beyond
+//!   a byte budget,
+//! - [`DiskBackend`] writes one file per patch to a directory, surviving restarts,
+//! - `StorageBackend` (feature `store`) keeps them in a delta store's storage, and
+//! - `RedisBackend` (feature `redis`) shares them between all instances of a server.
+//!
+//! [`PatchCache::get_or_insert_with`] protects against stampedes: when many requests for a
+//! patch that is not cached arrive at once, one of them computes it and the others wait for
+//! its result instead of encoding it again.
+//!
+//! Patches are usually identified by a [`PatchKey`], the hashes of their base and target. The
+//! memory backend accepts any key; the disk and Redis backends need keys implementing
+//! [`CacheKey`]. [`PatchService`](crate::service::PatchService) keeps its patches in a
+//! `PatchCache` as well.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::cache::{PatchCache, PatchKey};
+//!
+//! let base = b"Hello, World!";
+//! let target = b"Hello, Rust World!";
+//! let key = PatchKey::new([1; 32], [2; 32]);
+//!
+//! let cache = PatchCache::memory(64 * 1024 * 1024);
+//! let encode = || Ok::<_, std::io::Error>(xpatch::delta::encode(0, base, target, true));
+//! let patch = cache.get_or_insert_with(key, encode)?;
+//! assert_eq!(xpatch::delta::decode(base, &patch).unwrap(), target);
+//!
+//! // Served from the cache
+//! assert_eq!(cache.get(&key), Some(patch));
+//! assert_eq!(cache.stats().hits, 1);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisBackend;
+
+/// Identifies a patch by the hashes of its base and target, e.g. their SHA-256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PatchKey {
+    /// Hash of the base the patch applies to
+    pub base: [u8; 32],
+    /// Hash of the data the patch produces
+    pub target: [u8; 32],
+}
+
+impl PatchKey {
+    /// Creates a key from the hashes of a base and a target.
+    pub const fn new(base: [u8; 32], target: [u8; 32]) -> Self {
+        Self { base, target }
+    }
+}
+
+impl fmt::Display for PatchKey {
+    /// Formats the key as `<base hex>-<target hex>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.base {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str("-")?;
+        for byte in &self.target {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "store")]
+impl From<(crate::store::ContentHash, crate::store::ContentHash)> for PatchKey {
+    fn from((base, target): (crate::store::ContentHash, crate::store::ContentHash)) -> Self {
+        Self::new(*base.as_bytes(), *target.as_bytes())
+    }
+}
+
+/// A key that can name a patch outside the process, as a file name or Redis key.
+pub trait CacheKey {
+    /// Returns a name that is stable across processes and unique per key, made of ASCII
+    /// letters, digits, `-` and `.`.
+    fn cache_key(&self) -> String;
+}
+
+impl CacheKey for PatchKey {
+    fn cache_key(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Keys of [`PatchService`](crate::service::PatchService), which caches patches per profile
+/// index. Patches of the first profile are named after the key alone.
+impl<K: CacheKey> CacheKey for (usize, K) {
+    fn cache_key(&self) -> String {
+        match self.0 {
+            0 => self.1.cache_key(),
+            profile => format!("{}.{}", self.1.cache_key(), profile),
+        }
+    }
+}
+
+/// Storage of a [`PatchCache`].
+///
+/// Backends are best-effort: [`PatchCache`] treats a failed lookup as a miss and a failed
+/// write as a patch that wasn't cached, and counts both in [`CacheStats::errors`].
+pub trait Backend<K>: Send + Sync {
+    /// Returns the patch stored for `key`.
+    fn get(&self, key: &K) -> io::Result<Option<Arc<[u8]>>>;
+
+    /// Stores `patch` for `key`, replacing any previous one.
+    fn put(&self, key: &K, patch: &Arc<[u8]>) -> io::Result<()>;
+
+    /// Removes the patch stored for `key`, if any.
+    fn remove(&self, key: &K) -> io::Result<()>;
+
+    /// Removes all patches.
+    fn clear(&self) -> io::Result<()>;
+
+    /// Returns the number of stored patches and their total size in bytes, if the backend
+    /// keeps track of them.
+    fn usage(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+/// Keeps patches in memory, evicting the least recently used ones once their total size
+/// exceeds a budget.
+pub struct MemoryBackend<K> {
+    capacity: usize,
+    lru: Mutex<Lru<K>>,
+}
+
+struct Lru<K> {
+    patches: HashMap<K, (Arc<[u8]>, u64)>,
+    /// Keys by last use
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    bytes: usize,
+}
+
+impl<K: Hash + Eq + Clone> Lru<K> {
+    fn remove(&mut self, key: &K) {
+        if let Some((patch, used)) = self.patches.remove(key) {
+            self.recency.remove(&used);
+            self.bytes -= patch.len();
+        }
+    }
+
+    fn touch(&mut self, key: &K) -> u64 {
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.clock
+    }
+}
+
+impl<K> MemoryBackend<K> {
+    /// Creates a backend holding at most `capacity` bytes of patches; 0 stores nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Mutex::new(Lru {
+                patches: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                bytes: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru<K>> {
+        // The map stays consistent if a panic unwinds through a lock holder
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send> Backend<K> for MemoryBackend<K> {
+    fn get(&self, key: &K) -> io::Result<Option<Arc<[u8]>>> {
+        let mut lru = self.lock();
+        let Some((patch, used)) = lru.patches.get(key) else {
+            return Ok(None);
+        };
+        let (patch, used) = (patch.clone(), *used);
+        lru.recency.remove(&used);
+        let used = lru.touch(key);
+        lru.patches.get_mut(key).unwrap().1 = used;
+        Ok(Some(patch))
+    }
+
+    fn put(&self, key: &K, patch: &Arc<[u8]>) -> io::Result<()> {
+        if patch.len() > self.capacity {
+            return Ok(());
+        }
+        let mut lru = self.lock();
+        lru.remove(key);
+        let used = lru.touch(key);
+        lru.bytes += patch.len();
+        lru.patches.insert(key.clone(), (patch.clone(), used));
+        while lru.bytes > self.capacity {
+            let Some((_, oldest)) = lru.recency.pop_first() else {
+                break;
+            };
+            if let Some((patch, _)) = lru.patches.remove(&oldest) {
+                lru.bytes -= patch.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        self.lock().remove(key);
+        Ok(())
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        let mut lru = self.lock();
+        lru.patches.clear();
+        lru.recency.clear();
+        lru.bytes = 0;
+        Ok(())
+    }
+
+    fn usage(&self) -> Option<(usize, usize)> {
+        let lru = self.lock();
+        Some((lru.patches.len(), lru.bytes))
+    }
+}
+
+/// Keeps one file per patch in a directory, named after the key's [`CacheKey::cache_key`].
+///
+/// Nothing is evicted; remove stale patches with [`Backend::remove`] or [`Backend::clear`].
+/// Patches are written to a temporary file and renamed, so concurrent readers, also in other
+/// processes, never see a partial patch.
+pub struct DiskBackend {
+    dir: PathBuf,
+}
+
+impl DiskBackend {
+    /// Opens a cache directory, creating it if needed.
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Returns the cache directory.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    fn file(&self, key: &impl CacheKey) -> PathBuf {
+        self.dir.join(key.cache_key())
+    }
+}
+
+impl<K: CacheKey> Backend<K> for DiskBackend {
+    fn get(&self, key: &K) -> io::Result<Option<Arc<[u8]>>> {
+        match fs::read(self.file(key)) {
+            Ok(patch) => Ok(Some(patch.into())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &K, patch: &Arc<[u8]>) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let name = key.cache_key();
+        let temp = self.dir.join(format!(
+            ".{}.{}-{}.tmp",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, patch)?;
+        fs::rename(&temp, self.dir.join(name)).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        match fs::remove_file(self.file(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps patches in the [`Storage`](crate::store::Storage) of a delta store, under
+/// `patches/<cache key>`, next to the versions they were encoded from.
+///
+/// Like [`DiskBackend`], nothing is evicted, and the store's garbage collection leaves the
+/// patches alone.
+#[cfg(feature = "store")]
+pub struct StorageBackend<S> {
+    storage: S,
+}
+
+#[cfg(feature = "store")]
+impl<S: crate::store::Storage> StorageBackend<S> {
+    /// Prefix of the storage keys patches are stored under.
+    pub const PREFIX: &'static str = "patches/";
+
+    /// Stores patches in `storage`, e.g. a clone of [`DeltaStore::storage`].
+    ///
+    /// [`DeltaStore::storage`]: crate::store::DeltaStore::storage
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Returns the storage.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+}
+
+#[cfg(feature = "store")]
+impl<K, S> Backend<K> for StorageBackend<S>
+where
+    K: CacheKey,
+    S: crate::store::Storage + Send + Sync,
+{
+    fn get(&self, key: &K) -> io::Result<Option<Arc<[u8]>>> {
+        let patch = self
+            .storage
+            .get(&format!("{}{}", Self::PREFIX, key.cache_key()))?;
+        Ok(patch.map(Into::into))
+    }
+
+    fn put(&self, key: &K, patch: &Arc<[u8]>) -> io::Result<()> {
+        self.storage
+            .put(&format!("{}{}", Self::PREFIX, key.cache_key()), patch)
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        self.storage
+            .delete(&format!("{}{}", Self::PREFIX, key.cache_key()))
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        for key in self.storage.list(Self::PREFIX)? {
+            self.storage.delete(&key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters of a [`PatchCache`], see [`PatchCache::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the backend
+    pub hits: u64,
+    /// Lookups the backend had no patch for
+    pub misses: u64,
+    /// Requests that waited for another request computing the same patch
+    pub coalesced: u64,
+    /// Backend operations that failed
+    pub errors: u64,
+}
+
+/// Thread-safe patch cache over a [`Backend`], see the [module documentation](self).
+pub struct PatchCache<K> {
+    backend: Box<dyn Backend<K>>,
+    state: Mutex<State<K>>,
+    /// Signalled whenever a computation finishes
+    finished: Condvar,
+}
+
+struct State<K> {
+    /// Keys being computed
+    in_flight: HashSet<K>,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone> PatchCache<K> {
+    /// Creates a cache storing patches in `backend`.
+    pub fn new(backend: impl Backend<K> + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+            state: Mutex::new(State {
+                in_flight: HashSet::new(),
+                stats: CacheStats::default(),
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Creates a cache holding at most `capacity` bytes of patches in memory.
+    pub fn memory(capacity: usize) -> Self
+    where
+        K: Send + 'static,
+    {
+        Self::new(MemoryBackend::new(capacity))
+    }
+
+    /// Returns the backend.
+    pub fn backend(&self) -> &dyn Backend<K> {
+        &*self.backend
+    }
+
+    /// Returns the patch cached for `key`.
+    pub fn get(&self, key: &K) -> Option<Arc<[u8]>> {
+        let patch = self.lookup(key);
+        let mut state = self.lock();
+        match patch {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1,
+        }
+        patch
+    }
+
+    /// Caches `patch` for `key`.
+    pub fn insert(&self, key: &K, patch: &Arc<[u8]>) {
+        if self.backend.put(key, patch).is_err() {
+            self.lock().stats.errors += 1;
+        }
+    }
+
+    /// Drops the patch cached for `key`.
+    pub fn remove(&self, key: &K) {
+        if self.backend.remove(key).is_err() {
+            self.lock().stats.errors += 1;
+        }
+    }
+
+    /// Drops all cached patches.
+    pub fn clear(&self) {
+        if self.backend.clear().is_err() {
+            self.lock().stats.errors += 1;
+        }
+    }
+
+    /// Returns the patch cached for `key`, or computes it with `compute` and caches it.
+    ///
+    /// While `compute` runs, other requests for the same key wait for it and then read its
+    /// result from the cache. If it fails, they compute the patch themselves.
+    pub fn get_or_insert_with<E>(
+        &self,
+        key: K,
+        compute: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<[u8]>, E> {
+        if let Some(patch) = self.lookup(&key) {
+            self.lock().stats.hits += 1;
+            return Ok(patch);
+        }
+
+        let mut state = self.lock();
+        let mut coalesced = false;
+        while state.in_flight.contains(&key) {
+            if !coalesced {
+                coalesced = true;
+                state.stats.coalesced += 1;
+            }
+            state = self.finished.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.in_flight.insert(key.clone());
+        drop(state);
+        let flight = Flight { cache: self, key };
+
+        // Another request may have cached the patch since the first lookup
+        if let Some(patch) = self.lookup(&flight.key) {
+            self.lock().stats.hits += 1;
+            return Ok(patch);
+        }
+        self.lock().stats.misses += 1;
+        let patch: Arc<[u8]> = compute()?.into();
+        self.insert(&flight.key, &patch);
+        Ok(patch)
+    }
+
+    /// Returns the cache's counters.
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    fn lookup(&self, key: &K) -> Option<Arc<[u8]>> {
+        self.backend.get(key).unwrap_or_else(|_| {
+            self.lock().stats.errors += 1;
+            None
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<K>> {
+        // The state stays consistent if a panic unwinds through a lock holder
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A key being computed by [`PatchCache::get_or_insert_with`], released even if the
+/// computation fails or panics.
+struct Flight<'a, K: Hash + Eq + Clone> {
+    cache: &'a PatchCache<K>,
+    key: K,
+}
+
+impl<K: Hash + Eq + Clone> Drop for Flight<'_, K> {
+    fn drop(&mut self) {
+        self.cache.lock().in_flight.remove(&self.key);
+        self.cache.finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    fn key(n: u8) -> PatchKey {
+        PatchKey::new([n; 32], [n + 1; 32])
+    }
+
+    #[test]
+    fn test_memory_backend_evicts() {
+        let backend = MemoryBackend::new(100);
+        let patch = |len: usize| -> Arc<[u8]> { vec![0; len].into() };
+        backend.put(&1, &patch(40)).unwrap();
+        backend.put(&2, &patch(40)).unwrap();
+        // Using 1 makes 2 the least recently used
+        backend.get(&1).unwrap().unwrap();
+        backend.put(&3, &patch(40)).unwrap();
+        assert!(backend.get(&2).unwrap().is_none());
+        assert!(backend.get(&1).unwrap().is_some());
+        assert_eq!(backend.usage(), Some((2, 80)));
+
+        backend.put(&4, &patch(101)).unwrap();
+        assert!(backend.get(&4).unwrap().is_none());
+        backend.clear().unwrap();
+        assert_eq!(backend.usage(), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_disk_backend() {
+        let dir = std::env::temp_dir().join(format!("xpatch-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = PatchCache::new(DiskBackend::new(&dir).unwrap());
+        assert_eq!(cache.get(&key(1)), None);
+        let patch = cache
+            .get_or_insert_with(key(1), || Ok::<_, ()>(b"patch".to_vec()))
+            .unwrap();
+        assert_eq!(&*patch, b"patch");
+        assert!(dir.join(key(1).to_string()).exists());
+
+        // A second cache over the same directory sees the patch
+        let other = PatchCache::new(DiskBackend::new(&dir).unwrap());
+        assert_eq!(other.get(&key(1)).as_deref(), Some(&b"patch"[..]));
+        other.remove(&key(1));
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 3,
+                coalesced: 0,
+                errors: 0
+            }
+        );
+
+        assert_eq!((0, key(1)).cache_key(), key(1).to_string());
+        assert_eq!((2, key(1)).cache_key(), format!("{}.2", key(1)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "store")]
+    #[test]
+    fn test_storage_backend() {
+        use crate::store::{DeltaStore, GcPolicy, Storage};
+
+        let dir = std::env::temp_dir().join(format!("xpatch-cache-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let base = store.insert(b"Hello, World!").unwrap();
+        let target = store.insert(b"Hello, Rust World!").unwrap();
+        store.set_ref("latest", target).unwrap();
+
+        let cache = PatchCache::new(StorageBackend::new(store.storage().clone()));
+        let key = PatchKey::from((base, target));
+        cache.insert(&key, &Arc::from(&b"patch"[..]));
+        let stored = format!("patches/{}", key);
+        assert_eq!(store.storage().get(&stored).unwrap().unwrap(), b"patch");
+
+        // Neither the store nor its garbage collection mind the patches
+        store.gc(&GcPolicy::default()).unwrap();
+        let store = DeltaStore::open(&dir).unwrap();
+        assert_eq!(store.get(&target).unwrap(), b"Hello, Rust World!");
+        assert_eq!(cache.get(&key).as_deref(), Some(&b"patch"[..]));
+
+        cache.clear();
+        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.stats().errors, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stampede_computes_once() {
+        let cache = PatchCache::memory(1024);
+        let computed = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let patch = cache
+                        .get_or_insert_with(key(1), || {
+                            computed.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(std::time::Duration::from_millis(50));
+                            Ok::<_, ()>(b"patch".to_vec())
+                        })
+                        .unwrap();
+                    assert_eq!(&*patch, b"patch");
+                });
+            }
+        });
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 7);
+
+        // A failed computation is not cached and releases the key
+        assert_eq!(
+            cache.get_or_insert_with(key(2), || Err("failed")),
+            Err("failed")
+        );
+        let patch = cache.get_or_insert_with(key(2), || Ok::<_, ()>(b"retry".to_vec()));
+        assert_eq!(patch.as_deref(), Ok(&b"retry"[..]));
+    }
+}
@@ -0,0 +1,226 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! [`Backend`] in a Redis server.
+
+use super::{Backend, CacheKey};
+use redis::{Commands, Connection};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Keeps patches in Redis, shared by every process using the same server and prefix.
+///
+/// Each patch is a string value under `<prefix><cache key>`. With [`ttl`](Self::ttl), patches
+/// expire on their own; otherwise configure an eviction policy such as `allkeys-lru` on the
+/// server. The connection is opened on first use and re-opened after an error.
+pub struct RedisBackend {
+    client: redis::Client,
+    connection: Mutex<Option<Connection>>,
+    prefix: String,
+    ttl: Option<Duration>,
+}
+
+impl RedisBackend {
+    /// Creates a backend for the server at `url`, e.g. `redis://127.0.0.1/`, storing patches
+    /// under keys starting with `prefix`.
+    pub fn new(url: &str, prefix: impl Into<String>) -> io::Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url).map_err(io::Error::other)?,
+            connection: Mutex::new(None),
+            prefix: prefix.into(),
+            ttl: None,
+        })
+    }
+
+    /// Lets stored patches expire after `ttl`, rounded up to whole seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn key(&self, key: &impl CacheKey) -> String {
+        format!("{}{}", self.prefix, key.cache_key())
+    }
+
+    /// Runs `f` on the connection, dropping the connection if it fails.
+    fn run<T>(&self, f: impl FnOnce(&mut Connection) -> redis::RedisResult<T>) -> io::Result<T> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        if connection.is_none() {
+            *connection = Some(self.client.get_connection().map_err(io::Error::other)?);
+        }
+        let result = f(connection.as_mut().unwrap());
+        if result.is_err() {
+            *connection = None;
+        }
+        result.map_err(io::Error::other)
+    }
+}
+
+impl<K: CacheKey> Backend<K> for RedisBackend {
+    fn get(&self, key: &K) -> io::Result<Option<Arc<[u8]>>> {
+        let key = self.key(key);
+        let patch: Option<Vec<u8>> = self.run(|connection| connection.get(&key))?;
+        Ok(patch.map(Into::into))
+    }
+
+    fn put(&self, key: &K, patch: &Arc<[u8]>) -> io::Result<()> {
+        let key = self.key(key);
+        match self.ttl {
+            Some(ttl) => {
+                let seconds = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+                self.run(|connection| connection.set_ex(&key, &**patch, seconds.max(1)))
+            }
+            None => self.run(|connection| connection.set(&key, &**patch)),
+        }
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        let key = self.key(key);
+        self.run(|connection| connection.del(&key))
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        let pattern = format!("{}*", glob_escape(&self.prefix));
+        let keys: Vec<String> =
+            self.run(|connection| Ok(connection.scan_match(&pattern)?.collect()))?;
+        for batch in keys.chunks(512) {
+            self.run(|connection| connection.del::<_, ()>(batch))?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the characters Redis glob patterns treat specially.
+fn glob_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{PatchCache, PatchKey};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Reads one RESP command, an array of bulk strings.
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::new();
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).ok()?;
+            arg.truncate(len);
+            args.push(arg);
+        }
+        Some(args)
+    }
+
+    fn bulk(data: &[u8]) -> Vec<u8> {
+        let mut reply = format!("${}\r\n", data.len()).into_bytes();
+        reply.extend_from_slice(data);
+        reply.extend_from_slice(b"\r\n");
+        reply
+    }
+
+    /// Serves GET, SET, SETEX, DEL and SCAN from a map, answering everything else with OK.
+    fn serve(stream: TcpStream, data: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        while let Some(args) = read_command(&mut reader) {
+            let mut data = data.lock().unwrap();
+            let reply = match args[0].to_ascii_uppercase().as_slice() {
+                b"GET" => data.get(&args[1]).map_or(b"$-1\r\n".to_vec(), |v| bulk(v)),
+                b"SET" => {
+                    data.insert(args[1].clone(), args[2].clone());
+                    b"+OK\r\n".to_vec()
+                }
+                b"SETEX" => {
+                    data.insert(args[1].clone(), args[3].clone());
+                    b"+OK\r\n".to_vec()
+                }
+                b"DEL" => {
+                    let removed = args[1..].iter().filter(|k| data.remove(*k).is_some());
+                    format!(":{}\r\n", removed.count()).into_bytes()
+                }
+                b"SCAN" => {
+                    let keys: Vec<_> = data.keys().cloned().collect();
+                    let mut reply = b"*2\r\n$1\r\n0\r\n".to_vec();
+                    reply.extend_from_slice(format!("*{}\r\n", keys.len()).as_bytes());
+                    for key in keys {
+                        reply.extend_from_slice(&bulk(&key));
+                    }
+                    reply
+                }
+                _ => b"+OK\r\n".to_vec(),
+            };
+            writer.write_all(&reply).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_redis_backend() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let data = Arc::new(Mutex::new(HashMap::new()));
+        let server_data = data.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let data = server_data.clone();
+                thread::spawn(move || serve(stream.unwrap(), data));
+            }
+        });
+
+        let key = PatchKey::new([1; 32], [2; 32]);
+        let backend = RedisBackend::new(&url, "xpatch:").unwrap();
+        let cache = PatchCache::new(backend.ttl(Duration::from_millis(1500)));
+        let patch = cache
+            .get_or_insert_with(key, || Ok::<_, ()>(b"patch".to_vec()))
+            .unwrap();
+        assert_eq!(&*patch, b"patch");
+        let stored = format!("xpatch:{}", key).into_bytes();
+        assert_eq!(data.lock().unwrap()[&stored], b"patch");
+
+        let other = PatchCache::new(RedisBackend::new(&url, "xpatch:").unwrap());
+        assert_eq!(other.get(&key).as_deref(), Some(&b"patch"[..]));
+        other.clear();
+        assert!(data.lock().unwrap().is_empty());
+        assert_eq!(cache.stats().errors, 0);
+        assert_eq!(other.stats().errors, 0);
+
+        let unreachable = PatchCache::new(RedisBackend::new("redis://127.0.0.1:1/", "").unwrap());
+        assert_eq!(unreachable.get(&key), None);
+        assert_eq!(unreachable.stats().errors, 1);
+    }
+}
@@ -0,0 +1,636 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Deltas between compressed files.
+//!
+//! Compression hides similarity: changing one file in a `.tar.gz` changes nearly every byte
+//! after it, so a delta between two compressed archives is about as large as the new archive.
+//! [`encode`] looks through gzip and zstd compression instead. It decompresses the inputs,
+//! encodes a delta between their contents, and records how the new file was compressed, so
+//! that decoding reproduces its original compressed bytes exactly. With the `compressed`
+//! feature enabled, [`delta::decode`](crate::delta::decode) applies such deltas transparently.
+//!
+//! Recompression has to be bit-exact, so an input is only looked into if xpatch finds settings
+//! that reproduce it: zlib levels 1–9 for single-member gzip files, and levels 1–22 with the
+//! frame's checksum and content size flags, compressed in one piece or in multithreaded jobs,
+//! for single-frame zstd files. This covers files written by the `zstd` tool, `pigz`, and
+//! programs using zlib or libzstd with default parameters. GNU `gzip` has its own deflate
+//! implementation, which zlib does not reproduce. Other inputs, including plain ones, are
+//! diffed as they are.
+//!
+//! # Format
+//!
+//! ```text
+//! 0xFC 0x00 "CZ" | version u8 | base recipe | new recipe | delta
+//! recipe = 0                                    stored as is
+//!        | 1 | varint(header length) header | level u8      gzip
+//!        | 2 | level u8 | flags u8 (1 checksum, 2 content size, 4 multithreaded)   zstd
+//! ```
+//!
+//! The first two bytes form an invalid delta header, so a wrapped delta is never mistaken for
+//! a plain one. The gzip trailer is recomputed from the decompressed data.
+//!
+//! # Example
+//!
+//! ```
+//! use std::io::Write;
+//! use xpatch::{compressed, delta};
+//!
+//! let gzip = |data: &[u8]| {
+//!     let mut encoder =
+//!         flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+//!     encoder.write_all(data).unwrap();
+//!     encoder.finish().unwrap()
+//! };
+//! let lines: Vec<String> = (0..2000).map(|i| format!("line {i}\n")).collect();
+//! let base = gzip(lines.concat().as_bytes());
+//! let new = gzip(lines.concat().replace("line 1234\n", "line 1234, edited\n").as_bytes());
+//!
+//! let patch = compressed::encode(0, &base, &new, &delta::EncodeOptions::default());
+//! assert!(patch.len() < delta::encode(0, &base, &new, true).len());
+//! assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+//! ```
+
+use crate::delta::EncodeOptions;
+use crate::delta::read_header_varint;
+use crate::formats;
+use crate::varint::encode_varint;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'C', b'Z'];
+const VERSION: u8 = 1;
+
+const GZIP_MAGIC: [u8; 3] = [0x1F, 0x8B, 0x08];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+const ZSTD_CHECKSUM: u8 = 1;
+const ZSTD_CONTENT_SIZE: u8 = 2;
+const ZSTD_MULTITHREADED: u8 = 4;
+
+/// How an input was compressed, as found by [`detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compressed {
+    /// A single-member gzip file written with zlib at `level`
+    Gzip {
+        /// Everything before the deflate stream: magic, flags, mtime, file name, ...
+        header: Vec<u8>,
+        /// zlib compression level, 1-9
+        level: u32,
+    },
+    /// A single zstd frame written with libzstd at `level`
+    Zstd {
+        /// zstd compression level, 1-22
+        level: i32,
+        /// Whether the frame ends with a checksum
+        checksum: bool,
+        /// Whether the frame header records the decompressed size
+        content_size: bool,
+        /// Whether the frame was compressed in jobs by zstd's multithreaded mode, which
+        /// splits blocks differently; the output does not depend on the number of workers
+        multithreaded: bool,
+    },
+}
+
+impl Compressed {
+    /// Compresses `data` the same way the detected input was compressed.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compressed::Gzip { header, level } => {
+                let mut out = deflate(data, *level, header.clone());
+                out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out
+            }
+            Compressed::Zstd { .. } => {
+                zstd_compress(data, self, Vec::new()).expect("writing to a Vec cannot fail")
+            }
+        }
+    }
+
+    fn write_recipe(&self, out: &mut Vec<u8>) {
+        match self {
+            Compressed::Gzip { header, level } => {
+                out.push(1);
+                out.extend(encode_varint(header.len()));
+                out.extend_from_slice(header);
+                out.push(*level as u8);
+            }
+            Compressed::Zstd {
+                level,
+                checksum,
+                content_size,
+                multithreaded,
+            } => {
+                let mut flags = 0;
+                if *checksum {
+                    flags |= ZSTD_CHECKSUM;
+                }
+                if *content_size {
+                    flags |= ZSTD_CONTENT_SIZE;
+                }
+                if *multithreaded {
+                    flags |= ZSTD_MULTITHREADED;
+                }
+                out.extend_from_slice(&[2, *level as u8, flags]);
+            }
+        }
+    }
+}
+
+/// Finds compression settings that reproduce `data` exactly, or `None` if `data` is not a
+/// gzip or zstd file xpatch can recompress.
+pub fn detect(data: &[u8]) -> Option<Compressed> {
+    detect_and_decompress(data).map(|(compressed, _)| compressed)
+}
+
+/// Encodes a delta between the decompressed contents of `base_data` and `new_data`.
+///
+/// Inputs that [`detect`] does not recognize are diffed as they are. Contents are diffed with
+/// [`formats::encode`], so compressed tarballs get member-by-member deltas. If neither input is
+/// recognized, that delta is returned as is. If only one is, a compressed input is diffed
+/// against a plain one; both deltas are encoded then, and the smaller one is returned.
+///
+/// Deltas for [`FORMAT_V1`](crate::delta::FORMAT_V1) decoders, which do not know the envelope,
+/// diff the inputs as they are.
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let base = detect_and_decompress(base_data);
+    let new = detect_and_decompress(new_data);
+    if (base.is_none() && new.is_none()) || options.target_format < crate::delta::FORMAT_V2 {
+        return formats::encode(tag, base_data, new_data, options);
+    }
+    let mixed = base.is_none() || new.is_none();
+
+    let (base_recipe, base_contents) = match &base {
+        Some((compressed, contents)) => (Some(compressed), contents.as_slice()),
+        None => (None, base_data),
+    };
+    let (new_recipe, new_contents) = match &new {
+        Some((compressed, contents)) => (Some(compressed), contents.as_slice()),
+        None => (None, new_data),
+    };
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    for recipe in [base_recipe, new_recipe] {
+        match recipe {
+            Some(compressed) => compressed.write_recipe(&mut out),
+            None => out.push(0),
+        }
+    }
+    out.extend(formats::encode(tag, base_contents, new_contents, options));
+
+    if mixed {
+        let plain = formats::encode(tag, base_data, new_data, options);
+        if plain.len() <= out.len() {
+            return plain;
+        }
+    }
+    out
+}
+
+/// Whether `data` was produced by [`encode`] from at least one compressed input.
+pub fn is_wrapped(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// A delta produced by [`encode`], split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<'a> {
+    /// How the base file was compressed, if it was
+    pub base: Option<Compressed>,
+    /// How the new file is compressed, if it is
+    pub new: Option<Compressed>,
+    /// The delta between the decompressed contents
+    pub delta: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// Splits a delta produced by [`encode`] into its parts.
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        const INVALID: &str = "Invalid compressed delta envelope";
+
+        if !is_wrapped(data) {
+            return Err(INVALID);
+        }
+        match data.get(MAGIC.len()) {
+            Some(&VERSION) => {}
+            Some(_) => return Err("Unsupported compressed delta version"),
+            None => return Err(INVALID),
+        }
+        let mut pos = MAGIC.len() + 1;
+        let base = read_recipe(data, &mut pos).ok_or(INVALID)?;
+        let new = read_recipe(data, &mut pos).ok_or(INVALID)?;
+        Ok(Self {
+            base,
+            new,
+            delta: &data[pos..],
+        })
+    }
+
+    /// Decompresses the base data if it was compressed when the delta was encoded.
+    pub(crate) fn base_contents<'b>(
+        &self,
+        base_data: &'b [u8],
+    ) -> Result<std::borrow::Cow<'b, [u8]>, &'static str> {
+        match &self.base {
+            Some(compressed) => decompress(compressed, base_data)
+                .map(Into::into)
+                .ok_or("Base data is not compressed as expected"),
+            None => Ok(base_data.into()),
+        }
+    }
+
+    /// Compresses the decoded contents into the new file.
+    pub(crate) fn new_data(&self, contents: Vec<u8>) -> Vec<u8> {
+        match &self.new {
+            Some(compressed) => compressed.compress(&contents),
+            None => contents,
+        }
+    }
+}
+
+fn read_recipe(data: &[u8], pos: &mut usize) -> Option<Option<Compressed>> {
+    let kind = *data.get(*pos)?;
+    *pos += 1;
+    match kind {
+        0 => Some(None),
+        1 => {
+            let len = read_header_varint(data, pos).ok()?;
+            let end = pos.checked_add(len)?;
+            let header = data.get(*pos..end)?.to_vec();
+            let level = *data.get(end)?;
+            if !(1..=9).contains(&level) {
+                return None;
+            }
+            *pos = end + 1;
+            Some(Some(Compressed::Gzip {
+                header,
+                level: level as u32,
+            }))
+        }
+        2 => {
+            let &[level, flags] = data.get(*pos..*pos + 2)? else {
+                return None;
+            };
+            if !(1..=22).contains(&level) {
+                return None;
+            }
+            *pos += 2;
+            Some(Some(Compressed::Zstd {
+                level: level as i32,
+                checksum: flags & ZSTD_CHECKSUM != 0,
+                content_size: flags & ZSTD_CONTENT_SIZE != 0,
+                multithreaded: flags & ZSTD_MULTITHREADED != 0,
+            }))
+        }
+        _ => None,
+    }
+}
+
+fn detect_and_decompress(data: &[u8]) -> Option<(Compressed, Vec<u8>)> {
+    if data.starts_with(&GZIP_MAGIC) {
+        detect_gzip(data)
+    } else if data.starts_with(&ZSTD_MAGIC) {
+        detect_zstd(data)
+    } else {
+        None
+    }
+}
+
+fn decompress(compressed: &Compressed, data: &[u8]) -> Option<Vec<u8>> {
+    match compressed {
+        Compressed::Gzip { .. } => {
+            let header_len = gzip_header_len(data)?;
+            inflate(data.get(header_len..data.len().checked_sub(8)?)?)
+        }
+        Compressed::Zstd { .. } => zstd::decode_all(data).ok(),
+    }
+}
+
+/// Length of the gzip member header, up to the deflate stream.
+fn gzip_header_len(data: &[u8]) -> Option<usize> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let flags = *data.get(3)?;
+    if flags & 0xE0 != 0 {
+        return None;
+    }
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += data.get(pos..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    (pos <= data.len()).then_some(pos)
+}
+
+/// Decompresses a raw deflate stream that must span all of `stream`.
+pub(crate) fn inflate(stream: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = flate2::bufread::DeflateDecoder::new(stream);
+    let mut contents = Vec::new();
+    decoder.read_to_end(&mut contents).ok()?;
+    decoder.get_ref().is_empty().then_some(contents)
+}
+
+fn detect_gzip(data: &[u8]) -> Option<(Compressed, Vec<u8>)> {
+    let header_len = gzip_header_len(data)?;
+    let stream = data.get(header_len..data.len().checked_sub(8)?)?;
+    let contents = inflate(stream)?;
+    let trailer = &data[data.len() - 8..];
+    if trailer[..4] != crc32fast::hash(&contents).to_le_bytes()
+        || trailer[4..] != (contents.len() as u32).to_le_bytes()
+    {
+        return None;
+    }
+
+    // XFL hints at the level gzip used
+    let hint = match data[8] {
+        2 => 9,
+        4 => 1,
+        _ => 6,
+    };
+    let level = deflate_level(stream, &contents, hint)?;
+    let header = data[..header_len].to_vec();
+    Some((Compressed::Gzip { header, level }, contents))
+}
+
+/// Appends `data` to `out` as a raw deflate stream written by zlib at `level`.
+pub(crate) fn deflate(data: &[u8], level: u32, out: Vec<u8>) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(out, Compression::new(level));
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec cannot fail");
+    encoder.finish().expect("writing to a Vec cannot fail")
+}
+
+/// Finds the zlib level that compresses `contents` into exactly `stream`, trying `hint` first.
+pub(crate) fn deflate_level(stream: &[u8], contents: &[u8], hint: u32) -> Option<u32> {
+    std::iter::once(hint)
+        .chain((1..=9).filter(|&level| level != hint))
+        .find(|&level| {
+            let mut encoder = DeflateEncoder::new(Expect::new(stream), Compression::new(level));
+            encoder
+                .write_all(contents)
+                .and_then(|()| encoder.finish())
+                .is_ok_and(|expect| expect.is_complete())
+        })
+}
+
+fn detect_zstd(data: &[u8]) -> Option<(Compressed, Vec<u8>)> {
+    const DICTIONARY_ID: u8 = 0x03;
+    const CHECKSUM: u8 = 0x04;
+
+    if zstd::zstd_safe::find_frame_compressed_size(data).ok()? != data.len() {
+        return None;
+    }
+    let descriptor = *data.get(4)?;
+    if descriptor & DICTIONARY_ID != 0 {
+        return None;
+    }
+    let checksum = descriptor & CHECKSUM != 0;
+    // Content size is present unless the field size flag is 0 and the frame is not single-segment
+    let content_size = descriptor & 0xE0 != 0;
+    let contents = zstd::decode_all(data).ok()?;
+
+    let compressed = std::iter::once(zstd::DEFAULT_COMPRESSION_LEVEL)
+        .chain((1..=22).filter(|&level| level != zstd::DEFAULT_COMPRESSION_LEVEL))
+        .flat_map(|level| {
+            [false, true].map(|multithreaded| Compressed::Zstd {
+                level,
+                checksum,
+                content_size,
+                multithreaded,
+            })
+        })
+        .find(|compressed| {
+            zstd_compress(&contents, compressed, Expect::new(data))
+                .is_ok_and(|expect| expect.is_complete())
+        })?;
+    Some((compressed, contents))
+}
+
+/// Compresses `data` with the settings of a [`Compressed::Zstd`].
+fn zstd_compress<W: Write>(data: &[u8], settings: &Compressed, out: W) -> io::Result<W> {
+    let &Compressed::Zstd {
+        level,
+        checksum,
+        content_size,
+        multithreaded,
+    } = settings
+    else {
+        unreachable!("zstd_compress called with gzip settings");
+    };
+
+    let mut encoder = zstd::stream::write::Encoder::new(out, level)?;
+    encoder.include_checksum(checksum)?;
+    encoder.include_contentsize(content_size)?;
+    if content_size {
+        encoder.set_pledged_src_size(Some(data.len() as u64))?;
+    }
+    if multithreaded {
+        encoder.multithread(1)?;
+    }
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// A writer that fails as soon as the written bytes stop matching `expected`, so that trying
+/// the wrong compression settings stops at the first differing block.
+struct Expect<'a> {
+    expected: &'a [u8],
+}
+
+impl<'a> Expect<'a> {
+    fn new(expected: &'a [u8]) -> Self {
+        Self { expected }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.expected.is_empty()
+    }
+}
+
+impl Write for Expect<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.expected.strip_prefix(buf) {
+            Some(rest) => {
+                self.expected = rest;
+                Ok(buf.len())
+            }
+            None => Err(io::Error::other("output differs")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta;
+
+    fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn sample(version: u8) -> Vec<u8> {
+        (0..200_000u32)
+            .map(|i| {
+                if i % 50_000 == 7 {
+                    version
+                } else {
+                    (i * 31 % 97) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gzip_roundtrip() {
+        for level in [1, 6, 9] {
+            let (base, new) = (gzip(&sample(1), level), gzip(&sample(2), level));
+            assert!(matches!(detect(&new), Some(Compressed::Gzip { level: l, .. }) if l == level));
+
+            let patch = encode(3, &base, &new, &EncodeOptions::default());
+            assert!(is_wrapped(&patch));
+            assert!(patch.len() < 200, "{} bytes", patch.len());
+            assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_zstd_roundtrip() {
+        let compress = |data: &[u8], level| zstd::encode_all(data, level).unwrap();
+        for level in [1, 3, 19] {
+            let (base, new) = (compress(&sample(1), level), compress(&sample(2), level));
+            assert!(matches!(detect(&new), Some(Compressed::Zstd { level: l, .. }) if l == level));
+
+            let patch = encode(0, &base, &new, &EncodeOptions::default());
+            assert!(patch.len() < 200, "{} bytes", patch.len());
+            assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_zstd_multithreaded() {
+        let compress = |data: &[u8]| {
+            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 3).unwrap();
+            encoder.include_checksum(true).unwrap();
+            encoder
+                .set_pledged_src_size(Some(data.len() as u64))
+                .unwrap();
+            encoder.multithread(2).unwrap();
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        // Like the zstd tool, which compresses in jobs and records the size. The modes only
+        // split blocks differently on larger inputs, reliably from a few MiB on.
+        let contents = |version: u8| {
+            let mut text = [
+                include_bytes!("delta.rs").as_slice(),
+                include_bytes!("token_list.rs"),
+            ]
+            .concat()
+            .repeat(8);
+            text[1000] = version;
+            text
+        };
+        let (base, new) = (compress(&contents(1)), compress(&contents(2)));
+        assert!(matches!(
+            detect(&new),
+            Some(Compressed::Zstd {
+                multithreaded: true,
+                ..
+            })
+        ));
+
+        let patch = encode(0, &base, &new, &EncodeOptions::default());
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+    }
+
+    #[test]
+    fn test_mixed_and_plain_inputs() {
+        let options = EncodeOptions::default();
+
+        // Compressed base, plain new data
+        let base = gzip(&sample(1), 6);
+        let patch = encode(0, &base, &sample(2), &options);
+        assert!(is_wrapped(&patch));
+        assert_eq!(delta::decode(&base, &patch).unwrap(), sample(2));
+
+        // Neither input is compressed
+        let patch = encode(0, &sample(1), &sample(2), &options);
+        assert!(!is_wrapped(&patch));
+        assert_eq!(
+            patch,
+            delta::encode_with_options(0, &sample(1), &sample(2), &options)
+        );
+
+        // Truncated gzip data is diffed as is, and decompressing only one side does not pay off
+        let new = gzip(&sample(2), 6);
+        assert_eq!(detect(&new[..new.len() - 1]), None);
+        let patch = encode(0, &base, &new[..new.len() - 1], &options);
+        assert!(!is_wrapped(&patch));
+        assert_eq!(delta::decode(&base, &patch).unwrap(), &new[..new.len() - 1]);
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        let (base, new) = (gzip(&sample(1), 6), gzip(&sample(2), 6));
+        let patch = encode(0, &base, &new, &EncodeOptions::default());
+        // Skip the magic, version and base recipe to the new recipe's level byte
+        let new_recipe = MAGIC.len() + 1 + 2 + patch[MAGIC.len() + 2] as usize + 1;
+        let level = new_recipe + 2 + patch[new_recipe + 1] as usize;
+        assert_eq!(patch[level], 6);
+
+        for invalid in [0, 10, 255] {
+            let mut corrupt = patch.clone();
+            corrupt[level] = invalid;
+            assert_eq!(
+                Envelope::parse(&corrupt),
+                Err("Invalid compressed delta envelope")
+            );
+            assert!(delta::decode(&base, &corrupt).is_err());
+        }
+    }
+
+    #[test]
+    fn test_wrong_base() {
+        let new = gzip(&sample(2), 6);
+        let patch = encode(0, &gzip(&sample(1), 6), &new, &EncodeOptions::default());
+        assert!(delta::decode(&sample(1), &patch).is_err());
+    }
+}
@@ -18,6 +18,12 @@
 // For commercial use in proprietary software, a commercial license is
 // available. Contact xpatch-commercial@alias.oseifert.ch for details.
 
+// Builds without the encoder or the decoder leave some of these unused
+#![cfg_attr(
+    not(all(feature = "encode", feature = "decode")),
+    allow(unused_macros, unused_imports)
+)]
+
 #[cfg(feature = "debug_delta_encode")]
 macro_rules! debug_delta_encode {
     ($($arg:tt)*) => (println!("[DELTA][ENCODE] {}", format_args!($($arg)*)));
@@ -88,6 +94,20 @@ macro_rules! debug_tokenizer {
     ($($arg:tt)*) => {};
 }
 
+/// Enters a `tracing` span at the given level for the rest of the scope, e.g.
+/// `let _span = trace_span!(DEBUG, "compress", size = data.len());`.
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+    ($level:ident, $($arg:tt)*) => (tracing::span!(tracing::Level::$level, $($arg)*).entered());
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
 pub(crate) use debug_delta_analyze;
 pub(crate) use debug_delta_compress;
 pub(crate) use debug_delta_encode;
@@ -95,3 +115,4 @@ pub(crate) use debug_delta_header;
 pub(crate) use debug_delta_pattern;
 pub(crate) use debug_delta_token;
 pub(crate) use debug_tokenizer;
+pub(crate) use trace_span;
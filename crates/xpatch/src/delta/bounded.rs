@@ -0,0 +1,376 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Applying deltas in a fixed amount of memory.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::debug::trace_span;
+
+use super::ops::{Instructions, RawOp};
+use super::{Algorithm, HoleFiller, invalid_data, parse_header, read_header_varint, write_zeros};
+
+/// Applies a delta to a base read on demand from `base`, writing the new data to `out` and
+/// returning its size, without allocating.
+///
+/// Every copy seeks in `base` and streams the range through `scratch`, inserted bytes are
+/// written straight from `delta`, and the output is never held, so memory use is the size of
+/// `scratch` whatever the size of the data. This targets devices such as microcontrollers
+/// applying an update from external flash with a buffer of a few KiB; a larger `scratch` only
+/// means fewer reads.
+///
+/// GDelta, Chars, RepeatChars and Remove deltas are supported, sparse and stored deltas
+/// included. Deltas compressed with zstd, token-based deltas, encrypted deltas and the
+/// envelopes for compressed files, executables, archives and error correction need more
+/// memory and are rejected with [`io::ErrorKind::Unsupported`]; encode for such devices with
+/// `enable_zstd` off.
+///
+/// The base checksum is verified before anything is written, by reading the base once through
+/// `scratch`. The output checksum is verified after the output was written, so discard the
+/// output if an error is returned.
+///
+/// # Errors
+/// Fails if `scratch` is empty, if the delta is invalid or doesn't match the base, or if
+/// reading the base or writing the output fails.
+///
+/// # Example
+/// ```
+/// use std::io::Cursor;
+/// use xpatch::delta;
+///
+/// let base = b"firmware 1.0 with a long stretch that stays the same".repeat(100);
+/// let mut new = base.clone();
+/// new[9..12].copy_from_slice(b"1.1");
+/// let patch = delta::encode(0, &base, &new, false);
+///
+/// let mut scratch = [0; 256];
+/// let mut out = Vec::new();
+/// delta::decode_bounded(Cursor::new(&base), &patch, &mut out, &mut scratch).unwrap();
+/// assert_eq!(out, new);
+/// ```
+pub fn decode_bounded(
+    mut base: impl Read + Seek,
+    delta: &[u8],
+    out: &mut impl Write,
+    scratch: &mut [u8],
+) -> io::Result<u64> {
+    let _span = trace_span!(INFO, "decode", delta_size = delta.len(), bounded = true);
+    #[cfg(feature = "metrics")]
+    let start = crate::metrics::start();
+    let result = apply(&mut base, delta, out, scratch);
+    #[cfg(feature = "metrics")]
+    crate::metrics::finish(
+        start,
+        crate::metrics::Operation::Decode,
+        *result.as_ref().unwrap_or(&0) as usize,
+        delta.len(),
+        result.as_ref().err().map(|e| match e.kind() {
+            io::ErrorKind::InvalidData => "Invalid delta",
+            _ => "I/O error",
+        }),
+    );
+    result
+}
+
+/// [`decode_bounded`] without metrics.
+fn apply(
+    base: &mut (impl Read + Seek),
+    delta: &[u8],
+    out: &mut impl Write,
+    scratch: &mut [u8],
+) -> io::Result<u64> {
+    let invalid = invalid_data;
+
+    if scratch.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Scratch buffer is empty",
+        ));
+    }
+    if delta.is_empty() {
+        return Err(invalid("Empty delta"));
+    }
+    let header = parse_header(delta).map_err(|_| unsupported_or_invalid(delta))?;
+    if header.encrypted {
+        return Err(unsupported("Delta is encrypted"));
+    }
+    let payload = &delta[header.size..];
+
+    if let Some(expected) = header.base_checksum
+        && checksum(base, scratch)? != expected
+    {
+        return Err(invalid("Base data checksum mismatch"));
+    }
+
+    let mut output = Output {
+        out,
+        hasher: header.output_checksum.map(|_| crc32fast::Hasher::new()),
+        written: 0,
+        holes: HoleFiller::new(&header.holes),
+    };
+    match header.algorithm {
+        Algorithm::Chars if header.stored => output.insert(payload)?,
+        _ if header.stored => return Err(unsupported("Stored delta is compressed")),
+        Algorithm::GDelta => {
+            let instructions =
+                Instructions::new(payload).map_err(|_| invalid("Error decoding gdelta"))?;
+            for instruction in instructions {
+                match instruction
+                    .map_err(|_| invalid("Error decoding gdelta"))?
+                    .op
+                {
+                    RawOp::Copy { offset, len } => {
+                        output.copy(base, offset as u64, len, scratch)?
+                    }
+                    RawOp::Insert(bytes) => output.insert(bytes)?,
+                }
+            }
+        }
+        Algorithm::Chars | Algorithm::RepeatChars => {
+            let mut pos = 0;
+            let position = read_header_varint(payload, &mut pos).map_err(invalid)?;
+            let base_len = base_len(base)?;
+            if position as u64 > base_len {
+                return Err(invalid("Insert position out of bounds"));
+            }
+            output.copy(base, 0, position, scratch)?;
+            if header.algorithm == Algorithm::Chars {
+                output.insert(&payload[pos..])?;
+            } else {
+                let count = read_header_varint(payload, &mut pos).map_err(invalid)?;
+                let pattern = &payload[pos..];
+                if pattern.is_empty() {
+                    return Err(invalid("Empty pattern in repeat chars"));
+                }
+                for _ in 0..count {
+                    output.insert(pattern)?;
+                }
+            }
+            output.copy_rest(base, position as u64, base_len, scratch)?;
+        }
+        Algorithm::Remove => {
+            let mut pos = 0;
+            let start = read_header_varint(payload, &mut pos).map_err(invalid)?;
+            let distance = read_header_varint(payload, &mut pos).map_err(invalid)?;
+            let base_len = base_len(base)?;
+            let end = start
+                .checked_add(distance)
+                .filter(|&end| end as u64 <= base_len)
+                .ok_or_else(|| invalid("Invalid deletion range"))?;
+            output.copy(base, 0, start, scratch)?;
+            output.copy_rest(base, end as u64, base_len, scratch)?;
+        }
+        Algorithm::Tokens
+        | Algorithm::RepeatTokens
+        | Algorithm::GDeltaZstd
+        | Algorithm::CharsZstd => {
+            return Err(unsupported(
+                "Algorithm needs more memory than bounded decoding",
+            ));
+        }
+    }
+    let (written, checksum) = output.finish()?;
+
+    if let Some(expected) = header.output_checksum
+        && checksum != Some(expected)
+    {
+        return Err(invalid("Output checksum mismatch"));
+    }
+    Ok(written)
+}
+
+/// Where [`decode_bounded`] writes the new data, putting the zeros of holes back.
+struct Output<'a, W> {
+    out: &'a mut W,
+    hasher: Option<crc32fast::Hasher>,
+    written: u64,
+    holes: HoleFiller<'a>,
+}
+
+impl<W: Write> Output<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(bytes);
+        }
+        self.written += bytes.len() as u64;
+        self.out.write_all(bytes)
+    }
+
+    fn zeros(&mut self, len: usize) -> io::Result<()> {
+        write_zeros(len, &mut |bytes| self.write(bytes))
+    }
+
+    /// Writes the holes after the last byte, returning the size and checksum of the output.
+    fn finish(mut self) -> io::Result<(u64, Option<u32>)> {
+        let holes = std::mem::replace(&mut self.holes, HoleFiller::new(&[]));
+        self.zeros(holes.finish().map_err(invalid_data)?)?;
+        Ok((self.written, self.hasher.map(crc32fast::Hasher::finalize)))
+    }
+
+    /// Writes `bytes`, splitting them at the holes they cross.
+    fn insert(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            let (zeros, len) = self.holes.next(bytes.len());
+            self.zeros(zeros)?;
+            self.write(&bytes[..len])?;
+            bytes = &bytes[len..];
+        }
+        Ok(())
+    }
+
+    /// Writes the `len` base bytes at `offset`, reading them through `scratch`.
+    fn copy(
+        &mut self,
+        base: &mut (impl Read + Seek),
+        offset: u64,
+        mut len: usize,
+        scratch: &mut [u8],
+    ) -> io::Result<()> {
+        if len > 0 {
+            base.seek(SeekFrom::Start(offset))?;
+        }
+        while len > 0 {
+            let (zeros, mut run) = self.holes.next(len);
+            self.zeros(zeros)?;
+            len -= run;
+            while run > 0 {
+                let n = run.min(scratch.len());
+                let chunk = &mut scratch[..n];
+                base.read_exact(chunk).map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => invalid_data("Error decoding gdelta"),
+                    _ => e,
+                })?;
+                run -= chunk.len();
+                self.write(chunk)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the base from `offset` to its end.
+    fn copy_rest(
+        &mut self,
+        base: &mut (impl Read + Seek),
+        offset: u64,
+        base_len: u64,
+        scratch: &mut [u8],
+    ) -> io::Result<()> {
+        let len = usize::try_from(base_len - offset)
+            .map_err(|_| invalid_data("Base is too large for this platform"))?;
+        self.copy(base, offset, len, scratch)
+    }
+}
+
+/// Returns the CRC32 of the whole base, read through `scratch`.
+fn checksum(base: &mut (impl Read + Seek), scratch: &mut [u8]) -> io::Result<u32> {
+    base.seek(SeekFrom::Start(0))?;
+    let mut hasher = crc32fast::Hasher::new();
+    loop {
+        match base.read(scratch) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(n) => hasher.update(&scratch[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn base_len(base: &mut impl Seek) -> io::Result<u64> {
+    base.seek(SeekFrom::End(0))
+}
+
+fn unsupported(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+/// The error for a delta without a plain header: envelopes need more memory, anything else is
+/// invalid.
+fn unsupported_or_invalid(delta: &[u8]) -> io::Error {
+    match delta.starts_with(&[0xFC, 0x00]) || super::is_multi_source(delta) {
+        true => unsupported("Delta envelope needs more memory than bounded decoding"),
+        false => invalid_data("Invalid delta header"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{EncodeOptions, encode, encode_with_options};
+    use std::io::Cursor;
+
+    fn bounded(base: &[u8], delta: &[u8], scratch: usize) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut scratch = vec![0; scratch];
+        let written = decode_bounded(Cursor::new(base), delta, &mut out, &mut scratch)?;
+        assert_eq!(written, out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn test_decode_bounded() {
+        let base: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut changed = base.clone();
+        changed[1000..1010].copy_from_slice(b"0123456789");
+        changed.drain(50_000..60_000);
+        let mut inserted = base.clone();
+        inserted.splice(500..500, [0xFF, 0x00, 0x80, 0x7F]);
+        let mut repeated = base.clone();
+        repeated.splice(500..500, [0xFF, 0x00].repeat(100));
+        let mut removed = base.clone();
+        removed.drain(100..200);
+        let mut sparse = base.clone();
+        sparse[10_000..90_000].fill(0);
+        sparse[95_000..95_010].copy_from_slice(b"0123456789");
+
+        let options = EncodeOptions {
+            enable_zstd: false,
+            checksum: true,
+            hole_size: 4096,
+            ..EncodeOptions::default()
+        };
+        let random: Vec<u8> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        for new in [&changed, &inserted, &repeated, &removed, &sparse, &random] {
+            let delta = encode_with_options(0, &base, new, &options);
+            for scratch in [1, 64, 65536] {
+                assert_eq!(&bounded(&base, &delta, scratch).unwrap(), new);
+            }
+        }
+
+        let delta = encode(0, &base, &changed, false);
+        let err = bounded(&base[..1000], &delta, 64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = bounded(&base, &delta, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let delta = encode_with_options(0, &base, &changed, &options);
+        let err = bounded(&changed, &delta, 64).unwrap_err();
+        assert_eq!(err.to_string(), "Base data checksum mismatch");
+
+        #[cfg(feature = "zstd")]
+        {
+            let text = b"some text that compresses well ".repeat(1000);
+            let delta = encode(0, b"", &text, true);
+            let err = bounded(b"", &delta, 64).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+}
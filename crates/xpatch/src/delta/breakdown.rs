@@ -0,0 +1,307 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Where the bytes of a delta go.
+
+use super::ops::{Instructions, RawOp};
+use super::{Algorithm, parse_header, read_header_varint, zstd_decompress};
+
+/// The bytes of a delta by what they encode, as returned by [`breakdown`].
+///
+/// The parts of the payload add up to [`uncompressed_payload_bytes`]:
+/// `framing_bytes + copy_op_bytes + insert_op_bytes + literal_bytes + other_bytes`.
+///
+/// [`uncompressed_payload_bytes`]: Breakdown::uncompressed_payload_bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakdown {
+    /// Algorithm of the delta
+    pub algorithm: Algorithm,
+    /// Header size in bytes, checksums and provenance included
+    pub header_bytes: usize,
+    /// Payload size in bytes, as stored
+    pub payload_bytes: usize,
+    /// Payload size in bytes before zstd compression; equal to `payload_bytes` for
+    /// uncompressed algorithms
+    pub uncompressed_payload_bytes: usize,
+    /// Number of GDelta copy instructions
+    pub copy_ops: usize,
+    /// Bytes of the copy instructions (lengths and offsets)
+    pub copy_op_bytes: usize,
+    /// Output bytes the copy instructions produce
+    pub copied_bytes: usize,
+    /// Number of GDelta insert instructions
+    pub insert_ops: usize,
+    /// Bytes of the insert instructions (lengths)
+    pub insert_op_bytes: usize,
+    /// Literal bytes inserted into the output: the literal section of GDelta, the inserted
+    /// text of Chars, the pattern of RepeatChars, the whole data of stored deltas
+    pub literal_bytes: usize,
+    /// Lengths and positions around the other parts, e.g. the length of the GDelta
+    /// instruction section or the insert position of Chars
+    pub framing_bytes: usize,
+    /// Bytes not broken down further: token streams, and GDelta literals no instruction uses
+    pub other_bytes: usize,
+    /// Sizes of the instruction kinds and the literals compressed with zstd, each on its
+    /// own; `None` in builds without zstd compression
+    pub compressed: Option<CompressedParts>,
+}
+
+/// Sizes of the parts of a [`Breakdown`] after zstd compression.
+///
+/// Each part is compressed on its own at level 3, so the sizes show how well each part
+/// compresses rather than adding up to the payload of a `GDeltaZstd` delta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressedParts {
+    /// Compressed size of the copy instructions
+    pub copy_op_bytes: usize,
+    /// Compressed size of the insert instructions
+    pub insert_op_bytes: usize,
+    /// Compressed size of the literals
+    pub literal_bytes: usize,
+}
+
+/// Breaks the bytes of a delta down by what they encode: header, framing, copy and insert
+/// instructions, and literals, before and after compression.
+///
+/// Unlike [`inspect`](super::inspect), which only reads the header, this decompresses and
+/// walks the payload, but it needs no base. It answers questions such as whether a delta is
+/// large because of the literals (the data is new, or matched poorly) or because of the
+/// instructions (many short copies, as in JSON with small scattered edits).
+///
+/// # Errors
+/// Fails for encrypted deltas, for envelopes (unwrap them first) and for malformed payloads.
+///
+/// # Example
+/// ```
+/// use xpatch::delta;
+///
+/// let base = br#"{"id": 1, "name": "first", "tags": ["a", "b"]}"#.repeat(50);
+/// let new = String::from_utf8(base.clone()).unwrap().replace("first", "second");
+/// let patch = delta::encode(0, &base, new.as_bytes(), false);
+///
+/// let breakdown = delta::breakdown(&patch).unwrap();
+/// assert_eq!(breakdown.header_bytes + breakdown.payload_bytes, patch.len());
+/// assert_eq!(breakdown.copied_bytes + breakdown.literal_bytes, new.len());
+/// assert!(breakdown.copy_ops > 1);
+/// ```
+pub fn breakdown(delta: &[u8]) -> Result<Breakdown, &'static str> {
+    if delta.is_empty() {
+        return Err("Empty delta");
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let stored = &delta[header.size..];
+    let compressed = matches!(
+        header.algorithm,
+        Algorithm::GDeltaZstd | Algorithm::CharsZstd
+    );
+    let decompress =
+        |data: &[u8]| zstd_decompress(data).map_err(|_| "Error decompressing zstd data");
+
+    let mut breakdown = Breakdown {
+        algorithm: header.algorithm,
+        header_bytes: header.size,
+        payload_bytes: stored.len(),
+        uncompressed_payload_bytes: stored.len(),
+        copy_ops: 0,
+        copy_op_bytes: 0,
+        copied_bytes: 0,
+        insert_ops: 0,
+        insert_op_bytes: 0,
+        literal_bytes: 0,
+        framing_bytes: 0,
+        other_bytes: 0,
+        compressed: None,
+    };
+    // The bytes of each part, for compressing them on their own
+    let mut copy_ops = Vec::new();
+    let mut insert_ops = Vec::new();
+    let literals;
+
+    match header.algorithm {
+        _ if header.stored => {
+            literals = match compressed {
+                true => decompress(stored)?,
+                false => stored.to_vec(),
+            };
+            breakdown.literal_bytes = literals.len();
+        }
+        Algorithm::GDelta | Algorithm::GDeltaZstd => {
+            let payload = match compressed {
+                true => decompress(stored)?,
+                false => stored.to_vec(),
+            };
+            let mut pos = 0;
+            let section_len = read_header_varint(&payload, &mut pos)?;
+            let section_end = pos
+                .checked_add(section_len)
+                .filter(|&end| end <= payload.len())
+                .ok_or("Instruction section is truncated")?;
+            breakdown.framing_bytes = pos;
+
+            let mut instructions = Instructions::new(&payload).map_err(|(_, e)| e)?;
+            // Where each instruction starts, and whether it is a copy
+            let mut starts = Vec::new();
+            for instruction in &mut instructions {
+                let instruction = instruction.map_err(|(_, e)| e)?;
+                match instruction.op {
+                    RawOp::Copy { len, .. } => {
+                        breakdown.copy_ops += 1;
+                        breakdown.copied_bytes += len;
+                    }
+                    RawOp::Insert(bytes) => {
+                        breakdown.insert_ops += 1;
+                        breakdown.literal_bytes += bytes.len();
+                    }
+                }
+                starts.push((
+                    instruction.position,
+                    matches!(instruction.op, RawOp::Copy { .. }),
+                ));
+            }
+            let ends = starts.iter().skip(1).map(|&(start, _)| start);
+            for (&(start, copy), end) in starts.iter().zip(ends.chain([section_end])) {
+                let bytes = &payload[start..end];
+                match copy {
+                    true => copy_ops.extend_from_slice(bytes),
+                    false => insert_ops.extend_from_slice(bytes),
+                }
+            }
+            breakdown.copy_op_bytes = copy_ops.len();
+            breakdown.insert_op_bytes = insert_ops.len();
+            breakdown.other_bytes = instructions.unused_literals();
+            breakdown.uncompressed_payload_bytes = payload.len();
+            literals = payload[section_end..payload.len() - breakdown.other_bytes].to_vec();
+        }
+        Algorithm::Chars | Algorithm::CharsZstd => {
+            let mut pos = 0;
+            read_header_varint(stored, &mut pos)?;
+            breakdown.framing_bytes = pos;
+            literals = match compressed {
+                true => decompress(&stored[pos..])?,
+                false => stored[pos..].to_vec(),
+            };
+            breakdown.literal_bytes = literals.len();
+            breakdown.uncompressed_payload_bytes = pos + literals.len();
+        }
+        Algorithm::RepeatChars => {
+            let mut pos = 0;
+            read_header_varint(stored, &mut pos)?;
+            read_header_varint(stored, &mut pos)?;
+            breakdown.framing_bytes = pos;
+            literals = stored[pos..].to_vec();
+            breakdown.literal_bytes = literals.len();
+        }
+        Algorithm::Remove => {
+            breakdown.framing_bytes = stored.len();
+            literals = Vec::new();
+        }
+        Algorithm::Tokens | Algorithm::RepeatTokens => {
+            breakdown.other_bytes = stored.len();
+            literals = Vec::new();
+        }
+    }
+
+    breakdown.compressed = compressed_size(&literals).map(|literal_bytes| CompressedParts {
+        copy_op_bytes: compressed_size(&copy_ops).unwrap_or_default(),
+        insert_op_bytes: compressed_size(&insert_ops).unwrap_or_default(),
+        literal_bytes,
+    });
+    Ok(breakdown)
+}
+
+/// Returns the size of `data` compressed with zstd at level 3, or `None` if this build cannot
+/// compress.
+fn compressed_size(data: &[u8]) -> Option<usize> {
+    #[cfg(all(feature = "encode", feature = "zstd"))]
+    return zstd::bulk::compress(data, 3)
+        .ok()
+        .map(|compressed| compressed.len());
+    #[cfg(not(all(feature = "encode", feature = "zstd")))]
+    {
+        let _ = data;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{EncodeOptions, encode, encode_with_options};
+
+    fn parts(breakdown: &Breakdown) -> usize {
+        breakdown.framing_bytes
+            + breakdown.copy_op_bytes
+            + breakdown.insert_op_bytes
+            + breakdown.literal_bytes
+            + breakdown.other_bytes
+    }
+
+    #[test]
+    fn test_breakdown_adds_up() {
+        let base: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut changed = base.clone();
+        for i in (0..20_000).step_by(1000) {
+            changed[i] = b'x';
+        }
+        let mut appended = base.clone();
+        appended.extend_from_slice(b"appended text");
+        let mut repeated = base.clone();
+        repeated.extend_from_slice(&b"ab".repeat(500));
+        let removed = base[..15_000].to_vec();
+        let random: Vec<u8> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+
+        for zstd in [false, true] {
+            let options = EncodeOptions {
+                enable_zstd: zstd,
+                checksum: true,
+                ..EncodeOptions::default()
+            };
+            for new in [&changed, &appended, &repeated, &removed, &random] {
+                let delta = encode_with_options(0, &base, new, &options);
+                let breakdown = breakdown(&delta).unwrap();
+                assert_eq!(
+                    breakdown.header_bytes + breakdown.payload_bytes,
+                    delta.len()
+                );
+                assert_eq!(parts(&breakdown), breakdown.uncompressed_payload_bytes);
+                if matches!(
+                    breakdown.algorithm,
+                    Algorithm::GDelta | Algorithm::GDeltaZstd
+                ) {
+                    assert_eq!(breakdown.copied_bytes + breakdown.literal_bytes, new.len());
+                }
+            }
+        }
+
+        let delta = encode(0, &base, &changed, false);
+        let breakdown = super::breakdown(&delta).unwrap();
+        assert_eq!(breakdown.algorithm, Algorithm::GDelta);
+        assert_eq!(breakdown.insert_ops, 20);
+        assert!(breakdown.literal_bytes >= 20);
+        #[cfg(feature = "zstd")]
+        assert!(breakdown.compressed.unwrap().copy_op_bytes < breakdown.copy_op_bytes);
+        assert_eq!(super::breakdown(b""), Err("Empty delta"));
+    }
+}
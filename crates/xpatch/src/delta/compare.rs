@@ -0,0 +1,229 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Comparing deltas by what they do rather than how they are encoded.
+
+use super::ops::{Op, parse_gdelta, write_gdelta};
+use super::{
+    Algorithm, decode, encode_header, extended_header, parse_header, read_header_varint,
+    zstd_decompress,
+};
+use crate::varint::encode_varint;
+
+/// Returns whether two deltas reconstruct the same output from `base_data`.
+///
+/// Deltas made by different encoder versions or with different options (algorithm, zstd
+/// level, copy distance limit) are equivalent as long as they decode to the same bytes. A delta
+/// that doesn't decode against `base_data`, e.g. because its base checksum doesn't match, is
+/// equivalent to nothing.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog".repeat(4);
+/// let new = [&base[..], b" and runs away"].concat();
+/// let compressed = delta::encode(0, &base, &new, true);
+/// let options = EncodeOptions { enable_zstd: false, ..EncodeOptions::default() };
+/// let plain = delta::encode_with_options(0, &base, &new, &options);
+///
+/// assert!(delta::equivalent(&compressed, &plain, &base));
+/// assert!(!delta::equivalent(&compressed, &delta::encode(0, &base, &base, true), &base));
+/// ```
+pub fn equivalent(delta_a: &[u8], delta_b: &[u8], base_data: &[u8]) -> bool {
+    match (decode(base_data, delta_a), decode(base_data, delta_b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Rewrites a delta into a canonical encoding that decodes the same way.
+///
+/// The tag, checksums and holes are kept; compression, provenance and the copy distance limit
+/// are dropped. GDelta instructions are merged (adjacent inserts, contiguous copies) and stored
+/// deltas become a single GDelta insert, so two GDelta deltas with the same normalized bytes
+/// reconstruct the same output from any base. Deltas using the specialized algorithms are only
+/// decompressed, as their instructions depend on the base.
+///
+/// The base isn't needed, which makes this cheap for comparing patch sets; use [`equivalent`]
+/// to compare deltas that normalize differently but may still agree on a given base.
+///
+/// # Errors
+///
+/// Returns an error if the delta is encrypted or its header or payload can't be read.
+pub fn normalize(delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return normalize(&repaired);
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let payload = &delta[header.size..];
+    let decompress =
+        |data: &[u8]| zstd_decompress(data).map_err(|_| "Error decompressing zstd data");
+
+    let (algorithm, payload) = match header.algorithm {
+        _ if header.stored => {
+            let output = match header.algorithm {
+                Algorithm::Chars => payload.to_vec(),
+                Algorithm::CharsZstd => decompress(payload)?,
+                _ => return Err("Unsupported stored delta"),
+            };
+            (Algorithm::GDelta, gdelta_payload(&[Op::Insert(output)]))
+        }
+        Algorithm::GDelta => (
+            Algorithm::GDelta,
+            gdelta_payload(&merge(parse_gdelta(payload)?)),
+        ),
+        Algorithm::GDeltaZstd => {
+            let ops = parse_gdelta(&decompress(payload)?)?;
+            (Algorithm::GDelta, gdelta_payload(&merge(ops)))
+        }
+        Algorithm::CharsZstd => {
+            let mut pos = 0;
+            let position = read_header_varint(payload, &mut pos)?;
+            let mut chars = encode_varint(position);
+            chars.extend(decompress(&payload[pos..])?);
+            (Algorithm::Chars, chars)
+        }
+        algorithm => (algorithm, payload.to_vec()),
+    };
+
+    let mut normalized = match (header.base_checksum, header.output_checksum) {
+        (None, None) if header.holes.is_empty() => encode_header(algorithm, header.tag),
+        (base, output) => extended_header(
+            algorithm,
+            header.tag,
+            base,
+            output,
+            None,
+            false,
+            &header.holes,
+            None,
+        ),
+    };
+    normalized.extend(payload);
+    Ok(normalized)
+}
+
+/// Merges adjacent inserts and contiguous copies and drops empty instructions.
+fn merge(ops: Vec<Op>) -> Vec<Op> {
+    let mut merged: Vec<Op> = Vec::with_capacity(ops.len());
+    for op in ops.into_iter().filter(|op| !op.is_empty()) {
+        match (merged.last_mut(), op) {
+            (Some(Op::Insert(bytes)), Op::Insert(more)) => bytes.extend(more),
+            (
+                Some(Op::Copy { offset, len }),
+                Op::Copy {
+                    offset: next,
+                    len: more,
+                },
+            ) if *offset + *len == next => *len += more,
+            (_, op) => merged.push(op),
+        }
+    }
+    merged
+}
+
+/// Serializes instructions as an uncompressed GDelta payload.
+fn gdelta_payload(ops: &[Op]) -> Vec<u8> {
+    let (instructions, literals) = write_gdelta(ops);
+    let mut payload = encode_varint(instructions.len());
+    payload.extend(instructions);
+    payload.extend(literals);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{EncodeOptions, encode_with_options, inspect, ops, stored_delta};
+
+    #[test]
+    fn test_normalize_and_equivalent() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(40);
+        let new = [
+            &base[..300],
+            b"a few new words",
+            &base[200..900],
+            &base[950..],
+        ]
+        .concat();
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let plain_options = EncodeOptions {
+            enable_zstd: false,
+            ..options.clone()
+        };
+
+        let compressed = encode_with_options(5, &base, &new, &options);
+        let plain = encode_with_options(5, &base, &new, &plain_options);
+        let split: Vec<Op> = ops::decode_ops(&base, &plain)
+            .unwrap()
+            .into_iter()
+            .flat_map(|op| match op {
+                Op::Copy { offset, len } if len > 1 => vec![
+                    Op::Copy { offset, len: 1 },
+                    Op::Insert(Vec::new()),
+                    Op::Copy {
+                        offset: offset + 1,
+                        len: len - 1,
+                    },
+                ],
+                Op::Insert(bytes) if bytes.len() > 1 => {
+                    vec![
+                        Op::Insert(bytes[..1].to_vec()),
+                        Op::Insert(bytes[1..].to_vec()),
+                    ]
+                }
+                op => vec![op],
+            })
+            .collect();
+        let split = ops::encode_from_ops(5, &base, &split, &plain_options).unwrap();
+        assert_ne!(split, plain);
+
+        for delta in [&compressed, &plain, &split] {
+            let normalized = normalize(delta).unwrap();
+            assert_eq!(normalized, normalize(&plain).unwrap());
+            assert_eq!(normalize(&normalized).unwrap(), normalized);
+            assert_eq!(decode(&base, &normalized).unwrap(), new);
+            let info = inspect(&normalized).unwrap();
+            assert_eq!((info.tag, info.algorithm), (5, Algorithm::GDelta));
+            assert!(info.base_checksum.is_some() && info.output_checksum.is_some());
+            assert!(equivalent(delta, &plain, &base));
+        }
+
+        // A stored delta is a single insert
+        let stored = stored_delta(5, &new, None, &EncodeOptions::default());
+        let insert = [Op::Insert(new.clone())];
+        let insert = ops::encode_from_ops(5, &base, &insert, &EncodeOptions::default()).unwrap();
+        assert!(normalize(&stored).unwrap() == normalize(&insert).unwrap());
+
+        let other = encode_with_options(5, &base, &base, &options);
+        assert!(!equivalent(&compressed, &other, &base));
+        assert!(!equivalent(&compressed, &compressed, b"wrong base"));
+    }
+}
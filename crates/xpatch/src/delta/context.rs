@@ -0,0 +1,137 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Compression contexts reused across calls.
+
+#[cfg(feature = "decode")]
+use super::decode;
+#[cfg(all(feature = "encode", feature = "zstd"))]
+use super::small;
+#[cfg(feature = "encode")]
+use super::{EncodeOptions, encode_with_options};
+#[cfg(all(feature = "decode", feature = "zstd"))]
+use std::cell::RefCell;
+#[cfg(all(feature = "decode", feature = "zstd"))]
+use zstd::zstd_safe::DCtx;
+
+/// Holds zstd compression and decompression contexts for encoding and decoding many deltas.
+///
+/// [`encode`](super::encode) and [`decode`](super::decode) set up a zstd context for every
+/// delta above a few KiB, which allocates several MiB per call at higher levels. A `Context`
+/// creates its contexts on first use and keeps them, so hot paths allocate only the deltas
+/// and outputs they return. The deltas are the same as those of
+/// [`encode_with_options`](super::encode_with_options), apart from zstd windows sized to the
+/// data.
+///
+/// A context is used by one call at a time; keep one per thread.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{Context, EncodeOptions};
+///
+/// let mut context = Context::new();
+/// let options = EncodeOptions::default();
+/// for version in 1..10 {
+///     let base = format!("counter = {}\n", version - 1).repeat(100);
+///     let new = format!("counter = {version}\n").repeat(100);
+///     let delta = context.encode(0, base.as_bytes(), new.as_bytes(), &options);
+///     assert_eq!(context.decode(base.as_bytes(), &delta).unwrap(), new.as_bytes());
+/// }
+/// ```
+#[derive(Default)]
+pub struct Context {
+    #[cfg(all(feature = "encode", feature = "zstd"))]
+    compressor: small::Slot,
+    #[cfg(all(feature = "decode", feature = "zstd"))]
+    decompressor: Option<DCtx<'static>>,
+}
+
+impl Context {
+    /// Creates a context. The zstd contexts are allocated by the first call that needs them.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes a delta like [`encode_with_options`](super::encode_with_options), reusing this
+    /// context's zstd compression context.
+    #[cfg(feature = "encode")]
+    pub fn encode(
+        &mut self,
+        tag: usize,
+        base_data: &[u8],
+        new_data: &[u8],
+        options: &EncodeOptions,
+    ) -> Vec<u8> {
+        #[cfg(feature = "zstd")]
+        let _lent = small::lend(&mut self.compressor);
+        encode_with_options(tag, base_data, new_data, options)
+    }
+
+    /// Decodes a delta like [`decode`](super::decode), reusing this context's zstd
+    /// decompression context.
+    #[cfg(feature = "decode")]
+    pub fn decode(&mut self, base_data: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+        #[cfg(feature = "zstd")]
+        let _lent = LentDecompressor::new(&mut self.decompressor);
+        decode(base_data, delta)
+    }
+}
+
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context").finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(feature = "decode", feature = "zstd"))]
+thread_local! {
+    /// Decompression context lent to the calling thread by [`Context::decode`].
+    static DECOMPRESSOR: RefCell<Option<DCtx<'static>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the decompression context lent to the calling thread, if any.
+#[cfg(all(feature = "decode", feature = "zstd"))]
+pub(super) fn with_decompressor<R>(f: impl FnOnce(Option<&mut DCtx<'static>>) -> R) -> R {
+    DECOMPRESSOR.with_borrow_mut(|decompressor| f(decompressor.as_mut()))
+}
+
+/// Lends a [`Context`]'s decompression context, created if needed, to the calling thread
+/// until dropped.
+#[cfg(all(feature = "decode", feature = "zstd"))]
+struct LentDecompressor<'a> {
+    slot: &'a mut Option<DCtx<'static>>,
+    previous: Option<DCtx<'static>>,
+}
+
+#[cfg(all(feature = "decode", feature = "zstd"))]
+impl<'a> LentDecompressor<'a> {
+    fn new(slot: &'a mut Option<DCtx<'static>>) -> Self {
+        let decompressor = slot.take().unwrap_or_else(DCtx::create);
+        let previous = DECOMPRESSOR.replace(Some(decompressor));
+        Self { slot, previous }
+    }
+}
+
+#[cfg(all(feature = "decode", feature = "zstd"))]
+impl Drop for LentDecompressor<'_> {
+    fn drop(&mut self) {
+        *self.slot = DECOMPRESSOR.replace(self.previous.take());
+    }
+}
@@ -0,0 +1,483 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Validating deltas without building their output.
+
+use super::ops::{Instructions, RawOp};
+use super::{
+    Algorithm, HoleFiller, decode, is_multi_source, parse_header, read_header_varint,
+    zstd_decompress,
+};
+use crate::debug::trace_span;
+
+/// Whether a checksum embedded in a delta matched, see [`DryRunReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumCheck {
+    /// The delta carries no such checksum
+    Missing,
+    /// The checksum matched
+    Matched,
+    /// The checksum did not match
+    Mismatched,
+}
+
+/// Something that makes applying a delta risky even though it applies, see
+/// [`DryRunReport::risks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Risk {
+    /// The delta carries no base checksum, so a wrong base goes unnoticed unless a copy runs
+    /// past its end
+    BaseUnverified,
+    /// The delta carries no output checksum, so a corrupted delta goes unnoticed
+    OutputUnverified,
+    /// More than half of the output is inserted rather than copied, a hint that the delta was
+    /// made for a different base
+    MostlyInserted,
+    /// The delta carries literal bytes no instruction uses, a hint that it was tampered with
+    UnusedData,
+}
+
+/// What applying a delta would do, as returned by [`dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// Algorithm of the delta, or `None` for envelopes (compressed files, executables,
+    /// archives) and deltas whose header cannot be read
+    pub algorithm: Option<Algorithm>,
+    /// Length of the base
+    pub base_len: usize,
+    /// Length of the output, up to the first error
+    pub output_len: usize,
+    /// CRC32 of the output, up to the first error
+    pub output_crc32: u32,
+    /// Number of copies from the base
+    pub copies: usize,
+    /// Output bytes copied from the base
+    pub copied_bytes: usize,
+    /// Output bytes inserted from the delta, holes of sparse deltas included
+    pub inserted_bytes: usize,
+    /// Literal bytes of a GDelta payload that no instruction uses
+    pub unused_bytes: usize,
+    /// Outcome of the base checksum
+    pub base_checksum: ChecksumCheck,
+    /// Outcome of the output checksum
+    pub output_checksum: ChecksumCheck,
+    /// Whether the output had to be built after all, for the algorithms and envelopes a dry
+    /// run cannot follow; only its length and CRC32 are known then
+    pub decoded: bool,
+    /// Why applying the delta would fail, if it would
+    pub error: Option<&'static str>,
+}
+
+impl DryRunReport {
+    /// Returns true if applying the delta would succeed.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Returns what makes applying the delta risky, for an updater to weigh against its
+    /// policy; empty for a checksummed delta that matches its base.
+    pub fn risks(&self) -> Vec<Risk> {
+        let mut risks = Vec::new();
+        if self.base_checksum == ChecksumCheck::Missing {
+            risks.push(Risk::BaseUnverified);
+        }
+        if self.output_checksum == ChecksumCheck::Missing {
+            risks.push(Risk::OutputUnverified);
+        }
+        if !self.decoded && self.inserted_bytes > self.output_len / 2 {
+            risks.push(Risk::MostlyInserted);
+        }
+        if self.unused_bytes > 0 {
+            risks.push(Risk::UnusedData);
+        }
+        risks
+    }
+}
+
+/// Checks that `delta` applies to `base_data` without building the output, and reports its
+/// length, CRC32 and how much of it comes from the base.
+///
+/// Every instruction is validated: copies must lie within the base and, if the delta was
+/// encoded with [`max_copy_distance`](super::EncodeOptions::max_copy_distance), within that
+/// distance of the previous copy; holes must lie within the output; both embedded checksums
+/// are verified. The output only passes through a CRC32, so a dry run of a delta producing
+/// gigabytes needs no more memory than the delta. Updaters can use it to decide whether to
+/// apply an update before writing anything.
+///
+/// GDelta, Chars, RepeatChars and Remove deltas, zstd-compressed or not, are followed without
+/// building the output. Token-based deltas and envelopes are decoded in memory, which the
+/// report records in [`decoded`](DryRunReport::decoded); the checksums of deltas inside
+/// envelopes are verified but reported as missing. Deltas protected with error correction are
+/// repaired first. Encrypted deltas fail.
+///
+/// # Example
+/// ```
+/// use xpatch::delta::{self, EncodeOptions, Risk};
+///
+/// let base = b"config v1 with settings that stay the same".repeat(100);
+/// let mut new = base.clone();
+/// new[8] = b'2';
+/// let options = EncodeOptions {
+///     checksum: true,
+///     ..EncodeOptions::default()
+/// };
+/// let patch = delta::encode_with_options(0, &base, &new, &options);
+///
+/// let report = delta::dry_run(&base, &patch);
+/// assert!(report.is_ok() && report.risks().is_empty());
+/// assert_eq!(report.output_len, new.len());
+///
+/// // The patch was made for another base
+/// let report = delta::dry_run(&new, &patch);
+/// assert_eq!(report.error, Some("Base data checksum mismatch"));
+///
+/// let unchecked = delta::encode(0, &base, &new, true);
+/// let risks = delta::dry_run(&base, &unchecked).risks();
+/// assert_eq!(risks, [Risk::BaseUnverified, Risk::OutputUnverified]);
+/// ```
+pub fn dry_run(base_data: &[u8], delta: &[u8]) -> DryRunReport {
+    let _span = trace_span!(INFO, "dry_run", delta_size = delta.len());
+    let mut run = Run {
+        base: base_data,
+        report: DryRunReport {
+            algorithm: None,
+            base_len: base_data.len(),
+            output_len: 0,
+            output_crc32: 0,
+            copies: 0,
+            copied_bytes: 0,
+            inserted_bytes: 0,
+            unused_bytes: 0,
+            base_checksum: ChecksumCheck::Missing,
+            output_checksum: ChecksumCheck::Missing,
+            decoded: false,
+            error: None,
+        },
+        hasher: crc32fast::Hasher::new(),
+    };
+    run.report.error = run.check(delta).err();
+    run.report.output_crc32 = run.hasher.finalize();
+    run.report
+}
+
+/// The state of a [`dry_run`].
+struct Run<'a> {
+    base: &'a [u8],
+    report: DryRunReport,
+    hasher: crc32fast::Hasher,
+}
+
+impl Run<'_> {
+    fn check(&mut self, delta: &[u8]) -> Result<(), &'static str> {
+        if delta.is_empty() {
+            return Err("Empty delta");
+        }
+        #[cfg(feature = "fec")]
+        if crate::fec::is_protected(delta) {
+            let (repaired, _) = crate::fec::repair(delta)?;
+            return self.check(&repaired);
+        }
+        if is_multi_source(delta) {
+            return Err("Multi-source delta needs decode_multi_source");
+        }
+        let header = match parse_header(delta) {
+            Ok(header) => header,
+            // Envelopes, which decode takes apart
+            Err(_) if delta.starts_with(&[0xFC, 0x00]) => return self.decode(delta),
+            Err(e) => return Err(e),
+        };
+        if header.encrypted {
+            return Err("Delta is encrypted");
+        }
+        self.report.algorithm = Some(header.algorithm);
+        if let Some(expected) = header.base_checksum {
+            if crc32fast::hash(self.base) != expected {
+                self.report.base_checksum = ChecksumCheck::Mismatched;
+                return Err("Base data checksum mismatch");
+            }
+            self.report.base_checksum = ChecksumCheck::Matched;
+        }
+
+        let payload = &delta[header.size..];
+        let mut holes = HoleFiller::new(&header.holes);
+        match header.algorithm {
+            _ if header.stored => match header.algorithm {
+                Algorithm::Chars => self.insert(&mut holes, payload),
+                Algorithm::CharsZstd => self.insert(&mut holes, &decompress(payload)?),
+                _ => return Err("Unsupported stored delta"),
+            },
+            Algorithm::GDelta => self.gdelta(&mut holes, payload, header.max_copy_distance)?,
+            Algorithm::GDeltaZstd => {
+                self.gdelta(&mut holes, &decompress(payload)?, header.max_copy_distance)?
+            }
+            Algorithm::Chars | Algorithm::CharsZstd | Algorithm::RepeatChars => {
+                let mut pos = 0;
+                let position = read_header_varint(payload, &mut pos)?;
+                if position > self.base.len() {
+                    return Err("Insert position out of bounds");
+                }
+                self.copy(&mut holes, 0, position)?;
+                match header.algorithm {
+                    Algorithm::Chars => self.insert(&mut holes, &payload[pos..]),
+                    Algorithm::CharsZstd => self.insert(&mut holes, &decompress(&payload[pos..])?),
+                    _ => {
+                        let count = read_header_varint(payload, &mut pos)?;
+                        let pattern = &payload[pos..];
+                        if pattern.is_empty() {
+                            return Err("Empty pattern in repeat chars");
+                        }
+                        for _ in 0..count {
+                            self.insert(&mut holes, pattern);
+                        }
+                    }
+                }
+                self.copy(&mut holes, position, self.base.len() - position)?;
+            }
+            Algorithm::Remove => {
+                let mut pos = 0;
+                let start = read_header_varint(payload, &mut pos)?;
+                let distance = read_header_varint(payload, &mut pos)?;
+                let end = start
+                    .checked_add(distance)
+                    .filter(|&end| end <= self.base.len())
+                    .ok_or("Invalid deletion range")?;
+                self.copy(&mut holes, 0, start)?;
+                self.copy(&mut holes, end, self.base.len() - end)?;
+            }
+            Algorithm::Tokens | Algorithm::RepeatTokens => {
+                // Decoding verified the output checksum
+                self.decode(delta)?;
+                if header.output_checksum.is_some() {
+                    self.report.output_checksum = ChecksumCheck::Matched;
+                }
+                return Ok(());
+            }
+        }
+        let zeros = holes.finish()?;
+        self.zeros(zeros);
+
+        if let Some(expected) = header.output_checksum {
+            if self.hasher.clone().finalize() != expected {
+                self.report.output_checksum = ChecksumCheck::Mismatched;
+                return Err("Output checksum mismatch");
+            }
+            self.report.output_checksum = ChecksumCheck::Matched;
+        }
+        Ok(())
+    }
+
+    /// Follows the instructions of a GDelta payload.
+    fn gdelta(
+        &mut self,
+        holes: &mut HoleFiller<'_>,
+        payload: &[u8],
+        max_distance: Option<usize>,
+    ) -> Result<(), &'static str> {
+        let mut instructions = Instructions::new(payload).map_err(|(_, e)| e)?;
+        // Base position after the previous copy
+        let mut cursor = 0;
+        for instruction in &mut instructions {
+            match instruction.map_err(|(_, e)| e)?.op {
+                RawOp::Copy { offset, len } => {
+                    if max_distance.is_some_and(|max| offset.abs_diff(cursor) > max) {
+                        return Err("Copy exceeds the copy distance limit");
+                    }
+                    self.copy(holes, offset, len)?;
+                    cursor = offset + len;
+                }
+                RawOp::Insert(bytes) => self.insert(holes, bytes),
+            }
+        }
+        self.report.unused_bytes = instructions.unused_literals();
+        Ok(())
+    }
+
+    /// Passes `len` base bytes at `offset` to the output.
+    fn copy(
+        &mut self,
+        holes: &mut HoleFiller<'_>,
+        offset: usize,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let mut bytes = offset
+            .checked_add(len)
+            .and_then(|end| self.base.get(offset..end))
+            .ok_or("Copy out of bounds")?;
+        self.report.copies += usize::from(len > 0);
+        self.report.copied_bytes += len;
+        while !bytes.is_empty() {
+            let (zeros, run) = holes.next(bytes.len());
+            self.zeros(zeros);
+            self.output(&bytes[..run]);
+            bytes = &bytes[run..];
+        }
+        Ok(())
+    }
+
+    /// Passes literal bytes to the output.
+    fn insert(&mut self, holes: &mut HoleFiller<'_>, mut bytes: &[u8]) {
+        self.report.inserted_bytes += bytes.len();
+        while !bytes.is_empty() {
+            let (zeros, run) = holes.next(bytes.len());
+            self.zeros(zeros);
+            self.output(&bytes[..run]);
+            bytes = &bytes[run..];
+        }
+    }
+
+    /// Passes the zeros of holes to the output.
+    fn zeros(&mut self, mut len: usize) {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        self.report.inserted_bytes += len;
+        while len > 0 {
+            let n = len.min(ZEROS.len());
+            self.output(&ZEROS[..n]);
+            len -= n;
+        }
+    }
+
+    fn output(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.report.output_len += bytes.len();
+    }
+
+    /// Decodes a delta the dry run cannot follow, hashing its output.
+    fn decode(&mut self, delta: &[u8]) -> Result<(), &'static str> {
+        self.report.decoded = true;
+        let output = decode(self.base, delta)?;
+        self.output(&output);
+        Ok(())
+    }
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    zstd_decompress(data).map_err(|_| "Error decompressing zstd data")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{EncodeOptions, encode, encode_with_options};
+
+    #[test]
+    fn test_dry_run_matches_decode() {
+        let base: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut changed = base.clone();
+        changed[1000..1010].copy_from_slice(b"0123456789");
+        changed.drain(50_000..60_000);
+        let mut inserted = base.clone();
+        inserted.splice(500..500, *b"inserted text");
+        let mut repeated = base.clone();
+        repeated.splice(500..500, b"ab".repeat(100));
+        let mut removed = base.clone();
+        removed.drain(100..200);
+        let mut sparse = base.clone();
+        sparse[10_000..90_000].fill(0);
+
+        for zstd in [false, true] {
+            let options = EncodeOptions {
+                enable_zstd: zstd,
+                checksum: true,
+                hole_size: 4096,
+                max_copy_distance: 1 << 20,
+                ..EncodeOptions::default()
+            };
+            for new in [
+                &changed,
+                &inserted,
+                &repeated,
+                &removed,
+                &sparse,
+                &base[..0],
+            ] {
+                let delta = encode_with_options(0, &base, new, &options);
+                let report = dry_run(&base, &delta);
+                assert!(report.is_ok(), "{:?}", report.error);
+                assert_eq!(report.output_len, new.len());
+                assert_eq!(report.output_crc32, crc32fast::hash(new));
+                assert_eq!(report.output_checksum, ChecksumCheck::Matched);
+                if !report.decoded {
+                    assert_eq!(report.copied_bytes + report.inserted_bytes, new.len());
+                }
+            }
+        }
+
+        let delta = encode_with_options(
+            0,
+            &base,
+            &changed,
+            &EncodeOptions {
+                checksum: true,
+                ..EncodeOptions::default()
+            },
+        );
+        let report = dry_run(&changed, &delta);
+        assert_eq!(report.base_checksum, ChecksumCheck::Mismatched);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_dry_run_risks() {
+        let base: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = base.clone();
+        new[5000] ^= 0xFF;
+        let delta = encode(0, &base, &new, false);
+
+        // A base that is too short makes the copies run past its end
+        let report = dry_run(&base[..6000], &delta);
+        assert_eq!(report.error, Some("Copy out of bounds"));
+
+        // Literals no instruction reads
+        let mut padded = delta.clone();
+        padded.extend_from_slice(b"hidden");
+        let report = dry_run(&base, &padded);
+        assert!(report.is_ok());
+        assert!(report.risks().contains(&Risk::UnusedData));
+
+        let unrelated: Vec<u8> = (0..10_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let delta = encode(0, &base, &unrelated, false);
+        assert!(
+            dry_run(&base, &delta)
+                .risks()
+                .contains(&Risk::MostlyInserted)
+        );
+        assert_eq!(dry_run(&base, b"").error, Some("Empty delta"));
+    }
+
+    #[test]
+    fn test_dry_run_truncated() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(30);
+        let mut inserted = base.clone();
+        inserted.splice(50..50, *b"inserted words ");
+        let mut repeated = base.clone();
+        repeated.splice(50..50, b"the dog ".repeat(20));
+        for new in [&inserted, &repeated, &base[..100].to_vec()] {
+            let delta = encode(0, &base, new, false);
+            // Some prefixes are valid deltas themselves; none may panic
+            for len in 0..delta.len() {
+                let report = dry_run(&base, &delta[..len]);
+                assert_eq!(report.error.is_none(), decode(&base, &delta[..len]).is_ok());
+            }
+        }
+        assert!(dry_run(&base, &[0x4e, 0xae, 0x06]).error.is_some());
+    }
+}
@@ -0,0 +1,345 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Cheap delta size estimates.
+
+use super::{
+    Algorithm, ChangeType, analyze_change, detect_repeating_pattern, encode_header, encode_remove,
+    extended_header, find_common_prefix,
+};
+
+/// Shortest run along a diagonal that counts as a match.
+const WORD: usize = 8;
+
+/// Bytes hashed per index entry; longer than a word so common words do not crowd the index.
+const KEY: usize = 12;
+
+/// The base is indexed at every `STRIDE`th byte.
+const STRIDE: usize = 8;
+
+/// Diagonals this close to the current one are tried before the index, which covers small
+/// insertions and removals without building it.
+const NEARBY: isize = 16;
+
+/// The new data is walked in cells of this many bytes; a diagonal that stops matching is only
+/// searched for again within the current cell.
+const CELL: usize = 64;
+
+/// Shortest common prefix or suffix GDelta turns into a copy.
+const MIN_AFFIX: usize = 16;
+
+/// Estimates the size of the delta [`encode`](super::encode) would produce, without encoding.
+///
+/// Simple insertions and removals are sized as their uncompressed encodings. For everything
+/// else the new data is walked along the diagonals it shares with the base, realigning on a
+/// nearby diagonal after small insertions and removals and on a sparse index of the base
+/// otherwise, and the copies and literals found are priced as GDelta instructions. This skips
+/// GDelta's rolling hash over unchanged data and all serialization, making it several times
+/// faster than encoding, and far faster on large inputs with few changes.
+///
+/// On realistic edits the estimate is usually within a quarter of the delta size without zstd,
+/// and often within 10%; GDelta splits copies on highly repetitive data, where the estimate
+/// runs low. The zstd stage of [`encode`](super::encode) can only shrink the delta further.
+/// Meant for deciding between sending a delta or the full data, not for allocating buffers.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta;
+///
+/// let base = "The quick brown fox jumps over the lazy dog.\n".repeat(200);
+/// let new = base.replacen("lazy", "sleepy", 3);
+/// let estimate = delta::estimate_size(base.as_bytes(), new.as_bytes());
+/// assert!(estimate < new.len() / 10);
+/// ```
+pub fn estimate_size(base_data: &[u8], new_data: &[u8]) -> usize {
+    match analyze_change(base_data, new_data) {
+        ChangeType::ContinuousAdd { position, data } => {
+            let chars = varint_len(position) + data.len();
+            let payload = match detect_repeating_pattern(&data) {
+                Some((pattern, count)) if count >= 2 => {
+                    chars.min(varint_len(position) + varint_len(count) + pattern.len())
+                }
+                _ => chars,
+            };
+            encode_header(Algorithm::Chars, 0).len() + payload
+        }
+        ChangeType::ContinuousRemove { start, end } => {
+            encode_header(Algorithm::Remove, 0).len() + encode_remove(start, end).len()
+        }
+        ChangeType::Complex => {
+            // Encoding falls back to storing the new data whole if that is smaller
+            let stored = extended_header(Algorithm::Chars, 0, None, None, None, true, &[], None)
+                .len()
+                + new_data.len();
+            (encode_header(Algorithm::GDelta, 0).len() + estimate_gdelta(base_data, new_data))
+                .min(stored)
+        }
+    }
+}
+
+/// Running totals of an estimated GDelta payload.
+#[derive(Default)]
+struct Payload {
+    instructions: usize,
+    literals: usize,
+    /// Last instruction, still open for merging: `Some((offset, len))` for a copy, `None` with
+    /// the literal length otherwise
+    pending: Option<(Option<usize>, usize)>,
+}
+
+impl Payload {
+    fn copy(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        if let Some((Some(start), pending_len)) = &mut self.pending
+            && *start + *pending_len == offset
+        {
+            *pending_len += len;
+            return;
+        }
+        self.flush();
+        self.pending = Some((Some(offset), len));
+    }
+
+    fn literal(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        if let Some((None, pending_len)) = &mut self.pending {
+            *pending_len += len;
+            return;
+        }
+        self.flush();
+        self.pending = Some((None, len));
+    }
+
+    fn flush(&mut self) {
+        match self.pending.take() {
+            Some((Some(offset), len)) => self.instructions += unit_len(len) + varint_len(offset),
+            Some((None, len)) => {
+                self.instructions += unit_len(len);
+                self.literals += len;
+            }
+            None => {}
+        }
+    }
+
+    fn size(mut self) -> usize {
+        self.flush();
+        varint_len(self.instructions) + self.instructions + self.literals
+    }
+}
+
+fn estimate_gdelta(base_data: &[u8], new_data: &[u8]) -> usize {
+    let mut payload = Payload::default();
+
+    // Common prefix and suffix, as GDelta finds them
+    let prefix = find_common_prefix(base_data, new_data);
+    let prefix = if prefix >= MIN_AFFIX { prefix } else { 0 };
+    let suffix = common_suffix(&base_data[prefix..], &new_data[prefix..]);
+    let suffix = if suffix >= MIN_AFFIX { suffix } else { 0 };
+    let base = &base_data[prefix..base_data.len() - suffix];
+    let new = &new_data[prefix..new_data.len() - suffix];
+
+    payload.copy(0, prefix);
+    if base.len() < KEY || new.len() < KEY {
+        payload.literal(new.len());
+    } else {
+        estimate_middle(base, prefix, new, &mut payload);
+    }
+    payload.copy(base_data.len() - suffix, suffix);
+
+    payload.size()
+}
+
+/// Prices the part between the common prefix and suffix; `shift` is the prefix length.
+///
+/// Works through the new data following the current diagonal (base position minus new
+/// position, starting at 0 right after the prefix) with plain comparisons: matching runs are
+/// copies and the bytes until the next run of at least a word are literals. When the diagonal
+/// stops matching for the rest of a cell, the new data is checked every eight bytes for a match
+/// along another diagonal, and the first one found takes over.
+fn estimate_middle(base: &[u8], shift: usize, new: &[u8], payload: &mut Payload) {
+    let mut index = Index::new(base);
+    let mut diagonal = 0isize;
+    for pos in (0..new.len()).step_by(CELL) {
+        let end = (pos + CELL).min(new.len());
+        let mut at = pos;
+        while at < end {
+            // Copies along the current diagonal
+            let len = match_after(base, new, at, end, diagonal);
+            payload.copy((shift as isize + at as isize + diagonal) as usize, len);
+            at += len;
+            if at >= end {
+                break;
+            }
+
+            // Literals up to the next run along the same diagonal
+            if let Some(next) = (at + 1..end.saturating_sub(WORD - 1))
+                .find(|&next| match_after(base, new, next, next + WORD, diagonal) == WORD)
+            {
+                payload.literal(next - at);
+                at = next;
+                continue;
+            }
+
+            // Literals up to a match along another diagonal
+            let found = (at + 2 * STRIDE..end + 2 * STRIDE)
+                .step_by(2 * STRIDE)
+                .map(|checkpoint| checkpoint.min(end))
+                .find_map(|checkpoint| {
+                    let (next, tail) = index.find_diagonal(new, at, checkpoint, diagonal);
+                    (tail > 0).then_some((checkpoint, next, tail))
+                });
+            let Some((checkpoint, next, tail)) = found else {
+                payload.literal(end - at);
+                break;
+            };
+            payload.literal(checkpoint - tail - at);
+            payload.copy(
+                (shift as isize + (checkpoint - tail) as isize + next) as usize,
+                tail,
+            );
+            at = checkpoint;
+            diagonal = next;
+        }
+    }
+}
+
+/// Hash index of the base, built on first use.
+struct Index<'a> {
+    base: &'a [u8],
+    /// Base offset per slot (`u32::MAX` if empty) and the slot bits
+    table: Option<(Vec<u32>, u32)>,
+}
+
+impl<'a> Index<'a> {
+    fn new(base: &'a [u8]) -> Self {
+        Self { base, table: None }
+    }
+
+    /// Finds the diagonal giving the longest match ending at `end` and not reaching back
+    /// before `start`. Diagonals near `current` and the one leading into the common suffix
+    /// are tried first; the index is only consulted when none of them matches.
+    /// Returns the diagonal and the match length, or `current` and 0 if nothing matches a
+    /// whole word.
+    fn find_diagonal(
+        &mut self,
+        new: &[u8],
+        start: usize,
+        end: usize,
+        current: isize,
+    ) -> (isize, usize) {
+        let base = self.base;
+        let best = |diagonals: &mut dyn Iterator<Item = isize>| {
+            diagonals
+                .map(|d| (d, match_before(base, new, start, end, d)))
+                .filter(|&(_, len)| len >= WORD)
+                .max_by_key(|&(_, len)| len)
+        };
+
+        let suffix = base.len() as isize - new.len() as isize;
+        let nearby = best(&mut (current - NEARBY..=current + NEARBY).chain([suffix]));
+        if let Some(found) = nearby {
+            return found;
+        }
+        // Give nearby diagonals a couple of keys' worth of data before building the index
+        if end < start + 2 * KEY {
+            return (current, 0);
+        }
+
+        // One of STRIDE consecutive probes is aligned with an indexed offset; probing two
+        // strides' worth gets past a key that a later duplicate took over
+        let (table, bits) = self.table.get_or_insert_with(|| build_table(base));
+        let last = end - KEY;
+        let probed = best(
+            &mut (start.max(last.saturating_sub(2 * STRIDE - 1))..=last).filter_map(|pos| {
+                let offset = table[slot(new, pos, *bits)];
+                (offset != u32::MAX).then(|| offset as isize - pos as isize)
+            }),
+        );
+        probed.unwrap_or((current, 0))
+    }
+}
+
+/// Indexes the base every STRIDE bytes; later keys overwrite colliding slots.
+fn build_table(base: &[u8]) -> (Vec<u32>, u32) {
+    let bits = usize::BITS - (base.len() / STRIDE * 2).leading_zeros();
+    let mut table = vec![u32::MAX; 1 << bits];
+    for offset in (0..=base.len() - KEY).step_by(STRIDE) {
+        table[slot(base, offset, bits)] = offset as u32;
+    }
+    (table, bits)
+}
+
+/// Length of the match starting at `start` along diagonal `d`, not reaching past `end`.
+fn match_after(base: &[u8], new: &[u8], start: usize, end: usize, d: isize) -> usize {
+    let base_start = start as isize + d;
+    if base_start < 0 || base_start as usize >= base.len() {
+        return 0;
+    }
+    let base_start = base_start as usize;
+    let len = (end - start).min(base.len() - base_start);
+    find_common_prefix(
+        &new[start..start + len],
+        &base[base_start..base_start + len],
+    )
+}
+
+/// Length of the match ending at `end` along diagonal `d`, not reaching back before `start`.
+fn match_before(base: &[u8], new: &[u8], start: usize, end: usize, d: isize) -> usize {
+    let base_end = end as isize + d;
+    if base_end < 0 || base_end as usize > base.len() {
+        return 0;
+    }
+    let base_end = base_end as usize;
+    let len = (end - start).min(base_end);
+    common_suffix(&base[base_end - len..base_end], &new[end - len..end])
+}
+
+fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Index slot of the key at `offset`.
+fn slot(data: &[u8], offset: usize, bits: u32) -> usize {
+    let word = |at: usize| u64::from_le_bytes(data[at..at + WORD].try_into().unwrap());
+    let key = word(offset) ^ word(offset + KEY - WORD).rotate_left(29);
+    (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - bits)) as usize
+}
+
+/// Size of a GDelta instruction head: one byte holding 6 length bits, then a varint of the rest.
+fn unit_len(len: usize) -> usize {
+    1 + if len >> 6 > 0 {
+        varint_len(len >> 6)
+    } else {
+        0
+    }
+}
+
+fn varint_len(value: usize) -> usize {
+    (usize::BITS - value.leading_zeros()).div_ceil(7).max(1) as usize
+}
@@ -0,0 +1,346 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Reusable match indexes of a base.
+
+use super::{
+    EncodeOptions, MatchHash, Progress, encode_internal, find_common_prefix, write_gdelta_unit,
+};
+use crate::debug::trace_span;
+use crate::varint::encode_varint;
+
+/// Bytes hashed per index entry; also the shortest match that starts a copy.
+const WORD: usize = 8;
+
+/// The base is indexed at every `STRIDE`th byte.
+const STRIDE: usize = 4;
+
+const INDEX_MAGIC: &[u8; 4] = b"XBI\x01";
+/// Magic of indexes built with a [`MatchHash`] other than the standard one.
+const INDEX_MAGIC_HASHED: &[u8; 4] = b"XBI\x02";
+const INDEX_HEADER_SIZE: usize = 16;
+
+/// A match index of base data, built once and reused by [`encode_with_index`].
+///
+/// [`encode`](super::encode) hashes the whole base for every delta it builds, which dominates
+/// the cost when one base is diffed against many targets, e.g. a golden image against the
+/// images of thousands of clients. A `BaseIndex` does that work once, up front; it takes
+/// one to two bytes of memory per byte of base and can be shared between threads.
+///
+/// The index carries the options every delta built from it is encoded with. It can be saved
+/// with [`BaseIndex::serialize`] and loaded next to its base with [`BaseIndex::deserialize`],
+/// which skips building it again.
+///
+/// # Format
+///
+/// ```text
+/// "XBI\x01" | base length u64 | base crc32 u32 | base offset u32 per slot
+/// ```
+///
+/// All integers are little-endian; empty slots hold `u32::MAX`. The number of slots follows
+/// from the base length. Indexes built with a [`MatchHash`] other than the standard one start
+/// with `"XBI\x02"` followed by the hash's `u8` id instead.
+#[derive(Debug, Clone)]
+pub struct BaseIndex<'a> {
+    base: &'a [u8],
+    options: EncodeOptions,
+    base_checksum: u32,
+    /// Base offset per slot, `u32::MAX` if empty
+    table: Vec<u32>,
+    bits: u32,
+}
+
+impl<'a> BaseIndex<'a> {
+    /// Indexes `base_data` for encoding deltas with `options`.
+    ///
+    /// Only the first 4 GiB of the base are indexed; data beyond that is still copied where a
+    /// match runs into it, but never found on its own.
+    pub fn new(base_data: &'a [u8], options: &EncodeOptions) -> Self {
+        let _span = trace_span!(DEBUG, "index", base_size = base_data.len());
+        let indexed = base_data.len().min(u32::MAX as usize);
+        let bits = slot_bits(base_data.len());
+        let mut table = vec![u32::MAX; 1 << bits];
+        if indexed >= WORD {
+            for offset in (0..=indexed - WORD).step_by(STRIDE) {
+                table[slot(options.match_hash, base_data, offset, bits)] = offset as u32;
+            }
+        }
+
+        Self {
+            base: base_data,
+            options: options.clone(),
+            base_checksum: crc32fast::hash(base_data),
+            table,
+            bits,
+        }
+    }
+
+    /// Serializes the index for storage next to its base.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(INDEX_HEADER_SIZE + 1 + self.table.len() * 4);
+        match self.options.match_hash {
+            MatchHash::Standard => bytes.extend_from_slice(INDEX_MAGIC),
+            hash => {
+                bytes.extend_from_slice(INDEX_MAGIC_HASHED);
+                bytes.push(hash.into());
+            }
+        }
+        bytes.extend_from_slice(&(self.base.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.base_checksum.to_le_bytes());
+        for offset in &self.table {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Loads an index serialized with [`BaseIndex::serialize`] for `base_data`, to encode
+    /// deltas with `options`.
+    ///
+    /// Checking the base against the recorded length and CRC32 is the only pass over it, so
+    /// this is much cheaper than [`BaseIndex::new`].
+    ///
+    /// # Errors
+    /// Returns an error if `bytes` is not a serialized index, or if it was built from
+    /// different base data or with a different [`EncodeOptions::match_hash`].
+    pub fn deserialize(
+        bytes: &[u8],
+        base_data: &'a [u8],
+        options: &EncodeOptions,
+    ) -> Result<Self, &'static str> {
+        // Past the magic and the hash id, if any
+        let (hash, fields) = match bytes.split_first_chunk::<4>() {
+            Some((magic, fields)) if magic == INDEX_MAGIC => (MatchHash::Standard, fields),
+            Some((magic, [hash, fields @ ..])) if magic == INDEX_MAGIC_HASHED => (
+                MatchHash::try_from(*hash).map_err(|_| "Invalid base index")?,
+                fields,
+            ),
+            _ => return Err("Invalid base index"),
+        };
+        if fields.len() < INDEX_HEADER_SIZE - 4 {
+            return Err("Invalid base index");
+        }
+        if hash != options.match_hash {
+            return Err("Base index was built with a different match hash");
+        }
+        let base_len = u64::from_le_bytes(fields[..8].try_into().unwrap());
+        let base_checksum = u32::from_le_bytes(fields[8..12].try_into().unwrap());
+        if base_len != base_data.len() as u64 || base_checksum != crc32fast::hash(base_data) {
+            return Err("Base index does not match the base data");
+        }
+
+        let bits = slot_bits(base_data.len());
+        let entries = &fields[INDEX_HEADER_SIZE - 4..];
+        if entries.len() != 4 << bits {
+            return Err("Invalid base index length");
+        }
+        let table = entries
+            .chunks_exact(4)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            base: base_data,
+            options: options.clone(),
+            base_checksum,
+            table,
+            bits,
+        })
+    }
+
+    /// The indexed base data.
+    pub fn base(&self) -> &'a [u8] {
+        self.base
+    }
+
+    /// The options deltas are encoded with.
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    /// CRC32 of the base, embedded in deltas when `options.checksum` is set.
+    pub(super) fn base_checksum(&self) -> u32 {
+        self.base_checksum
+    }
+
+    /// Encodes `new_data` against the base as a plain GDelta payload.
+    ///
+    /// Like GDelta, the common prefix and suffix become copies and the rest is scanned byte by
+    /// byte for words found in the base. A word that continues the previous copy is preferred
+    /// over the index, and every match is extended in both directions.
+    pub(super) fn encode_gdelta(&self, new_data: &[u8]) -> Vec<u8> {
+        let base = self.base;
+        let prefix = find_common_prefix(base, new_data);
+        let prefix = if prefix >= WORD { prefix } else { 0 };
+        let suffix = common_suffix(&base[prefix..], &new_data[prefix..]);
+        let suffix = if suffix >= WORD { suffix } else { 0 };
+        let end = new_data.len() - suffix;
+
+        let mut payload = Payload::default();
+        payload.copy(0, prefix);
+        let mut literal_start = prefix;
+        // Base position minus new position of the last copy
+        let mut diagonal = 0isize;
+        let mut pos = prefix;
+
+        while pos + WORD <= end {
+            let word = &new_data[pos..pos + WORD];
+            let matches = |offset: usize| base.get(offset..offset + WORD) == Some(word);
+            let continued = usize::try_from(pos as isize + diagonal)
+                .ok()
+                .filter(|&offset| matches(offset));
+            let found = continued.or_else(|| {
+                let offset = self.table[slot(self.options.match_hash, new_data, pos, self.bits)];
+                (offset != u32::MAX && matches(offset as usize)).then_some(offset as usize)
+            });
+            let Some(offset) = found else {
+                pos += 1;
+                continue;
+            };
+
+            let back = common_suffix(&base[..offset], &new_data[literal_start..pos]);
+            let forward = find_common_prefix(&base[offset + WORD..], &new_data[pos + WORD..end]);
+            let (offset, start, len) = (offset - back, pos - back, back + WORD + forward);
+            payload.literal(&new_data[literal_start..start]);
+            payload.copy(offset, len);
+            pos = start + len;
+            literal_start = pos;
+            diagonal = offset as isize - start as isize;
+        }
+
+        payload.literal(&new_data[literal_start..end]);
+        payload.copy(base.len() - suffix, suffix);
+        payload.finish()
+    }
+}
+
+/// Encodes a delta from `index.base()` to `new_data` with the index's options.
+///
+/// Simple insertions and removals are encoded exactly like [`encode_with_options`] would;
+/// everything else uses the prebuilt index instead of hashing the base again. The result is
+/// applied with [`decode`](super::decode) like any other delta.
+///
+/// [`encode_with_options`]: super::encode_with_options
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::{self, BaseIndex, EncodeOptions};
+///
+/// let golden = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+/// let index = BaseIndex::new(&golden, &EncodeOptions::default());
+///
+/// for client in 0..10 {
+///     let mut image = golden.clone();
+///     image[client * 100..client * 100 + 5].copy_from_slice(b"12345");
+///     let patch = delta::encode_with_index(0, &index, &image);
+///     assert_eq!(delta::decode(&golden, &patch).unwrap(), image);
+/// }
+/// ```
+pub fn encode_with_index(tag: usize, index: &BaseIndex, new_data: &[u8]) -> Vec<u8> {
+    encode_internal(
+        tag,
+        index.base,
+        new_data,
+        &index.options,
+        &mut Progress::none(),
+        Some(index),
+    )
+    .expect("encoding without a progress callback cannot be cancelled")
+}
+
+/// GDelta instructions and literals being collected, with the last copy held back so that
+/// adjacent copies merge.
+#[derive(Default)]
+pub(super) struct Payload {
+    instructions: Vec<u8>,
+    literals: Vec<u8>,
+    copy: Option<(usize, usize)>,
+}
+
+impl Payload {
+    pub(super) fn copy(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        match &mut self.copy {
+            Some((start, pending)) if *start + *pending == offset => *pending += len,
+            _ => {
+                self.flush();
+                self.copy = Some((offset, len));
+            }
+        }
+    }
+
+    pub(super) fn literal(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.flush();
+        write_gdelta_unit(&mut self.instructions, false, data.len(), 0);
+        self.literals.extend_from_slice(data);
+    }
+
+    fn flush(&mut self) {
+        if let Some((offset, len)) = self.copy.take() {
+            write_gdelta_unit(&mut self.instructions, true, len, offset);
+        }
+    }
+
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        self.flush();
+        let mut payload = encode_varint(self.instructions.len());
+        payload.extend(self.instructions);
+        payload.extend(self.literals);
+        payload
+    }
+}
+
+pub(super) fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[a.len() - len..], &b[b.len() - len..]);
+    // Compare 8 bytes at a time, then the rest of the last differing chunk byte by byte
+    let mut end = len;
+    while end >= 8 && a[end - 8..end] == b[end - 8..end] {
+        end -= 8;
+    }
+    let tail = a[..end]
+        .iter()
+        .rev()
+        .zip(b[..end].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    len - end + tail
+}
+
+/// Number of bits of a slot for a base of `base_len` bytes: one to two slots per indexed
+/// offset.
+fn slot_bits(base_len: usize) -> u32 {
+    let indexed = base_len.min(u32::MAX as usize);
+    (indexed / STRIDE)
+        .max(2)
+        .next_power_of_two()
+        .trailing_zeros()
+}
+
+/// Index slot of the word at `offset`.
+fn slot(hash: MatchHash, data: &[u8], offset: usize, bits: u32) -> usize {
+    let word = u64::from_le_bytes(data[offset..offset + WORD].try_into().unwrap());
+    hash.slot(word, bits)
+}
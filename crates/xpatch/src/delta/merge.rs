@@ -0,0 +1,417 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Three-way merges of two deltas against the same base.
+
+use super::ops::{Op, apply_ops, decode_ops, encode_from_ops};
+use super::{Algorithm, DeltaInfo, EncodeOptions, inspect};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Shortest copy that anchors a delta to the base when it skips ahead. Shorter forward copies
+/// are usually coincidental matches and count as part of the surrounding change.
+const MIN_ANCHOR: usize = 32;
+
+/// A part of the base that both deltas changed differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Range of the base covered by the overlapping changes. Empty where both deltas insert at
+    /// the same position
+    pub base: Range<usize>,
+    /// What the first delta puts in place of `base`
+    pub a: Vec<u8>,
+    /// What the second delta puts in place of `base`
+    pub b: Vec<u8>,
+}
+
+/// The conflicting changes that stopped a [`merge`], in base order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflicts(pub Vec<Conflict>);
+
+impl fmt::Display for Conflicts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Conflicting changes at base")?;
+        for (i, conflict) in self.0.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{:?}", separator, conflict.base)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Conflicts {}
+
+/// Why [`merge`] could not combine two deltas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// A delta can't be read or doesn't apply to the base
+    Invalid(&'static str),
+    /// Both deltas change the same part of the base
+    Conflicts(Conflicts),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::Invalid(e) => f.write_str(e),
+            MergeError::Conflicts(conflicts) => conflicts.fmt(f),
+        }
+    }
+}
+
+impl Error for MergeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MergeError::Invalid(_) => None,
+            MergeError::Conflicts(conflicts) => Some(conflicts),
+        }
+    }
+}
+
+impl From<&'static str> for MergeError {
+    fn from(e: &'static str) -> Self {
+        MergeError::Invalid(e)
+    }
+}
+
+/// A range of the base a delta replaces, and the instructions producing the replacement.
+#[derive(Debug, Clone)]
+struct Hunk {
+    base: Range<usize>,
+    ops: Vec<Op>,
+}
+
+/// Combines two deltas made independently against `base` into one that applies both changes.
+///
+/// Each delta is split into hunks: the ranges of the base it replaces and what it puts there.
+/// When no hunk of one delta overlaps a hunk of the other, the result keeps the base between
+/// them and applies every hunk of both. Hunks both deltas made identically are applied once.
+/// Overlapping hunks, and insertions of both deltas at the same position, are reported as
+/// [`Conflict`]s with what each delta wanted there.
+///
+/// The result carries the tag of `delta_a`. It has checksums if either delta had them and is
+/// compressed if either delta was.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::{self, MergeError};
+///
+/// let base = b"title: Report\nauthor: Ann\n".repeat(8);
+/// let mut ours = base.clone();
+/// ours[7..13].copy_from_slice(b"Review");
+/// let mut theirs = base.clone();
+/// theirs.extend_from_slice(b"status: draft\n");
+///
+/// let a = delta::encode(0, &base, &ours, false);
+/// let b = delta::encode(0, &base, &theirs, false);
+/// let merged = delta::merge(&base, &a, &b)?;
+/// assert!(delta::decode(&base, &merged)?.starts_with(b"title: Review\n"));
+/// assert!(delta::decode(&base, &merged)?.ends_with(b"status: draft\n"));
+///
+/// // Changing the same word twice conflicts
+/// let mut other = base.clone();
+/// other[7..13].copy_from_slice(b"Slides");
+/// let c = delta::encode(0, &base, &other, false);
+/// match delta::merge(&base, &a, &c) {
+///     Err(MergeError::Conflicts(conflicts)) => {
+///         let conflict = &conflicts.0[0];
+///         assert!(conflict.a.ends_with(b"Review") && conflict.b.ends_with(b"Slides"));
+///     }
+///     other => panic!("expected a conflict, got {:?}", other),
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn merge(base: &[u8], delta_a: &[u8], delta_b: &[u8]) -> Result<Vec<u8>, MergeError> {
+    let info_a = check(base, delta_a)?;
+    let info_b = check(base, delta_b)?;
+    let hunks_a = hunks(base, &decode_ops(base, delta_a)?)?;
+    let hunks_b = hunks(base, &decode_ops(base, delta_b)?)?;
+
+    let mut merged = Vec::with_capacity(hunks_a.len() + hunks_b.len());
+    let mut conflicts = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < hunks_a.len() || j < hunks_b.len() {
+        // Take the next hunk in base order, then every hunk of either side chained to it by
+        // overlaps
+        let a_first = j == hunks_b.len()
+            || i < hunks_a.len() && range_key(&hunks_a[i].base) <= range_key(&hunks_b[j].base);
+        let (start_i, start_j) = (i, j);
+        let mut end = if a_first {
+            i += 1;
+            hunks_a[i - 1].base.clone()
+        } else {
+            j += 1;
+            hunks_b[j - 1].base.clone()
+        };
+        loop {
+            if i < hunks_a.len() && overlaps(&end, &hunks_a[i].base) {
+                end.end = end.end.max(hunks_a[i].base.end);
+                i += 1;
+            } else if j < hunks_b.len() && overlaps(&end, &hunks_b[j].base) {
+                end.end = end.end.max(hunks_b[j].base.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        let (side_a, side_b) = (&hunks_a[start_i..i], &hunks_b[start_j..j]);
+        if side_a.is_empty() || side_b.is_empty() {
+            merged.extend(side_a.iter().chain(side_b).cloned());
+            continue;
+        }
+        let a = replacement(base, side_a, end.clone())?;
+        let b = replacement(base, side_b, end.clone())?;
+        if a == b {
+            merged.extend(side_a.iter().cloned());
+        } else {
+            conflicts.push(Conflict { base: end, a, b });
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(MergeError::Conflicts(Conflicts(conflicts)));
+    }
+
+    let mut ops = Vec::new();
+    let mut position = 0;
+    for hunk in merged {
+        ops.push(Op::Copy {
+            offset: position,
+            len: hunk.base.start - position,
+        });
+        ops.extend(hunk.ops);
+        position = hunk.base.end;
+    }
+    ops.push(Op::Copy {
+        offset: position,
+        len: base.len() - position,
+    });
+
+    let compressed =
+        |info: &DeltaInfo| matches!(info.algorithm, Algorithm::GDeltaZstd | Algorithm::CharsZstd);
+    let options = EncodeOptions {
+        enable_zstd: compressed(&info_a) || compressed(&info_b),
+        checksum: info_a.base_checksum.is_some() || info_b.base_checksum.is_some(),
+        optimize: true,
+        ..EncodeOptions::default()
+    };
+    Ok(encode_from_ops(info_a.tag, base, &ops, &options)?)
+}
+
+/// Inspects a delta and checks its base checksum, if it has one.
+fn check(base: &[u8], delta: &[u8]) -> Result<DeltaInfo, &'static str> {
+    let info = inspect(delta)?;
+    if info
+        .base_checksum
+        .is_some_and(|checksum| checksum != crc32fast::hash(base))
+    {
+        return Err("Base data checksum mismatch");
+    }
+    Ok(info)
+}
+
+/// Splits instructions into the hunks they apply to the base, in base order.
+///
+/// Copies that continue where the base was left, or skip ahead by at least [`MIN_ANCHOR`]
+/// bytes, keep the base; the base bytes they skip are removed. Every other instruction is
+/// part of a hunk. Hunks that leave their range as it was are dropped.
+fn hunks(base: &[u8], ops: &[Op]) -> Result<Vec<Hunk>, &'static str> {
+    let mut hunks = Vec::new();
+    let mut position = 0;
+    let mut current: Option<Hunk> = None;
+    for op in ops.iter().filter(|op| !op.is_empty()) {
+        if let Op::Copy { offset, len } = *op {
+            if offset.checked_add(len).is_none_or(|end| end > base.len()) {
+                return Err("Copy out of bounds");
+            }
+            if offset == position || offset > position && len >= MIN_ANCHOR {
+                if offset > position || current.is_some() {
+                    let mut hunk = current.take().unwrap_or(Hunk {
+                        base: position..position,
+                        ops: Vec::new(),
+                    });
+                    hunk.base.end = offset;
+                    hunks.push(hunk);
+                }
+                position = offset + len;
+                continue;
+            }
+        }
+        current
+            .get_or_insert_with(|| Hunk {
+                base: position..position,
+                ops: Vec::new(),
+            })
+            .ops
+            .push(op.clone());
+    }
+    if position < base.len() || current.is_some() {
+        let mut hunk = current.unwrap_or(Hunk {
+            base: position..position,
+            ops: Vec::new(),
+        });
+        hunk.base.end = base.len();
+        hunks.push(hunk);
+    }
+
+    // Hunks that put back the bytes they replace change nothing, e.g. a small base the encoder
+    // wrote out as a literal
+    let mut changed = Vec::with_capacity(hunks.len());
+    for hunk in hunks {
+        let len = hunk.ops.iter().map(Op::len).sum::<usize>();
+        if len != hunk.base.len() || apply_ops(base, &hunk.ops)? != base[hunk.base.clone()] {
+            changed.push(hunk);
+        }
+    }
+    Ok(changed)
+}
+
+/// Sort key of a hunk's range, placing an insertion after a change ending where it is.
+fn range_key(range: &Range<usize>) -> (usize, usize) {
+    (range.start, range.end)
+}
+
+/// Whether two hunks touch the same part of the base: their ranges overlap, or they start at
+/// the same position (so their order would be ambiguous).
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start == b.start || a.start < b.end && b.start < a.end
+}
+
+/// What `hunks` put in place of `range` together with the base bytes between them.
+fn replacement(base: &[u8], hunks: &[Hunk], range: Range<usize>) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::new();
+    let mut position = range.start;
+    for hunk in hunks {
+        output.extend_from_slice(&base[position..hunk.base.start]);
+        output.extend(apply_ops(base, &hunk.ops)?);
+        position = hunk.base.end;
+    }
+    output.extend_from_slice(&base[position..range.end]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{decode, encode, encode_with_options};
+
+    fn lines(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| format!("record {:04}: the value is {}\n", i, i * 7).into_bytes())
+            .collect()
+    }
+
+    fn replace(data: &[u8], range: Range<usize>, with: &[u8]) -> Vec<u8> {
+        [&data[..range.start], with, &data[range.end..]].concat()
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = lines(400);
+        let ours = replace(&base, 1_000..1_010, b"edited by a");
+        let theirs = replace(
+            &replace(&base, 9_000..9_400, b""),
+            100..100,
+            b"inserted by b",
+        );
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let a = encode_with_options(4, &base, &ours, &options);
+        let b = encode(7, &base, &theirs, false);
+
+        let merged = merge(&base, &a, &b).unwrap();
+        let expected = replace(
+            &replace(
+                &replace(&base, 9_000..9_400, b""),
+                1_000..1_010,
+                b"edited by a",
+            ),
+            100..100,
+            b"inserted by b",
+        );
+        assert_eq!(decode(&base, &merged).unwrap(), expected);
+        let info = inspect(&merged).unwrap();
+        assert_eq!(info.tag, 4);
+        assert!(info.base_checksum.is_some());
+
+        // Merging is symmetric, and the same change made twice is applied once
+        assert_eq!(
+            decode(&base, &merge(&base, &b, &a).unwrap()).unwrap(),
+            expected
+        );
+        assert_eq!(decode(&base, &merge(&base, &a, &a).unwrap()).unwrap(), ours);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let base = lines(400);
+        let a = encode(0, &base, &replace(&base, 2_000..2_004, b"AAAA"), false);
+        let b = encode(0, &base, &replace(&base, 2_002..2_006, b"BBBB"), false);
+
+        let Err(MergeError::Conflicts(Conflicts(conflicts))) = merge(&base, &a, &b) else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert!(conflict.base.start <= 2_000 && conflict.base.end >= 2_006);
+        assert_eq!(
+            replace(&base, conflict.base.clone(), &conflict.a),
+            replace(&base, 2_000..2_004, b"AAAA")
+        );
+        assert_eq!(
+            replace(&base, conflict.base.clone(), &conflict.b),
+            replace(&base, 2_002..2_006, b"BBBB")
+        );
+
+        // Insertions at the same position conflict too
+        let a = encode(0, &base, &replace(&base, 500..500, b"one"), false);
+        let b = encode(0, &base, &replace(&base, 500..500, b"two"), false);
+        assert!(matches!(
+            merge(&base, &a, &b),
+            Err(MergeError::Conflicts(_))
+        ));
+
+        assert!(matches!(
+            merge(&lines(10), &a, &b),
+            Err(MergeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_merge_identity_on_short_base() {
+        let base = b"short base".to_vec();
+        let edited = replace(&base, 0..5, b"long");
+        let a = encode(0, &base, &edited, false);
+        let identity = encode(0, &base, &base, false);
+
+        assert_eq!(
+            decode(&base, &merge(&base, &a, &identity).unwrap()).unwrap(),
+            edited
+        );
+        assert_eq!(
+            decode(&base, &merge(&base, &identity, &a).unwrap()).unwrap(),
+            edited
+        );
+    }
+}
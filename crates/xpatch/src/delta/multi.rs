@@ -0,0 +1,218 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Deltas against a set of labeled bases.
+//!
+//! A target assembled from several artifacts, e.g. an app binary that embeds a shared asset
+//! pack, shares data with each of them. [`encode_multi_source`] encodes it against all of them
+//! at once, so copies can reference any base, and [`decode_multi_source`] applies the delta
+//! to the same set, looking the bases up by label.
+//!
+//! # Format
+//!
+//! ```text
+//! 0xFC 0x00 "MS" | version u8 | varint(base count) | base* | delta
+//! base = varint(label length) | label | varint(size) | crc32 u32 LE
+//! ```
+//!
+//! `delta` is a plain delta against the bases concatenated in the listed order. The first two
+//! bytes form an invalid delta header, so a multi-source delta is never mistaken for a plain
+//! one.
+
+use super::read_header_varint;
+#[cfg(feature = "encode")]
+use super::{EncodeOptions, encode_with_options};
+#[cfg(feature = "encode")]
+use crate::varint::encode_varint;
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'M', b'S'];
+const VERSION: u8 = 1;
+
+/// Encodes `new_data` against several bases, each identified by a label.
+///
+/// Copies may reference any of the bases, so the delta is smaller than one against any single
+/// base when the new data combines content from several of them. The labels, sizes and CRC32s
+/// of the bases are stored in the delta; decode it with [`decode_multi_source`]. The `tag` and
+/// `options` apply to the inner delta.
+///
+/// # Panics
+/// Panics if two bases have the same label.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::{self, EncodeOptions};
+///
+/// let app = b"fn main() { println!(\"v1\"); }".repeat(8);
+/// let assets = b"icon.png logo.svg font.ttf".repeat(8);
+/// let new = [&app[..], b" v2 ", &assets[..]].concat();
+///
+/// let sources = [("app", &app[..]), ("assets", &assets[..])];
+/// let patch = delta::encode_multi_source(0, &sources, &new, &EncodeOptions::default());
+/// assert_eq!(delta::multi_source_labels(&patch).unwrap(), ["app", "assets"]);
+///
+/// // The bases may be passed in any order
+/// let reversed = [("assets", &assets[..]), ("app", &app[..])];
+/// assert_eq!(delta::decode_multi_source(&reversed, &patch).unwrap(), new);
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_multi_source<L: AsRef<str>, B: AsRef<[u8]>>(
+    tag: usize,
+    sources: &[(L, B)],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend(encode_varint(sources.len()));
+    let mut combined =
+        Vec::with_capacity(sources.iter().map(|(_, data)| data.as_ref().len()).sum());
+    for (i, (label, data)) in sources.iter().enumerate() {
+        let (label, data) = (label.as_ref(), data.as_ref());
+        assert!(
+            sources[..i]
+                .iter()
+                .all(|(other, _)| other.as_ref() != label),
+            "duplicate multi-source label"
+        );
+        out.extend(encode_varint(label.len()));
+        out.extend_from_slice(label.as_bytes());
+        out.extend(encode_varint(data.len()));
+        out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        combined.extend_from_slice(data);
+    }
+    out.extend(encode_with_options(tag, &combined, new_data, options));
+    out
+}
+
+/// Applies a delta made by [`encode_multi_source`] to the labeled bases it was encoded
+/// against.
+///
+/// The bases may be passed in any order and `sources` may hold bases the delta doesn't use.
+///
+/// # Errors
+/// Returns an error if a base the delta needs is missing or differs in size or CRC32 from the
+/// one it was encoded against, or any error [`decode`](super::decode) returns.
+#[cfg(feature = "decode")]
+pub fn decode_multi_source<L: AsRef<str>, B: AsRef<[u8]>>(
+    sources: &[(L, B)],
+    delta: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    let (bases, inner) = parse(delta)?;
+    let mut combined = Vec::with_capacity(bases.iter().map(|base| base.size).sum());
+    for base in &bases {
+        let data = sources
+            .iter()
+            .find(|(label, _)| label.as_ref() == base.label)
+            .map(|(_, data)| data.as_ref())
+            .ok_or("Missing base for multi-source delta")?;
+        if data.len() != base.size || crc32fast::hash(data) != base.checksum {
+            return Err("Multi-source base mismatch");
+        }
+        combined.extend_from_slice(data);
+    }
+    super::decode(&combined, inner)
+}
+
+/// Returns whether `delta` was made by [`encode_multi_source`].
+pub fn is_multi_source(delta: &[u8]) -> bool {
+    delta.starts_with(&MAGIC)
+}
+
+/// Returns the labels of the bases a multi-source delta needs, in the order they were encoded.
+///
+/// # Errors
+/// Returns an error if `delta` is not a valid multi-source delta.
+pub fn multi_source_labels(delta: &[u8]) -> Result<Vec<&str>, &'static str> {
+    Ok(parse(delta)?.0.into_iter().map(|base| base.label).collect())
+}
+
+/// A base listed in a multi-source delta; only the decoder checks sizes and checksums.
+#[cfg_attr(not(feature = "decode"), allow(dead_code))]
+struct Base<'a> {
+    label: &'a str,
+    size: usize,
+    checksum: u32,
+}
+
+/// Splits a multi-source delta into its bases and the inner delta.
+fn parse(delta: &[u8]) -> Result<(Vec<Base<'_>>, &[u8]), &'static str> {
+    if !is_multi_source(delta) {
+        return Err("Not a multi-source delta");
+    }
+    if delta.get(MAGIC.len()) != Some(&VERSION) {
+        return Err("Unsupported multi-source delta version");
+    }
+    let mut pos = MAGIC.len() + 1;
+    let count = read_header_varint(delta, &mut pos)?;
+    let mut bases = Vec::new();
+    for _ in 0..count {
+        let len = read_header_varint(delta, &mut pos)?;
+        let label = pos
+            .checked_add(len)
+            .and_then(|end| delta.get(pos..end))
+            .and_then(|label| std::str::from_utf8(label).ok())
+            .ok_or("Invalid multi-source label")?;
+        pos += len;
+        let size = read_header_varint(delta, &mut pos)?;
+        let checksum = delta
+            .get(pos..pos + 4)
+            .ok_or("Truncated multi-source delta")?;
+        pos += 4;
+        bases.push(Base {
+            label,
+            size,
+            checksum: u32::from_le_bytes(checksum.try_into().unwrap()),
+        });
+    }
+    Ok((bases, &delta[pos..]))
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_source() {
+        let app: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let assets: Vec<u8> = (0..20_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        let new = [&app[..10_000], b"patched", &assets[5_000..], &app[10_000..]].concat();
+        let options = EncodeOptions::default();
+        let sources = [("app", &app[..]), ("assets", &assets[..])];
+
+        let patch = encode_multi_source(3, &sources, &new, &options);
+        assert!(is_multi_source(&patch));
+        assert_eq!(decode_multi_source(&sources, &patch).unwrap(), new);
+        let single = encode_with_options(3, &app, &new, &options);
+        assert!(patch.len() < single.len());
+
+        assert_eq!(
+            decode_multi_source(&sources[..1], &patch),
+            Err("Missing base for multi-source delta")
+        );
+        let changed = [("app", &assets[..]), ("assets", &assets[..])];
+        assert_eq!(
+            decode_multi_source(&changed, &patch),
+            Err("Multi-source base mismatch")
+        );
+        assert!(super::super::decode(&app, &patch).is_err());
+        assert!(multi_source_labels(&patch[..6]).is_err());
+    }
+}
@@ -0,0 +1,967 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Low-level copy/insert instruction streams.
+//!
+//! Every delta can be expressed as a list of [`Op`]s that build the new data from left to right,
+//! either by copying a range of the base or by inserting literal bytes. This module exposes that
+//! form so instruction streams can be analyzed (e.g. for visual diffs) or rewritten before they
+//! are serialized with [`encode_from_ops`]. [`optimize`] rewrites a stream into the canonical
+//! form used by [`EncodeOptions::optimize`].
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::delta::{self, EncodeOptions};
+//! use xpatch::delta::ops::{self, Op};
+//!
+//! let base = b"The quick brown fox jumps over the lazy dog";
+//! let new = b"The quick red fox jumps over the lazy dog!";
+//!
+//! let ops = ops::encode_ops(base, new);
+//! let inserted: usize = ops
+//!     .iter()
+//!     .filter_map(|op| match op {
+//!         Op::Insert(bytes) => Some(bytes.len()),
+//!         Op::Copy { .. } => None,
+//!     })
+//!     .sum();
+//! assert!(inserted < new.len());
+//! assert_eq!(ops::apply_ops(base, &ops)?, new);
+//!
+//! let patch = ops::encode_from_ops(0, base, &ops, &EncodeOptions::default())?;
+//! assert_eq!(delta::decode(base, &patch)?, new);
+//! # Ok::<(), &'static str>(())
+//! ```
+
+#[cfg(all(feature = "encode", feature = "decode"))]
+use super::decode;
+use super::read_header_varint;
+#[cfg(feature = "decode")]
+use super::{Algorithm, HoleFiller, parse_header};
+#[cfg(feature = "encode")]
+use super::{EncodeOptions, assemble_delta, finish_gdelta, write_gdelta_unit};
+#[cfg(feature = "encode")]
+use crate::varint::encode_varint;
+#[cfg(feature = "decode")]
+use std::ops::Range;
+
+/// A single instruction of a delta.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Copy `len` bytes of the base, starting at `offset`
+    Copy {
+        /// Start of the range in the base
+        offset: usize,
+        /// Number of bytes to copy
+        len: usize,
+    },
+    /// Insert literal bytes
+    Insert(Vec<u8>),
+}
+
+impl Op {
+    /// Returns the number of output bytes the instruction produces.
+    pub fn len(&self) -> usize {
+        match self {
+            Op::Copy { len, .. } => *len,
+            Op::Insert(bytes) => bytes.len(),
+        }
+    }
+
+    /// Returns `true` if the instruction produces no output.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Computes the instructions that turn `base_data` into `new_data`.
+#[cfg(feature = "encode")]
+pub fn encode_ops(base_data: &[u8], new_data: &[u8]) -> Vec<Op> {
+    let payload = gdelta::encode(new_data, base_data).expect("GDelta failed");
+    parse_gdelta(&payload).expect("GDelta produced an invalid payload")
+}
+
+/// Extracts the instructions of an existing delta.
+///
+/// GDelta payloads are read directly. Deltas using one of the specialized algorithms are
+/// decoded and diffed again, so their instructions describe the same change but not
+/// necessarily the original encoding.
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn decode_ops(base_data: &[u8], delta: &[u8]) -> Result<Vec<Op>, &'static str> {
+    #[cfg(feature = "fec")]
+    if crate::fec::is_protected(delta) {
+        let (repaired, _) = crate::fec::repair(delta)?;
+        return decode_ops(base_data, &repaired);
+    }
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let ops = match header.algorithm {
+        _ if header.stored => return Ok(encode_ops(base_data, &decode(base_data, delta)?)),
+        Algorithm::GDelta => parse_gdelta(&delta[header.size..])?,
+        Algorithm::GDeltaZstd => {
+            let payload = crate::delta::zstd_decompress(&delta[header.size..])
+                .map_err(|_| "Error decompressing zstd data")?;
+            parse_gdelta(&payload)?
+        }
+        _ => return Ok(encode_ops(base_data, &decode(base_data, delta)?)),
+    };
+    fill_hole_ops(ops, &header.holes)
+}
+
+/// The instructions of a delta as read by [`trace`].
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    /// Algorithm of the delta, [`Algorithm::GDelta`] or [`Algorithm::GDeltaZstd`]
+    pub algorithm: Algorithm,
+    /// Instructions read before the first malformed one, in order
+    pub ops: Vec<TracedOp>,
+    /// Literal bytes left over after the last instruction
+    pub unused_literals: usize,
+    /// Position and description of the first malformed instruction
+    pub error: Option<(usize, &'static str)>,
+}
+
+/// An instruction read by [`trace`], with the position of its bytes.
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedOp {
+    /// The instruction
+    pub op: Op,
+    /// Position of the instruction
+    pub position: usize,
+    /// Position of the literal bytes of an insert
+    pub literals: Option<usize>,
+}
+
+/// Reads the instructions of a delta one by one, for diagnosing damaged deltas.
+///
+/// Unlike [`decode_ops`], a malformed instruction doesn't fail the call: the instructions read
+/// before it are returned together with the position where reading stopped, so a truncated
+/// delta shows exactly how far it got. Positions are byte offsets into the delta, or into the
+/// decompressed payload for GDeltaZstd deltas.
+///
+/// Only GDelta deltas have an instruction stream; other algorithms, encrypted deltas and deltas
+/// whose header or zstd frame can't be read return an error.
+#[cfg(feature = "decode")]
+pub fn trace(delta: &[u8]) -> Result<Trace, &'static str> {
+    let header = parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is encrypted");
+    }
+    let decompressed;
+    let (payload, start) = match header.algorithm {
+        Algorithm::GDelta => (&delta[header.size..], header.size),
+        Algorithm::GDeltaZstd => {
+            decompressed = crate::delta::zstd_decompress(&delta[header.size..])
+                .map_err(|_| "Error decompressing zstd data")?;
+            (&decompressed[..], 0)
+        }
+        _ => return Err("Only GDelta deltas have an instruction stream"),
+    };
+
+    let mut ops = Vec::new();
+    let walked = walk_gdelta(payload, |position, literals, op| {
+        ops.push(TracedOp {
+            op,
+            position: start + position,
+            literals: literals.map(|literals| start + literals),
+        })
+    });
+    let (unused_literals, error) = match walked {
+        Ok(unused) => (unused, None),
+        Err((position, message)) => (0, Some((start + position, message))),
+    };
+    Ok(Trace {
+        algorithm: header.algorithm,
+        ops,
+        unused_literals,
+        error,
+    })
+}
+
+/// Applies instructions to `base_data`.
+pub fn apply_ops(base_data: &[u8], ops: &[Op]) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::with_capacity(ops.iter().map(Op::len).sum());
+    for op in ops {
+        match op {
+            Op::Copy { offset, len } => {
+                let range = offset
+                    .checked_add(*len)
+                    .and_then(|end| base_data.get(*offset..end))
+                    .ok_or("Copy out of bounds")?;
+                output.extend_from_slice(range);
+            }
+            Op::Insert(bytes) => output.extend_from_slice(bytes),
+        }
+    }
+    Ok(output)
+}
+
+/// Serializes instructions as a GDelta delta that [`decode`] applies to `base_data`.
+///
+/// `base_data` is needed to check that the copies are in bounds, to compute the checksums with
+/// `options.checksum`, to [`optimize`] the instructions with `options.optimize` and to
+/// [`limit_copy_distance`] with `options.max_copy_distance`. Empty instructions are skipped.
+#[cfg(feature = "encode")]
+pub fn encode_from_ops(
+    tag: usize,
+    base_data: &[u8],
+    ops: &[Op],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, &'static str> {
+    let options = &*options.for_target_format();
+    let output = apply_ops(base_data, ops)?;
+    let mut rewritten;
+    let mut ops = ops;
+    if options.optimize {
+        rewritten = optimize(base_data, ops);
+        ops = &rewritten;
+    }
+    if options.max_copy_distance > 0 {
+        rewritten = limit_copy_distance(base_data, ops, options.max_copy_distance);
+        ops = &rewritten;
+    }
+
+    let (instructions, literals) = write_gdelta(ops);
+    let (algorithm, payload) = finish_gdelta(instructions, literals, options);
+    let checksums = options
+        .checksum
+        .then(|| (crc32fast::hash(base_data), crc32fast::hash(&output)));
+    Ok(assemble_delta(
+        tag,
+        algorithm,
+        &payload,
+        checksums,
+        options.recorded_copy_distance(),
+    ))
+}
+
+/// Rewrites instructions into a canonical form that produces the same output.
+///
+/// Copies too short to be cheaper than their bytes become inserts, bytes at the edges of an
+/// insert that continue a neighbouring copy in the base are folded into that copy, and adjacent
+/// inserts and contiguous copies are merged. Empty instructions are dropped. The serialized
+/// result is never larger, and since the rewrite is deterministic, the same instructions always
+/// give the same bytes. Out of bounds copies are kept as they are.
+#[cfg(feature = "encode")]
+pub fn optimize(base_data: &[u8], ops: &[Op]) -> Vec<Op> {
+    let mut merged: Vec<Op> = Vec::with_capacity(ops.len());
+    for op in ops {
+        let op = match op {
+            Op::Copy { offset, len } if *len < copy_cost(*offset, *len) => offset
+                .checked_add(*len)
+                .and_then(|end| base_data.get(*offset..end))
+                .map_or(op.clone(), |bytes| Op::Insert(bytes.to_vec())),
+            op => op.clone(),
+        };
+        push_merged(&mut merged, op);
+    }
+
+    for i in 1..merged.len() {
+        let (before, after) = merged.split_at_mut(i);
+        match (&mut before[i - 1], &mut after[0]) {
+            (Op::Copy { offset, len }, Op::Insert(bytes)) => {
+                let following = base_data.get(*offset + *len..).unwrap_or_default();
+                let n = bytes
+                    .iter()
+                    .zip(following)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                *len += n;
+                bytes.drain(..n);
+            }
+            (Op::Insert(bytes), Op::Copy { offset, len }) => {
+                let preceding = base_data.get(..*offset).unwrap_or_default();
+                let n = bytes
+                    .iter()
+                    .rev()
+                    .zip(preceding.iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                *offset -= n;
+                *len += n;
+                bytes.truncate(bytes.len() - n);
+            }
+            _ => {}
+        }
+    }
+
+    let mut optimized = Vec::with_capacity(merged.len());
+    for op in merged {
+        push_merged(&mut optimized, op);
+    }
+    optimized
+}
+
+/// Rewrites instructions so that every copy starts at most `max_distance` bytes from where the
+/// previous copy ended in the base, or from the start of the base for the first copy.
+///
+/// Runs of output that lost a copy to the limit are matched again against the part of the base
+/// that is within reach, and what still can't be copied is inserted. The output is unchanged.
+/// Out of bounds copies are kept as they are. See [`EncodeOptions::max_copy_distance`].
+#[cfg(feature = "encode")]
+pub fn limit_copy_distance(base_data: &[u8], ops: &[Op], max_distance: usize) -> Vec<Op> {
+    let mut limited = Vec::with_capacity(ops.len());
+    // Base position after the last copy kept
+    let mut cursor = 0;
+    // Output bytes waiting to be matched again, and whether a copy was dropped among them
+    let mut pending = Vec::new();
+    let mut dropped = false;
+
+    for op in ops {
+        match op {
+            Op::Copy { offset, len } => {
+                let Some(bytes) = offset
+                    .checked_add(*len)
+                    .and_then(|end| base_data.get(*offset..end))
+                else {
+                    flush_limited(
+                        base_data,
+                        &mut limited,
+                        &mut cursor,
+                        &mut pending,
+                        dropped,
+                        max_distance,
+                    );
+                    dropped = false;
+                    push_merged(&mut limited, op.clone());
+                    continue;
+                };
+                if offset.abs_diff(cursor) <= max_distance {
+                    flush_limited(
+                        base_data,
+                        &mut limited,
+                        &mut cursor,
+                        &mut pending,
+                        dropped,
+                        max_distance,
+                    );
+                    dropped = false;
+                }
+                // Matching the pending output again may have moved the cursor out of reach
+                if pending.is_empty() && offset.abs_diff(cursor) <= max_distance {
+                    push_merged(&mut limited, op.clone());
+                    cursor = offset + len;
+                } else {
+                    pending.extend_from_slice(bytes);
+                    dropped = true;
+                }
+            }
+            Op::Insert(bytes) => pending.extend_from_slice(bytes),
+        }
+    }
+    flush_limited(
+        base_data,
+        &mut limited,
+        &mut cursor,
+        &mut pending,
+        dropped,
+        max_distance,
+    );
+    limited
+}
+
+/// Emits `pending` output for [`limit_copy_distance`], matched against the base within reach
+/// of `cursor` if a copy was dropped from it. Long runs are matched a chunk at a time, each
+/// against the window around where the previous chunk left the cursor.
+#[cfg(feature = "encode")]
+fn flush_limited(
+    base_data: &[u8],
+    limited: &mut Vec<Op>,
+    cursor: &mut usize,
+    pending: &mut Vec<u8>,
+    dropped: bool,
+    max_distance: usize,
+) {
+    if !dropped {
+        push_merged(limited, Op::Insert(std::mem::take(pending)));
+        return;
+    }
+
+    // Chunks no longer than the limit can be copied whole from a window that only holds
+    // starts within reach
+    let chunk_size = max_distance.clamp(16, 1 << 20);
+    for chunk in pending.chunks(chunk_size) {
+        let start = cursor.saturating_sub(max_distance).min(base_data.len());
+        let end = cursor
+            .saturating_add(max_distance.max(chunk.len()))
+            .min(base_data.len());
+        if start >= end {
+            push_merged(limited, Op::Insert(chunk.to_vec()));
+            continue;
+        }
+        for op in encode_ops(&base_data[start..end], chunk) {
+            match op {
+                Op::Copy { offset, len } if (start + offset).abs_diff(*cursor) <= max_distance => {
+                    push_merged(
+                        limited,
+                        Op::Copy {
+                            offset: start + offset,
+                            len,
+                        },
+                    );
+                    *cursor = start + offset + len;
+                }
+                Op::Copy { offset, len } => push_merged(
+                    limited,
+                    Op::Insert(base_data[start + offset..start + offset + len].to_vec()),
+                ),
+                op => push_merged(limited, op),
+            }
+        }
+    }
+    pending.clear();
+}
+
+/// Applies [`limit_copy_distance`] to a GDelta payload.
+#[cfg(feature = "encode")]
+pub(super) fn limit_copy_distance_gdelta(
+    base_data: &[u8],
+    payload: &[u8],
+    max_distance: usize,
+) -> Result<Vec<u8>, &'static str> {
+    let ops = limit_copy_distance(base_data, &parse_gdelta(payload)?, max_distance);
+    let (instructions, literals) = write_gdelta(&ops);
+    let mut payload = encode_varint(instructions.len());
+    payload.extend(instructions);
+    payload.extend(literals);
+    Ok(payload)
+}
+
+/// Optimizes a GDelta payload, see [`optimize`].
+#[cfg(feature = "encode")]
+pub(super) fn optimize_gdelta(base_data: &[u8], payload: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let ops = optimize(base_data, &parse_gdelta(payload)?);
+    let (instructions, literals) = write_gdelta(&ops);
+    let mut payload = encode_varint(instructions.len());
+    payload.extend(instructions);
+    payload.extend(literals);
+    Ok(payload)
+}
+
+/// Appends `op`, merging it into the previous instruction where possible.
+#[cfg(feature = "encode")]
+pub(super) fn push_merged(ops: &mut Vec<Op>, op: Op) {
+    if op.is_empty() {
+        return;
+    }
+    match (ops.last_mut(), op) {
+        (Some(Op::Insert(previous)), Op::Insert(bytes)) => previous.extend(bytes),
+        (
+            Some(Op::Copy { offset, len }),
+            Op::Copy {
+                offset: next,
+                len: more,
+            },
+        ) if *offset + *len == next => {
+            *len += more;
+        }
+        (_, op) => ops.push(op),
+    }
+}
+
+/// Size of a copy instruction in a GDelta payload.
+#[cfg(feature = "encode")]
+fn copy_cost(offset: usize, len: usize) -> usize {
+    let mut unit = Vec::new();
+    write_gdelta_unit(&mut unit, true, len, offset);
+    unit.len()
+}
+
+/// Serializes instructions into GDelta instruction and literal sections.
+#[cfg(feature = "encode")]
+pub(super) fn write_gdelta(ops: &[Op]) -> (Vec<u8>, Vec<u8>) {
+    let mut instructions = Vec::new();
+    let mut literals = Vec::new();
+    for op in ops.iter().filter(|op| !op.is_empty()) {
+        match op {
+            Op::Copy { offset, len } => write_gdelta_unit(&mut instructions, true, *len, *offset),
+            Op::Insert(bytes) => {
+                write_gdelta_unit(&mut instructions, false, bytes.len(), 0);
+                literals.extend_from_slice(bytes);
+            }
+        }
+    }
+    (instructions, literals)
+}
+
+/// Splits instructions decoding to the data of a sparse delta at its holes and inserts the
+/// holes' zeros, giving instructions for the whole output.
+#[cfg(feature = "decode")]
+pub(super) fn fill_hole_ops(ops: Vec<Op>, holes: &[Range<usize>]) -> Result<Vec<Op>, &'static str> {
+    if holes.is_empty() {
+        return Ok(ops);
+    }
+    let mut filled = Vec::with_capacity(ops.len() + 2 * holes.len());
+    let mut filler = HoleFiller::new(holes);
+    for op in ops {
+        let mut done = 0;
+        while done < op.len() {
+            let (zeros, len) = filler.next(op.len() - done);
+            if zeros > 0 {
+                filled.push(Op::Insert(vec![0; zeros]));
+            }
+            filled.push(match &op {
+                Op::Copy { offset, .. } => Op::Copy {
+                    offset: offset + done,
+                    len,
+                },
+                Op::Insert(bytes) => Op::Insert(bytes[done..done + len].to_vec()),
+            });
+            done += len;
+        }
+    }
+    let zeros = filler.finish()?;
+    if zeros > 0 {
+        filled.push(Op::Insert(vec![0; zeros]));
+    }
+    Ok(filled)
+}
+
+/// Parses a GDelta payload: `varint(instructions length) | instructions | literals`.
+pub(super) fn parse_gdelta(payload: &[u8]) -> Result<Vec<Op>, &'static str> {
+    let mut ops = Vec::new();
+    walk_gdelta(payload, |_, _, op| ops.push(op)).map_err(|_| "Invalid GDelta payload")?;
+    Ok(ops)
+}
+
+/// Reads a GDelta payload instruction by instruction, passing each one to `visit` with its
+/// position and, for inserts, the position of its literals.
+///
+/// Returns the number of literal bytes no instruction used, or the position of the first
+/// malformed instruction and what is wrong with it.
+fn walk_gdelta(
+    payload: &[u8],
+    mut visit: impl FnMut(usize, Option<usize>, Op),
+) -> Result<usize, (usize, &'static str)> {
+    let mut instructions = Instructions::new(payload)?;
+    for instruction in &mut instructions {
+        let instruction = instruction?;
+        let op = match instruction.op {
+            RawOp::Copy { offset, len } => Op::Copy { offset, len },
+            RawOp::Insert(bytes) => Op::Insert(bytes.to_vec()),
+        };
+        visit(instruction.position, instruction.literals, op);
+    }
+    Ok(instructions.unused_literals())
+}
+
+/// An instruction of a GDelta payload whose inserted bytes are borrowed from the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RawOp<'a> {
+    Copy { offset: usize, len: usize },
+    Insert(&'a [u8]),
+}
+
+/// An instruction read by [`Instructions`], with the position of its bytes in the payload.
+pub(super) struct RawInstruction<'a> {
+    pub(super) op: RawOp<'a>,
+    pub(super) position: usize,
+    pub(super) literals: Option<usize>,
+}
+
+/// Iterates over the instructions of a GDelta payload:
+/// `varint(instructions length) | instructions | literals`.
+///
+/// Nothing is allocated, so large payloads can be applied in constant memory. After the first
+/// error, iteration ends.
+pub(super) struct Instructions<'a> {
+    payload: &'a [u8],
+    pos: usize,
+    instructions_end: usize,
+    declared_end: usize,
+    literals: usize,
+    done: bool,
+}
+
+impl<'a> Instructions<'a> {
+    pub(super) fn new(payload: &'a [u8]) -> Result<Self, (usize, &'static str)> {
+        let mut pos = 0;
+        let instructions_len = read_header_varint(payload, &mut pos)
+            .map_err(|_| (0, "Incomplete instruction section length"))?;
+        let declared_end = pos
+            .checked_add(instructions_len)
+            .ok_or((0, "Instruction section length overflows"))?;
+        // Read what is there of a truncated instruction section before reporting it
+        let instructions_end = declared_end.min(payload.len());
+        Ok(Self {
+            payload,
+            pos,
+            instructions_end,
+            declared_end,
+            literals: instructions_end,
+            done: false,
+        })
+    }
+
+    /// Returns the number of literal bytes no instruction read so far used.
+    pub(super) fn unused_literals(&self) -> usize {
+        self.payload.len() - self.literals
+    }
+
+    fn read(&mut self) -> Result<RawInstruction<'a>, (usize, &'static str)> {
+        let instructions = &self.payload[..self.instructions_end];
+        let start = self.pos;
+        let unit = instructions[self.pos];
+        self.pos += 1;
+        let mut len = (unit & 0x3F) as usize;
+        if unit & 0x40 != 0 {
+            let more = read_header_varint(instructions, &mut self.pos).map_err(|_| {
+                (
+                    start,
+                    "Instruction length runs past the instruction section",
+                )
+            })?;
+            len |= more
+                .checked_shl(6)
+                .ok_or((start, "Instruction length overflows"))?;
+        }
+        if unit & 0x80 != 0 {
+            let offset = read_header_varint(instructions, &mut self.pos)
+                .map_err(|_| (start, "Copy offset runs past the instruction section"))?;
+            return Ok(RawInstruction {
+                op: RawOp::Copy { offset, len },
+                position: start,
+                literals: None,
+            });
+        }
+        let bytes = self
+            .payload
+            .get(self.literals..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or((start, "Insert runs past the end of the literals"))?;
+        let literals = self.literals;
+        self.literals += len;
+        Ok(RawInstruction {
+            op: RawOp::Insert(bytes),
+            position: start,
+            literals: Some(literals),
+        })
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<RawInstruction<'a>, (usize, &'static str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.pos < self.instructions_end {
+            let instruction = self.read();
+            self.done = instruction.is_err();
+            return Some(instruction);
+        }
+        self.done = true;
+        (self.declared_end > self.payload.len()).then_some(Err((
+            self.payload.len(),
+            "Instruction section is truncated",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{encode, get_tag};
+
+    fn sample() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = base.clone();
+        new.splice(1000..1200, b"replaced section".iter().copied());
+        new.splice(3000..3000, vec![0xAB; 300]);
+        new.truncate(4500);
+        (base, new)
+    }
+
+    #[test]
+    fn test_encode_apply_roundtrip() {
+        let (base, new) = sample();
+        let ops = encode_ops(&base, &new);
+        assert!(ops.iter().any(|op| matches!(op, Op::Copy { .. })));
+        assert!(ops.iter().any(|op| matches!(op, Op::Insert(_))));
+        assert_eq!(ops.iter().map(Op::len).sum::<usize>(), new.len());
+        assert_eq!(apply_ops(&base, &ops).unwrap(), new);
+    }
+
+    #[test]
+    fn test_decode_ops_matches_delta() {
+        let (base, new) = sample();
+        for enable_zstd in [false, true] {
+            let delta = encode(0, &base, &new, enable_zstd);
+            let ops = decode_ops(&base, &delta).unwrap();
+            assert_eq!(apply_ops(&base, &ops).unwrap(), new);
+        }
+
+        // Specialized algorithms are translated too
+        let delta = encode(0, b"hello", b"hello world", false);
+        let ops = decode_ops(b"hello", &delta).unwrap();
+        assert_eq!(apply_ops(b"hello", &ops).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_trace() {
+        let (base, new) = sample();
+        let delta = encode(0, &base, &new, false);
+        let full = trace(&delta).unwrap();
+        assert_eq!(full.algorithm, Algorithm::GDelta);
+        assert_eq!(full.error, None);
+        assert_eq!(full.unused_literals, 0);
+        let ops: Vec<Op> = full.ops.iter().map(|traced| traced.op.clone()).collect();
+        assert_eq!(ops, decode_ops(&base, &delta).unwrap());
+        for traced in &full.ops {
+            if let (Op::Insert(bytes), Some(literals)) = (&traced.op, traced.literals) {
+                assert_eq!(&delta[literals..literals + bytes.len()], &bytes[..]);
+            }
+        }
+
+        // A truncated delta keeps the instructions before the damage
+        for len in [delta.len() - 1, delta.len() / 2, 3] {
+            let truncated = trace(&delta[..len]).unwrap();
+            let (position, _) = truncated.error.unwrap();
+            assert!(position <= len);
+            assert!(truncated.ops.len() < full.ops.len());
+            assert_eq!(truncated.ops, full.ops[..truncated.ops.len()]);
+        }
+
+        assert!(trace(&encode(0, b"hello", b"hello world", false)).is_err());
+    }
+
+    #[test]
+    fn test_encode_from_ops() {
+        let (base, new) = sample();
+        let mut ops = encode_ops(&base, &new);
+        ops.push(Op::Insert(b"!".to_vec()));
+        ops.insert(0, Op::Copy { offset: 0, len: 0 });
+
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_from_ops(7, &base, &ops, &options).unwrap();
+        let mut expected = new.clone();
+        expected.push(b'!');
+        assert_eq!(decode(&base, &delta).unwrap(), expected);
+        assert_eq!(get_tag(&delta).unwrap(), 7);
+        assert_eq!(decode_ops(&base, &delta).unwrap(), ops[1..]);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let base = b"0123456789abcdefghijklmnopqrstuvwxyz";
+        let ops = [
+            Op::Copy { offset: 0, len: 10 },
+            Op::Copy { offset: 10, len: 0 },
+            Op::Insert(b"abcXY".to_vec()),
+            Op::Insert(b"Z".to_vec()),
+            // Costs more than its single byte
+            Op::Copy { offset: 30, len: 1 },
+            Op::Insert(b"vw".to_vec()),
+            Op::Copy { offset: 33, len: 3 },
+        ];
+        let optimized = optimize(base, &ops);
+        assert_eq!(
+            optimized,
+            [
+                Op::Copy { offset: 0, len: 13 },
+                Op::Insert(b"XYZ".to_vec()),
+                Op::Copy { offset: 30, len: 6 },
+            ]
+        );
+        assert_eq!(apply_ops(base, &optimized), apply_ops(base, &ops));
+        assert_eq!(optimize(base, &optimized), optimized);
+    }
+
+    #[test]
+    fn test_optimized_encoding() {
+        let (base, new) = sample();
+        let options = EncodeOptions {
+            optimize: true,
+            ..EncodeOptions::default()
+        };
+        for enable_zstd in [false, true] {
+            let options = EncodeOptions {
+                enable_zstd,
+                ..options.clone()
+            };
+            let plain = encode(0, &base, &new, enable_zstd);
+            let optimized = crate::delta::encode_with_options(0, &base, &new, &options);
+            assert_eq!(decode(&base, &optimized).unwrap(), new);
+            if !enable_zstd {
+                assert!(optimized.len() <= plain.len());
+            }
+        }
+
+        // Differently split instructions give the same delta
+        let ops = encode_ops(&base, &new);
+        let mut split = Vec::new();
+        for op in &ops {
+            match op {
+                Op::Copy { offset, len } if *len > 1 => {
+                    split.push(Op::Copy {
+                        offset: *offset,
+                        len: 1,
+                    });
+                    split.push(Op::Copy {
+                        offset: offset + 1,
+                        len: len - 1,
+                    });
+                }
+                op => split.push(op.clone()),
+            }
+        }
+        assert_eq!(
+            encode_from_ops(0, &base, &split, &options).unwrap(),
+            encode_from_ops(0, &base, &ops, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_limit_copy_distance() {
+        let base: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        // Moved blocks, a far jump back and a small local edit
+        let mut new = [&base[40_000..50_000], &base[..40_000], &base[50_000..]].concat();
+        new[20_000..20_008].copy_from_slice(b"modified");
+
+        let within = |ops: &[Op], max: usize| {
+            let mut cursor = 0;
+            ops.iter().all(|op| match op {
+                Op::Copy { offset, len } => {
+                    let ok = offset.abs_diff(cursor) <= max;
+                    cursor = offset + len;
+                    ok
+                }
+                Op::Insert(_) => true,
+            })
+        };
+        assert!(!within(&encode_ops(&base, &new), 4096));
+
+        for max in [1, 4096, 1 << 20] {
+            let options = EncodeOptions {
+                enable_zstd: false,
+                checksum: true,
+                ..EncodeOptions::default().max_copy_distance(max)
+            };
+            let delta = crate::delta::encode_with_options(9, &base, &new, &options);
+            assert_eq!(decode(&base, &delta).unwrap(), new);
+            let info = crate::delta::inspect(&delta).unwrap();
+            assert!(!info.stored);
+            assert!(within(&decode_ops(&base, &delta).unwrap(), max));
+            assert!(max == 1 || delta.len() < new.len() / 2);
+
+            assert_eq!(info.max_copy_distance, Some(max));
+            assert_eq!(info.tag, 9);
+            assert!(info.output_checksum.is_some());
+
+            let provenance = crate::delta::Provenance::new(b"a".to_vec(), b"b".to_vec());
+            let annotated = crate::delta::annotate(&delta, &provenance).unwrap();
+            let info = crate::delta::inspect(&annotated).unwrap();
+            assert_eq!(info.max_copy_distance, Some(max));
+            assert_eq!(info.provenance, Some(provenance));
+            assert_eq!(decode(&base, &annotated).unwrap(), new);
+
+            let ops = encode_ops(&base, &new);
+            let delta = encode_from_ops(0, &base, &ops, &options).unwrap();
+            assert!(within(&decode_ops(&base, &delta).unwrap(), max));
+        }
+
+        // Only data that moved far pays for the limit
+        let limited = crate::delta::encode_with_options(
+            0,
+            &base,
+            &new,
+            &EncodeOptions::default().max_copy_distance(4096),
+        );
+        assert!(limited.len() < new.len() / 4);
+        assert_eq!(
+            crate::delta::inspect(&encode(0, &base, &new, true))
+                .unwrap()
+                .max_copy_distance,
+            None
+        );
+    }
+
+    #[test]
+    fn test_limit_copy_distance_random() {
+        let mut state = 1u64;
+        let mut next = |bound: usize| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as usize % bound
+        };
+        let within = |ops: &[Op], max: usize| {
+            let mut cursor = 0;
+            ops.iter().all(|op| match op {
+                Op::Copy { offset, len } => {
+                    let ok = offset.abs_diff(cursor) <= max;
+                    cursor = offset + len;
+                    ok
+                }
+                Op::Insert(_) => true,
+            })
+        };
+
+        for _ in 0..3000 {
+            let base: Vec<u8> = (0..1 + next(8000)).map(|_| b'a' + next(4) as u8).collect();
+            let mut new = Vec::new();
+            for _ in 0..1 + next(8) {
+                let start = next(base.len());
+                match next(3) {
+                    0 => new.extend((0..next(200)).map(|_| b'a' + next(8) as u8)),
+                    _ => new.extend_from_slice(&base[start..(start + next(3000)).min(base.len())]),
+                }
+            }
+            let max = [16, 100, 500, 2000][next(4)];
+
+            let limited = limit_copy_distance(&base, &encode_ops(&base, &new), max);
+            assert_eq!(apply_ops(&base, &limited).unwrap(), new);
+            assert!(within(&limited, max), "max {max}");
+
+            let options = EncodeOptions::default().max_copy_distance(max);
+            let delta = crate::delta::encode_with_options(0, &base, &new, &options);
+            let info = crate::delta::inspect(&delta).unwrap();
+            match info.algorithm {
+                Algorithm::GDelta | Algorithm::GDeltaZstd => {
+                    assert!(
+                        within(&decode_ops(&base, &delta).unwrap(), max),
+                        "max {max}"
+                    )
+                }
+                // Skipping the removed bytes jumps as far as their length
+                Algorithm::Remove => assert!(base.len() - new.len() <= max),
+                _ => {}
+            }
+            assert!(crate::delta::dry_run(&base, &delta).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_out_of_bounds_copy() {
+        let ops = [Op::Copy { offset: 3, len: 5 }];
+        assert_eq!(apply_ops(b"short", &ops), Err("Copy out of bounds"));
+        assert!(encode_from_ops(0, b"short", &ops, &EncodeOptions::default()).is_err());
+        assert!(parse_gdelta(&[5, 0x80]).is_err());
+    }
+}
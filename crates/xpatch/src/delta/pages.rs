@@ -0,0 +1,236 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Page mode, see [`EncodeOptions::page_size`].
+
+use std::collections::HashMap;
+
+use super::ops::{Op, encode_ops, optimize, push_merged, write_gdelta};
+use super::{Algorithm, EncodeOptions, finish_gdelta};
+
+/// Encodes `new_data` page by page as a GDelta payload.
+///
+/// GDelta over the whole inputs finds data that moved between pages; its copies are cut at the
+/// page boundaries of the new data. A page that changed is also diffed on its own against the
+/// base page at the same offset and against the base page it copies most from, which finds
+/// the small edits that a matcher over the whole file skips. Each page keeps whichever
+/// instructions are smallest, identical pages are copied whole, and copies that continue each
+/// other across pages are merged again.
+pub(super) fn encode_pages(
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> (Algorithm, Vec<u8>) {
+    let page_size = options.page_size;
+    let mut base_pages: HashMap<&[u8], usize> = HashMap::new();
+    for (index, page) in base_data.chunks_exact(page_size).enumerate() {
+        base_pages.entry(page).or_insert(index * page_size);
+    }
+    let mut matches = split_pages(encode_ops(base_data, new_data), page_size).into_iter();
+
+    let mut ops = Vec::new();
+    for (index, page) in new_data.chunks(page_size).enumerate() {
+        let offset = index * page_size;
+        let matched = matches.next().unwrap_or_default();
+        let source = if base_data.get(offset..offset + page.len()) == Some(page) {
+            Some(offset)
+        } else {
+            base_pages.get(page).copied()
+        };
+        if let Some(source) = source {
+            push_merged(
+                &mut ops,
+                Op::Copy {
+                    offset: source,
+                    len: page.len(),
+                },
+            );
+            continue;
+        }
+
+        let mut best = optimize(base_data, &matched);
+        let mut best_size = size(&best);
+        let mut candidates = vec![offset];
+        if let Some(source) = main_source(&best, page_size)
+            && source != offset
+        {
+            candidates.push(source);
+        }
+        for start in candidates {
+            let Some(base_page) = base_data.get(start..(start + page_size).min(base_data.len()))
+            else {
+                continue;
+            };
+            if base_page.is_empty() {
+                continue;
+            }
+            let local: Vec<Op> = encode_ops(base_page, page)
+                .into_iter()
+                .map(|op| match op {
+                    Op::Copy { offset, len } => Op::Copy {
+                        offset: offset + start,
+                        len,
+                    },
+                    op => op,
+                })
+                .collect();
+            let local = optimize(base_data, &local);
+            let local_size = size(&local);
+            if local_size < best_size {
+                best = local;
+                best_size = local_size;
+            }
+        }
+        for op in best {
+            push_merged(&mut ops, op);
+        }
+    }
+
+    let (instructions, literals) = write_gdelta(&ops);
+    finish_gdelta(instructions, literals, options)
+}
+
+/// Cuts instructions at every multiple of `page_size` in the output, one list per page.
+fn split_pages(ops: Vec<Op>, page_size: usize) -> Vec<Vec<Op>> {
+    let mut pages = vec![Vec::new()];
+    let mut room = page_size;
+    for mut op in ops {
+        while !op.is_empty() {
+            if room == 0 {
+                pages.push(Vec::new());
+                room = page_size;
+            }
+            let len = op.len().min(room);
+            let (head, rest) = match op {
+                Op::Copy { offset, len: total } => (
+                    Op::Copy { offset, len },
+                    Op::Copy {
+                        offset: offset + len,
+                        len: total - len,
+                    },
+                ),
+                Op::Insert(mut bytes) => {
+                    let rest = bytes.split_off(len);
+                    (Op::Insert(bytes), Op::Insert(rest))
+                }
+            };
+            pages.last_mut().unwrap().push(head);
+            room -= len;
+            op = rest;
+        }
+    }
+    pages
+}
+
+/// The aligned base page that a page's copies take the most bytes from.
+fn main_source(ops: &[Op], page_size: usize) -> Option<usize> {
+    let mut copied: HashMap<usize, usize> = HashMap::new();
+    for op in ops {
+        if let Op::Copy { offset, len } = op {
+            *copied.entry(offset / page_size * page_size).or_default() += len;
+        }
+    }
+    copied
+        .into_iter()
+        .max_by_key(|&(page, len)| (len, page))
+        .map(|(page, _)| page)
+}
+
+/// Serialized size of instructions, before compression.
+fn size(ops: &[Op]) -> usize {
+    let (instructions, literals) = write_gdelta(ops);
+    instructions.len() + literals.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta;
+
+    const PAGE: usize = 4096;
+
+    /// Pages of pseudo-random records, like the leaves of a B-tree.
+    fn database(pages: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..pages * PAGE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b'a' + (state % 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_page_mode_roundtrip() {
+        let base = database(64);
+        let mut new = base.clone();
+        // Updated records, a page moved to the end and a shorter last page
+        for page in (0..60).step_by(3) {
+            new[page * PAGE + 100..page * PAGE + 108].copy_from_slice(b"updated!");
+        }
+        new.extend_from_slice(&base[5 * PAGE..6 * PAGE]);
+        new.extend_from_slice(&base[..1000]);
+
+        let options = EncodeOptions::default().page_size(PAGE);
+        let delta = delta::encode_with_options(3, &base, &new, &options);
+        assert_eq!(delta::decode(&base, &delta).unwrap(), new);
+        assert_eq!(delta::get_tag(&delta).unwrap(), 3);
+
+        let plain = delta::encode(3, &base, &new, true);
+        assert!(
+            delta.len() < plain.len(),
+            "{} vs {}",
+            delta.len(),
+            plain.len()
+        );
+    }
+
+    #[test]
+    fn test_page_mode_small_inputs() {
+        let options = EncodeOptions::default().page_size(PAGE);
+        for (base, new) in [
+            (&b""[..], &b"new"[..]),
+            (b"base", b""),
+            (b"hello world", b"hello, world"),
+        ] {
+            let delta = delta::encode_with_options(0, base, new, &options);
+            assert_eq!(delta::decode(base, &delta).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_split_pages() {
+        let ops = vec![
+            Op::Insert(b"abcdef".to_vec()),
+            Op::Copy { offset: 10, len: 7 },
+        ];
+        assert_eq!(
+            split_pages(ops, 4),
+            [
+                vec![Op::Insert(b"abcd".to_vec())],
+                vec![Op::Insert(b"ef".to_vec()), Op::Copy { offset: 10, len: 2 }],
+                vec![Op::Copy { offset: 12, len: 4 }],
+                vec![Op::Copy { offset: 16, len: 1 }],
+            ]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Moving a delta onto a slightly different base.
+
+use super::ops::{Op, decode_ops, encode_from_ops, encode_ops, push_merged};
+use super::{Algorithm, EncodeOptions, find_common_prefix, inspect};
+
+/// Smallest stretch of the new base searched on each side when re-matching bytes the new base
+/// changed.
+const MIN_WINDOW: usize = 4096;
+
+/// Rewrites a delta against `old_base` into one that produces the same output from `new_base`.
+///
+/// A patch authored against one build can then be applied to a build that differs only a
+/// little, such as a hotfix, without having the output to encode it again. The two bases are diffed, skipping
+/// their common prefix and suffix, and every copy is moved to where its bytes are in the new
+/// base. Bytes of the old base that the new base changed are matched again against the new base
+/// around where the copy would have continued, and inserted where they can't be found.
+///
+/// The tag, checksums, compression and copy distance limit are kept; a provenance record is
+/// dropped, since it names the old base. The more the bases differ, the more the result grows
+/// compared to encoding the output against `new_base` from scratch.
+///
+/// # Errors
+/// Fails if the delta doesn't apply to `old_base` or can't be read, e.g. because it is
+/// encrypted.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta;
+///
+/// let release = b"fn main() { greet(); }\nfn greet() { println!(\"hi\"); }\n".repeat(20);
+/// let patched = [&release[..], b"fn farewell() {}\n"].concat();
+/// let patch = delta::encode(3, &release, &patched, true);
+///
+/// // The hotfix build adds a comment at the top
+/// let hotfix = [b"// hotfix\n", &release[..]].concat();
+/// let rebased = delta::rebase(&patch, &release, &hotfix)?;
+///
+/// assert_eq!(delta::get_tag(&rebased), Ok(3));
+/// assert_eq!(delta::decode(&hotfix, &rebased)?, patched);
+/// # Ok::<(), &'static str>(())
+/// ```
+pub fn rebase(delta: &[u8], old_base: &[u8], new_base: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let info = inspect(delta)?;
+    if info
+        .base_checksum
+        .is_some_and(|checksum| checksum != crc32fast::hash(old_base))
+    {
+        return Err("Base data checksum mismatch");
+    }
+
+    let ops = decode_ops(old_base, delta)?;
+    let ops = rebase_ops(&ops, old_base, new_base)?;
+    let options = EncodeOptions {
+        enable_zstd: matches!(info.algorithm, Algorithm::GDeltaZstd | Algorithm::CharsZstd),
+        checksum: info.base_checksum.is_some(),
+        optimize: true,
+        max_copy_distance: info.max_copy_distance.unwrap_or(0),
+        ..EncodeOptions::default()
+    };
+    encode_from_ops(info.tag, new_base, &ops, &options)
+}
+
+/// A run of the old base and where the same bytes start in the new base, if they are there.
+struct Segment {
+    start: usize,
+    len: usize,
+    target: Option<usize>,
+}
+
+/// Diffs the bases into segments covering the whole old base, in order.
+fn map_bases(old_base: &[u8], new_base: &[u8]) -> Vec<Segment> {
+    let prefix = find_common_prefix(old_base, new_base);
+    let suffix = old_base[prefix..]
+        .iter()
+        .rev()
+        .zip(new_base[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old_base[prefix..old_base.len() - suffix];
+    let new_middle = &new_base[prefix..new_base.len() - suffix];
+
+    let mut segments = vec![Segment {
+        start: 0,
+        len: prefix,
+        target: Some(0),
+    }];
+    let mut position = prefix;
+    let middle = if new_middle.is_empty() {
+        vec![Op::Insert(old_middle.to_vec())]
+    } else {
+        encode_ops(new_middle, old_middle)
+    };
+    for op in middle {
+        let target = match op {
+            Op::Copy { offset, .. } => Some(prefix + offset),
+            Op::Insert(_) => None,
+        };
+        segments.push(Segment {
+            start: position,
+            len: op.len(),
+            target,
+        });
+        position += op.len();
+    }
+    segments.push(Segment {
+        start: position,
+        len: suffix,
+        target: Some(new_base.len() - suffix),
+    });
+    segments.retain(|segment| segment.len > 0);
+    segments
+}
+
+/// Moves the copies of `ops` from `old_base` to `new_base`, see [`rebase`].
+fn rebase_ops(ops: &[Op], old_base: &[u8], new_base: &[u8]) -> Result<Vec<Op>, &'static str> {
+    let segments = map_bases(old_base, new_base);
+    let mut rebased = Vec::with_capacity(ops.len());
+    // New base position after the last copy, and changed bytes waiting to be matched again
+    let mut cursor = 0;
+    let mut pending = Vec::new();
+
+    for op in ops {
+        let (offset, len) = match op {
+            Op::Copy { offset, len } => (*offset, *len),
+            Op::Insert(bytes) => {
+                rematch(new_base, &mut rebased, &mut cursor, &mut pending);
+                push_merged(&mut rebased, Op::Insert(bytes.clone()));
+                continue;
+            }
+        };
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= old_base.len())
+            .ok_or("Copy out of bounds")?;
+
+        let mut position = offset;
+        let mut i = segments.partition_point(|segment| segment.start + segment.len <= offset);
+        while position < end {
+            let segment = &segments[i];
+            let n = end.min(segment.start + segment.len) - position;
+            match segment.target {
+                Some(target) => {
+                    rematch(new_base, &mut rebased, &mut cursor, &mut pending);
+                    let offset = target + (position - segment.start);
+                    push_merged(&mut rebased, Op::Copy { offset, len: n });
+                    cursor = offset + n;
+                }
+                None => pending.extend_from_slice(&old_base[position..position + n]),
+            }
+            position += n;
+            i += 1;
+        }
+    }
+    rematch(new_base, &mut rebased, &mut cursor, &mut pending);
+    Ok(rebased)
+}
+
+/// Emits `pending` bytes, copied from the new base around `cursor` where they can be found.
+fn rematch(new_base: &[u8], rebased: &mut Vec<Op>, cursor: &mut usize, pending: &mut Vec<u8>) {
+    if pending.is_empty() {
+        return;
+    }
+    let margin = pending.len().max(MIN_WINDOW);
+    let start = cursor.saturating_sub(margin).min(new_base.len());
+    let end = cursor.saturating_add(margin).min(new_base.len());
+    if start >= end {
+        push_merged(rebased, Op::Insert(std::mem::take(pending)));
+        return;
+    }
+    for op in encode_ops(&new_base[start..end], pending) {
+        match op {
+            Op::Copy { offset, len } => {
+                push_merged(
+                    rebased,
+                    Op::Copy {
+                        offset: start + offset,
+                        len,
+                    },
+                );
+                *cursor = start + offset + len;
+            }
+            op => push_merged(rebased, op),
+        }
+    }
+    pending.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{decode, encode_with_options};
+
+    fn build(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rebase() {
+        let base = build(1, 64 * 1024);
+        let new = [&base[..20_000], &build(2, 300), &base[20_100..]].concat();
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+        let delta = encode_with_options(9, &base, &new, &options);
+
+        // The hotfix changes bytes the delta copies and shifts everything after them
+        let hotfix = [
+            &base[..5_000],
+            &build(3, 40),
+            &base[5_010..40_000],
+            &base[40_500..],
+        ]
+        .concat();
+        let rebased = rebase(&delta, &base, &hotfix).unwrap();
+        assert_eq!(decode(&hotfix, &rebased).unwrap(), new);
+
+        // Only the 510 bytes the hotfix replaced or removed had to be inserted
+        let info = inspect(&rebased).unwrap();
+        assert_eq!(info.tag, 9);
+        assert!(info.base_checksum.is_some());
+        assert!(rebased.len() < delta.len() + 510 + 50);
+
+        // Identical bases give the same output
+        assert_eq!(
+            decode(&base, &rebase(&delta, &base, &base).unwrap()).unwrap(),
+            new
+        );
+        assert_eq!(
+            rebase(&delta, &hotfix, &base),
+            Err("Base data checksum mismatch")
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Region mode, see [`EncodeOptions::regions`].
+
+use std::ops::Range;
+
+#[cfg(feature = "encode")]
+use super::ops::{Op, encode_ops, limit_copy_distance, optimize, push_merged, write_gdelta};
+#[cfg(feature = "encode")]
+use super::{Algorithm, EncodeOptions, finish_gdelta};
+
+/// Sorts regions, drops empty ones and merges those that overlap or touch.
+pub(super) fn normalize(regions: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut sorted: Vec<Range<usize>> = regions
+        .iter()
+        .filter(|region| !region.is_empty())
+        .cloned()
+        .collect();
+    sorted.sort_by_key(|region| region.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(sorted.len());
+    for region in sorted {
+        match merged.last_mut() {
+            Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Encodes `new_data` as copies of the base outside `options.regions` and a diff of each
+/// region against the base bytes at the same offsets, as a GDelta payload.
+///
+/// Returns `None` if a byte outside the regions differs from the base byte at its offset, or
+/// lies past the end of the base.
+#[cfg(feature = "encode")]
+pub(super) fn encode_regions(
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+) -> Option<(Algorithm, Vec<u8>)> {
+    let mut regions = normalize(&options.regions);
+    regions.retain(|region| region.start < new_data.len());
+    if let Some(last) = regions.last_mut() {
+        last.end = last.end.min(new_data.len());
+    }
+
+    let mut ops = Vec::new();
+    let mut position = 0;
+    let gaps = regions.iter().map(|region| (region.start, Some(region)));
+    for (gap_end, region) in gaps.chain([(new_data.len(), None)]) {
+        if base_data.get(position..gap_end)? != &new_data[position..gap_end] {
+            return None;
+        }
+        push_merged(
+            &mut ops,
+            Op::Copy {
+                offset: position,
+                len: gap_end - position,
+            },
+        );
+        let Some(region) = region else { break };
+
+        // The last region may grow or shrink the data: diff it against the rest of the base
+        let window_end = if region.end == new_data.len() {
+            base_data.len()
+        } else {
+            region.end
+        };
+        let window = base_data.get(region.start..window_end).unwrap_or_default();
+        let new_region = &new_data[region.clone()];
+        if window.is_empty() {
+            push_merged(&mut ops, Op::Insert(new_region.to_vec()));
+        } else {
+            for op in encode_ops(window, new_region) {
+                let op = match op {
+                    Op::Copy { offset, len } => Op::Copy {
+                        offset: region.start + offset,
+                        len,
+                    },
+                    op => op,
+                };
+                push_merged(&mut ops, op);
+            }
+        }
+        position = region.end;
+    }
+
+    if options.optimize {
+        ops = optimize(base_data, &ops);
+    }
+    // Copies after a region resume at its end, however far back in it the last copy was
+    if options.max_copy_distance > 0 {
+        ops = limit_copy_distance(base_data, &ops, options.max_copy_distance);
+    }
+    let (instructions, literals) = write_gdelta(&ops);
+    Some(finish_gdelta(instructions, literals, options))
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::*;
+    use crate::delta;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len as u32).map(|i| (i * 31 % 253) as u8).collect()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(&[30..40, 0..10, 5..12, 12..20, 50..50]),
+            [0..20, 30..40]
+        );
+    }
+
+    #[test]
+    fn test_regions() {
+        let base = data(256 * 1024);
+        let mut new = base.clone();
+        new[1000..1016].copy_from_slice(b"changed in place");
+        new.truncate(200_000);
+        new.extend_from_slice(b"a new tail");
+
+        let options = EncodeOptions::default().regions(&[900..1100, 199_000..usize::MAX]);
+        let patch = delta::encode_with_options(3, &base, &new, &options);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+        assert!(patch.len() < 100);
+
+        // A base that differs outside the regions is caught by the checksums
+        let mut other = base.clone();
+        other[50_000] ^= 1;
+        assert_eq!(
+            delta::decode(&other, &patch),
+            Err("Base data checksum mismatch")
+        );
+
+        // Changes outside the regions fall back to diffing everything
+        new[60_000] ^= 1;
+        assert!(encode_regions(&base, &new, &options).is_none());
+        let patch = delta::encode_with_options(3, &base, &new, &options);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+    }
+
+    #[test]
+    fn test_regions_copy_distance() {
+        let base = data(64 * 1024);
+        // A region that keeps only its first bytes, so the copy after it jumps far ahead
+        let mut new = base.clone();
+        for (i, byte) in new[1100..9000].iter_mut().enumerate() {
+            *byte = ((i as u32).wrapping_mul(2654435761) >> 13) as u8;
+        }
+
+        let options = EncodeOptions::default()
+            .regions(&[1000..9000, 20_000..20_100])
+            .max_copy_distance(1024);
+        let patch = delta::encode_with_options(0, &base, &new, &options);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+        assert_eq!(
+            delta::inspect(&patch).unwrap().max_copy_distance,
+            Some(1024)
+        );
+        assert!(delta::dry_run(&base, &patch).is_ok());
+    }
+}
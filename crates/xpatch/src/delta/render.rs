@@ -0,0 +1,383 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Human-readable rendering of the change a delta makes.
+
+use super::decode;
+use super::ops::{self, Op};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Bytes per hex dump row.
+const HEX_ROW: usize = 16;
+
+/// Hex dump rows shown per region before the rest is summarized.
+const MAX_HEX_ROWS: usize = 8;
+
+/// Renders the change `delta` makes to `base_data` as a unified diff.
+///
+/// If both the base and the result are text (UTF-8 without NUL bytes), the diff is line by
+/// line, in hunks with three lines of context: lines the delta copies whole from the base are
+/// unchanged, all others are removed (`-`) or added (`+`). Binary data is shown as byte
+/// regions instead: copied ranges, inserted bytes and skipped base ranges, the latter two as
+/// hex dumps. Every line starts with `@`, `+`, `-` or a space, so callers can color it, and the
+/// result is empty if nothing changed.
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta;
+///
+/// let base = b"one\ntwo\nthree\n";
+/// let patch = delta::encode(0, base, b"one\n2\nthree\n", false);
+/// assert_eq!(
+///     delta::render_diff(base, &patch)?,
+///     "@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"
+/// );
+/// # Ok::<(), &'static str>(())
+/// ```
+pub fn render_diff(base_data: &[u8], delta: &[u8]) -> Result<String, &'static str> {
+    let new_data = decode(base_data, delta)?;
+    let ops = ops::decode_ops(base_data, delta)?;
+    if is_text(base_data) && is_text(&new_data) {
+        Ok(render_lines(base_data, &new_data, &ops))
+    } else {
+        Ok(render_regions(base_data, &ops))
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    !data.contains(&0) && std::str::from_utf8(data).is_ok()
+}
+
+/// Splits data into lines, keeping line endings, with the offset of each line.
+fn lines(data: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut offset = 0;
+    data.split_inclusive(|&b| b == b'\n')
+        .map(|line| {
+            offset += line.len();
+            (offset - line.len(), line)
+        })
+        .collect()
+}
+
+fn render_lines(base_data: &[u8], new_data: &[u8], ops: &[Op]) -> String {
+    let base_lines = lines(base_data);
+    let base_starts: HashMap<usize, usize> = base_lines
+        .iter()
+        .enumerate()
+        .map(|(i, (start, _))| (*start, i))
+        .collect();
+
+    // Output offset at which each instruction starts
+    let mut spans = Vec::with_capacity(ops.len());
+    let mut pos = 0;
+    for op in ops.iter().filter(|op| !op.is_empty()) {
+        spans.push((pos, op));
+        pos += op.len();
+    }
+
+    // Lines copied whole from a base line anchor the diff. In between, instruction boundaries
+    // rarely fall on line ends, so the remaining lines are matched up by content.
+    let mut diff: Vec<(char, &[u8])> = Vec::new();
+    let mut added = Vec::new();
+    let mut cursor = 0;
+    for (start, line) in lines(new_data) {
+        let source = copied_from(&spans, start, line.len())
+            .and_then(|offset| base_starts.get(&offset).copied())
+            .filter(|&i| i >= cursor && base_lines[i].1.len() == line.len());
+        match source {
+            Some(i) => {
+                diff_region(&mut diff, &base_lines[cursor..i], &added);
+                diff.push((' ', line));
+                added.clear();
+                cursor = i + 1;
+            }
+            None => added.push(line),
+        }
+    }
+    diff_region(&mut diff, &base_lines[cursor..], &added);
+
+    let mut out = String::new();
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut i = 0;
+    while i < diff.len() {
+        let Some(first_change) = diff[i..].iter().position(|(kind, _)| *kind != ' ') else {
+            break;
+        };
+        // Extend the hunk while changes are closer than twice the context
+        let start = (i + first_change).saturating_sub(CONTEXT_LINES).max(i);
+        let mut end = i + first_change;
+        let mut unchanged = 0;
+        while end < diff.len() && unchanged <= 2 * CONTEXT_LINES {
+            if diff[end].0 == ' ' {
+                unchanged += 1;
+            } else {
+                unchanged = 0;
+            }
+            end += 1;
+        }
+        let end = (end - unchanged + CONTEXT_LINES.min(unchanged)).min(diff.len());
+
+        for (kind, _) in &diff[i..start] {
+            debug_assert_eq!(*kind, ' ');
+            old_line += 1;
+            new_line += 1;
+        }
+        let hunk = &diff[start..end];
+        let old_count = hunk.iter().filter(|(kind, _)| *kind != '+').count();
+        let new_count = hunk.iter().filter(|(kind, _)| *kind != '-').count();
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            if old_count == 0 {
+                old_line - 1
+            } else {
+                old_line
+            },
+            old_count,
+            if new_count == 0 {
+                new_line - 1
+            } else {
+                new_line
+            },
+            new_count
+        );
+        for (kind, line) in hunk {
+            let text = String::from_utf8_lossy(line);
+            let _ = writeln!(out, "{}{}", kind, text.strip_suffix('\n').unwrap_or(&text));
+        }
+        old_line += old_count;
+        new_line += new_count;
+        i = end;
+    }
+    out
+}
+
+/// Returns the base offset `len` output bytes at `start` are copied from, if they all come
+/// from one contiguous base range.
+fn copied_from(spans: &[(usize, &Op)], start: usize, len: usize) -> Option<usize> {
+    let mut index = spans.partition_point(|(s, _)| *s <= start) - 1;
+    let mut pos = start;
+    let mut source = None;
+    while pos < start + len {
+        let (span_start, op) = spans[index];
+        let Op::Copy {
+            offset,
+            len: span_len,
+        } = op
+        else {
+            return None;
+        };
+        let from = offset + (pos - span_start);
+        if source.is_some_and(|source| source + (pos - start) != from) {
+            return None;
+        }
+        source.get_or_insert(from);
+        pos = span_start + span_len;
+        index += 1;
+    }
+    source
+}
+
+/// Appends the line diff of removed and added lines between two anchors, using their longest
+/// common subsequence. Regions too large for that are shown as fully replaced.
+fn diff_region<'a>(
+    diff: &mut Vec<(char, &'a [u8])>,
+    removed: &[(usize, &'a [u8])],
+    added: &[&'a [u8]],
+) {
+    let (n, m) = (removed.len(), added.len());
+    if n.saturating_mul(m) > 1 << 20 {
+        diff.extend(removed.iter().map(|(_, line)| ('-', *line)));
+        diff.extend(added.iter().map(|line| ('+', *line)));
+        return;
+    }
+
+    // lcs[i][j] = longest common subsequence of removed[i..] and added[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if removed[i].1 == added[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && removed[i].1 == added[j] {
+            diff.push((' ', added[j]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(('-', removed[i].1));
+            i += 1;
+        } else {
+            diff.push(('+', added[j]));
+            j += 1;
+        }
+    }
+}
+
+fn render_regions(base_data: &[u8], ops: &[Op]) -> String {
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut cursor = 0;
+    for op in ops.iter().filter(|op| !op.is_empty()) {
+        match op {
+            Op::Copy { offset, len } => {
+                if *offset > cursor {
+                    render_removed(&mut out, base_data, cursor, *offset);
+                }
+                let _ = writeln!(
+                    out,
+                    "@@ copy base {:08x}..{:08x} to {:08x}..{:08x} ({} bytes) @@",
+                    offset,
+                    offset + len,
+                    pos,
+                    pos + len,
+                    len
+                );
+                cursor = cursor.max(offset + len);
+            }
+            Op::Insert(bytes) => {
+                let _ = writeln!(
+                    out,
+                    "@@ insert at {:08x}..{:08x} ({} bytes) @@",
+                    pos,
+                    pos + bytes.len(),
+                    bytes.len()
+                );
+                hex_dump(&mut out, '+', pos, bytes);
+            }
+        }
+        pos += op.len();
+    }
+    if cursor < base_data.len() {
+        render_removed(&mut out, base_data, cursor, base_data.len());
+    }
+    out
+}
+
+fn render_removed(out: &mut String, base_data: &[u8], start: usize, end: usize) {
+    let _ = writeln!(
+        out,
+        "@@ remove base {:08x}..{:08x} ({} bytes) @@",
+        start,
+        end,
+        end - start
+    );
+    hex_dump(out, '-', start, &base_data[start..end]);
+}
+
+/// Writes `bytes` as hex dump rows starting at `offset`, each prefixed with `kind`.
+fn hex_dump(out: &mut String, kind: char, offset: usize, bytes: &[u8]) {
+    for (row, chunk) in bytes.chunks(HEX_ROW).take(MAX_HEX_ROWS).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            let _ = write!(hex, "{}{:02x}", if i == 0 { "" } else { " " }, byte);
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "{}{:08x}  {:<width$}  |{}|",
+            kind,
+            offset + row * HEX_ROW,
+            hex,
+            ascii,
+            width = HEX_ROW * 3 - 1
+        );
+    }
+    let shown = bytes.len().min(HEX_ROW * MAX_HEX_ROWS);
+    if shown < bytes.len() {
+        let _ = writeln!(out, "{}... {} more bytes", kind, bytes.len() - shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::encode;
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_text_hunks() {
+        let base = numbered_lines(30);
+        let new = base
+            .replace("line 5\n", "line five\n")
+            .replace("line 25\n", "")
+            .replace("line 27\n", "line 27\nextra\n");
+        let delta = encode(0, base.as_bytes(), new.as_bytes(), false);
+        let diff = render_diff(base.as_bytes(), &delta).unwrap();
+
+        assert_eq!(
+            diff,
+            "@@ -2,7 +2,7 @@\n line 2\n line 3\n line 4\n-line 5\n+line five\n line 6\n line 7\n line 8\n\
+             @@ -22,9 +22,9 @@\n line 22\n line 23\n line 24\n-line 25\n line 26\n line 27\n+extra\n line 28\n line 29\n line 30\n"
+        );
+    }
+
+    #[test]
+    fn test_text_without_changes_or_newline() {
+        let base = b"same\ntext";
+        let delta = encode(0, base, base, false);
+        assert_eq!(render_diff(base, &delta).unwrap(), "");
+
+        let delta = encode(0, base, b"same\ntext!", false);
+        assert_eq!(
+            render_diff(base, &delta).unwrap(),
+            "@@ -1,2 +1,2 @@\n same\n-text\n+text!\n"
+        );
+    }
+
+    #[test]
+    fn test_binary_regions() {
+        let base: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        let mut new = base.clone();
+        new.splice(1024..1040, [0xFFu8; 4]);
+        let delta = encode(0, &base, &new, false);
+        let diff = render_diff(&base, &delta).unwrap();
+
+        assert!(
+            diff.contains("@@ insert at 00000400..00000404 (4 bytes) @@\n+00000400  ff ff ff ff")
+        );
+        assert!(diff.contains("@@ remove base "));
+        assert!(diff.starts_with("@@ copy base 00000000..00000400 to 00000000..00000400"));
+        assert!(diff.lines().all(|line| line.starts_with(['@', '+', '-'])));
+    }
+}
@@ -0,0 +1,244 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Encoding of small inputs, such as config blobs and cache values.
+//!
+//! For a few hundred bytes, setting up the encoder costs more than the encoding itself:
+//! GDelta allocates its hash table and zstd a fresh compression context for every delta. Below
+//! [`SMALL_INPUT`] bytes, matches are found with a table on the stack and zstd reuses a
+//! context per thread, so the fixed cost of an encode stays well under a microsecond, plus
+//! time in proportion to the data. Within a [`BatchScope`], or while a
+//! [`Context`](super::Context) lends its own context with [`lend`], inputs of any size reuse
+//! the context.
+
+use super::find_common_prefix;
+use super::index::{Payload, common_suffix};
+#[cfg(feature = "zstd")]
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+#[cfg(feature = "zstd")]
+use zstd::bulk::Compressor;
+
+/// Largest base and new data, in bytes, encoded on the small-input path.
+pub(super) const SMALL_INPUT: usize = 4096;
+
+/// Bytes hashed per table entry; also the shortest match that starts a copy.
+const WORD: usize = 4;
+
+/// The base is indexed at every `STRIDE`th byte, which finds every match one byte longer
+/// than a word.
+const STRIDE: usize = 2;
+
+const TABLE_BITS: u32 = 10;
+
+/// Most base positions compared per word of the new data.
+const MAX_CANDIDATES: usize = 16;
+
+/// Length of a match long enough to take without looking for a longer one.
+const NICE_LENGTH: usize = 64;
+
+/// Encodes `new_data` against `base_data`, both at most [`SMALL_INPUT`] bytes, as a plain
+/// GDelta payload.
+///
+/// Works like [`BaseIndex::encode_gdelta`](super::BaseIndex), except that every
+/// [`STRIDE`]th base position is indexed and chained to the next one with the same hash, in
+/// tables on the stack, and the longest of the chained matches is taken. No table is built
+/// when the common prefix and suffix leave less than a word to match.
+pub(super) fn encode_gdelta(base_data: &[u8], new_data: &[u8]) -> Vec<u8> {
+    debug_assert!(base_data.len() <= SMALL_INPUT && new_data.len() <= SMALL_INPUT);
+    let prefix = find_common_prefix(base_data, new_data);
+    let prefix = if prefix >= WORD { prefix } else { 0 };
+    let suffix = common_suffix(&base_data[prefix..], &new_data[prefix..]);
+    let suffix = if suffix >= WORD { suffix } else { 0 };
+    let end = new_data.len() - suffix;
+
+    let mut payload = Payload::default();
+    payload.copy(0, prefix);
+    if end - prefix >= WORD {
+        encode_matches(base_data, new_data, prefix..end, &mut payload);
+    } else {
+        payload.literal(&new_data[prefix..end]);
+    }
+    payload.copy(base_data.len() - suffix, suffix);
+    payload.finish()
+}
+
+/// Encodes `new_data[range]` as copies of matches in the base and literals in between.
+fn encode_matches(base_data: &[u8], new_data: &[u8], range: Range<usize>, payload: &mut Payload) {
+    // Earliest base position per slot, and for each position the next one in its slot
+    let mut heads = [u16::MAX; 1 << TABLE_BITS];
+    let mut chain = [u16::MAX; SMALL_INPUT];
+    for offset in (0..(base_data.len() + 1).saturating_sub(WORD))
+        .step_by(STRIDE)
+        .rev()
+    {
+        let slot = slot(base_data, offset);
+        chain[offset] = heads[slot];
+        heads[slot] = offset as u16;
+    }
+
+    let end = range.end;
+    let mut literal_start = range.start;
+    // Base position minus new position of the last copy
+    let mut diagonal = 0isize;
+    let mut pos = range.start;
+
+    while pos + WORD <= end {
+        let rest = &new_data[pos..end];
+        let length = |offset: usize| find_common_prefix(&base_data[offset..], rest);
+        let continued = usize::try_from(pos as isize + diagonal)
+            .ok()
+            .filter(|&offset| offset < base_data.len())
+            .map(|offset| (offset, length(offset)))
+            .filter(|&(_, len)| len >= WORD);
+        // The earliest of the longest matches, whose offset is the shortest to encode and, in
+        // repetitive data, repeats from copy to copy so that zstd compresses the instructions
+        let mut found = continued;
+        let mut candidate = heads[slot(new_data, pos)];
+        for _ in 0..MAX_CANDIDATES {
+            if candidate == u16::MAX || found.is_some_and(|(_, len)| len >= NICE_LENGTH) {
+                break;
+            }
+            let len = length(candidate as usize);
+            if len >= WORD
+                && found.is_none_or(|(best_offset, best)| {
+                    len > best || (len == best && (candidate as usize) < best_offset)
+                })
+            {
+                found = Some((candidate as usize, len));
+            }
+            candidate = chain[candidate as usize];
+        }
+        let Some((offset, len)) = found else {
+            pos += 1;
+            continue;
+        };
+
+        let back = common_suffix(&base_data[..offset], &new_data[literal_start..pos]);
+        let (offset, start, len) = (offset - back, pos - back, back + len);
+        payload.literal(&new_data[literal_start..start]);
+        payload.copy(offset, len);
+        pos = start + len;
+        literal_start = pos;
+        diagonal = offset as isize - start as isize;
+    }
+
+    payload.literal(&new_data[literal_start..end]);
+}
+
+/// Table slot of the word at `offset`.
+fn slot(data: &[u8], offset: usize) -> usize {
+    let word = u32::from_le_bytes(data[offset..offset + WORD].try_into().unwrap());
+    (word.wrapping_mul(0x9E37_79B1) >> (32 - TABLE_BITS)) as usize
+}
+
+/// A zstd context and the level it compresses at.
+#[cfg(feature = "zstd")]
+pub(super) type Slot = Option<(i32, Compressor<'static>)>;
+
+#[cfg(feature = "zstd")]
+thread_local! {
+    /// The calling thread's zstd context.
+    static COMPRESSOR: RefCell<Slot> = const { RefCell::new(None) };
+    /// Whether inputs of any size use the context, while a batch is encoded on this thread.
+    static IN_BATCH: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether [`zstd_compress`] is used for `len` bytes: always for small inputs, and for any
+/// input within a [`BatchScope`].
+#[cfg(feature = "zstd")]
+pub(super) fn keeps_context(len: usize) -> bool {
+    len <= SMALL_INPUT || IN_BATCH.get()
+}
+
+/// Compresses `data` at `level` with a zstd context kept for the calling thread.
+///
+/// Like [`zstd::encode_all`], the frames omit the content size, so they are no larger; their
+/// window is sized to the data.
+#[cfg(feature = "zstd")]
+pub(super) fn zstd_compress(data: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    use zstd::zstd_safe::CParameter;
+
+    COMPRESSOR.with_borrow_mut(|compressor| {
+        let compressor = match compressor {
+            Some((current, compressor)) if *current == level => compressor,
+            _ => {
+                let mut fresh = Compressor::new(level)?;
+                fresh.set_parameter(CParameter::ContentSizeFlag(false))?;
+                &mut compressor.insert((level, fresh)).1
+            }
+        };
+        compressor.compress(data)
+    })
+}
+
+/// While alive, lets inputs of any size reuse the calling thread's zstd context. The outermost
+/// scope frees the context when it ends, since large inputs grow it.
+pub(super) struct BatchScope {
+    #[cfg(feature = "zstd")]
+    nested: bool,
+}
+
+impl BatchScope {
+    pub(super) fn enter() -> Self {
+        Self {
+            #[cfg(feature = "zstd")]
+            nested: IN_BATCH.replace(true),
+        }
+    }
+}
+
+impl Drop for BatchScope {
+    fn drop(&mut self) {
+        #[cfg(feature = "zstd")]
+        if !self.nested {
+            IN_BATCH.set(false);
+            COMPRESSOR.take();
+        }
+    }
+}
+
+/// Lends the zstd context in `slot` to the calling thread for inputs of any size, until the
+/// returned guard hands it back. The thread's own context is kept aside meanwhile.
+#[cfg(feature = "zstd")]
+pub(super) fn lend(slot: &mut Slot) -> Lent<'_> {
+    let previous = COMPRESSOR.replace(slot.take());
+    Lent {
+        slot,
+        previous,
+        in_batch: IN_BATCH.replace(true),
+    }
+}
+
+/// Guard returned by [`lend`].
+#[cfg(feature = "zstd")]
+pub(super) struct Lent<'a> {
+    slot: &'a mut Slot,
+    previous: Slot,
+    in_batch: bool,
+}
+
+#[cfg(feature = "zstd")]
+impl Drop for Lent<'_> {
+    fn drop(&mut self) {
+        *self.slot = COMPRESSOR.replace(self.previous.take());
+        IN_BATCH.set(self.in_batch);
+    }
+}
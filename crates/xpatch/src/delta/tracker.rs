@@ -0,0 +1,293 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Rolling snapshots of in-memory state.
+
+#[cfg(feature = "decode")]
+use super::decode;
+use super::read_header_varint;
+#[cfg(feature = "encode")]
+use super::{EncodeOptions, encode_with_options, estimate_size};
+use crate::varint::encode_varint;
+use std::collections::VecDeque;
+
+/// One step of a replicated state: the delta from a retained version to a new one.
+///
+/// Produced by [`Tracker::commit`] on the sending side and consumed by [`Tracker::apply`] on
+/// the receiving side. [`Commit::to_bytes`] frames it for the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// Version the delta produces
+    pub version: u64,
+    /// Version the delta is against, `None` if it holds the whole state
+    pub base: Option<u64>,
+    /// The delta itself, as produced by [`encode`](super::encode)
+    pub delta: Vec<u8>,
+}
+
+impl Commit {
+    /// Serializes the commit as `varint version | varint (base + 1, or 0) | delta`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = encode_varint(self.version as usize);
+        out.extend(encode_varint(self.base.map_or(0, |base| base as usize + 1)));
+        out.extend_from_slice(&self.delta);
+        out
+    }
+
+    /// Parses a commit serialized by [`Commit::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut pos = 0;
+        let version = read_header_varint(bytes, &mut pos)?;
+        let base = read_header_varint(bytes, &mut pos)?;
+        Ok(Self {
+            version: version as u64,
+            base: base.checked_sub(1).map(|base| base as u64),
+            delta: bytes[pos..].to_vec(),
+        })
+    }
+}
+
+/// The last few versions of a byte buffer, for replicating state as a stream of deltas.
+///
+/// Meant for game-state or document replication loops that ship a snapshot 10 to 60 times a
+/// second. The sender calls [`commit`](Tracker::commit) with each new state; the tracker
+/// encodes it against whichever retained version gives the smallest delta, estimated with
+/// [`estimate_size`](super::estimate_size), and remembers it. The receiver keeps a tracker of
+/// the same capacity and calls [`apply`](Tracker::apply) with each commit. As long as the
+/// receiver sees every commit in order, it holds the same versions as the sender, so every
+/// base the sender picks is available to it.
+///
+/// Memory is bounded by the capacity: the oldest version is dropped once more than
+/// `capacity` are held. A receiver that misses commits, e.g. on an unreliable transport, can
+/// report the last version it has and be caught up with [`delta_since`](Tracker::delta_since).
+///
+/// # Example
+///
+/// ```
+/// use xpatch::delta::Tracker;
+///
+/// let mut sender = Tracker::new(8);
+/// let mut receiver = Tracker::new(8);
+///
+/// let mut state = b"player=1 x=10 y=20 hp=100;".repeat(20);
+/// for tick in 0..30u8 {
+///     state[9] = b'0' + tick % 10;
+///     let commit = sender.commit(&state);
+///     let wire = commit.to_bytes();
+///     assert!(tick == 0 || wire.len() < 32);
+///
+///     let commit = xpatch::delta::Commit::from_bytes(&wire).unwrap();
+///     assert_eq!(receiver.apply(&commit).unwrap(), &state[..]);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    capacity: usize,
+    /// Retained versions, oldest first
+    versions: VecDeque<(u64, Vec<u8>)>,
+    next_version: u64,
+    #[cfg(feature = "encode")]
+    options: EncodeOptions,
+}
+
+impl Tracker {
+    /// Creates a tracker retaining the last `capacity` versions, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            versions: VecDeque::new(),
+            next_version: 0,
+            #[cfg(feature = "encode")]
+            options: EncodeOptions::default(),
+        }
+    }
+
+    /// Sets the options deltas are encoded with.
+    #[cfg(feature = "encode")]
+    pub fn with_options(mut self, options: EncodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the number of versions retained at most.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of versions currently retained.
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// Returns true if no version has been committed or applied yet.
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Returns the newest version and its state.
+    pub fn latest(&self) -> Option<(u64, &[u8])> {
+        self.versions
+            .back()
+            .map(|(version, state)| (*version, &state[..]))
+    }
+
+    /// Returns the state of a retained version.
+    pub fn get(&self, version: u64) -> Option<&[u8]> {
+        self.versions
+            .iter()
+            .find(|(v, _)| *v == version)
+            .map(|(_, state)| &state[..])
+    }
+
+    /// Returns the total size of the retained states in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.versions.iter().map(|(_, state)| state.len()).sum()
+    }
+
+    /// Records a new state and returns the delta that produces it from a retained version.
+    ///
+    /// Every retained version is a candidate base, as is the empty buffer, so a state that
+    /// shares nothing with its predecessors is sent whole. Ties go to the newest version.
+    #[cfg(feature = "encode")]
+    pub fn commit(&mut self, state: &[u8]) -> Commit {
+        let mut best: Option<(usize, Option<u64>, &[u8])> = None;
+        for (version, base) in self.versions.iter().rev() {
+            let size = estimate_size(base, state);
+            if best.is_none_or(|(best_size, _, _)| size < best_size) {
+                best = Some((size, Some(*version), base));
+            }
+        }
+        let (base, base_data) = match best {
+            Some((size, version, base)) if size < estimate_size(&[], state) => (version, base),
+            _ => (None, &[][..]),
+        };
+        let delta = encode_with_options(0, base_data, state, &self.options);
+
+        let version = self.next_version;
+        self.push(version, state.to_vec());
+        Commit {
+            version,
+            base,
+            delta,
+        }
+    }
+
+    /// Returns the delta from `base`, or from nothing if `None`, to the newest version.
+    ///
+    /// Returns `None` if nothing was committed yet or `base` is no longer retained; a
+    /// receiver that has fallen that far behind is caught up with `delta_since(None)`.
+    #[cfg(feature = "encode")]
+    pub fn delta_since(&self, base: Option<u64>) -> Option<Commit> {
+        let (version, state) = self.latest()?;
+        let base_data = match base {
+            Some(base) => self.get(base)?,
+            None => &[],
+        };
+        Some(Commit {
+            version,
+            base,
+            delta: encode_with_options(0, base_data, state, &self.options),
+        })
+    }
+
+    /// Applies a commit from the sending side and returns the state it produces.
+    ///
+    /// Fails if the commit's base is not retained or it is not newer than the newest
+    /// version held. On success the new version is retained like a committed one.
+    #[cfg(feature = "decode")]
+    pub fn apply(&mut self, commit: &Commit) -> Result<&[u8], &'static str> {
+        if self
+            .latest()
+            .is_some_and(|(latest, _)| commit.version <= latest)
+        {
+            return Err("Commit is not newer than the latest version");
+        }
+        let state = match commit.base {
+            Some(base) => decode(
+                self.get(base).ok_or("Commit base is not retained")?,
+                &commit.delta,
+            )?,
+            None => decode(&[], &commit.delta)?,
+        };
+        self.push(commit.version, state);
+        Ok(self.latest().map(|(_, state)| state).unwrap_or_default())
+    }
+
+    fn push(&mut self, version: u64, state: Vec<u8>) {
+        if self.versions.len() == self.capacity {
+            self.versions.pop_front();
+        }
+        self.versions.push_back((version, state));
+        self.next_version = version + 1;
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_replicates_and_picks_best_base() {
+        let mut sender = Tracker::new(4);
+        let mut receiver = Tracker::new(4);
+        let a = b"alpha state with plenty of shared content to copy from ".repeat(40);
+        let b = b"bravo state that looks nothing like the alpha one at all".repeat(40);
+
+        for (state, base) in [(&a, None), (&b, None), (&a, Some(0))] {
+            let commit = sender.commit(state);
+            assert_eq!(commit.base, base);
+            assert_eq!(receiver.apply(&commit).unwrap(), &state[..]);
+        }
+
+        let commit = sender.commit(&a);
+        assert_eq!(commit.base, Some(2));
+        assert_eq!(Commit::from_bytes(&commit.to_bytes()).unwrap(), commit);
+        receiver.apply(&commit).unwrap();
+
+        // Capacity bounds what is retained on both sides
+        sender.commit(&b);
+        assert_eq!(sender.len(), 4);
+        assert_eq!(sender.get(0), None);
+        assert_eq!(sender.memory_usage(), 2 * a.len() + 2 * b.len());
+    }
+
+    #[test]
+    fn test_tracker_catches_up_missed_commits() {
+        let mut sender = Tracker::new(2);
+        let mut receiver = Tracker::new(2);
+        let mut state = b"0123456789".repeat(50);
+
+        receiver.apply(&sender.commit(&state)).unwrap();
+        for i in 0..5 {
+            state[i] = b'x';
+            sender.commit(&state);
+        }
+        let missed = sender.commit(&state);
+        assert_eq!(receiver.apply(&missed), Err("Commit base is not retained"));
+        assert!(sender.delta_since(Some(0)).is_none());
+
+        let catch_up = sender.delta_since(None).unwrap();
+        assert_eq!(receiver.apply(&catch_up).unwrap(), &state[..]);
+        assert_eq!(
+            receiver.apply(&catch_up),
+            Err("Commit is not newer than the latest version")
+        );
+    }
+}
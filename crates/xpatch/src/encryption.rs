@@ -0,0 +1,274 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Encrypted deltas.
+//!
+//! Patches often travel over untrusted channels or sit on shared storage, and a delta reveals
+//! the bytes it inserts. This module wraps a complete delta in an XChaCha20-Poly1305 envelope,
+//! so only holders of the 32-byte key can apply it.
+//!
+//! The envelope starts with an extended header carrying the encryption flag, followed by a
+//! random 24-byte nonce and the encrypted delta:
+//!
+//! ```text
+//! [3-bit algo][1][0x04] 0x00 [varint tag] [nonce: 24 bytes] [ciphertext + 16-byte auth tag]
+//! ```
+//!
+//! The algorithm and tag stay readable, so [`get_tag`](crate::delta::get_tag) and
+//! [`inspect`](crate::delta::inspect) work without the key, and the header is authenticated
+//! along with the ciphertext. [`delta::decode`](crate::delta::decode) rejects encrypted deltas
+//! with `"Delta is encrypted"`; use [`decode`] with the key instead.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::delta::EncodeOptions;
+//! use xpatch::encryption;
+//!
+//! let key = encryption::generate_key()?;
+//! let base = b"Hello, World!";
+//! let new = b"Hello, secret World!";
+//!
+//! let delta = encryption::encode(0, base, new, &EncodeOptions::default(), &key)?;
+//! assert!(encryption::is_encrypted(&delta));
+//! assert!(xpatch::decode(base, &delta).is_err());
+//! assert_eq!(encryption::decode(base, &delta, &key)?, new);
+//! # Ok::<(), &'static str>(())
+//! ```
+
+use crate::delta::{self, EncodeOptions};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+
+/// Size of an encryption key in bytes.
+pub const KEY_SIZE: usize = 32;
+
+/// Size of the nonce stored in every encrypted delta.
+pub const NONCE_SIZE: usize = 24;
+
+/// Generates a random key from the operating system's random number generator.
+pub fn generate_key() -> Result<[u8; KEY_SIZE], &'static str> {
+    let mut key = [0u8; KEY_SIZE];
+    getrandom::fill(&mut key).map_err(|_| "Failed to generate random bytes")?;
+    Ok(key)
+}
+
+/// Returns whether `delta` is an encrypted delta.
+///
+/// Data that is not a valid delta header is reported as not encrypted.
+pub fn is_encrypted(delta: &[u8]) -> bool {
+    delta::parse_header(delta).is_ok_and(|header| header.encrypted)
+}
+
+/// Encrypts an encoded delta with a fresh random nonce.
+///
+/// The result keeps the delta's algorithm and tag in its header.
+///
+/// # Errors
+/// Returns an error if `delta` has no valid header or is already encrypted.
+pub fn encrypt(delta: &[u8], key: &[u8; KEY_SIZE]) -> Result<Vec<u8>, &'static str> {
+    let header = delta::parse_header(delta)?;
+    if header.encrypted {
+        return Err("Delta is already encrypted");
+    }
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    getrandom::fill(&mut nonce).map_err(|_| "Failed to generate random bytes")?;
+
+    let mut envelope = delta::encode_encrypted_header(header.algorithm, header.tag);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: delta,
+                aad: &envelope,
+            },
+        )
+        .map_err(|_| "Encryption failed")?;
+
+    envelope.reserve(NONCE_SIZE + ciphertext.len());
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decrypts an encrypted delta, returning the plain delta inside.
+///
+/// # Errors
+/// Returns `"Delta is not encrypted"` for plain deltas and `"Decryption failed"` if the key is
+/// wrong or the envelope was modified.
+pub fn decrypt(envelope: &[u8], key: &[u8; KEY_SIZE]) -> Result<Vec<u8>, &'static str> {
+    let header = delta::parse_header(envelope)?;
+    if !header.encrypted {
+        return Err("Delta is not encrypted");
+    }
+
+    let (aad, rest) = envelope.split_at(header.size);
+    if rest.len() < NONCE_SIZE {
+        return Err("Truncated encrypted delta");
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| "Decryption failed")
+}
+
+/// Encodes a delta like [`delta::encode_with_options`] and encrypts it.
+pub fn encode(
+    tag: usize,
+    base_data: &[u8],
+    new_data: &[u8],
+    options: &EncodeOptions,
+    key: &[u8; KEY_SIZE],
+) -> Result<Vec<u8>, &'static str> {
+    encrypt(
+        &delta::encode_with_options(tag, base_data, new_data, options),
+        key,
+    )
+}
+
+/// Decrypts an encrypted delta and applies it to `base_data`.
+///
+/// # Errors
+/// Returns any error of [`decrypt`] or [`delta::decode`].
+pub fn decode(
+    base_data: &[u8],
+    envelope: &[u8],
+    key: &[u8; KEY_SIZE],
+) -> Result<Vec<u8>, &'static str> {
+    delta::decode(base_data, &decrypt(envelope, key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [0x42; KEY_SIZE];
+
+    #[test]
+    fn test_roundtrip() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"The quick brown fox jumps over the lazy cat";
+        let options = EncodeOptions {
+            checksum: true,
+            ..EncodeOptions::default()
+        };
+
+        let envelope = encode(1234, base, new, &options, &KEY).unwrap();
+        assert_eq!(decode(base, &envelope, &KEY).unwrap(), new);
+
+        let plain = decrypt(&envelope, &KEY).unwrap();
+        assert_eq!(plain, delta::encode_with_options(1234, base, new, &options));
+    }
+
+    #[test]
+    fn test_header_stays_readable() {
+        let plain = delta::encode(99, b"abc", b"abcdef", false);
+        let envelope = encrypt(&plain, &KEY).unwrap();
+
+        assert!(is_encrypted(&envelope));
+        assert!(!is_encrypted(&plain));
+        assert!(!is_encrypted(b""));
+        assert_eq!(delta::get_tag(&envelope), Ok(99));
+
+        let info = delta::inspect(&envelope).unwrap();
+        assert!(info.encrypted);
+        assert_eq!(info.algorithm, delta::inspect(&plain).unwrap().algorithm);
+        assert_eq!(info.payload_size, NONCE_SIZE + plain.len() + 16);
+    }
+
+    #[test]
+    fn test_plain_decode_rejects_envelope() {
+        let envelope = encode(0, b"abc", b"abcdef", &EncodeOptions::default(), &KEY).unwrap();
+        assert_eq!(delta::decode(b"abc", &envelope), Err("Delta is encrypted"));
+    }
+
+    #[test]
+    fn test_provenance_is_encrypted_with_delta() {
+        let provenance = delta::Provenance::new(b"base".to_vec(), b"new".to_vec());
+        let plain = delta::encode(0, b"abc", b"abcdef", false);
+        let annotated = delta::annotate(&plain, &provenance).unwrap();
+
+        let envelope = encrypt(&annotated, &KEY).unwrap();
+        assert_eq!(delta::inspect(&envelope).unwrap().provenance, None);
+        assert_eq!(
+            delta::annotate(&envelope, &provenance),
+            Err("Delta is encrypted")
+        );
+
+        let decrypted = decrypt(&envelope, &KEY).unwrap();
+        assert_eq!(
+            delta::inspect(&decrypted).unwrap().provenance,
+            Some(provenance)
+        );
+        assert_eq!(decode(b"abc", &envelope, &KEY).unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn test_nonce_is_random() {
+        let plain = delta::encode(0, b"abc", b"abcdef", false);
+        assert_ne!(
+            encrypt(&plain, &KEY).unwrap(),
+            encrypt(&plain, &KEY).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let plain = delta::encode(7, b"abc", b"abcdef", false);
+        let envelope = encrypt(&plain, &KEY).unwrap();
+
+        assert_eq!(
+            decrypt(&envelope, &[0u8; KEY_SIZE]),
+            Err("Decryption failed")
+        );
+        assert_eq!(decrypt(&plain, &KEY), Err("Delta is not encrypted"));
+        assert_eq!(encrypt(&envelope, &KEY), Err("Delta is already encrypted"));
+
+        // Truncated
+        let header_size = delta::inspect(&envelope).unwrap().header_size;
+        assert_eq!(
+            decrypt(&envelope[..header_size + 10], &KEY),
+            Err("Truncated encrypted delta")
+        );
+        assert_eq!(
+            decrypt(&envelope[..envelope.len() - 1], &KEY),
+            Err("Decryption failed")
+        );
+
+        // Tampered ciphertext
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(decrypt(&tampered, &KEY), Err("Decryption failed"));
+
+        // Tampered header: the tag is authenticated
+        let mut tampered = envelope.clone();
+        tampered[2] = 8;
+        assert_eq!(delta::get_tag(&tampered), Ok(8));
+        assert_eq!(decrypt(&tampered, &KEY), Err("Decryption failed"));
+    }
+}
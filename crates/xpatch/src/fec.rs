@@ -0,0 +1,528 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Forward error correction for deltas.
+//!
+//! Patches stored in flash or sent over lossy links can pick up a few flipped bytes, which
+//! makes them unusable. [`protect`] wraps a delta in an envelope with Reed–Solomon parity, so
+//! that [`repair`] can correct corrupted bytes without a retransmission. With the `fec`
+//! feature enabled, [`delta::decode`](crate::delta::decode) repairs protected deltas
+//! transparently; use [`decode`] to also learn how many bytes were corrected.
+//!
+//! # Format
+//!
+//! ```text
+//! header ×3 | (data block, parity)*
+//! header = 0xFC 0x00 "RS" | version u8 | parity u8 | delta length u32 | delta crc32 u32 | header crc32 u32
+//! ```
+//!
+//! The delta is cut into blocks of `255 - parity` bytes, each followed by `parity` bytes of
+//! RS(255) parity over GF(2^8), correcting up to `parity / 2` corrupted bytes per block,
+//! parity bytes included. The header is stored three times and repaired by majority vote. Its
+//! first two bytes form an invalid delta header, so a protected delta is never mistaken for a
+//! plain one. After repair, the delta is checked against its CRC32.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::{delta, fec};
+//!
+//! let base = b"Hello, World!".repeat(20);
+//! let new = b"Hello, Rust World!".repeat(20);
+//! let mut protected = fec::protect(&delta::encode(0, &base, &new, true));
+//!
+//! // Flip a few bytes
+//! for i in [60, 61, 100] {
+//!     protected[i] ^= 0xFF;
+//! }
+//!
+//! let (decoded, corrected) = fec::decode(&base, &protected)?;
+//! assert_eq!(decoded, new);
+//! assert_eq!(corrected, 3);
+//! # Ok::<(), &'static str>(())
+//! ```
+
+use crate::delta;
+
+/// Parity bytes per block used by [`protect`]: 8 correctable bytes per 255, about 7% overhead.
+pub const DEFAULT_PARITY: usize = 16;
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'R', b'S'];
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 18;
+const HEADER_COPIES: usize = 3;
+const BLOCK_SIZE: usize = 255;
+
+/// Wraps a delta in an error-correcting envelope with [`DEFAULT_PARITY`].
+pub fn protect(delta: &[u8]) -> Vec<u8> {
+    protect_with_parity(delta, DEFAULT_PARITY).expect("default parity is valid")
+}
+
+/// Wraps a delta in an error-correcting envelope with `parity` bytes per 255-byte block.
+///
+/// Each block corrects up to `parity / 2` corrupted bytes. `parity` must be even and between
+/// 2 and 128.
+pub fn protect_with_parity(delta: &[u8], parity: usize) -> Result<Vec<u8>, &'static str> {
+    if !valid_parity(parity) {
+        return Err("Invalid parity size");
+    }
+    let length = u32::try_from(delta.len()).map_err(|_| "Delta too large")?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.push(parity as u8);
+    header.extend_from_slice(&length.to_le_bytes());
+    header.extend_from_slice(&crc32fast::hash(delta).to_le_bytes());
+    header.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+
+    let blocks = delta.len().div_ceil(BLOCK_SIZE - parity);
+    let mut envelope =
+        Vec::with_capacity(HEADER_SIZE * HEADER_COPIES + delta.len() + blocks * parity);
+    for _ in 0..HEADER_COPIES {
+        envelope.extend_from_slice(&header);
+    }
+    let generator = rs::generator(parity);
+    for block in delta.chunks(BLOCK_SIZE - parity) {
+        envelope.extend_from_slice(block);
+        envelope.extend(rs::parity(block, &generator));
+    }
+    Ok(envelope)
+}
+
+/// Returns `true` if `data` looks like an envelope produced by [`protect`].
+pub fn is_protected(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE * HEADER_COPIES && vote(data, 0..MAGIC.len()) == MAGIC
+}
+
+/// Recovers the delta from an envelope produced by [`protect`].
+///
+/// Returns the delta and the number of corrupted bytes that were corrected.
+pub fn repair(envelope: &[u8]) -> Result<(Vec<u8>, usize), &'static str> {
+    if !is_protected(envelope) {
+        return Err("Not an FEC envelope");
+    }
+    // Prefer the byte-wise majority, falling back to any intact copy
+    let header = std::iter::once(vote(envelope, 0..HEADER_SIZE))
+        .chain(
+            envelope
+                .chunks(HEADER_SIZE)
+                .take(HEADER_COPIES)
+                .map(<[u8]>::to_vec),
+        )
+        .find(|header| {
+            u32::from_le_bytes(header[14..18].try_into().unwrap()) == crc32fast::hash(&header[..14])
+        })
+        .ok_or("Invalid FEC envelope header")?;
+    if header[4] != VERSION {
+        return Err("Unsupported FEC envelope version");
+    }
+    let parity = header[5] as usize;
+    if !valid_parity(parity) {
+        return Err("Invalid FEC envelope header");
+    }
+    let length = u32::from_le_bytes(header[6..10].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[10..14].try_into().unwrap());
+
+    let mut corrected = envelope[..HEADER_SIZE * HEADER_COPIES]
+        .chunks(HEADER_SIZE)
+        .map(|copy| copy.iter().zip(&header).filter(|(a, b)| a != b).count())
+        .sum();
+
+    let body = &envelope[HEADER_SIZE * HEADER_COPIES..];
+    let blocks = length.div_ceil(BLOCK_SIZE - parity);
+    if body.len() != length + blocks * parity {
+        return Err("Truncated FEC envelope");
+    }
+
+    let mut delta = Vec::with_capacity(length);
+    for block in body.chunks(BLOCK_SIZE) {
+        let mut block = block.to_vec();
+        corrected += rs::correct(&mut block, parity)?;
+        delta.extend_from_slice(&block[..block.len() - parity]);
+    }
+    if crc32fast::hash(&delta) != checksum {
+        return Err("FEC repair failed");
+    }
+    Ok((delta, corrected))
+}
+
+/// Repairs and decodes a protected delta.
+///
+/// Returns the reconstructed data and the number of corrupted bytes that were corrected.
+pub fn decode(base_data: &[u8], envelope: &[u8]) -> Result<(Vec<u8>, usize), &'static str> {
+    let (delta, corrected) = repair(envelope)?;
+    Ok((delta::decode(base_data, &delta)?, corrected))
+}
+
+fn valid_parity(parity: usize) -> bool {
+    (2..=128).contains(&parity) && parity.is_multiple_of(2)
+}
+
+/// Majority vote over the header copies, byte by byte.
+fn vote(envelope: &[u8], range: std::ops::Range<usize>) -> Vec<u8> {
+    range
+        .map(|i| {
+            let (a, b, c) = (
+                envelope[i],
+                envelope[HEADER_SIZE + i],
+                envelope[2 * HEADER_SIZE + i],
+            );
+            if a != b && b == c { b } else { a }
+        })
+        .collect()
+}
+
+/// Reed–Solomon coding over GF(2^8) with the primitive polynomial 0x11D and generator 2.
+///
+/// Polynomials are stored highest degree first. Blocks shorter than 255 bytes are shortened
+/// codewords, implicitly padded with leading zeros.
+mod rs {
+    const fn build_tables() -> ([u8; 512], [u8; 256]) {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        let mut i = 0;
+        while i < 255 {
+            exp[i] = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11D;
+            }
+            i += 1;
+        }
+        while i < 512 {
+            exp[i] = exp[i - 255];
+            i += 1;
+        }
+        (exp, log)
+    }
+
+    const TABLES: ([u8; 512], [u8; 256]) = build_tables();
+    const EXP: [u8; 512] = TABLES.0;
+    const LOG: [u8; 256] = TABLES.1;
+
+    fn mul(a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+        }
+    }
+
+    fn div(a: u8, b: u8) -> u8 {
+        if a == 0 {
+            0
+        } else {
+            EXP[(LOG[a as usize] as usize + 255 - LOG[b as usize] as usize) % 255]
+        }
+    }
+
+    fn inverse(a: u8) -> u8 {
+        EXP[255 - LOG[a as usize] as usize]
+    }
+
+    /// Returns 2^e.
+    fn pow2(e: usize) -> u8 {
+        EXP[e % 255]
+    }
+
+    fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+        p.iter().map(|&c| mul(c, x)).collect()
+    }
+
+    fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+        let len = p.len().max(q.len());
+        let mut r = vec![0u8; len];
+        for (i, &c) in p.iter().enumerate() {
+            r[i + len - p.len()] = c;
+        }
+        for (i, &c) in q.iter().enumerate() {
+            r[i + len - q.len()] ^= c;
+        }
+        r
+    }
+
+    fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+        let mut r = vec![0u8; p.len() + q.len() - 1];
+        for (j, &b) in q.iter().enumerate() {
+            for (i, &a) in p.iter().enumerate() {
+                r[i + j] ^= mul(a, b);
+            }
+        }
+        r
+    }
+
+    fn poly_eval(p: &[u8], x: u8) -> u8 {
+        p.iter().fold(0, |y, &c| mul(y, x) ^ c)
+    }
+
+    /// Generator polynomial for `nsym` parity symbols.
+    pub(super) fn generator(nsym: usize) -> Vec<u8> {
+        (0..nsym).fold(vec![1], |g, i| poly_mul(&g, &[1, pow2(i)]))
+    }
+
+    /// Computes the parity bytes of a block.
+    pub(super) fn parity(data: &[u8], generator: &[u8]) -> Vec<u8> {
+        let nsym = generator.len() - 1;
+        let mut remainder = data.to_vec();
+        remainder.resize(data.len() + nsym, 0);
+        for i in 0..data.len() {
+            let coef = remainder[i];
+            if coef != 0 {
+                for (j, &g) in generator.iter().enumerate().skip(1) {
+                    remainder[i + j] ^= mul(g, coef);
+                }
+            }
+        }
+        remainder.split_off(data.len())
+    }
+
+    /// Syndromes of a block, with a leading zero.
+    fn syndromes(block: &[u8], nsym: usize) -> Vec<u8> {
+        let mut synd = vec![0u8];
+        synd.extend((0..nsym).map(|i| poly_eval(block, pow2(i))));
+        synd
+    }
+
+    /// Corrects a block (data followed by `nsym` parity bytes) in place and returns the number
+    /// of corrected bytes.
+    pub(super) fn correct(block: &mut [u8], nsym: usize) -> Result<usize, &'static str> {
+        let synd = syndromes(block, nsym);
+        if synd.iter().all(|&s| s == 0) {
+            return Ok(0);
+        }
+
+        let err_loc = error_locator(&synd, nsym)?;
+        let reversed: Vec<u8> = err_loc.iter().rev().copied().collect();
+        let err_pos = find_errors(&reversed, block.len())?;
+        correct_errata(block, &synd, &err_pos)?;
+
+        if syndromes(block, nsym).iter().any(|&s| s != 0) {
+            return Err("Too many errors to correct");
+        }
+        Ok(err_pos.len())
+    }
+
+    /// Berlekamp–Massey: computes the error locator polynomial from the syndromes.
+    fn error_locator(synd: &[u8], nsym: usize) -> Result<Vec<u8>, &'static str> {
+        let mut err_loc = vec![1u8];
+        let mut old_loc = vec![1u8];
+        for k in 1..=nsym {
+            let mut delta = synd[k];
+            for j in 1..err_loc.len() {
+                delta ^= mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+            }
+            old_loc.push(0);
+            if delta != 0 {
+                if old_loc.len() > err_loc.len() {
+                    let new_loc = poly_scale(&old_loc, delta);
+                    old_loc = poly_scale(&err_loc, inverse(delta));
+                    err_loc = new_loc;
+                }
+                err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+            }
+        }
+
+        let leading = err_loc.iter().take_while(|&&c| c == 0).count();
+        err_loc.drain(..leading);
+        if err_loc.is_empty() || (err_loc.len() - 1) * 2 > nsym {
+            return Err("Too many errors to correct");
+        }
+        Ok(err_loc)
+    }
+
+    /// Chien search: finds the positions of the errors from the (reversed) locator.
+    fn find_errors(err_loc: &[u8], len: usize) -> Result<Vec<usize>, &'static str> {
+        let positions: Vec<usize> = (0..len)
+            .filter(|&i| poly_eval(err_loc, pow2(i)) == 0)
+            .map(|i| len - 1 - i)
+            .collect();
+        if positions.len() != err_loc.len() - 1 {
+            return Err("Too many errors to correct");
+        }
+        Ok(positions)
+    }
+
+    /// Forney algorithm: computes the error magnitudes and fixes the block.
+    fn correct_errata(
+        block: &mut [u8],
+        synd: &[u8],
+        err_pos: &[usize],
+    ) -> Result<(), &'static str> {
+        let coef_pos: Vec<usize> = err_pos.iter().map(|&p| block.len() - 1 - p).collect();
+        let err_loc = coef_pos
+            .iter()
+            .fold(vec![1u8], |loc, &i| poly_mul(&loc, &[pow2(i), 1]));
+
+        // Error evaluator: (S(x) * Λ(x)) mod x^(errors + 1)
+        let reversed_synd: Vec<u8> = synd.iter().rev().copied().collect();
+        let product = poly_mul(&reversed_synd, &err_loc);
+        let err_eval = &product[product.len().saturating_sub(err_loc.len())..];
+
+        let x: Vec<u8> = coef_pos.iter().map(|&i| pow2(i)).collect();
+        for (i, &xi) in x.iter().enumerate() {
+            let xi_inv = inverse(xi);
+            let err_loc_prime = x
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .fold(1, |acc, (_, &xj)| mul(acc, 1 ^ mul(xi_inv, xj)));
+            if err_loc_prime == 0 {
+                return Err("Too many errors to correct");
+            }
+            let y = mul(xi, poly_eval(err_eval, xi_inv));
+            block[err_pos[i]] ^= div(y, err_loc_prime);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_roundtrip_without_errors() {
+        for len in [0, 1, 100, 238, 239, 240, 5000] {
+            let delta = pseudo_random(len, len as u64);
+            let envelope = protect(&delta);
+            assert!(is_protected(&envelope));
+            assert_eq!(repair(&envelope).unwrap(), (delta, 0));
+        }
+        assert!(!is_protected(&pseudo_random(100, 1)));
+        assert!(!is_protected(&delta::encode(0, b"abc", b"abcdef", false)));
+    }
+
+    #[test]
+    fn test_corrects_up_to_half_parity_per_block() {
+        for parity in [2, 16, 32, 128] {
+            let delta = pseudo_random(3000, parity as u64);
+            let clean = protect_with_parity(&delta, parity).unwrap();
+            let body_start = HEADER_SIZE * HEADER_COPIES;
+
+            // Corrupt parity / 2 bytes in every block, data and parity alike
+            let mut envelope = clean.clone();
+            let mut corrupted = 0;
+            for (n, block) in envelope[body_start..].chunks_mut(BLOCK_SIZE).enumerate() {
+                let positions = pseudo_random(parity / 2, n as u64 + 100);
+                let mut hit = std::collections::HashSet::new();
+                for p in positions {
+                    let pos = p as usize % block.len();
+                    if hit.insert(pos) {
+                        block[pos] ^= (p | 1).rotate_left(n as u32 % 8);
+                        corrupted += 1;
+                    }
+                }
+            }
+            assert_eq!(repair(&envelope).unwrap(), (delta.clone(), corrupted));
+
+            // One byte more than correctable in one block fails
+            let mut envelope = clean;
+            for pos in 0..parity / 2 + 1 {
+                envelope[body_start + pos * 3] ^= 0x5A;
+            }
+            assert!(repair(&envelope).is_err());
+        }
+    }
+
+    #[test]
+    fn test_header_majority_vote() {
+        let delta = pseudo_random(500, 9);
+        let mut envelope = protect(&delta);
+        // Destroy the first copy completely and one byte of the second
+        for byte in &mut envelope[..HEADER_SIZE] {
+            *byte = !*byte;
+        }
+        envelope[HEADER_SIZE + 7] ^= 1;
+        assert!(is_protected(&envelope));
+        assert_eq!(repair(&envelope).unwrap(), (delta.clone(), HEADER_SIZE + 1));
+
+        // Without a majority, an intact copy is used
+        let mut envelope = protect(&delta);
+        envelope[6] ^= 1;
+        envelope[HEADER_SIZE + 6] ^= 1;
+        assert_eq!(repair(&envelope).unwrap(), (delta.clone(), 2));
+
+        // The same corruption in every copy cannot be repaired
+        let mut envelope = protect(&delta);
+        for copy in 0..HEADER_COPIES {
+            envelope[copy * HEADER_SIZE + 6] ^= 1;
+        }
+        assert_eq!(repair(&envelope), Err("Invalid FEC envelope header"));
+    }
+
+    #[test]
+    fn test_transparent_decode() {
+        let base = pseudo_random(4000, 1);
+        let mut new = base.clone();
+        new[1000..1100].copy_from_slice(&pseudo_random(100, 2));
+        let options = delta::EncodeOptions {
+            checksum: true,
+            ..delta::EncodeOptions::default()
+        };
+        let mut envelope = protect(&delta::encode_with_options(0, &base, &new, &options));
+        envelope[HEADER_SIZE * HEADER_COPIES + 3] ^= 0xFF;
+        *envelope.last_mut().unwrap() ^= 0x01;
+
+        assert_eq!(delta::decode(&base, &envelope).unwrap(), new);
+        assert_eq!(decode(&base, &envelope).unwrap(), (new, 2));
+    }
+
+    #[test]
+    fn test_transparent_header() {
+        let base = pseudo_random(4000, 1);
+        let mut new = base.clone();
+        new[1000..1100].copy_from_slice(&pseudo_random(100, 2));
+        let patch = delta::encode(42, &base, &new, true);
+        let mut envelope = protect(&patch);
+        envelope[HEADER_SIZE * HEADER_COPIES] ^= 0xFF;
+
+        assert_eq!(delta::get_tag(&envelope), delta::get_tag(&patch));
+        assert_eq!(delta::inspect(&envelope), delta::inspect(&patch));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(protect_with_parity(b"x", 0), Err("Invalid parity size"));
+        assert_eq!(protect_with_parity(b"x", 3), Err("Invalid parity size"));
+        assert_eq!(protect_with_parity(b"x", 130), Err("Invalid parity size"));
+        assert_eq!(repair(b"plain delta"), Err("Not an FEC envelope"));
+
+        let envelope = protect(&pseudo_random(1000, 3));
+        assert_eq!(
+            repair(&envelope[..envelope.len() - 1]),
+            Err("Truncated FEC envelope")
+        );
+    }
+}
@@ -0,0 +1,225 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Hash assertions for firmware updates.
+//!
+//! An update script that flashes whatever a patch produced only notices a wrong base or a
+//! corrupted patch after the device fails to boot. [`apply`] checks the SHA-256 of the base
+//! before decoding and the SHA-256 of the output before returning it, so nothing is written
+//! unless both are the images the release was built from. The checksums a delta may embed are
+//! CRC32s chosen by whoever encoded it; these hashes come from the release process instead.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::{delta, firmware};
+//!
+//! let base = b"firmware 1.0".to_vec();
+//! let new = b"firmware 1.1".to_vec();
+//! let patch = delta::encode(0, &base, &new, false);
+//!
+//! let expected = firmware::Expected {
+//!     base_sha256: Some(firmware::sha256(&base)),
+//!     output_sha256: Some(firmware::sha256(&new)),
+//! };
+//! assert_eq!(firmware::apply(&base, &patch, &expected)?, new);
+//! assert!(firmware::apply(&new, &patch, &expected).is_err());
+//! # Ok::<(), firmware::ApplyError>(())
+//! ```
+
+use crate::delta;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+
+/// Size of a SHA-256 hash in bytes.
+pub const HASH_SIZE: usize = 32;
+
+/// The hashes [`apply`] asserts; `None` skips a check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expected {
+    /// SHA-256 of the base the patch must be applied to
+    pub base_sha256: Option<[u8; HASH_SIZE]>,
+    /// SHA-256 of the data the patch must produce
+    pub output_sha256: Option<[u8; HASH_SIZE]>,
+}
+
+/// Why [`apply`] refused to produce an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    /// The base is not the expected image
+    BaseMismatch {
+        expected: [u8; HASH_SIZE],
+        actual: [u8; HASH_SIZE],
+    },
+    /// The patch did not produce the expected image
+    OutputMismatch {
+        expected: [u8; HASH_SIZE],
+        actual: [u8; HASH_SIZE],
+    },
+    /// The patch can't be read or doesn't apply to the base
+    Decode(&'static str),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::BaseMismatch { expected, actual } => write!(
+                f,
+                "Base SHA-256 mismatch: expected {}, got {}",
+                to_hex(expected),
+                to_hex(actual)
+            ),
+            ApplyError::OutputMismatch { expected, actual } => write!(
+                f,
+                "Output SHA-256 mismatch: expected {}, got {}",
+                to_hex(expected),
+                to_hex(actual)
+            ),
+            ApplyError::Decode(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for ApplyError {}
+
+impl From<&'static str> for ApplyError {
+    fn from(message: &'static str) -> Self {
+        ApplyError::Decode(message)
+    }
+}
+
+/// Hashes `data` with SHA-256.
+pub fn sha256(data: &[u8]) -> [u8; HASH_SIZE] {
+    Sha256::digest(data).into()
+}
+
+/// Parses a SHA-256 hash written as 64 hex digits, in either case.
+pub fn parse_sha256(hex: &str) -> Result<[u8; HASH_SIZE], &'static str> {
+    const INVALID: &str = "SHA-256 must be 64 hex digits";
+    if hex.len() != HASH_SIZE * 2 {
+        return Err(INVALID);
+    }
+    let mut hash = [0; HASH_SIZE];
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| INVALID)?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| INVALID)?;
+    }
+    Ok(hash)
+}
+
+/// Returns `hash` as lowercase hex.
+pub fn to_hex(hash: &[u8; HASH_SIZE]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks the base against [`Expected::base_sha256`].
+pub fn verify_base(base: &[u8], expected: &Expected) -> Result<(), ApplyError> {
+    check(base, expected.base_sha256, |expected, actual| {
+        ApplyError::BaseMismatch { expected, actual }
+    })
+}
+
+/// Checks decoded data against [`Expected::output_sha256`].
+pub fn verify_output(output: &[u8], expected: &Expected) -> Result<(), ApplyError> {
+    check(output, expected.output_sha256, |expected, actual| {
+        ApplyError::OutputMismatch { expected, actual }
+    })
+}
+
+/// Applies `delta` to `base` like [`delta::decode`], failing before decoding if the base
+/// doesn't have the expected hash and instead of returning an output that doesn't.
+pub fn apply(base: &[u8], delta: &[u8], expected: &Expected) -> Result<Vec<u8>, ApplyError> {
+    verify_base(base, expected)?;
+    let output = delta::decode(base, delta)?;
+    verify_output(&output, expected)?;
+    Ok(output)
+}
+
+fn check(
+    data: &[u8],
+    expected: Option<[u8; HASH_SIZE]>,
+    mismatch: impl FnOnce([u8; HASH_SIZE], [u8; HASH_SIZE]) -> ApplyError,
+) -> Result<(), ApplyError> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = sha256(data);
+    match actual == expected {
+        true => Ok(()),
+        false => Err(mismatch(expected, actual)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let base = b"bootloader v3, application v7".repeat(50);
+        let mut new = base.clone();
+        new[27..29].copy_from_slice(b"v8");
+        let patch = delta::encode(0, &base, &new, false);
+
+        assert_eq!(apply(&base, &patch, &Expected::default()).unwrap(), new);
+        let expected = Expected {
+            base_sha256: Some(sha256(&base)),
+            output_sha256: Some(sha256(&new)),
+        };
+        assert_eq!(apply(&base, &patch, &expected).unwrap(), new);
+
+        let err = apply(&new, &patch, &expected).unwrap_err();
+        assert_eq!(
+            err,
+            ApplyError::BaseMismatch {
+                expected: sha256(&base),
+                actual: sha256(&new)
+            }
+        );
+        let wrong = Expected {
+            output_sha256: Some(sha256(&base)),
+            ..expected
+        };
+        assert!(matches!(
+            apply(&base, &patch, &wrong),
+            Err(ApplyError::OutputMismatch { .. })
+        ));
+        assert!(matches!(
+            apply(&base, b"", &expected),
+            Err(ApplyError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let hash = sha256(b"abc");
+        let hex = to_hex(&hash);
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(parse_sha256(&hex), Ok(hash));
+        assert_eq!(parse_sha256(&hex.to_uppercase()), Ok(hash));
+        assert!(parse_sha256(&hex[2..]).is_err());
+        assert!(parse_sha256(&format!("zz{}", &hex[2..])).is_err());
+        assert!(parse_sha256(&format!("é{}", &hex[3..])).is_err());
+    }
+}
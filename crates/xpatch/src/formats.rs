@@ -0,0 +1,105 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Deltas that understand file formats.
+//!
+//! A generic delta sees an archive as one long byte string. When files are added, removed or
+//! reordered, their contents end up at unrelated offsets and the encoder has to rediscover
+//! every match. [`tar`] and [`zip`] split an archive into its members, diff each member
+//! against its counterpart in the base, and still reproduce the new archive byte for byte.
+//! [`exe`] realigns the branches and data references of executables whose code moved, and
+//! [`json`] matches the members and elements of reordered JSON documents.
+//! [`delta::decode`](crate::delta::decode) applies all of their deltas transparently.
+//!
+//! [`encode`] picks the module that understands both inputs. ZIP support needs the
+//! `compressed` feature, since it inflates and recompresses entries, and executables need the
+//! `exe` feature.
+
+#[cfg(feature = "exe")]
+pub mod exe;
+#[cfg(feature = "encode")]
+pub mod json;
+pub mod tar;
+#[cfg(feature = "compressed")]
+pub mod zip;
+
+#[cfg(feature = "encode")]
+use crate::delta::EncodeOptions;
+#[cfg(feature = "decode")]
+use crate::delta::read_header_varint;
+#[cfg(feature = "encode")]
+use crate::varint::encode_varint;
+
+/// Encodes a delta with [`exe::encode`] if both inputs are executables, with [`zip::encode`]
+/// if both are ZIP archives, with [`json::encode`] if both are JSON, with [`tar::encode`] if
+/// both are tar archives, and as a plain delta otherwise.
+///
+/// Deltas for [`FORMAT_V1`](crate::delta::FORMAT_V1) decoders are never wrapped in an
+/// envelope; only the JSON diff, which writes plain deltas, applies to them.
+#[cfg(feature = "encode")]
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let envelopes = options.target_format >= crate::delta::FORMAT_V2;
+    #[cfg(feature = "exe")]
+    if envelopes && exe::detect(base_data).is_some() && exe::detect(new_data).is_some() {
+        return exe::encode(tag, base_data, new_data, options);
+    }
+    #[cfg(feature = "compressed")]
+    if envelopes && zip::is_zip(base_data) && zip::is_zip(new_data) {
+        return zip::encode(tag, base_data, new_data, options);
+    }
+    if json::is_json(base_data) && json::is_json(new_data) {
+        return json::encode(tag, base_data, new_data, options);
+    }
+    if !envelopes {
+        return crate::delta::encode_with_options(tag, base_data, new_data, options);
+    }
+    tar::encode(tag, base_data, new_data, options)
+}
+
+/// Member paths, without the top-level directory if all members are in the same one, so that
+/// `project-1.0/src/main.rs` matches `project-1.1/src/main.rs`.
+#[cfg(feature = "encode")]
+fn relative_paths<'a>(paths: impl Iterator<Item = &'a [u8]> + Clone) -> Vec<&'a [u8]> {
+    let first = paths.clone().next().unwrap_or_default();
+    let root = first
+        .iter()
+        .position(|&b| b == b'/')
+        .map(|slash| &first[..=slash])
+        .filter(|root| paths.clone().all(|path| path.starts_with(root)));
+    let skip = root.map_or(0, <[u8]>::len);
+    paths.map(|path| &path[skip..]).collect()
+}
+
+#[cfg(feature = "encode")]
+fn write_delta(out: &mut Vec<u8>, delta: &[u8]) {
+    out.extend(encode_varint(delta.len()));
+    out.extend_from_slice(delta);
+}
+
+#[cfg(feature = "decode")]
+fn read_delta<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], &'static str> {
+    let len = read_header_varint(data, pos)?;
+    let delta = pos
+        .checked_add(len)
+        .and_then(|end| data.get(*pos..end))
+        .ok_or("Invalid delta length")?;
+    *pos += len;
+    Ok(delta)
+}
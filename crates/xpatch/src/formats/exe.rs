@@ -0,0 +1,692 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Deltas between executables that see through moved code.
+//!
+//! Adding a few bytes to one function of a program shifts everything after it, and every
+//! relative call or jump across the shift gets a new displacement. A plain delta sees those
+//! changed displacements scattered through the whole code section. [`encode`] finds the code
+//! sections of ELF and PE files and works out from a plain delta's copies how each part of the
+//! base's code moved in the new executable. It then rewrites the displacements of the base's
+//! branches to point where their targets moved, in the way courgette and zucchini project
+//! references between versions, and encodes the new executable against the adjusted base.
+//! Branches whose source and target moved alike keep their bytes, and the others now match
+//! the new executable.
+//!
+//! Only the base is rewritten, so decoding adjusts the base again from the [`Shift`]s stored
+//! in the delta and applies the inner delta to it. Nothing needs to be undone, and data
+//! mistaken for code costs at most a slightly larger delta. The x86 rewrite covers `call` and
+//! `jmp` with 32-bit displacements (x86 and x86-64); the ARM64 rewrite covers `b` and `bl`.
+//! When the adjustment does not pay off, [`encode`] returns the plain delta.
+//!
+//! # Format
+//!
+//! ```text
+//! 0xFC 0x00 "EX" | version u8 | arch u8 | regions | shifts | delta
+//! regions = varint(count) | (varint(file offset) varint(length) varint(address))*
+//! shifts  = varint(count) | (varint(address - previous address) zigzag-varint(distance))*
+//! arch    = 1 (x86) | 2 (ARM64) | 3 (x86-64)
+//! ```
+//!
+//! The regions are the base's code sections. The first two bytes form an invalid delta
+//! header, so an executable delta is never mistaken for a plain one.
+
+use crate::delta::ops::{self, Op};
+use crate::delta::read_header_varint;
+use crate::delta::{self, EncodeOptions};
+use crate::varint::encode_varint;
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'E', b'X'];
+const VERSION: u8 = 1;
+
+/// Shortest copy of a plain delta that counts as moved code.
+const MIN_MOVE: usize = 32;
+
+/// Instruction set whose branches are rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    /// 32-bit x86: `call rel32`, `jmp rel32` and absolute memory operands
+    X86,
+    /// x86-64: `call rel32`, `jmp rel32` and RIP-relative memory operands
+    X86_64,
+    /// 64-bit ARM: `b` and `bl`
+    Arm64,
+}
+
+/// Part of a file that holds code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Where the code starts in the file
+    pub offset: usize,
+    /// Length of the code in bytes
+    pub len: usize,
+    /// Virtual address the code is loaded at
+    pub address: u64,
+}
+
+/// The code in an executable, as found by [`detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    /// Instruction set of the code
+    pub arch: Arch,
+    /// Executable sections, in file order and not overlapping
+    pub regions: Vec<Region>,
+    /// All sections loaded into memory, code included
+    pub sections: Vec<Region>,
+}
+
+/// How far the base's code moved in the new executable, from `address` up to the next shift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shift {
+    /// First base address the shift applies to
+    pub address: u64,
+    /// Distance the code moved, in bytes
+    pub distance: i64,
+}
+
+/// Finds the code sections of a little-endian ELF or PE file for x86, x86-64 or ARM64.
+///
+/// Returns `None` for other files, and for executables without section headers.
+pub fn detect(data: &[u8]) -> Option<Executable> {
+    let (arch, mut regions, sections) = if data.starts_with(b"\x7FELF") {
+        elf_regions(data)?
+    } else if data.starts_with(b"MZ") {
+        pe_regions(data)?
+    } else {
+        return None;
+    };
+
+    regions.retain(|region| region.len > 0);
+    regions.sort_by_key(|region| region.offset);
+    let mut end = 0;
+    regions.retain(|region| {
+        let keep = region.offset >= end;
+        if keep {
+            end = region.offset + region.len;
+        }
+        keep
+    });
+    (!regions.is_empty()).then_some(Executable {
+        arch,
+        regions,
+        sections,
+    })
+}
+
+/// Encodes a delta from `base_data` to `new_data` against a base whose branches were adjusted
+/// to the new executable's layout.
+///
+/// Returns a plain delta from [`delta::encode_with_options`] unless both inputs are
+/// executables for the same instruction set and the adjusted delta is smaller.
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let plain = delta::encode_with_options(tag, base_data, new_data, options);
+    let (Some(base), Some(new)) = (detect(base_data), detect(new_data)) else {
+        return plain;
+    };
+    if base.arch != new.arch {
+        return plain;
+    }
+    let shifts = shifts(&base, &new, &ops::encode_ops(base_data, new_data));
+    if shifts.is_empty() {
+        return plain;
+    }
+
+    let mut adjusted = base_data.to_vec();
+    adjust(&mut adjusted, base.arch, &base.regions, &shifts);
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.push(match base.arch {
+        Arch::X86 => 1,
+        Arch::Arm64 => 2,
+        Arch::X86_64 => 3,
+    });
+    out.extend(encode_varint(base.regions.len()));
+    for region in &base.regions {
+        out.extend(encode_varint(region.offset));
+        out.extend(encode_varint(region.len));
+        out.extend(encode_varint(region.address as usize));
+    }
+    out.extend(encode_varint(shifts.len()));
+    let mut previous = 0;
+    for shift in &shifts {
+        out.extend(encode_varint((shift.address - previous) as usize));
+        out.extend(encode_varint(
+            ((shift.distance << 1) ^ (shift.distance >> 63)) as usize,
+        ));
+        previous = shift.address;
+    }
+    out.extend(delta::encode_with_options(
+        tag, &adjusted, new_data, options,
+    ));
+
+    if out.len() < plain.len() { out } else { plain }
+}
+
+/// Whether `data` was produced by [`encode`] from two executables.
+pub fn is_wrapped(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// A delta produced by [`encode`], split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<'a> {
+    /// Instruction set of both executables
+    pub arch: Arch,
+    /// Code sections of the base executable
+    pub regions: Vec<Region>,
+    /// How the base's code moved, by ascending address
+    pub shifts: Vec<Shift>,
+    /// The delta from the adjusted base to the new executable
+    pub delta: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// Splits a delta produced by [`encode`] into its parts.
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        const INVALID: &str = "Invalid executable delta";
+
+        if !is_wrapped(data) {
+            return Err(INVALID);
+        }
+        match data.get(MAGIC.len()) {
+            Some(&VERSION) => {}
+            Some(_) => return Err("Unsupported executable delta version"),
+            None => return Err(INVALID),
+        }
+        let arch = match data.get(MAGIC.len() + 1) {
+            Some(1) => Arch::X86,
+            Some(2) => Arch::Arm64,
+            Some(3) => Arch::X86_64,
+            _ => return Err(INVALID),
+        };
+        let mut pos = MAGIC.len() + 2;
+
+        let count = read_header_varint(data, &mut pos)?;
+        // Every region takes at least three bytes, which bounds the allocation
+        let mut regions = Vec::with_capacity(count.min(data.len() / 3));
+        let mut end = 0;
+        for _ in 0..count {
+            let region = Region {
+                offset: read_header_varint(data, &mut pos)?,
+                len: read_header_varint(data, &mut pos)?,
+                address: read_header_varint(data, &mut pos)? as u64,
+            };
+            if region.offset < end {
+                return Err(INVALID);
+            }
+            end = region.offset.checked_add(region.len).ok_or(INVALID)?;
+            regions.push(region);
+        }
+
+        let count = read_header_varint(data, &mut pos)?;
+        let mut shifts = Vec::with_capacity(count.min(data.len() / 2));
+        let mut address = 0u64;
+        for _ in 0..count {
+            address = address
+                .checked_add(read_header_varint(data, &mut pos)? as u64)
+                .ok_or(INVALID)?;
+            let zigzag = read_header_varint(data, &mut pos)? as u64;
+            let distance = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            shifts.push(Shift { address, distance });
+        }
+
+        Ok(Self {
+            arch,
+            regions,
+            shifts,
+            delta: &data[pos..],
+        })
+    }
+
+    /// The base data with its branches adjusted like when the delta was encoded.
+    pub(crate) fn adjusted_base(&self, base_data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let fits = self
+            .regions
+            .last()
+            .is_none_or(|region| region.offset + region.len <= base_data.len());
+        if !fits {
+            return Err("Base data does not match the executable delta");
+        }
+        let mut adjusted = base_data.to_vec();
+        adjust(&mut adjusted, self.arch, &self.regions, &self.shifts);
+        Ok(adjusted)
+    }
+}
+
+/// Collects where the plain delta copies code from one place to another, by base address.
+fn shifts(base: &Executable, new: &Executable, ops: &[Op]) -> Vec<Shift> {
+    let mut moves = Vec::new();
+    let mut pos = 0;
+    for op in ops {
+        if let Op::Copy { offset, len } = *op
+            && len >= MIN_MOVE
+            && let Some(from) = address(&base.sections, offset, len)
+            && let Some(to) = address(&new.sections, pos, len)
+        {
+            moves.push(Shift {
+                address: from,
+                distance: to.wrapping_sub(from) as i64,
+            });
+        }
+        pos += op.len();
+    }
+
+    moves.sort_by_key(|shift| shift.address);
+    let mut shifts: Vec<Shift> = Vec::new();
+    for shift in moves {
+        if shifts.last().map_or(0, |last| last.distance) != shift.distance {
+            shifts.push(shift);
+        }
+    }
+    shifts
+}
+
+/// The address of `len` bytes at `offset`, if they lie within one region.
+fn address(regions: &[Region], offset: usize, len: usize) -> Option<u64> {
+    let region = regions
+        .iter()
+        .find(|region| offset >= region.offset && offset + len <= region.offset + region.len)?;
+    Some(region.address + (offset - region.offset) as u64)
+}
+
+/// Rewrites the references in `regions` so that they point where their targets moved.
+fn adjust(data: &mut [u8], arch: Arch, regions: &[Region], shifts: &[Shift]) {
+    let distance = |address: u64| match shifts.partition_point(|shift| shift.address <= address) {
+        0 => 0,
+        i => shifts[i - 1].distance,
+    };
+
+    for region in regions {
+        let Some(code) = data.get_mut(region.offset..region.offset + region.len) else {
+            continue;
+        };
+        match arch {
+            Arch::X86 => adjust_x86(code, region.address, false, distance),
+            Arch::X86_64 => adjust_x86(code, region.address, true, distance),
+            Arch::Arm64 => adjust_arm64(code, region.address, distance),
+        }
+    }
+}
+
+/// Adjusts `call rel32` (E8), `jmp rel32` (E9) and `mov`, `lea` and SSE loads and stores with
+/// a memory operand that is RIP-relative in 64-bit code or absolute in 32-bit code.
+///
+/// Relative operands must be within ±16 MiB, which skips most data that looks like code.
+fn adjust_x86(code: &mut [u8], address: u64, wide: bool, distance: impl Fn(u64) -> i64) {
+    let mut i = 0;
+    while i < code.len() {
+        let operand = match code[i..] {
+            [0xE8 | 0xE9, ..] => 1,
+            [0x89 | 0x8B | 0x8D, modrm, ..] if modrm & 0xC7 == 0x05 => 2,
+            [0xFF, 0x15 | 0x25, ..] => 2,
+            [0x0F, 0x10 | 0x11 | 0x28 | 0x29, modrm, ..] if modrm & 0xC7 == 0x05 => 3,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let end = i + operand + 4;
+        let Some(field) = code.get_mut(end - 4..end) else {
+            break;
+        };
+        let value = i32::from_le_bytes((&*field).try_into().unwrap());
+        let source = address.wrapping_add(i as u64);
+
+        if operand == 1 || wide {
+            if !matches!(field[3], 0x00 | 0xFF) {
+                i += 1;
+                continue;
+            }
+            let target = address
+                .wrapping_add(end as u64)
+                .wrapping_add_signed(value.into());
+            let moved = distance(target).wrapping_sub(distance(source));
+            if let Ok(adjusted) = i32::try_from(i64::from(value).wrapping_add(moved)) {
+                field.copy_from_slice(&adjusted.to_le_bytes());
+            }
+        } else {
+            let target = u64::from(value as u32);
+            if let Ok(adjusted) = u32::try_from(target.wrapping_add_signed(distance(target))) {
+                field.copy_from_slice(&adjusted.to_le_bytes());
+            }
+        }
+        i = end;
+    }
+}
+
+/// Adjusts the 26-bit word offset of `b` and `bl`.
+fn adjust_arm64(code: &mut [u8], address: u64, distance: impl Fn(u64) -> i64) {
+    for (i, word) in code.chunks_exact_mut(4).enumerate() {
+        let insn = u32::from_le_bytes((&*word).try_into().unwrap());
+        if insn & 0x7C00_0000 != 0x1400_0000 {
+            continue;
+        }
+        let offset = i64::from((insn << 6) as i32 >> 6) * 4;
+        let source = address.wrapping_add(i as u64 * 4);
+        let target = source.wrapping_add_signed(offset);
+        let adjusted = offset.wrapping_add(distance(target).wrapping_sub(distance(source)));
+        if adjusted % 4 == 0 && (-(1 << 27)..1 << 27).contains(&adjusted) {
+            let imm = (adjusted >> 2) as u32 & 0x03FF_FFFF;
+            word.copy_from_slice(&(insn & 0xFC00_0000 | imm).to_le_bytes());
+        }
+    }
+}
+
+/// The code sections and all loaded sections of an executable.
+type Sections = (Arch, Vec<Region>, Vec<Region>);
+
+fn elf_regions(data: &[u8]) -> Option<Sections> {
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_NOBITS: u32 = 8;
+    const SHF_ALLOC: u64 = 2;
+    const SHF_EXECINSTR: u64 = 4;
+
+    // Little-endian only
+    if *data.get(5)? != 1 {
+        return None;
+    }
+    let arch = match u16_at(data, 18)? {
+        3 => Arch::X86,
+        62 => Arch::X86_64,
+        183 => Arch::Arm64,
+        _ => return None,
+    };
+    let wide = match data[4] {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let (table, entry_size, count) = match wide {
+        true => (
+            u64_at(data, 0x28)?,
+            u16_at(data, 0x3A)?,
+            u16_at(data, 0x3C)?,
+        ),
+        false => (
+            u64::from(u32_at(data, 0x20)?),
+            u16_at(data, 0x2E)?,
+            u16_at(data, 0x30)?,
+        ),
+    };
+    let table = usize::try_from(table).ok()?;
+
+    let mut regions = Vec::new();
+    let mut sections = Vec::new();
+    for i in 0..count as usize {
+        let header = table.checked_add(i * entry_size as usize)?;
+        let (kind, flags, address, offset, size) = match wide {
+            true => (
+                u32_at(data, header + 4)?,
+                u64_at(data, header + 8)?,
+                u64_at(data, header + 16)?,
+                u64_at(data, header + 24)?,
+                u64_at(data, header + 32)?,
+            ),
+            false => (
+                u32_at(data, header + 4)?,
+                u64::from(u32_at(data, header + 8)?),
+                u64::from(u32_at(data, header + 12)?),
+                u64::from(u32_at(data, header + 16)?),
+                u64::from(u32_at(data, header + 20)?),
+            ),
+        };
+        if kind != SHT_NOBITS && flags & SHF_ALLOC != 0 {
+            sections.push(region(data, offset, size, address)?);
+        }
+        if kind == SHT_PROGBITS && flags & SHF_EXECINSTR != 0 {
+            regions.push(region(data, offset, size, address)?);
+        }
+    }
+    Some((arch, regions, sections))
+}
+
+fn pe_regions(data: &[u8]) -> Option<Sections> {
+    const CODE: u32 = 0x0000_0020;
+    const EXECUTE: u32 = 0x2000_0000;
+
+    let pe = u32_at(data, 0x3C)? as usize;
+    if data.get(pe..pe + 4)? != b"PE\0\0" {
+        return None;
+    }
+    let arch = match u16_at(data, pe + 4)? {
+        0x014C => Arch::X86,
+        0x8664 => Arch::X86_64,
+        0xAA64 => Arch::Arm64,
+        _ => return None,
+    };
+    let count = u16_at(data, pe + 6)? as usize;
+    let optional = pe + 24;
+    // Section addresses are relative to the image base, which absolute operands include
+    let image_base = match u16_at(data, optional)? {
+        0x10B => u64::from(u32_at(data, optional + 28)?),
+        0x20B => u64_at(data, optional + 24)?,
+        _ => return None,
+    };
+    let table = optional + u16_at(data, pe + 20)? as usize;
+
+    let mut regions = Vec::new();
+    let mut sections = Vec::new();
+    for i in 0..count {
+        let header = table + i * 40;
+        let characteristics = u32_at(data, header + 36)?;
+        let section = region(
+            data,
+            u64::from(u32_at(data, header + 20)?),
+            u64::from(u32_at(data, header + 16)?),
+            image_base + u64::from(u32_at(data, header + 12)?),
+        )?;
+        sections.push(section);
+        if characteristics & (CODE | EXECUTE) != 0 {
+            regions.push(section);
+        }
+    }
+    Some((arch, regions, sections))
+}
+
+/// A region clamped to the end of the file.
+fn region(data: &[u8], offset: u64, size: u64, address: u64) -> Option<Region> {
+    let offset = usize::try_from(offset).ok()?.min(data.len());
+    let len = usize::try_from(size).ok()?.min(data.len() - offset);
+    Some(Region {
+        offset,
+        len,
+        address,
+    })
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(pos..pos.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(pos..pos.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(pos..pos.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT_OFFSET: usize = 0x1000;
+    const TEXT_ADDRESS: u64 = 0x40_1000;
+
+    /// An x86-64 ELF file whose only section is `text`.
+    fn elf(text: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; TEXT_OFFSET];
+        data[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data.extend_from_slice(text);
+
+        let table = data.len();
+        data[0x28..0x30].copy_from_slice(&(table as u64).to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&2u16.to_le_bytes());
+        let mut header = [0u8; 64];
+        header[4..8].copy_from_slice(&1u32.to_le_bytes());
+        header[8..16].copy_from_slice(&6u64.to_le_bytes());
+        header[16..24].copy_from_slice(&TEXT_ADDRESS.to_le_bytes());
+        header[24..32].copy_from_slice(&(TEXT_OFFSET as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(&header);
+        data
+    }
+
+    /// Functions of the given sizes that call each other every 24 bytes.
+    fn program(sizes: &[usize]) -> Vec<u8> {
+        let starts: Vec<usize> = sizes
+            .iter()
+            .scan(0, |start, size| {
+                let current = *start;
+                *start += size;
+                Some(current)
+            })
+            .collect();
+
+        let mut text = Vec::new();
+        for (function, &size) in sizes.iter().enumerate() {
+            let mut state = function as u64 + 1;
+            let mut next = || {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            };
+            let start = text.len();
+            while text.len() < start + size {
+                if (text.len() - start) % 24 == 20 && text.len() + 5 <= start + size {
+                    let callee = starts[next() as usize % starts.len()];
+                    let displacement = callee as i64 - (text.len() + 5) as i64;
+                    text.push(0xE8);
+                    text.extend_from_slice(&(displacement as i32).to_le_bytes());
+                } else {
+                    // Stays clear of the opcodes that are adjusted
+                    text.push(next() as u8 & 0x7F);
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_detect() {
+        let text = program(&[100, 200]);
+        let data = elf(&text);
+        let region = Region {
+            offset: TEXT_OFFSET,
+            len: text.len(),
+            address: TEXT_ADDRESS,
+        };
+        assert_eq!(
+            detect(&data),
+            Some(Executable {
+                arch: Arch::X86_64,
+                regions: vec![region],
+                sections: vec![region],
+            })
+        );
+        assert_eq!(detect(b"\x7FELF not really"), None);
+        assert_eq!(detect(&text), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let sizes = vec![480; 400];
+        let mut grown = sizes.clone();
+        grown[3] += 40;
+        let base = elf(&program(&sizes));
+        let new = elf(&program(&grown));
+
+        let options = EncodeOptions::default();
+        let patch = encode(7, &base, &new, &options);
+        let plain = delta::encode_with_options(7, &base, &new, &options);
+        assert!(is_wrapped(&patch));
+        assert!(
+            patch.len() < plain.len() / 2,
+            "{} vs {}",
+            patch.len(),
+            plain.len()
+        );
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+
+        let envelope = Envelope::parse(&patch).unwrap();
+        assert_eq!(envelope.arch, Arch::X86_64);
+        assert_eq!(envelope.regions, detect(&base).unwrap().regions);
+        assert_eq!(delta::get_tag(envelope.delta).unwrap(), 7);
+        let moved = envelope
+            .shifts
+            .iter()
+            .find(|shift| shift.distance != 0)
+            .unwrap();
+        assert_eq!(moved.distance, 40);
+    }
+
+    #[test]
+    fn test_plain_inputs() {
+        let exe = elf(&program(&[480; 10]));
+        let options = EncodeOptions::default();
+        for (base, new) in [(&b"plain"[..], &exe[..]), (&exe[..], &b"plain"[..])] {
+            let patch = encode(0, base, new, &options);
+            assert!(!is_wrapped(&patch));
+            assert_eq!(delta::decode(base, &patch).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_wrong_base() {
+        let sizes = vec![480; 100];
+        let mut grown = sizes.clone();
+        grown[3] += 40;
+        let base = elf(&program(&sizes));
+        let patch = encode(0, &base, &elf(&program(&grown)), &EncodeOptions::default());
+        assert!(is_wrapped(&patch));
+
+        assert!(delta::decode(&base[..TEXT_OFFSET], &patch).is_err());
+        assert!(Envelope::parse(&patch[..6]).is_err());
+    }
+
+    #[test]
+    fn test_adjust_arm64() {
+        let bl = |offset: i64| (0x9400_0000 | (offset >> 2) as u32 & 0x03FF_FFFF).to_le_bytes();
+        let mut code = [bl(0x100), bl(-0x100)].concat();
+        // Code from 0x1080 on moved 8 bytes further
+        let shifts = [Shift {
+            address: 0x1080,
+            distance: 8,
+        }];
+        let region = Region {
+            offset: 0,
+            len: code.len(),
+            address: 0x1000,
+        };
+        adjust(&mut code, Arch::Arm64, &[region], &shifts);
+        assert_eq!(code, [bl(0x108), bl(-0x100)].concat());
+    }
+}
@@ -0,0 +1,523 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Structural deltas between JSON documents.
+//!
+//! Exports of the same data often differ less in content than in order: records come out of
+//! a database in a different order, objects list their keys differently, and a single edit
+//! reflows nothing but still moves everything after it. [`diff`] parses both documents and
+//! matches object members by key and array elements by content, by their first member (such
+//! as an `"id"`) or by position. Matched values that are equal become one copy from the base,
+//! matched containers are diffed recursively, and only what has no counterpart is inserted.
+//!
+//! The result is an ordinary instruction stream, so [`encode`] produces a GDelta delta that
+//! [`delta::decode`] applies like any other, and the new document is reproduced byte for byte,
+//! whitespace included. Besides single documents, whitespace-separated sequences of values such
+//! as JSON Lines are understood. Inputs that do not parse are diffed byte by byte.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::delta::{self, EncodeOptions};
+//! use xpatch::formats::json;
+//!
+//! let records: Vec<String> = (0..500)
+//!     .map(|i| format!(r#"{{"id": {i}, "name": "user {i}", "active": {}}}"#, i % 3 == 0))
+//!     .collect();
+//! let base = format!("[{}]", records.join(", "));
+//! let mut reordered = records.clone();
+//! reordered.reverse();
+//! let new = format!("[{}]", reordered.join(", "));
+//!
+//! let delta = json::encode(0, base.as_bytes(), new.as_bytes(), &EncodeOptions::default());
+//! assert_eq!(delta::decode(base.as_bytes(), &delta).unwrap(), new.as_bytes());
+//! ```
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::delta::ops::{self, Op};
+use crate::delta::{self, EncodeOptions};
+
+/// Deepest nesting that is parsed; deeper documents are diffed byte by byte.
+const MAX_DEPTH: usize = 256;
+
+/// Shortest copy that is kept; shorter matches are inserted.
+const MIN_COPY: usize = 32;
+
+/// Whether `data` is a JSON document or a sequence of JSON values that [`diff`] understands.
+pub fn is_json(data: &[u8]) -> bool {
+    parse(data).is_some()
+}
+
+/// Computes instructions that turn the JSON document `base_data` into `new_data`.
+///
+/// Returns `None` if either input is not JSON.
+pub fn diff(base_data: &[u8], new_data: &[u8]) -> Option<Vec<Op>> {
+    let base = parse(base_data)?;
+    let new = parse(new_data)?;
+
+    let mut differ = Differ {
+        base: base_data,
+        new: new_data,
+        ops: Vec::new(),
+    };
+    differ.items(&base, &new, false);
+    let base_tail = base.last().map_or(0, |item| item.value.range.end)..base_data.len();
+    let new_tail = new.last().map_or(0, |item| item.value.range.end)..new_data.len();
+    differ.either(base_tail, new_tail);
+
+    // Short copies, such as single members of a reordered object, compress better as text
+    let ops = ops::optimize(base_data, &differ.ops)
+        .into_iter()
+        .map(|op| match op {
+            Op::Copy { offset, len } if len < MIN_COPY => {
+                Op::Insert(base_data[offset..offset + len].to_vec())
+            }
+            op => op,
+        })
+        .collect::<Vec<_>>();
+    Some(ops::optimize(base_data, &ops))
+}
+
+/// Encodes a delta from `base_data` to `new_data` with a structural diff of the documents.
+///
+/// Returns a plain delta from [`delta::encode_with_options`] if either input is not JSON or
+/// if the plain delta is smaller.
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let plain = delta::encode_with_options(tag, base_data, new_data, options);
+    let Some(ops) = diff(base_data, new_data) else {
+        return plain;
+    };
+    match ops::encode_from_ops(tag, base_data, &ops, options) {
+        Ok(structural) if structural.len() < plain.len() => structural,
+        _ => plain,
+    }
+}
+
+/// A parsed value and the bytes it covers.
+struct Value {
+    range: Range<usize>,
+    kind: Kind,
+}
+
+enum Kind {
+    Scalar,
+    Object(Vec<Item>),
+    Array(Vec<Item>),
+}
+
+/// An object member or array element.
+struct Item {
+    /// Where the item starts, including the separator and whitespace before it
+    start: usize,
+    /// The key of an object member, quoted and as written
+    key: Option<Range<usize>>,
+    value: Value,
+}
+
+impl Item {
+    fn range(&self) -> Range<usize> {
+        self.start..self.value.range.end
+    }
+}
+
+/// Parses a whitespace-separated sequence of values, usually just one.
+fn parse(data: &[u8]) -> Option<Vec<Item>> {
+    let mut parser = Parser {
+        data,
+        pos: 0,
+        depth: 0,
+    };
+    let mut items = Vec::new();
+    loop {
+        let start = parser.pos;
+        parser.whitespace();
+        if parser.pos == data.len() {
+            break;
+        }
+        let value = parser.value()?;
+        items.push(Item {
+            start,
+            key: None,
+            value,
+        });
+    }
+    (!items.is_empty()).then_some(items)
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while matches!(self.data.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        let start = self.pos;
+        let kind = match *self.data.get(self.pos)? {
+            b'{' => Kind::Object(self.items(b'}', true)?),
+            b'[' => Kind::Array(self.items(b']', false)?),
+            b'"' => {
+                self.string()?;
+                Kind::Scalar
+            }
+            b'-' | b'0'..=b'9' => {
+                self.pos += 1;
+                while matches!(
+                    self.data.get(self.pos),
+                    Some(b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-')
+                ) {
+                    self.pos += 1;
+                }
+                Kind::Scalar
+            }
+            _ => {
+                let literal = [&b"true"[..], b"false", b"null"]
+                    .into_iter()
+                    .find(|literal| self.data[self.pos..].starts_with(literal))?;
+                self.pos += literal.len();
+                Kind::Scalar
+            }
+        };
+        Some(Value {
+            range: start..self.pos,
+            kind,
+        })
+    }
+
+    fn string(&mut self) -> Option<()> {
+        if self.data.get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        self.pos += 1;
+        loop {
+            match *self.data.get(self.pos)? {
+                b'"' => break,
+                b'\\' => self.pos += 2,
+                byte if byte < 0x20 => return None,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        Some(())
+    }
+
+    /// Parses the items of an object or array, starting at its opening bracket.
+    fn items(&mut self, close: u8, keyed: bool) -> Option<Vec<Item>> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        self.pos += 1;
+
+        let mut items = Vec::new();
+        let mut start = self.pos;
+        self.whitespace();
+        if self.data.get(self.pos) != Some(&close) {
+            loop {
+                let key = match keyed {
+                    true => {
+                        let key_start = self.pos;
+                        self.string()?;
+                        let key = key_start..self.pos;
+                        self.whitespace();
+                        if self.data.get(self.pos) != Some(&b':') {
+                            return None;
+                        }
+                        self.pos += 1;
+                        self.whitespace();
+                        Some(key)
+                    }
+                    false => None,
+                };
+                let value = self.value()?;
+                items.push(Item { start, key, value });
+
+                start = self.pos;
+                self.whitespace();
+                match *self.data.get(self.pos)? {
+                    b',' => {
+                        self.pos += 1;
+                        self.whitespace();
+                    }
+                    byte if byte == close => break,
+                    _ => return None,
+                }
+            }
+        }
+        self.pos += 1;
+        self.depth -= 1;
+        Some(items)
+    }
+}
+
+struct Differ<'a> {
+    base: &'a [u8],
+    new: &'a [u8],
+    ops: Vec<Op>,
+}
+
+impl Differ<'_> {
+    /// Copies `base` if it holds the same bytes as `new`, and inserts `new` otherwise.
+    fn either(&mut self, base: Range<usize>, new: Range<usize>) {
+        if self.base[base.clone()] == self.new[new.clone()] {
+            self.ops.push(Op::Copy {
+                offset: base.start,
+                len: base.len(),
+            });
+        } else {
+            self.insert(new);
+        }
+    }
+
+    fn insert(&mut self, new: Range<usize>) {
+        self.ops.push(Op::Insert(self.new[new].to_vec()));
+    }
+
+    fn value(&mut self, base: &Value, new: &Value) {
+        let (base_items, new_items, keyed) = match (&base.kind, &new.kind) {
+            (Kind::Object(base_items), Kind::Object(new_items)) => (base_items, new_items, true),
+            (Kind::Array(base_items), Kind::Array(new_items)) => (base_items, new_items, false),
+            _ => return self.either(base.range.clone(), new.range.clone()),
+        };
+        if self.base[base.range.clone()] == self.new[new.range.clone()] {
+            return self.either(base.range.clone(), new.range.clone());
+        }
+
+        self.insert(new.range.start..new.range.start + 1);
+        self.items(base_items, new_items, keyed);
+        let base_tail = base_items
+            .last()
+            .map_or(base.range.start + 1, |item| item.value.range.end);
+        let new_tail = new_items
+            .last()
+            .map_or(new.range.start + 1, |item| item.value.range.end);
+        self.either(base_tail..base.range.end, new_tail..new.range.end);
+    }
+
+    /// Diffs each new item against its counterpart in the base, if it has one.
+    fn items(&mut self, base_items: &[Item], new_items: &[Item], keyed: bool) {
+        let mut by_key: HashMap<&[u8], usize> = HashMap::new();
+        let mut by_content: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        let mut by_identity: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (index, item) in base_items.iter().enumerate().rev() {
+            match &item.key {
+                Some(key) => {
+                    by_key.insert(&self.base[key.clone()], index);
+                }
+                None => {
+                    by_content
+                        .entry(&self.base[item.value.range.clone()])
+                        .or_default()
+                        .push(index);
+                    if let Some(identity) = identity(self.base, &item.value) {
+                        by_identity.entry(identity).or_default().push(index);
+                    }
+                }
+            }
+        }
+
+        let mut used = vec![false; base_items.len()];
+        for (position, item) in new_items.iter().enumerate() {
+            let matched = if keyed {
+                let key = &self.new[item.key.clone().unwrap_or_default()];
+                by_key.get(key).copied()
+            } else {
+                take(
+                    by_content.get_mut(&self.new[item.value.range.clone()]),
+                    &mut used,
+                )
+                .or_else(|| {
+                    let identity = identity(self.new, &item.value)?;
+                    take(by_identity.get_mut(identity), &mut used)
+                })
+                .or_else(|| {
+                    let same_kind = base_items.get(position).is_some_and(|base| {
+                        matches!(
+                            (&base.value.kind, &item.value.kind),
+                            (Kind::Object(_), Kind::Object(_)) | (Kind::Array(_), Kind::Array(_))
+                        )
+                    });
+                    (same_kind && !used[position]).then(|| {
+                        used[position] = true;
+                        position
+                    })
+                })
+            };
+
+            let Some(index) = matched else {
+                self.insert(item.range());
+                continue;
+            };
+            let base = &base_items[index];
+            if self.base[base.range()] == self.new[item.range()] {
+                self.either(base.range(), item.range());
+            } else {
+                self.either(
+                    base.start..base.value.range.start,
+                    item.start..item.value.range.start,
+                );
+                self.value(&base.value, &item.value);
+            }
+        }
+    }
+}
+
+/// Takes the first candidate that is not used yet.
+fn take(candidates: Option<&mut Vec<usize>>, used: &mut [bool]) -> Option<usize> {
+    let candidates = candidates?;
+    while let Some(index) = candidates.pop() {
+        if !used[index] {
+            used[index] = true;
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// The first member of an object, key and value, which usually identifies a record.
+fn identity<'a>(data: &'a [u8], value: &Value) -> Option<&'a [u8]> {
+    let Kind::Object(items) = &value.kind else {
+        return None;
+    };
+    let first = items.first()?;
+    Some(&data[first.key.clone()?.start..first.value.range.end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(ids: impl Iterator<Item = usize>) -> Vec<String> {
+        ids.map(|id| {
+            format!(
+                "{{\n    \"id\": {id},\n    \"name\": \"user {id}\",\n    \"email\": \"user{id}@example.com\"\n  }}"
+            )
+        })
+        .collect()
+    }
+
+    fn array(records: &[String]) -> Vec<u8> {
+        format!("[\n  {}\n]\n", records.join(",\n  ")).into_bytes()
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = br#" {"a": [1, 2.5e3, "x\"y"], "b": {}, "c": null} [true] "#;
+        let items = parse(data).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].range(), 0..data.len() - 8);
+        let Kind::Object(members) = &items[0].value.kind else {
+            panic!("not an object");
+        };
+        let keys: Vec<&[u8]> = members
+            .iter()
+            .map(|member| &data[member.key.clone().unwrap()])
+            .collect();
+        assert_eq!(keys, [&br#""a""#[..], br#""b""#, br#""c""#]);
+        let Kind::Array(elements) = &members[0].value.kind else {
+            panic!("not an array");
+        };
+        assert_eq!(&data[elements[2].range()], br#", "x\"y""#);
+
+        for invalid in [
+            &b""[..],
+            b"{",
+            b"[1,]",
+            b"{1: 2}",
+            b"nul",
+            b"\"a\nb\"",
+            b"[] x",
+        ] {
+            assert!(parse(invalid).is_none(), "{:?}", invalid);
+        }
+        assert!(!is_json(&[b'['; MAX_DEPTH + 1]));
+    }
+
+    #[test]
+    fn test_reordered_records() {
+        let base = records(0..2000);
+        let mut new = base.clone();
+        new.reverse();
+        // An edited record, a removed one and a new one
+        new[10] = new[10].replace("user", "admin");
+        new.remove(20);
+        new.extend(records(5000..5001));
+        let (base, new) = (array(&base), array(&new));
+
+        let options = EncodeOptions::default();
+        let structural = encode(3, &base, &new, &options);
+        let plain = delta::encode_with_options(3, &base, &new, &options);
+        assert!(
+            structural.len() < plain.len() * 2 / 3,
+            "{} vs {}",
+            structural.len(),
+            plain.len()
+        );
+        assert_eq!(delta::decode(&base, &structural).unwrap(), new);
+        assert_eq!(delta::get_tag(&structural).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_json_lines_and_key_order() {
+        let base: String = (0..1000)
+            .map(|id| format!("{{\"id\":{id},\"kind\":\"event\",\"value\":{}}}\n", id * 7))
+            .collect();
+        let new: String = (0..1000)
+            .rev()
+            .map(|id| format!("{{\"value\":{},\"id\":{id},\"kind\":\"event\"}}\n", id * 7))
+            .collect();
+
+        let ops = diff(base.as_bytes(), new.as_bytes()).unwrap();
+        assert_eq!(
+            ops::apply_ops(base.as_bytes(), &ops).unwrap(),
+            new.as_bytes()
+        );
+        let delta = encode(
+            0,
+            base.as_bytes(),
+            new.as_bytes(),
+            &EncodeOptions::default(),
+        );
+        assert_eq!(
+            delta::decode(base.as_bytes(), &delta).unwrap(),
+            new.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_plain_inputs() {
+        let json = array(&records(0..10));
+        let options = EncodeOptions::default();
+        for (base, new) in [(&b"{ not json"[..], &json[..]), (&json[..], &b"plain"[..])] {
+            assert!(diff(base, new).is_none());
+            let delta = encode(0, base, new, &options);
+            assert_eq!(delta, delta::encode_with_options(0, base, new, &options));
+            assert_eq!(delta::decode(base, &delta).unwrap(), new);
+        }
+    }
+}
@@ -0,0 +1,629 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Member-by-member deltas between tar archives.
+//!
+//! [`encode`] splits both archives into their members, matches the members of the new archive
+//! to those of the base by path, and encodes one delta per matched member. A member is a
+//! file's header, any pax or GNU long-name headers before it, and its data padded to whole
+//! blocks, so the deltas together reproduce the new archive exactly, including timestamps,
+//! padding and the end-of-archive blocks. The members without a match, such as renamed or new
+//! files, are diffed together against the whole base archive.
+//!
+//! This suits container layers and release tarballs, where most files are unchanged but shift
+//! around as others grow, shrink, appear or disappear. An archive made up mostly of new files
+//! gains nothing and can come out slightly larger than a plain delta.
+//!
+//! Compressed tarballs are looked into by [`compressed::encode`](crate::compressed::encode)
+//! (feature `compressed`), which diffs their contents with this module.
+//!
+//! # Format
+//!
+//! ```text
+//! 0xFC 0x00 "TR" | version u8 | varint(tag) | varint(member count) | member* | rest
+//! member = varint(base member index + 1) | varint(length) | delta   diffed against that member
+//!        | 0 | varint(member length)                                part of the rest
+//! rest   = varint(length) | delta   from the whole base to the other members and the trailer
+//! ```
+//!
+//! The first two bytes form an invalid delta header, so a tar delta is never mistaken for a
+//! plain one.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::delta::{self, EncodeOptions};
+//! use xpatch::formats::tar;
+//!
+//! # fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+//! #     let mut out = Vec::new();
+//! #     for (path, data) in files {
+//! #         let mut header = [0u8; 512];
+//! #         header[..path.len()].copy_from_slice(path.as_bytes());
+//! #         header[100..107].copy_from_slice(b"0000644");
+//! #         header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+//! #         header[156] = b'0';
+//! #         header[257..263].copy_from_slice(b"ustar\0");
+//! #         header[148..156].fill(b' ');
+//! #         let sum: u32 = header.iter().map(|&b| b as u32).sum();
+//! #         header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+//! #         out.extend_from_slice(&header);
+//! #         out.extend_from_slice(data);
+//! #         out.resize(out.len().div_ceil(512) * 512, 0);
+//! #     }
+//! #     out.resize(out.len() + 1024, 0);
+//! #     out
+//! # }
+//! let base = archive(&[("README", b"Version 1"), ("LICENSE", b"MIT")]);
+//! let new = archive(&[("LICENSE", b"MIT"), ("CHANGELOG", b"Fixed it"), ("README", b"Version 2")]);
+//!
+//! let patch = tar::encode(0, &base, &new, &EncodeOptions::default());
+//! assert!(tar::is_patch(&patch));
+//! assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+//! ```
+
+#[cfg(feature = "decode")]
+use super::read_delta;
+#[cfg(feature = "encode")]
+use super::{relative_paths, write_delta};
+#[cfg(feature = "decode")]
+use crate::delta::read_header_varint;
+#[cfg(feature = "encode")]
+use crate::delta::{self, EncodeOptions};
+#[cfg(feature = "encode")]
+use crate::varint::encode_varint;
+#[cfg(feature = "encode")]
+use std::collections::HashMap;
+use std::ops::Range;
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'T', b'R'];
+const VERSION: u8 = 1;
+
+/// Size of a tar header and the unit data is padded to.
+const BLOCK: usize = 512;
+
+/// A member of a tar archive, as found by [`members`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Path of the member, taken from its pax or GNU long-name header if it has one
+    pub path: Vec<u8>,
+    /// Where the member's headers and padded data are in the archive
+    pub range: Range<usize>,
+}
+
+/// Splits a tar archive into its members.
+///
+/// Parsing stops at the end-of-archive blocks or at the first block that is not a valid
+/// header; those blocks and anything after them belong to no member. Returns `None` if `data`
+/// does not start with a valid header, such as for data that is not a tar archive.
+pub fn members(data: &[u8]) -> Option<Vec<Member>> {
+    let mut members = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    let mut long_name = None;
+    let mut pax = Pax::default();
+
+    'headers: while let Some(header) = data.get(pos..pos + BLOCK) {
+        if !valid_checksum(header) {
+            break;
+        }
+        let kind = header[156];
+        let extension = matches!(kind, b'x' | b'g' | b'L' | b'K');
+        let Some(mut size) = parse_number(&header[124..136]) else {
+            break;
+        };
+        if !extension && let Some(pax_size) = pax.size.take() {
+            size = pax_size;
+        }
+        let Ok(size) = usize::try_from(size) else {
+            break;
+        };
+
+        // Old GNU sparse files continue their map in extra header blocks
+        let mut data_start = pos + BLOCK;
+        let mut extended = kind == b'S' && header[482] != 0;
+        while extended {
+            let Some(block) = data.get(data_start..data_start + BLOCK) else {
+                break 'headers;
+            };
+            extended = block[504] != 0;
+            data_start += BLOCK;
+        }
+
+        let Some(end) = size
+            .checked_next_multiple_of(BLOCK)
+            .and_then(|padded| data_start.checked_add(padded))
+            .filter(|&end| end <= data.len())
+        else {
+            break;
+        };
+        let body = &data[data_start..data_start + size];
+        match kind {
+            b'L' => long_name = Some(until_nul(body).to_vec()),
+            b'x' => pax = Pax::parse(body),
+            b'g' | b'K' => {}
+            _ => {
+                let path = match (pax.path.take(), long_name.take()) {
+                    (Some(path), _) | (None, Some(path)) => path,
+                    (None, None) => ustar_path(header),
+                };
+                members.push(Member {
+                    path,
+                    range: start..end,
+                });
+                pax = Pax::default();
+                start = end;
+            }
+        }
+        pos = end;
+    }
+
+    (!members.is_empty()).then_some(members)
+}
+
+/// Encodes a member-by-member delta from the tar archive `base_data` to `new_data`.
+///
+/// Members are matched by path, relative to the archive's top-level directory if all of its
+/// members are in one, so that `project-1.0/src/main.rs` matches `project-1.1/src/main.rs`.
+/// Returns a plain delta from [`delta::encode_with_options`] if either input is not a tar
+/// archive. Every delta inside is encoded with `options`; `tag` is stored once for the whole
+/// delta.
+#[cfg(feature = "encode")]
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let (Some(base), Some(new)) = (members(base_data), members(new_data)) else {
+        return delta::encode_with_options(tag, base_data, new_data, options);
+    };
+
+    // With duplicate paths, as left by `tar --append`, the last member wins like on extraction
+    let by_path: HashMap<&[u8], usize> =
+        relative_paths(base.iter().map(|member| member.path.as_slice()))
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| (path, i))
+            .collect();
+    let matches: Vec<Option<usize>> =
+        relative_paths(new.iter().map(|member| member.path.as_slice()))
+            .into_iter()
+            .map(|path| by_path.get(path).copied())
+            .collect();
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend(encode_varint(tag));
+    out.extend(encode_varint(new.len()));
+    let mut rest = Vec::new();
+    for (member, matched) in new.iter().zip(&matches) {
+        let new_member = &new_data[member.range.clone()];
+        match *matched {
+            Some(i) => {
+                out.extend(encode_varint(i + 1));
+                write_delta(
+                    &mut out,
+                    &delta::encode_with_options(
+                        0,
+                        &base_data[base[i].range.clone()],
+                        new_member,
+                        options,
+                    ),
+                );
+            }
+            None => {
+                out.push(0);
+                out.extend(encode_varint(new_member.len()));
+                rest.extend_from_slice(new_member);
+            }
+        }
+    }
+    rest.extend_from_slice(&new_data[trailer_start(&new)..]);
+    write_delta(
+        &mut out,
+        &delta::encode_with_options(0, base_data, &rest, options),
+    );
+    out
+}
+
+/// Whether `data` was produced by [`encode`] from two tar archives.
+#[cfg(feature = "decode")]
+pub fn is_patch(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// A delta produced by [`encode`], split into its parts.
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch<'a> {
+    /// The tag passed to [`encode`]
+    pub tag: usize,
+    /// The members of the new archive, in order
+    pub members: Vec<PatchMember<'a>>,
+    /// The delta from the whole base archive to the new members in [`PatchMember::Rest`],
+    /// followed by the new archive's trailing blocks
+    pub rest: &'a [u8],
+}
+
+/// A member of the new archive in a [`Patch`].
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchMember<'a> {
+    /// Diffed against a base member
+    Diffed {
+        /// Index of the base member, as returned by [`members`]
+        base: usize,
+        /// The delta from that member
+        delta: &'a [u8],
+    },
+    /// Decoded as part of [`Patch::rest`]
+    Rest {
+        /// Length of the member
+        len: usize,
+    },
+}
+
+#[cfg(feature = "decode")]
+impl<'a> Patch<'a> {
+    /// Splits a delta produced by [`encode`] into its parts.
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        const INVALID: &str = "Invalid tar delta";
+
+        if !is_patch(data) {
+            return Err(INVALID);
+        }
+        match data.get(MAGIC.len()) {
+            Some(&VERSION) => {}
+            Some(_) => return Err("Unsupported tar delta version"),
+            None => return Err(INVALID),
+        }
+        let mut pos = MAGIC.len() + 1;
+        let tag = read_header_varint(data, &mut pos)?;
+        let count = read_header_varint(data, &mut pos)?;
+
+        // Every member takes at least two bytes, which bounds the allocation
+        let mut members = Vec::with_capacity(count.min(data.len() / 2));
+        for _ in 0..count {
+            let member = match read_header_varint(data, &mut pos)?.checked_sub(1) {
+                Some(base) => PatchMember::Diffed {
+                    base,
+                    delta: read_delta(data, &mut pos)?,
+                },
+                None => PatchMember::Rest {
+                    len: read_header_varint(data, &mut pos)?,
+                },
+            };
+            members.push(member);
+        }
+        let rest = read_delta(data, &mut pos)?;
+        if pos != data.len() {
+            return Err(INVALID);
+        }
+        Ok(Self { tag, members, rest })
+    }
+
+    /// Rebuilds the new archive from `base_data`, decoding each delta with `decode`.
+    pub(crate) fn apply(
+        &self,
+        base_data: &[u8],
+        mut decode: impl FnMut(&[u8], &[u8]) -> Result<Vec<u8>, &'static str>,
+    ) -> Result<Vec<u8>, &'static str> {
+        const MISMATCH: &str = "Base data does not match the tar delta";
+
+        let base = members(base_data).ok_or("Base data is not a tar archive")?;
+        let rest = decode(base_data, self.rest)?;
+
+        let mut rest = rest.as_slice();
+        let mut out = Vec::with_capacity(base_data.len());
+        for member in &self.members {
+            match *member {
+                PatchMember::Diffed { base: i, delta } => {
+                    let range = &base.get(i).ok_or(MISMATCH)?.range;
+                    out.extend(decode(&base_data[range.clone()], delta)?);
+                }
+                PatchMember::Rest { len } => {
+                    let (member, tail) = rest.split_at_checked(len).ok_or(MISMATCH)?;
+                    out.extend_from_slice(member);
+                    rest = tail;
+                }
+            }
+        }
+        out.extend_from_slice(rest);
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "encode")]
+fn trailer_start(members: &[Member]) -> usize {
+    members.last().map_or(0, |member| member.range.end)
+}
+
+/// The fields of a pax extended header that affect how members are split and matched.
+#[derive(Default)]
+struct Pax {
+    path: Option<Vec<u8>>,
+    size: Option<u64>,
+}
+
+impl Pax {
+    /// Parses `"<length> <key>=<value>\n"` records, stopping at the first malformed one.
+    fn parse(mut body: &[u8]) -> Self {
+        let mut pax = Self::default();
+        while let Some(space) = body.iter().position(|&b| b == b' ') {
+            let Some(len) = std::str::from_utf8(&body[..space])
+                .ok()
+                .and_then(|len| len.parse::<usize>().ok())
+                .filter(|&len| len > space && len <= body.len())
+            else {
+                break;
+            };
+            let record = &body[space + 1..len];
+            let record = record.strip_suffix(b"\n").unwrap_or(record);
+            if let Some(eq) = record.iter().position(|&b| b == b'=') {
+                let value = &record[eq + 1..];
+                match &record[..eq] {
+                    b"path" => pax.path = Some(value.to_vec()),
+                    b"size" => {
+                        pax.size = std::str::from_utf8(value)
+                            .ok()
+                            .and_then(|size| size.parse().ok())
+                    }
+                    _ => {}
+                }
+            }
+            body = &body[len..];
+        }
+        pax
+    }
+}
+
+/// Joins the ustar prefix and name fields.
+fn ustar_path(header: &[u8]) -> Vec<u8> {
+    let name = until_nul(&header[..100]);
+    // GNU tar uses the prefix field for other data and marks its headers "ustar  \0"
+    let prefix = match &header[257..263] {
+        b"ustar\0" => until_nul(&header[345..500]),
+        _ => &[],
+    };
+    if prefix.is_empty() {
+        return name.to_vec();
+    }
+    [prefix, b"/", name].concat()
+}
+
+fn until_nul(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// Parses a numeric header field: octal digits padded with spaces and NULs, or GNU's base-256
+/// encoding for values that do not fit.
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        if field[0] == 0xFF {
+            return None;
+        }
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7F), |value, &b| {
+                value.checked_mul(256)?.checked_add(u64::from(b))
+            });
+    }
+    let field = &field[field.iter().position(|&b| b != b' ')?..];
+    let len = field
+        .iter()
+        .take_while(|&&b| matches!(b, b'0'..=b'7'))
+        .count();
+    if len == 0 || field[len..].iter().any(|&b| b != b' ' && b != 0) {
+        return None;
+    }
+    field[..len].iter().try_fold(0u64, |value, &b| {
+        value.checked_mul(8)?.checked_add(u64::from(b - b'0'))
+    })
+}
+
+/// Checks the header checksum, which some old implementations computed over signed bytes.
+fn valid_checksum(header: &[u8]) -> bool {
+    let Some(stored) = parse_number(&header[148..156]) else {
+        return false;
+    };
+    let byte = |(i, &b): (usize, &u8)| if (148..156).contains(&i) { b' ' } else { b };
+    let unsigned: u64 = header.iter().enumerate().map(|b| u64::from(byte(b))).sum();
+    let signed: i64 = header
+        .iter()
+        .enumerate()
+        .map(|b| i64::from(byte(b) as i8))
+        .sum();
+    stored == unsigned || i64::try_from(stored) == Ok(signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(path: &[u8], kind: u8, size: usize) -> [u8; BLOCK] {
+        let mut header = [0u8; BLOCK];
+        let name = &path[..path.len().min(100)];
+        header[..name.len()].copy_from_slice(name);
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header
+    }
+
+    fn append(out: &mut Vec<u8>, path: &[u8], kind: u8, data: &[u8]) {
+        out.extend_from_slice(&header(path, kind, data.len()));
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(BLOCK), 0);
+    }
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (path, data) in files {
+            append(&mut out, path.as_bytes(), b'0', data);
+        }
+        out.resize(out.len() + 2 * BLOCK, 0);
+        out
+    }
+
+    fn sample(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b'a' + (state % 26) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_members() {
+        let long = format!("{}/file.txt", "d".repeat(120));
+        // The length includes its own three digits
+        let pax_record = format!("{} path={}\n", long.len() + 10, long);
+
+        let mut data = Vec::new();
+        append(&mut data, b"dir/", b'5', b"");
+        append(&mut data, b"dir/plain.txt", b'0', b"plain");
+        append(&mut data, b"PaxHeaders/x", b'x', pax_record.as_bytes());
+        append(&mut data, long.as_bytes(), b'0', b"pax");
+        append(
+            &mut data,
+            b"././@LongLink",
+            b'L',
+            format!("{long}\0").as_bytes(),
+        );
+        append(&mut data, long.as_bytes(), b'0', b"gnu");
+        let mut prefixed = header(b"name.txt", b'0', 0);
+        prefixed[345..351].copy_from_slice(b"prefix");
+        prefixed[148..156].fill(b' ');
+        let sum: u32 = prefixed.iter().map(|&b| b as u32).sum();
+        prefixed[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        data.extend_from_slice(&prefixed);
+        let end = data.len();
+        data.resize(end + 2 * BLOCK, 0);
+
+        let found = members(&data).unwrap();
+        let paths: Vec<&[u8]> = found.iter().map(|m| m.path.as_slice()).collect();
+        assert_eq!(
+            paths,
+            [
+                &b"dir/"[..],
+                b"dir/plain.txt",
+                long.as_bytes(),
+                long.as_bytes(),
+                b"prefix/name.txt"
+            ]
+        );
+        assert_eq!(found[0].range.start, 0);
+        for pair in found.windows(2) {
+            assert_eq!(pair[0].range.end, pair[1].range.start);
+        }
+        assert_eq!(found[4].range.end, end);
+
+        assert_eq!(members(b"not a tar archive"), None);
+        assert_eq!(members(&[0; 2 * BLOCK]), None);
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(b"0000644\0"), Some(0o644));
+        assert_eq!(parse_number(b"   644 \0"), Some(0o644));
+        assert_eq!(
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]),
+            Some(256)
+        );
+        assert_eq!(parse_number(b"0000648\0"), None);
+        assert_eq!(parse_number(b"\0\0\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (readme, a, b, old) = (
+            sample(1, 3000),
+            sample(2, 20_000),
+            sample(3, 8000),
+            sample(4, 5000),
+        );
+        let base = archive(&[
+            ("v1/README", &readme),
+            ("v1/src/a.rs", &a),
+            ("v1/src/b.rs", &b),
+            ("v1/old.rs", &old),
+        ]);
+
+        let mut new_a = a.clone();
+        new_a[10_000..10_010].copy_from_slice(b"0123456789");
+        let mut new = archive(&[
+            ("v2/src/b.rs", &b),
+            ("v2/README", &[&readme[..], b"More docs"].concat()),
+            ("v2/renamed.rs", &old),
+            ("v2/src/a.rs", &new_a),
+        ]);
+        // Archives are often padded to whole records
+        new.resize(new.len().next_multiple_of(10240), 0);
+
+        let options = EncodeOptions::default();
+        let patch = encode(7, &base, &new, &options);
+        assert!(is_patch(&patch));
+        #[cfg(feature = "zstd")]
+        assert!(patch.len() < new.len() / 100, "{} bytes", patch.len());
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+
+        let parsed = Patch::parse(&patch).unwrap();
+        assert_eq!(parsed.tag, 7);
+        let bases: Vec<Option<usize>> = parsed
+            .members
+            .iter()
+            .map(|member| match member {
+                PatchMember::Diffed { base, .. } => Some(*base),
+                PatchMember::Rest { .. } => None,
+            })
+            .collect();
+        assert_eq!(bases, [Some(2), Some(0), None, Some(1)]);
+    }
+
+    #[test]
+    fn test_plain_inputs() {
+        let tar = archive(&[("file", &sample(1, 1000))]);
+        let options = EncodeOptions::default();
+        for (base, new) in [(&b"plain"[..], &tar[..]), (&tar[..], &b"plain"[..])] {
+            let patch = encode(0, base, new, &options);
+            assert!(!is_patch(&patch));
+            assert_eq!(delta::decode(base, &patch).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_wrong_base() {
+        let (a, b) = (sample(1, 1000), sample(2, 1000));
+        let base = archive(&[("a", &a), ("b", &b)]);
+        let new = archive(&[("b", &b), ("a", &a)]);
+        let patch = encode(0, &base, &new, &EncodeOptions::default());
+
+        assert!(delta::decode(b"not a tar archive", &patch).is_err());
+        let short = archive(&[("a", &a)]);
+        assert!(delta::decode(&short, &patch).is_err());
+        assert!(delta::decode(&base, &patch[..patch.len() - 1]).is_err());
+    }
+}
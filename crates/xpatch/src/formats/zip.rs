@@ -0,0 +1,775 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Entry-by-entry deltas between ZIP archives, such as JARs, APKs and Python wheels.
+//!
+//! Every entry of a ZIP archive is compressed on its own, so a one-line change to a file
+//! rewrites all of that entry's deflate stream, and a generic delta cannot see through it.
+//! [`encode`] matches the entries of the new archive to those of the base by name, inflates
+//! deflated entries on both sides and diffs their contents. For each new entry it records the
+//! zlib level that deflates the contents back into the exact original bytes; entries that no
+//! zlib level reproduces are diffed as they are stored. Local headers, data descriptors,
+//! alignment padding, the central directory and anything before the first entry are carried
+//! over byte for byte, so decoding rebuilds the new archive exactly. Entries without a match
+//! are diffed together against the whole base, like in [`tar`](super::tar).
+//!
+//! Archives written by Python's `zipfile`, Java's `jar` and `java.util.zip`, and by Android
+//! build tools use zlib and can be recompressed. Info-ZIP's `zip` has its own deflate
+//! implementation, so many of its entries are diffed as stored. ZIP64 archives are supported;
+//! split and encrypted archives are not, and are diffed as plain data.
+//!
+//! # Format
+//!
+//! ```text
+//! 0xFC 0x00 "ZP" | version u8 | varint(tag) | varint(prefix length) | varint(entry count)
+//!     | entry* | rest
+//! entry  = varint(base entry index + 1) | recipe | varint(length) | delta   diffed against it
+//!        | 0 | recipe | varint(expanded length)                            part of the rest
+//! recipe = 0                                         stored as is
+//!        | level u8 | varint(inflated length)        deflated again at zlib level 1-9
+//! rest   = varint(length) | delta   from the expanded base to the prefix, the other entries
+//!                                   and the central directory
+//! ```
+//!
+//! An expanded entry is its local header, its data and its data descriptor. The data of a new
+//! entry is inflated if its recipe says so, and the base entry it is diffed against is then
+//! inflated as well; otherwise both are taken as stored. In the expanded base, every deflated
+//! entry is inflated. The first two bytes form an invalid delta header, so a ZIP delta is
+//! never mistaken for a plain one.
+
+use super::{read_delta, relative_paths, write_delta};
+use crate::compressed::{deflate, deflate_level, inflate};
+use crate::delta::read_header_varint;
+use crate::delta::{self, EncodeOptions};
+use crate::varint::encode_varint;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+const MAGIC: [u8; 4] = [0xFC, 0x00, b'Z', b'P'];
+const VERSION: u8 = 1;
+
+const LOCAL_HEADER: [u8; 4] = *b"PK\x03\x04";
+const CENTRAL_HEADER: [u8; 4] = *b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: [u8; 4] = *b"PK\x05\x06";
+const ZIP64_END_OF_CENTRAL_DIRECTORY: [u8; 4] = *b"PK\x06\x06";
+const ZIP64_LOCATOR: [u8; 4] = *b"PK\x06\x07";
+const ZIP64_EXTRA: u16 = 0x0001;
+
+const DEFLATE: u16 = 8;
+const ENCRYPTED: u16 = 0x0001;
+
+/// An entry of a ZIP archive, as found by [`entries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Name of the entry, as stored in the central directory
+    pub name: Vec<u8>,
+    /// Compression method; 0 is stored and 8 is deflate
+    pub method: u16,
+    /// Where the local header, data and data descriptor are in the archive
+    pub range: Range<usize>,
+    /// Where the compressed data is in the archive
+    pub data: Range<usize>,
+}
+
+/// Lists the entries of a ZIP archive in the order they are stored.
+///
+/// Each entry's range reaches up to the next entry, or up to the central directory for the
+/// last one. Returns `None` if `data` is not a ZIP archive with at least one entry, or if it
+/// is split into several files.
+pub fn entries(data: &[u8]) -> Option<Vec<Entry>> {
+    let directory = central_directory(data)?;
+    let mut entries = Vec::with_capacity(directory.entries.min(data.len() / 46));
+    let mut pos = directory.offset;
+    for _ in 0..directory.entries {
+        let header = data.get(pos..pos.checked_add(46)?)?;
+        if header[..4] != CENTRAL_HEADER || u16_at(header, 34) != 0 {
+            return None;
+        }
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        let name = data.get(pos + 46..pos + 46 + name_len)?.to_vec();
+        let extra = data.get(pos + 46 + name_len..pos + 46 + name_len + extra_len)?;
+
+        // ZIP64 extra fields hold the values whose 32-bit fields are saturated, in this order
+        let mut zip64 = zip64_extra(extra).unwrap_or_default().chunks_exact(8);
+        let mut field = |offset| match u32_at(header, offset) {
+            u32::MAX => zip64
+                .next()
+                .map(|value| u64::from_le_bytes(value.try_into().unwrap())),
+            value => Some(u64::from(value)),
+        };
+        let _uncompressed_size = field(24)?;
+        let compressed_size = usize::try_from(field(20)?).ok()?;
+        let offset = usize::try_from(field(42)?).ok()?;
+
+        let local = data.get(offset..offset.checked_add(30)?)?;
+        if local[..4] != LOCAL_HEADER || u16_at(local, 6) & ENCRYPTED != 0 {
+            return None;
+        }
+        let data_start = offset + 30 + u16_at(local, 26) as usize + u16_at(local, 28) as usize;
+        let data_end = data_start.checked_add(compressed_size)?;
+        if data_end > directory.offset {
+            return None;
+        }
+        entries.push(Entry {
+            name,
+            method: u16_at(header, 10),
+            range: offset..data_end,
+            data: data_start..data_end,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+
+    // Extend every entry up to the next one to take in data descriptors and padding
+    entries.sort_by_key(|entry| entry.range.start);
+    let ends: Vec<usize> = entries
+        .iter()
+        .skip(1)
+        .map(|entry| entry.range.start)
+        .chain([directory.offset])
+        .collect();
+    for (entry, end) in entries.iter_mut().zip(ends) {
+        if end < entry.range.end {
+            return None;
+        }
+        entry.range.end = end;
+    }
+    (!entries.is_empty()).then_some(entries)
+}
+
+/// Whether `data` is a ZIP archive that [`entries`] can split.
+pub fn is_zip(data: &[u8]) -> bool {
+    entries(data).is_some()
+}
+
+/// Encodes an entry-by-entry delta from the ZIP archive `base_data` to `new_data`.
+///
+/// Entries are matched by name, relative to the archive's top-level directory if all of its
+/// entries are in one. Returns a plain delta from [`delta::encode_with_options`] if either
+/// input is not a ZIP archive. Every delta inside is encoded with `options`; `tag` is stored
+/// once for the whole delta.
+pub fn encode(tag: usize, base_data: &[u8], new_data: &[u8], options: &EncodeOptions) -> Vec<u8> {
+    let (Some(base), Some(new)) = (entries(base_data), entries(new_data)) else {
+        return delta::encode_with_options(tag, base_data, new_data, options);
+    };
+    let expanded_base: Vec<Cow<[u8]>> = base
+        .iter()
+        .map(|entry| expand(base_data, entry, inflate(&base_data[entry.data.clone()])))
+        .collect();
+
+    // With duplicate names the last entry wins, like on extraction
+    let by_name: HashMap<&[u8], usize> =
+        relative_paths(base.iter().map(|entry| entry.name.as_slice()))
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name, i))
+            .collect();
+    let matches = relative_paths(new.iter().map(|entry| entry.name.as_slice()))
+        .into_iter()
+        .map(|name| by_name.get(name).copied());
+
+    let prefix_len = new[0].range.start;
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend(encode_varint(tag));
+    out.extend(encode_varint(prefix_len));
+    out.extend(encode_varint(new.len()));
+    let mut rest = new_data[..prefix_len].to_vec();
+    for (entry, matched) in new.iter().zip(matches) {
+        let (recipe, expanded) = recompressible(new_data, entry);
+        out.extend(encode_varint(matched.map_or(0, |i| i + 1)));
+        match recipe {
+            Some(Deflated { level, len }) => {
+                out.push(level as u8);
+                out.extend(encode_varint(len));
+            }
+            None => out.push(0),
+        }
+        match matched {
+            Some(i) => {
+                let base_entry = match recipe {
+                    Some(_) => &expanded_base[i],
+                    None => &base_data[base[i].range.clone()],
+                };
+                write_delta(
+                    &mut out,
+                    &delta::encode_with_options(0, base_entry, &expanded, options),
+                );
+            }
+            None => {
+                out.extend(encode_varint(expanded.len()));
+                rest.extend_from_slice(&expanded);
+            }
+        }
+    }
+    rest.extend_from_slice(&new_data[new[new.len() - 1].range.end..]);
+    write_delta(
+        &mut out,
+        &delta::encode_with_options(
+            0,
+            &whole_base(base_data, &base, &expanded_base),
+            &rest,
+            options,
+        ),
+    );
+    out
+}
+
+/// Whether `data` was produced by [`encode`] from two ZIP archives.
+pub fn is_patch(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// A delta produced by [`encode`], split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch<'a> {
+    /// The tag passed to [`encode`]
+    pub tag: usize,
+    /// Length of the data before the first entry, such as a self-extractor
+    pub prefix_len: usize,
+    /// The entries of the new archive, in order
+    pub entries: Vec<PatchEntry<'a>>,
+    /// The delta from the expanded base archive to the prefix, the expanded entries in
+    /// [`Source::Rest`] and everything after the last entry
+    pub rest: &'a [u8],
+}
+
+/// An entry of the new archive in a [`Patch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchEntry<'a> {
+    /// How the entry's data is compressed again, or `None` if it is kept as stored
+    pub deflated: Option<Deflated>,
+    /// Where the expanded entry comes from
+    pub source: Source<'a>,
+}
+
+/// How to deflate an entry's data back into its original bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deflated {
+    /// zlib compression level, 1-9
+    pub level: u32,
+    /// Length of the inflated data
+    pub len: usize,
+}
+
+/// Where an expanded entry of a [`Patch`] comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source<'a> {
+    /// Diffed against a base entry
+    Diffed {
+        /// Index of the base entry, as returned by [`entries`]
+        base: usize,
+        /// The delta from that expanded entry
+        delta: &'a [u8],
+    },
+    /// Decoded as part of [`Patch::rest`]
+    Rest {
+        /// Length of the expanded entry
+        len: usize,
+    },
+}
+
+impl<'a> Patch<'a> {
+    /// Splits a delta produced by [`encode`] into its parts.
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        const INVALID: &str = "Invalid ZIP delta";
+
+        if !is_patch(data) {
+            return Err(INVALID);
+        }
+        match data.get(MAGIC.len()) {
+            Some(&VERSION) => {}
+            Some(_) => return Err("Unsupported ZIP delta version"),
+            None => return Err(INVALID),
+        }
+        let mut pos = MAGIC.len() + 1;
+        let tag = read_header_varint(data, &mut pos)?;
+        let prefix_len = read_header_varint(data, &mut pos)?;
+        let count = read_header_varint(data, &mut pos)?;
+
+        // Every entry takes at least three bytes, which bounds the allocation
+        let mut entries = Vec::with_capacity(count.min(data.len() / 3));
+        for _ in 0..count {
+            let base = read_header_varint(data, &mut pos)?.checked_sub(1);
+            let level = *data.get(pos).ok_or(INVALID)?;
+            pos += 1;
+            let deflated = match level {
+                0 => None,
+                1..=9 => Some(Deflated {
+                    level: level as u32,
+                    len: read_header_varint(data, &mut pos)?,
+                }),
+                _ => return Err(INVALID),
+            };
+            let source = match base {
+                Some(base) => Source::Diffed {
+                    base,
+                    delta: read_delta(data, &mut pos)?,
+                },
+                None => Source::Rest {
+                    len: read_header_varint(data, &mut pos)?,
+                },
+            };
+            entries.push(PatchEntry { deflated, source });
+        }
+        let rest = read_delta(data, &mut pos)?;
+        if pos != data.len() {
+            return Err(INVALID);
+        }
+        Ok(Self {
+            tag,
+            prefix_len,
+            entries,
+            rest,
+        })
+    }
+
+    /// Rebuilds the new archive from `base_data`, decoding each delta with `decode`.
+    pub(crate) fn apply(
+        &self,
+        base_data: &[u8],
+        mut decode: impl FnMut(&[u8], &[u8]) -> Result<Vec<u8>, &'static str>,
+    ) -> Result<Vec<u8>, &'static str> {
+        const MISMATCH: &str = "Base data does not match the ZIP delta";
+
+        let base = entries(base_data).ok_or("Base data is not a ZIP archive")?;
+        let expanded_base: Vec<Cow<[u8]>> = base
+            .iter()
+            .map(|entry| expand(base_data, entry, inflate(&base_data[entry.data.clone()])))
+            .collect();
+        let rest = decode(&whole_base(base_data, &base, &expanded_base), self.rest)?;
+
+        let (prefix, mut rest) = rest.split_at_checked(self.prefix_len).ok_or(MISMATCH)?;
+        let mut out = Vec::with_capacity(base_data.len());
+        out.extend_from_slice(prefix);
+        for entry in &self.entries {
+            let expanded = match entry.source {
+                Source::Diffed { base: i, delta } => {
+                    let base_entry = match entry.deflated {
+                        Some(_) => expanded_base.get(i).ok_or(MISMATCH)?.as_ref(),
+                        None => &base_data[base.get(i).ok_or(MISMATCH)?.range.clone()],
+                    };
+                    Cow::Owned(decode(base_entry, delta)?)
+                }
+                Source::Rest { len } => {
+                    let (expanded, tail) = rest.split_at_checked(len).ok_or(MISMATCH)?;
+                    rest = tail;
+                    Cow::Borrowed(expanded)
+                }
+            };
+            match entry.deflated {
+                Some(Deflated { level, len }) => {
+                    let start = local_header_len(&expanded).ok_or(MISMATCH)?;
+                    let end = start.checked_add(len).ok_or(MISMATCH)?;
+                    let contents = expanded.get(start..end).ok_or(MISMATCH)?;
+                    out.extend_from_slice(&expanded[..start]);
+                    out = deflate(contents, level, out);
+                    out.extend_from_slice(&expanded[end..]);
+                }
+                None => out.extend_from_slice(&expanded),
+            }
+        }
+        out.extend_from_slice(rest);
+        Ok(out)
+    }
+}
+
+/// The entry with its data replaced by `contents`, if it is deflated and they are given.
+fn expand<'a>(data: &'a [u8], entry: &Entry, contents: Option<Vec<u8>>) -> Cow<'a, [u8]> {
+    match contents {
+        Some(contents) if entry.method == DEFLATE => Cow::Owned(
+            [
+                &data[entry.range.start..entry.data.start],
+                &contents,
+                &data[entry.data.end..entry.range.end],
+            ]
+            .concat(),
+        ),
+        _ => Cow::Borrowed(&data[entry.range.clone()]),
+    }
+}
+
+/// The expanded new entry and how to deflate it back, if zlib reproduces its data.
+fn recompressible<'a>(data: &'a [u8], entry: &Entry) -> (Option<Deflated>, Cow<'a, [u8]>) {
+    let stream = &data[entry.data.clone()];
+    if entry.method != DEFLATE {
+        return (None, expand(data, entry, None));
+    }
+    let Some(contents) = inflate(stream) else {
+        return (None, expand(data, entry, None));
+    };
+    // Flag bits 1 and 2 hint at the level: normal, maximum, fast or super fast
+    let hint = match u16_at(data, entry.range.start + 6) >> 1 & 3 {
+        0 => 6,
+        1 => 9,
+        _ => 1,
+    };
+    match deflate_level(stream, &contents, hint) {
+        Some(level) => {
+            let len = contents.len();
+            (
+                Some(Deflated { level, len }),
+                expand(data, entry, Some(contents)),
+            )
+        }
+        None => (None, expand(data, entry, None)),
+    }
+}
+
+/// The base archive with its deflated entries expanded, which unmatched entries are diffed
+/// against.
+fn whole_base(data: &[u8], entries: &[Entry], expanded: &[Cow<[u8]>]) -> Vec<u8> {
+    let mut whole = data[..entries[0].range.start].to_vec();
+    for entry in expanded {
+        whole.extend_from_slice(entry);
+    }
+    whole.extend_from_slice(&data[entries[entries.len() - 1].range.end..]);
+    whole
+}
+
+fn local_header_len(entry: &[u8]) -> Option<usize> {
+    let header = entry.get(..30)?;
+    if header[..4] != LOCAL_HEADER {
+        return None;
+    }
+    let len = 30 + u16_at(header, 26) as usize + u16_at(header, 28) as usize;
+    (len <= entry.len()).then_some(len)
+}
+
+/// Location of the central directory, from the (ZIP64) end of central directory record.
+struct CentralDirectory {
+    offset: usize,
+    entries: usize,
+}
+
+fn central_directory(data: &[u8]) -> Option<CentralDirectory> {
+    // The record is 22 bytes followed by a comment of up to 64 KiB
+    let search_start = data.len().checked_sub(22)?;
+    let end = (search_start.saturating_sub(u16::MAX as usize)..=search_start)
+        .rev()
+        .find(|&pos| {
+            data[pos..pos + 4] == END_OF_CENTRAL_DIRECTORY
+                && pos + 22 + u16_at(data, pos + 20) as usize == data.len()
+        })?;
+    let record = &data[end..end + 22];
+    // Archives split over several disks are not supported
+    if u16_at(record, 4) != 0 || u16_at(record, 6) != 0 {
+        return None;
+    }
+
+    let entries = u16_at(record, 10);
+    let offset = u32_at(record, 16);
+    let (entries, offset) = if entries == u16::MAX || offset == u32::MAX {
+        let locator = data.get(end.checked_sub(20)?..end)?;
+        if locator[..4] != ZIP64_LOCATOR {
+            return None;
+        }
+        let record_offset = usize::try_from(u64_at(locator, 8)).ok()?;
+        let record = data.get(record_offset..record_offset.checked_add(56)?)?;
+        if record[..4] != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            return None;
+        }
+        (u64_at(record, 32), u64_at(record, 48))
+    } else {
+        (u64::from(entries), u64::from(offset))
+    };
+    let offset = usize::try_from(offset)
+        .ok()
+        .filter(|&offset| offset <= end)?;
+    Some(CentralDirectory {
+        offset,
+        entries: usize::try_from(entries).ok()?,
+    })
+}
+
+/// The data of the ZIP64 extended information field.
+fn zip64_extra(mut extra: &[u8]) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let len = u16_at(extra, 2) as usize;
+        let field = extra.get(4..4 + len)?;
+        if u16_at(extra, 0) == ZIP64_EXTRA {
+            return Some(field);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    enum Method {
+        Stored,
+        Deflated(u32),
+        /// A deflate stream with a sync flush in the middle, which zlib does not reproduce
+        Flushed,
+    }
+
+    fn archive(prefix: &[u8], files: &[(&str, &[u8], Method)], zip64: bool) -> Vec<u8> {
+        let mut out = prefix.to_vec();
+        let mut central = Vec::new();
+        for (name, data, method) in files {
+            let (flags, method, stored) = match method {
+                Method::Stored => (0u16, 0u16, data.to_vec()),
+                Method::Deflated(level) => {
+                    let flags = match level {
+                        9 => 2,
+                        1 => 4,
+                        _ => 0,
+                    };
+                    (flags, DEFLATE, deflate(data, *level, Vec::new()))
+                }
+                Method::Flushed => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    );
+                    encoder.write_all(&data[..data.len() / 2]).unwrap();
+                    encoder.flush().unwrap();
+                    encoder.write_all(&data[data.len() / 2..]).unwrap();
+                    (0, DEFLATE, encoder.finish().unwrap())
+                }
+            };
+            let offset = out.len();
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&20u16.to_le_bytes());
+            fields.extend_from_slice(&flags.to_le_bytes());
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);
+            fields.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+            fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+            out.extend_from_slice(&LOCAL_HEADER);
+            out.extend_from_slice(&fields);
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&stored);
+
+            central.extend_from_slice(&CENTRAL_HEADER);
+            central.extend_from_slice(&20u16.to_le_bytes());
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&(if zip64 { 12u16 } else { 0 }).to_le_bytes());
+            central.extend_from_slice(&[0; 10]);
+            let offset_field = if zip64 { u32::MAX } else { offset as u32 };
+            central.extend_from_slice(&offset_field.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+            if zip64 {
+                central.extend_from_slice(&ZIP64_EXTRA.to_le_bytes());
+                central.extend_from_slice(&8u16.to_le_bytes());
+                central.extend_from_slice(&(offset as u64).to_le_bytes());
+            }
+        }
+
+        let offset = out.len();
+        out.extend_from_slice(&central);
+        let mut end = END_OF_CENTRAL_DIRECTORY.to_vec();
+        end.extend_from_slice(&[0; 4]);
+        if zip64 {
+            let record = out.len();
+            out.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY);
+            out.extend_from_slice(&44u64.to_le_bytes());
+            out.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            for value in [files.len(), files.len(), central.len(), offset] {
+                out.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            out.extend_from_slice(&ZIP64_LOCATOR);
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&(record as u64).to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            end.extend_from_slice(&[0xFF; 4]);
+            end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+            end.extend_from_slice(&[0xFF; 4]);
+        } else {
+            end.extend_from_slice(&(files.len() as u16).to_le_bytes());
+            end.extend_from_slice(&(files.len() as u16).to_le_bytes());
+            end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+            end.extend_from_slice(&(offset as u32).to_le_bytes());
+        }
+        end.extend_from_slice(&7u16.to_le_bytes());
+        end.extend_from_slice(b"comment");
+        out.extend_from_slice(&end);
+        out
+    }
+
+    fn text(seed: u64, lines: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        let mut out = Vec::new();
+        for i in 0..lines {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            out.extend(format!("line {i}: value {}\n", state % 100_000).into_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn test_entries() {
+        for zip64 in [false, true] {
+            let data = archive(
+                b"#!/bin/sh\n",
+                &[
+                    ("a.txt", b"stored", Method::Stored),
+                    ("dir/b.txt", &text(1, 100), Method::Deflated(6)),
+                ],
+                zip64,
+            );
+            let found = entries(&data).unwrap();
+            assert_eq!(found.len(), 2);
+            assert_eq!(found[0].name, b"a.txt");
+            assert_eq!(found[0].method, 0);
+            assert_eq!(found[0].range.start, 10);
+            assert_eq!(&data[found[0].data.clone()], b"stored");
+            assert_eq!(found[1].name, b"dir/b.txt");
+            assert_eq!(found[1].method, DEFLATE);
+            assert_eq!(found[0].range.end, found[1].range.start);
+            assert_eq!(inflate(&data[found[1].data.clone()]).unwrap(), text(1, 100));
+        }
+
+        assert_eq!(entries(b"not a zip archive"), None);
+        assert!(!is_zip(&archive(b"", &[], false)));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (a, b, c, d) = (text(1, 2000), text(2, 1000), text(3, 500), text(4, 800));
+        let base = archive(
+            b"",
+            &[
+                ("app/a.txt", &a, Method::Deflated(6)),
+                ("app/b.txt", &b, Method::Deflated(9)),
+                ("app/c.txt", &c, Method::Stored),
+                ("app/old.txt", &d, Method::Deflated(1)),
+            ],
+            false,
+        );
+
+        let mut new_a = a.clone();
+        new_a.splice(5000..5000, b"inserted line\n".iter().copied());
+        let new = archive(
+            b"",
+            &[
+                ("app2/b.txt", &b, Method::Deflated(9)),
+                ("app2/a.txt", &new_a, Method::Deflated(6)),
+                ("app2/c.txt", &c, Method::Stored),
+                ("app2/renamed.txt", &d, Method::Deflated(1)),
+            ],
+            false,
+        );
+
+        let patch = encode(3, &base, &new, &EncodeOptions::default());
+        assert!(is_patch(&patch));
+        assert!(patch.len() < new.len() / 20, "{} bytes", patch.len());
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+        assert_eq!(
+            crate::formats::encode(3, &base, &new, &EncodeOptions::default()),
+            patch
+        );
+
+        let parsed = Patch::parse(&patch).unwrap();
+        assert_eq!(parsed.tag, 3);
+        let summary: Vec<(Option<usize>, Option<u32>)> = parsed
+            .entries
+            .iter()
+            .map(|entry| {
+                let base = match entry.source {
+                    Source::Diffed { base, .. } => Some(base),
+                    Source::Rest { .. } => None,
+                };
+                (base, entry.deflated.map(|deflated| deflated.level))
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some(1), Some(9)),
+                (Some(0), Some(6)),
+                (Some(2), None),
+                (None, Some(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreproducible_entries() {
+        let (a, b) = (text(1, 2000), text(2, 2000));
+        let base = archive(
+            b"prefix",
+            &[("a", &a, Method::Flushed), ("b", &b, Method::Deflated(6))],
+            true,
+        );
+        let new = archive(
+            b"prefix",
+            &[("a", &a, Method::Flushed), ("b", &a, Method::Flushed)],
+            true,
+        );
+
+        let patch = encode(0, &base, &new, &EncodeOptions::default());
+        let parsed = Patch::parse(&patch).unwrap();
+        assert!(parsed.entries.iter().all(|entry| entry.deflated.is_none()));
+        assert_eq!(parsed.prefix_len, 6);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), new);
+    }
+
+    #[test]
+    fn test_truncated_entry() {
+        let zip = archive(b"", &[("a.txt", &text(1, 100), Method::Deflated(6))], false);
+        let entry = &zip[entries(&zip).unwrap()[0].range.clone()];
+        assert_eq!(local_header_len(entry), Some(30 + 5));
+        for len in 0..35 {
+            assert_eq!(local_header_len(&entry[..len]), None);
+        }
+    }
+
+    #[test]
+    fn test_plain_inputs_and_wrong_base() {
+        let zip = archive(b"", &[("a", &text(1, 100), Method::Deflated(6))], false);
+        let options = EncodeOptions::default();
+        for (base, new) in [(&b"plain"[..], &zip[..]), (&zip[..], &b"plain"[..])] {
+            let patch = encode(0, base, new, &options);
+            assert!(!is_patch(&patch));
+            assert_eq!(delta::decode(base, &patch).unwrap(), new);
+        }
+
+        let new = archive(b"", &[("a", &text(2, 100), Method::Deflated(6))], false);
+        let patch = encode(0, &zip, &new, &options);
+        assert!(is_patch(&patch));
+        assert!(delta::decode(b"not a zip archive", &patch).is_err());
+        assert!(delta::decode(&zip, &patch[..patch.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,610 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! gRPC patch service.
+//!
+//! [`GrpcServer`] serves a [`DeltaStore`] over the `xpatch.v1.PatchService` protocol
+//! (`proto/xpatch.proto` in the crate), so other services can fetch patches and upload versions
+//! through a typed API:
+//!
+//! - `GetPatch` returns the patch from a version the client has to a stored version or ref.
+//!   Patches are produced by a shared [`PatchService`], so they are cached and concurrent
+//!   requests for the same patch are encoded once.
+//! - `GetSignature` returns the [`Signature`](delta::Signature) of a stored version, and
+//! - `UploadVersion` stores a version sent in full or as a delta, e.g. one encoded with
+//!   [`delta::encode_from_signature`] against a stored version.
+//!
+//! Errors map to the usual status codes: `NOT_FOUND` for unknown versions and refs,
+//! `INVALID_ARGUMENT` for malformed hashes or deltas, and `RESOURCE_EXHAUSTED` when the patch
+//! service is [busy](ServiceError::Busy). The generated client is in
+//! [`proto::patch_service_client`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use xpatch::grpc::{GrpcOptions, GrpcServer};
+//! use xpatch::store::DeltaStore;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let store = DeltaStore::open("versions")?;
+//! let server = GrpcServer::new(store, GrpcOptions::default());
+//! server.serve("0.0.0.0:50051".parse()?).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::delta;
+use crate::service::{PatchService, ServiceError, ServiceOptions, ServiceStats};
+use crate::store::{ContentHash, DeltaStore, FsStorage, Storage};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tonic::{Request, Response, Status};
+
+use proto::patch_service_server::PatchServiceServer;
+use proto::*;
+
+/// Messages, client and server of the `xpatch.v1` protocol.
+///
+/// The messages mirror `proto/xpatch.proto`; the client and server are generated at build time.
+pub mod proto {
+    /// Request of `GetPatch`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetPatchRequest {
+        /// Version the client has, empty for a patch from empty data
+        #[prost(bytes = "vec", tag = "1")]
+        pub from: Vec<u8>,
+        /// Requested version, empty to use `ref` instead
+        #[prost(bytes = "vec", tag = "2")]
+        pub to: Vec<u8>,
+        /// Ref naming the requested version if `to` is empty, defaults to `latest`
+        #[prost(string, tag = "3")]
+        pub r#ref: String,
+    }
+
+    /// Response of `GetPatch`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetPatchResponse {
+        /// Base of the patch, empty if it applies to empty data
+        #[prost(bytes = "vec", tag = "1")]
+        pub from: Vec<u8>,
+        /// Version the patch produces
+        #[prost(bytes = "vec", tag = "2")]
+        pub to: Vec<u8>,
+        /// The patch, empty if `from` already is the requested version
+        #[prost(bytes = "vec", tag = "3")]
+        pub patch: Vec<u8>,
+        /// Size of the requested version in bytes
+        #[prost(uint64, tag = "4")]
+        pub size: u64,
+    }
+
+    /// Request of `GetSignature`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetSignatureRequest {
+        /// Version to sign, empty to use `ref` instead
+        #[prost(bytes = "vec", tag = "1")]
+        pub hash: Vec<u8>,
+        /// Ref naming the version if `hash` is empty, defaults to `latest`
+        #[prost(string, tag = "2")]
+        pub r#ref: String,
+        /// Block size of the signature, 0 to choose one from the version's size
+        #[prost(uint32, tag = "3")]
+        pub block_size: u32,
+        /// Hash of the signature's blocks, see [`MatchHash`](crate::delta::MatchHash)
+        #[prost(uint32, tag = "4")]
+        pub match_hash: u32,
+    }
+
+    /// Response of `GetSignature`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetSignatureResponse {
+        /// The signed version
+        #[prost(bytes = "vec", tag = "1")]
+        pub hash: Vec<u8>,
+        /// The signature, see [`Signature::to_bytes`](crate::delta::Signature::to_bytes)
+        #[prost(bytes = "vec", tag = "2")]
+        pub signature: Vec<u8>,
+    }
+
+    /// Request of `UploadVersion`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UploadVersionRequest {
+        /// Content of the version, or a delta against `base` if that is set
+        #[prost(bytes = "vec", tag = "1")]
+        pub data: Vec<u8>,
+        /// Stored version `data` is a delta against, empty if `data` is the full content
+        #[prost(bytes = "vec", tag = "2")]
+        pub base: Vec<u8>,
+        /// Ref to point at the new version, empty to set no ref
+        #[prost(string, tag = "3")]
+        pub r#ref: String,
+    }
+
+    /// Response of `UploadVersion`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UploadVersionResponse {
+        /// Hash of the stored version
+        #[prost(bytes = "vec", tag = "1")]
+        pub hash: Vec<u8>,
+        /// Size of the stored version in bytes
+        #[prost(uint64, tag = "2")]
+        pub size: u64,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/xpatch.v1.PatchService.rs"));
+}
+
+/// Default limit for the size of a request or response message.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Settings of a [`GrpcServer`].
+#[derive(Debug, Clone)]
+pub struct GrpcOptions {
+    /// Settings of the patch service producing `GetPatch` responses
+    pub service: ServiceOptions,
+    /// Largest request or response message in bytes; larger ones fail with `OUT_OF_RANGE` or
+    /// `RESOURCE_EXHAUSTED`
+    pub max_message_size: usize,
+    /// Ref used by requests that name neither a hash nor a ref
+    pub default_ref: String,
+}
+
+impl Default for GrpcOptions {
+    fn default() -> Self {
+        Self {
+            service: ServiceOptions {
+                encode: delta::EncodeOptions {
+                    checksum: true,
+                    ..delta::EncodeOptions::default()
+                },
+                ..ServiceOptions::default()
+            },
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            default_ref: "latest".to_string(),
+        }
+    }
+}
+
+/// Serves a [`DeltaStore`] over gRPC, see the [module documentation](self).
+///
+/// Clones share the store and the patch cache.
+pub struct GrpcServer<S: Storage = FsStorage> {
+    inner: Arc<Inner<S>>,
+}
+
+struct Inner<S: Storage> {
+    store: RwLock<DeltaStore<S>>,
+    patches: PatchService<(Option<ContentHash>, ContentHash)>,
+    max_message_size: usize,
+    default_ref: String,
+}
+
+impl<S: Storage> Clone for GrpcServer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> GrpcServer<S> {
+    /// Creates a server for `store`.
+    pub fn new(store: DeltaStore<S>, options: GrpcOptions) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                store: RwLock::new(store),
+                patches: PatchService::new(options.service),
+                max_message_size: options.max_message_size,
+                default_ref: options.default_ref,
+            }),
+        }
+    }
+
+    /// Returns the counters of the patch service answering `GetPatch`.
+    pub fn patch_stats(&self) -> ServiceStats {
+        self.inner.patches.stats()
+    }
+
+    /// Wraps the server in a tonic service, e.g. to add it to a
+    /// [`Router`](tonic::transport::server::Router) next to other services.
+    pub fn into_service(self) -> PatchServiceServer<Self> {
+        let max_message_size = self.inner.max_message_size;
+        PatchServiceServer::new(self)
+            .max_decoding_message_size(max_message_size)
+            .max_encoding_message_size(max_message_size)
+    }
+
+    /// Serves requests on `addr` until an error occurs.
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_service())
+            .serve(addr)
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl<S: Storage + Send + Sync + 'static> proto::patch_service_server::PatchService
+    for GrpcServer<S>
+{
+    async fn get_patch(
+        &self,
+        request: Request<GetPatchRequest>,
+    ) -> Result<Response<GetPatchResponse>, Status> {
+        let inner = self.inner.clone();
+        blocking(move || inner.get_patch(request.into_inner())).await
+    }
+
+    async fn get_signature(
+        &self,
+        request: Request<GetSignatureRequest>,
+    ) -> Result<Response<GetSignatureResponse>, Status> {
+        let inner = self.inner.clone();
+        blocking(move || inner.get_signature(request.into_inner())).await
+    }
+
+    async fn upload_version(
+        &self,
+        request: Request<UploadVersionRequest>,
+    ) -> Result<Response<UploadVersionResponse>, Status> {
+        let inner = self.inner.clone();
+        blocking(move || inner.upload_version(request.into_inner())).await
+    }
+}
+
+impl<S: Storage> Inner<S> {
+    fn get_patch(&self, request: GetPatchRequest) -> Result<GetPatchResponse, Status> {
+        let (from, to, size) = {
+            let store = self.read();
+            let from = match parse_hash(&request.from)? {
+                Some(hash) if !store.contains(&hash) => {
+                    return Err(Status::not_found("Unknown base version"));
+                }
+                from => from,
+            };
+            let to = self.resolve(&store, &request.to, &request.r#ref)?;
+            let size = store.info(&to).map_or(0, |info| info.size);
+            (from, to, size)
+        };
+
+        let patch = if from == Some(to) {
+            Vec::new()
+        } else {
+            let load = || {
+                let store = self.read();
+                let base = match from {
+                    Some(hash) => store.get(&hash)?,
+                    None => Vec::new(),
+                };
+                Ok::<_, io::Error>((base, store.get(&to)?))
+            };
+            let patch = self.patches.patch((from, to), load).map_err(|e| match e {
+                e @ ServiceError::Busy => Status::resource_exhausted(e.to_string()),
+                ServiceError::Load(e) => status(e),
+            })?;
+            patch.to_vec()
+        };
+
+        Ok(GetPatchResponse {
+            from: from.map_or(Vec::new(), |hash| hash.as_bytes().to_vec()),
+            to: to.as_bytes().to_vec(),
+            patch,
+            size,
+        })
+    }
+
+    fn get_signature(&self, request: GetSignatureRequest) -> Result<GetSignatureResponse, Status> {
+        let (hash, data) = {
+            let store = self.read();
+            let hash = self.resolve(&store, &request.hash, &request.r#ref)?;
+            (hash, store.get(&hash).map_err(status)?)
+        };
+
+        let match_hash = u8::try_from(request.match_hash)
+            .ok()
+            .and_then(|id| delta::MatchHash::try_from(id).ok())
+            .ok_or_else(|| Status::invalid_argument("Unknown match hash"))?;
+        let signature =
+            delta::signature_with_options(&data, request.block_size as usize, match_hash);
+        Ok(GetSignatureResponse {
+            hash: hash.as_bytes().to_vec(),
+            signature: signature.to_bytes(),
+        })
+    }
+
+    fn upload_version(
+        &self,
+        request: UploadVersionRequest,
+    ) -> Result<UploadVersionResponse, Status> {
+        let data = match parse_hash(&request.base)? {
+            Some(base) => {
+                let base = self.read().get(&base).map_err(status)?;
+                delta::decode(&base, &request.data).map_err(Status::invalid_argument)?
+            }
+            None => request.data,
+        };
+
+        let mut store = self.write();
+        let hash = store.insert(&data).map_err(status)?;
+        if !request.r#ref.is_empty() {
+            store.set_ref(&request.r#ref, hash).map_err(status)?;
+        }
+        Ok(UploadVersionResponse {
+            hash: hash.as_bytes().to_vec(),
+            size: data.len() as u64,
+        })
+    }
+
+    /// Picks the version named by `hash`, or by `name` (or the default ref) if `hash` is empty.
+    fn resolve(
+        &self,
+        store: &DeltaStore<S>,
+        hash: &[u8],
+        name: &str,
+    ) -> Result<ContentHash, Status> {
+        match parse_hash(hash)? {
+            Some(hash) if store.contains(&hash) => Ok(hash),
+            Some(_) => Err(Status::not_found("Unknown version")),
+            None => {
+                let name = if name.is_empty() {
+                    &self.default_ref
+                } else {
+                    name
+                };
+                store
+                    .get_ref(name)
+                    .ok_or_else(|| Status::not_found(format!("Unknown ref: {}", name)))
+            }
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, DeltaStore<S>> {
+        self.store.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, DeltaStore<S>> {
+        self.store.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Runs a handler on tokio's blocking thread pool, as store access and encoding block.
+async fn blocking<T: Send + 'static>(
+    handler: impl FnOnce() -> Result<T, Status> + Send + 'static,
+) -> Result<Response<T>, Status> {
+    match tokio::task::spawn_blocking(handler).await {
+        Ok(result) => result.map(Response::new),
+        Err(e) => Err(Status::internal(format!("Request handler failed: {}", e))),
+    }
+}
+
+/// Parses an optional hash field, which is either empty or a 32-byte SHA-256.
+fn parse_hash(bytes: &[u8]) -> Result<Option<ContentHash>, Status> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| Status::invalid_argument("Hashes must be 32 bytes"))?;
+    Ok(Some(ContentHash::from_bytes(bytes)))
+}
+
+fn status(e: io::Error) -> Status {
+    match e.kind() {
+        ErrorKind::NotFound => Status::not_found(e.to_string()),
+        ErrorKind::InvalidInput => Status::invalid_argument(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::patch_service_client::PatchServiceClient;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+    use tonic::Code;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::{Channel, Server};
+
+    fn temp_store_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("xpatch-grpc-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Starts a server for a fresh store and connects a client to it.
+    async fn start(name: &str) -> (GrpcServer, PatchServiceClient<Channel>) {
+        let store = DeltaStore::open(temp_store_dir(name)).unwrap();
+        let server = GrpcServer::new(store, GrpcOptions::default());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = server.clone().into_service();
+        tokio::spawn(
+            Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+
+        let client = PatchServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn test_upload_and_patch() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (server, mut client) = start("roundtrip").await;
+            let v1 = b"Hello, World! This is the first version of the file.".to_vec();
+            let v2 = b"Hello, World! This is the second version of the file.".to_vec();
+
+            let uploaded = client
+                .upload_version(UploadVersionRequest {
+                    data: v1.clone(),
+                    r#ref: "latest".to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(uploaded.hash, ContentHash::of(&v1).as_bytes());
+
+            // Upload the second version as a delta against the signature of the first
+            let signature = client
+                .get_signature(GetSignatureRequest {
+                    match_hash: u8::from(delta::MatchHash::XxHash).into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(signature.hash, uploaded.hash);
+            let signature = delta::Signature::from_bytes(&signature.signature).unwrap();
+            assert_eq!(signature.match_hash(), delta::MatchHash::XxHash);
+            let delta = delta::encode_from_signature(&signature, &v2);
+            let uploaded = client
+                .upload_version(UploadVersionRequest {
+                    data: delta,
+                    base: hash_of(&v1),
+                    r#ref: "latest".to_string(),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(uploaded.size, v2.len() as u64);
+
+            let request = GetPatchRequest {
+                from: hash_of(&v1),
+                ..Default::default()
+            };
+            let response = client
+                .get_patch(request.clone())
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.to, uploaded.hash);
+            assert_eq!(response.size, v2.len() as u64);
+            assert_eq!(delta::decode(&v1, &response.patch).unwrap(), v2);
+
+            // The second request is answered from the cache
+            let cached = client.get_patch(request).await.unwrap().into_inner();
+            assert_eq!(cached.patch, response.patch);
+            let stats = server.patch_stats();
+            assert_eq!((stats.encodes, stats.hits), (1, 1));
+
+            // A patch from empty data, and one from the latest version to itself
+            let full = client
+                .get_patch(GetPatchRequest::default())
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(full.from.is_empty());
+            assert_eq!(delta::decode(&[], &full.patch).unwrap(), v2);
+            let current = client
+                .get_patch(GetPatchRequest {
+                    from: uploaded.hash.clone(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(current.patch.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_errors() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (_server, mut client) = start("errors").await;
+
+            // Empty store: the default ref does not exist yet
+            let error = client
+                .get_patch(GetPatchRequest::default())
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::NotFound);
+
+            client
+                .upload_version(UploadVersionRequest {
+                    data: b"version".to_vec(),
+                    r#ref: "latest".to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+
+            let error = client
+                .get_patch(GetPatchRequest {
+                    from: vec![1; 32],
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::NotFound);
+
+            let error = client
+                .get_signature(GetSignatureRequest {
+                    hash: vec![1; 5],
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::InvalidArgument);
+
+            let error = client
+                .get_signature(GetSignatureRequest {
+                    match_hash: 9,
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::InvalidArgument);
+
+            let error = client
+                .upload_version(UploadVersionRequest {
+                    data: b"not a delta".to_vec(),
+                    base: hash_of(b"version"),
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::InvalidArgument);
+
+            let error = client
+                .upload_version(UploadVersionRequest {
+                    data: b"version".to_vec(),
+                    r#ref: "bad ref".to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::InvalidArgument);
+        });
+    }
+
+    fn hash_of(data: &[u8]) -> Vec<u8> {
+        ContentHash::of(data).as_bytes().to_vec()
+    }
+}
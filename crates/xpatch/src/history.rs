@@ -0,0 +1,613 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Version history of keyed documents in an SQLite database.
+//!
+//! A [`SqlHistory`] records every revision of arbitrary keys (document ids, file paths, ...) in a
+//! single table. Like the `store` module, each revision is saved either as a keyframe or as
+//! a delta against one of the key's recent revisions most [similar](crate::sketch) to it,
+//! whichever encodes smallest, with delta chains capped at [`HistoryOptions::max_chain_length`]. Deltas are tagged with
+//! [`Tag::KEYFRAME`] or [`Tag::chain_depth`], so stored deltas stay self-describing.
+//!
+//! Appends run in a transaction, and [`SqlHistory::append_all`] commits several revisions
+//! atomically. Revisions carry a timestamp, so [`SqlHistory::get_at`] can read a key as it was at
+//! any point in time.
+//!
+//! # Schema
+//!
+//! ```text
+//! xpatch_history(key, version, time, size, base, chain, sketch, data)
+//! ```
+//!
+//! `version` counts from 1 per key, `time` is in nanoseconds since the Unix epoch and `base` is
+//! the version `data` is a delta against (`NULL` for a keyframe). `sketch` caches the revision's
+//! similarity sketch for base selection. The table can live next to an
+//! application's own tables, see [`SqlHistory::from_connection`].
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::history::SqlHistory;
+//!
+//! let mut history = SqlHistory::open_in_memory()?;
+//! history.append("readme", b"Hello, World!")?;
+//! history.append("readme", b"Hello, Rust World!")?;
+//!
+//! assert_eq!(history.latest("readme")?.unwrap(), b"Hello, Rust World!");
+//! assert_eq!(history.get("readme", 1)?.unwrap(), b"Hello, World!");
+//! assert_eq!(history.versions("readme")?[1].base, Some(1));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::delta::{self, EncodeOptions};
+use crate::sketch::Sketch;
+use crate::tag::Tag;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS xpatch_history (
+    key TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    time INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    base INTEGER,
+    chain INTEGER NOT NULL,
+    sketch BLOB,
+    data BLOB NOT NULL,
+    PRIMARY KEY (key, version)
+)";
+
+/// Options controlling how a [`SqlHistory`] encodes new revisions.
+#[derive(Debug, Clone)]
+pub struct HistoryOptions {
+    /// Maximum number of deltas between a revision and its keyframe (0 stores only keyframes)
+    pub max_chain_length: usize,
+    /// Number of recent revisions of the key ranked by similarity to a new revision
+    pub sketch_candidates: usize,
+    /// Number of the most similar of those revisions tried as base for a new revision
+    pub base_candidates: usize,
+    /// Options passed to the encoder
+    pub encode: EncodeOptions,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            max_chain_length: 16,
+            sketch_candidates: 64,
+            base_candidates: 4,
+            encode: EncodeOptions::default(),
+        }
+    }
+}
+
+/// Metadata about a stored revision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Revision number, counting from 1
+    pub version: u64,
+    /// Time the revision was appended
+    pub time: SystemTime,
+    /// Size of the content in bytes
+    pub size: u64,
+    /// Size of the encoded revision in bytes
+    pub stored_size: u64,
+    /// Revision this one is stored as a delta against, or `None` for a keyframe
+    pub base: Option<u64>,
+    /// Number of deltas to apply to reconstruct the revision (0 for a keyframe)
+    pub chain_length: usize,
+}
+
+/// Revisions of keyed documents stored in an SQLite database.
+///
+/// See the [module documentation](self) for the storage model.
+pub struct SqlHistory {
+    conn: Connection,
+    options: HistoryOptions,
+}
+
+impl SqlHistory {
+    /// Opens the database at `path` with default options, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_options(path, HistoryOptions::default())
+    }
+
+    /// Opens the database at `path`, creating it if it does not exist.
+    ///
+    /// The options only affect revisions appended from now on.
+    pub fn open_with_options(path: impl AsRef<Path>, options: HistoryOptions) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(sql_error)?;
+        Self::from_connection(conn, options)
+    }
+
+    /// Creates a history in a fresh in-memory database.
+    pub fn open_in_memory() -> io::Result<Self> {
+        let conn = Connection::open_in_memory().map_err(sql_error)?;
+        Self::from_connection(conn, HistoryOptions::default())
+    }
+
+    /// Uses an existing connection, creating the `xpatch_history` table if needed.
+    pub fn from_connection(conn: Connection, options: HistoryOptions) -> io::Result<Self> {
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        Ok(Self { conn, options })
+    }
+
+    /// Returns the underlying connection.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Consumes the history and returns the underlying connection.
+    pub fn into_connection(self) -> Connection {
+        self.conn
+    }
+
+    /// Appends a revision of `key` timestamped now and returns its version number.
+    pub fn append(&mut self, key: &str, data: &[u8]) -> io::Result<u64> {
+        self.append_at(key, data, SystemTime::now())
+    }
+
+    /// Appends a revision of `key` with an explicit timestamp, e.g. when importing history.
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`] if `time` is earlier than the key's latest revision.
+    pub fn append_at(&mut self, key: &str, data: &[u8], time: SystemTime) -> io::Result<u64> {
+        Ok(self.append_all([(key, data)], time)?[0])
+    }
+
+    /// Appends one revision per entry in a single transaction and returns their version numbers.
+    ///
+    /// Either all revisions are stored or, on error, none of them. A key may appear several times.
+    pub fn append_all<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+        time: SystemTime,
+    ) -> io::Result<Vec<u64>> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(sql_error)?;
+        let versions = entries
+            .into_iter()
+            .map(|(key, data)| append_revision(&tx, &self.options, key, data, time))
+            .collect::<io::Result<Vec<_>>>()?;
+        tx.commit().map_err(sql_error)?;
+        Ok(versions)
+    }
+
+    /// Reconstructs revision `version` of `key`, or returns `None` if it does not exist.
+    pub fn get(&self, key: &str, version: u64) -> io::Result<Option<Vec<u8>>> {
+        read_revision(&self.conn, key, version)
+    }
+
+    /// Reconstructs the latest revision of `key`, or returns `None` if the key has none.
+    pub fn latest(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match latest_version(&self.conn, key)? {
+            Some((version, _)) => self.get(key, version),
+            None => Ok(None),
+        }
+    }
+
+    /// Reconstructs `key` as it was at `time`: the latest revision appended at or before it.
+    ///
+    /// Returns `None` if the key had no revision yet.
+    pub fn get_at(&self, key: &str, time: SystemTime) -> io::Result<Option<Vec<u8>>> {
+        let version: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT MAX(version) FROM xpatch_history WHERE key = ?1 AND time <= ?2",
+                params![key, to_nanos(time)],
+                |row| row.get(0),
+            )
+            .map_err(sql_error)?;
+        match version {
+            Some(version) => self.get(key, version as u64),
+            None => Ok(None),
+        }
+    }
+
+    /// Lists the revisions of `key`, oldest first.
+    pub fn versions(&self, key: &str) -> io::Result<Vec<VersionInfo>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT version, time, size, length(data), base, chain FROM xpatch_history
+                 WHERE key = ?1 ORDER BY version",
+            )
+            .map_err(sql_error)?;
+        let rows = stmt
+            .query_map(params![key], |row| {
+                Ok(VersionInfo {
+                    version: row.get::<_, i64>(0)? as u64,
+                    time: from_nanos(row.get(1)?),
+                    size: row.get::<_, i64>(2)? as u64,
+                    stored_size: row.get::<_, i64>(3)? as u64,
+                    base: row.get::<_, Option<i64>>(4)?.map(|base| base as u64),
+                    chain_length: row.get::<_, i64>(5)? as usize,
+                })
+            })
+            .map_err(sql_error)?;
+        rows.collect::<Result<_, _>>().map_err(sql_error)
+    }
+
+    /// Lists all keys with at least one revision, sorted.
+    pub fn keys(&self) -> io::Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT key FROM xpatch_history ORDER BY key")
+            .map_err(sql_error)?;
+        let rows = stmt.query_map([], |row| row.get(0)).map_err(sql_error)?;
+        rows.collect::<Result<_, _>>().map_err(sql_error)
+    }
+
+    /// Deletes every revision of `key` and returns how many were removed.
+    pub fn remove(&mut self, key: &str) -> io::Result<usize> {
+        self.conn
+            .execute("DELETE FROM xpatch_history WHERE key = ?1", params![key])
+            .map_err(sql_error)
+    }
+}
+
+/// Encodes and inserts one revision; runs inside the caller's transaction.
+fn append_revision(
+    conn: &Connection,
+    options: &HistoryOptions,
+    key: &str,
+    data: &[u8],
+    time: SystemTime,
+) -> io::Result<u64> {
+    let time = to_nanos(time);
+    let version = match latest_version(conn, key)? {
+        Some((_, latest_time)) if time < latest_time => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Revision is older than the latest revision",
+            ));
+        }
+        Some((latest, _)) => latest + 1,
+        None => 1,
+    };
+
+    let mut best = delta::encode_tagged_with_options(Tag::KEYFRAME, &[], data, &options.encode);
+    let mut best_base = None;
+
+    let recent = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT version, chain, sketch FROM xpatch_history
+                 WHERE key = ?1 AND chain < ?2 ORDER BY version DESC LIMIT ?3",
+            )
+            .map_err(sql_error)?;
+        let rows = stmt
+            .query_map(
+                params![
+                    key,
+                    options.max_chain_length as i64,
+                    options.sketch_candidates.max(options.base_candidates) as i64
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)? as u64,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, Option<Vec<u8>>>(2)?,
+                    ))
+                },
+            )
+            .map_err(sql_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(sql_error)?
+    };
+    let mut sketched = Vec::with_capacity(recent.len());
+    for (candidate, chain, sketch) in recent {
+        let sketch = match sketch.as_deref().and_then(Sketch::from_bytes) {
+            Some(sketch) => sketch,
+            None => Sketch::of(
+                &read_revision(conn, key, candidate)?
+                    .ok_or_else(|| invalid_data("Missing base revision"))?,
+            ),
+        };
+        sketched.push(((candidate, chain), sketch));
+    }
+    let sketch = Sketch::of(data);
+    let candidates = sketch.rank(
+        sketched
+            .iter()
+            .map(|(candidate, sketch)| (*candidate, sketch)),
+    );
+
+    for (candidate, chain) in candidates.into_iter().take(options.base_candidates) {
+        let base = read_revision(conn, key, candidate)?
+            .ok_or_else(|| invalid_data("Missing base revision"))?;
+        let depth = chain as usize + 1;
+        let tag = if depth <= Tag::MAX_CHAIN_DEPTH {
+            Tag::chain_depth(depth)
+        } else {
+            Tag::NONE
+        };
+        let encoded = delta::encode_tagged_with_options(tag, &base, data, &options.encode);
+        if encoded.len() < best.len() {
+            best = encoded;
+            best_base = Some((candidate, chain + 1));
+        }
+    }
+
+    conn.execute(
+        "INSERT INTO xpatch_history (key, version, time, size, base, chain, sketch, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            key,
+            version as i64,
+            time,
+            data.len() as i64,
+            best_base.map(|(base, _)| base as i64),
+            best_base.map_or(0, |(_, chain)| chain),
+            sketch.to_bytes(),
+            best,
+        ],
+    )
+    .map_err(sql_error)?;
+    Ok(version)
+}
+
+/// Follows the base chain of a revision back to its keyframe and decodes it forward.
+fn read_revision(conn: &Connection, key: &str, version: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT base, size, data FROM xpatch_history WHERE key = ?1 AND version = ?2",
+        )
+        .map_err(sql_error)?;
+
+    let mut chain = Vec::new();
+    let mut size = None;
+    let mut next = Some(version);
+    while let Some(current) = next {
+        let row: Option<(Option<i64>, i64, Vec<u8>)> = stmt
+            .query_row(params![key, current as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()
+            .map_err(sql_error)?;
+        let Some((base, revision_size, data)) = row else {
+            if chain.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid_data("Missing base revision"));
+        };
+        size.get_or_insert(revision_size as u64);
+        next = base.map(|base| base as u64);
+        if next.is_some_and(|base| base >= current) {
+            return Err(invalid_data("Invalid base revision"));
+        }
+        chain.push(data);
+    }
+
+    let mut data = Vec::new();
+    for encoded in chain.iter().rev() {
+        data = delta::decode(&data, encoded).map_err(invalid_data)?;
+    }
+    if Some(data.len() as u64) != size {
+        return Err(invalid_data("Revision size mismatch"));
+    }
+    Ok(Some(data))
+}
+
+/// Returns the latest version number of `key` and its timestamp.
+fn latest_version(conn: &Connection, key: &str) -> io::Result<Option<(u64, i64)>> {
+    conn.query_row(
+        "SELECT version, time FROM xpatch_history WHERE key = ?1 ORDER BY version DESC LIMIT 1",
+        params![key],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+    )
+    .optional()
+    .map_err(sql_error)
+}
+
+fn to_nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as i64)
+}
+
+fn from_nanos(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
+fn sql_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn revision(n: usize) -> Vec<u8> {
+        format!("Document revision {}\n", n).repeat(50).into_bytes()
+    }
+
+    #[test]
+    fn test_roundtrip_all_revisions() {
+        let mut history = SqlHistory::open_in_memory().unwrap();
+        for n in 1..=40 {
+            assert_eq!(history.append("doc", &revision(n)).unwrap(), n as u64);
+        }
+        for n in 1..=40 {
+            assert_eq!(history.get("doc", n as u64).unwrap().unwrap(), revision(n));
+        }
+        assert_eq!(history.latest("doc").unwrap().unwrap(), revision(40));
+        assert_eq!(history.get("doc", 41).unwrap(), None);
+        assert_eq!(history.latest("other").unwrap(), None);
+
+        let versions = history.versions("doc").unwrap();
+        assert_eq!(versions.len(), 40);
+        assert_eq!(versions[0].base, None);
+        assert!(versions.iter().all(|v| v.chain_length <= 16));
+        assert!(versions[1..].iter().any(|v| v.base.is_some()));
+    }
+
+    #[test]
+    fn test_tag_records_chain_depth() {
+        let mut history = SqlHistory::open_in_memory().unwrap();
+        let v1 = "x".repeat(2000) + "first";
+        let v2 = "y".repeat(2000) + "second";
+        history.append("doc", v1.as_bytes()).unwrap();
+        history.append("doc", v2.as_bytes()).unwrap();
+        history.append("doc", v1.as_bytes()).unwrap();
+
+        let info = history.versions("doc").unwrap()[2];
+        assert_eq!(info.base, Some(1));
+        let stored: Vec<u8> = history
+            .connection()
+            .query_row(
+                "SELECT data FROM xpatch_history WHERE key = 'doc' AND version = 3",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(Tag::of(&stored), Ok(Tag::chain_depth(1)));
+        assert_eq!(history.get("doc", 3).unwrap().unwrap(), v1.as_bytes());
+    }
+
+    #[test]
+    fn test_point_in_time_reads() {
+        let mut history = SqlHistory::open_in_memory().unwrap();
+        history.append_at("doc", b"one", at(10)).unwrap();
+        history.append_at("doc", b"two", at(20)).unwrap();
+        history.append_at("doc", b"three", at(30)).unwrap();
+
+        assert_eq!(history.get_at("doc", at(5)).unwrap(), None);
+        assert_eq!(history.get_at("doc", at(10)).unwrap().unwrap(), b"one");
+        assert_eq!(history.get_at("doc", at(25)).unwrap().unwrap(), b"two");
+        assert_eq!(history.get_at("doc", at(99)).unwrap().unwrap(), b"three");
+        assert_eq!(history.versions("doc").unwrap()[1].time, at(20));
+
+        let err = history.append_at("doc", b"late", at(15)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(history.versions("doc").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_append_all_is_atomic() {
+        let mut history = SqlHistory::open_in_memory().unwrap();
+        history.append_at("a", b"a1", at(10)).unwrap();
+
+        let versions = history
+            .append_all([("a", &b"a2"[..]), ("b", b"b1"), ("a", b"a3")], at(20))
+            .unwrap();
+        assert_eq!(versions, vec![2, 1, 3]);
+        assert_eq!(history.keys().unwrap(), vec!["a", "b"]);
+
+        // "c" would be fine, but "a" is older than its latest revision
+        let err = history
+            .append_all([("c", &b"c1"[..]), ("a", b"a4")], at(15))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(history.keys().unwrap(), vec!["a", "b"]);
+        assert_eq!(history.latest("a").unwrap().unwrap(), b"a3");
+    }
+
+    #[test]
+    fn test_picks_similar_base() {
+        let options = HistoryOptions {
+            base_candidates: 1,
+            ..HistoryOptions::default()
+        };
+        let mut history =
+            SqlHistory::from_connection(Connection::open_in_memory().unwrap(), options).unwrap();
+        let text = |seed: u32| -> Vec<u8> {
+            (0..3000u32)
+                .map(|i| b'a' + (i.wrapping_mul(seed).wrapping_add(i / 7) % 26) as u8)
+                .collect()
+        };
+        for seed in 3..13 {
+            history.append("doc", &text(seed)).unwrap();
+        }
+        let mut edited = text(3);
+        edited.splice(100..100, *b"an edit");
+        let version = history.append("doc", &edited).unwrap();
+
+        assert_eq!(history.versions("doc").unwrap()[10].base, Some(1));
+        assert_eq!(history.get("doc", version).unwrap().unwrap(), edited);
+    }
+
+    #[test]
+    fn test_keyframes_only() {
+        let options = HistoryOptions {
+            max_chain_length: 0,
+            ..HistoryOptions::default()
+        };
+        let conn = Connection::open_in_memory().unwrap();
+        let mut history = SqlHistory::from_connection(conn, options).unwrap();
+        for n in 1..=5 {
+            history.append("doc", &revision(n)).unwrap();
+        }
+        assert!(
+            history
+                .versions("doc")
+                .unwrap()
+                .iter()
+                .all(|v| v.base.is_none())
+        );
+        assert_eq!(history.get("doc", 3).unwrap().unwrap(), revision(3));
+    }
+
+    #[test]
+    fn test_persists_and_removes() {
+        let path =
+            std::env::temp_dir().join(format!("xpatch-history-test-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut history = SqlHistory::open(&path).unwrap();
+            history.append("doc", b"Hello, World!").unwrap();
+            history.append("doc", b"Hello, Rust World!").unwrap();
+            history.append("notes", b"todo").unwrap();
+        }
+        let mut history = SqlHistory::open(&path).unwrap();
+        assert_eq!(history.get("doc", 1).unwrap().unwrap(), b"Hello, World!");
+        assert_eq!(
+            history.latest("doc").unwrap().unwrap(),
+            b"Hello, Rust World!"
+        );
+
+        assert_eq!(history.remove("doc").unwrap(), 2);
+        assert_eq!(history.keys().unwrap(), vec!["notes"]);
+        drop(history);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_revision() {
+        let mut history = SqlHistory::open_in_memory().unwrap();
+        history.append("doc", &revision(1)).unwrap();
+        history
+            .connection()
+            .execute("UPDATE xpatch_history SET size = 1", [])
+            .unwrap();
+        let err = history.get("doc", 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
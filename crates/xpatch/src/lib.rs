@@ -32,12 +32,78 @@
 //! let decoded = delta::decode(base, &delta).unwrap();
 //! assert_eq!(decoded, new);
 //! ```
+//!
+//! ## Decoder-only builds
+//! The encoder and the decoder are the default `encode` and `decode` features. Devices that only
+//! apply patches can depend on xpatch with `default-features = false, features = ["decode"]`,
+//! which leaves out the matchers and the zstd compressor; add the `zstd` feature, or `ruzstd`
+//! for a pure-Rust decoder, to apply zstd-compressed deltas. Features built on both directions,
+//! such as `store` or `cli`, enable both.
+
+#[cfg(not(any(feature = "encode", feature = "decode")))]
+compile_error!("xpatch needs the `encode` feature, the `decode` feature, or both");
 
+#[cfg(feature = "store")]
+pub mod archive;
+#[cfg(feature = "store")]
+pub mod backup;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "decode")]
+pub mod block;
+pub mod cache;
+#[cfg(feature = "compressed")]
+pub mod compressed;
 pub(crate) mod debug;
 pub mod delta;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "fec")]
+pub mod fec;
+#[cfg(feature = "firmware")]
+pub mod firmware;
+pub mod formats;
+#[cfg(feature = "git")]
+pub mod git;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "sqlite")]
+pub mod history;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(any(feature = "http", feature = "serve"))]
+pub mod net;
+#[cfg(feature = "store")]
+pub mod pack;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "store")]
+pub mod planner;
+#[cfg(feature = "encode")]
+pub mod service;
+#[cfg(feature = "encode")]
+pub mod sketch;
+pub mod stats;
+#[cfg(feature = "store")]
+pub mod store;
+pub mod stream;
+pub mod tag;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod token_list;
 pub mod tokenizer;
+#[cfg(feature = "serde")]
+pub mod typed;
 pub mod varint;
 
 // Re-export main public API
-pub use delta::{Algorithm, decode, encode, get_tag};
+pub use delta::{Algorithm, DeltaInfo, EncodeOptions, get_tag, inspect};
+#[cfg(feature = "decode")]
+pub use delta::{decode, verify};
+#[cfg(feature = "encode")]
+pub use delta::{encode, encode_with_options};
+#[cfg(feature = "parallel")]
+pub use parallel::{ThreadPoolConfig, set_thread_pool};
+pub use tag::Tag;
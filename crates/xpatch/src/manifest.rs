@@ -0,0 +1,500 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Signed release manifests.
+//!
+//! A [`Manifest`] describes a release channel: its releases in order (name, content hash, size
+//! and download URL) and the patches published between them (base, target, URL, size and hash).
+//! Updaters fetch the manifest, [verify](verify) its Ed25519 signature, and pick the patch
+//! from their installed release to the latest one with [`Manifest::find_patch`], falling back
+//! to the full download of [`Manifest::latest`].
+//!
+//! Manifests are stored as JSON or CBOR with the same fields; [`Manifest::parse`] reads both.
+//! The signature covers the manifest's content rather than its encoding, so a signed manifest
+//! can be converted between the two without signing it again.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::manifest::{self, Builder, SigningKey};
+//!
+//! let (v1, v2) = (b"release 1.0".as_slice(), b"release 1.1".as_slice());
+//! let patch = xpatch::delta::encode(0, v1, v2, true);
+//!
+//! let key = SigningKey::from_bytes(&[7; 32]);
+//! let manifest = Builder::new("stable")
+//!     .release("1.0", v1, "https://example.com/app-1.0.bin")
+//!     .release("1.1", v2, "https://example.com/app-1.1.bin")
+//!     .patch("1.0", "1.1", &patch, "https://example.com/1.0-1.1.xpatch")
+//!     .sign(&key)?;
+//! let json = manifest.to_json();
+//!
+//! // On the client
+//! let manifest = manifest::verify(json.as_bytes(), &key.verifying_key())?;
+//! let installed = xpatch::store::ContentHash::of(v1);
+//! let patch_ref = manifest.find_patch(&installed).unwrap();
+//! assert_eq!(patch_ref.url, "https://example.com/1.0-1.1.xpatch");
+//! assert_eq!(xpatch::delta::decode(v1, &patch).unwrap(), v2);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::store::ContentHash;
+use crate::varint::encode_varint;
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+
+/// Version of the manifest format written by this crate.
+///
+/// Manifests declare the oldest format a reader must understand in
+/// [`Manifest::min_format_version`]; [`Manifest::parse`] rejects newer ones.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Prefix of the signed bytes, so a signature cannot be replayed for other data.
+const SIGNATURE_CONTEXT: &[u8] = b"xpatch-release-manifest\0";
+
+/// A release in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    /// Name of the release, e.g. a version number
+    pub name: String,
+    /// Content hash of the release
+    pub hash: ContentHash,
+    /// Size of the release in bytes
+    pub size: u64,
+    /// Where the complete release can be downloaded
+    pub url: String,
+}
+
+/// A patch between two releases of a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    /// Release the patch applies to
+    pub from: ContentHash,
+    /// Release the patch produces
+    pub to: ContentHash,
+    /// Where the patch can be downloaded
+    pub url: String,
+    /// Size of the patch in bytes
+    pub size: u64,
+    /// Content hash of the patch, for checking the download before applying it
+    pub hash: ContentHash,
+}
+
+/// Releases and patches of a release channel, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Name of the channel, e.g. `stable` or `beta`
+    pub channel: String,
+    /// Oldest manifest format version a reader must support to use this manifest
+    pub min_format_version: u32,
+    /// Releases from oldest to latest
+    pub releases: Vec<Release>,
+    pub patches: Vec<Patch>,
+    /// Ed25519 signature over the content
+    pub signature: Option<[u8; 64]>,
+}
+
+impl Manifest {
+    /// Returns the latest release.
+    pub fn latest(&self) -> Option<&Release> {
+        self.releases.last()
+    }
+
+    /// Returns the release with content hash `hash`.
+    pub fn release(&self, hash: &ContentHash) -> Option<&Release> {
+        self.releases.iter().find(|release| release.hash == *hash)
+    }
+
+    /// Returns the patch from release `from` to the latest release, if one is published.
+    pub fn find_patch(&self, from: &ContentHash) -> Option<&Patch> {
+        let latest = self.latest()?;
+        self.patches
+            .iter()
+            .find(|patch| patch.from == *from && patch.to == latest.hash)
+    }
+
+    /// Signs the manifest's content.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(key.sign(&self.signed_bytes()).to_bytes());
+    }
+
+    /// Checks the signature against `key`.
+    pub fn verify(&self, key: &VerifyingKey) -> io::Result<()> {
+        let signature = self
+            .signature
+            .ok_or_else(|| invalid_data("Manifest is not signed"))?;
+        key.verify(&self.signed_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| invalid_data("Manifest signature mismatch"))
+    }
+
+    /// Parses a manifest in JSON or CBOR form, without checking its signature.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let value: Value = match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => {
+                serde_json::from_slice(bytes).map_err(|_| invalid_data("Invalid manifest"))?
+            }
+            _ => ciborium::from_reader(bytes).map_err(|_| invalid_data("Invalid manifest"))?,
+        };
+        let manifest = Self::from_value(&value).ok_or_else(|| invalid_data("Invalid manifest"))?;
+
+        if manifest.min_format_version > FORMAT_VERSION {
+            return Err(invalid_data(
+                "Manifest requires a newer manifest format version",
+            ));
+        }
+        manifest.validate().map_err(invalid_data)?;
+        Ok(manifest)
+    }
+
+    /// Serializes the manifest to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_value()).expect("manifest values serialize")
+    }
+
+    /// Serializes the manifest to CBOR.
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&self.to_value(), &mut bytes).expect("manifest values serialize");
+        bytes
+    }
+
+    fn to_value(&self) -> Value {
+        let releases: Vec<Value> = self
+            .releases
+            .iter()
+            .map(|release| {
+                json!({
+                    "name": release.name,
+                    "hash": release.hash.to_string(),
+                    "size": release.size,
+                    "url": release.url,
+                })
+            })
+            .collect();
+        let patches: Vec<Value> = self
+            .patches
+            .iter()
+            .map(|patch| {
+                json!({
+                    "from": patch.from.to_string(),
+                    "to": patch.to.to_string(),
+                    "url": patch.url,
+                    "size": patch.size,
+                    "hash": patch.hash.to_string(),
+                })
+            })
+            .collect();
+
+        let mut value = json!({
+            "channel": self.channel,
+            "min_format_version": self.min_format_version,
+            "releases": releases,
+            "patches": patches,
+        });
+        if let Some(signature) = &self.signature {
+            value["signature"] = to_hex(signature).into();
+        }
+        value
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let string = |value: &Value, name: &str| Some(value.get(name)?.as_str()?.to_string());
+        let hash = |value: &Value, name: &str| value.get(name)?.as_str()?.parse().ok();
+        let number = |value: &Value, name: &str| value.get(name)?.as_u64();
+
+        let releases = value
+            .get("releases")?
+            .as_array()?
+            .iter()
+            .map(|release| {
+                Some(Release {
+                    name: string(release, "name")?,
+                    hash: hash(release, "hash")?,
+                    size: number(release, "size")?,
+                    url: string(release, "url")?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let patches = value
+            .get("patches")?
+            .as_array()?
+            .iter()
+            .map(|patch| {
+                Some(Patch {
+                    from: hash(patch, "from")?,
+                    to: hash(patch, "to")?,
+                    url: string(patch, "url")?,
+                    size: number(patch, "size")?,
+                    hash: hash(patch, "hash")?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let signature = match value.get("signature") {
+            Some(hex) => Some(from_hex(hex.as_str()?)?.try_into().ok()?),
+            None => None,
+        };
+
+        Some(Self {
+            channel: string(value, "channel")?,
+            min_format_version: number(value, "min_format_version")?.try_into().ok()?,
+            releases,
+            patches,
+            signature,
+        })
+    }
+
+    /// Checks that releases are unique and patches connect known releases.
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.releases.is_empty() {
+            return Err("Manifest has no releases");
+        }
+        let mut names = HashSet::new();
+        let mut hashes = HashSet::new();
+        for release in &self.releases {
+            if !names.insert(release.name.as_str()) || !hashes.insert(release.hash) {
+                return Err("Duplicate release in manifest");
+            }
+        }
+        for patch in &self.patches {
+            if !hashes.contains(&patch.from) || !hashes.contains(&patch.to) {
+                return Err("Manifest patch refers to an unknown release");
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes every field except the signature unambiguously.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNATURE_CONTEXT.to_vec();
+        let string = |bytes: &mut Vec<u8>, s: &str| {
+            bytes.extend(encode_varint(s.len()));
+            bytes.extend_from_slice(s.as_bytes());
+        };
+
+        string(&mut bytes, &self.channel);
+        bytes.extend_from_slice(&self.min_format_version.to_le_bytes());
+        bytes.extend(encode_varint(self.releases.len()));
+        for release in &self.releases {
+            string(&mut bytes, &release.name);
+            bytes.extend_from_slice(release.hash.as_bytes());
+            bytes.extend_from_slice(&release.size.to_le_bytes());
+            string(&mut bytes, &release.url);
+        }
+        bytes.extend(encode_varint(self.patches.len()));
+        for patch in &self.patches {
+            bytes.extend_from_slice(patch.from.as_bytes());
+            bytes.extend_from_slice(patch.to.as_bytes());
+            string(&mut bytes, &patch.url);
+            bytes.extend_from_slice(&patch.size.to_le_bytes());
+            bytes.extend_from_slice(patch.hash.as_bytes());
+        }
+        bytes
+    }
+}
+
+/// Parses a manifest in JSON or CBOR form and checks its signature against `key`.
+pub fn verify(bytes: &[u8], key: &VerifyingKey) -> io::Result<Manifest> {
+    let manifest = Manifest::parse(bytes)?;
+    manifest.verify(key)?;
+    Ok(manifest)
+}
+
+/// Assembles a [`Manifest`] from release and patch files.
+///
+/// Releases are added from oldest to latest. Patches name their releases, so those must be
+/// added first.
+pub struct Builder {
+    manifest: Manifest,
+    error: Option<&'static str>,
+}
+
+impl Builder {
+    /// Starts an empty manifest for `channel`.
+    pub fn new(channel: impl Into<String>) -> Self {
+        Self {
+            manifest: Manifest {
+                channel: channel.into(),
+                min_format_version: FORMAT_VERSION,
+                releases: Vec::new(),
+                patches: Vec::new(),
+                signature: None,
+            },
+            error: None,
+        }
+    }
+
+    /// Sets the oldest manifest format version readers must support (default
+    /// [`FORMAT_VERSION`]).
+    pub fn min_format_version(mut self, version: u32) -> Self {
+        self.manifest.min_format_version = version;
+        self
+    }
+
+    /// Adds the release `name` with content `data`, downloadable from `url`.
+    pub fn release(mut self, name: impl Into<String>, data: &[u8], url: impl Into<String>) -> Self {
+        self.manifest.releases.push(Release {
+            name: name.into(),
+            hash: ContentHash::of(data),
+            size: data.len() as u64,
+            url: url.into(),
+        });
+        self
+    }
+
+    /// Adds `patch` from release `from` to release `to`, downloadable from `url`.
+    pub fn patch(mut self, from: &str, to: &str, patch: &[u8], url: impl Into<String>) -> Self {
+        let find = |name: &str| {
+            self.manifest
+                .releases
+                .iter()
+                .find(|release| release.name == name)
+                .map(|release| release.hash)
+        };
+        match (find(from), find(to)) {
+            (Some(from), Some(to)) => self.manifest.patches.push(Patch {
+                from,
+                to,
+                url: url.into(),
+                size: patch.len() as u64,
+                hash: ContentHash::of(patch),
+            }),
+            _ => {
+                self.error
+                    .get_or_insert("Manifest patch refers to an unknown release");
+            }
+        }
+        self
+    }
+
+    /// Returns the unsigned manifest.
+    ///
+    /// Fails if no release was added, a release was added twice, or a patch names an unknown
+    /// release.
+    pub fn build(self) -> io::Result<Manifest> {
+        if let Some(error) = self.error {
+            return Err(invalid_input(error));
+        }
+        self.manifest.validate().map_err(invalid_input)?;
+        Ok(self.manifest)
+    }
+
+    /// Returns the manifest signed with `key`, see [`build`](Self::build).
+    pub fn sign(self, key: &SigningKey) -> io::Result<Manifest> {
+        let mut manifest = self.build()?;
+        manifest.sign(key);
+        Ok(manifest)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> Manifest {
+        Builder::new("stable")
+            .release("1.0", b"one", "https://example.com/1.0")
+            .release("1.1", b"two", "https://example.com/1.1")
+            .release("1.2", b"three", "https://example.com/1.2")
+            .patch("1.0", "1.2", b"patch a", "https://example.com/a")
+            .patch("1.1", "1.2", b"patch b", "https://example.com/b")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_and_signature() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut manifest = manifest();
+        manifest.sign(&key);
+
+        // The signature survives a conversion between JSON and CBOR
+        let json = manifest.to_json();
+        let cbor = Manifest::parse(json.as_bytes()).unwrap().to_cbor();
+        assert_eq!(
+            verify(json.as_bytes(), &key.verifying_key()).unwrap(),
+            manifest
+        );
+        assert_eq!(verify(&cbor, &key.verifying_key()).unwrap(), manifest);
+
+        let other = SigningKey::from_bytes(&[2; 32]);
+        assert!(verify(&cbor, &other.verifying_key()).is_err());
+
+        let mut tampered = manifest.clone();
+        tampered.patches[1].url = "https://evil.example.com/b".to_string();
+        assert!(tampered.verify(&key.verifying_key()).is_err());
+
+        let unsigned = self::manifest().to_json();
+        assert!(verify(unsigned.as_bytes(), &key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_find_patch() {
+        let manifest = manifest();
+        assert_eq!(manifest.latest().unwrap().name, "1.2");
+        let patch = manifest.find_patch(&ContentHash::of(b"two")).unwrap();
+        assert_eq!(patch.url, "https://example.com/b");
+        assert_eq!(patch.hash, ContentHash::of(b"patch b"));
+        assert!(manifest.find_patch(&ContentHash::of(b"three")).is_none());
+        assert!(manifest.find_patch(&ContentHash::of(b"unknown")).is_none());
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        let builder = || Builder::new("stable").release("1.0", b"one", "");
+        assert!(Builder::new("stable").build().is_err());
+        assert!(builder().release("1.0", b"two", "").build().is_err());
+        assert!(builder().release("1.1", b"one", "").build().is_err());
+        assert!(builder().patch("0.9", "1.0", b"", "").build().is_err());
+
+        let newer = builder()
+            .min_format_version(FORMAT_VERSION + 1)
+            .build()
+            .unwrap();
+        assert!(Manifest::parse(&newer.to_cbor()).is_err());
+        assert!(Manifest::parse(b"{\"channel\": \"stable\"}").is_err());
+        assert!(Manifest::parse(b"\xff").is_err());
+    }
+}
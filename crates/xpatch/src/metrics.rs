@@ -0,0 +1,368 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Hooks for monitoring encode and decode operations.
+//!
+//! A [`Recorder`] installed with [`set_recorder`] is called once per [`encode`] and [`decode`]
+//! (and every function built on them) with the operation's duration, sizes and outcome. Without
+//! a recorder, nothing is measured. [`PrometheusRecorder`] aggregates the events into counters
+//! and histograms and renders them in the OpenMetrics text format, ready to be scraped:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use xpatch::metrics::{self, PrometheusRecorder};
+//!
+//! let recorder = Arc::new(PrometheusRecorder::new());
+//! metrics::set_recorder(recorder.clone());
+//!
+//! let delta = xpatch::encode(0, b"Hello, world!", b"Hello, metrics!", true);
+//! xpatch::decode(b"Hello, world!", &delta).unwrap();
+//!
+//! let text = recorder.render();
+//! assert!(text.contains("xpatch_operations_total{operation=\"encode\"}"));
+//! # metrics::clear_recorder();
+//! ```
+//!
+//! [`encode`]: crate::delta::encode
+//! [`decode`]: crate::delta::decode
+
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The kind of operation an [`Event`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// A delta was created from base and new data.
+    Encode,
+    /// A delta was applied to base data.
+    Decode,
+}
+
+impl Operation {
+    /// The name used for the operation in metric labels: `encode` or `decode`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Encode => "encode",
+            Operation::Decode => "decode",
+        }
+    }
+}
+
+/// One finished encode or decode, as passed to [`Recorder::record`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// What was done.
+    pub operation: Operation,
+    /// Wall-clock time the operation took.
+    pub duration: Duration,
+    /// Size of the new data in bytes: the input of an encode, the output of a decode. 0 if a
+    /// decode failed.
+    pub data_size: usize,
+    /// Size of the delta in bytes: the output of an encode, the input of a decode. 0 if an
+    /// encode failed.
+    pub delta_size: usize,
+    /// The error the operation returned, if it failed.
+    pub error: Option<&'static str>,
+}
+
+impl Event {
+    /// Delta size relative to the new data (`delta_size / data_size`); lower is better. `None`
+    /// if the operation failed or the new data is empty.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.error.is_none() && self.data_size > 0)
+            .then(|| self.delta_size as f64 / self.data_size as f64)
+    }
+}
+
+/// Receives an [`Event`] for every encode and decode while installed with [`set_recorder`].
+///
+/// `record` runs on the thread that did the work, right after it finished, so it should be
+/// cheap; aggregate and export from elsewhere.
+pub trait Recorder: Send + Sync {
+    /// Called once per finished operation.
+    fn record(&self, event: &Event);
+}
+
+static RECORDER: RwLock<Option<Arc<dyn Recorder>>> = RwLock::new(None);
+
+/// Installs `recorder` for all threads of the process, replacing the previous one.
+pub fn set_recorder(recorder: Arc<dyn Recorder>) {
+    *RECORDER.write().unwrap_or_else(|e| e.into_inner()) = Some(recorder);
+}
+
+/// Removes the installed recorder; operations are no longer measured.
+pub fn clear_recorder() {
+    *RECORDER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Starts measuring an operation, or returns `None` if no recorder is installed.
+pub(crate) fn start() -> Option<Instant> {
+    RECORDER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+        .then(Instant::now)
+}
+
+/// Reports an operation started with [`start`] to the installed recorder.
+pub(crate) fn finish(
+    start: Option<Instant>,
+    operation: Operation,
+    data_size: usize,
+    delta_size: usize,
+    error: Option<&'static str>,
+) {
+    let Some(start) = start else {
+        return;
+    };
+    let recorder = RECORDER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(recorder) = recorder {
+        recorder.record(&Event {
+            operation,
+            duration: start.elapsed(),
+            data_size,
+            delta_size,
+            error,
+        });
+    }
+}
+
+/// Upper bounds of the duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 8] = [0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0, 10.0];
+
+/// Upper bounds of the ratio histogram buckets.
+const RATIO_BUCKETS: [f64; 8] = [0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0];
+
+/// A [`Recorder`] that aggregates events into Prometheus metrics.
+///
+/// [`render`](Self::render) returns, per operation:
+/// - `xpatch_operations_total` and `xpatch_errors_total` - finished and failed operations
+/// - `xpatch_data_bytes_total` and `xpatch_delta_bytes_total` - bytes of new data and deltas
+///   of successful operations; their quotient is the overall delta ratio
+/// - `xpatch_duration_seconds` - a histogram of operation durations
+/// - `xpatch_delta_ratio` - a histogram of the delta ratios of successful operations
+#[derive(Debug, Default)]
+pub struct PrometheusRecorder {
+    stats: Mutex<[Stats; 2]>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    operations: u64,
+    errors: u64,
+    data_bytes: u64,
+    delta_bytes: u64,
+    duration: Histogram<{ DURATION_BUCKETS.len() }>,
+    ratio: Histogram<{ RATIO_BUCKETS.len() }>,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram<const N: usize> {
+    /// Observations per bucket, not cumulative.
+    buckets: [u64; N],
+    /// Observations above the last bound.
+    overflow: u64,
+    sum: f64,
+}
+
+impl<const N: usize> Default for Histogram<N> {
+    fn default() -> Self {
+        Self {
+            buckets: [0; N],
+            overflow: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl<const N: usize> Histogram<N> {
+    fn observe(&mut self, bounds: &[f64; N], value: f64) {
+        match bounds.iter().position(|&bound| value <= bound) {
+            Some(i) => self.buckets[i] += 1,
+            None => self.overflow += 1,
+        }
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, operation: &str, bounds: &[f64; N]) {
+        let mut count = 0;
+        for (bound, observed) in bounds.iter().zip(self.buckets) {
+            count += observed;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{operation=\"{operation}\",le=\"{bound:?}\"}} {count}"
+            );
+        }
+        count += self.overflow;
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{operation=\"{operation}\",le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(out, "{name}_count{{operation=\"{operation}\"}} {count}");
+        let _ = writeln!(
+            out,
+            "{name}_sum{{operation=\"{operation}\"}} {:?}",
+            self.sum
+        );
+    }
+}
+
+impl PrometheusRecorder {
+    /// Media type of [`render`](Self::render)'s output, for the `Content-Type` of a scrape
+    /// response.
+    pub const CONTENT_TYPE: &'static str =
+        "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+    /// Creates a recorder with all metrics at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders the metrics in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let operations = [Operation::Encode, Operation::Decode];
+        let mut out = String::new();
+
+        let counters = [
+            (
+                "xpatch_operations",
+                "Finished operations.",
+                stats.each_ref().map(|s| s.operations),
+            ),
+            (
+                "xpatch_errors",
+                "Failed operations.",
+                stats.each_ref().map(|s| s.errors),
+            ),
+            (
+                "xpatch_data_bytes",
+                "Bytes of new data handled by successful operations.",
+                stats.each_ref().map(|s| s.data_bytes),
+            ),
+            (
+                "xpatch_delta_bytes",
+                "Bytes of deltas handled by successful operations.",
+                stats.each_ref().map(|s| s.delta_bytes),
+            ),
+        ];
+        for (name, help, values) in counters {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "# HELP {name} {help}");
+            for (operation, value) in operations.iter().zip(values) {
+                let _ = writeln!(
+                    out,
+                    "{name}_total{{operation=\"{}\"}} {value}",
+                    operation.name()
+                );
+            }
+        }
+
+        let _ = writeln!(out, "# TYPE xpatch_duration_seconds histogram");
+        let _ = writeln!(
+            out,
+            "# HELP xpatch_duration_seconds Duration of operations."
+        );
+        let _ = writeln!(out, "# UNIT xpatch_duration_seconds seconds");
+        for (operation, stats) in operations.iter().zip(&stats) {
+            stats.duration.render(
+                &mut out,
+                "xpatch_duration_seconds",
+                operation.name(),
+                &DURATION_BUCKETS,
+            );
+        }
+
+        let _ = writeln!(out, "# TYPE xpatch_delta_ratio histogram");
+        let _ = writeln!(
+            out,
+            "# HELP xpatch_delta_ratio Delta size relative to the new data."
+        );
+        for (operation, stats) in operations.iter().zip(&stats) {
+            stats.ratio.render(
+                &mut out,
+                "xpatch_delta_ratio",
+                operation.name(),
+                &RATIO_BUCKETS,
+            );
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+impl Recorder for PrometheusRecorder {
+    fn record(&self, event: &Event) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = &mut stats[event.operation as usize];
+        stats.operations += 1;
+        stats
+            .duration
+            .observe(&DURATION_BUCKETS, event.duration.as_secs_f64());
+        if event.error.is_some() {
+            stats.errors += 1;
+            return;
+        }
+        stats.data_bytes += event.data_size as u64;
+        stats.delta_bytes += event.delta_size as u64;
+        if let Some(ratio) = event.ratio() {
+            stats.ratio.observe(&RATIO_BUCKETS, ratio);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_recorder() {
+        let recorder = PrometheusRecorder::new();
+        recorder.record(&Event {
+            operation: Operation::Encode,
+            duration: Duration::from_millis(2),
+            data_size: 1000,
+            delta_size: 40,
+            error: None,
+        });
+        recorder.record(&Event {
+            operation: Operation::Decode,
+            duration: Duration::from_micros(50),
+            data_size: 0,
+            delta_size: 40,
+            error: Some("Base checksum mismatch"),
+        });
+
+        let text = recorder.render();
+        assert!(text.contains("xpatch_operations_total{operation=\"encode\"} 1\n"));
+        assert!(text.contains("xpatch_errors_total{operation=\"decode\"} 1\n"));
+        assert!(text.contains("xpatch_data_bytes_total{operation=\"encode\"} 1000\n"));
+        assert!(text.contains("xpatch_delta_bytes_total{operation=\"decode\"} 0\n"));
+        assert!(text.contains(
+            "xpatch_duration_seconds_bucket{operation=\"encode\",le=\"0.001\"} 0\n\
+             xpatch_duration_seconds_bucket{operation=\"encode\",le=\"0.005\"} 1\n"
+        ));
+        assert!(text.contains("xpatch_delta_ratio_bucket{operation=\"encode\",le=\"0.05\"} 1\n"));
+        assert!(text.contains("xpatch_delta_ratio_count{operation=\"decode\"} 0\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
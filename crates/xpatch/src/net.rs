@@ -0,0 +1,726 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Fetch-and-apply updates from an [`xpatch-serve`] server.
+//!
+//! [`Updater`] reads a [`Manifest`] naming the current version of a file, downloads a patch from
+//! the local file's version to it, checks the result against the manifest's content hash and
+//! replaces the file atomically. Patch downloads resume where an interrupted attempt stopped.
+//!
+//! [`Updater::update_resumable`] goes through a [`ResumableApply`] instead, which journals its
+//! progress so an update interrupted while downloading or applying continues where it stopped.
+//!
+//! Manifests can be signed with Ed25519: the server signs with `--signing-key` and the updater
+//! rejects unsigned or wrongly signed manifests once given the public key.
+//!
+//! [`xpatch-serve`]: https://github.com/ImGajeed76/xpatch/tree/main/crates/xpatch/src/bin/serve
+//!
+//! # Example
+//!
+//! ```no_run
+//! use xpatch::net::{UpdateStatus, Updater};
+//!
+//! let updater = Updater::new("https://updates.example.com/manifest");
+//! match updater.update("app.bin")? {
+//!     UpdateStatus::UpToDate(version) => println!("Already at {}", version),
+//!     UpdateStatus::Updated { to, .. } => println!("Updated to {}", to),
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+#[cfg(feature = "http")]
+mod resume;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+#[cfg(feature = "http")]
+pub use resume::{ApplyPhase, DEFAULT_CHECKPOINT_INTERVAL, ResumableApply};
+
+#[cfg(feature = "http")]
+use crate::delta;
+use crate::store::ContentHash;
+use ed25519_dalek::{Signature, Signer, Verifier};
+#[cfg(feature = "http")]
+use std::fs;
+#[cfg(feature = "http")]
+use std::io::Write;
+use std::io::{self, ErrorKind};
+#[cfg(feature = "http")]
+use std::path::{Path, PathBuf};
+
+/// Prefix of the signed manifest bytes, so a signature cannot be replayed for other data.
+const SIGNATURE_CONTEXT: &[u8] = b"xpatch-manifest\0";
+
+/// The version a server currently offers for a ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Ref the manifest was requested for
+    pub name: String,
+    /// Content hash of the version
+    pub version: ContentHash,
+    /// Size of the version in bytes
+    pub size: u64,
+    /// Ed25519 signature over the version and size
+    pub signature: Option<[u8; 64]>,
+}
+
+impl Manifest {
+    /// Creates an unsigned manifest.
+    pub fn new(name: impl Into<String>, version: ContentHash, size: u64) -> Self {
+        Self {
+            name: name.into(),
+            version,
+            size,
+            signature: None,
+        }
+    }
+
+    /// Parses the JSON form served at `/manifest`.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|_| invalid_data("Invalid manifest"))?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| invalid_data("Invalid manifest"))
+        };
+
+        let name = field("ref")?.as_str().unwrap_or_default().to_string();
+        let version = field("version")?
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid_data("Invalid manifest"))?;
+        let size = field("size")?
+            .as_u64()
+            .ok_or_else(|| invalid_data("Invalid manifest"))?;
+        let signature = match value.get("signature").and_then(|s| s.as_str()) {
+            Some(hex) => Some(
+                from_hex(hex)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| invalid_data("Invalid manifest signature"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            name,
+            version,
+            size,
+            signature,
+        })
+    }
+
+    /// Serializes the manifest to JSON.
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::json!({
+            "ref": self.name,
+            "version": self.version.to_string(),
+            "size": self.size,
+        });
+        if let Some(signature) = &self.signature {
+            value["signature"] = to_hex(signature).into();
+        }
+        value.to_string()
+    }
+
+    /// Signs the version and size.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(key.sign(&self.signed_bytes()).to_bytes());
+    }
+
+    /// Checks the signature against `key`.
+    pub fn verify(&self, key: &VerifyingKey) -> io::Result<()> {
+        let signature = self
+            .signature
+            .ok_or_else(|| invalid_data("Manifest is not signed"))?;
+        key.verify(&self.signed_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| invalid_data("Manifest signature mismatch"))
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNATURE_CONTEXT.to_vec();
+        bytes.extend_from_slice(self.version.as_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+}
+
+/// Outcome of [`Updater::update`].
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// The file already matches the manifest
+    UpToDate(ContentHash),
+    /// The file was replaced with the manifest's version
+    Updated {
+        /// Version of the replaced file, or `None` if it did not exist
+        from: Option<ContentHash>,
+        /// Version of the new file
+        to: ContentHash,
+    },
+}
+
+/// Downloads and applies patches from an `xpatch-serve` server.
+#[cfg(feature = "http")]
+pub struct Updater {
+    manifest_url: String,
+    agent: ureq::Agent,
+    public_key: Option<VerifyingKey>,
+}
+
+#[cfg(feature = "http")]
+impl Updater {
+    /// Creates an updater for the manifest at `manifest_url`.
+    ///
+    /// Patches are fetched from the same server: the part of the URL before `/manifest`,
+    /// followed by `/patch/<from>/<to>`.
+    pub fn new(manifest_url: impl Into<String>) -> Self {
+        Self {
+            manifest_url: manifest_url.into(),
+            agent: agent(),
+            public_key: None,
+        }
+    }
+
+    /// Requires manifests to be signed by `key`.
+    pub fn with_public_key(mut self, key: VerifyingKey) -> Self {
+        self.public_key = Some(key);
+        self
+    }
+
+    /// Downloads the manifest, checking its signature if a public key is set.
+    pub fn fetch_manifest(&self) -> io::Result<Manifest> {
+        let mut response = self.get(&self.manifest_url, None)?;
+        if response.status() != 200 {
+            return Err(http_error(response.status().as_u16()));
+        }
+        let json = response
+            .body_mut()
+            .read_to_string()
+            .map_err(ureq::Error::into_io)?;
+
+        let manifest = Manifest::from_json(&json)?;
+        if let Some(key) = &self.public_key {
+            manifest.verify(key)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Brings the file at `path` to the manifest's version.
+    ///
+    /// A missing file is downloaded in full. The new content is checked against the manifest
+    /// before it replaces the file, which happens by renaming, so readers never see a partial
+    /// file. If the download is interrupted, the next call resumes it.
+    pub fn update(&self, path: impl AsRef<Path>) -> io::Result<UpdateStatus> {
+        let path = path.as_ref();
+        let manifest = self.fetch_manifest()?;
+
+        let current = match fs::read(path) {
+            Ok(data) => Some(data),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let from = current.as_deref().map(ContentHash::of);
+        if from == Some(manifest.version) {
+            return Ok(UpdateStatus::UpToDate(manifest.version));
+        }
+
+        // The server may not know our version; fall back to a full download
+        let (base, part_path, patch) = match self.download(path, from, manifest.version) {
+            Err(e) if e.kind() == ErrorKind::NotFound && from.is_some() => {
+                let (part_path, patch) = self.download(path, None, manifest.version)?;
+                (&[][..], part_path, patch)
+            }
+            result => {
+                let (part_path, patch) = result?;
+                (current.as_deref().unwrap_or_default(), part_path, patch)
+            }
+        };
+
+        let new = delta::decode(base, &patch);
+        let new = match new {
+            Ok(new)
+                if new.len() as u64 == manifest.size
+                    && ContentHash::of(&new) == manifest.version =>
+            {
+                new
+            }
+            _ => {
+                // Don't resume from a corrupt download
+                let _ = fs::remove_file(&part_path);
+                return Err(invalid_data("Patched file does not match manifest"));
+            }
+        };
+
+        replace_file(path, &new)?;
+        let _ = fs::remove_file(&part_path);
+        Ok(UpdateStatus::Updated {
+            from,
+            to: manifest.version,
+        })
+    }
+
+    /// Brings the file at `path` to the manifest's version through a [`ResumableApply`].
+    ///
+    /// Unlike [`update`](Self::update), this also checkpoints applying the patch and never holds
+    /// the new file in memory. If an update of `path` to the same version was interrupted, it is
+    /// resumed; the journal of an update to an older version is discarded.
+    pub fn update_resumable(&self, path: impl AsRef<Path>) -> io::Result<UpdateStatus> {
+        let path = path.as_ref();
+        let manifest = self.fetch_manifest()?;
+        let from = match fs::read(path) {
+            Ok(data) => Some(ContentHash::of(&data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if from == Some(manifest.version) {
+            ResumableApply::discard(path)?;
+            return Ok(UpdateStatus::UpToDate(manifest.version));
+        }
+
+        let apply = |from| {
+            let url = self.patch_url(from, manifest.version)?;
+            ResumableApply::new(path, url, from, manifest.version, manifest.size)?.run()
+        };
+        match apply(from) {
+            // The server may not know our version; fall back to a full download
+            Err(e) if e.kind() == ErrorKind::NotFound && from.is_some() => apply(None)?,
+            result => result?,
+        }
+        Ok(UpdateStatus::Updated {
+            from,
+            to: manifest.version,
+        })
+    }
+
+    /// Downloads a patch into a partial file next to `path`, resuming an earlier attempt.
+    fn download(
+        &self,
+        path: &Path,
+        from: Option<ContentHash>,
+        to: ContentHash,
+    ) -> io::Result<(PathBuf, Vec<u8>)> {
+        let url = self.patch_url(from, to)?;
+        let from_name = from.map_or("none".to_string(), |hash| hash.to_string());
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let part_path = path.with_file_name(format!(
+            ".{}.{}-{}.part",
+            file_name,
+            &from_name[..from_name.len().min(16)],
+            &to.to_string()[..16]
+        ));
+
+        // Patches addressed by hash never change, so a plain range request is safe
+        let resume_from = fs::metadata(&part_path).map_or(0, |m| m.len());
+        let range = (resume_from > 0).then(|| format!("bytes={}-", resume_from));
+        let mut response = self.get(&url, range.as_deref())?;
+
+        let mut part = match response.status().as_u16() {
+            206 => fs::OpenOptions::new().append(true).open(&part_path)?,
+            200 => fs::File::create(&part_path)?,
+            // Everything was downloaded before the interruption
+            416 if resume_from > 0 => return Ok((part_path.clone(), fs::read(&part_path)?)),
+            404 => return Err(io::Error::new(ErrorKind::NotFound, "Patch not found")),
+            status => return Err(http_error(status)),
+        };
+        io::copy(&mut response.body_mut().as_reader(), &mut part)?;
+        part.flush()?;
+        drop(part);
+
+        let patch = fs::read(&part_path)?;
+        Ok((part_path, patch))
+    }
+
+    fn patch_url(&self, from: Option<ContentHash>, to: ContentHash) -> io::Result<String> {
+        let base_url = self
+            .manifest_url
+            .rfind("/manifest")
+            .map(|end| &self.manifest_url[..end])
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Invalid manifest URL"))?;
+        let from_name = from.map_or("none".to_string(), |hash| hash.to_string());
+        Ok(format!("{}/patch/{}/{}", base_url, from_name, to))
+    }
+
+    fn get(&self, url: &str, range: Option<&str>) -> io::Result<ureq::http::Response<ureq::Body>> {
+        let mut request = self.agent.get(url);
+        if let Some(range) = range {
+            request = request.header("Range", range);
+        }
+        request.call().map_err(ureq::Error::into_io)
+    }
+}
+
+#[cfg(feature = "http")]
+fn agent() -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .build()
+        .into()
+}
+
+/// Writes `data` next to `path` and renames it over `path`, keeping the old permissions.
+#[cfg(feature = "http")]
+fn replace_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.xpatch-tmp", file_name));
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(feature = "http")]
+fn http_error(status: u16) -> io::Error {
+    io::Error::other(format!("HTTP status {}", status))
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    #[test]
+    fn test_manifest_json_roundtrip() {
+        let mut manifest = Manifest::new("latest", ContentHash::of(b"v2"), 2);
+        assert_eq!(Manifest::from_json(&manifest.to_json()).unwrap(), manifest);
+
+        manifest.sign(&signing_key());
+        assert_eq!(Manifest::from_json(&manifest.to_json()).unwrap(), manifest);
+        assert!(Manifest::from_json("{}").is_err());
+        assert!(Manifest::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_manifest_signature() {
+        let key = signing_key();
+        let mut manifest = Manifest::new("latest", ContentHash::of(b"v2"), 2);
+        assert!(manifest.verify(&key.verifying_key()).is_err());
+
+        manifest.sign(&key);
+        manifest.verify(&key.verifying_key()).unwrap();
+
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        assert!(manifest.verify(&other.verifying_key()).is_err());
+
+        manifest.size = 3;
+        assert!(manifest.verify(&key.verifying_key()).is_err());
+    }
+
+    #[cfg(feature = "http")]
+    mod updater {
+        use super::*;
+        use std::sync::{Arc, Mutex};
+        use tiny_http::{Header, Response, Server};
+
+        /// Serves a manifest for `new` and patches to it from `old` or from nothing.
+        struct TestServer {
+            url: String,
+            ranges: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl TestServer {
+            fn start(old: &[u8], new: &[u8], manifest: Manifest) -> Self {
+                let server = Server::http("127.0.0.1:0").unwrap();
+                let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+                let to = ContentHash::of(new);
+                let routes = [
+                    ("/manifest".to_string(), manifest.to_json().into_bytes()),
+                    (
+                        format!("/patch/{}/{}", ContentHash::of(old), to),
+                        delta::encode(0, old, new, true),
+                    ),
+                    (
+                        format!("/patch/none/{}", to),
+                        delta::encode(0, &[], new, true),
+                    ),
+                ];
+
+                let ranges = Arc::new(Mutex::new(Vec::new()));
+                let seen = ranges.clone();
+                std::thread::spawn(move || {
+                    for request in server.incoming_requests() {
+                        let body = routes.iter().find(|(path, _)| path == request.url());
+                        let Some((_, body)) = body else {
+                            let _ = request.respond(Response::empty(404));
+                            continue;
+                        };
+                        let range = request
+                            .headers()
+                            .iter()
+                            .find(|h| h.field.equiv("Range"))
+                            .map(|h| h.value.to_string());
+                        let start = range.as_deref().and_then(|r| {
+                            r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()
+                        });
+                        let response = match start {
+                            Some(start) => {
+                                seen.lock().unwrap().push(range.unwrap());
+                                let header = Header::from_bytes(
+                                    &b"Content-Range"[..],
+                                    format!("bytes {}-{}/{}", start, body.len() - 1, body.len()),
+                                )
+                                .unwrap();
+                                Response::from_data(body[start..].to_vec())
+                                    .with_status_code(206)
+                                    .with_header(header)
+                            }
+                            None => Response::from_data(body.clone()),
+                        };
+                        let _ = request.respond(response);
+                    }
+                });
+                Self { url, ranges }
+            }
+
+            fn manifest_url(&self) -> String {
+                format!("{}/manifest", self.url)
+            }
+        }
+
+        fn temp_file(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!(
+                "xpatch-net-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir.join("app.bin")
+        }
+
+        fn versions() -> (Vec<u8>, Vec<u8>) {
+            let old: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+            let mut new = old.clone();
+            new.splice(5_000..5_000, b"new feature".iter().copied());
+            (old, new)
+        }
+
+        fn manifest_for(new: &[u8]) -> Manifest {
+            Manifest::new("latest", ContentHash::of(new), new.len() as u64)
+        }
+
+        #[test]
+        fn test_update_applies_patch() {
+            let (old, new) = versions();
+            let server = TestServer::start(&old, &new, manifest_for(&new));
+            let path = temp_file("patch");
+            fs::write(&path, &old).unwrap();
+
+            let updater = Updater::new(server.manifest_url());
+            let status = updater.update(&path).unwrap();
+            assert_eq!(
+                status,
+                UpdateStatus::Updated {
+                    from: Some(ContentHash::of(&old)),
+                    to: ContentHash::of(&new),
+                }
+            );
+            assert_eq!(fs::read(&path).unwrap(), new);
+            assert_eq!(
+                updater.update(&path).unwrap(),
+                UpdateStatus::UpToDate(ContentHash::of(&new))
+            );
+            // No partial or temporary files are left behind
+            assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_update_downloads_missing_and_unknown_files() {
+            let (old, new) = versions();
+            let server = TestServer::start(&old, &new, manifest_for(&new));
+            let updater = Updater::new(server.manifest_url());
+
+            let path = temp_file("full");
+            let status = updater.update(&path).unwrap();
+            assert!(matches!(status, UpdateStatus::Updated { from: None, .. }));
+            assert_eq!(fs::read(&path).unwrap(), new);
+
+            // The server has no patch from this version
+            fs::write(&path, b"locally modified").unwrap();
+            updater.update(&path).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), new);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_update_resumes_partial_download() {
+            let (old, new) = versions();
+            let server = TestServer::start(&old, &new, manifest_for(&new));
+            let path = temp_file("resume");
+            fs::write(&path, &old).unwrap();
+
+            // Leave half of the patch behind as if the download had been interrupted
+            let patch = delta::encode(0, &old, &new, true);
+            let from = ContentHash::of(&old).to_string();
+            let to = ContentHash::of(&new).to_string();
+            let part = path.with_file_name(format!(".app.bin.{}-{}.part", &from[..16], &to[..16]));
+            fs::write(&part, &patch[..patch.len() / 2]).unwrap();
+
+            Updater::new(server.manifest_url()).update(&path).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), new);
+            assert_eq!(
+                *server.ranges.lock().unwrap(),
+                vec![format!("bytes={}-", patch.len() / 2)]
+            );
+            assert!(!part.exists());
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_update_resumable() {
+            let (old, new) = versions();
+            let server = TestServer::start(&old, &new, manifest_for(&new));
+            let path = temp_file("resumable");
+            fs::write(&path, &old).unwrap();
+
+            let updater = Updater::new(server.manifest_url());
+            let status = updater.update_resumable(&path).unwrap();
+            assert!(matches!(
+                status,
+                UpdateStatus::Updated { from: Some(_), .. }
+            ));
+            assert_eq!(fs::read(&path).unwrap(), new);
+            assert_eq!(
+                updater.update_resumable(&path).unwrap(),
+                UpdateStatus::UpToDate(ContentHash::of(&new))
+            );
+            assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_resumable_apply_survives_interruptions() {
+            // Incompressible content, so the full patch spans many checkpoints
+            let mut state = 1u32;
+            let new: Vec<u8> = (0..40_000)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (state >> 24) as u8
+                })
+                .collect();
+            let server = TestServer::start(b"", &new, manifest_for(&new));
+            let path = temp_file("interrupted");
+            let url = format!("{}/patch/none/{}", server.url, ContentHash::of(&new));
+            let open = || {
+                ResumableApply::new(&path, &url, None, ContentHash::of(&new), new.len() as u64)
+                    .unwrap()
+                    .checkpoint_interval(4096)
+            };
+
+            // Interrupt the download after two checkpoints
+            let mut apply = open();
+            assert_eq!(apply.step().unwrap(), ApplyPhase::Download);
+            assert_eq!(apply.step().unwrap(), ApplyPhase::Download);
+            assert_eq!(apply.downloaded(), 8192);
+            drop(apply);
+
+            let mut apply = open();
+            assert_eq!(apply.downloaded(), 8192);
+            while apply.step().unwrap() == ApplyPhase::Download {}
+            assert_eq!(*server.ranges.lock().unwrap(), vec!["bytes=8192-"]);
+            assert_eq!(apply.phase(), ApplyPhase::Apply);
+            assert_eq!(apply.step().unwrap(), ApplyPhase::Commit);
+            drop(apply);
+
+            // Pretend applying stopped after a checkpoint at 8 KiB, with garbage written after it
+            let journal_path = path.with_file_name(".app.bin.xpatch-journal");
+            let new_path = path.with_file_name(".app.bin.xpatch-new");
+            let mut journal: serde_json::Value =
+                serde_json::from_slice(&fs::read(&journal_path).unwrap()).unwrap();
+            journal["phase"] = "apply".into();
+            journal["applied"] = 8192.into();
+            fs::write(&journal_path, journal.to_string()).unwrap();
+            let mut partial = new[..8192].to_vec();
+            partial.extend_from_slice(&[0; 1000]);
+            fs::write(&new_path, partial).unwrap();
+
+            let mut apply = open();
+            assert_eq!(apply.applied(), 8192);
+            apply.run().unwrap();
+            assert_eq!(fs::read(&path).unwrap(), new);
+            assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+            // A journal for another update is discarded
+            fs::write(&journal_path, journal.to_string()).unwrap();
+            let other = ResumableApply::new(&path, &url, None, ContentHash::of(b"x"), 1).unwrap();
+            assert_eq!(other.phase(), ApplyPhase::Download);
+            assert!(!journal_path.exists());
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_update_rejects_bad_manifests() {
+            let (old, new) = versions();
+            let key = signing_key();
+            let path = temp_file("signed");
+            fs::write(&path, &old).unwrap();
+
+            // Unsigned manifest while a key is required
+            let server = TestServer::start(&old, &new, manifest_for(&new));
+            let updater = Updater::new(server.manifest_url()).with_public_key(key.verifying_key());
+            assert!(updater.update(&path).is_err());
+            assert_eq!(fs::read(&path).unwrap(), old);
+
+            // Manifest naming different content than the patch produces
+            let mut wrong = Manifest::new("latest", ContentHash::of(b"other"), new.len() as u64);
+            wrong.sign(&key);
+            let server = TestServer::start(&old, &new, wrong);
+            let updater = Updater::new(server.manifest_url()).with_public_key(key.verifying_key());
+            assert!(updater.update(&path).is_err());
+            assert_eq!(fs::read(&path).unwrap(), old);
+
+            // Correctly signed
+            let mut manifest = manifest_for(&new);
+            manifest.sign(&key);
+            let server = TestServer::start(&old, &new, manifest);
+            let updater = Updater::new(server.manifest_url()).with_public_key(key.verifying_key());
+            updater.update(&path).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), new);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+}
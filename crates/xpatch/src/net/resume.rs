@@ -0,0 +1,415 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Checkpointed download and apply of a single patch.
+
+use super::{agent, http_error, invalid_data};
+use crate::delta;
+use crate::store::{ContentHash, write_atomic};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Default amount of data downloaded or written between two checkpoints.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1024 * 1024;
+
+/// Step a [`ResumableApply`] is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyPhase {
+    /// Downloading the patch
+    Download,
+    /// Applying the downloaded patch to a new file next to the target
+    Apply,
+    /// The new file is complete and verified, and replaces the target next
+    Commit,
+    /// The target was replaced and the journal removed
+    Done,
+}
+
+impl ApplyPhase {
+    fn name(self) -> &'static str {
+        match self {
+            ApplyPhase::Download => "download",
+            ApplyPhase::Apply => "apply",
+            ApplyPhase::Commit => "commit",
+            ApplyPhase::Done => "done",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            ApplyPhase::Download,
+            ApplyPhase::Apply,
+            ApplyPhase::Commit,
+            ApplyPhase::Done,
+        ]
+        .into_iter()
+        .find(|phase| phase.name() == name)
+    }
+}
+
+/// Downloads a patch and applies it to a file in checkpointed steps, so an interrupted update
+/// resumes where it stopped instead of starting over.
+///
+/// Progress is recorded in a journal next to the target (`.<name>.xpatch-journal`): how much of
+/// the patch (`.<name>.xpatch-patch`) and of the new file (`.<name>.xpatch-new`) is safely on
+/// disk. Both are synced before each checkpoint, and anything written after the last one is
+/// discarded on resume. The target keeps its old content until the new file is complete and
+/// matches the expected content hash; it is then replaced by renaming.
+///
+/// Drive the update with [`step`](Self::step), which does at most one checkpoint interval of
+/// work, or [`run`](Self::run). After an error or a crash, create the `ResumableApply` again
+/// with the same arguments to resume.
+pub struct ResumableApply {
+    path: PathBuf,
+    agent: ureq::Agent,
+    journal: Journal,
+    checkpoint_interval: u64,
+    response: Option<ureq::BodyReader<'static>>,
+}
+
+/// State persisted between attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Journal {
+    url: String,
+    from: Option<ContentHash>,
+    to: ContentHash,
+    size: u64,
+    phase: ApplyPhase,
+    /// Bytes of the patch on disk
+    downloaded: u64,
+    /// Bytes of the new file on disk
+    applied: u64,
+}
+
+impl ResumableApply {
+    /// Starts updating the file at `path` from version `from` (`None` if it does not exist) to
+    /// version `to` of `size` bytes, using the patch at `url`.
+    ///
+    /// If the journal next to `path` records the same update, it is resumed. The journal and
+    /// partial files of any other update are discarded.
+    pub fn new(
+        path: impl AsRef<Path>,
+        url: impl Into<String>,
+        from: Option<ContentHash>,
+        to: ContentHash,
+        size: u64,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let fresh = Journal {
+            url: url.into(),
+            from,
+            to,
+            size,
+            phase: ApplyPhase::Download,
+            downloaded: 0,
+            applied: 0,
+        };
+
+        let journal = match fs::read(sibling(&path, "journal")) {
+            Ok(bytes) => match Journal::parse(&bytes) {
+                Some(journal)
+                    if (&journal.url, journal.from, journal.to, journal.size)
+                        == (&fresh.url, from, to, size) =>
+                {
+                    journal
+                }
+                _ => {
+                    Self::discard(&path)?;
+                    fresh
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => fresh,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            agent: agent(),
+            journal,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            response: None,
+        })
+    }
+
+    /// Removes the journal and partial files of any update of the file at `path`.
+    pub fn discard(path: impl AsRef<Path>) -> io::Result<()> {
+        for suffix in ["journal", "patch", "new"] {
+            match fs::remove_file(sibling(path.as_ref(), suffix)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets how many bytes are downloaded or written between checkpoints (default
+    /// [`DEFAULT_CHECKPOINT_INTERVAL`]).
+    pub fn checkpoint_interval(mut self, bytes: u64) -> Self {
+        self.checkpoint_interval = bytes.max(1);
+        self
+    }
+
+    /// Returns the step the update is at.
+    pub fn phase(&self) -> ApplyPhase {
+        self.journal.phase
+    }
+
+    /// Returns how many bytes of the patch are on disk.
+    pub fn downloaded(&self) -> u64 {
+        self.journal.downloaded
+    }
+
+    /// Returns how many bytes of the new file are on disk.
+    pub fn applied(&self) -> u64 {
+        self.journal.applied
+    }
+
+    /// Does the next piece of work and records it in the journal.
+    ///
+    /// Downloads up to one checkpoint interval of the patch, applies the downloaded patch
+    /// (checkpointing its output), or replaces the target. Returns the phase reached.
+    pub fn step(&mut self) -> io::Result<ApplyPhase> {
+        match self.journal.phase {
+            ApplyPhase::Download => self.download()?,
+            ApplyPhase::Apply => self.apply()?,
+            ApplyPhase::Commit => self.commit()?,
+            ApplyPhase::Done => {}
+        }
+        Ok(self.journal.phase)
+    }
+
+    /// Runs the remaining steps.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.step()? != ApplyPhase::Done {}
+        Ok(())
+    }
+
+    fn download(&mut self) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(&self.path, "patch"))?;
+        // Drop anything written after the last checkpoint
+        file.set_len(self.journal.downloaded)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let reader = match &mut self.response {
+            Some(reader) => reader,
+            None => {
+                let downloaded = self.journal.downloaded;
+                let mut request = self.agent.get(&self.journal.url);
+                if downloaded > 0 {
+                    request = request.header("Range", format!("bytes={}-", downloaded));
+                }
+                let response = request.call().map_err(ureq::Error::into_io)?;
+                match response.status().as_u16() {
+                    206 => {}
+                    // The server ignored the range
+                    200 => {
+                        file.set_len(0)?;
+                        file.seek(SeekFrom::Start(0))?;
+                    }
+                    // Everything was downloaded before the interruption
+                    416 if downloaded > 0 => {
+                        self.journal.phase = ApplyPhase::Apply;
+                        return self.save();
+                    }
+                    404 => return Err(io::Error::new(ErrorKind::NotFound, "Patch not found")),
+                    status => return Err(http_error(status)),
+                }
+                self.response.insert(response.into_body().into_reader())
+            }
+        };
+
+        let result = io::copy(&mut reader.take(self.checkpoint_interval), &mut file);
+        // Keep whatever arrived, even if the connection broke
+        file.sync_data()?;
+        self.journal.downloaded = file.stream_position()?;
+        match result {
+            Ok(copied) if copied < self.checkpoint_interval => {
+                self.response = None;
+                self.journal.phase = ApplyPhase::Apply;
+            }
+            Ok(_) => {}
+            Err(_) => self.response = None,
+        }
+        self.save()?;
+        result.map(drop)
+    }
+
+    fn apply(&mut self) -> io::Result<()> {
+        let patch = fs::read(sibling(&self.path, "patch"))?;
+        if let Some(from) = self.journal.from
+            && ContentHash::of(&fs::read(&self.path)?) != from
+        {
+            Self::discard(&self.path)?;
+            return Err(invalid_data("File changed during the update"));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(&self.path, "new"))?;
+        file.set_len(self.journal.applied)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let from_base = self.journal.from.is_some();
+        let journal_path = sibling(&self.path, "journal");
+        let mut out = CheckpointWriter {
+            file,
+            hasher: Sha256::new(),
+            position: 0,
+            skip: self.journal.applied,
+            unsynced: 0,
+            interval: self.checkpoint_interval,
+            journal: &mut self.journal,
+            journal_path: &journal_path,
+        };
+        // Decoding is deterministic, so the output written before the interruption is skipped
+        let result = if from_base {
+            delta::decode_with_base_reader(fs::File::open(&self.path)?, &patch, &mut out)
+        } else {
+            delta::decode_with_base_reader(io::Cursor::new(&[][..]), &patch, &mut out)
+        };
+        let hash = ContentHash::from_bytes(out.hasher.finalize().into());
+        let file = out.file;
+
+        match result {
+            Ok(written) if written == self.journal.size && hash == self.journal.to => {
+                file.sync_all()?;
+                self.journal.applied = written;
+                self.journal.phase = ApplyPhase::Commit;
+                self.save()
+            }
+            // A corrupt patch cannot be resumed
+            _ => {
+                drop(file);
+                Self::discard(&self.path)?;
+                Err(invalid_data("Patched file does not match manifest"))
+            }
+        }
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        let new_path = sibling(&self.path, "new");
+        if new_path.exists() {
+            if let Ok(metadata) = fs::metadata(&self.path) {
+                fs::set_permissions(&new_path, metadata.permissions())?;
+            }
+            fs::rename(&new_path, &self.path)?;
+        } else if ContentHash::of(&fs::read(&self.path)?) != self.journal.to {
+            // The new file should have been renamed over the target in an earlier attempt
+            Self::discard(&self.path)?;
+            return Err(invalid_data("Updated file is missing"));
+        }
+
+        self.journal.phase = ApplyPhase::Done;
+        Self::discard(&self.path)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        write_atomic(
+            &sibling(&self.path, "journal"),
+            self.journal.to_json().as_bytes(),
+        )
+    }
+}
+
+impl Journal {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+        let from = match value.get("from")? {
+            serde_json::Value::Null => None,
+            hash => Some(hash.as_str()?.parse().ok()?),
+        };
+        Some(Self {
+            url: value.get("url")?.as_str()?.to_string(),
+            from,
+            to: value.get("to")?.as_str()?.parse().ok()?,
+            size: value.get("size")?.as_u64()?,
+            phase: ApplyPhase::from_name(value.get("phase")?.as_str()?)?,
+            downloaded: value.get("downloaded")?.as_u64()?,
+            applied: value.get("applied")?.as_u64()?,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "url": self.url,
+            "from": self.from.map(|hash| hash.to_string()),
+            "to": self.to.to_string(),
+            "size": self.size,
+            "phase": self.phase.name(),
+            "downloaded": self.downloaded,
+            "applied": self.applied,
+        })
+        .to_string()
+    }
+}
+
+/// Writes decoded output after the part already on disk, checkpointing as it goes.
+struct CheckpointWriter<'a> {
+    file: fs::File,
+    hasher: Sha256,
+    /// Bytes of output produced so far
+    position: u64,
+    /// Bytes of output on disk from an earlier attempt
+    skip: u64,
+    unsynced: u64,
+    interval: u64,
+    journal: &'a mut Journal,
+    journal_path: &'a Path,
+}
+
+impl Write for CheckpointWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        let skipped = self
+            .skip
+            .saturating_sub(self.position)
+            .min(buf.len() as u64) as usize;
+        self.file.write_all(&buf[skipped..])?;
+        self.position += buf.len() as u64;
+        self.unsynced += (buf.len() - skipped) as u64;
+
+        if self.unsynced >= self.interval {
+            self.file.sync_data()?;
+            self.journal.applied = self.position;
+            write_atomic(self.journal_path, self.journal.to_json().as_bytes())?;
+            self.unsynced = 0;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Returns the path of a partial file of an update of `path`.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.xpatch-{}", file_name, suffix))
+}
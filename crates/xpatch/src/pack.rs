@@ -0,0 +1,749 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! `.xpk` pack files holding many versions with a random access index.
+//!
+//! A pack stores each version either as a snapshot (a delta against empty data) or as a delta
+//! against another version in the same pack. The index at the end of the file records each
+//! entry's content hash, base, tag and byte range, so [`PackReader`] can look an entry up by
+//! hash and read just its bytes. Entries are also usable directly as HTTP range requests.
+//!
+//! # Format
+//!
+//! ```text
+//! "XPK" 0x01 | payload* | index | footer
+//! index  = entry*
+//! entry  = hash[32] | flags u8 | base[32] | tag u64 | offset u64 | length u64 | size u64
+//!          | name_len u16 | name
+//! footer = index_offset u64 | entry_count u64 | index_crc32 u32 | "XPK" 0x01
+//! ```
+//!
+//! Integers are little-endian. Flag `0x01` marks a delta entry; for snapshots the base field is
+//! zero. Entries appear in write order and a delta's base always precedes it, unless flag `0x02`
+//! marks the base as external: content the reader already has, such as the previous version of
+//! a file being updated (see [`PackWriter::add_external`]).
+//!
+//! # Example
+//!
+//! ```
+//! use std::io::Cursor;
+//! use xpatch::pack::{PackReader, PackWriter};
+//!
+//! let mut writer = PackWriter::new(Vec::new(), true)?;
+//! writer.add_snapshot("v1.txt", 1, b"Hello, World!")?;
+//! let v2 = writer.add_delta("v2.txt", 2, b"Hello, World!", b"Hello, Rust World!")?;
+//! let pack = writer.finish()?;
+//!
+//! let mut reader = PackReader::new(Cursor::new(pack))?;
+//! assert_eq!(reader.entry(&v2).unwrap().tag, 2);
+//! assert_eq!(reader.get(&v2)?, b"Hello, Rust World!");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::delta;
+use crate::store::ContentHash;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Magic bytes at the start and end of a pack file.
+pub const PACK_MAGIC: [u8; 4] = *b"XPK\x01";
+
+/// Size of the fixed footer at the end of a pack file.
+const FOOTER_SIZE: usize = 8 + 8 + 4 + 4;
+
+/// Size of an index entry without its name.
+const ENTRY_FIXED_SIZE: usize = 32 + 1 + 32 + 8 + 8 + 8 + 8 + 2;
+
+const FLAG_DELTA: u8 = 0x01;
+const FLAG_EXTERNAL: u8 = 0x02;
+
+/// An entry in a pack's index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackEntry {
+    /// Hash of the reconstructed content
+    pub hash: ContentHash,
+    /// Entry this one is a delta against, or `None` for a snapshot
+    pub base: Option<ContentHash>,
+    /// Whether `base` is external content rather than an entry of the pack
+    pub external: bool,
+    /// Tag embedded in the entry's delta
+    pub tag: usize,
+    /// Offset of the entry's delta from the start of the pack
+    pub offset: u64,
+    /// Length of the entry's delta in bytes
+    pub length: u64,
+    /// Size of the reconstructed content in bytes
+    pub size: u64,
+    /// Name given when the entry was added (may be empty)
+    pub name: String,
+}
+
+/// Writes a pack file.
+///
+/// Call [`finish`](Self::finish) to write the index; a pack without it cannot be read.
+pub struct PackWriter<W: Write> {
+    inner: W,
+    enable_zstd: bool,
+    offset: u64,
+    entries: Vec<PackEntry>,
+    sizes: HashMap<ContentHash, u64>,
+}
+
+impl<W: Write> PackWriter<W> {
+    /// Starts a pack, writing its header to `inner`.
+    pub fn new(mut inner: W, enable_zstd: bool) -> io::Result<Self> {
+        inner.write_all(&PACK_MAGIC)?;
+        Ok(Self {
+            inner,
+            enable_zstd,
+            offset: PACK_MAGIC.len() as u64,
+            entries: Vec::new(),
+            sizes: HashMap::new(),
+        })
+    }
+
+    /// Adds `data` as a snapshot and returns its hash.
+    pub fn add_snapshot(&mut self, name: &str, tag: usize, data: &[u8]) -> io::Result<ContentHash> {
+        let hash = ContentHash::of(data);
+        let delta = delta::encode(tag, &[], data, self.enable_zstd);
+        self.add_encoded(name, hash, None, data.len() as u64, &delta)?;
+        Ok(hash)
+    }
+
+    /// Adds `data` as a delta against `base` and returns its hash.
+    ///
+    /// `base` must already be in the pack.
+    pub fn add_delta(
+        &mut self,
+        name: &str,
+        tag: usize,
+        base: &[u8],
+        data: &[u8],
+    ) -> io::Result<ContentHash> {
+        let hash = ContentHash::of(data);
+        let delta = delta::encode(tag, base, data, self.enable_zstd);
+        self.add_encoded(
+            name,
+            hash,
+            Some(ContentHash::of(base)),
+            data.len() as u64,
+            &delta,
+        )?;
+        Ok(hash)
+    }
+
+    /// Adds an already encoded delta.
+    ///
+    /// `hash` and `size` describe the content `delta` reconstructs; `base` must be `None` if
+    /// it was encoded against empty data, or else already be in the pack.
+    pub fn add_encoded(
+        &mut self,
+        name: &str,
+        hash: ContentHash,
+        base: Option<ContentHash>,
+        size: u64,
+        delta: &[u8],
+    ) -> io::Result<()> {
+        if base.is_some_and(|base| !self.sizes.contains_key(&base)) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Base not in pack"));
+        }
+        self.push(name, hash, base, false, size, delta)
+    }
+
+    /// Adds a delta against content that is not in the pack, such as a file the reader already
+    /// has.
+    ///
+    /// `hash` and `size` describe the content `delta` reconstructs and `base` the content it was
+    /// encoded against. Read the entry with [`PackReader::get_with_base`].
+    pub fn add_external(
+        &mut self,
+        name: &str,
+        hash: ContentHash,
+        base: ContentHash,
+        size: u64,
+        delta: &[u8],
+    ) -> io::Result<()> {
+        self.push(name, hash, Some(base), true, size, delta)
+    }
+
+    /// Returns `true` if an entry with this hash was added.
+    pub fn contains(&self, hash: &ContentHash) -> bool {
+        self.sizes.contains_key(hash)
+    }
+
+    fn push(
+        &mut self,
+        name: &str,
+        hash: ContentHash,
+        base: Option<ContentHash>,
+        external: bool,
+        size: u64,
+        delta: &[u8],
+    ) -> io::Result<()> {
+        if name.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Entry name too long",
+            ));
+        }
+        let tag = delta::get_tag(delta).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+        self.inner.write_all(delta)?;
+        self.entries.push(PackEntry {
+            hash,
+            base,
+            external,
+            tag,
+            offset: self.offset,
+            length: delta.len() as u64,
+            size,
+            name: name.to_string(),
+        });
+        self.sizes.insert(hash, size);
+        self.offset += delta.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the index and footer and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut index = Vec::new();
+        for entry in &self.entries {
+            index.extend_from_slice(entry.hash.as_bytes());
+            match entry.base {
+                Some(base) => {
+                    index.push(if entry.external {
+                        FLAG_DELTA | FLAG_EXTERNAL
+                    } else {
+                        FLAG_DELTA
+                    });
+                    index.extend_from_slice(base.as_bytes());
+                }
+                None => {
+                    index.push(0);
+                    index.extend_from_slice(&[0; 32]);
+                }
+            }
+            index.extend_from_slice(&(entry.tag as u64).to_le_bytes());
+            index.extend_from_slice(&entry.offset.to_le_bytes());
+            index.extend_from_slice(&entry.length.to_le_bytes());
+            index.extend_from_slice(&entry.size.to_le_bytes());
+            index.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            index.extend_from_slice(entry.name.as_bytes());
+        }
+
+        self.inner.write_all(&index)?;
+        self.inner.write_all(&self.offset.to_le_bytes())?;
+        self.inner
+            .write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        self.inner
+            .write_all(&crc32fast::hash(&index).to_le_bytes())?;
+        self.inner.write_all(&PACK_MAGIC)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads entries from a pack file.
+pub struct PackReader<R: Read + Seek> {
+    inner: R,
+    entries: Vec<PackEntry>,
+    lookup: HashMap<ContentHash, usize>,
+}
+
+impl PackReader<BufReader<File>> {
+    /// Opens the pack file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> PackReader<R> {
+    /// Reads the index of the pack in `inner`.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        inner.seek(SeekFrom::Start(0))?;
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| invalid_data("Not a pack file"))?;
+        if magic != PACK_MAGIC {
+            return Err(invalid_data("Not a pack file"));
+        }
+
+        let file_size = inner.seek(SeekFrom::End(0))?;
+        if file_size < (PACK_MAGIC.len() + FOOTER_SIZE) as u64 {
+            return Err(invalid_data("Truncated pack file"));
+        }
+        let mut footer = [0u8; FOOTER_SIZE];
+        inner.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        inner.read_exact(&mut footer)?;
+        if footer[20..] != PACK_MAGIC {
+            return Err(invalid_data("Truncated pack file"));
+        }
+        let index_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        let entry_count = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+        let index_crc = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+
+        let index_end = file_size - FOOTER_SIZE as u64;
+        if index_offset < PACK_MAGIC.len() as u64 || index_offset > index_end {
+            return Err(invalid_data("Corrupt pack index"));
+        }
+        let mut index = vec![0u8; (index_end - index_offset) as usize];
+        inner.seek(SeekFrom::Start(index_offset))?;
+        inner.read_exact(&mut index)?;
+        if crc32fast::hash(&index) != index_crc {
+            return Err(invalid_data("Pack index checksum mismatch"));
+        }
+
+        let entries = parse_index(&index, entry_count, index_offset)
+            .ok_or_else(|| invalid_data("Corrupt pack index"))?;
+        let mut lookup = HashMap::with_capacity(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            if !entry.external && entry.base.is_some_and(|base| !lookup.contains_key(&base)) {
+                return Err(invalid_data("Corrupt pack index"));
+            }
+            lookup.entry(entry.hash).or_insert(i);
+        }
+
+        Ok(Self {
+            inner,
+            entries,
+            lookup,
+        })
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the pack has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns all entries in write order.
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+
+    /// Looks up the entry for a content hash.
+    ///
+    /// If several entries have the same content, the first one is returned.
+    pub fn entry(&self, hash: &ContentHash) -> Option<&PackEntry> {
+        self.lookup.get(hash).map(|&i| &self.entries[i])
+    }
+
+    /// Reads an entry's encoded delta without decoding it.
+    pub fn read_raw(&mut self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        let entry = self.entry(hash).ok_or_else(not_found)?;
+        let (offset, length) = (entry.offset, entry.length);
+        self.read_range(offset, length)
+    }
+
+    /// Reconstructs an entry's content, applying its chain of deltas.
+    ///
+    /// The result is checked against `hash`. Entries built on external content need
+    /// [`get_with_base`](Self::get_with_base).
+    pub fn get(&mut self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        self.reconstruct(hash, None)
+    }
+
+    /// Reconstructs an entry whose chain of deltas starts at external content (see
+    /// [`PackWriter::add_external`]).
+    ///
+    /// `base` is checked against the hash recorded for the external content. Entries that
+    /// don't depend on external content ignore it.
+    pub fn get_with_base(&mut self, hash: &ContentHash, base: &[u8]) -> io::Result<Vec<u8>> {
+        self.reconstruct(hash, Some(base))
+    }
+
+    /// Checks every entry in the pack end to end.
+    ///
+    /// A first pass only parses each delta's header: it must carry the tag recorded in the
+    /// index, and an embedded [`Provenance`](delta::Provenance) record with SHA-256 digests must
+    /// name the same base and content hashes as the index. Then every entry is decoded once, in
+    /// write order, against its already verified base and checked against its hash and size.
+    /// Content is only kept while a later entry still uses it as a base, so a corrupted delta is
+    /// reported without reconstructing each version from its snapshot.
+    ///
+    /// Deltas against external content, and entries built on them, only have their headers
+    /// checked.
+    ///
+    /// The error names the first entry that failed.
+    pub fn verify(&mut self) -> io::Result<()> {
+        let entries = self.entries.clone();
+        for entry in &entries {
+            let delta = self.read_range(entry.offset, entry.length)?;
+            check_header(entry, &delta).map_err(|e| entry_error(entry, e))?;
+        }
+
+        let mut users: HashMap<ContentHash, usize> = HashMap::new();
+        for base in entries.iter().filter_map(|entry| entry.base) {
+            *users.entry(base).or_default() += 1;
+        }
+
+        let mut external = HashSet::new();
+        let mut content: HashMap<ContentHash, Vec<u8>> = HashMap::new();
+        for entry in &entries {
+            if entry.external || entry.base.is_some_and(|base| external.contains(&base)) {
+                external.insert(entry.hash);
+                continue;
+            }
+            let delta = self.read_range(entry.offset, entry.length)?;
+            let base = match entry.base {
+                Some(base) => content[&base].as_slice(),
+                None => &[],
+            };
+            let data = delta::decode(base, &delta).map_err(|e| entry_error(entry, e))?;
+            if data.len() as u64 != entry.size {
+                return Err(entry_error(entry, "Content size mismatch"));
+            }
+            if ContentHash::of(&data) != entry.hash {
+                return Err(entry_error(entry, "Content hash mismatch"));
+            }
+
+            if let Some(base) = entry.base {
+                let remaining = users.get_mut(&base).expect("base counted above");
+                *remaining -= 1;
+                if *remaining == 0 {
+                    content.remove(&base);
+                }
+            }
+            if users.get(&entry.hash).is_some_and(|&n| n > 0) {
+                content.entry(entry.hash).or_insert(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn reconstruct(&mut self, hash: &ContentHash, external: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut chain = Vec::new();
+        let mut data = Vec::new();
+        let mut current = self.entry(hash).ok_or_else(not_found)?;
+        loop {
+            chain.push((current.offset, current.length));
+            match current.base {
+                Some(base) if current.external => {
+                    let external = external.ok_or_else(|| {
+                        io::Error::new(ErrorKind::NotFound, "Entry needs an external base")
+                    })?;
+                    if ContentHash::of(external) != base {
+                        return Err(invalid_data("External base hash mismatch"));
+                    }
+                    data = external.to_vec();
+                    break;
+                }
+                Some(base) => current = self.entry(&base).ok_or_else(not_found)?,
+                None => break,
+            }
+        }
+
+        for &(offset, length) in chain.iter().rev() {
+            let delta = self.read_range(offset, length)?;
+            data = delta::decode(&data, &delta).map_err(invalid_data)?;
+        }
+
+        if ContentHash::of(&data) != *hash {
+            return Err(invalid_data("Content hash mismatch"));
+        }
+        Ok(data)
+    }
+
+    fn read_range(&mut self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; length as usize];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+fn parse_index(mut index: &[u8], entry_count: u64, payload_end: u64) -> Option<Vec<PackEntry>> {
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        if index.len() < ENTRY_FIXED_SIZE {
+            return None;
+        }
+        let (fixed, rest) = index.split_at(ENTRY_FIXED_SIZE);
+        let u64_at = |pos: usize| u64::from_le_bytes(fixed[pos..pos + 8].try_into().unwrap());
+
+        let hash = ContentHash::from_bytes(fixed[0..32].try_into().unwrap());
+        let base = ContentHash::from_bytes(fixed[33..65].try_into().unwrap());
+        let (base, external) = match fixed[32] {
+            0 => (None, false),
+            FLAG_DELTA => (Some(base), false),
+            flags if flags == FLAG_DELTA | FLAG_EXTERNAL => (Some(base), true),
+            _ => return None,
+        };
+        let tag = usize::try_from(u64_at(65)).ok()?;
+        let offset = u64_at(73);
+        let length = u64_at(81);
+        let size = u64_at(89);
+        let name_len = u16::from_le_bytes(fixed[97..99].try_into().unwrap()) as usize;
+
+        if offset < PACK_MAGIC.len() as u64 || offset.checked_add(length)? > payload_end {
+            return None;
+        }
+        if rest.len() < name_len {
+            return None;
+        }
+        let (name, rest) = rest.split_at(name_len);
+        let name = String::from_utf8(name.to_vec()).ok()?;
+        index = rest;
+
+        entries.push(PackEntry {
+            hash,
+            base,
+            external,
+            tag,
+            offset,
+            length,
+            size,
+            name,
+        });
+    }
+    index.is_empty().then_some(entries)
+}
+
+/// Checks what an entry's delta header says about it against the index.
+fn check_header(entry: &PackEntry, delta: &[u8]) -> Result<(), &'static str> {
+    let info = delta::inspect(delta)?;
+    if info.tag != entry.tag {
+        return Err("Tag does not match index");
+    }
+    if let Some(provenance) = info.provenance {
+        let base = entry.base.unwrap_or_else(|| ContentHash::of(&[]));
+        if provenance.source_hash.len() == 32 && provenance.source_hash != base.as_bytes() {
+            return Err("Provenance base hash does not match index");
+        }
+        if provenance.target_hash.len() == 32 && provenance.target_hash != entry.hash.as_bytes() {
+            return Err("Provenance content hash does not match index");
+        }
+    }
+    Ok(())
+}
+
+fn entry_error(entry: &PackEntry, message: &str) -> io::Error {
+    let name = if entry.name.is_empty() {
+        entry.hash.to_string()
+    } else {
+        entry.name.clone()
+    };
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Entry {}: {}", name, message),
+    )
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "Entry not found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn build_pack() -> (Vec<u8>, Vec<(ContentHash, Vec<u8>)>) {
+        let versions: Vec<Vec<u8>> = (0..5)
+            .map(|i| format!("line one\nline two\nversion {}\n", i).into_bytes())
+            .collect();
+
+        let mut writer = PackWriter::new(Vec::new(), true).unwrap();
+        let mut hashes = vec![writer.add_snapshot("v0", 0, &versions[0]).unwrap()];
+        for i in 1..versions.len() {
+            let name = format!("v{}", i);
+            hashes.push(
+                writer
+                    .add_delta(&name, i, &versions[i - 1], &versions[i])
+                    .unwrap(),
+            );
+        }
+        (
+            writer.finish().unwrap(),
+            hashes.into_iter().zip(versions).collect(),
+        )
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (pack, versions) = build_pack();
+        let mut reader = PackReader::new(Cursor::new(pack)).unwrap();
+
+        assert_eq!(reader.len(), versions.len());
+        for (i, (hash, content)) in versions.iter().enumerate() {
+            let entry = reader.entry(hash).unwrap();
+            assert_eq!(entry.tag, i);
+            assert_eq!(entry.name, format!("v{}", i));
+            assert_eq!(entry.size, content.len() as u64);
+            assert_eq!(entry.base.is_none(), i == 0);
+            assert_eq!(reader.get(hash).unwrap(), *content);
+        }
+    }
+
+    #[test]
+    fn test_read_raw_matches_range() {
+        let (pack, versions) = build_pack();
+        let mut reader = PackReader::new(Cursor::new(pack.clone())).unwrap();
+        let entry = reader.entry(&versions[3].0).unwrap().clone();
+
+        let raw = reader.read_raw(&entry.hash).unwrap();
+        let start = entry.offset as usize;
+        assert_eq!(raw, &pack[start..start + entry.length as usize]);
+        assert_eq!(delta::get_tag(&raw).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_empty_pack() {
+        let pack = PackWriter::new(Vec::new(), false)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let reader = PackReader::new(Cursor::new(pack)).unwrap();
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_delta_requires_base_in_pack() {
+        let mut writer = PackWriter::new(Vec::new(), true).unwrap();
+        let err = writer
+            .add_delta("x", 0, b"missing base", b"data")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_external_base() {
+        let base = b"installed version of the file".to_vec();
+        let v1 = b"installed version of the file, updated".to_vec();
+        let v2 = b"updated again".to_vec();
+
+        let mut writer = PackWriter::new(Vec::new(), false).unwrap();
+        let v1_hash = ContentHash::of(&v1);
+        writer
+            .add_external(
+                "app.txt",
+                v1_hash,
+                ContentHash::of(&base),
+                v1.len() as u64,
+                &delta::encode(0, &base, &v1, false),
+            )
+            .unwrap();
+        let v2_hash = writer.add_delta("app.txt", 0, &v1, &v2).unwrap();
+        let pack = writer.finish().unwrap();
+
+        let mut reader = PackReader::new(Cursor::new(pack)).unwrap();
+        let entry = reader.entry(&v1_hash).unwrap();
+        assert!(entry.external);
+        assert_eq!(entry.base, Some(ContentHash::of(&base)));
+        assert!(!reader.entry(&v2_hash).unwrap().external);
+
+        assert_eq!(reader.get_with_base(&v1_hash, &base).unwrap(), v1);
+        assert_eq!(reader.get_with_base(&v2_hash, &base).unwrap(), v2);
+        assert_eq!(
+            reader.get(&v2_hash).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            reader.get_with_base(&v2_hash, &v2).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        reader.verify().unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let (pack, versions) = build_pack();
+        let mut reader = PackReader::new(Cursor::new(pack.clone())).unwrap();
+        reader.verify().unwrap();
+
+        // Corrupt an intermediate delta
+        let entry = reader.entry(&versions[2].0).unwrap().clone();
+        let mut corrupt = pack;
+        corrupt[(entry.offset + entry.length - 1) as usize] ^= 0xFF;
+        let mut reader = PackReader::new(Cursor::new(corrupt)).unwrap();
+        let err = reader.verify().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Entry v2:"));
+    }
+
+    #[test]
+    fn test_verify_checks_provenance() {
+        let base = b"first version".to_vec();
+        let data = b"second version".to_vec();
+        let delta = delta::encode(1, &base, &data, false);
+        // Record the wrong target hash
+        let provenance = delta::Provenance::new(
+            ContentHash::of(&base).as_bytes().to_vec(),
+            ContentHash::of(&base).as_bytes().to_vec(),
+        );
+        let annotated = delta::annotate(&delta, &provenance).unwrap();
+
+        let mut writer = PackWriter::new(Vec::new(), false).unwrap();
+        let base_hash = writer.add_snapshot("v0", 0, &base).unwrap();
+        writer
+            .add_encoded(
+                "v1",
+                ContentHash::of(&data),
+                Some(base_hash),
+                data.len() as u64,
+                &annotated,
+            )
+            .unwrap();
+        let pack = writer.finish().unwrap();
+
+        let mut reader = PackReader::new(Cursor::new(pack)).unwrap();
+        let err = reader.verify().unwrap_err();
+        assert!(err.to_string().contains("Provenance content hash"));
+    }
+
+    #[test]
+    fn test_corrupt_pack_rejected() {
+        let (pack, versions) = build_pack();
+
+        assert!(PackReader::new(Cursor::new(b"nope".to_vec())).is_err());
+        assert!(PackReader::new(Cursor::new(pack[..pack.len() - 1].to_vec())).is_err());
+
+        // Corrupt the index
+        let mut corrupt = pack.clone();
+        let pos = corrupt.len() - FOOTER_SIZE - 3;
+        corrupt[pos] ^= 0xFF;
+        assert!(PackReader::new(Cursor::new(corrupt)).is_err());
+
+        // Corrupt a payload: detected on read
+        let mut corrupt = pack;
+        let reader = PackReader::new(Cursor::new(corrupt.clone())).unwrap();
+        let entry = reader.entry(&versions[0].0).unwrap();
+        let pos = (entry.offset + entry.length - 1) as usize;
+        corrupt[pos] ^= 0xFF;
+        let mut reader = PackReader::new(Cursor::new(corrupt)).unwrap();
+        assert!(reader.get(&versions[0].0).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Control over the threads xpatch runs parallel work on.
+//!
+//! Parallel work, such as encoding stream windows with [`StreamEncoder::set_parallel`], runs
+//! on rayon's global thread pool by default, which starts one thread per CPU the first time it
+//! is used. Embedders that need to cap xpatch's CPU usage or share their own pool call
+//! [`set_thread_pool`] once at startup:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use xpatch::ThreadPoolConfig;
+//!
+//! // Run on the host application's pool...
+//! let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+//! xpatch::set_thread_pool(ThreadPoolConfig::Pool(pool)).unwrap();
+//!
+//! // ...or never start any threads at all
+//! xpatch::set_thread_pool(ThreadPoolConfig::CallerThread).unwrap();
+//! # xpatch::set_thread_pool(ThreadPoolConfig::Global).unwrap();
+//! ```
+//!
+//! zstd's worker threads ([`EncodeOptions::zstd_threads`]) are started by zstd itself and are
+//! not affected; they stay off unless requested.
+//!
+//! [`StreamEncoder::set_parallel`]: crate::stream::StreamEncoder::set_parallel
+//! [`EncodeOptions::zstd_threads`]: crate::EncodeOptions::zstd_threads
+
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::sync::{Arc, RwLock};
+
+/// Where xpatch runs its parallel work, as set with [`set_thread_pool`].
+#[derive(Debug, Clone, Default)]
+pub enum ThreadPoolConfig {
+    /// rayon's global thread pool, shared with everything else in the process that uses it.
+    #[default]
+    Global,
+    /// A pool owned by the host application.
+    Pool(Arc<ThreadPool>),
+    /// A dedicated pool of this many threads, started by [`set_thread_pool`].
+    Threads(usize),
+    /// No extra threads: parallel work runs sequentially on the calling thread.
+    CallerThread,
+}
+
+/// The pool in effect.
+enum Pool {
+    Global,
+    Custom(Arc<ThreadPool>),
+    CallerThread,
+}
+
+static POOL: RwLock<Pool> = RwLock::new(Pool::Global);
+
+/// Sets where xpatch runs its parallel work, for all threads of the process.
+///
+/// Operations already running finish on the pool they started on.
+///
+/// # Errors
+/// Returns an error if a dedicated pool for [`ThreadPoolConfig::Threads`] could not be
+/// started; the previous setting is kept.
+pub fn set_thread_pool(config: ThreadPoolConfig) -> Result<(), ThreadPoolBuildError> {
+    let pool = match config {
+        ThreadPoolConfig::Global => Pool::Global,
+        ThreadPoolConfig::Pool(pool) => Pool::Custom(pool),
+        ThreadPoolConfig::Threads(threads) => Pool::Custom(Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("xpatch-{i}"))
+                .build()?,
+        )),
+        ThreadPoolConfig::CallerThread => Pool::CallerThread,
+    };
+    *POOL.write().unwrap_or_else(|e| e.into_inner()) = pool;
+    Ok(())
+}
+
+/// Number of threads parallel work is spread over; 1 when it runs on the calling thread.
+pub(crate) fn num_threads() -> usize {
+    match &*POOL.read().unwrap_or_else(|e| e.into_inner()) {
+        Pool::Global => rayon::current_num_threads(),
+        Pool::Custom(pool) => pool.current_num_threads(),
+        Pool::CallerThread => 1,
+    }
+}
+
+/// Runs `parallel` on the configured pool, or `sequential` if parallel work should stay on
+/// the calling thread.
+pub(crate) fn run<R: Send>(
+    parallel: impl FnOnce() -> R + Send,
+    sequential: impl FnOnce() -> R,
+) -> R {
+    let pool = match &*POOL.read().unwrap_or_else(|e| e.into_inner()) {
+        Pool::Global => None,
+        Pool::Custom(pool) => Some(Arc::clone(pool)),
+        Pool::CallerThread => return sequential(),
+    };
+    match pool {
+        Some(pool) => pool.install(parallel),
+        None => parallel(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_thread_pool() {
+        let on_pool = || run(|| rayon::current_thread_index().is_some(), || false);
+
+        set_thread_pool(ThreadPoolConfig::Threads(3)).unwrap();
+        assert_eq!(num_threads(), 3);
+        assert!(on_pool());
+
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+        set_thread_pool(ThreadPoolConfig::Pool(Arc::clone(&pool))).unwrap();
+        assert_eq!(num_threads(), 2);
+        assert!(on_pool());
+
+        set_thread_pool(ThreadPoolConfig::CallerThread).unwrap();
+        assert_eq!(num_threads(), 1);
+        assert!(!on_pool());
+
+        set_thread_pool(ThreadPoolConfig::Global).unwrap();
+        assert_eq!(num_threads(), rayon::current_num_threads());
+    }
+}
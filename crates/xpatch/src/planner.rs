@@ -0,0 +1,385 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Choosing between downloading a patch and the full file.
+//!
+//! A patch is not always the better deal: on a fast link a small file downloads quicker than
+//! a slow device can apply a patch to it, and a patch between distant versions can be almost as
+//! large as the file. [`choose_transfer`] compares the estimated time of both options from the
+//! link's throughput and latency and a [`CpuClass`] model of how fast the device decodes, and
+//! returns a [`Decision`] whose [`Transfer`] and [`Reason`] have stable names for showing in
+//! updater UIs or logging.
+//!
+//! The server describes what it offers for a version as a [`Target`]. Patch sizes are either
+//! known (published patches) or estimated with [`delta::estimate_size`](crate::delta::estimate_size),
+//! which is much cheaper than encoding every candidate.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::planner::{self, CpuClass, LinkSpeed, PatchOffer, Target, Transfer};
+//! use xpatch::store::ContentHash;
+//!
+//! let (installed, latest) = (vec![b'a'; 100_000], vec![b'b'; 100_000]);
+//! let target = Target {
+//!     hash: ContentHash::of(&latest),
+//!     size: latest.len() as u64,
+//!     patches: vec![PatchOffer::estimate(&installed, &latest)],
+//! };
+//!
+//! let base = ContentHash::of(&installed);
+//! let decision = planner::choose_transfer(Some(&base), &target, LinkSpeed::mbit(10), CpuClass::Mid);
+//! println!("{} ({})", decision.transfer.as_str(), decision.reason.as_str());
+//! ```
+
+use crate::delta;
+use crate::store::ContentHash;
+use std::time::Duration;
+
+/// The client's network connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkSpeed {
+    /// Download throughput in bytes per second
+    pub bytes_per_second: u64,
+    /// Round-trip time, paid once per download
+    pub latency: Duration,
+}
+
+impl LinkSpeed {
+    /// A link with `megabits` Mbit/s of throughput and 50 ms latency.
+    pub const fn mbit(megabits: u64) -> Self {
+        Self {
+            bytes_per_second: megabits * 125_000,
+            latency: Duration::from_millis(50),
+        }
+    }
+}
+
+/// How fast the client applies patches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuClass {
+    /// Microcontrollers, routers, old phones: about 40 MB/s of output
+    Low,
+    /// Phones and laptops: about 400 MB/s of output
+    Mid,
+    /// Desktops and servers: about 1.5 GB/s of output
+    High,
+    /// Measured decode throughput in bytes of output per second
+    Custom(u64),
+}
+
+impl CpuClass {
+    /// Returns the modelled decode throughput in bytes of output per second.
+    pub fn decode_bytes_per_second(self) -> u64 {
+        match self {
+            CpuClass::Low => 40_000_000,
+            CpuClass::Mid => 400_000_000,
+            CpuClass::High => 1_500_000_000,
+            CpuClass::Custom(bytes_per_second) => bytes_per_second.max(1),
+        }
+    }
+}
+
+/// A patch the server can send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchOffer {
+    /// Version the patch applies to
+    pub base: ContentHash,
+    /// Size of the patch in bytes
+    pub size: u64,
+    /// Whether `size` is an estimate rather than the size of an encoded patch
+    pub estimated: bool,
+}
+
+impl PatchOffer {
+    /// Offers a patch between two versions, sizing it with
+    /// [`delta::estimate_size`](crate::delta::estimate_size) instead of encoding it.
+    pub fn estimate(base_data: &[u8], target_data: &[u8]) -> Self {
+        Self {
+            base: ContentHash::of(base_data),
+            size: delta::estimate_size(base_data, target_data) as u64,
+            estimated: true,
+        }
+    }
+}
+
+/// What the server offers for a version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// Content hash of the version
+    pub hash: ContentHash,
+    /// Size of the full download in bytes
+    pub size: u64,
+    /// Patches to the version from older ones
+    pub patches: Vec<PatchOffer>,
+}
+
+#[cfg(feature = "manifest")]
+impl Target {
+    /// Describes the latest release of a manifest and the patches published to it.
+    pub fn from_manifest(manifest: &crate::manifest::Manifest) -> Option<Self> {
+        let latest = manifest.latest()?;
+        let patches = manifest
+            .patches
+            .iter()
+            .filter(|patch| patch.to == latest.hash)
+            .map(|patch| PatchOffer {
+                base: patch.from,
+                size: patch.size,
+                estimated: false,
+            })
+            .collect();
+        Some(Self {
+            hash: latest.hash,
+            size: latest.size,
+            patches,
+        })
+    }
+}
+
+/// What to download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// Nothing, the client already has the target
+    None,
+    /// The patch from the client's version
+    Patch(PatchOffer),
+    /// The complete target
+    Full,
+}
+
+impl Transfer {
+    /// Returns a stable name: `none`, `patch` or `full`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transfer::None => "none",
+            Transfer::Patch(_) => "patch",
+            Transfer::Full => "full",
+        }
+    }
+}
+
+/// Why a [`Transfer`] was chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The client's version is the target
+    UpToDate,
+    /// The client has no version, or none the server has a patch from
+    NoPatch,
+    /// Downloading and applying the patch is estimated to be faster
+    PatchFaster,
+    /// Downloading the full target is estimated to be faster
+    FullFaster,
+}
+
+impl Reason {
+    /// Returns a stable name: `up_to_date`, `no_patch`, `patch_faster` or `full_faster`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::UpToDate => "up_to_date",
+            Reason::NoPatch => "no_patch",
+            Reason::PatchFaster => "patch_faster",
+            Reason::FullFaster => "full_faster",
+        }
+    }
+}
+
+/// Result of [`choose_transfer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub transfer: Transfer,
+    pub reason: Reason,
+    /// Bytes to download
+    pub download_size: u64,
+    /// Estimated time to download and, for a patch, apply it
+    pub estimated_time: Duration,
+    /// Estimated time of the option not chosen, if there was one
+    pub alternative_time: Option<Duration>,
+}
+
+impl Decision {
+    /// Returns the bytes saved compared to downloading the full target.
+    pub fn bytes_saved(&self, target: &Target) -> u64 {
+        match self.transfer {
+            Transfer::None => target.size,
+            _ => target.size.saturating_sub(self.download_size),
+        }
+    }
+}
+
+/// Decides whether a client at version `base_hash` (`None` if it has none) should download a
+/// patch or the full `target`.
+///
+/// A patch costs its download plus decoding, modelled as producing the target at the CPU
+/// class's throughput; the full target costs its download. Both pay the link latency once.
+/// Ties go to the patch, which saves bandwidth.
+pub fn choose_transfer(
+    base_hash: Option<&ContentHash>,
+    target: &Target,
+    link_speed: LinkSpeed,
+    cpu_class: CpuClass,
+) -> Decision {
+    if base_hash == Some(&target.hash) {
+        return Decision {
+            transfer: Transfer::None,
+            reason: Reason::UpToDate,
+            download_size: 0,
+            estimated_time: Duration::ZERO,
+            alternative_time: None,
+        };
+    }
+
+    let download =
+        |bytes: u64| link_speed.latency + seconds(bytes, link_speed.bytes_per_second.max(1));
+    let full_time = download(target.size);
+    let full = Decision {
+        transfer: Transfer::Full,
+        reason: Reason::NoPatch,
+        download_size: target.size,
+        estimated_time: full_time,
+        alternative_time: None,
+    };
+
+    let Some(patch) = base_hash.and_then(|base| {
+        target
+            .patches
+            .iter()
+            .filter(|patch| patch.base == *base)
+            .min_by_key(|patch| patch.size)
+    }) else {
+        return full;
+    };
+    let patch_time = download(patch.size)
+        + seconds(
+            target.size + patch.size,
+            cpu_class.decode_bytes_per_second(),
+        );
+
+    if patch_time <= full_time {
+        Decision {
+            transfer: Transfer::Patch(*patch),
+            reason: Reason::PatchFaster,
+            download_size: patch.size,
+            estimated_time: patch_time,
+            alternative_time: Some(full_time),
+        }
+    } else {
+        Decision {
+            reason: Reason::FullFaster,
+            alternative_time: Some(patch_time),
+            ..full
+        }
+    }
+}
+
+/// Time to process `bytes` at `per_second`.
+fn seconds(bytes: u64, per_second: u64) -> Duration {
+    Duration::from_secs_f64(bytes as f64 / per_second as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(size: u64, patches: &[(&[u8], u64)]) -> Target {
+        Target {
+            hash: ContentHash::of(b"target"),
+            size,
+            patches: patches
+                .iter()
+                .map(|&(base, size)| PatchOffer {
+                    base: ContentHash::of(base),
+                    size,
+                    estimated: false,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_trivial_decisions() {
+        let target = target(1_000_000, &[(b"v1", 10_000)]);
+        let speed = LinkSpeed::mbit(10);
+
+        let decision = choose_transfer(Some(&target.hash), &target, speed, CpuClass::Mid);
+        assert_eq!(
+            (decision.transfer, decision.reason),
+            (Transfer::None, Reason::UpToDate)
+        );
+        assert_eq!(decision.bytes_saved(&target), 1_000_000);
+
+        for base in [None, Some(ContentHash::of(b"unknown"))] {
+            let decision = choose_transfer(base.as_ref(), &target, speed, CpuClass::Mid);
+            assert_eq!(
+                (decision.transfer, decision.reason),
+                (Transfer::Full, Reason::NoPatch)
+            );
+            assert_eq!(decision.download_size, 1_000_000);
+            assert_eq!(decision.alternative_time, None);
+        }
+    }
+
+    #[test]
+    fn test_weighs_download_against_decode() {
+        // 1 GB target with a 300 MB patch
+        let target = target(1_000_000_000, &[(b"v1", 300_000_000), (b"v1", 400_000_000)]);
+        let base = ContentHash::of(b"v1");
+
+        // Slow link: the smaller download wins even on a slow CPU
+        let decision = choose_transfer(Some(&base), &target, LinkSpeed::mbit(10), CpuClass::Low);
+        assert_eq!(decision.reason, Reason::PatchFaster);
+        assert_eq!(decision.download_size, 300_000_000);
+        assert_eq!(decision.bytes_saved(&target), 700_000_000);
+        assert!(decision.alternative_time.unwrap() > decision.estimated_time);
+
+        // 10 Gbit/s link: decoding 1.3 GB on a slow CPU takes longer than downloading 1 GB
+        let decision =
+            choose_transfer(Some(&base), &target, LinkSpeed::mbit(10_000), CpuClass::Low);
+        assert_eq!(
+            (decision.transfer, decision.reason),
+            (Transfer::Full, Reason::FullFaster)
+        );
+        assert!(decision.alternative_time.unwrap() > decision.estimated_time);
+        assert_eq!(decision.reason.as_str(), "full_faster");
+    }
+
+    #[test]
+    fn test_estimated_offer() {
+        let base: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut new = base.clone();
+        new.splice(50_000..50_000, b"inserted".iter().copied());
+
+        let offer = PatchOffer::estimate(&base, &new);
+        assert!(offer.estimated);
+        assert!(offer.size < 1_000);
+        let target = Target {
+            hash: ContentHash::of(&new),
+            size: new.len() as u64,
+            patches: vec![offer],
+        };
+        let decision = choose_transfer(
+            Some(&ContentHash::of(&base)),
+            &target,
+            LinkSpeed::mbit(10),
+            CpuClass::Mid,
+        );
+        assert_eq!(decision.transfer, Transfer::Patch(offer));
+    }
+}
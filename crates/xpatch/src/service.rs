@@ -0,0 +1,820 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Patch generation for servers.
+//!
+//! A [`PatchService`] produces the patch between two versions on demand and is meant to be
+//! shared by all request handlers of a server. It
+//!
+//! - caches patches in a [`PatchCache`], by default in memory, evicting the least recently
+//!   used ones beyond a byte budget (see [`with_cache`](PatchService::with_cache) for disk or
+//!   Redis),
+//! - coalesces requests: while a patch is being encoded, further requests for it wait for that
+//!   encode and share its result instead of starting their own, even if the patch is too
+//!   large to cache, and
+//! - limits the number of concurrent encodes, queueing a bounded number of requests for a
+//!   permit and rejecting the rest with [`ServiceError::Busy`] (e.g. to answer HTTP 503).
+//!
+//! Versions are identified by a key chosen by the caller, typically a pair of version ids or
+//! content hashes. The versions' content is only loaded when a patch actually has to be
+//! encoded.
+//!
+//! New encoder settings can be rolled out gradually with [`ServiceOptions::profiles`]:
+//! [`PatchService::patch_for`] assigns each client to one of several [`Profile`]s by weight and
+//! serves it patches encoded with that profile, and [`PatchService::profile_stats`] compares
+//! the patch sizes and encode times the profiles achieved.
+//!
+//! A [`Scheduler`] encodes the patches from the versions clients request most to the latest
+//! release while the service is idle, so they are cached before the clients ask for them.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::service::{PatchService, ServiceOptions};
+//!
+//! /// Reads two versions from disk, a database, ...
+//! fn load(base: &str, target: &str) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+//! #   let load = |version| format!("Hello, {} World!", version).into_bytes();
+//! #   Ok((load(base), load(target)))
+//! }
+//!
+//! let service = PatchService::new(ServiceOptions::default());
+//! let patch = service.patch(("v1", "v2"), || load("v1", "v2"))?;
+//! let (base, target) = load("v1", "v2")?;
+//! assert_eq!(xpatch::delta::decode(&base, &patch).unwrap(), target);
+//!
+//! // Served from the cache; the versions are not loaded again
+//! let cached = service.patch(("v1", "v2"), || load("v1", "v2"))?;
+//! assert_eq!(cached, patch);
+//! assert_eq!(service.stats().hits, 1);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::cache::PatchCache;
+use crate::delta::{self, EncodeOptions};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+mod scheduler;
+
+pub use self::scheduler::{Scheduler, SchedulerHandle, SchedulerOptions, SchedulerStats};
+
+/// Limits and encoder settings of a [`PatchService`].
+#[derive(Debug, Clone)]
+pub struct ServiceOptions {
+    /// Maximum number of patches encoded at the same time
+    pub max_concurrent: usize,
+    /// Maximum number of requests waiting for an encode permit; further requests fail with
+    /// [`ServiceError::Busy`]
+    pub max_queued: usize,
+    /// Total size of the patches cached in memory in bytes (0 disables caching), unless
+    /// the service was created [`with_cache`](PatchService::with_cache)
+    pub cache_size: usize,
+    /// Metadata tag stored in every patch
+    pub tag: usize,
+    /// Options passed to the encoder
+    pub encode: EncodeOptions,
+    /// Encoder profiles clients are split between by [`PatchService::patch_for`]; if empty,
+    /// all patches are encoded with `tag` and `encode`. The first profile is the control group,
+    /// used by [`PatchService::patch`].
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            max_queued: 64,
+            cache_size: 256 * 1024 * 1024,
+            tag: 0,
+            encode: EncodeOptions::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+/// Encoder settings a [`PatchService`] assigns to a share of its clients.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Name identifying the profile in [`ProfileStats`]
+    pub name: String,
+    /// Share of the clients receiving the profile, relative to the other profiles' weights
+    pub weight: u32,
+    /// Metadata tag stored in the profile's patches, e.g. to identify the profile on clients
+    pub tag: usize,
+    /// Options passed to the encoder
+    pub encode: EncodeOptions,
+}
+
+impl Profile {
+    /// Creates a profile with the given name, weight 1, tag 0 and encoder options.
+    pub fn new(name: impl Into<String>, encode: EncodeOptions) -> Self {
+        Self {
+            name: name.into(),
+            weight: 1,
+            tag: 0,
+            encode,
+        }
+    }
+
+    /// Sets the weight.
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Sets the tag.
+    pub fn tag(mut self, tag: usize) -> Self {
+        self.tag = tag;
+        self
+    }
+}
+
+/// Counters of one [`Profile`], see [`PatchService::profile_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileStats {
+    /// Name of the profile
+    pub name: String,
+    /// Patches served, from the cache or freshly encoded
+    pub served: u64,
+    /// Total size of the patches served in bytes
+    pub served_bytes: u64,
+    /// Patches encoded
+    pub encodes: u64,
+    /// Time spent encoding
+    pub encode_time: Duration,
+    /// Total size of the targets encoded in bytes
+    pub target_bytes: u64,
+    /// Total size of the patches encoded in bytes
+    pub patch_bytes: u64,
+}
+
+impl ProfileStats {
+    /// Size of the encoded patches relative to their targets (`patch_bytes / target_bytes`);
+    /// lower is better. 0 if nothing was encoded yet.
+    pub fn ratio(&self) -> f64 {
+        if self.target_bytes == 0 {
+            0.0
+        } else {
+            self.patch_bytes as f64 / self.target_bytes as f64
+        }
+    }
+}
+
+/// Counters of a [`PatchService`], see [`PatchService::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceStats {
+    /// Requests answered from the cache or by another request's encode
+    pub hits: u64,
+    /// Patches encoded
+    pub encodes: u64,
+    /// Requests that waited for another request's encode
+    pub coalesced: u64,
+    /// Requests rejected with [`ServiceError::Busy`]
+    pub rejected: u64,
+    /// Patches encoded by [`PatchService::pregenerate`] (included in `encodes`)
+    pub pregenerated: u64,
+    /// Number of cached patches, if the cache's backend tracks it
+    pub cached_patches: usize,
+    /// Total size of the cached patches in bytes, if the cache's backend tracks it
+    pub cached_bytes: usize,
+}
+
+/// Why a [`PatchService`] could not produce a patch.
+#[derive(Debug)]
+pub enum ServiceError<E> {
+    /// All encode permits are taken and the queue is full
+    Busy,
+    /// Loading the versions failed
+    Load(E),
+}
+
+impl<E: fmt::Display> fmt::Display for ServiceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::Busy => f.write_str("Too many patches are being generated"),
+            ServiceError::Load(e) => write!(f, "Failed to load versions: {}", e),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ServiceError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServiceError::Busy => None,
+            ServiceError::Load(e) => Some(e),
+        }
+    }
+}
+
+/// Thread-safe, caching patch generator, see the [module documentation](self).
+pub struct PatchService<K> {
+    options: ServiceOptions,
+    /// The options' profiles, or a single profile made of `tag` and `encode`
+    profiles: Vec<Profile>,
+    /// Patches are cached per profile index
+    cache: PatchCache<(usize, K)>,
+    state: Mutex<State<(usize, K)>>,
+    /// Signalled whenever an encode finishes, releasing its permit and key
+    finished: Condvar,
+}
+
+/// The patch of an encode in progress, shared with the requests waiting for it.
+type Flight = Arc<OnceLock<Arc<[u8]>>>;
+
+struct State<K> {
+    /// Keys being encoded
+    in_flight: HashMap<K, Flight>,
+    running: usize,
+    queued: usize,
+    stats: ServiceStats,
+    profile_stats: Vec<ProfileStats>,
+}
+
+impl<K> State<K> {
+    fn served(&mut self, profile: usize, patch: &[u8]) {
+        let stats = &mut self.profile_stats[profile];
+        stats.served += 1;
+        stats.served_bytes += patch.len() as u64;
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send + 'static> PatchService<K> {
+    /// Creates a service with an empty in-memory cache of
+    /// [`cache_size`](ServiceOptions::cache_size) bytes.
+    pub fn new(options: ServiceOptions) -> Self {
+        let cache = PatchCache::memory(options.cache_size);
+        Self::with_cache(options, cache)
+    }
+}
+
+impl<K: Hash + Eq + Clone> PatchService<K> {
+    /// Creates a service keeping its patches in `cache`, e.g. one with a
+    /// [`DiskBackend`](crate::cache::DiskBackend) shared with other processes.
+    ///
+    /// Patches are cached under `(profile index, key)`.
+    pub fn with_cache(options: ServiceOptions, cache: PatchCache<(usize, K)>) -> Self {
+        let profiles = if options.profiles.is_empty() {
+            vec![Profile::new("default", options.encode.clone()).tag(options.tag)]
+        } else {
+            options.profiles.clone()
+        };
+        let profile_stats = profiles
+            .iter()
+            .map(|profile| ProfileStats {
+                name: profile.name.clone(),
+                ..ProfileStats::default()
+            })
+            .collect();
+        Self {
+            options,
+            profiles,
+            cache,
+            state: Mutex::new(State {
+                in_flight: HashMap::new(),
+                running: 0,
+                queued: 0,
+                stats: ServiceStats::default(),
+                profile_stats,
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Returns the options the service was created with.
+    pub fn options(&self) -> &ServiceOptions {
+        &self.options
+    }
+
+    /// Returns the cache holding the service's patches.
+    pub fn cache(&self) -> &PatchCache<(usize, K)> {
+        &self.cache
+    }
+
+    /// Returns the patch for `key`, from the cache or by encoding the `(base, target)` pair
+    /// returned by `load`.
+    ///
+    /// `load` is only called if this request has to encode the patch. If it fails, requests
+    /// waiting for the same patch try again with their own loader.
+    ///
+    /// With [`ServiceOptions::profiles`], the patch is encoded with the first profile.
+    pub fn patch<E>(
+        &self,
+        key: K,
+        load: impl FnOnce() -> Result<(Vec<u8>, Vec<u8>), E>,
+    ) -> Result<Arc<[u8]>, ServiceError<E>> {
+        self.patch_with_profile(0, key, load)
+    }
+
+    /// Returns the patch for `key` like [`patch`](Self::patch), encoded with the profile
+    /// assigned to `client` (see [`profile_for`](Self::profile_for)), and that profile.
+    pub fn patch_for<E>(
+        &self,
+        client: &[u8],
+        key: K,
+        load: impl FnOnce() -> Result<(Vec<u8>, Vec<u8>), E>,
+    ) -> Result<(Arc<[u8]>, &Profile), ServiceError<E>> {
+        let profile = self.assign(client);
+        let patch = self.patch_with_profile(profile, key, load)?;
+        Ok((patch, &self.profiles[profile]))
+    }
+
+    /// Returns the profile assigned to `client`, an identifier such as a device id.
+    ///
+    /// Clients are split between the profiles in proportion to their weights by a hash of the
+    /// identifier, so a client keeps its profile for as long as the profiles stay the same.
+    pub fn profile_for(&self, client: &[u8]) -> &Profile {
+        &self.profiles[self.assign(client)]
+    }
+
+    fn assign(&self, client: &[u8]) -> usize {
+        let total: u64 = self.profiles.iter().map(|p| u64::from(p.weight)).sum();
+        if total == 0 {
+            return 0;
+        }
+        let mut point = u64::from(crc32fast::hash(client)) % total;
+        for (i, profile) in self.profiles.iter().enumerate() {
+            match point.checked_sub(u64::from(profile.weight)) {
+                Some(rest) => point = rest,
+                None => return i,
+            }
+        }
+        unreachable!("point is below the total weight")
+    }
+
+    fn patch_with_profile<E>(
+        &self,
+        profile: usize,
+        key: K,
+        load: impl FnOnce() -> Result<(Vec<u8>, Vec<u8>), E>,
+    ) -> Result<Arc<[u8]>, ServiceError<E>> {
+        let key = (profile, key);
+        let mut coalesced = false;
+        let mut state = loop {
+            // The backend is not asked while holding the lock, it may be slow
+            if let Some(patch) = self.cache.get(&key) {
+                return Ok(self.hit(profile, patch));
+            }
+            let mut state = self.lock();
+            if let Some(flight) = state.in_flight.get(&key).cloned() {
+                if !coalesced {
+                    coalesced = true;
+                    state.stats.coalesced += 1;
+                }
+                while state
+                    .in_flight
+                    .get(&key)
+                    .is_some_and(|current| Arc::ptr_eq(current, &flight))
+                {
+                    state = self.wait(state);
+                }
+                drop(state);
+                // Taken from the encode rather than the cache, which may not keep the patch
+                if let Some(patch) = flight.get() {
+                    return Ok(self.hit(profile, patch.clone()));
+                }
+                continue;
+            }
+            if state.running < self.options.max_concurrent.max(1) {
+                break state;
+            }
+            if state.queued >= self.options.max_queued {
+                state.stats.rejected += 1;
+                return Err(ServiceError::Busy);
+            }
+            state.queued += 1;
+            let mut state = self.wait(state);
+            state.queued -= 1;
+        };
+        state.running += 1;
+        let flight = Flight::default();
+        state.in_flight.insert(key.clone(), flight.clone());
+        drop(state);
+
+        // Releases the permit and the key even if loading or encoding panics
+        let permit = Permit {
+            service: self,
+            key,
+            flight,
+        };
+        // An encode may have finished between the lookup and taking the permit
+        if let Some(patch) = self.cache.get(&permit.key) {
+            return Ok(self.hit(profile, patch));
+        }
+        let (base, target) = load().map_err(ServiceError::Load)?;
+        Ok(self.encode(&permit, &base, &target, true))
+    }
+
+    /// Encodes the patch of a permit's key, counts it and stores it in the cache.
+    fn encode(
+        &self,
+        permit: &Permit<'_, K>,
+        base: &[u8],
+        target: &[u8],
+        served: bool,
+    ) -> Arc<[u8]> {
+        let profile = permit.key.0;
+        let settings = &self.profiles[profile];
+        let start = Instant::now();
+        let patch: Arc<[u8]> =
+            delta::encode_with_options(settings.tag, base, target, &settings.encode).into();
+        let encode_time = start.elapsed();
+
+        let mut state = self.lock();
+        state.stats.encodes += 1;
+        let stats = &mut state.profile_stats[profile];
+        stats.encodes += 1;
+        stats.encode_time += encode_time;
+        stats.target_bytes += target.len() as u64;
+        stats.patch_bytes += patch.len() as u64;
+        if served {
+            state.served(profile, &patch);
+        } else {
+            state.stats.pregenerated += 1;
+        }
+        drop(state);
+        // Stored before the permit releases the key, so waiting requests find it
+        let _ = permit.flight.set(patch.clone());
+        self.cache.insert(&permit.key, &patch);
+        patch
+    }
+
+    /// Encodes and caches the patches for `key` that are not cached yet, one per profile with a
+    /// non-zero weight, ahead of the requests for them. Returns the number of patches encoded.
+    ///
+    /// Unlike [`patch`](Self::patch), this never waits: if all encode permits are taken or
+    /// requests are queued for one, it fails with [`ServiceError::Busy`], and patches another
+    /// request is already encoding are skipped. `load` is called once per patch to encode.
+    /// The patches are counted in [`ServiceStats::pregenerated`], not as served.
+    pub fn pregenerate<E>(
+        &self,
+        key: K,
+        mut load: impl FnMut() -> Result<(Vec<u8>, Vec<u8>), E>,
+    ) -> Result<usize, ServiceError<E>> {
+        let mut encoded = 0;
+        for profile in 0..self.profiles.len() {
+            if self.profiles[profile].weight == 0 && profile > 0 {
+                continue;
+            }
+            let key = (profile, key.clone());
+            if self.cache.get(&key).is_some() {
+                continue;
+            }
+            let mut state = self.lock();
+            if state.in_flight.contains_key(&key) {
+                continue;
+            }
+            if state.running >= self.options.max_concurrent.max(1) || state.queued > 0 {
+                return Err(ServiceError::Busy);
+            }
+            state.running += 1;
+            let flight = Flight::default();
+            state.in_flight.insert(key.clone(), flight.clone());
+            drop(state);
+
+            let permit = Permit {
+                service: self,
+                key,
+                flight,
+            };
+            if self.cache.get(&permit.key).is_some() {
+                continue;
+            }
+            let (base, target) = load().map_err(ServiceError::Load)?;
+            self.encode(&permit, &base, &target, false);
+            encoded += 1;
+        }
+        Ok(encoded)
+    }
+
+    /// Returns whether no patch is being encoded and no request is waiting for a permit.
+    pub fn is_idle(&self) -> bool {
+        let state = self.lock();
+        state.running == 0 && state.queued == 0
+    }
+
+    fn hit(&self, profile: usize, patch: Arc<[u8]>) -> Arc<[u8]> {
+        let mut state = self.lock();
+        state.stats.hits += 1;
+        state.served(profile, &patch);
+        patch
+    }
+
+    /// Returns the patch for `key` if it is cached (for the first profile).
+    pub fn get_cached(&self, key: &K) -> Option<Arc<[u8]>> {
+        self.cache.get(&(0, key.clone()))
+    }
+
+    /// Drops the cached patches for `key`, e.g. after its target was withdrawn.
+    pub fn invalidate(&self, key: &K) {
+        for profile in 0..self.profiles.len() {
+            self.cache.remove(&(profile, key.clone()));
+        }
+    }
+
+    /// Drops all cached patches.
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+
+    /// Returns the service's counters.
+    pub fn stats(&self) -> ServiceStats {
+        let mut stats = self.lock().stats;
+        (stats.cached_patches, stats.cached_bytes) =
+            self.cache.backend().usage().unwrap_or_default();
+        stats
+    }
+
+    /// Returns the counters of each profile, in the order of [`ServiceOptions::profiles`].
+    pub fn profile_stats(&self) -> Vec<ProfileStats> {
+        self.lock().profile_stats.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<(usize, K)>> {
+        // The state stays consistent if a panic unwinds through a lock holder
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, State<(usize, K)>>,
+    ) -> MutexGuard<'a, State<(usize, K)>> {
+        self.finished.wait(state).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An encode permit held for a key.
+struct Permit<'a, K: Hash + Eq + Clone> {
+    service: &'a PatchService<K>,
+    key: (usize, K),
+    flight: Flight,
+}
+
+impl<K: Hash + Eq + Clone> Drop for Permit<'_, K> {
+    fn drop(&mut self) {
+        let mut state = self.service.lock();
+        state.running -= 1;
+        state.in_flight.remove(&self.key);
+        drop(state);
+        self.service.finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Barrier, mpsc};
+    use std::thread;
+
+    fn versions(n: u8) -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let mut target = base.clone();
+        target[100] = n;
+        (base, target)
+    }
+
+    fn load(n: u8) -> impl FnOnce() -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+        move || Ok(versions(n))
+    }
+
+    #[test]
+    fn test_patch_and_cache() {
+        let service = PatchService::new(ServiceOptions::default());
+        let patch = service.patch(1, load(1)).unwrap();
+        let (base, target) = versions(1);
+        assert_eq!(delta::decode(&base, &patch).unwrap(), target);
+
+        let cached = service.patch(1, || Err("not called")).unwrap();
+        assert!(Arc::ptr_eq(&patch, &cached));
+        assert!(matches!(
+            service.patch(2, || Err("load failed")),
+            Err(ServiceError::Load("load failed"))
+        ));
+
+        let stats = service.stats();
+        assert_eq!((stats.hits, stats.encodes, stats.cached_patches), (1, 1, 1));
+        assert_eq!(stats.cached_bytes, patch.len());
+
+        service.invalidate(&1);
+        assert!(service.get_cached(&1).is_none());
+        assert_eq!(service.stats().cached_bytes, 0);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let service = PatchService::new(ServiceOptions {
+            profiles: vec![
+                Profile::new("control", EncodeOptions::default()).weight(3),
+                Profile::new(
+                    "candidate",
+                    EncodeOptions::default().zstd_long_distance(true),
+                )
+                .tag(7),
+            ],
+            ..ServiceOptions::default()
+        });
+
+        let clients: Vec<String> = (0..400).map(|i| format!("device-{i}")).collect();
+        let mut candidates = 0;
+        for client in &clients {
+            let (patch, profile) = service.patch_for(client.as_bytes(), 1, load(1)).unwrap();
+            assert_eq!(service.profile_for(client.as_bytes()).name, profile.name);
+            assert_eq!(delta::get_tag(&patch).unwrap(), profile.tag);
+            if profile.name == "candidate" {
+                candidates += 1;
+            }
+        }
+        assert!((50..150).contains(&candidates), "{candidates} candidates");
+
+        let stats = service.profile_stats();
+        assert_eq!(stats[0].name, "control");
+        assert_eq!((stats[0].encodes, stats[1].encodes), (1, 1));
+        assert_eq!(stats[0].served + stats[1].served, 400);
+        assert_eq!(stats[1].served, candidates);
+        assert!(stats[1].ratio() > 0.0);
+        assert_eq!(service.stats().cached_patches, 2);
+
+        service.invalidate(&1);
+        assert_eq!(service.stats().cached_patches, 0);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let size = PatchService::new(ServiceOptions::default())
+            .patch(0, load(0))
+            .unwrap()
+            .len();
+        let service = PatchService::new(ServiceOptions {
+            cache_size: size * 2,
+            ..ServiceOptions::default()
+        });
+        service.patch(1, load(1)).unwrap();
+        service.patch(2, load(2)).unwrap();
+        // Using 1 makes 2 the least recently used
+        service.patch(1, load(1)).unwrap();
+        service.patch(3, load(3)).unwrap();
+
+        assert!(service.get_cached(&1).is_some());
+        assert!(service.get_cached(&2).is_none());
+        assert!(service.get_cached(&3).is_some());
+        assert_eq!(service.stats().cached_patches, 2);
+    }
+
+    #[test]
+    fn test_coalesces_identical_requests() {
+        let service = PatchService::new(ServiceOptions::default());
+        let loads = AtomicUsize::new(0);
+        let (started, release) = (Barrier::new(2), Barrier::new(2));
+
+        thread::scope(|scope| {
+            let leader = scope.spawn(|| {
+                service.patch("a", || {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    started.wait();
+                    release.wait();
+                    load(1)()
+                })
+            });
+            started.wait();
+            let followers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        service.patch("a", || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            load(1)()
+                        })
+                    })
+                })
+                .collect();
+            while service.stats().coalesced < 4 {
+                thread::yield_now();
+            }
+            release.wait();
+
+            let patch = leader.join().unwrap().unwrap();
+            for follower in followers {
+                assert_eq!(follower.join().unwrap().unwrap(), patch);
+            }
+        });
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(service.stats().encodes, 1);
+    }
+
+    #[test]
+    fn test_coalesces_uncacheable_patch() {
+        // The patch exceeds the cache, so waiting requests must take it from the encode
+        let service = PatchService::new(ServiceOptions {
+            cache_size: 1,
+            ..ServiceOptions::default()
+        });
+        let loads = AtomicUsize::new(0);
+        let (started, release) = (Barrier::new(2), Barrier::new(2));
+
+        thread::scope(|scope| {
+            let leader = scope.spawn(|| {
+                service.patch("a", || {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    started.wait();
+                    release.wait();
+                    load(1)()
+                })
+            });
+            started.wait();
+            let followers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        service.patch("a", || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            load(1)()
+                        })
+                    })
+                })
+                .collect();
+            while service.stats().coalesced < 4 {
+                thread::yield_now();
+            }
+            release.wait();
+
+            let patch = leader.join().unwrap().unwrap();
+            assert!(patch.len() > 1);
+            for follower in followers {
+                assert!(Arc::ptr_eq(&follower.join().unwrap().unwrap(), &patch));
+            }
+        });
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        let stats = service.stats();
+        assert_eq!((stats.encodes, stats.hits, stats.cached_patches), (1, 4, 0));
+        assert!(service.is_idle());
+    }
+
+    #[test]
+    fn test_permits_and_queue_limit() {
+        let service = PatchService::new(ServiceOptions {
+            max_concurrent: 1,
+            max_queued: 1,
+            ..ServiceOptions::default()
+        });
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        thread::scope(|scope| {
+            let busy = scope.spawn(|| {
+                service.patch(1, move || {
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                    load(1)()
+                })
+            });
+            started_rx.recv().unwrap();
+
+            // The only permit is taken: one request queues, the next is rejected
+            let queued = scope.spawn(|| service.patch(2, load(2)));
+            while service.lock().queued == 0 {
+                thread::yield_now();
+            }
+            assert!(matches!(service.patch(3, load(3)), Err(ServiceError::Busy)));
+
+            release_tx.send(()).unwrap();
+            busy.join().unwrap().unwrap();
+            queued.join().unwrap().unwrap();
+        });
+        let stats = service.stats();
+        assert_eq!((stats.encodes, stats.rejected), (2, 1));
+    }
+
+    #[test]
+    fn test_permit_released_on_panic() {
+        let service = PatchService::new(ServiceOptions {
+            max_concurrent: 1,
+            max_queued: 0,
+            ..ServiceOptions::default()
+        });
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            service.patch(1, || -> Result<_, &str> { panic!("loader panicked") })
+        }));
+        assert!(result.is_err());
+        assert!(service.patch(1, load(1)).is_ok());
+    }
+}
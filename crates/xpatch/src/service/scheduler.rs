@@ -0,0 +1,361 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Pre-generation of the patches clients are about to request.
+
+use super::{PatchService, ServiceError};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Settings of a [`Scheduler`].
+#[derive(Debug, Clone)]
+pub struct SchedulerOptions {
+    /// Number of most requested versions to keep patches to the latest release ready for
+    pub top_versions: usize,
+    /// Minimum time between two pre-generated patches, limiting the CPU spent on them
+    pub interval: Duration,
+    /// Request counts are halved after this many recorded requests, so versions clients moved
+    /// away from fade out
+    pub decay_after: u64,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        Self {
+            top_versions: 8,
+            interval: Duration::from_secs(1),
+            decay_after: 10_000,
+        }
+    }
+}
+
+/// Counters of a [`Scheduler`], see [`Scheduler::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedulerStats {
+    /// Versions whose patches to the latest release were pre-generated
+    pub generated: u64,
+    /// Versions whose content could not be loaded
+    pub failed: u64,
+    /// Runs put off because the service was busy
+    pub deferred: u64,
+}
+
+/// Pre-generates the patches from the most requested client versions to the latest release
+/// while a [`PatchService`] is idle.
+///
+/// The server reports the version each client runs with [`record`](Self::record) and the
+/// latest release with [`set_latest`](Self::set_latest). [`run_once`](Self::run_once), or the
+/// thread started by [`spawn`](Self::spawn), then encodes the patches from the
+/// [`top_versions`](SchedulerOptions::top_versions) most frequent versions to the latest
+/// release into the service's cache, at most one every
+/// [`interval`](SchedulerOptions::interval) and only while no request is being encoded, so
+/// clients get them from the cache once they ask. To keep the patches across restarts, create
+/// the service [`with_cache`](PatchService::with_cache) over a persistent backend such as a
+/// `StorageBackend` in the delta store.
+pub struct Scheduler<V> {
+    options: SchedulerOptions,
+    state: Mutex<State<V>>,
+}
+
+struct State<V> {
+    /// Requests per client version, halved every `decay_after` requests
+    counts: HashMap<V, u64>,
+    recorded: u64,
+    latest: Option<V>,
+    /// Versions whose patches to `latest` were pre-generated or failed
+    done: HashSet<V>,
+    last_run: Option<Instant>,
+    stats: SchedulerStats,
+}
+
+impl<V: Hash + Eq + Clone> Scheduler<V> {
+    /// Creates a scheduler that has not seen any request yet.
+    pub fn new(options: SchedulerOptions) -> Self {
+        Self {
+            options,
+            state: Mutex::new(State {
+                counts: HashMap::new(),
+                recorded: 0,
+                latest: None,
+                done: HashSet::new(),
+                last_run: None,
+                stats: SchedulerStats::default(),
+            }),
+        }
+    }
+
+    /// Records a request from a client running `version`.
+    pub fn record(&self, version: V) {
+        let mut state = self.lock();
+        *state.counts.entry(version).or_default() += 1;
+        state.recorded += 1;
+        if state.recorded >= self.options.decay_after.max(1) {
+            state.recorded = 0;
+            state.counts.retain(|_, count| {
+                *count /= 2;
+                *count > 0
+            });
+        }
+    }
+
+    /// Sets the release patches are pre-generated to. Changing it schedules all versions again.
+    pub fn set_latest(&self, version: V) {
+        let mut state = self.lock();
+        if state.latest.as_ref() != Some(&version) {
+            state.latest = Some(version);
+            state.done.clear();
+        }
+    }
+
+    /// Returns the versions whose patches to the latest release are still to be pre-generated,
+    /// most requested first.
+    pub fn pending(&self) -> Vec<V> {
+        let state = self.lock();
+        let Some(latest) = &state.latest else {
+            return Vec::new();
+        };
+        let mut top: Vec<(&V, u64)> = state
+            .counts
+            .iter()
+            .filter(|(version, _)| *version != latest)
+            .map(|(version, count)| (version, *count))
+            .collect();
+        top.sort_by_key(|(_, count)| Reverse(*count));
+        top.truncate(self.options.top_versions);
+        top.into_iter()
+            .filter(|(version, _)| !state.done.contains(*version))
+            .map(|(version, _)| version.clone())
+            .collect()
+    }
+
+    /// Pre-generates the patches for the most requested pending version if the service is idle
+    /// and the interval since the last run has passed, and returns that version.
+    ///
+    /// `key` builds the service's key from a client version and the latest release; `load`
+    /// loads the content of both. A version whose content fails to load is not retried until
+    /// the latest release changes.
+    pub fn run_once<K, E>(
+        &self,
+        service: &PatchService<K>,
+        key: impl Fn(&V, &V) -> K,
+        mut load: impl FnMut(&V, &V) -> Result<(Vec<u8>, Vec<u8>), E>,
+    ) -> Result<Option<V>, E>
+    where
+        K: Hash + Eq + Clone,
+    {
+        let state = self.lock();
+        if state
+            .last_run
+            .is_some_and(|last| last.elapsed() < self.options.interval)
+        {
+            return Ok(None);
+        }
+        let Some(latest) = state.latest.clone() else {
+            return Ok(None);
+        };
+        drop(state);
+        let Some(version) = self.pending().into_iter().next() else {
+            return Ok(None);
+        };
+        if !service.is_idle() {
+            self.lock().stats.deferred += 1;
+            return Ok(None);
+        }
+
+        let result = service.pregenerate(key(&version, &latest), || load(&version, &latest));
+        let mut state = self.lock();
+        state.last_run = Some(Instant::now());
+        match result {
+            Ok(_) => {
+                state.stats.generated += 1;
+                state.done.insert(version.clone());
+                Ok(Some(version))
+            }
+            Err(ServiceError::Busy) => {
+                state.stats.deferred += 1;
+                Ok(None)
+            }
+            Err(ServiceError::Load(e)) => {
+                state.stats.failed += 1;
+                state.done.insert(version);
+                Err(e)
+            }
+        }
+    }
+
+    /// Calls [`run_once`](Self::run_once) on a background thread every
+    /// [`interval`](SchedulerOptions::interval) until the returned handle is stopped or dropped.
+    /// Load errors are only counted in [`stats`](Self::stats).
+    pub fn spawn<K, E>(
+        self: &Arc<Self>,
+        service: Arc<PatchService<K>>,
+        key: impl Fn(&V, &V) -> K + Send + 'static,
+        mut load: impl FnMut(&V, &V) -> Result<(Vec<u8>, Vec<u8>), E> + Send + 'static,
+    ) -> SchedulerHandle
+    where
+        V: Send + 'static,
+        K: Hash + Eq + Clone + Send + Sync + 'static,
+    {
+        let scheduler = self.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            loop {
+                let _ = scheduler.run_once(&service, &key, &mut load);
+                if stopped.recv_timeout(scheduler.options.interval)
+                    != Err(RecvTimeoutError::Timeout)
+                {
+                    break;
+                }
+            }
+        });
+        SchedulerHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the scheduler's counters.
+    pub fn stats(&self) -> SchedulerStats {
+        self.lock().stats
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<V>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The background thread of a [`Scheduler`], see [`Scheduler::spawn`].
+///
+/// Dropping the handle stops the thread as well.
+pub struct SchedulerHandle {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Stops the thread, waiting for a patch being pre-generated to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::ServiceOptions;
+
+    fn content(version: u8) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        data[100] = version;
+        data
+    }
+
+    fn load(base: &u8, target: &u8) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+        if *base == 9 {
+            return Err("version 9 is gone");
+        }
+        Ok((content(*base), content(*target)))
+    }
+
+    #[test]
+    fn test_pregenerates_most_requested() {
+        let service = PatchService::new(ServiceOptions::default());
+        let scheduler = Scheduler::new(SchedulerOptions {
+            top_versions: 3,
+            interval: Duration::ZERO,
+            ..SchedulerOptions::default()
+        });
+        for (version, requests) in [(1, 5), (2, 1), (3, 8), (4, 3), (9, 6)] {
+            for _ in 0..requests {
+                scheduler.record(version);
+            }
+        }
+        assert!(scheduler.pending().is_empty());
+        scheduler.set_latest(4);
+        assert_eq!(scheduler.pending(), [3, 9, 1]);
+
+        let key = |base: &u8, target: &u8| (*base, *target);
+        assert_eq!(scheduler.run_once(&service, key, load), Ok(Some(3)));
+        assert_eq!(
+            scheduler.run_once(&service, key, load),
+            Err("version 9 is gone")
+        );
+        assert_eq!(scheduler.run_once(&service, key, load), Ok(Some(1)));
+        assert_eq!(scheduler.run_once(&service, key, load), Ok(None));
+
+        let patch = service.patch((1, 4), || Err("not called")).unwrap();
+        assert_eq!(
+            crate::delta::decode(&content(1), &patch).unwrap(),
+            content(4)
+        );
+        let stats = service.stats();
+        assert_eq!((stats.pregenerated, stats.hits), (2, 1));
+        assert_eq!(
+            scheduler.stats(),
+            SchedulerStats {
+                generated: 2,
+                failed: 1,
+                deferred: 0
+            }
+        );
+
+        // A new release schedules the versions again
+        scheduler.set_latest(5);
+        assert_eq!(scheduler.pending(), [3, 9, 1]);
+    }
+
+    #[test]
+    fn test_background_thread() {
+        let service = Arc::new(PatchService::new(ServiceOptions::default()));
+        let scheduler = Arc::new(Scheduler::new(SchedulerOptions {
+            interval: Duration::from_millis(1),
+            ..SchedulerOptions::default()
+        }));
+        scheduler.record(1);
+        scheduler.record(2);
+        scheduler.set_latest(3);
+        let handle = scheduler.spawn(service.clone(), |base, target| (*base, *target), load);
+        let start = Instant::now();
+        while !scheduler.pending().is_empty() && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        handle.stop();
+        assert!(service.get_cached(&(1, 3)).is_some());
+        assert!(service.get_cached(&(2, 3)).is_some());
+    }
+}
@@ -0,0 +1,243 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Similarity sketches for choosing a delta base without trial encodes.
+//!
+//! A [`Sketch`] is a bottom-k MinHash of data: the smallest hashes of all its 8-byte shingles.
+//! Two sketches estimate the Jaccard similarity of the shingle sets they were built from, which
+//! tracks closely how well one would encode as a delta against the other. Sketching is a single
+//! pass over the data, and comparing two sketches costs `O(k)` regardless of the data size, so
+//! ranking many candidate bases is far cheaper than encoding against each of them.
+//!
+//! [`delta::encode_multi`](crate::delta::encode_multi) and the stores built on xpatch use
+//! sketches to narrow their base search down to a few trial encodes.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::sketch::Sketch;
+//!
+//! let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+//! let edited = text.replace("lazy", "sleepy");
+//! let unrelated = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(20);
+//!
+//! let sketch = Sketch::of(text.as_bytes());
+//! assert!(
+//!     sketch.similarity(&Sketch::of(edited.as_bytes()))
+//!         > sketch.similarity(&Sketch::of(unrelated.as_bytes()))
+//! );
+//! ```
+
+use std::cmp::Ordering;
+
+/// Number of hashes kept in a sketch.
+pub const SKETCH_SIZE: usize = 64;
+
+/// Length of the shingles that are hashed.
+const SHINGLE: usize = 8;
+
+/// Bottom-k MinHash sketch of some data.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Sketch {
+    /// Smallest distinct shingle hashes, sorted ascending
+    hashes: Vec<u64>,
+}
+
+impl Sketch {
+    /// Sketches `data`.
+    ///
+    /// Data shorter than a shingle is hashed as a whole; empty data has an empty sketch.
+    pub fn of(data: &[u8]) -> Self {
+        let mut hashes: Vec<u64> = Vec::with_capacity(SKETCH_SIZE + 1);
+        if data.is_empty() {
+            return Self { hashes };
+        }
+
+        for shingle in data.windows(SHINGLE.min(data.len())) {
+            let hash = mix(shingle);
+            if hashes.len() == SKETCH_SIZE && hash >= hashes[SKETCH_SIZE - 1] {
+                continue;
+            }
+            if let Err(pos) = hashes.binary_search(&hash) {
+                hashes.insert(pos, hash);
+                hashes.truncate(SKETCH_SIZE);
+            }
+        }
+        Self { hashes }
+    }
+
+    /// Restores a sketch from [`to_bytes`](Self::to_bytes) output.
+    ///
+    /// Returns `None` if `bytes` is not a valid sketch.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(8) || bytes.len() / 8 > SKETCH_SIZE {
+            return None;
+        }
+        let hashes: Vec<u64> = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        hashes
+            .windows(2)
+            .all(|pair| pair[0] < pair[1])
+            .then_some(Self { hashes })
+    }
+
+    /// Serializes the sketch as little-endian hashes, at most `8 * SKETCH_SIZE` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.hashes
+            .iter()
+            .flat_map(|hash| hash.to_le_bytes())
+            .collect()
+    }
+
+    /// Estimates the similarity of the sketched data, from 0.0 (unrelated) to 1.0 (identical
+    /// shingles).
+    pub fn similarity(&self, other: &Sketch) -> f64 {
+        if self.hashes.is_empty() && other.hashes.is_empty() {
+            return 1.0;
+        }
+
+        // The smallest hashes of the union are a sample of it; count how many are in both sets
+        let (mut a, mut b) = (
+            self.hashes.iter().peekable(),
+            other.hashes.iter().peekable(),
+        );
+        let (mut sampled, mut shared) = (0, 0);
+        while sampled < SKETCH_SIZE {
+            match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) => match x.cmp(y) {
+                    Ordering::Less => {
+                        a.next();
+                    }
+                    Ordering::Greater => {
+                        b.next();
+                    }
+                    Ordering::Equal => {
+                        shared += 1;
+                        a.next();
+                        b.next();
+                    }
+                },
+                (Some(_), None) => {
+                    a.next();
+                }
+                (None, Some(_)) => {
+                    b.next();
+                }
+                (None, None) => break,
+            }
+            sampled += 1;
+        }
+        shared as f64 / sampled as f64
+    }
+
+    /// Orders candidates from most to least similar to this sketch.
+    ///
+    /// The sort is stable, so equally similar candidates keep their order (e.g. most recent
+    /// first).
+    pub fn rank<'a, K>(&self, candidates: impl IntoIterator<Item = (K, &'a Sketch)>) -> Vec<K> {
+        let mut scored: Vec<(f64, K)> = candidates
+            .into_iter()
+            .map(|(key, sketch)| (self.similarity(sketch), key))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, key)| key).collect()
+    }
+}
+
+/// Hashes a shingle (FNV-1a followed by a 64-bit finalizer, so the low bits mix well).
+fn mix(shingle: &[u8]) -> u64 {
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    for &byte in shingle {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_data() {
+        let data = pseudo_random(10_000, 1);
+        let sketch = Sketch::of(&data);
+        assert_eq!(sketch.hashes.len(), SKETCH_SIZE);
+        assert_eq!(sketch.similarity(&Sketch::of(&data)), 1.0);
+        assert_eq!(Sketch::of(b"").similarity(&Sketch::of(b"")), 1.0);
+        assert_eq!(Sketch::of(b"").similarity(&sketch), 0.0);
+    }
+
+    #[test]
+    fn test_similarity_tracks_edits() {
+        let data = pseudo_random(20_000, 2);
+        let mut small_edit = data.clone();
+        small_edit[5_000..5_100].copy_from_slice(&pseudo_random(100, 3));
+        let mut large_edit = data.clone();
+        large_edit[..10_000].copy_from_slice(&pseudo_random(10_000, 4));
+        let unrelated = pseudo_random(20_000, 5);
+
+        let sketch = Sketch::of(&data);
+        let small = sketch.similarity(&Sketch::of(&small_edit));
+        let large = sketch.similarity(&Sketch::of(&large_edit));
+        let none = sketch.similarity(&Sketch::of(&unrelated));
+        assert!(small > 0.9, "{}", small);
+        assert!(small > large && large > none);
+        assert!(none < 0.1, "{}", none);
+    }
+
+    #[test]
+    fn test_rank() {
+        let data = pseudo_random(8_000, 6);
+        let mut close = data.clone();
+        close[100] ^= 1;
+        let far = pseudo_random(8_000, 7);
+
+        let sketches = [Sketch::of(&far), Sketch::of(&close), Sketch::of(&far)];
+        let ranked = Sketch::of(&data).rank(sketches.iter().enumerate());
+        assert_eq!(ranked, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let sketch = Sketch::of(&pseudo_random(1_000, 8));
+        assert_eq!(Sketch::from_bytes(&sketch.to_bytes()), Some(sketch));
+        assert_eq!(Sketch::from_bytes(&[]), Some(Sketch::default()));
+        assert_eq!(Sketch::from_bytes(&[0; 7]), None);
+        assert_eq!(Sketch::from_bytes(&[0; 16]), None);
+    }
+}
@@ -0,0 +1,185 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Resource usage of single operations.
+//!
+//! [`ResourceStats`] reports how long an encode or decode took and, when the process runs on
+//! [`CountingAllocator`], how much heap it needed at peak and how many allocations it made.
+//! That validates memory claims for constrained targets: install the allocator in a test
+//! binary, encode or decode with [`EncodeOptions::stats`](crate::EncodeOptions::stats), and
+//! compare the peak against the device's budget.
+//!
+//! ```
+//! use xpatch::delta::{self, EncodeOptions};
+//! use xpatch::stats::CountingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator;
+//!
+//! let base = b"temperature=21.5;humidity=40".repeat(100);
+//! let new = b"temperature=22.0;humidity=40".repeat(100);
+//! let options = EncodeOptions::default().stats(true);
+//! let (delta, stats) = delta::encode_with_stats(0, &base, &new, &options);
+//! let (_, stats) = delta::decode_with_stats(&base, &delta, &options).unwrap();
+//! let stats = stats.unwrap();
+//! assert!(stats.peak_bytes.unwrap() >= new.len());
+//! assert!(stats.allocations.unwrap() > 0);
+//! ```
+//!
+//! The counters are process-wide, so allocations made by other threads during an operation
+//! are counted too, including the pool threads of the `parallel` feature. zstd's contexts are
+//! allocated by the C library's `malloc` and are not counted; the `ruzstd` decoder's are.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Resources an encode or decode used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceStats {
+    /// Most heap bytes the operation held at once, beyond those live when it started, including
+    /// its result. `None` unless the process runs on [`CountingAllocator`].
+    pub peak_bytes: Option<usize>,
+    /// Number of heap allocations and reallocations the operation made. `None` unless the
+    /// process runs on [`CountingAllocator`].
+    pub allocations: Option<usize>,
+    /// Wall-clock time the operation took.
+    pub elapsed: Duration,
+}
+
+/// The system allocator, counting allocations and the live and peak heap size.
+///
+/// Install it as the global allocator to have [`ResourceStats`] report memory. Each
+/// allocation costs a few atomic operations on top of the system allocator.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BLOCKS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE_BLOCKS.fetch_sub(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            if new_size >= layout.size() {
+                grow(new_size - layout.size());
+            } else {
+                LIVE_BYTES.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+fn record_alloc(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    LIVE_BLOCKS.fetch_add(1, Ordering::Relaxed);
+    grow(size);
+}
+
+fn grow(size: usize) {
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Whether the process runs on [`CountingAllocator`].
+pub fn is_counting() -> bool {
+    // Every process allocates before main, so the counter only stays at 0 without the allocator
+    ALLOCATIONS.load(Ordering::Relaxed) > 0
+}
+
+/// Allocations and reallocations made so far, with [`CountingAllocator`] installed.
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Heap bytes currently allocated, with [`CountingAllocator`] installed.
+pub fn live_bytes() -> usize {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+/// Heap allocations currently live, with [`CountingAllocator`] installed.
+pub fn live_blocks() -> usize {
+    LIVE_BLOCKS.load(Ordering::Relaxed)
+}
+
+/// Runs `f` and reports the resources it used.
+///
+/// The peak is reset when `f` starts, so measurements must not overlap.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, ResourceStats) {
+    let counting = is_counting();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let live = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(live, Ordering::Relaxed);
+    let start = Instant::now();
+
+    let result = f();
+
+    let elapsed = start.elapsed();
+    let stats = ResourceStats {
+        peak_bytes: counting.then(|| PEAK_BYTES.load(Ordering::Relaxed).saturating_sub(live)),
+        allocations: counting.then(|| {
+            ALLOCATIONS
+                .load(Ordering::Relaxed)
+                .wrapping_sub(allocations)
+        }),
+        elapsed,
+    };
+    (result, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_without_allocator() {
+        // The test harness runs on the system allocator
+        let (data, stats) = measure(|| vec![0u8; 1 << 20]);
+        assert_eq!(data.len(), 1 << 20);
+        assert!(!is_counting());
+        assert_eq!(stats.peak_bytes, None);
+        assert_eq!(stats.allocations, None);
+    }
+}
@@ -0,0 +1,1676 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Content-addressed store of file versions.
+//!
+//! A [`DeltaStore`] keeps many versions of a file in a directory, identified by the SHA-256 of
+//! their content. Each version is stored either as a keyframe (a delta against empty data, i.e.
+//! the compressed content) or as a delta against an earlier version. On insert, the most recent
+//! versions are ranked by [similarity](crate::sketch) to the new content, the most similar ones
+//! are tried as bases and the smallest encoding wins. A [`KeyframePolicy`] caps delta chains by
+//! length and decode cost and stores a keyframe instead of a delta that saves too little, so
+//! reading a version stays cheap.
+//!
+//! Named refs (e.g. `"stable"`) mark the versions worth keeping. [`DeltaStore::gc`] deletes
+//! every version that no ref points to, turning survivors whose base was deleted into keyframes,
+//! and can re-base delta chains that grew past a [`GcPolicy`]'s limits.
+//! [`DeltaStore::write_pack`] exports the whole store as a single [pack](crate::pack) file.
+//! The [`filter`] module keeps binary files of a git repository in a store.
+//!
+//! With [`StoreOptions::dedup_chunks`], keyframes are split into content-defined chunks that
+//! are stored once and shared by all versions, so hundreds of similar variants (e.g. firmware
+//! builds for different boards) don't each store the parts they have in common. Such a
+//! version's object lists its chunks; chunks are reference-counted, and [`DeltaStore::gc`]
+//! deletes a chunk together with the last version using it.
+//!
+//! # Layout
+//!
+//! ```text
+//! <root>/index           <hash> <base hash or -> <size> <stored size>[ chunked], one version
+//!                        per line
+//! <root>/refs            <name> <hash>, one ref per line
+//! <root>/sketches        (<hash[32]> <count u8> <u64 LE>*count)*, a cache rebuilt when missing
+//! <root>/chunk-index     <hash> <references> <stored size>, one chunk per line
+//! <root>/objects/<hash>  the encoded version, or the hashes of its chunks if chunked
+//! <root>/chunks/<hash>   a chunk, encoded as a delta against empty data
+//! ```
+//!
+//! Versions are listed in insertion order, so a base always precedes the versions built on it.
+//! A chunk's references are counted before a version using it is listed and uncounted after
+//! the version was removed, so an interrupted write can leak chunks but never lose one.
+//!
+//! # Storage backends
+//!
+//! The files live in a [`Storage`], a directory on disk ([`FsStorage`]) by default. Objects never
+//! change once written, while the index and refs are replaced as a whole with
+//! [`Storage::compare_and_swap`]: if another writer changed them since this store read or wrote
+//! them, the update fails instead of silently dropping the other writer's versions. The `s3`
+//! feature adds [`s3::S3Storage`], which keeps the store in an S3-compatible bucket.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::store::{DeltaStore, GcPolicy};
+//!
+//! # let dir = std::env::temp_dir().join(format!("xpatch-store-doc-{}", std::process::id()));
+//! # let _ = std::fs::remove_dir_all(&dir);
+//! let mut store = DeltaStore::open(&dir)?;
+//! let v1 = store.insert(b"Hello, World!")?;
+//! let v2 = store.insert(b"Hello, Rust World!")?;
+//! store.set_ref("latest", v2)?;
+//!
+//! assert_eq!(store.info(&v2).unwrap().base, Some(v1));
+//! assert_eq!(store.get(&v2)?, b"Hello, Rust World!");
+//!
+//! // v1 is not referenced anymore
+//! assert_eq!(store.gc(&GcPolicy::default())?.versions_removed, 1);
+//! assert_eq!(store.get(&v2)?, b"Hello, Rust World!");
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::backup::chunk_boundary;
+use crate::delta::{self, EncodeOptions};
+use crate::pack::PackWriter;
+use crate::sketch::Sketch;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub mod filter;
+#[cfg(feature = "s3")]
+pub mod s3;
+
+const INDEX_FILE: &str = "index";
+const REFS_FILE: &str = "refs";
+const SKETCHES_FILE: &str = "sketches";
+const CHUNK_INDEX_FILE: &str = "chunk-index";
+const OBJECTS_DIR: &str = "objects";
+const CHUNKS_DIR: &str = "chunks";
+
+/// SHA-256 of a version's content.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Hashes `data`.
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    /// Wraps a raw SHA-256 digest.
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns the raw digest.
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContentHash({})", self)
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = &'static str;
+
+    /// Parses the 64-character hex form produced by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.as_bytes();
+        if s.len() != 64 {
+            return Err("Invalid content hash");
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(s.chunks_exact(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| "Invalid content hash")?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| "Invalid content hash")?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// When a [`DeltaStore`] stores a new version as a keyframe rather than a delta.
+///
+/// A version becomes a keyframe when no recent version can serve as its base within the chain
+/// limits, or when the best delta is not enough smaller than the keyframe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyframePolicy {
+    /// Maximum number of deltas between a version and its keyframe (0 stores only keyframes)
+    pub max_chain_length: usize,
+    /// Maximum [`VersionInfo::chain_cost`] of a version stored as a delta
+    pub max_chain_cost: u64,
+    /// Largest delta size, as a fraction of the keyframe size, still stored as a delta
+    /// (1.0 keeps every delta smaller than the keyframe)
+    pub max_delta_ratio: f64,
+}
+
+impl Default for KeyframePolicy {
+    fn default() -> Self {
+        Self {
+            max_chain_length: 16,
+            max_chain_cost: u64::MAX,
+            max_delta_ratio: 1.0,
+        }
+    }
+}
+
+/// Options controlling how a [`DeltaStore`] encodes new versions.
+#[derive(Debug, Clone)]
+pub struct StoreOptions {
+    /// When to store keyframes instead of deltas
+    pub keyframes: KeyframePolicy,
+    /// Number of recent versions ranked by similarity to a new version
+    pub sketch_candidates: usize,
+    /// Number of the most similar of those versions tried as base for a new version
+    pub base_candidates: usize,
+    /// Store keyframes as content-defined chunks shared with all other versions
+    pub dedup_chunks: bool,
+    /// Options passed to the encoder
+    pub encode: EncodeOptions,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            keyframes: KeyframePolicy::default(),
+            sketch_candidates: 64,
+            base_candidates: 4,
+            dedup_chunks: false,
+            encode: EncodeOptions::default(),
+        }
+    }
+}
+
+/// Metadata about a stored version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Hash of the version's content
+    pub hash: ContentHash,
+    /// Version this one is stored as a delta against, or `None` for a keyframe
+    pub base: Option<ContentHash>,
+    /// Size of the content in bytes
+    pub size: u64,
+    /// Size of the encoded object in bytes; for a chunked keyframe, the size of its chunk list
+    pub stored_size: u64,
+    /// Whether the version is a keyframe stored as shared chunks
+    /// (see [`StoreOptions::dedup_chunks`])
+    pub chunked: bool,
+    /// Number of deltas to apply to reconstruct the version (0 for a keyframe)
+    pub chain_length: usize,
+    /// Bytes read and written to reconstruct the version: the stored and content sizes of it
+    /// and every version in its chain, an estimate of the decode time
+    pub chain_cost: u64,
+}
+
+impl VersionInfo {
+    /// Returns how many times smaller the stored object is than the content.
+    pub fn dedup_ratio(&self) -> f64 {
+        self.size as f64 / self.stored_size.max(1) as f64
+    }
+}
+
+/// Health report of a [`DeltaStore`], see [`DeltaStore::stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreStats {
+    /// Every version in insertion order, with its chain depth and decode cost
+    pub versions: Vec<VersionInfo>,
+    /// Number of refs
+    pub refs: usize,
+    /// Versions no ref points to, which [`DeltaStore::gc`] deletes by default
+    pub unreferenced: Vec<ContentHash>,
+    /// Storage keys of objects no version uses, left behind by interrupted inserts or
+    /// collections. They waste space but do no harm.
+    pub orphaned_objects: Vec<String>,
+    /// Versions whose object is missing. They, and the versions built on them, cannot be read.
+    pub missing_objects: Vec<ContentHash>,
+    /// Number of stored chunks
+    pub chunks: usize,
+    /// Total size of the stored chunks in bytes
+    pub chunk_size: u64,
+}
+
+impl StoreStats {
+    /// Returns the total size of all versions' content.
+    pub fn content_size(&self) -> u64 {
+        self.versions.iter().map(|v| v.size).sum()
+    }
+
+    /// Returns the total size of all versions' objects and chunks.
+    pub fn stored_size(&self) -> u64 {
+        self.versions.iter().map(|v| v.stored_size).sum::<u64>() + self.chunk_size
+    }
+
+    /// Returns how many times smaller the store is than its content.
+    pub fn dedup_ratio(&self) -> f64 {
+        self.content_size() as f64 / self.stored_size().max(1) as f64
+    }
+
+    /// Returns the number of versions stored as keyframes.
+    pub fn keyframes(&self) -> usize {
+        self.versions.iter().filter(|v| v.base.is_none()).count()
+    }
+
+    /// Returns the version with the longest delta chain.
+    pub fn deepest(&self) -> Option<&VersionInfo> {
+        self.versions.iter().max_by_key(|v| v.chain_length)
+    }
+
+    /// Returns the version that is most expensive to decode.
+    pub fn costliest(&self) -> Option<&VersionInfo> {
+        self.versions.iter().max_by_key(|v| v.chain_cost)
+    }
+
+    /// Returns whether every version can be read.
+    pub fn is_healthy(&self) -> bool {
+        self.missing_objects.is_empty()
+    }
+}
+
+/// Which versions [`DeltaStore::gc`] keeps and how long their delta chains may get.
+///
+/// The default keeps only versions a ref points to and leaves chains as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Number of most recently inserted versions kept even if no ref points to them
+    pub keep_recent: usize,
+    /// Longest delta chain kept; longer chains are re-based onto a new keyframe
+    pub max_chain_length: usize,
+    /// Largest [`VersionInfo::chain_cost`] kept; costlier chains are re-based onto a new keyframe
+    pub max_chain_cost: u64,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        Self {
+            keep_recent: 0,
+            max_chain_length: usize::MAX,
+            max_chain_cost: u64::MAX,
+        }
+    }
+}
+
+/// Result of [`DeltaStore::gc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Number of deleted versions
+    pub versions_removed: usize,
+    /// Number of versions re-encoded as keyframes, because their base was deleted or their
+    /// chain was too long
+    pub versions_rebased: usize,
+    /// Number of chunks deleted because no version uses them anymore
+    pub chunks_removed: usize,
+    /// Decrease of the total stored size (0 if the new keyframes outweigh the deleted versions)
+    pub bytes_freed: u64,
+}
+
+/// Where a [`DeltaStore`] keeps its files.
+///
+/// Keys are the relative names from the [layout](self#layout), such as `index` or
+/// `objects/<hash>`.
+pub trait Storage {
+    /// Reads the value at `key`, or returns `None` if there is none.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Writes `data` at `key`, replacing any previous value. Readers see either the old or the
+    /// new value, never a partial one.
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Writes `data` at `key` only if the current value is `expected` (`None` meaning no value),
+    /// and returns whether it was written.
+    fn compare_and_swap(&self, key: &str, expected: Option<&[u8]>, data: &[u8])
+    -> io::Result<bool>;
+
+    /// Returns the keys starting with `prefix`, in no particular order.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    /// Deletes the value at `key`. Deleting a missing key is not an error.
+    fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// [`Storage`] in a local directory, one file per key.
+///
+/// [`compare_and_swap`](Storage::compare_and_swap) compares and writes without a lock, so it
+/// only detects writers that finished before the comparison.
+#[derive(Debug, Clone)]
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    /// Uses the directory `root`, creating it if it does not exist.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(OBJECTS_DIR))?;
+        fs::create_dir_all(root.join(CHUNKS_DIR))?;
+        Ok(Self { root })
+    }
+
+    /// Returns the storage directory.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Storage for FsStorage {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.root.join(key);
+        match write_atomic(&path, data) {
+            // Keys outside the directories created up front, e.g. cached patches
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                fs::create_dir_all(path.parent().unwrap_or(&self.root))?;
+                write_atomic(&path, data)
+            }
+            result => result,
+        }
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        data: &[u8],
+    ) -> io::Result<bool> {
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.put(key, data)?;
+        Ok(true)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // Keys are file paths, so only the directory part of the prefix has to be walked
+        let (dir, _) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+        let mut keys = Vec::new();
+        let entries = match fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(keys),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let key = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if key.starts_with(prefix) && entry.file_type()?.is_file() {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A collection of versions keyed by content hash, kept in a [`Storage`].
+///
+/// See the [module documentation](self) for the storage model.
+pub struct DeltaStore<S: Storage = FsStorage> {
+    storage: S,
+    options: StoreOptions,
+    /// Hashes in insertion order
+    order: Vec<ContentHash>,
+    versions: HashMap<ContentHash, VersionInfo>,
+    refs: BTreeMap<String, ContentHash>,
+    sketches: HashMap<ContentHash, Sketch>,
+    chunks: HashMap<ContentHash, ChunkInfo>,
+    /// The index, refs and chunk index as last read or written, for detecting other writers
+    seen: HashMap<&'static str, Option<Vec<u8>>>,
+}
+
+/// A stored chunk.
+#[derive(Debug, Clone, Copy)]
+struct ChunkInfo {
+    /// Number of times chunked versions list the chunk
+    references: u64,
+    stored_size: u64,
+}
+
+/// A version encoded as a keyframe, ready to be stored.
+struct Keyframe {
+    /// A delta against empty data, or the hashes of the chunks if chunked
+    object: Vec<u8>,
+    chunked: bool,
+    /// The chunks in the object
+    chunks: Vec<ContentHash>,
+    /// Encoded chunks that are not stored yet
+    new_chunks: HashMap<ContentHash, Vec<u8>>,
+}
+
+impl Keyframe {
+    /// Bytes storing the keyframe adds to the store.
+    fn cost(&self) -> usize {
+        self.object.len() + self.new_chunks.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+impl DeltaStore {
+    /// Opens the store at `root` with default options, creating it if it does not exist.
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_options(root, StoreOptions::default())
+    }
+
+    /// Opens the store at `root`, creating it if it does not exist.
+    ///
+    /// The options only affect versions inserted from now on.
+    pub fn open_with_options(root: impl AsRef<Path>, options: StoreOptions) -> io::Result<Self> {
+        Self::with_storage(FsStorage::new(root)?, options)
+    }
+
+    /// Returns the store's root directory.
+    pub fn path(&self) -> &Path {
+        self.storage.path()
+    }
+}
+
+impl<S: Storage> DeltaStore<S> {
+    /// Opens the store kept in `storage`, which may be empty.
+    ///
+    /// The options only affect versions inserted from now on.
+    pub fn with_storage(storage: S, options: StoreOptions) -> io::Result<Self> {
+        let mut store = Self {
+            storage,
+            options,
+            order: Vec::new(),
+            versions: HashMap::new(),
+            refs: BTreeMap::new(),
+            sketches: HashMap::new(),
+            chunks: HashMap::new(),
+            seen: HashMap::new(),
+        };
+        store.load_index()?;
+        store.load_refs()?;
+        store.load_sketches()?;
+        store.load_chunk_index()?;
+        Ok(store)
+    }
+
+    /// Returns the storage backend.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Returns the number of stored versions.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns `true` if the store holds no versions.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns `true` if a version with this hash is stored.
+    pub fn contains(&self, hash: &ContentHash) -> bool {
+        self.versions.contains_key(hash)
+    }
+
+    /// Returns metadata about a stored version.
+    pub fn info(&self, hash: &ContentHash) -> Option<&VersionInfo> {
+        self.versions.get(hash)
+    }
+
+    /// Returns the versions decoded to reconstruct a version, from its keyframe to the version
+    /// itself.
+    ///
+    /// The chain's length and cost are also in the version's [`VersionInfo`].
+    pub fn chain(&self, hash: &ContentHash) -> Option<Vec<&VersionInfo>> {
+        let mut chain = vec![self.versions.get(hash)?];
+        while let Some(base) = chain.last().unwrap().base {
+            chain.push(&self.versions[&base]);
+        }
+        chain.reverse();
+        Some(chain)
+    }
+
+    /// Iterates over all versions in insertion order.
+    pub fn versions(&self) -> impl DoubleEndedIterator<Item = &VersionInfo> {
+        self.order.iter().map(|hash| &self.versions[hash])
+    }
+
+    /// Stores `data` and returns its hash.
+    ///
+    /// Inserting content that is already stored is a no-op.
+    pub fn insert(&mut self, data: &[u8]) -> io::Result<ContentHash> {
+        let hash = ContentHash::of(data);
+        if self.contains(&hash) {
+            return Ok(hash);
+        }
+
+        let policy = self.options.keyframes;
+        let keyframe = self.keyframe(data);
+        let mut best: Option<(ContentHash, Vec<u8>)> = None;
+
+        let recent: Vec<ContentHash> = self
+            .versions()
+            .rev()
+            .filter(|v| {
+                v.chain_length < policy.max_chain_length
+                    && v.chain_cost.saturating_add(data.len() as u64) < policy.max_chain_cost
+            })
+            .take(
+                self.options
+                    .sketch_candidates
+                    .max(self.options.base_candidates),
+            )
+            .map(|v| v.hash)
+            .collect();
+        for hash in &recent {
+            if !self.sketches.contains_key(hash) {
+                let content = self.get(hash)?;
+                self.sketches.insert(*hash, Sketch::of(&content));
+            }
+        }
+        let sketch = Sketch::of(data);
+        let candidates = sketch.rank(recent.iter().map(|hash| (*hash, &self.sketches[hash])));
+        for candidate in candidates.into_iter().take(self.options.base_candidates) {
+            let base = self.get(&candidate)?;
+            let encoded = delta::encode_with_options(0, &base, data, &self.options.encode);
+            let cost = self.versions[&candidate].chain_cost + (encoded.len() + data.len()) as u64;
+            let best_size = best
+                .as_ref()
+                .map_or(keyframe.cost(), |(_, delta)| delta.len());
+            if encoded.len() < best_size && cost <= policy.max_chain_cost {
+                best = Some((candidate, encoded));
+            }
+        }
+        if best.as_ref().is_some_and(|(_, delta)| {
+            delta.len() as f64 > keyframe.cost() as f64 * policy.max_delta_ratio
+        }) {
+            best = None;
+        }
+
+        let (best_base, object, chunked) = match best {
+            Some((base, delta)) => (Some(base), delta, false),
+            None => {
+                self.add_chunks(&keyframe)?;
+                (None, keyframe.object, keyframe.chunked)
+            }
+        };
+        self.storage.put(&object_key(&hash), &object)?;
+        let (chain_length, chain_cost) = best_base.map_or((0, 0), |base| {
+            let base = &self.versions[&base];
+            (base.chain_length + 1, base.chain_cost)
+        });
+        self.order.push(hash);
+        self.versions.insert(
+            hash,
+            VersionInfo {
+                hash,
+                base: best_base,
+                size: data.len() as u64,
+                stored_size: object.len() as u64,
+                chunked,
+                chain_length,
+                chain_cost: chain_cost + (object.len() + data.len()) as u64,
+            },
+        );
+        self.sketches.insert(hash, sketch);
+        self.save_index()?;
+        self.save_sketches()?;
+        Ok(hash)
+    }
+
+    /// Reconstructs a stored version.
+    ///
+    /// The result is checked against `hash`, so a corrupted object is reported as
+    /// [`ErrorKind::InvalidData`] rather than returned.
+    pub fn get(&self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        let chain = self
+            .chain(hash)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Version not found"))?;
+
+        let mut data = Vec::new();
+        for info in chain {
+            let object = self.read_object(&info.hash)?;
+            data = if info.chunked {
+                self.read_chunks(&object)?
+            } else {
+                delta::decode(&data, &object).map_err(invalid_data)?
+            };
+        }
+
+        if ContentHash::of(&data) != *hash {
+            return Err(invalid_data("Content hash mismatch"));
+        }
+        Ok(data)
+    }
+
+    /// Reads a version's encoded object without decoding it.
+    ///
+    /// This is a delta against [`VersionInfo::base`], or against empty data for a keyframe.
+    /// A keyframe stored as chunks is reconstructed and encoded as a delta against empty data.
+    pub fn read_raw(&self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        if self.version(hash)?.chunked {
+            let data = self.get(hash)?;
+            return Ok(delta::encode_with_options(
+                0,
+                &[],
+                &data,
+                &self.options.encode,
+            ));
+        }
+        self.read_object(hash)
+    }
+
+    /// Points the ref `name` at a stored version, replacing any previous target.
+    ///
+    /// Ref names must be non-empty and must not contain whitespace.
+    pub fn set_ref(&mut self, name: &str, hash: ContentHash) -> io::Result<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid ref name"));
+        }
+        self.version(&hash)?;
+        self.refs.insert(name.to_string(), hash);
+        self.save_refs()
+    }
+
+    /// Removes a ref and returns its previous target.
+    pub fn remove_ref(&mut self, name: &str) -> io::Result<Option<ContentHash>> {
+        let removed = self.refs.remove(name);
+        if removed.is_some() {
+            self.save_refs()?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the version a ref points at.
+    pub fn get_ref(&self, name: &str) -> Option<ContentHash> {
+        self.refs.get(name).copied()
+    }
+
+    /// Iterates over all refs in name order.
+    pub fn refs(&self) -> impl Iterator<Item = (&str, ContentHash)> {
+        self.refs.iter().map(|(name, hash)| (name.as_str(), *hash))
+    }
+
+    /// Deletes every version the policy does not keep and shortens long delta chains.
+    ///
+    /// Surviving versions stored as deltas against a deleted version are re-encoded as
+    /// keyframes first, so they stay readable. So is the first version of a chain that exceeds
+    /// the policy's limits, re-basing the rest of the chain onto the fresh keyframe.
+    pub fn gc(&mut self, policy: &GcPolicy) -> io::Result<GcStats> {
+        let mut live: HashSet<ContentHash> = self.refs.values().copied().collect();
+        live.extend(self.order.iter().rev().take(policy.keep_recent));
+        let dead: Vec<ContentHash> = self
+            .order
+            .iter()
+            .filter(|hash| !live.contains(hash))
+            .copied()
+            .collect();
+        let stored_before = self.stored_size();
+        let mut stats = GcStats {
+            versions_removed: dead.len(),
+            ..GcStats::default()
+        };
+        let mut released = Vec::new();
+        for hash in &dead {
+            if self.versions[hash].chunked {
+                released.extend(chunk_list(&self.read_object(hash)?)?);
+            }
+        }
+
+        // Bases precede the versions built on them, so each chain is known when it is extended
+        let mut chains: HashMap<ContentHash, (usize, u64)> = HashMap::new();
+        for hash in self.order.clone() {
+            if !live.contains(&hash) {
+                continue;
+            }
+            let info = self.versions[&hash];
+            let chain = info.base.map(|base| {
+                chains
+                    .get(&base)
+                    .map(|&(length, cost)| (length + 1, cost + info.stored_size + info.size))
+            });
+            let rebase = match chain {
+                None => false,
+                // The base is being deleted
+                Some(None) => true,
+                Some(Some((length, cost))) => {
+                    length > policy.max_chain_length || cost > policy.max_chain_cost
+                }
+            };
+
+            let info = if rebase {
+                // Decoded before anything in its chain is deleted
+                let data = self.get(&hash)?;
+                let keyframe = self.keyframe(&data);
+                self.add_chunks(&keyframe)?;
+                self.storage.put(&object_key(&hash), &keyframe.object)?;
+                let info = self.versions.get_mut(&hash).unwrap();
+                info.base = None;
+                info.stored_size = keyframe.object.len() as u64;
+                info.chunked = keyframe.chunked;
+                stats.versions_rebased += 1;
+                *info
+            } else {
+                info
+            };
+            let (length, cost) = match chain {
+                Some(Some(chain)) if !rebase => chain,
+                _ => (0, info.stored_size + info.size),
+            };
+            chains.insert(hash, (length, cost));
+        }
+        if dead.is_empty() && stats.versions_rebased == 0 {
+            return Ok(stats);
+        }
+
+        self.order.retain(|hash| live.contains(hash));
+        for hash in &dead {
+            self.versions.remove(hash);
+            self.sketches.remove(hash);
+        }
+        self.update_chains();
+        self.save_index()?;
+        self.save_sketches()?;
+
+        for hash in &dead {
+            self.storage.delete(&object_key(hash))?;
+        }
+        stats.chunks_removed = self.release_chunks(&released)?;
+        stats.bytes_freed = stored_before.saturating_sub(self.stored_size());
+        Ok(stats)
+    }
+
+    /// Reports chain depths, decode costs and dedup ratios, and checks that the stored objects
+    /// match the index.
+    ///
+    /// Objects are listed but not read; use [`get`](Self::get) to verify their content.
+    pub fn stats(&self) -> io::Result<StoreStats> {
+        let prefix = format!("{}/", OBJECTS_DIR);
+        let mut objects = HashSet::new();
+        let mut orphaned_objects = Vec::new();
+        for key in self.storage.list(&prefix)? {
+            let hash = key[prefix.len()..].parse::<ContentHash>().ok();
+            match hash.filter(|hash| self.contains(hash)) {
+                Some(hash) => {
+                    objects.insert(hash);
+                }
+                None => orphaned_objects.push(key),
+            }
+        }
+        let prefix = format!("{}/", CHUNKS_DIR);
+        for key in self.storage.list(&prefix)? {
+            let hash = key[prefix.len()..].parse::<ContentHash>().ok();
+            if !hash.is_some_and(|hash| self.chunks.contains_key(&hash)) {
+                orphaned_objects.push(key);
+            }
+        }
+        orphaned_objects.sort();
+
+        let live: HashSet<ContentHash> = self.refs.values().copied().collect();
+        Ok(StoreStats {
+            versions: self.versions().copied().collect(),
+            refs: self.refs.len(),
+            unreferenced: self
+                .order
+                .iter()
+                .filter(|hash| !live.contains(hash))
+                .copied()
+                .collect(),
+            orphaned_objects,
+            missing_objects: self
+                .order
+                .iter()
+                .filter(|hash| !objects.contains(hash))
+                .copied()
+                .collect(),
+            chunks: self.chunks.len(),
+            chunk_size: self.chunk_size(),
+        })
+    }
+
+    /// Writes every version to a [pack](crate::pack) and returns the inner writer.
+    ///
+    /// Versions keep their bases, so the pack is about as small as the store. Each entry is
+    /// named after the refs pointing at it, comma-separated.
+    pub fn write_pack<W: Write>(&self, writer: W) -> io::Result<W> {
+        let mut pack = PackWriter::new(writer, self.options.encode.enable_zstd)?;
+        for info in self.versions() {
+            let names: Vec<&str> = self
+                .refs()
+                .filter(|(_, hash)| *hash == info.hash)
+                .map(|(name, _)| name)
+                .collect();
+            let object = self.read_raw(&info.hash)?;
+            pack.add_encoded(&names.join(","), info.hash, info.base, info.size, &object)?;
+        }
+        pack.finish()
+    }
+
+    // Internal helpers
+
+    fn version(&self, hash: &ContentHash) -> io::Result<&VersionInfo> {
+        self.versions
+            .get(hash)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Version not found"))
+    }
+
+    fn stored_size(&self) -> u64 {
+        self.versions
+            .values()
+            .map(|info| info.stored_size)
+            .sum::<u64>()
+            + self.chunk_size()
+    }
+
+    fn chunk_size(&self) -> u64 {
+        self.chunks.values().map(|chunk| chunk.stored_size).sum()
+    }
+
+    fn read_object(&self, hash: &ContentHash) -> io::Result<Vec<u8>> {
+        self.storage
+            .get(&object_key(hash))?
+            .ok_or_else(|| invalid_data("Object missing from store"))
+    }
+
+    /// Encodes `data` as a keyframe, split into chunks if [`StoreOptions::dedup_chunks`] is set.
+    fn keyframe(&self, data: &[u8]) -> Keyframe {
+        if !self.options.dedup_chunks {
+            return Keyframe {
+                object: delta::encode_with_options(0, &[], data, &self.options.encode),
+                chunked: false,
+                chunks: Vec::new(),
+                new_chunks: HashMap::new(),
+            };
+        }
+
+        let mut keyframe = Keyframe {
+            object: Vec::new(),
+            chunked: true,
+            chunks: Vec::new(),
+            new_chunks: HashMap::new(),
+        };
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(chunk_boundary(rest));
+            let hash = ContentHash::of(chunk);
+            if !self.chunks.contains_key(&hash) && !keyframe.new_chunks.contains_key(&hash) {
+                let encoded = delta::encode_with_options(0, &[], chunk, &self.options.encode);
+                keyframe.new_chunks.insert(hash, encoded);
+            }
+            keyframe.object.extend_from_slice(hash.as_bytes());
+            keyframe.chunks.push(hash);
+            rest = tail;
+        }
+        keyframe
+    }
+
+    /// Writes a keyframe's new chunks and counts the references to all of its chunks.
+    fn add_chunks(&mut self, keyframe: &Keyframe) -> io::Result<()> {
+        if keyframe.chunks.is_empty() {
+            return Ok(());
+        }
+        for (hash, encoded) in &keyframe.new_chunks {
+            self.storage.put(&chunk_key(hash), encoded)?;
+        }
+        for hash in &keyframe.chunks {
+            let chunk = self.chunks.entry(*hash).or_insert(ChunkInfo {
+                references: 0,
+                stored_size: keyframe.new_chunks.get(hash).map_or(0, |c| c.len() as u64),
+            });
+            chunk.references += 1;
+        }
+        self.save_chunk_index()
+    }
+
+    /// Drops one reference to each of `hashes` and deletes the chunks no version uses anymore.
+    /// Returns the number of deleted chunks.
+    fn release_chunks(&mut self, hashes: &[ContentHash]) -> io::Result<usize> {
+        if hashes.is_empty() {
+            return Ok(0);
+        }
+        let mut unused = Vec::new();
+        for hash in hashes {
+            if let Some(chunk) = self.chunks.get_mut(hash) {
+                chunk.references = chunk.references.saturating_sub(1);
+                if chunk.references == 0 {
+                    self.chunks.remove(hash);
+                    unused.push(*hash);
+                }
+            }
+        }
+        self.save_chunk_index()?;
+        for hash in &unused {
+            self.storage.delete(&chunk_key(hash))?;
+        }
+        Ok(unused.len())
+    }
+
+    /// Reassembles a chunked keyframe from its chunk list.
+    fn read_chunks(&self, object: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for hash in chunk_list(object)? {
+            let encoded = self
+                .storage
+                .get(&chunk_key(&hash))?
+                .ok_or_else(|| invalid_data("Chunk missing from store"))?;
+            data.extend(delta::decode(&[], &encoded).map_err(invalid_data)?);
+        }
+        Ok(data)
+    }
+
+    /// Replaces the index or refs, failing if another writer changed them since they were last
+    /// read or written.
+    fn replace(&mut self, key: &'static str, content: Vec<u8>) -> io::Result<()> {
+        let expected = self.seen.get(key).cloned().flatten();
+        if !self
+            .storage
+            .compare_and_swap(key, expected.as_deref(), &content)?
+        {
+            return Err(io::Error::other(
+                "Store was changed by another writer; reopen it and retry",
+            ));
+        }
+        self.seen.insert(key, Some(content));
+        Ok(())
+    }
+
+    fn update_chains(&mut self) {
+        for hash in &self.order {
+            let info = self.versions[hash];
+            let (chain_length, chain_cost) = info.base.map_or((0, 0), |base| {
+                let base = &self.versions[&base];
+                (base.chain_length + 1, base.chain_cost)
+            });
+            let info = self.versions.get_mut(hash).unwrap();
+            info.chain_length = chain_length;
+            info.chain_cost = chain_cost + info.stored_size + info.size;
+        }
+    }
+
+    fn load_index(&mut self) -> io::Result<()> {
+        let Some(content) = self.load_text(INDEX_FILE)? else {
+            return Ok(());
+        };
+
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let info = parse_index_line(line).ok_or_else(|| invalid_data("Corrupt store index"))?;
+            if info
+                .base
+                .is_some_and(|base| !self.versions.contains_key(&base))
+            {
+                return Err(invalid_data("Corrupt store index"));
+            }
+            self.order.push(info.hash);
+            self.versions.insert(info.hash, info);
+        }
+        self.update_chains();
+        Ok(())
+    }
+
+    fn save_index(&mut self) -> io::Result<()> {
+        let mut content = String::new();
+        for info in self.versions() {
+            let base = info.base.map_or("-".to_string(), |base| base.to_string());
+            content.push_str(&format!(
+                "{} {} {} {}{}\n",
+                info.hash,
+                base,
+                info.size,
+                info.stored_size,
+                if info.chunked { " chunked" } else { "" }
+            ));
+        }
+        self.replace(INDEX_FILE, content.into_bytes())
+    }
+
+    fn load_chunk_index(&mut self) -> io::Result<()> {
+        let Some(content) = self.load_text(CHUNK_INDEX_FILE)? else {
+            return Ok(());
+        };
+
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let (hash, chunk) =
+                parse_chunk_line(line).ok_or_else(|| invalid_data("Corrupt chunk index"))?;
+            self.chunks.insert(hash, chunk);
+        }
+        Ok(())
+    }
+
+    fn save_chunk_index(&mut self) -> io::Result<()> {
+        let mut chunks: Vec<_> = self.chunks.iter().collect();
+        chunks.sort_by_key(|(hash, _)| **hash);
+        let mut content = String::new();
+        for (hash, chunk) in chunks {
+            content.push_str(&format!(
+                "{} {} {}\n",
+                hash, chunk.references, chunk.stored_size
+            ));
+        }
+        self.replace(CHUNK_INDEX_FILE, content.into_bytes())
+    }
+
+    fn load_refs(&mut self) -> io::Result<()> {
+        let Some(content) = self.load_text(REFS_FILE)? else {
+            return Ok(());
+        };
+
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let (name, hash) = line
+                .split_once(' ')
+                .and_then(|(name, hash)| Some((name, hash.parse::<ContentHash>().ok()?)))
+                .filter(|(_, hash)| self.versions.contains_key(hash))
+                .ok_or_else(|| invalid_data("Corrupt store refs"))?;
+            self.refs.insert(name.to_string(), hash);
+        }
+        Ok(())
+    }
+
+    fn save_refs(&mut self) -> io::Result<()> {
+        let mut content = String::new();
+        for (name, hash) in &self.refs {
+            content.push_str(&format!("{} {}\n", name, hash));
+        }
+        self.replace(REFS_FILE, content.into_bytes())
+    }
+
+    /// Reads the index or refs and remembers them for [`replace`](Self::replace).
+    fn load_text(&mut self, key: &'static str) -> io::Result<Option<String>> {
+        let content = self.storage.get(key)?;
+        self.seen.insert(key, content.clone());
+        content
+            .map(|content| String::from_utf8(content).map_err(|_| invalid_data("Corrupt store")))
+            .transpose()
+    }
+
+    /// Loads cached sketches. A damaged cache is dropped; sketches are recomputed on demand.
+    fn load_sketches(&mut self) -> io::Result<()> {
+        let Some(content) = self.storage.get(SKETCHES_FILE)? else {
+            return Ok(());
+        };
+        let Some(sketches) = parse_sketches(&content) else {
+            return Ok(());
+        };
+        self.sketches = sketches
+            .into_iter()
+            .filter(|(hash, _)| self.versions.contains_key(hash))
+            .collect();
+        Ok(())
+    }
+
+    fn save_sketches(&self) -> io::Result<()> {
+        let mut content = Vec::new();
+        for info in self.versions() {
+            if let Some(sketch) = self.sketches.get(&info.hash) {
+                let bytes = sketch.to_bytes();
+                content.extend_from_slice(info.hash.as_bytes());
+                content.push((bytes.len() / 8) as u8);
+                content.extend_from_slice(&bytes);
+            }
+        }
+        self.storage.put(SKETCHES_FILE, &content)
+    }
+}
+
+fn object_key(hash: &ContentHash) -> String {
+    format!("{}/{}", OBJECTS_DIR, hash)
+}
+
+fn chunk_key(hash: &ContentHash) -> String {
+    format!("{}/{}", CHUNKS_DIR, hash)
+}
+
+/// Splits a chunked keyframe's object into its chunk hashes.
+fn chunk_list(object: &[u8]) -> io::Result<Vec<ContentHash>> {
+    let (hashes, rest) = object.as_chunks::<32>();
+    if !rest.is_empty() {
+        return Err(invalid_data("Corrupt chunk list"));
+    }
+    Ok(hashes
+        .iter()
+        .copied()
+        .map(ContentHash::from_bytes)
+        .collect())
+}
+
+fn parse_index_line(line: &str) -> Option<VersionInfo> {
+    let mut fields = line.split(' ');
+    let hash = fields.next()?.parse().ok()?;
+    let base = match fields.next()? {
+        "-" => None,
+        base => Some(base.parse().ok()?),
+    };
+    let size = fields.next()?.parse().ok()?;
+    let stored_size = fields.next()?.parse().ok()?;
+    let chunked = match fields.next() {
+        None => false,
+        Some("chunked") if base.is_none() => true,
+        Some(_) => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(VersionInfo {
+        hash,
+        base,
+        size,
+        stored_size,
+        chunked,
+        chain_length: 0,
+        chain_cost: 0,
+    })
+}
+
+fn parse_chunk_line(line: &str) -> Option<(ContentHash, ChunkInfo)> {
+    let mut fields = line.split(' ');
+    let hash = fields.next()?.parse().ok()?;
+    let references = fields.next()?.parse().ok()?;
+    let stored_size = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((
+        hash,
+        ChunkInfo {
+            references,
+            stored_size,
+        },
+    ))
+}
+
+fn parse_sketches(mut content: &[u8]) -> Option<Vec<(ContentHash, Sketch)>> {
+    let mut sketches = Vec::new();
+    while !content.is_empty() {
+        let (hash, rest) = content.split_first_chunk::<32>()?;
+        let (&count, rest) = rest.split_first()?;
+        let len = count as usize * 8;
+        if rest.len() < len {
+            return None;
+        }
+        let sketch = Sketch::from_bytes(&rest[..len])?;
+        sketches.push((ContentHash::from_bytes(*hash), sketch));
+        content = &rest[len..];
+    }
+    Some(sketches)
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it into place.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("xpatch-store-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn versions(count: usize) -> Vec<Vec<u8>> {
+        let mut text: Vec<u8> = (0..4000u32).map(|i| b'a' + (i % 26) as u8).collect();
+        (0..count)
+            .map(|i| {
+                text.splice(i * 10..i * 10, format!("edit {} ", i).bytes());
+                text.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_get_roundtrip() {
+        let dir = temp_store_dir("roundtrip");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let contents = versions(10);
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+
+        assert_eq!(store.len(), 10);
+        for (hash, content) in hashes.iter().zip(&contents) {
+            assert_eq!(store.get(hash).unwrap(), *content);
+        }
+
+        // Later versions are stored as small deltas
+        let info = store.info(&hashes[9]).unwrap();
+        assert!(info.base.is_some());
+        assert!(info.stored_size < info.size / 10);
+        let base = store.get(&info.base.unwrap()).unwrap();
+        let raw = store.read_raw(&hashes[9]).unwrap();
+        assert_eq!(delta::decode(&base, &raw).unwrap(), contents[9]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_insert_deduplicates() {
+        let dir = temp_store_dir("dedup");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let a = store.insert(b"same content").unwrap();
+        let b = store.insert(b"same content").unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(store.len(), 1);
+        assert_eq!(a, ContentHash::of(b"same content"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chain_length_limit() {
+        let dir = temp_store_dir("chain");
+        let options = StoreOptions {
+            keyframes: KeyframePolicy {
+                max_chain_length: 3,
+                ..KeyframePolicy::default()
+            },
+            base_candidates: 1,
+            ..StoreOptions::default()
+        };
+        let mut store = DeltaStore::open_with_options(&dir, options).unwrap();
+        for content in versions(12) {
+            store.insert(&content).unwrap();
+        }
+
+        assert!(store.versions().all(|v| v.chain_length <= 3));
+        assert_eq!(store.versions().map(|v| v.chain_length).max(), Some(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_insert_picks_similar_base() {
+        let dir = temp_store_dir("similar");
+        let options = StoreOptions {
+            base_candidates: 1,
+            ..StoreOptions::default()
+        };
+        let mut store = DeltaStore::open_with_options(&dir, options.clone()).unwrap();
+        let text = |seed: u32| -> Vec<u8> {
+            (0..3000u32)
+                .map(|i| b'a' + (i.wrapping_mul(seed).wrapping_add(i / 7) % 26) as u8)
+                .collect()
+        };
+        let original = store.insert(&text(3)).unwrap();
+        for seed in 5..15 {
+            store.insert(&text(seed)).unwrap();
+        }
+        let mut edited = text(3);
+        edited.splice(100..100, *b"an edit");
+        let hash = store.insert(&edited).unwrap();
+        assert_eq!(store.info(&hash).unwrap().base, Some(original));
+
+        // Sketches survive a reopen
+        let reopened = DeltaStore::open_with_options(&dir, options).unwrap();
+        assert_eq!(reopened.sketches.len(), 12);
+        assert_eq!(reopened.sketches[&hash], Sketch::of(&edited));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chain_cost_limit() {
+        let dir = temp_store_dir("cost");
+        let contents = versions(12);
+        let max_chain_cost = 4 * (contents[11].len() as u64 + 100);
+        let options = StoreOptions {
+            keyframes: KeyframePolicy {
+                max_chain_cost,
+                ..KeyframePolicy::default()
+            },
+            sketch_candidates: 1,
+            base_candidates: 1,
+            ..StoreOptions::default()
+        };
+        let mut store = DeltaStore::open_with_options(&dir, options).unwrap();
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+
+        assert!(store.versions().all(|v| v.chain_cost <= max_chain_cost));
+        // Unlimited, the chain would grow to 11 deltas
+        assert_eq!(store.versions().map(|v| v.chain_length).max(), Some(3));
+
+        let chain = store.chain(&hashes[11]).unwrap();
+        let info = store.info(&hashes[11]).unwrap();
+        assert_eq!(chain.len(), info.chain_length + 1);
+        assert!(chain[0].base.is_none());
+        assert_eq!(chain.last().unwrap().hash, hashes[11]);
+        assert_eq!(
+            chain.iter().map(|v| v.stored_size + v.size).sum::<u64>(),
+            info.chain_cost
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delta_ratio_stores_keyframes() {
+        let dir = temp_store_dir("ratio");
+        let options = StoreOptions {
+            keyframes: KeyframePolicy {
+                max_delta_ratio: 0.0,
+                ..KeyframePolicy::default()
+            },
+            ..StoreOptions::default()
+        };
+        let mut store = DeltaStore::open_with_options(&dir, options).unwrap();
+        for content in versions(4) {
+            store.insert(&content).unwrap();
+        }
+
+        assert!(
+            store
+                .versions()
+                .all(|v| v.base.is_none() && v.chain_length == 0)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_persists_versions_and_refs() {
+        let dir = temp_store_dir("reopen");
+        let contents = versions(3);
+        let hashes: Vec<_> = {
+            let mut store = DeltaStore::open(&dir).unwrap();
+            let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+            store.set_ref("stable", hashes[1]).unwrap();
+            hashes
+        };
+
+        let store = DeltaStore::open(&dir).unwrap();
+        assert_eq!(store.versions().map(|v| v.hash).collect::<Vec<_>>(), hashes);
+        assert_eq!(store.get_ref("stable"), Some(hashes[1]));
+        assert_eq!(store.get(&hashes[2]).unwrap(), contents[2]);
+        assert_eq!(store.info(&hashes[2]).unwrap().chain_length, 2);
+        assert_eq!(
+            store.info(&hashes[2]).unwrap().chain_cost,
+            store
+                .versions()
+                .map(|v| v.stored_size + v.size)
+                .sum::<u64>()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writer_detected() {
+        let dir = temp_store_dir("concurrent");
+        let mut first = DeltaStore::open(&dir).unwrap();
+        let mut second = DeltaStore::open(&dir).unwrap();
+        let hash = first.insert(b"first writer").unwrap();
+
+        // The second store has not seen the first one's index
+        assert!(second.insert(b"second writer").is_err());
+
+        let mut second = DeltaStore::open(&dir).unwrap();
+        first.set_ref("a", hash).unwrap();
+        assert!(second.set_ref("b", hash).is_err());
+
+        let mut second = DeltaStore::open(&dir).unwrap();
+        second.insert(b"second writer").unwrap();
+        second.set_ref("b", hash).unwrap();
+        let reopened = DeltaStore::open(&dir).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened.refs().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_and_rewrites_orphans() {
+        let dir = temp_store_dir("gc");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let contents = versions(6);
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+        store.set_ref("old", hashes[0]).unwrap();
+        store.set_ref("latest", hashes[5]).unwrap();
+
+        let stats = store.gc(&GcPolicy::default()).unwrap();
+        assert_eq!(stats.versions_removed, 4);
+        assert_eq!(stats.versions_rebased, 1);
+        assert_eq!(store.len(), 2);
+        assert!(!store.contains(&hashes[3]));
+        assert!(!dir.join(OBJECTS_DIR).join(hashes[3].to_string()).exists());
+        assert_eq!(store.get(&hashes[0]).unwrap(), contents[0]);
+        assert_eq!(store.get(&hashes[5]).unwrap(), contents[5]);
+
+        // Nothing left to collect
+        assert_eq!(store.gc(&GcPolicy::default()).unwrap(), GcStats::default());
+
+        let reopened = DeltaStore::open(&dir).unwrap();
+        assert_eq!(reopened.get(&hashes[5]).unwrap(), contents[5]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gc_rebases_long_chains() {
+        let dir = temp_store_dir("gc-chains");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let contents = versions(6);
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+        assert_eq!(store.info(&hashes[5]).unwrap().chain_length, 5);
+
+        let policy = GcPolicy {
+            keep_recent: 6,
+            max_chain_length: 2,
+            ..GcPolicy::default()
+        };
+        let stats = store.gc(&policy).unwrap();
+        assert_eq!(stats.versions_removed, 0);
+        assert_eq!(stats.versions_rebased, 1);
+        let lengths: Vec<_> = store.versions().map(|v| v.chain_length).collect();
+        assert_eq!(lengths, [0, 1, 2, 0, 1, 2]);
+
+        // Unreferenced versions beyond the most recent ones are deleted
+        let policy = GcPolicy {
+            keep_recent: 2,
+            ..GcPolicy::default()
+        };
+        let stats = store.gc(&policy).unwrap();
+        assert_eq!(stats.versions_removed, 4);
+        assert!(stats.bytes_freed > 0);
+
+        let reopened = DeltaStore::open(&dir).unwrap();
+        assert_eq!(reopened.len(), 2);
+        for (hash, content) in hashes.iter().zip(&contents).skip(4) {
+            assert_eq!(reopened.get(hash).unwrap(), *content);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dedup_chunks() {
+        let dir = temp_store_dir("chunks");
+        let options = StoreOptions {
+            keyframes: KeyframePolicy {
+                max_chain_length: 0,
+                ..KeyframePolicy::default()
+            },
+            dedup_chunks: true,
+            ..StoreOptions::default()
+        };
+        let mut store = DeltaStore::open_with_options(&dir, options.clone()).unwrap();
+        // Firmware variants: a large common part and a small board-specific one
+        let mut state = 7u64;
+        let common: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+        let variants: Vec<Vec<u8>> = (0..5)
+            .map(|i| [&common[..], format!("board {i}").as_bytes()].concat())
+            .collect();
+        let hashes: Vec<_> = variants.iter().map(|v| store.insert(v).unwrap()).collect();
+
+        let stats = store.stats().unwrap();
+        assert!(stats.versions.iter().all(|v| v.chunked && v.base.is_none()));
+        assert!(stats.stored_size() < common.len() as u64 * 3 / 2);
+        assert!(stats.orphaned_objects.is_empty());
+        for (hash, variant) in hashes.iter().zip(&variants) {
+            assert_eq!(store.get(hash).unwrap(), *variant);
+        }
+        let raw = store.read_raw(&hashes[2]).unwrap();
+        assert_eq!(delta::decode(&[], &raw).unwrap(), variants[2]);
+
+        // Only the chunks of the deleted variants' endings go
+        store.set_ref("latest", hashes[4]).unwrap();
+        let chunks = stats.chunks;
+        let gc = store.gc(&GcPolicy::default()).unwrap();
+        assert_eq!(gc.versions_removed, 4);
+        assert_eq!(gc.chunks_removed, 4);
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.chunks, chunks - 4);
+        assert!(stats.orphaned_objects.is_empty());
+
+        let reopened = DeltaStore::open_with_options(&dir, options).unwrap();
+        assert_eq!(reopened.get(&hashes[4]).unwrap(), variants[4]);
+        assert_eq!(reopened.stats().unwrap().chunks, chunks - 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refs() {
+        let dir = temp_store_dir("refs");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let hash = store.insert(b"content").unwrap();
+
+        assert!(store.set_ref("bad name", hash).is_err());
+        assert!(store.set_ref("", hash).is_err());
+        let unknown = ContentHash::of(b"not stored");
+        assert_eq!(
+            store.set_ref("x", unknown).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        store.set_ref("a", hash).unwrap();
+        assert_eq!(store.refs().collect::<Vec<_>>(), vec![("a", hash)]);
+        assert_eq!(store.remove_ref("a").unwrap(), Some(hash));
+        assert_eq!(store.remove_ref("a").unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_object_detected() {
+        let dir = temp_store_dir("corrupt");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let a = store.insert(b"version one").unwrap();
+        let b = store.insert(b"version two").unwrap();
+
+        // Swap the objects' content
+        let object = fs::read(dir.join(object_key(&b))).unwrap();
+        fs::write(dir.join(object_key(&a)), object).unwrap();
+        assert!(store.get(&a).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let dir = temp_store_dir("stats");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let contents = versions(4);
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+        store.set_ref("latest", hashes[3]).unwrap();
+
+        let stats = store.stats().unwrap();
+        assert!(stats.is_healthy());
+        assert_eq!(stats.versions.len(), 4);
+        assert_eq!(stats.keyframes(), 1);
+        assert_eq!(stats.refs, 1);
+        assert_eq!(stats.unreferenced, &hashes[..3]);
+        assert_eq!(stats.deepest().unwrap().hash, hashes[3]);
+        assert_eq!(stats.costliest().unwrap().hash, hashes[3]);
+        assert_eq!(
+            stats.content_size(),
+            contents.iter().map(|c| c.len() as u64).sum::<u64>()
+        );
+        assert!(stats.dedup_ratio() > 1.0);
+        assert!(stats.orphaned_objects.is_empty());
+
+        fs::write(dir.join(OBJECTS_DIR).join("leftover.tmp"), b"").unwrap();
+        fs::remove_file(dir.join(object_key(&hashes[1]))).unwrap();
+        let stats = store.stats().unwrap();
+        assert!(!stats.is_healthy());
+        assert_eq!(stats.missing_objects, [hashes[1]]);
+        assert_eq!(stats.orphaned_objects, ["objects/leftover.tmp"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_pack() {
+        let dir = temp_store_dir("pack");
+        let mut store = DeltaStore::open(&dir).unwrap();
+        let contents = versions(4);
+        let hashes: Vec<_> = contents.iter().map(|v| store.insert(v).unwrap()).collect();
+        store.set_ref("latest", hashes[3]).unwrap();
+
+        let pack = store.write_pack(Vec::new()).unwrap();
+        let mut reader = crate::pack::PackReader::new(io::Cursor::new(pack)).unwrap();
+        assert_eq!(reader.len(), 4);
+        assert_eq!(reader.entry(&hashes[3]).unwrap().name, "latest");
+        assert_eq!(
+            reader.entry(&hashes[3]).unwrap().base,
+            store.info(&hashes[3]).unwrap().base
+        );
+        for (hash, content) in hashes.iter().zip(&contents) {
+            assert_eq!(reader.get(hash).unwrap(), *content);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_content_hash_parse() {
+        let hash = ContentHash::of(b"abc");
+        assert_eq!(
+            hash.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash.to_string().parse::<ContentHash>().unwrap(), hash);
+        assert!("xyz".parse::<ContentHash>().is_err());
+        assert!("zz".repeat(32).parse::<ContentHash>().is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Storing files of a git repository in a [`DeltaStore`] through a clean/smudge filter.
+//!
+//! Git stores every version of a binary asset in full, so a large file that changes a little
+//! in each commit bloats the repository. With the filter installed for such files, git only
+//! commits a small [`Pointer`] while the content goes into a delta store, where each version is
+//! a keyframe or a delta against a similar earlier version. On checkout the pointer is
+//! replaced by the content again.
+//!
+//! [`clean`] runs when git reads a file into the index: it stores the content and returns the
+//! pointer to commit. [`smudge`] runs when git writes a file to the working tree and turns the
+//! pointer back into the content. Data that is not a pointer passes through unchanged, so
+//! files committed before the filter was installed still check out. Every stored version gets
+//! the ref `filter/<hash>`, so [`DeltaStore::gc`] keeps versions older commits point to.
+//!
+//! The pointer is three lines of text:
+//!
+//! ```text
+//! xpatch-filter v1
+//! sha256 <64 hex digits>
+//! size <bytes>
+//! ```
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::store::DeltaStore;
+//! use xpatch::store::filter::{self, Pointer};
+//!
+//! # let dir = std::env::temp_dir().join(format!("xpatch-filter-doc-{}", std::process::id()));
+//! let mut store = DeltaStore::open(&dir)?;
+//! let content = b"PNG...lots of pixels".repeat(100);
+//!
+//! let committed = filter::clean(&mut store, &content)?;
+//! assert!(Pointer::parse(&committed).is_some());
+//! assert_eq!(filter::smudge(&store, &committed)?, content);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use super::{ContentHash, DeltaStore, Storage};
+use std::io::{self, ErrorKind};
+
+/// First line of a pointer.
+const HEADER: &str = "xpatch-filter v1";
+
+/// Prefix of the refs that keep stored versions alive.
+pub const REF_PREFIX: &str = "filter/";
+
+/// What git commits in place of a filtered file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pointer {
+    /// Hash of the content in the store
+    pub hash: ContentHash,
+    /// Size of the content in bytes
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Returns the pointer for `data`.
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            hash: ContentHash::of(data),
+            size: data.len() as u64,
+        }
+    }
+
+    /// Parses a pointer, returning `None` if `data` is not one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.strip_suffix('\n')?.split('\n');
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let hash = lines.next()?.strip_prefix("sha256 ")?.parse().ok()?;
+        let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+        match lines.next() {
+            None => Some(Self { hash, size }),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the pointer as committed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!("{}\nsha256 {}\nsize {}\n", HEADER, self.hash, self.size).into_bytes()
+    }
+}
+
+/// Stores `data` and returns the pointer to commit instead.
+///
+/// A pointer is returned as it is, e.g. when git re-cleans a file that was checked out
+/// without the filter.
+pub fn clean<S: Storage>(store: &mut DeltaStore<S>, data: &[u8]) -> io::Result<Vec<u8>> {
+    if Pointer::parse(data).is_some() {
+        return Ok(data.to_vec());
+    }
+    let hash = store.insert(data)?;
+    let name = format!("{}{}", REF_PREFIX, hash);
+    if store.get_ref(&name) != Some(hash) {
+        store.set_ref(&name, hash)?;
+    }
+    Ok(Pointer::of(data).to_bytes())
+}
+
+/// Returns the content a committed pointer stands for, or `data` itself if it is not a
+/// pointer.
+///
+/// # Errors
+/// Fails with [`ErrorKind::NotFound`] if the store doesn't have the content, e.g. because it
+/// was committed in another clone with its own store.
+pub fn smudge<S: Storage>(store: &DeltaStore<S>, data: &[u8]) -> io::Result<Vec<u8>> {
+    let Some(pointer) = Pointer::parse(data) else {
+        return Ok(data.to_vec());
+    };
+    if !store.contains(&pointer.hash) {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Version {} is not in the store", pointer.hash),
+        ));
+    }
+    let content = store.get(&pointer.hash)?;
+    if content.len() as u64 != pointer.size {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Stored version has the wrong size",
+        ));
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::GcPolicy;
+    use std::fs;
+
+    #[test]
+    fn test_clean_smudge() {
+        let dir = std::env::temp_dir().join(format!("xpatch-filter-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = DeltaStore::open(&dir).unwrap();
+
+        let v1: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 253) as u8).collect();
+        let mut v2 = v1.clone();
+        v2[100..110].fill(0xAA);
+        let p1 = clean(&mut store, &v1).unwrap();
+        let p2 = clean(&mut store, &v2).unwrap();
+        assert_eq!(Pointer::parse(&p2), Some(Pointer::of(&v2)));
+        assert_eq!(clean(&mut store, &p2).unwrap(), p2);
+        assert_eq!(clean(&mut store, &v2).unwrap(), p2);
+        assert!(store.info(&ContentHash::of(&v2)).unwrap().base.is_some());
+
+        // Both versions survive a gc
+        store.gc(&GcPolicy::default()).unwrap();
+        assert_eq!(smudge(&store, &p1).unwrap(), v1);
+        assert_eq!(smudge(&store, &p2).unwrap(), v2);
+        assert_eq!(smudge(&store, b"not a pointer").unwrap(), b"not a pointer");
+
+        let missing = Pointer::of(b"never stored").to_bytes();
+        let err = smudge(&store, &missing).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        assert_eq!(Pointer::parse(&p1[..p1.len() - 1]), None);
+        let mut extra = p1.clone();
+        extra.extend_from_slice(b"more\n");
+        assert_eq!(Pointer::parse(&extra), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Windowed streaming encoder and decoder for large inputs.
+//!
+//! [`delta::encode`](crate::delta::encode) needs the complete new file in memory. The types in
+//! this module accept the new data (or the encoded stream) in arbitrary chunks and process it in
+//! fixed-size windows, so only the base data and roughly one window have to be resident at a time.
+//!
+//! # Format
+//!
+//! ```text
+//! "XPS" 0x01 | varint(window_size) | frame* | varint(0)
+//! frame = varint(len) | delta
+//! ```
+//!
+//! Window `i` of the new data is encoded as a regular xpatch delta against the base region
+//! starting half a window before its offset and ending half a window after it. Since deltas are
+//! never empty, a zero length marks the end of the stream.
+//!
+//! Windows are independent, so with the `parallel` feature the encoder can encode several of
+//! them at once on a rayon thread pool (see [`StreamEncoder::set_parallel`]).
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::stream::{StreamDecoder, StreamEncoder};
+//!
+//! let base = vec![7u8; 10_000];
+//! let mut new = base.clone();
+//! new[5_000] = 8;
+//!
+//! let mut encoder = StreamEncoder::with_window_size(&base[..], 0, true, 4096);
+//! let mut patch = Vec::new();
+//! for chunk in new.chunks(1000) {
+//!     patch.extend(encoder.push(chunk));
+//! }
+//! patch.extend(encoder.finish());
+//!
+//! let mut decoder = StreamDecoder::new(&base[..]);
+//! let mut decoded = Vec::new();
+//! for chunk in patch.chunks(7) {
+//!     decoded.extend(decoder.push(chunk).unwrap());
+//! }
+//! decoder.finish().unwrap();
+//! assert_eq!(decoded, new);
+//! ```
+
+use crate::delta;
+#[cfg(feature = "decode")]
+use crate::varint::decode_varint;
+#[cfg(feature = "encode")]
+use crate::varint::encode_varint;
+
+/// Magic bytes identifying a windowed stream.
+pub const STREAM_MAGIC: [u8; 4] = *b"XPS\x01";
+
+/// Default window size (1 MiB).
+pub const DEFAULT_WINDOW_SIZE: usize = 1 << 20;
+
+/// Maximum number of bytes in a varint encoding a `usize`.
+#[cfg(feature = "decode")]
+const MAX_VARINT_LEN: usize = 10;
+
+/// Encodes new data chunk by chunk against an in-memory base.
+#[cfg(feature = "encode")]
+pub struct StreamEncoder<B: AsRef<[u8]>> {
+    base: B,
+    tag: usize,
+    enable_zstd: bool,
+    window_size: usize,
+    pending: Vec<u8>,
+    offset: usize,
+    header_written: bool,
+    #[cfg(feature = "parallel")]
+    parallel: bool,
+}
+
+#[cfg(feature = "encode")]
+impl<B: AsRef<[u8]>> StreamEncoder<B> {
+    /// Creates an encoder using [`DEFAULT_WINDOW_SIZE`].
+    pub fn new(base: B, tag: usize, enable_zstd: bool) -> Self {
+        Self::with_window_size(base, tag, enable_zstd, DEFAULT_WINDOW_SIZE)
+    }
+
+    /// Creates an encoder with a custom window size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_size` is zero.
+    pub fn with_window_size(base: B, tag: usize, enable_zstd: bool, window_size: usize) -> Self {
+        assert!(window_size > 0, "window size must be non-zero");
+        Self {
+            base,
+            tag,
+            enable_zstd,
+            window_size,
+            pending: Vec::new(),
+            offset: 0,
+            header_written: false,
+            #[cfg(feature = "parallel")]
+            parallel: false,
+        }
+    }
+
+    /// Enables or disables encoding windows in parallel on the thread pool set with
+    /// [`set_thread_pool`](crate::set_thread_pool), rayon's global pool by default.
+    ///
+    /// When enabled, the encoder buffers up to one window per thread before encoding them
+    /// together, trading memory for throughput. The output is identical either way.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallel(&mut self, enabled: bool) {
+        self.parallel = enabled;
+    }
+
+    /// Feeds the next chunk of new data and returns any stream bytes that are ready.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = self.take_header();
+        self.pending.extend_from_slice(chunk);
+
+        let ready = self.pending.len() / self.window_size;
+        if ready > 0 && ready >= self.batch_size() {
+            self.flush(&mut out, false);
+        }
+
+        out
+    }
+
+    /// Flushes the remaining data and returns the final stream bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut out = self.take_header();
+        self.flush(&mut out, true);
+        out.extend(encode_varint(0));
+        out
+    }
+
+    /// Number of complete windows to collect before encoding.
+    fn batch_size(&self) -> usize {
+        #[cfg(feature = "parallel")]
+        if self.parallel {
+            return crate::parallel::num_threads();
+        }
+        1
+    }
+
+    /// Encodes all complete pending windows, and the partial tail if `tail` is set.
+    fn flush(&mut self, out: &mut Vec<u8>, tail: bool) {
+        let full = self.pending.len() / self.window_size;
+        let windows: Vec<(usize, &[u8])> = self
+            .pending
+            .chunks(self.window_size)
+            .take(if tail { usize::MAX } else { full })
+            .enumerate()
+            .map(|(i, window)| (self.offset + i * self.window_size, window))
+            .collect();
+
+        let (base, tag, zstd, window_size) = (
+            self.base.as_ref(),
+            self.tag,
+            self.enable_zstd,
+            self.window_size,
+        );
+        let encode = |&(offset, window): &(usize, &[u8])| {
+            delta::encode(tag, base_window(base, offset, window_size), window, zstd)
+        };
+
+        #[cfg(feature = "parallel")]
+        let deltas: Vec<Vec<u8>> = if self.parallel {
+            use rayon::prelude::*;
+            crate::parallel::run(
+                || windows.par_iter().map(encode).collect(),
+                || windows.iter().map(encode).collect(),
+            )
+        } else {
+            windows.iter().map(encode).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let deltas: Vec<Vec<u8>> = windows.iter().map(encode).collect();
+
+        let consumed: usize = windows.iter().map(|(_, window)| window.len()).sum();
+        for delta in deltas {
+            out.extend(encode_varint(delta.len()));
+            out.extend(delta);
+        }
+
+        self.offset += consumed;
+        self.pending.drain(..consumed);
+    }
+
+    fn take_header(&mut self) -> Vec<u8> {
+        if self.header_written {
+            return Vec::new();
+        }
+        self.header_written = true;
+
+        let mut header = STREAM_MAGIC.to_vec();
+        header.extend(encode_varint(self.window_size));
+        header
+    }
+}
+
+/// Reconstructs new data from a stream fed in arbitrary chunks.
+#[cfg(feature = "decode")]
+pub struct StreamDecoder<B: AsRef<[u8]>> {
+    base: B,
+    buffer: Vec<u8>,
+    window_size: Option<usize>,
+    offset: usize,
+    finished: bool,
+}
+
+#[cfg(feature = "decode")]
+impl<B: AsRef<[u8]>> StreamDecoder<B> {
+    /// Creates a decoder for streams encoded against `base`.
+    pub fn new(base: B) -> Self {
+        Self {
+            base,
+            buffer: Vec::new(),
+            window_size: None,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Feeds the next chunk of the stream and returns any decoded bytes that are ready.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if self.finished {
+            return if chunk.is_empty() {
+                Ok(Vec::new())
+            } else {
+                Err("Trailing data after end of stream")
+            };
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let mut out = Vec::new();
+        let mut pos = 0;
+
+        let window_size = match self.window_size {
+            Some(size) => size,
+            None => {
+                if self.buffer.len() < STREAM_MAGIC.len() {
+                    return Ok(out);
+                }
+                if self.buffer[..STREAM_MAGIC.len()] != STREAM_MAGIC {
+                    return Err("Invalid stream header");
+                }
+                let Some((size, len)) = read_varint(&self.buffer[STREAM_MAGIC.len()..])? else {
+                    return Ok(out);
+                };
+                if size == 0 {
+                    return Err("Invalid stream window size");
+                }
+                pos = STREAM_MAGIC.len() + len;
+                self.window_size = Some(size);
+                size
+            }
+        };
+
+        while let Some((frame_len, len)) = read_varint(&self.buffer[pos..])? {
+            if frame_len == 0 {
+                pos += len;
+                self.finished = true;
+                if pos != self.buffer.len() {
+                    return Err("Trailing data after end of stream");
+                }
+                break;
+            }
+
+            let start = pos + len;
+            let end = start
+                .checked_add(frame_len)
+                .ok_or("Invalid stream frame length")?;
+            if end > self.buffer.len() {
+                break;
+            }
+
+            let base = base_window(self.base.as_ref(), self.offset, window_size);
+            let decoded = delta::decode(base, &self.buffer[start..end])?;
+            if decoded.len() > window_size {
+                return Err("Stream frame exceeds window size");
+            }
+            self.offset += decoded.len();
+            out.extend(decoded);
+            pos = end;
+        }
+
+        self.buffer.drain(..pos);
+        Ok(out)
+    }
+
+    /// Checks that the complete stream was received.
+    pub fn finish(self) -> Result<(), &'static str> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err("Truncated stream")
+        }
+    }
+}
+
+/// Returns the base region used for the window starting at `offset`.
+fn base_window(base: &[u8], offset: usize, window_size: usize) -> &[u8] {
+    let start = offset.saturating_sub(window_size / 2).min(base.len());
+    let end = offset
+        .saturating_add(window_size + window_size / 2)
+        .min(base.len());
+    &base[start..end]
+}
+
+/// Reads a varint, returning `None` if more bytes are needed.
+#[cfg(feature = "decode")]
+fn read_varint(bytes: &[u8]) -> Result<Option<(usize, usize)>, &'static str> {
+    match bytes
+        .iter()
+        .take(MAX_VARINT_LEN)
+        .position(|b| b & 0x80 == 0)
+    {
+        Some(last) => Ok(Some(decode_varint(&bytes[..=last]))),
+        None if bytes.len() >= MAX_VARINT_LEN => Err("Invalid varint in stream"),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_all(base: &[u8], new: &[u8], window_size: usize, chunk_size: usize) -> Vec<u8> {
+        let mut encoder = StreamEncoder::with_window_size(base, 3, true, window_size);
+        let mut stream = Vec::new();
+        for chunk in new.chunks(chunk_size) {
+            stream.extend(encoder.push(chunk));
+        }
+        stream.extend(encoder.finish());
+        stream
+    }
+
+    fn decode_all(base: &[u8], stream: &[u8], chunk_size: usize) -> Result<Vec<u8>, &'static str> {
+        let mut decoder = StreamDecoder::new(base);
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(chunk_size) {
+            decoded.extend(decoder.push(chunk)?);
+        }
+        decoder.finish()?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn test_roundtrip_various_chunk_sizes() {
+        let base: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        let mut new = base.clone();
+        new.splice(20_000..20_000, b"inserted text".iter().copied());
+        new.truncate(45_000);
+
+        for (window, chunk) in [(4096, 1), (4096, 333), (1000, 50_000), (100_000, 4096)] {
+            let stream = encode_all(&base, &new, window, chunk);
+            assert_eq!(decode_all(&base, &stream, 17).unwrap(), new);
+            assert_eq!(decode_all(&base, &stream, stream.len()).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn test_empty_new_data() {
+        let stream = encode_all(b"base", b"", 16, 1);
+        assert_eq!(decode_all(b"base", &stream, 1).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_frames_carry_tag() {
+        let stream = encode_all(b"hello", b"hello world", 4, 1);
+        let (_, header_len) = decode_varint(&stream[STREAM_MAGIC.len()..]);
+        let pos = STREAM_MAGIC.len() + header_len;
+        let (frame_len, len) = decode_varint(&stream[pos..]);
+        let frame = &stream[pos + len..pos + len + frame_len];
+        assert_eq!(delta::get_tag(frame).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let stream = encode_all(b"hello", b"hello world", 4, 1);
+        let result = decode_all(b"hello", &stream[..stream.len() - 1], 3);
+        assert_eq!(result, Err("Truncated stream"));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut stream = encode_all(b"hello", b"hello world", 4, 1);
+        stream.push(0);
+        assert!(decode_all(b"hello", &stream, 1).is_err());
+        assert!(decode_all(b"hello", &stream, stream.len()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let delta = delta::encode(0, b"hello", b"hello world", false);
+        assert_eq!(
+            decode_all(b"hello", &delta, delta.len()),
+            Err("Invalid stream header")
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let base: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut new = base.clone();
+        new[12_345] ^= 0xFF;
+        new.extend_from_slice(b"appended");
+
+        let sequential = encode_all(&base, &new, 4096, 1500);
+
+        let mut encoder = StreamEncoder::with_window_size(&base[..], 3, true, 4096);
+        encoder.set_parallel(true);
+        let mut parallel = Vec::new();
+        for chunk in new.chunks(1500) {
+            parallel.extend(encoder.push(chunk));
+        }
+        parallel.extend(encoder.finish());
+
+        assert_eq!(parallel, sequential);
+    }
+}
@@ -0,0 +1,269 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Well-known delta tags.
+//!
+//! A delta's tag is a free `usize`, so components exchanging deltas need to agree on what it
+//! means. This module splits the tag space into namespaces and names the tags xpatch itself
+//! uses, so application tags cannot collide with them:
+//!
+//! | Tags                 | Namespace                    | Header cost      |
+//! |----------------------|------------------------------|------------------|
+//! | `0`                  | [`Namespace::None`]          | 0 extra bytes    |
+//! | `1..=15`             | [`Namespace::Xpatch`]        | 0 extra bytes    |
+//! | `16..=4095`          | [`Namespace::ChainDepth`]    | 1-2 extra bytes  |
+//! | `4096..=65535`       | [`Namespace::Reserved`]      | 2 extra bytes    |
+//! | `65536..`            | [`Namespace::User`]          | 2+ extra bytes   |
+//!
+//! Tag 0 carries no meaning and is what most callers pass to [`encode`](crate::delta::encode).
+//! Plain `usize` tags remain valid everywhere; the registry only matters when deltas are shared
+//! between components that interpret tags.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::delta;
+//! use xpatch::tag::{Namespace, Tag};
+//!
+//! let delta = delta::encode_tagged(Tag::chain_depth(3), b"Hello", b"Hello, world", true);
+//! let tag = Tag::of(&delta).unwrap();
+//! assert_eq!(tag.namespace(), Namespace::ChainDepth);
+//! assert_eq!(tag.as_chain_depth(), Some(3));
+//!
+//! let app = Tag::user(42);
+//! assert_eq!(app.as_user(), Some(42));
+//! assert_eq!(delta::get_tag(&delta::encode_tagged(app, b"", b"x", false)), Ok(app.value()));
+//! ```
+
+use crate::delta;
+use std::ops::RangeInclusive;
+
+const XPATCH_START: usize = 1;
+const CHAIN_DEPTH_START: usize = 16;
+const RESERVED_START: usize = 4096;
+const USER_START: usize = 65536;
+
+/// Range of the tag space a [`Tag`] belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// Tag 0: no metadata
+    None,
+    /// Named tags defined by xpatch, such as [`Tag::KEYFRAME`]
+    Xpatch,
+    /// Position of a delta in a delta chain, see [`Tag::chain_depth`]
+    ChainDepth,
+    /// Reserved for future use by xpatch
+    Reserved,
+    /// Free for applications, see [`Tag::user`]
+    User,
+}
+
+impl Namespace {
+    /// Returns the tag values belonging to this namespace.
+    pub const fn range(self) -> RangeInclusive<usize> {
+        match self {
+            Namespace::None => 0..=0,
+            Namespace::Xpatch => XPATCH_START..=CHAIN_DEPTH_START - 1,
+            Namespace::ChainDepth => CHAIN_DEPTH_START..=RESERVED_START - 1,
+            Namespace::Reserved => RESERVED_START..=USER_START - 1,
+            Namespace::User => USER_START..=usize::MAX,
+        }
+    }
+}
+
+/// A delta tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Tag(usize);
+
+impl Tag {
+    /// No metadata; the default tag.
+    pub const NONE: Tag = Tag(0);
+    /// The delta is encoded against empty data and holds the full content.
+    pub const KEYFRAME: Tag = Tag(1);
+    /// The delta is between bincode serializations of two values (see `typed`).
+    pub const TYPED_BINCODE: Tag = Tag(2);
+    /// The delta is between CBOR serializations of two values (see `typed`).
+    pub const TYPED_CBOR: Tag = Tag(3);
+
+    /// Deepest chain position representable by [`Tag::chain_depth`].
+    pub const MAX_CHAIN_DEPTH: usize = RESERVED_START - CHAIN_DEPTH_START;
+
+    /// Wraps a raw tag value.
+    pub const fn new(value: usize) -> Self {
+        Self(value)
+    }
+
+    /// Returns the raw tag value.
+    pub const fn value(self) -> usize {
+        self.0
+    }
+
+    /// Tag for a delta that is the `depth`-th link of a delta chain, i.e. `depth` deltas have to
+    /// be applied starting from a keyframe. Depth 0 is [`Tag::KEYFRAME`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` exceeds [`Tag::MAX_CHAIN_DEPTH`].
+    pub const fn chain_depth(depth: usize) -> Self {
+        assert!(depth <= Self::MAX_CHAIN_DEPTH, "chain depth out of range");
+        if depth == 0 {
+            Self::KEYFRAME
+        } else {
+            Self(CHAIN_DEPTH_START + depth - 1)
+        }
+    }
+
+    /// Returns the chain depth for [`Tag::KEYFRAME`] and [`Tag::chain_depth`] tags.
+    pub const fn as_chain_depth(self) -> Option<usize> {
+        match self.namespace() {
+            Namespace::ChainDepth => Some(self.0 - CHAIN_DEPTH_START + 1),
+            _ if self.0 == Self::KEYFRAME.0 => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Application-defined tag `value`, mapped into the [`Namespace::User`] range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` does not fit into the user range.
+    pub const fn user(value: usize) -> Self {
+        assert!(value <= usize::MAX - USER_START, "user tag out of range");
+        Self(USER_START + value)
+    }
+
+    /// Returns the application-defined value of a [`Tag::user`] tag.
+    pub const fn as_user(self) -> Option<usize> {
+        match self.namespace() {
+            Namespace::User => Some(self.0 - USER_START),
+            _ => None,
+        }
+    }
+
+    /// Returns the namespace the tag belongs to.
+    pub const fn namespace(self) -> Namespace {
+        match self.0 {
+            0 => Namespace::None,
+            XPATCH_START..CHAIN_DEPTH_START => Namespace::Xpatch,
+            CHAIN_DEPTH_START..RESERVED_START => Namespace::ChainDepth,
+            RESERVED_START..USER_START => Namespace::Reserved,
+            _ => Namespace::User,
+        }
+    }
+
+    /// Reads the tag of a delta without decoding it.
+    pub fn of(delta: &[u8]) -> Result<Self, &'static str> {
+        delta::get_tag(delta).map(Self)
+    }
+}
+
+impl From<usize> for Tag {
+    fn from(value: usize) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Tag> for usize {
+    fn from(tag: Tag) -> Self {
+        tag.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces_cover_tag_space() {
+        let namespaces = [
+            Namespace::None,
+            Namespace::Xpatch,
+            Namespace::ChainDepth,
+            Namespace::Reserved,
+            Namespace::User,
+        ];
+        let mut next = 0;
+        for namespace in namespaces {
+            let range = namespace.range();
+            assert_eq!(*range.start(), next);
+            assert_eq!(Tag::new(*range.start()).namespace(), namespace);
+            assert_eq!(Tag::new(*range.end()).namespace(), namespace);
+            next = range.end().wrapping_add(1);
+        }
+        assert_eq!(next, 0);
+
+        assert_eq!(Tag::NONE.namespace(), Namespace::None);
+        for tag in [Tag::KEYFRAME, Tag::TYPED_BINCODE, Tag::TYPED_CBOR] {
+            assert_eq!(tag.namespace(), Namespace::Xpatch);
+        }
+    }
+
+    #[test]
+    fn test_chain_depth() {
+        assert_eq!(Tag::chain_depth(0), Tag::KEYFRAME);
+        for depth in [0, 1, 2, 100, Tag::MAX_CHAIN_DEPTH] {
+            assert_eq!(Tag::chain_depth(depth).as_chain_depth(), Some(depth));
+        }
+        assert_eq!(
+            Tag::chain_depth(Tag::MAX_CHAIN_DEPTH).value(),
+            *Namespace::ChainDepth.range().end()
+        );
+        assert_eq!(Tag::NONE.as_chain_depth(), None);
+        assert_eq!(Tag::user(1).as_chain_depth(), None);
+        assert_eq!(Tag::TYPED_CBOR.as_chain_depth(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "chain depth out of range")]
+    fn test_chain_depth_out_of_range() {
+        Tag::chain_depth(Tag::MAX_CHAIN_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_user_tags() {
+        assert_eq!(Tag::user(0).value(), USER_START);
+        assert_eq!(Tag::user(7).as_user(), Some(7));
+        assert_eq!(
+            Tag::user(usize::MAX - USER_START).as_user(),
+            Some(usize::MAX - USER_START)
+        );
+        assert_eq!(Tag::KEYFRAME.as_user(), None);
+        assert_eq!(Tag::new(5000).as_user(), None);
+    }
+
+    #[test]
+    fn test_roundtrip_through_delta() {
+        for tag in [
+            Tag::NONE,
+            Tag::KEYFRAME,
+            Tag::chain_depth(9),
+            Tag::new(5000),
+            Tag::user(123_456),
+        ] {
+            let delta = delta::encode_tagged(tag, b"base data", b"base data, changed", true);
+            assert_eq!(Tag::of(&delta), Ok(tag));
+            assert_eq!(
+                delta::decode(b"base data", &delta).unwrap(),
+                b"base data, changed"
+            );
+        }
+        assert_eq!(usize::from(Tag::from(77usize)), 77);
+    }
+}
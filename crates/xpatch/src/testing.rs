@@ -0,0 +1,336 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Helpers for property tests and fuzz targets.
+//!
+//! [`arbitrary_pair`] turns a seed into a base/new pair shaped like real edits (text and binary
+//! data, insertions, deletions, moved and duplicated ranges, plus the empty and identical edge
+//! cases), and [`roundtrip_check`] checks a delta for such a pair against every decoding path of
+//! this crate. Bindings can feed deltas from their own encoders through the same checks, so a
+//! broken wrapper shows up as a differential failure against the core library.
+//!
+//! # Example
+//!
+//! ```
+//! use xpatch::testing::{arbitrary_pair, roundtrip_check};
+//!
+//! for seed in 0..32 {
+//!     let (base, new) = arbitrary_pair(seed);
+//!     let delta = xpatch::encode(0, &base, &new, true);
+//!     roundtrip_check(&base, &new, &delta).unwrap();
+//! }
+//! ```
+
+use crate::delta::{self, EncodeOptions, ops};
+
+/// Small deterministic generator (SplitMix64), so failures reproduce from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..n`; 0 when `n` is 0.
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// A length biased towards small values, at most 16 KiB.
+    fn len(&mut self) -> usize {
+        let limit = [16, 256, 4096, 16384][self.below(4)];
+        self.below(limit + 1)
+    }
+
+    fn range(&mut self, len: usize) -> (usize, usize) {
+        let start = self.below(len + 1);
+        (start, start + self.below(len - start + 1))
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+const WORDS: &[&str] = &[
+    "fn", "let", "the", "value", "delta", "patch", "base", "return", "{", "}", "(", ")", ";", "0",
+    "42", "=", "if", "else", "xpatch", "version",
+];
+
+/// Generates data of a randomly chosen shape.
+fn generate(rng: &mut Rng) -> Vec<u8> {
+    let len = rng.len();
+    match rng.below(5) {
+        // Text: words, spaces and newlines
+        0 => {
+            let mut text = Vec::with_capacity(len + 16);
+            while text.len() < len {
+                text.extend_from_slice(WORDS[rng.below(WORDS.len())].as_bytes());
+                text.push(if rng.below(8) == 0 { b'\n' } else { b' ' });
+            }
+            text
+        }
+        // Random bytes
+        1 => rng.bytes(len),
+        // A short pattern repeated
+        2 => {
+            let pattern_len = 1 + rng.below(8);
+            let pattern = rng.bytes(pattern_len);
+            pattern.iter().copied().cycle().take(len).collect()
+        }
+        // Fixed-size records with a counter, like tables or arrays of structs
+        3 => {
+            let record_len = 4 + rng.below(28);
+            let record = rng.bytes(record_len);
+            let mut data = Vec::with_capacity(len + record.len());
+            let mut counter = rng.next() as u32;
+            while data.len() < len {
+                data.extend_from_slice(&counter.to_le_bytes());
+                data.extend_from_slice(&record);
+                counter = counter.wrapping_add(1);
+            }
+            data
+        }
+        // A single byte repeated
+        _ => vec![rng.next() as u8; len],
+    }
+}
+
+/// Applies one random edit to `data`.
+fn edit(rng: &mut Rng, data: &mut Vec<u8>) {
+    let (start, end) = rng.range(data.len());
+    match rng.below(6) {
+        0 => {
+            let insert = if rng.below(2) == 0 {
+                let len = rng.below(64);
+                rng.bytes(len)
+            } else {
+                generate(rng)
+            };
+            data.splice(start..start, insert);
+        }
+        1 => {
+            data.drain(start..end);
+        }
+        2 => {
+            let replacement = rng.bytes(end - start);
+            data.splice(start..end, replacement);
+        }
+        3 => {
+            let copy = data[start..end].to_vec();
+            let at = rng.below(data.len() + 1);
+            data.splice(at..at, copy);
+        }
+        4 => {
+            let moved: Vec<u8> = data.drain(start..end).collect();
+            let at = rng.below(data.len() + 1);
+            data.splice(at..at, moved);
+        }
+        _ => {
+            // Flip a few single bytes
+            for _ in 0..1 + rng.below(4) {
+                if !data.is_empty() {
+                    let i = rng.below(data.len());
+                    data[i] ^= 1 << rng.below(8);
+                }
+            }
+        }
+    }
+}
+
+/// Generates a base/new pair from `seed`.
+///
+/// Most pairs are a base followed by a few random edits of it; some are the edge cases encoders
+/// tend to get wrong: empty data on either side, identical data and unrelated data. The same
+/// seed always gives the same pair.
+pub fn arbitrary_pair(seed: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut rng = Rng(seed);
+    let base = if rng.below(16) == 0 {
+        Vec::new()
+    } else {
+        generate(&mut rng)
+    };
+    let new = match rng.below(16) {
+        0 => Vec::new(),
+        1 => base.clone(),
+        2 => generate(&mut rng),
+        _ => {
+            let mut new = base.clone();
+            for _ in 0..1 + rng.below(8) {
+                edit(&mut rng, &mut new);
+            }
+            new
+        }
+    };
+    (base, new)
+}
+
+/// Checks that `delta` turns `base` into `new`, through every way this crate can apply it.
+///
+/// The delta may come from any encoder, e.g. a binding under test, and may be FEC-protected
+/// (with the `fec` feature) but not encrypted. Checks, in order:
+///
+/// - the header parses and accounts for the whole delta
+/// - [`delta::decode`] reproduces `new`
+/// - the instructions from [`ops::decode_ops`] reproduce `new` via [`ops::apply_ops`]
+/// - [`delta::recompress`] keeps the tag and still reproduces `new`
+/// - a delta from this crate's own encoder, with the same tag, reproduces `new`
+///
+/// Returns a description of the first failed check.
+pub fn roundtrip_check(base: &[u8], new: &[u8], delta: &[u8]) -> Result<(), String> {
+    #[cfg(feature = "fec")]
+    let repaired;
+    #[cfg(feature = "fec")]
+    let delta = if crate::fec::is_protected(delta) {
+        repaired = crate::fec::repair(delta)
+            .map_err(|e| format!("fec: {}", e))?
+            .0;
+        repaired.as_slice()
+    } else {
+        delta
+    };
+
+    let info = delta::inspect(delta).map_err(|e| format!("inspect: {}", e))?;
+    if info.encrypted {
+        return Err("inspect: delta is encrypted".to_string());
+    }
+    if info.header_size + info.payload_size != delta.len() {
+        return Err(format!(
+            "inspect: header ({}) and payload ({}) do not add up to {} bytes",
+            info.header_size,
+            info.payload_size,
+            delta.len()
+        ));
+    }
+
+    let decoded = delta::decode(base, delta).map_err(|e| format!("decode: {}", e))?;
+    compare("decode", &decoded, new)?;
+
+    let instructions = ops::decode_ops(base, delta).map_err(|e| format!("decode_ops: {}", e))?;
+    let applied = ops::apply_ops(base, &instructions).map_err(|e| format!("apply_ops: {}", e))?;
+    compare("apply_ops", &applied, new)?;
+
+    let recompressed = delta::recompress(delta, base, &EncodeOptions::default())
+        .map_err(|e| format!("recompress: {}", e))?;
+    let tag = delta::get_tag(&recompressed).map_err(|e| format!("recompress: {}", e))?;
+    if tag != info.tag {
+        return Err(format!("recompress: tag {} became {}", info.tag, tag));
+    }
+    let decoded = delta::decode(base, &recompressed).map_err(|e| format!("recompress: {}", e))?;
+    compare("recompress", &decoded, new)?;
+
+    let reference = delta::encode(info.tag, base, new, true);
+    let decoded = delta::decode(base, &reference).map_err(|e| format!("encode: {}", e))?;
+    compare("encode", &decoded, new)
+}
+
+/// Reports where `actual` first differs from `expected`.
+fn compare(check: &str, actual: &[u8], expected: &[u8]) -> Result<(), String> {
+    if actual == expected {
+        return Ok(());
+    }
+    let at = actual
+        .iter()
+        .zip(expected)
+        .position(|(a, b)| a != b)
+        .unwrap_or(actual.len().min(expected.len()));
+    Err(format!(
+        "{}: output differs at byte {} ({} bytes, expected {})",
+        check,
+        at,
+        actual.len(),
+        expected.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_pair() {
+        assert_eq!(arbitrary_pair(7), arbitrary_pair(7));
+        assert_ne!(arbitrary_pair(7), arbitrary_pair(8));
+
+        let pairs: Vec<_> = (0..256).map(arbitrary_pair).collect();
+        assert!(pairs.iter().any(|(base, _)| base.is_empty()));
+        assert!(pairs.iter().any(|(_, new)| new.is_empty()));
+        assert!(
+            pairs
+                .iter()
+                .any(|(base, new)| base == new && !base.is_empty())
+        );
+        assert!(pairs.iter().any(|(base, _)| base.len() > 4096));
+    }
+
+    #[test]
+    fn test_roundtrip_options() {
+        let options = [
+            EncodeOptions::default(),
+            EncodeOptions {
+                enable_zstd: false,
+                ..EncodeOptions::default()
+            },
+            EncodeOptions {
+                checksum: true,
+                optimize: true,
+                ..EncodeOptions::default()
+            },
+            EncodeOptions {
+                block_size: 64,
+                ..EncodeOptions::default()
+            },
+        ];
+        for seed in 0..128 {
+            let (base, new) = arbitrary_pair(seed);
+            for (i, options) in options.iter().enumerate() {
+                let delta = delta::encode_with_options(seed as usize, &base, &new, options);
+                if let Err(e) = roundtrip_check(&base, &new, &delta) {
+                    panic!("seed {} options {}: {}", seed, i, e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_check_detects_mismatch() {
+        let (base, new) = (b"hello world".to_vec(), b"hello there world".to_vec());
+        let delta = delta::encode(3, &base, &new, false);
+        assert!(roundtrip_check(&base, &new, &delta).is_ok());
+        assert!(
+            roundtrip_check(&base, b"hello world!", &delta)
+                .unwrap_err()
+                .starts_with("decode:")
+        );
+        assert!(
+            roundtrip_check(&base, &new, &[])
+                .unwrap_err()
+                .starts_with("inspect:")
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// xpatch - High-performance delta compression library
+// Copyright (c) 2025 Oliver Seifert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Commercial License Option:
+// For commercial use in proprietary software, a commercial license is
+// available. Contact xpatch-commercial@alias.oseifert.ch for details.
+
+//! Deltas between serializable values.
+//!
+//! State-sync applications usually hold two versions of a struct rather than two byte buffers.
+//! [`diff`] serializes both values and encodes the delta between them; [`patch`] serializes the
+//! old value again, applies the delta and deserializes the result.
+//!
+//! Values are serialized with bincode (compact, the default) or CBOR (self-describing), see
+//! [`Format`]. The format is recorded in the delta's tag ([`Tag::TYPED_BINCODE`] or
+//! [`Tag::TYPED_CBOR`]), so [`patch`] picks it up on its own. Deltas embed checksums of both
+//! serialized values, so patching the wrong old value fails instead of producing a bogus result.
+//! Both sides must serialize the old value to the same bytes, which holds for plain structs but
+//! not for e.g. `HashMap`s with differing iteration order.
+//!
+//! # Example
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use xpatch::typed;
+//!
+//! #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+//! struct Player {
+//!     name: String,
+//!     score: u32,
+//!     inventory: Vec<String>,
+//! }
+//!
+//! let old = Player { name: "alice".into(), score: 10, inventory: vec!["sword".into()] };
+//! let mut new = Player { score: 25, ..old.clone() };
+//! new.inventory.push("shield".into());
+//!
+//! let delta = typed::diff(&old, &new)?;
+//! assert_eq!(typed::patch(&old, &delta)?, new);
+//! # Ok::<(), &'static str>(())
+//! ```
+
+use crate::delta::{self, EncodeOptions};
+use crate::tag::Tag;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Serialization format of the values in a typed delta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// bincode: compact and fast, but not self-describing
+    #[default]
+    Bincode,
+    /// CBOR (RFC 8949): self-describing, so deltas stay readable across schema changes
+    Cbor,
+}
+
+impl Format {
+    /// Returns the tag identifying this format in a delta.
+    pub const fn tag(self) -> Tag {
+        match self {
+            Format::Bincode => Tag::TYPED_BINCODE,
+            Format::Cbor => Tag::TYPED_CBOR,
+        }
+    }
+
+    /// Returns the format identified by a delta tag.
+    pub fn from_tag(tag: Tag) -> Option<Self> {
+        match tag {
+            Tag::TYPED_BINCODE => Some(Format::Bincode),
+            Tag::TYPED_CBOR => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Serializes `value` in this format.
+    pub fn serialize<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Format::Bincode => bincode::serialize(value).map_err(|_| "Serialization failed"),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).map_err(|_| "Serialization failed")?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Deserializes a value serialized in this format.
+    pub fn deserialize<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, &'static str> {
+        match self {
+            Format::Bincode => bincode::deserialize(bytes).map_err(|_| "Deserialization failed"),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|_| "Deserialization failed"),
+        }
+    }
+}
+
+/// Encodes the delta from `old` to `new`, serialized with bincode.
+pub fn diff<T: Serialize + ?Sized>(old: &T, new: &T) -> Result<Vec<u8>, &'static str> {
+    diff_with_format(Format::default(), old, new)
+}
+
+/// Encodes the delta from `old` to `new`, serialized with `format`.
+pub fn diff_with_format<T: Serialize + ?Sized>(
+    format: Format,
+    old: &T,
+    new: &T,
+) -> Result<Vec<u8>, &'static str> {
+    let old = format.serialize(old)?;
+    let new = format.serialize(new)?;
+    let options = EncodeOptions {
+        checksum: true,
+        ..EncodeOptions::default()
+    };
+    Ok(delta::encode_tagged_with_options(
+        format.tag(),
+        &old,
+        &new,
+        &options,
+    ))
+}
+
+/// Applies a delta produced by [`diff`] or [`diff_with_format`] to `old`.
+pub fn patch<T: Serialize + DeserializeOwned>(old: &T, delta: &[u8]) -> Result<T, &'static str> {
+    let format = Format::from_tag(Tag::of(delta)?).ok_or("Unknown serialization format")?;
+    let old = format.serialize(old)?;
+    let new = delta::decode(&old, delta)?;
+    format.deserialize(&new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Document {
+        title: String,
+        revision: u64,
+        tags: Vec<String>,
+        body: String,
+        meta: BTreeMap<String, i64>,
+        parent: Option<Box<Document>>,
+    }
+
+    fn document() -> Document {
+        Document {
+            title: "Design notes".into(),
+            revision: 7,
+            tags: vec!["draft".into(), "internal".into()],
+            body: "The quick brown fox jumps over the lazy dog. ".repeat(40),
+            meta: BTreeMap::from([("views".into(), 12), ("likes".into(), -3)]),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_both_formats() {
+        let old = document();
+        let mut new = old.clone();
+        new.revision += 1;
+        new.tags.push("reviewed".into());
+        new.body.insert_str(100, "Edited here. ");
+        new.parent = Some(Box::new(old.clone()));
+
+        for format in [Format::Bincode, Format::Cbor] {
+            let delta = diff_with_format(format, &old, &new).unwrap();
+            assert_eq!(Tag::of(&delta), Ok(format.tag()));
+            assert_eq!(patch(&old, &delta).unwrap(), new);
+            assert!(delta.len() < format.serialize(&new).unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_small_change_small_delta() {
+        let old = document();
+        let mut new = old.clone();
+        new.meta.insert("views".into(), 13);
+
+        let delta = diff(&old, &new).unwrap();
+        assert!(delta.len() < 40, "delta is {} bytes", delta.len());
+        assert_eq!(patch(&old, &delta).unwrap(), new);
+    }
+
+    #[test]
+    fn test_identical_values() {
+        let value = document();
+        let delta = diff(&value, &value).unwrap();
+        assert_eq!(patch(&value, &delta).unwrap(), value);
+    }
+
+    #[test]
+    fn test_wrong_base_rejected() {
+        let old = document();
+        let mut new = old.clone();
+        new.revision = 8;
+        let delta = diff(&old, &new).unwrap();
+
+        let mut other = old.clone();
+        other.title = "Other notes".into();
+        assert_eq!(patch(&other, &delta), Err("Base data checksum mismatch"));
+    }
+
+    #[test]
+    fn test_unknown_format_and_type_mismatch() {
+        let delta = delta::encode(5, b"a", b"b", false);
+        assert_eq!(
+            patch(&String::from("a"), &delta),
+            Err("Unknown serialization format")
+        );
+
+        // A delta between CBOR strings does not deserialize into an integer
+        let delta = diff_with_format(Format::Cbor, "old", "new").unwrap();
+        assert_eq!(patch(&String::from("old"), &delta).unwrap(), "new");
+        let delta = diff_with_format(Format::Cbor, &1u32, &2u32).unwrap();
+        assert_eq!(patch(&1u32, &delta).unwrap(), 2);
+        assert_eq!(
+            Format::Cbor.deserialize::<u32>(&Format::Cbor.serialize("text").unwrap()),
+            Err("Deserialization failed")
+        );
+    }
+}
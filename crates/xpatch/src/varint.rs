@@ -104,8 +104,9 @@ pub fn encode_varint(mut value: usize) -> Vec<u8> {
 ///
 /// # Panics
 ///
-/// Does not panic on malformed input, but may return incorrect values
-/// if the input is not a valid varint. Callers should ensure input validity.
+/// Panics if `bytes` is empty, and in debug builds if the varint is longer than a `usize`
+/// holds. Other malformed input yields incorrect values, so callers decoding untrusted data
+/// should check that the varint ends within `bytes` first.
 ///
 /// # Examples
 ///